@@ -0,0 +1,34 @@
+//! Renders the man pages of `nuclaw` and its subcommands from the CLI
+//! definition in `src/cli.rs`.
+//!
+//! The pages are written to `man/` next to the binary, e.g.
+//! `target/release/man/nuclaw.1`, or to `NUCLAW_MAN_DIR` if set.
+
+use std::path::PathBuf;
+
+#[allow(dead_code)]
+#[path = "src/cli.rs"]
+mod cli;
+#[allow(dead_code)]
+#[path = "src/settings.rs"]
+mod settings;
+
+fn main() -> std::io::Result<()> {
+    println!("cargo:rerun-if-changed=src/cli.rs");
+    println!("cargo:rerun-if-changed=src/settings.rs");
+    println!("cargo:rerun-if-env-changed=NUCLAW_MAN_DIR");
+
+    let dir = match std::env::var_os("NUCLAW_MAN_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        // OUT_DIR is target/<profile>/build/nuclaw-<hash>/out
+        None => {
+            let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+            match out_dir.ancestors().nth(3) {
+                Some(profile_dir) => profile_dir.join("man"),
+                None => out_dir.join("man"),
+            }
+        }
+    };
+    std::fs::create_dir_all(&dir)?;
+    clap_mangen::generate_to(cli::command(), &dir)
+}
@@ -241,6 +241,9 @@ setup_directories() {
 
     cd "$PROJECT_DIR"
 
+    # 数据保存在项目目录中，而不是 XDG 目录
+    export NUCLAW_HOME="$PROJECT_DIR"
+
     # 创建运行时目录
     mkdir -p store
     mkdir -p data
@@ -328,9 +331,9 @@ show_usage() {
     echo "使用方式:"
     echo "  ./target/release/nuclaw              # 启动服务"
     echo "  ./target/release/nuclaw --help       # 查看帮助"
-    echo "  ./target/release/nuclaw --auth       # 认证流程"
+    echo "  ./target/release/nuclaw auth whatsapp # 认证流程"
     echo ""
-    echo "目录说明:"
+    echo "目录说明 (运行时设置 NUCLAW_HOME=$PROJECT_DIR):"
     echo "  store/    - SQLite 数据库和认证文件"
     echo "  data/     - 运行时数据 (会话、IPC)"
     echo "  groups/   - 群组 CLAUDE.md 文件"
@@ -0,0 +1,1215 @@
+//! Admin HTTP API for NuClaw
+//!
+//! Served under `/api` by the HTTP server (see `http_server`) when a token
+//! is configured; every request must carry `Authorization: Bearer <token>`.
+//! It manages the default tenant (see `tenants`); maintenance mode applies
+//! to all tenants.
+//!
+//! Tokens have a name and a scope, `read` (only `GET` requests) or
+//! `manage` (all requests). They are listed in `ADMIN_API_TOKENS` as
+//! `<name>:<scope>:<token>` entries, usually in the config file:
+//!
+//! ```toml
+//! admin_api_tokens = ["grafana:read:6f1d...", "deploy:manage:9a0c..."]
+//! ```
+//!
+//! `ADMIN_API_TOKEN` adds a `manage` token named `admin`. Once the API is
+//! enabled, `/health` needs a token of either scope too.
+//!
+//! Endpoints:
+//! - `GET /api/metrics/containers?hours=24` - container run latency
+//!   percentiles, failure rate, and queue wait, overall and per group
+//! - `GET /api/stats?days=7` - usage over the last days: messages per
+//!   group and day, trigger rate, average container run duration, and task
+//!   success rate
+//! - `GET /api/chats` - chats seen by the channels with their names, the
+//!   most recently active first
+//! - `GET /api/tasks?chat_jid=...` - scheduled tasks, optionally of one chat
+//! - `POST /api/tasks` - create a task (`group_folder`, `chat_jid`,
+//!   `prompt`, `schedule_type`, `schedule_value`)
+//! - `GET|PATCH|DELETE /api/tasks/:id` - show, change (`prompt`,
+//!   `schedule_type`, `schedule_value`, `status`), or delete a task
+//! - `GET /api/tasks/:id/runs?limit=20` - a task's latest runs
+//! - `GET /api/runs?task_id=...` - the latest runs of all tasks
+//! - `GET /api/schedules/preview?schedule_type=cron&schedule_value=...&count=5`
+//!   - validate a schedule and list its next runs in the `TZ` timezone
+//! - `GET|POST /api/templates` - list or save task templates (`name`,
+//!   `prompt`, and optionally `schedule_type`, `schedule_value`,
+//!   `group_folder`)
+//! - `GET|DELETE /api/templates/:name` - show or delete a template
+//! - `POST /api/templates/:name/tasks` - create a task from a template
+//!   (`params`, and optionally `group_folder`, `chat_jid`, `schedule_type`,
+//!   `schedule_value`)
+//! - `GET /api/maintenance` - whether NuClaw is paused, since when, and by
+//!   whom; `POST /api/maintenance/pause` and `/resume` switch it
+//! - `GET|PUT /api/log-level` - show or replace the log filter (`level`:
+//!   a level or `target=level` directives) without a restart
+//! - `GET /api/logs/stream?level=info&module=nuclaw::telegram` - follow
+//!   log events as server-sent events (see `log_stream`); browsers, which
+//!   cannot set headers on an `EventSource`, may pass the token as
+//!   `?token=` instead
+//!
+//! Both run endpoints also filter by `status`, `since`, and `until`.
+//!
+//! Invalid input is answered with `400` and `{"error": "..."}`.
+//!
+//! Requests without a valid token or outside of its scope, and successful
+//! changes (anything but `GET`), are recorded in the audit log (see
+//! `audit`) with the token's name.
+
+use crate::analytics::{usage_stats, UsageStats, DEFAULT_STATS_DAYS};
+use crate::audit::{self, AuditEvent, AuditKind};
+use crate::db::Database;
+use crate::error::{NuClawError, Result as NuClawResult};
+use crate::health;
+use crate::log_stream::{self, LogStreamFilter};
+use crate::logging::{log_filter, set_log_filter};
+use crate::maintenance::{self, pause_state, Pause};
+use crate::metrics::{container_run_stats, ContainerRunStats};
+use crate::repository::Chat;
+use crate::shutdown;
+use crate::task_scheduler::{
+    create_task, preview_schedule, query_runs, update_task, NewTask, RunFilter, SchedulePreview,
+    TaskUpdate,
+};
+use crate::task_templates::{
+    delete_template, get_template, list_templates, save_template, task_from_template, TaskTemplate,
+    TemplateUse,
+};
+use crate::types::{ScheduledTask, TaskRunLog};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{error, warn};
+
+/// Runs returned by `/api/tasks/:id/runs` without a limit
+const DEFAULT_RUNS_LIMIT: usize = 20;
+/// Upper bound for the runs limit
+const MAX_RUNS_LIMIT: usize = 500;
+/// Runs listed by `/api/schedules/preview` without a count
+const DEFAULT_PREVIEW_COUNT: usize = 5;
+
+/// Route of the live log stream
+const LOG_STREAM_PATH: &str = "/api/logs/stream";
+
+/// Bearer token for the admin API (`ADMIN_API_TOKEN`)
+pub fn admin_api_token() -> Option<String> {
+    std::env::var("ADMIN_API_TOKEN")
+        .ok()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+/// What a token may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    /// `GET` requests only
+    Read,
+    /// All requests
+    Manage,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Manage => "manage",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "read" => Some(ApiScope::Read),
+            "manage" => Some(ApiScope::Manage),
+            _ => None,
+        }
+    }
+
+    /// Whether requests with `method` are in this scope
+    pub fn allows(&self, method: &Method) -> bool {
+        *self == ApiScope::Manage || method == Method::GET
+    }
+}
+
+/// A token accepted by the admin API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiToken {
+    pub name: String,
+    pub scope: ApiScope,
+    pub token: String,
+}
+
+/// Parse an `ADMIN_API_TOKENS` entry, `<name>:<scope>:<token>`
+pub fn parse_api_token(entry: &str) -> NuClawResult<ApiToken> {
+    let invalid = |message: &str| NuClawError::Config {
+        message: format!("Invalid ADMIN_API_TOKENS entry: {}", message),
+    };
+    let mut parts = entry.trim().splitn(3, ':');
+    let (Some(name), Some(scope), Some(token)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid("use <name>:<scope>:<token>"));
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(invalid("the name is empty"));
+    }
+    let scope = ApiScope::parse(scope).ok_or_else(|| {
+        invalid(&format!(
+            "unknown scope '{}' of '{}'; use read or manage",
+            scope.trim(),
+            name
+        ))
+    })?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(invalid(&format!("the token of '{}' is empty", name)));
+    }
+    Ok(ApiToken {
+        name: name.to_string(),
+        scope,
+        token: token.to_string(),
+    })
+}
+
+/// Tokens of `ADMIN_API_TOKENS` and `ADMIN_API_TOKEN`
+pub fn api_tokens() -> NuClawResult<Vec<ApiToken>> {
+    let mut tokens = std::env::var("ADMIN_API_TOKENS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(parse_api_token)
+        .collect::<NuClawResult<Vec<_>>>()?;
+    if let Some(token) = admin_api_token() {
+        tokens.push(ApiToken {
+            name: "admin".to_string(),
+            scope: ApiScope::Manage,
+            token,
+        });
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone)]
+struct AdminState {
+    db: Database,
+    tokens: Arc<[ApiToken]>,
+}
+
+/// Routes of the admin API and `/health`, or `None` when no token is
+/// configured
+pub fn router(db: Database) -> NuClawResult<Option<Router>> {
+    let tokens = api_tokens()?;
+    Ok((!tokens.is_empty()).then(|| router_with_tokens(db, tokens)))
+}
+
+fn router_with_tokens(db: Database, tokens: Vec<ApiToken>) -> Router {
+    let state = AdminState {
+        db: db.clone(),
+        tokens: Arc::from(tokens),
+    };
+    Router::new()
+        .route("/api/metrics/containers", get(container_metrics))
+        .route("/api/stats", get(usage_stats_show))
+        .route("/api/chats", get(chats_list))
+        .route("/api/tasks", get(tasks_list).post(tasks_create))
+        .route(
+            "/api/tasks/:id",
+            get(tasks_show).patch(tasks_update).delete(tasks_delete),
+        )
+        .route("/api/tasks/:id/runs", get(tasks_runs))
+        .route("/api/runs", get(runs_list))
+        .route("/api/schedules/preview", get(schedules_preview))
+        .route("/api/templates", get(templates_list).post(templates_save))
+        .route(
+            "/api/templates/:name",
+            get(templates_show).delete(templates_delete),
+        )
+        .route("/api/templates/:name/tasks", post(templates_create_task))
+        .route("/api/maintenance", get(maintenance_show))
+        .route("/api/maintenance/pause", post(maintenance_pause))
+        .route("/api/maintenance/resume", post(maintenance_resume))
+        .route("/api/log-level", get(log_level_show).put(log_level_set))
+        .route(LOG_STREAM_PATH, get(logs_stream))
+        .with_state(state.clone())
+        .merge(health::router(db))
+        .route_layer(middleware::from_fn_with_state(state, require_token))
+}
+
+/// Token sent with a request: the bearer token, or for the log stream
+/// also the `token` query parameter
+fn request_token(request: &Request) -> Option<String> {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string());
+    bearer.or_else(|| {
+        if request.uri().path() != LOG_STREAM_PATH {
+            return None;
+        }
+        url::form_urlencoded::parse(request.uri().query()?.as_bytes())
+            .find(|(name, _)| name == "token")
+            .map(|(_, token)| token.trim().to_string())
+    })
+}
+
+/// The configured token matching `token`
+///
+/// Every token is compared, so the time taken does not reveal which one
+/// matched.
+fn find_token<'a>(tokens: &'a [ApiToken], token: &str) -> Option<&'a ApiToken> {
+    tokens.iter().fold(None, |found, candidate| {
+        let matches = constant_time_eq(token.as_bytes(), candidate.token.as_bytes());
+        found.or(matches.then_some(candidate))
+    })
+}
+
+async fn require_token(State(state): State<AdminState>, request: Request, next: Next) -> Response {
+    let token = request_token(&request).and_then(|t| find_token(&state.tokens, &t).cloned());
+    let action = format!("{} {}", request.method(), request.uri().path());
+    let Some(token) = token else {
+        audit_request(
+            &state.db,
+            AuditKind::Denied,
+            "api",
+            format!("{}: missing or invalid token", action),
+        )
+        .await;
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    let actor = format!("api:{}", token.name);
+    if !token.scope.allows(request.method()) {
+        audit_request(
+            &state.db,
+            AuditKind::Denied,
+            &actor,
+            format!("{}: token is {}", action, token.scope.as_str()),
+        )
+        .await;
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let changes = request.method() != Method::GET;
+    let response = next.run(request).await;
+    if changes && response.status().is_success() {
+        audit_request(&state.db, AuditKind::Command, &actor, action).await;
+    }
+    response
+}
+
+/// Record a request in the audit log
+async fn audit_request(db: &Database, kind: AuditKind, actor: &str, detail: String) {
+    let event = AuditEvent::new(kind, actor, detail);
+    if let Err(e) = db.call(move |db| audit::append(db, &event)).await {
+        warn!("{}", e);
+    }
+}
+
+/// Compare secrets without revealing where they differ
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Debug, Deserialize)]
+struct MetricsQuery {
+    /// Only include runs from the last `hours` hours
+    hours: Option<i64>,
+}
+
+async fn container_metrics(
+    State(state): State<AdminState>,
+    Query(query): Query<MetricsQuery>,
+) -> Result<Json<ContainerRunStats>, StatusCode> {
+    let since = query
+        .hours
+        .map(|h| (chrono::Utc::now() - chrono::Duration::hours(h.max(0))).to_rfc3339());
+    state
+        .db
+        .call(move |db| container_run_stats(db, since.as_deref()))
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("Failed to compute container metrics: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Error response of the admin API
+struct ApiError(StatusCode, String);
+
+impl From<NuClawError> for ApiError {
+    fn from(e: NuClawError) -> Self {
+        match e {
+            NuClawError::Validation { message } => ApiError(StatusCode::BAD_REQUEST, message),
+            e => {
+                error!("Admin API request failed: {}", e);
+                ApiError(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal error".to_string(),
+                )
+            }
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn task_not_found(id: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("No task {}", id))
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsQuery {
+    days: Option<u32>,
+}
+
+async fn usage_stats_show(
+    State(state): State<AdminState>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<UsageStats>, ApiError> {
+    let days = query.days.unwrap_or(DEFAULT_STATS_DAYS);
+    Ok(Json(state.db.call(move |db| usage_stats(db, days)).await?))
+}
+
+async fn chats_list(State(state): State<AdminState>) -> Result<Json<Vec<Chat>>, ApiError> {
+    Ok(Json(state.db.call(|db| db.chats().list()).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct TasksQuery {
+    chat_jid: Option<String>,
+}
+
+async fn tasks_list(
+    State(state): State<AdminState>,
+    Query(query): Query<TasksQuery>,
+) -> Result<Json<Vec<ScheduledTask>>, ApiError> {
+    let tasks = state
+        .db
+        .call(move |db| db.tasks().list(query.chat_jid.as_deref()))
+        .await?;
+    Ok(Json(tasks))
+}
+
+async fn tasks_create(
+    State(state): State<AdminState>,
+    Json(task): Json<NewTask>,
+) -> Result<(StatusCode, Json<ScheduledTask>), ApiError> {
+    let task = state.db.call(move |db| create_task(db, task)).await?;
+    Ok((StatusCode::CREATED, Json(task)))
+}
+
+async fn tasks_show(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+) -> Result<Json<ScheduledTask>, ApiError> {
+    let task_id = id.clone();
+    state
+        .db
+        .call(move |db| db.tasks().get(&task_id))
+        .await?
+        .map(Json)
+        .ok_or_else(|| task_not_found(&id))
+}
+
+async fn tasks_update(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+    Json(update): Json<TaskUpdate>,
+) -> Result<Json<ScheduledTask>, ApiError> {
+    let task_id = id.clone();
+    state
+        .db
+        .call(move |db| update_task(db, &task_id, update))
+        .await?
+        .map(Json)
+        .ok_or_else(|| task_not_found(&id))
+}
+
+async fn tasks_delete(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let task_id = id.clone();
+    if state.db.call(move |db| db.tasks().delete(&task_id)).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(task_not_found(&id))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RunsQuery {
+    task_id: Option<String>,
+    status: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<usize>,
+}
+
+impl RunsQuery {
+    fn filter(self) -> (RunFilter, usize) {
+        let limit = self
+            .limit
+            .unwrap_or(DEFAULT_RUNS_LIMIT)
+            .clamp(1, MAX_RUNS_LIMIT);
+        let filter = RunFilter {
+            task_id: self.task_id,
+            status: self.status,
+            since: self.since,
+            until: self.until,
+        };
+        (filter, limit)
+    }
+}
+
+async fn tasks_runs(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+    Query(query): Query<RunsQuery>,
+) -> Result<Json<Vec<TaskRunLog>>, ApiError> {
+    let task_id = id.clone();
+    if state
+        .db
+        .call(move |db| db.tasks().get(&task_id))
+        .await?
+        .is_none()
+    {
+        return Err(task_not_found(&id));
+    }
+    let (filter, limit) = RunsQuery {
+        task_id: Some(id),
+        ..query
+    }
+    .filter();
+    let runs = state
+        .db
+        .call(move |db| query_runs(db, &filter, limit))
+        .await?;
+    Ok(Json(runs))
+}
+
+async fn runs_list(
+    State(state): State<AdminState>,
+    Query(query): Query<RunsQuery>,
+) -> Result<Json<Vec<TaskRunLog>>, ApiError> {
+    let (filter, limit) = query.filter();
+    let runs = state
+        .db
+        .call(move |db| query_runs(db, &filter, limit))
+        .await?;
+    Ok(Json(runs))
+}
+
+#[derive(Debug, Deserialize)]
+struct PreviewQuery {
+    schedule_type: String,
+    schedule_value: String,
+    count: Option<usize>,
+}
+
+async fn schedules_preview(
+    Query(query): Query<PreviewQuery>,
+) -> Result<Json<SchedulePreview>, ApiError> {
+    Ok(Json(preview_schedule(
+        &query.schedule_type,
+        &query.schedule_value,
+        query.count.unwrap_or(DEFAULT_PREVIEW_COUNT),
+    )?))
+}
+
+fn template_not_found(name: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("No template '{}'", name))
+}
+
+async fn templates_list(
+    State(state): State<AdminState>,
+) -> Result<Json<Vec<TaskTemplate>>, ApiError> {
+    Ok(Json(state.db.call(list_templates).await?))
+}
+
+async fn templates_save(
+    State(state): State<AdminState>,
+    Json(template): Json<TaskTemplate>,
+) -> Result<(StatusCode, Json<TaskTemplate>), ApiError> {
+    let template = state.db.call(move |db| save_template(db, template)).await?;
+    Ok((StatusCode::CREATED, Json(template)))
+}
+
+async fn templates_show(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> Result<Json<TaskTemplate>, ApiError> {
+    let template_name = name.clone();
+    state
+        .db
+        .call(move |db| get_template(db, &template_name))
+        .await?
+        .map(Json)
+        .ok_or_else(|| template_not_found(&name))
+}
+
+async fn templates_delete(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let template_name = name.clone();
+    if state
+        .db
+        .call(move |db| delete_template(db, &template_name))
+        .await?
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(template_not_found(&name))
+    }
+}
+
+async fn templates_create_task(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Json(with): Json<TemplateUse>,
+) -> Result<(StatusCode, Json<ScheduledTask>), ApiError> {
+    let template_name = name.clone();
+    if state
+        .db
+        .call(move |db| get_template(db, &template_name))
+        .await?
+        .is_none()
+    {
+        return Err(template_not_found(&name));
+    }
+    let task = state
+        .db
+        .call(move |db| create_task(db, task_from_template(db, &name, with)?))
+        .await?;
+    Ok((StatusCode::CREATED, Json(task)))
+}
+
+/// Maintenance state as returned by `/api/maintenance`
+#[derive(Debug, Serialize)]
+struct MaintenanceState {
+    paused: bool,
+    #[serde(flatten)]
+    pause: Option<Pause>,
+}
+
+async fn maintenance_show(
+    State(state): State<AdminState>,
+) -> Result<Json<MaintenanceState>, ApiError> {
+    let pause = state.db.call(pause_state).await?;
+    Ok(Json(MaintenanceState {
+        paused: pause.is_some(),
+        pause,
+    }))
+}
+
+async fn maintenance_pause(
+    State(state): State<AdminState>,
+) -> Result<Json<MaintenanceState>, ApiError> {
+    state
+        .db
+        .call(|db| maintenance::pause(db, "admin-api"))
+        .await?;
+    maintenance_show(State(state)).await
+}
+
+async fn maintenance_resume(
+    State(state): State<AdminState>,
+) -> Result<Json<MaintenanceState>, ApiError> {
+    state.db.call(maintenance::resume).await?;
+    maintenance_show(State(state)).await
+}
+
+/// Log filter as returned and accepted by `/api/log-level`
+#[derive(Debug, Serialize, Deserialize)]
+struct LogLevel {
+    level: String,
+}
+
+async fn log_level_show() -> Json<LogLevel> {
+    Json(LogLevel {
+        level: log_filter(),
+    })
+}
+
+async fn log_level_set(Json(body): Json<LogLevel>) -> Result<Json<LogLevel>, ApiError> {
+    set_log_filter(&body.level)?;
+    Ok(log_level_show().await)
+}
+
+#[derive(Debug, Deserialize)]
+struct LogStreamQuery {
+    level: Option<String>,
+    module: Option<String>,
+}
+
+/// Log events as server-sent `log` events with a JSON `LogRecord`, and
+/// `lagged` events with the number of events skipped; the stream ends
+/// when NuClaw shuts down
+async fn logs_stream(
+    Query(query): Query<LogStreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let filter = LogStreamFilter::parse(query.level.as_deref(), query.module.as_deref())?;
+    let events = stream::unfold(
+        (log_stream::subscribe(), filter),
+        |(mut receiver, filter)| async move {
+            loop {
+                let received = tokio::select! {
+                    _ = shutdown::requested() => return None,
+                    received = receiver.recv() => received,
+                };
+                let event = match received {
+                    Ok(record) if filter.matches(&record) => Event::default()
+                        .event("log")
+                        .json_data(&record)
+                        .unwrap_or_default(),
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        Event::default().event("lagged").data(skipped.to_string())
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                };
+                return Some((Ok(event), (receiver, filter)));
+            }
+        },
+    );
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use crate::metrics::{record_container_run, status, ContainerRunRecord};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    fn router_with_token(db: Database, token: &str) -> Router {
+        router_with_tokens(
+            db,
+            vec![parse_api_token(&format!("admin:manage:{}", token)).unwrap()],
+        )
+    }
+
+    fn get_request(uri: &str, token: Option<&str>) -> axum::http::Request<Body> {
+        let mut request = axum::http::Request::get(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_container_metrics_require_token() {
+        let (db, _dir) = test_database();
+        let app = router_with_token(db.clone(), "s3cret");
+
+        for token in [None, Some("wrong")] {
+            let response = app
+                .clone()
+                .oneshot(get_request("/api/metrics/containers", token))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let denied = audit::query(&db, &Default::default(), 10).unwrap();
+        assert_eq!(denied.len(), 2);
+        assert_eq!(denied[0].actor, "api");
+        assert_eq!(
+            denied[0].detail,
+            "GET /api/metrics/containers: missing or invalid token"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_scopes() {
+        let (db, _dir) = test_database();
+        let tokens = ["grafana:read:r3ader", "deploy:Manage:s3cret"]
+            .into_iter()
+            .map(|entry| parse_api_token(entry).unwrap())
+            .collect();
+        let app = router_with_tokens(db.clone(), tokens);
+
+        for (uri, token) in [("/health", None), ("/api/chats", Some("s3cret!"))] {
+            let response = app.clone().oneshot(get_request(uri, token)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        for uri in ["/health", "/api/chats"] {
+            let response = app
+                .clone()
+                .oneshot(get_request(uri, Some("r3ader")))
+                .await
+                .unwrap();
+            // No container runtime here, so `/health` may be 503
+            assert!(!response.status().is_client_error());
+        }
+
+        let post = |token: &str| {
+            axum::http::Request::post("/api/maintenance/pause")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = app.clone().oneshot(post("r3ader")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.oneshot(post("s3cret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let events = audit::query(&db, &Default::default(), 10).unwrap();
+        assert_eq!(events[0].actor, "api:deploy");
+        assert_eq!(events[0].detail, "POST /api/maintenance/pause");
+        assert_eq!(events[1].actor, "api:grafana");
+        assert_eq!(
+            events[1].detail,
+            "POST /api/maintenance/pause: token is read"
+        );
+    }
+
+    #[test]
+    fn test_parse_api_token() {
+        assert_eq!(
+            parse_api_token(" ci : read : a:b ").unwrap(),
+            ApiToken {
+                name: "ci".to_string(),
+                scope: ApiScope::Read,
+                token: "a:b".to_string(),
+            }
+        );
+        for entry in ["ci:write:abc", "ci:read", ":read:abc", "ci:manage: "] {
+            assert!(matches!(
+                parse_api_token(entry),
+                Err(NuClawError::Config { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_container_metrics() {
+        let (db, _dir) = test_database();
+        record_container_run(
+            &db,
+            &ContainerRunRecord {
+                group_folder: "family".to_string(),
+                chat_jid: "123@g.us".to_string(),
+                status: status::SUCCESS,
+                exit_code: Some(0),
+                duration_ms: 1500,
+                queue_wait_ms: 0,
+                output_bytes: 100,
+            },
+        )
+        .unwrap();
+        let app = router_with_token(db, "s3cret");
+
+        let response = app
+            .oneshot(get_request(
+                "/api/metrics/containers?hours=24",
+                Some("s3cret"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats["overall"]["runs"], 1);
+        assert_eq!(stats["overall"]["p50_duration_ms"], 1500);
+        assert_eq!(stats["groups"]["family"]["failure_rate"], 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_usage_stats() {
+        let (db, _dir) = test_database();
+        let app = router_with_token(db, "s3cret");
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/api/stats?days=30", Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stats = json_body(response).await;
+        assert_eq!(stats["days"], 30);
+        assert_eq!(stats["messages"], 0);
+
+        let response = app
+            .oneshot(get_request("/api/stats?days=0", Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    fn json_request(method: &str, uri: &str, body: serde_json::Value) -> axum::http::Request<Body> {
+        axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, "Bearer s3cret")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chats_list() {
+        let (db, _dir) = test_database();
+        db.chats()
+            .upsert("123@g.us", Some("Family"), "1767344400")
+            .unwrap();
+        let app = router_with_token(db, "s3cret");
+
+        let response = app
+            .oneshot(get_request("/api/chats", Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let chats = json_body(response).await;
+        assert_eq!(chats[0]["jid"], "123@g.us");
+        assert_eq!(chats[0]["name"], "Family");
+        assert_eq!(chats[0]["last_message_time"], "2026-01-02T09:00:00+00:00");
+    }
+
+    #[tokio::test]
+    async fn test_task_crud() {
+        let (db, _dir) = test_database();
+        let app = router_with_token(db, "s3cret");
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                "POST",
+                "/api/tasks",
+                serde_json::json!({
+                    "group_folder": "family",
+                    "chat_jid": "123@g.us",
+                    "prompt": "Summarize the news",
+                    "schedule_type": "interval",
+                    "schedule_value": "1h",
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let id = json_body(response).await["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                "PATCH",
+                &format!("/api/tasks/{}", id),
+                serde_json::json!({ "status": "paused", "prompt": "Summarize sports" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let task = json_body(response).await;
+        assert_eq!(task["status"], "paused");
+        assert_eq!(task["prompt"], "Summarize sports");
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/api/tasks?chat_jid=123@g.us", Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await.as_array().unwrap().len(), 1);
+
+        let response = app
+            .clone()
+            .oneshot(get_request(
+                &format!("/api/tasks/{}/runs", id),
+                Some("s3cret"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await, serde_json::json!([]));
+
+        let delete = axum::http::Request::delete(format!("/api/tasks/{}", id))
+            .header(header::AUTHORIZATION, "Bearer s3cret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(delete).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = app
+            .oneshot(get_request(&format!("/api/tasks/{}", id), Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_task_create_rejects_invalid_schedule() {
+        let (db, _dir) = test_database();
+        let app = router_with_token(db, "s3cret");
+
+        let response = app
+            .oneshot(json_request(
+                "POST",
+                "/api/tasks",
+                serde_json::json!({
+                    "group_folder": "family",
+                    "chat_jid": "123@g.us",
+                    "prompt": "Summarize the news",
+                    "schedule_type": "cron",
+                    "schedule_value": "every morning",
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(json_body(response).await["error"]
+            .as_str()
+            .unwrap()
+            .contains("every morning"));
+    }
+
+    #[tokio::test]
+    async fn test_schedules_preview() {
+        let (db, _dir) = test_database();
+        let app = router_with_token(db, "s3cret");
+
+        let response = app
+            .clone()
+            .oneshot(get_request(
+                "/api/schedules/preview?schedule_type=cron&schedule_value=0%200%209%20*%20*%20*&count=3",
+                Some("s3cret"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let preview = json_body(response).await;
+        assert_eq!(preview["next_runs"].as_array().unwrap().len(), 3);
+        assert!(preview["timezone"].is_string());
+
+        let response = app
+            .oneshot(get_request(
+                "/api/schedules/preview?schedule_type=interval&schedule_value=soon",
+                Some("s3cret"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_templates() {
+        let (db, _dir) = test_database();
+        let app = router_with_token(db, "s3cret");
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                "POST",
+                "/api/templates",
+                serde_json::json!({
+                    "name": "standup",
+                    "prompt": "Summarize {team}'s standup",
+                    "schedule_type": "cron",
+                    "schedule_value": "0 0 9 * * MON-FRI",
+                    "group_folder": "family",
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                "POST",
+                "/api/templates/standup/tasks",
+                serde_json::json!({ "chat_jid": "123@g.us" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                "POST",
+                "/api/templates/standup/tasks",
+                serde_json::json!({ "chat_jid": "123@g.us", "params": { "team": "backend" } }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let task = json_body(response).await;
+        assert_eq!(task["prompt"], "Summarize backend's standup");
+        assert_eq!(task["group_folder"], "family");
+
+        let delete = axum::http::Request::delete("/api/templates/standup")
+            .header(header::AUTHORIZATION, "Bearer s3cret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(delete).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = app
+            .oneshot(get_request("/api/templates/standup", Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance() {
+        let (db, _dir) = test_database();
+        let app = router_with_token(db, "s3cret");
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                "POST",
+                "/api/maintenance/pause",
+                serde_json::json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let state = json_body(response).await;
+        assert_eq!(state["paused"], true);
+        assert_eq!(state["by"], "admin-api");
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                "POST",
+                "/api/maintenance/resume",
+                serde_json::json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["paused"], false);
+
+        let response = app
+            .oneshot(get_request("/api/maintenance", Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(
+            json_body(response).await,
+            serde_json::json!({ "paused": false })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_log_level() {
+        crate::logging::init();
+        let (db, _dir) = test_database();
+        let app = router_with_token(db, "s3cret");
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                "PUT",
+                "/api/log-level",
+                serde_json::json!({ "level": "warn,nuclaw::telegram=debug" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(get_request("/api/log-level", Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(
+            json_body(response).await,
+            serde_json::json!({ "level": "warn,nuclaw::telegram=debug" })
+        );
+
+        let response = app
+            .oneshot(json_request(
+                "PUT",
+                "/api/log-level",
+                serde_json::json!({ "level": "loud" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_logs_stream() {
+        use futures_util::StreamExt;
+
+        crate::logging::init();
+        let (db, _dir) = test_database();
+        let app = router_with_token(db, "s3cret");
+
+        // The token may be a query parameter here, but nowhere else
+        for (uri, status) in [
+            ("/api/logs/stream?token=wrong", StatusCode::UNAUTHORIZED),
+            ("/api/chats?token=s3cret", StatusCode::UNAUTHORIZED),
+            (
+                "/api/logs/stream?token=s3cret&level=loud",
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let response = app.clone().oneshot(get_request(uri, None)).await.unwrap();
+            assert_eq!(response.status(), status, "{}", uri);
+        }
+
+        let response = app
+            .oneshot(get_request(
+                "/api/logs/stream?token=s3cret&level=error&module=nuclaw::admin_api",
+                None,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+
+        tracing::info!("Not streamed: below the level");
+        tracing::error!("Streamed to the browser");
+        let mut body = response.into_body().into_data_stream();
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let text = String::from_utf8(chunk.to_vec()).unwrap();
+        assert!(text.starts_with("event: log\ndata: {"), "{}", text);
+        assert!(
+            text.contains("\"message\":\"Streamed to the browser\""),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+    }
+}
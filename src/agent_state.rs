@@ -0,0 +1,181 @@
+//! Agent State for NuClaw
+//!
+//! A small key-value store per tenant and group folder, kept in the
+//! `agent_state` table, for state an agent carries from one run to the
+//! next (e.g. the last processed item of a feed). Values are any JSON.
+//!
+//! Before each run the group's entries are written to
+//! `/workspace/ipc/state.json` as one JSON object. Agents change them by
+//! writing `{"op": "set_state", "key": ..., "value": ...}` or
+//! `{"op": "delete_state", "key": ...}` files to `/workspace/ipc/requests/`;
+//! the changes are applied after the run, so the next run sees them.
+
+use crate::container_runner::create_group_ipc_directory;
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use std::collections::BTreeMap;
+
+/// Name of the state file in a group's IPC directory
+pub const STATE_FILE: &str = "state.json";
+/// Longest key, in bytes
+const MAX_KEY_LEN: usize = 128;
+/// Largest value, in bytes of JSON
+const MAX_VALUE_BYTES: usize = 64 * 1024;
+/// Most keys a group folder can hold
+const MAX_KEYS_PER_GROUP: usize = 1000;
+
+fn invalid(message: String) -> NuClawError {
+    NuClawError::Validation { message }
+}
+
+/// Whether `key` can be used as a state key
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_KEY_LEN
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '/'))
+}
+
+/// All entries of a group folder
+pub fn load_state(
+    db: &Database,
+    group_folder: &str,
+) -> Result<BTreeMap<String, serde_json::Value>> {
+    let rows = db.read_connection()?.query_map(
+        "SELECT key, value FROM agent_state WHERE tenant = ? AND group_folder = ?",
+        [db.tenant(), group_folder],
+        |row| Ok((row.get::<String>(0)?, row.get::<String>(1)?)),
+    )?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, serde_json::from_str(&value).ok()?)))
+        .collect())
+}
+
+/// One entry of a group folder
+pub fn get_state(
+    db: &Database,
+    group_folder: &str,
+    key: &str,
+) -> Result<Option<serde_json::Value>> {
+    let value = db.read_connection()?.query_opt(
+        "SELECT value FROM agent_state WHERE tenant = ? AND group_folder = ? AND key = ?",
+        [db.tenant(), group_folder, key],
+        |row| row.get::<String>(0),
+    )?;
+    Ok(value.and_then(|v| serde_json::from_str(&v).ok()))
+}
+
+/// Set an entry, replacing its value
+pub fn set_state(
+    db: &Database,
+    group_folder: &str,
+    key: &str,
+    value: &serde_json::Value,
+) -> Result<()> {
+    if !is_valid_key(key) {
+        return Err(invalid(format!(
+            "Invalid state key '{}'; use up to {} letters, digits, and '_-.:/'",
+            key, MAX_KEY_LEN
+        )));
+    }
+    let value = value.to_string();
+    if value.len() > MAX_VALUE_BYTES {
+        return Err(invalid(format!(
+            "Value of '{}' is larger than {} bytes",
+            key, MAX_VALUE_BYTES
+        )));
+    }
+
+    let conn = db.get_connection()?;
+    let keys: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM agent_state WHERE tenant = ? AND group_folder = ? AND key <> ?",
+        [db.tenant(), group_folder, key],
+        |row| row.get(0),
+    )?;
+    if keys as usize >= MAX_KEYS_PER_GROUP {
+        return Err(invalid(format!(
+            "Group {} already has {} state keys",
+            group_folder, MAX_KEYS_PER_GROUP
+        )));
+    }
+    conn.execute(
+        "INSERT INTO agent_state (tenant, group_folder, key, value, updated_at)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT (tenant, group_folder, key) DO UPDATE SET
+            value = excluded.value,
+            updated_at = excluded.updated_at",
+        [
+            db.tenant(),
+            group_folder,
+            key,
+            &value,
+            &chrono::Utc::now().to_rfc3339(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Delete an entry; `false` if there was none
+pub fn delete_state(db: &Database, group_folder: &str, key: &str) -> Result<bool> {
+    let deleted = db.get_connection()?.execute(
+        "DELETE FROM agent_state WHERE tenant = ? AND group_folder = ? AND key = ?",
+        [db.tenant(), group_folder, key],
+    )?;
+    Ok(deleted > 0)
+}
+
+/// Write a group folder's entries to the state file in its IPC directory
+pub fn write_state_file(db: &Database, group_folder: &str) -> Result<()> {
+    let state = load_state(db, group_folder)?;
+    let json = serde_json::to_string_pretty(&state).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to serialize state: {}", e),
+    })?;
+    let path = create_group_ipc_directory(group_folder)?.join(STATE_FILE);
+    std::fs::write(&path, json).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to write {}: {}", path.display(), e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use serde_json::json;
+
+    #[test]
+    fn test_agent_state() {
+        let (db, _dir) = test_database();
+        set_state(&db, "news", "feed:last_id", &json!(41)).unwrap();
+        set_state(&db, "news", "feed:last_id", &json!(42)).unwrap();
+        set_state(&db, "news", "seen", &json!({"a": [1, 2]})).unwrap();
+        set_state(&db.for_tenant("bob"), "news", "seen", &json!(null)).unwrap();
+
+        assert_eq!(
+            get_state(&db, "news", "feed:last_id").unwrap(),
+            Some(json!(42))
+        );
+        assert_eq!(get_state(&db, "other", "feed:last_id").unwrap(), None);
+        let state = load_state(&db, "news").unwrap();
+        assert_eq!(state.len(), 2);
+        assert_eq!(state["seen"], json!({"a": [1, 2]}));
+
+        assert!(delete_state(&db, "news", "seen").unwrap());
+        assert!(!delete_state(&db, "news", "seen").unwrap());
+    }
+
+    #[test]
+    fn test_set_state_validates() {
+        let (db, _dir) = test_database();
+        for key in ["", "has space", &"k".repeat(MAX_KEY_LEN + 1)] {
+            assert!(matches!(
+                set_state(&db, "news", key, &json!(1)),
+                Err(NuClawError::Validation { .. })
+            ));
+        }
+        let big = json!("x".repeat(MAX_VALUE_BYTES));
+        assert!(set_state(&db, "news", "big", &big).is_err());
+        assert!(load_state(&db, "news").unwrap().is_empty());
+    }
+}
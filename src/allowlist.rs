@@ -0,0 +1,181 @@
+//! Allowlists for NuClaw
+//!
+//! Stores the DM user allowlist and the group allowlist per tenant and
+//! channel in SQLite so admins can change them at runtime (`/allow`, `/deny`)
+//! without restarting the bot.
+
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+
+/// Kind of allowlist entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllowlistKind {
+    /// A user allowed to DM the bot
+    User,
+    /// A group allowed to use the bot
+    Group,
+}
+
+impl AllowlistKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AllowlistKind::User => "user",
+            AllowlistKind::Group => "group",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "user" | "users" => Some(AllowlistKind::User),
+            "group" | "groups" => Some(AllowlistKind::Group),
+            _ => None,
+        }
+    }
+}
+
+/// Add an entry; returns `false` if it was already present
+pub fn allow(
+    db: &Database,
+    channel: &str,
+    kind: AllowlistKind,
+    id: &str,
+    added_by: &str,
+) -> Result<bool> {
+    let conn = db.get_connection()?;
+    let inserted = conn
+        .execute(
+            "INSERT INTO allowlist (tenant, channel, kind, entry_id, added_by, added_at)
+             VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT DO NOTHING",
+            crate::params![
+                db.tenant(),
+                channel,
+                kind.as_str(),
+                id,
+                added_by,
+                chrono::Utc::now().to_rfc3339()
+            ],
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to update allowlist: {}", e),
+        })?;
+    Ok(inserted > 0)
+}
+
+/// Remove an entry; returns `false` if it was not present
+pub fn deny(db: &Database, channel: &str, kind: AllowlistKind, id: &str) -> Result<bool> {
+    let conn = db.get_connection()?;
+    let removed = conn
+        .execute(
+            "DELETE FROM allowlist
+             WHERE tenant = ? AND channel = ? AND kind = ? AND entry_id = ?",
+            [db.tenant(), channel, kind.as_str(), id],
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to update allowlist: {}", e),
+        })?;
+    Ok(removed > 0)
+}
+
+/// Check if an entry is on the allowlist
+pub fn is_allowed(db: &Database, channel: &str, kind: AllowlistKind, id: &str) -> Result<bool> {
+    let conn = db.read_connection()?;
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM allowlist
+         WHERE tenant = ? AND channel = ? AND kind = ? AND entry_id = ?",
+        [db.tenant(), channel, kind.as_str(), id],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// List entries of a kind for a channel
+pub fn list(db: &Database, channel: &str, kind: AllowlistKind) -> Result<Vec<String>> {
+    let conn = db.read_connection()?;
+    conn.query_map(
+        "SELECT entry_id FROM allowlist WHERE tenant = ? AND channel = ? AND kind = ?
+         ORDER BY added_at, entry_id",
+        [db.tenant(), channel, kind.as_str()],
+        |row| row.get(0),
+    )
+}
+
+/// Seed the allowlist from legacy env configuration
+///
+/// Entries are only imported while the list is still empty, so removals
+/// made at runtime are not undone on the next restart.
+pub fn seed(db: &Database, channel: &str, kind: AllowlistKind, ids: &[String]) -> Result<usize> {
+    if ids.is_empty() || !list(db, channel, kind)?.is_empty() {
+        return Ok(0);
+    }
+
+    let mut added = 0;
+    for id in ids {
+        if allow(db, channel, kind, id, "env")? {
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_allowlist_kind_parse() {
+        assert_eq!(AllowlistKind::parse("user"), Some(AllowlistKind::User));
+        assert_eq!(AllowlistKind::parse("Groups"), Some(AllowlistKind::Group));
+        assert_eq!(AllowlistKind::parse("chat"), None);
+    }
+
+    #[test]
+    fn test_allow_and_deny() {
+        let (db, _dir) = test_database();
+
+        assert!(allow(&db, "telegram", AllowlistKind::User, "42", "admin").unwrap());
+        assert!(!allow(&db, "telegram", AllowlistKind::User, "42", "admin").unwrap());
+        assert!(is_allowed(&db, "telegram", AllowlistKind::User, "42").unwrap());
+
+        // Kinds and channels are separate namespaces
+        assert!(!is_allowed(&db, "telegram", AllowlistKind::Group, "42").unwrap());
+        assert!(!is_allowed(&db, "whatsapp", AllowlistKind::User, "42").unwrap());
+
+        assert!(deny(&db, "telegram", AllowlistKind::User, "42").unwrap());
+        assert!(!deny(&db, "telegram", AllowlistKind::User, "42").unwrap());
+        assert!(!is_allowed(&db, "telegram", AllowlistKind::User, "42").unwrap());
+    }
+
+    #[test]
+    fn test_list() {
+        let (db, _dir) = test_database();
+        allow(&db, "telegram", AllowlistKind::Group, "-100", "admin").unwrap();
+        allow(&db, "telegram", AllowlistKind::Group, "-200", "admin").unwrap();
+
+        let groups = list(&db, "telegram", AllowlistKind::Group).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert!(groups.contains(&"-100".to_string()));
+        assert!(list(&db, "telegram", AllowlistKind::User)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_seed_only_when_empty() {
+        let (db, _dir) = test_database();
+        let ids = vec!["-100".to_string(), "-200".to_string()];
+
+        assert_eq!(
+            seed(&db, "telegram", AllowlistKind::Group, &ids).unwrap(),
+            2
+        );
+
+        // A runtime removal is not undone by seeding again
+        deny(&db, "telegram", AllowlistKind::Group, "-200").unwrap();
+        assert_eq!(
+            seed(&db, "telegram", AllowlistKind::Group, &ids).unwrap(),
+            0
+        );
+        assert!(!is_allowed(&db, "telegram", AllowlistKind::Group, "-200").unwrap());
+    }
+}
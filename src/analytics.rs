@@ -0,0 +1,291 @@
+//! Usage Analytics for NuClaw
+//!
+//! Summarizes how a tenant's assistant was used over the last days, from
+//! the tables NuClaw already keeps: messages per group and day, how many
+//! incoming messages mention a trigger, how long container runs take, and
+//! how often scheduled task runs succeed. Shown by `nuclaw stats` and
+//! `GET /api/stats`.
+//!
+//! Messages are grouped by the folder of their registered group, or by
+//! chat JID for chats that are not registered. Days are calendar days in
+//! the `TZ` timezone.
+
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use crate::groups::{load_registered_groups, match_trigger};
+use crate::repository::parse_message_time;
+use crate::task_scheduler::schedule_timezone;
+use crate::tenants::tenant;
+use crate::types::RegisteredGroup;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Days covered when none are given
+pub const DEFAULT_STATS_DAYS: u32 = 7;
+/// Upper bound for the days covered
+const MAX_STATS_DAYS: u32 = 366;
+
+/// Messages of one group over the covered days
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GroupUsage {
+    /// Incoming messages
+    pub messages: usize,
+    /// Incoming messages mentioning one of the group's triggers
+    pub triggered: usize,
+    /// Share of incoming messages that were triggers, from 0 to 1
+    pub trigger_rate: f64,
+    /// Incoming messages per day (`YYYY-MM-DD`)
+    pub per_day: BTreeMap<String, usize>,
+}
+
+/// Usage over the covered days
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UsageStats {
+    pub days: u32,
+    /// Start of the covered time (RFC 3339)
+    pub since: String,
+    pub messages: usize,
+    pub triggered: usize,
+    pub trigger_rate: f64,
+    /// Usage per group folder, or per chat JID for unregistered chats
+    pub groups: BTreeMap<String, GroupUsage>,
+    /// Container runs, including retries and scheduled tasks
+    pub container_runs: usize,
+    pub avg_container_duration_ms: u64,
+    pub task_runs: usize,
+    pub task_successes: usize,
+    /// Share of task runs that succeeded, from 0 to 1
+    pub task_success_rate: f64,
+}
+
+/// Check the number of days to cover
+pub fn validate_days(days: u32) -> Result<u32> {
+    if (1..=MAX_STATS_DAYS).contains(&days) {
+        Ok(days)
+    } else {
+        Err(NuClawError::Validation {
+            message: format!("days must be between 1 and {}", MAX_STATS_DAYS),
+        })
+    }
+}
+
+/// Usage of the database handle's tenant over the last `days` days
+pub fn usage_stats(db: &Database, days: u32) -> Result<UsageStats> {
+    let groups = load_registered_groups(db.tenant());
+    let default_trigger = format!("@{}", tenant(db.tenant()).assistant_name());
+    usage_stats_at(db, days, Utc::now(), &groups, &default_trigger)
+}
+
+fn usage_stats_at(
+    db: &Database,
+    days: u32,
+    now: DateTime<Utc>,
+    groups: &HashMap<String, RegisteredGroup>,
+    default_trigger: &str,
+) -> Result<UsageStats> {
+    let days = validate_days(days)?;
+    let since = now - chrono::Duration::days(days as i64);
+    let mut stats = UsageStats {
+        days,
+        since: since.to_rfc3339(),
+        ..Default::default()
+    };
+
+    let conn = db.read_connection()?;
+    // Timestamps are stored in each channel's format, so they are compared
+    // after parsing
+    let messages = conn.query_map(
+        "SELECT chat_jid, content, timestamp FROM messages
+         WHERE tenant = ? AND is_from_me = 0",
+        [db.tenant()],
+        |row| {
+            Ok((
+                row.get::<String>(0)?,
+                row.get::<Option<String>>(1)?.unwrap_or_default(),
+                row.get::<Option<String>>(2)?.unwrap_or_default(),
+            ))
+        },
+    )?;
+    let tz = schedule_timezone();
+    let default_triggers = vec![default_trigger.to_string()];
+    for (chat_jid, content, timestamp) in messages {
+        let Some(time) = parse_message_time(&timestamp).filter(|t| *t >= since) else {
+            continue;
+        };
+        let group = groups.get(&chat_jid);
+        let triggers = match group.map(RegisteredGroup::triggers) {
+            Some(triggers) if !triggers.is_empty() => triggers,
+            _ => default_triggers.clone(),
+        };
+        let usage = stats
+            .groups
+            .entry(group.map_or(chat_jid, |g| g.folder.clone()))
+            .or_default();
+        usage.messages += 1;
+        if match_trigger(&content, &triggers).is_some() {
+            usage.triggered += 1;
+        }
+        let day = time.with_timezone(&tz).format("%Y-%m-%d").to_string();
+        *usage.per_day.entry(day).or_default() += 1;
+    }
+    for usage in stats.groups.values_mut() {
+        usage.trigger_rate = rate(usage.triggered, usage.messages);
+        stats.messages += usage.messages;
+        stats.triggered += usage.triggered;
+    }
+    stats.trigger_rate = rate(stats.triggered, stats.messages);
+
+    let (runs, total_ms) = conn.query_row(
+        "SELECT COUNT(*), CAST(COALESCE(SUM(duration_ms), 0) AS BIGINT) FROM container_runs
+         WHERE tenant = ? AND started_at >= ?",
+        [db.tenant(), &stats.since],
+        |row| Ok((row.get::<i64>(0)?, row.get::<i64>(1)?)),
+    )?;
+    stats.container_runs = runs.max(0) as usize;
+    if runs > 0 {
+        stats.avg_container_duration_ms = (total_ms.max(0) / runs) as u64;
+    }
+
+    let (task_runs, successes) = conn.query_row(
+        "SELECT COUNT(*),
+                CAST(COALESCE(SUM(CASE WHEN status = 'success' THEN 1 ELSE 0 END), 0) AS BIGINT)
+         FROM task_run_logs WHERE tenant = ? AND run_at >= ?",
+        [db.tenant(), &stats.since],
+        |row| Ok((row.get::<i64>(0)?, row.get::<i64>(1)?)),
+    )?;
+    stats.task_runs = task_runs.max(0) as usize;
+    stats.task_successes = successes.max(0) as usize;
+    stats.task_success_rate = rate(stats.task_successes, stats.task_runs);
+    Ok(stats)
+}
+
+fn rate(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use crate::metrics::{record_container_run, status, ContainerRunRecord};
+    use crate::types::{NewMessage, TaskRunLog};
+
+    fn message(id: &str, chat_jid: &str, content: &str, timestamp: &str) -> NewMessage {
+        NewMessage {
+            id: id.to_string(),
+            chat_jid: chat_jid.to_string(),
+            sender: "u1".to_string(),
+            sender_name: "Alice".to_string(),
+            content: content.to_string(),
+            timestamp: timestamp.to_string(),
+            reply_to_id: None,
+            quoted_content: None,
+            chat_name: None,
+            correlation_id: None,
+        }
+    }
+
+    fn registry() -> HashMap<String, RegisteredGroup> {
+        HashMap::from([(
+            "family@g.us".to_string(),
+            RegisteredGroup {
+                name: "Family".to_string(),
+                folder: "family".to_string(),
+                trigger: "@Jarvis".to_string(),
+                added_at: String::new(),
+                aliases: vec![],
+                read_receipts: None,
+                presence: None,
+                container_config: None,
+            },
+        )])
+    }
+
+    #[test]
+    fn test_usage_stats() {
+        let (db, _dir) = test_database();
+        let now = "2026-03-10T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let messages = db.messages();
+        for (id, chat, content, timestamp) in [
+            (
+                "1",
+                "family@g.us",
+                "@jarvis hi",
+                "2026-03-09T08:00:00+00:00",
+            ),
+            ("2", "family@g.us", "dinner?", "2026-03-09T09:00:00+00:00"),
+            (
+                "3",
+                "family@g.us",
+                "@Jarvis remind me",
+                "2026-03-10T08:00:00+00:00",
+            ),
+            (
+                "4",
+                "family@g.us",
+                "@Jarvis too old",
+                "2026-03-01T08:00:00+00:00",
+            ),
+            // Telegram stores Unix seconds: 2026-03-10T10:00:00Z
+            ("5", "telegram:group:1", "@Andy hello", "1773136800"),
+        ] {
+            messages
+                .store(&message(id, chat, content, timestamp), false)
+                .unwrap();
+        }
+        messages
+            .store(&message("6", "family@g.us", "@Jarvis", "1773136800"), true)
+            .unwrap();
+        record_container_run(
+            &db,
+            &ContainerRunRecord {
+                group_folder: "family".to_string(),
+                chat_jid: "family@g.us".to_string(),
+                status: status::SUCCESS,
+                exit_code: Some(0),
+                duration_ms: 3000,
+                queue_wait_ms: 0,
+                output_bytes: 10,
+            },
+        )
+        .unwrap();
+        for status in ["success", "success", "error"] {
+            db.tasks()
+                .log_run(
+                    &TaskRunLog {
+                        task_id: "t".to_string(),
+                        run_at: Utc::now().to_rfc3339(),
+                        duration_ms: 1000,
+                        status: status.to_string(),
+                        result: None,
+                        error: None,
+                    },
+                    None,
+                )
+                .unwrap();
+        }
+
+        let stats = usage_stats_at(&db, 7, now, &registry(), "@Andy").unwrap();
+        assert_eq!(stats.messages, 4);
+        assert_eq!(stats.triggered, 3);
+        assert_eq!(stats.trigger_rate, 0.75);
+        let family = &stats.groups["family"];
+        assert_eq!(family.messages, 3);
+        assert_eq!(
+            family.per_day,
+            BTreeMap::from([("2026-03-09".to_string(), 2), ("2026-03-10".to_string(), 1)])
+        );
+        assert_eq!(stats.groups["telegram:group:1"].trigger_rate, 1.0);
+        assert_eq!(stats.container_runs, 1);
+        assert_eq!(stats.avg_container_duration_ms, 3000);
+        assert_eq!(stats.task_runs, 3);
+        assert_eq!(stats.task_successes, 2);
+
+        assert!(usage_stats_at(&db, 0, now, &registry(), "@Andy").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Attachments for NuClaw
+//!
+//! Incoming media is saved under `groups/<folder>/attachments/` and
+//! referenced in the prompt by its path inside the container. Files the
+//! agent writes to `groups/<folder>/outgoing/` are sent back to the chat
+//! after the run and removed once delivered.
+
+use crate::config::groups_dir;
+use crate::error::{NuClawError, Result};
+use std::path::{Path, PathBuf};
+
+/// Subdirectory of a group folder holding received media
+pub const ATTACHMENTS_DIR: &str = "attachments";
+/// Subdirectory of a group folder the agent drops files to send into
+pub const OUTGOING_DIR: &str = "outgoing";
+/// Where the group folder is mounted inside the container
+const CONTAINER_GROUP_DIR: &str = "/workspace/group";
+
+/// Save received media into a group's attachments directory
+///
+/// The file name is prefixed with the message ID so attachments with the
+/// same name never overwrite each other. Returns the saved file name.
+pub fn save_attachment(
+    group_folder: &str,
+    message_id: &str,
+    file_name: &str,
+    bytes: &[u8],
+) -> Result<String> {
+    save_attachment_in(
+        &groups_dir().join(group_folder),
+        message_id,
+        file_name,
+        bytes,
+    )
+}
+
+fn save_attachment_in(
+    group_dir: &Path,
+    message_id: &str,
+    file_name: &str,
+    bytes: &[u8],
+) -> Result<String> {
+    let dir = group_dir.join(ATTACHMENTS_DIR);
+    std::fs::create_dir_all(&dir).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to create attachments directory: {}", e),
+    })?;
+
+    let name = format!(
+        "{}-{}",
+        sanitize_file_name(message_id),
+        sanitize_file_name(file_name)
+    );
+    std::fs::write(dir.join(&name), bytes).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to save attachment: {}", e),
+    })?;
+
+    Ok(name)
+}
+
+/// Prompt line pointing the agent at a saved attachment
+pub fn prompt_reference(kind: &str, saved_name: &str) -> String {
+    format!(
+        "[Attached {}: {}/{}/{}]",
+        kind, CONTAINER_GROUP_DIR, ATTACHMENTS_DIR, saved_name
+    )
+}
+
+/// Files waiting in a group's outgoing directory, in name order
+pub fn outgoing_files(group_folder: &str) -> Result<Vec<PathBuf>> {
+    outgoing_files_in(&groups_dir().join(group_folder))
+}
+
+fn outgoing_files_in(group_dir: &Path) -> Result<Vec<PathBuf>> {
+    let dir = group_dir.join(OUTGOING_DIR);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .map_err(|e| NuClawError::FileSystem {
+            message: format!("Failed to read outgoing directory: {}", e),
+        })?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Guess a MIME type from a file extension
+pub fn mime_type_for(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp4" => "video/mp4",
+        "ogg" | "opus" => "audio/ogg",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "pdf" => "application/pdf",
+        "txt" | "md" => "text/plain",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Default file extension for a MIME type, used when media has no name
+pub fn extension_for(mime_type: &str) -> &'static str {
+    match mime_type.split(';').next().unwrap_or("").trim() {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "video/mp4" => "mp4",
+        "audio/ogg" => "ogg",
+        "audio/mpeg" => "mp3",
+        "audio/mp4" => "m4a",
+        "application/pdf" => "pdf",
+        "text/plain" => "txt",
+        _ => "bin",
+    }
+}
+
+/// Reduce a name to characters that are safe in a single path component
+fn sanitize_file_name(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let cleaned = cleaned.trim_start_matches('.');
+    if cleaned.is_empty() {
+        "file".to_string()
+    } else {
+        cleaned.chars().take(100).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("report.pdf"), "report.pdf");
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize_file_name("my photo (1).jpg"), "my_photo__1_.jpg");
+        assert_eq!(sanitize_file_name(".."), "file");
+        assert_eq!(sanitize_file_name(""), "file");
+    }
+
+    #[test]
+    fn test_save_attachment_in() {
+        let dir = TempDir::new().unwrap();
+        let name = save_attachment_in(dir.path(), "ABC123", "photo.jpg", b"data").unwrap();
+        assert_eq!(name, "ABC123-photo.jpg");
+        assert_eq!(
+            std::fs::read(dir.path().join(ATTACHMENTS_DIR).join(&name)).unwrap(),
+            b"data"
+        );
+        assert_eq!(
+            prompt_reference("image", &name),
+            "[Attached image: /workspace/group/attachments/ABC123-photo.jpg]"
+        );
+    }
+
+    #[test]
+    fn test_outgoing_files_in() {
+        let dir = TempDir::new().unwrap();
+        assert!(outgoing_files_in(dir.path()).unwrap().is_empty());
+
+        let outgoing = dir.path().join(OUTGOING_DIR);
+        std::fs::create_dir_all(outgoing.join("nested")).unwrap();
+        std::fs::write(outgoing.join("b.png"), b"").unwrap();
+        std::fs::write(outgoing.join("a.txt"), b"").unwrap();
+
+        let files = outgoing_files_in(dir.path()).unwrap();
+        assert_eq!(files, vec![outgoing.join("a.txt"), outgoing.join("b.png")]);
+    }
+
+    #[test]
+    fn test_mime_types() {
+        assert_eq!(mime_type_for(Path::new("chart.PNG")), "image/png");
+        assert_eq!(
+            mime_type_for(Path::new("notes")),
+            "application/octet-stream"
+        );
+        assert_eq!(extension_for("audio/ogg; codecs=opus"), "ogg");
+        assert_eq!(extension_for("application/x-unknown"), "bin");
+    }
+}
@@ -0,0 +1,290 @@
+//! Audit Log for NuClaw
+//!
+//! Security-relevant events are appended to the `audit_log` table, so
+//! groups shared by several people, some of them admins, keep a record of
+//! who did what:
+//!
+//! - `denied` - an admin command from a non-admin, a DM refused by the
+//!   DM policy, or an admin API request without a valid token
+//! - `allowlist` - an allowlist entry added or removed
+//! - `role` - a role granted or revoked (see `roles`)
+//! - `command` - an admin command run from a chat, the CLI, or the admin
+//!   API
+//! - `pairing` - a pairing code issued, redeemed, or rejected
+//! - `container` - an agent container started, with the SHA-256 of its
+//!   prompt rather than the prompt itself
+//! - `guard` - a message matching the content guard annotated, held, or
+//!   blocked, or a held message approved or rejected (see `prompt_guard`)
+//!
+//! The database rejects updates and deletes of audit rows. `nuclaw audit`
+//! lists them, newest first.
+
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use crate::task_scheduler::time_bound;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Kind of audited event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditKind {
+    Denied,
+    Allowlist,
+    Role,
+    Command,
+    Pairing,
+    Container,
+    Guard,
+}
+
+impl AuditKind {
+    pub const ALL: [AuditKind; 7] = [
+        AuditKind::Denied,
+        AuditKind::Allowlist,
+        AuditKind::Role,
+        AuditKind::Command,
+        AuditKind::Pairing,
+        AuditKind::Container,
+        AuditKind::Guard,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditKind::Denied => "denied",
+            AuditKind::Allowlist => "allowlist",
+            AuditKind::Role => "role",
+            AuditKind::Command => "command",
+            AuditKind::Pairing => "pairing",
+            AuditKind::Container => "container",
+            AuditKind::Guard => "guard",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s.trim().to_lowercase())
+    }
+}
+
+/// An event to record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub kind: AuditKind,
+    /// Who caused it: `<channel>:<sender>` for chat users (see
+    /// `chat_actor`), `cli`, `api`, `scheduler`, or `chat` for agent runs
+    /// answering messages
+    pub actor: String,
+    /// Chat it happened in, if any
+    pub chat_jid: Option<String>,
+    pub detail: String,
+}
+
+impl AuditEvent {
+    pub fn new(kind: AuditKind, actor: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            kind,
+            actor: actor.into(),
+            chat_jid: None,
+            detail: detail.into(),
+        }
+    }
+
+    /// Same event, in a chat
+    pub fn in_chat(self, chat_jid: &str) -> Self {
+        Self {
+            chat_jid: Some(chat_jid.to_string()),
+            ..self
+        }
+    }
+}
+
+/// Actor of an event caused by a chat user
+pub fn chat_actor(channel: &str, sender: &str) -> String {
+    format!("{}:{}", channel, sender)
+}
+
+/// Digest of a prompt, recorded instead of the prompt
+pub fn prompt_hash(prompt: &str) -> String {
+    format!("sha256:{:x}", Sha256::digest(prompt.as_bytes()))
+}
+
+/// Append an event to the audit log
+pub fn append(db: &Database, event: &AuditEvent) -> Result<()> {
+    db.get_connection()?
+        .execute(
+            "INSERT INTO audit_log (tenant, created_at, kind, actor, chat_jid, detail)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            crate::params![
+                db.tenant(),
+                chrono::Utc::now().to_rfc3339(),
+                event.kind.as_str(),
+                event.actor,
+                event.chat_jid,
+                event.detail
+            ],
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to append to the audit log: {}", e),
+        })?;
+    Ok(())
+}
+
+/// Append an event, logging rather than returning a failure, so auditing
+/// never stops what is audited
+pub fn record(db: &Database, event: AuditEvent) {
+    if let Err(e) = append(db, &event) {
+        tracing::warn!("{} (event: {:?})", e, event);
+    }
+}
+
+/// A recorded event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: String,
+    pub kind: String,
+    pub actor: String,
+    pub chat_jid: Option<String>,
+    pub detail: String,
+}
+
+/// Which events to load; unset fields match every event
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditFilter {
+    /// `denied`, `allowlist`, `role`, `command`, `pairing`, `container`, or
+    /// `guard`
+    pub kind: Option<String>,
+    pub actor: Option<String>,
+    pub chat_jid: Option<String>,
+    /// Events at or after this time (RFC 3339, or a `YYYY-MM-DD` date)
+    pub since: Option<String>,
+    /// Events before this time (RFC 3339, or a `YYYY-MM-DD` date)
+    pub until: Option<String>,
+}
+
+/// Load the events matching `filter`, newest first
+pub fn query(db: &Database, filter: &AuditFilter, limit: usize) -> Result<Vec<AuditEntry>> {
+    let kind = filter
+        .kind
+        .as_deref()
+        .map(|kind| {
+            AuditKind::parse(kind).ok_or_else(|| NuClawError::Validation {
+                message: format!(
+                    "Unknown audit event kind '{}'; use one of: {}",
+                    kind,
+                    AuditKind::ALL.map(|kind| kind.as_str()).join(", ")
+                ),
+            })
+        })
+        .transpose()?
+        .map(|kind| kind.as_str());
+    let since = filter.since.as_deref().map(time_bound).transpose()?;
+    let until = filter.until.as_deref().map(time_bound).transpose()?;
+    db.read_connection()?.query_map(
+        "SELECT id, created_at, kind, actor, chat_jid, detail
+         FROM audit_log
+         WHERE tenant = ?7
+           AND (CAST(?1 AS TEXT) IS NULL OR kind = ?1)
+           AND (CAST(?2 AS TEXT) IS NULL OR actor = ?2)
+           AND (CAST(?3 AS TEXT) IS NULL OR chat_jid = ?3)
+           AND (CAST(?4 AS TEXT) IS NULL OR created_at >= ?4)
+           AND (CAST(?5 AS TEXT) IS NULL OR created_at < ?5)
+         ORDER BY id DESC LIMIT ?6",
+        crate::params![
+            kind,
+            filter.actor,
+            filter.chat_jid,
+            since,
+            until,
+            limit as i64,
+            db.tenant()
+        ],
+        |row| {
+            Ok(AuditEntry {
+                id: row.get(0)?,
+                created_at: row.get(1)?,
+                kind: row.get(2)?,
+                actor: row.get(3)?,
+                chat_jid: row.get(4)?,
+                detail: row.get(5)?,
+            })
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_prompt_hash() {
+        assert_eq!(
+            prompt_hash("hello"),
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        let (db, _dir) = test_database();
+        record(
+            &db,
+            AuditEvent::new(AuditKind::Denied, chat_actor("telegram", "42"), "/pause")
+                .in_chat("telegram:group:-100"),
+        );
+        record(
+            &db,
+            AuditEvent::new(AuditKind::Command, "cli", "task delete t1"),
+        );
+
+        let all = query(&db, &AuditFilter::default(), 10).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].detail, "task delete t1");
+        assert_eq!(all[1].actor, "telegram:42");
+        assert_eq!(all[1].chat_jid.as_deref(), Some("telegram:group:-100"));
+
+        let denied = AuditFilter {
+            kind: Some("Denied".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(&db, &denied, 10).unwrap().len(), 1);
+        let by_cli = AuditFilter {
+            actor: Some("cli".to_string()),
+            since: Some("2000-01-01".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(&db, &by_cli, 10).unwrap().len(), 1);
+        assert_eq!(query(&db, &AuditFilter::default(), 1).unwrap().len(), 1);
+
+        let unknown = AuditFilter {
+            kind: Some("login".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            query(&db, &unknown, 10),
+            Err(NuClawError::Validation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_audit_log_is_append_only() {
+        let (db, _dir) = test_database();
+        record(
+            &db,
+            AuditEvent::new(AuditKind::Pairing, "cli", "Issued a pairing code"),
+        );
+
+        let conn = db.get_connection().unwrap();
+        assert!(conn
+            .execute("UPDATE audit_log SET actor = 'someone else'", ())
+            .is_err());
+        assert!(conn.execute("DELETE FROM audit_log", ()).is_err());
+        drop(conn);
+        assert_eq!(
+            query(&db, &AuditFilter::default(), 10).unwrap()[0].actor,
+            "cli"
+        );
+    }
+}
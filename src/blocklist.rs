@@ -0,0 +1,118 @@
+//! Blocklist for NuClaw
+//!
+//! Users blocked with `/block` are ignored on their channel: their
+//! messages and commands are dropped as they arrive, before they are
+//! stored, so they reach neither the agent nor the context of other
+//! users' messages. Entries are kept per tenant and channel in the
+//! `blocked_users` table, so a misbehaving group member can be silenced
+//! without disabling the whole group, and unblocked again with `/unblock`.
+
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use serde::Serialize;
+
+/// A blocked user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockedUser {
+    pub user_id: String,
+    pub reason: Option<String>,
+    pub blocked_by: String,
+    pub blocked_at: String,
+}
+
+/// Block a user; returns `false` if they were already blocked
+pub fn block(
+    db: &Database,
+    channel: &str,
+    user_id: &str,
+    reason: Option<&str>,
+    blocked_by: &str,
+) -> Result<bool> {
+    let inserted = db
+        .get_connection()?
+        .execute(
+            "INSERT INTO blocked_users (tenant, channel, user_id, reason, blocked_by, blocked_at)
+             VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT DO NOTHING",
+            crate::params![
+                db.tenant(),
+                channel,
+                user_id,
+                reason,
+                blocked_by,
+                chrono::Utc::now().to_rfc3339()
+            ],
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to update blocklist: {}", e),
+        })?;
+    Ok(inserted > 0)
+}
+
+/// Unblock a user; returns `false` if they were not blocked
+pub fn unblock(db: &Database, channel: &str, user_id: &str) -> Result<bool> {
+    let removed = db
+        .get_connection()?
+        .execute(
+            "DELETE FROM blocked_users WHERE tenant = ? AND channel = ? AND user_id = ?",
+            [db.tenant(), channel, user_id],
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to update blocklist: {}", e),
+        })?;
+    Ok(removed > 0)
+}
+
+/// Check if a user is blocked on a channel
+pub fn is_blocked(db: &Database, channel: &str, user_id: &str) -> Result<bool> {
+    let count: i64 = db.read_connection()?.query_row(
+        "SELECT COUNT(*) FROM blocked_users WHERE tenant = ? AND channel = ? AND user_id = ?",
+        [db.tenant(), channel, user_id],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// List the users blocked on a channel, oldest first
+pub fn list(db: &Database, channel: &str) -> Result<Vec<BlockedUser>> {
+    db.read_connection()?.query_map(
+        "SELECT user_id, reason, blocked_by, blocked_at FROM blocked_users
+         WHERE tenant = ? AND channel = ? ORDER BY blocked_at, user_id",
+        [db.tenant(), channel],
+        |row| {
+            Ok(BlockedUser {
+                user_id: row.get(0)?,
+                reason: row.get(1)?,
+                blocked_by: row.get(2)?,
+                blocked_at: row.get(3)?,
+            })
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_block_and_unblock() {
+        let (db, _dir) = test_database();
+
+        assert!(block(&db, "telegram", "42", Some("spam"), "7").unwrap());
+        assert!(!block(&db, "telegram", "42", None, "7").unwrap());
+        assert!(is_blocked(&db, "telegram", "42").unwrap());
+        // Channels are separate namespaces
+        assert!(!is_blocked(&db, "whatsapp", "42").unwrap());
+
+        block(&db, "telegram", "43", None, "7").unwrap();
+        let blocked = list(&db, "telegram").unwrap();
+        assert_eq!(blocked.len(), 2);
+        assert_eq!(blocked[0].user_id, "42");
+        assert_eq!(blocked[0].reason.as_deref(), Some("spam"));
+        assert_eq!(blocked[1].reason, None);
+
+        assert!(unblock(&db, "telegram", "42").unwrap());
+        assert!(!unblock(&db, "telegram", "42").unwrap());
+        assert!(!is_blocked(&db, "telegram", "42").unwrap());
+    }
+}
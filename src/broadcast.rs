@@ -0,0 +1,326 @@
+//! Broadcasts for NuClaw
+//!
+//! Sends the same text to several registered groups across channels.
+//! Targets are `all`, a group folder, or a named list from
+//! `data/broadcast_lists.json` (`{"family": ["family-chat", "cousins"]}`).
+//! Messages go through each channel's outbox, so the usual chunking, rate
+//! limiting, and retries apply. A broadcast reaches the groups and lists
+//! of the database handle's tenant only; other tenants keep their lists in
+//! their own data directory.
+//!
+//! Agents request broadcasts by writing `{"op": "broadcast", "target": ...,
+//! "text": ...}` files to `/workspace/ipc/requests/`; only group folders
+//! listed in `BROADCAST_IPC_FOLDERS` may do so. The same directory takes
+//! `{"op": "remember", "text": ...}` requests, which any group may use to
+//! store a memory (see `memory`), and `set_state` and `delete_state`
+//! requests for the group's key-value state (see `agent_state`).
+
+use crate::agent_state::{delete_state, set_state};
+use crate::container_runner::create_group_ipc_directory;
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use crate::groups::load_registered_groups;
+use crate::memory::add_memory;
+use crate::outbox::Outbox;
+use crate::tenants::tenant_data_dir;
+use crate::types::RegisteredGroup;
+use crate::utils::json::load_json;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Target that addresses every registered group
+const ALL_TARGET: &str = "all";
+/// Subdirectory of a group's IPC directory holding agent requests
+pub const IPC_REQUESTS_DIR: &str = "requests";
+
+/// Messages queued per channel by a broadcast
+pub type BroadcastReport = BTreeMap<&'static str, usize>;
+
+/// Path of a tenant's named broadcast lists
+pub fn broadcast_lists_path(tenant_id: &str) -> std::path::PathBuf {
+    tenant_data_dir(tenant_id).join("broadcast_lists.json")
+}
+
+/// Channel whose outbox delivers to a chat JID
+pub fn channel_for_jid(jid: &str) -> &'static str {
+    if jid.starts_with("telegram:") {
+        "telegram"
+    } else {
+        "whatsapp"
+    }
+}
+
+/// Queue `text` for every chat the target resolves to
+pub fn broadcast(db: &Database, target: &str, text: &str) -> Result<BroadcastReport> {
+    if text.trim().is_empty() {
+        return Err(NuClawError::Validation {
+            message: "Broadcast text is empty".to_string(),
+        });
+    }
+
+    let groups = load_registered_groups(db.tenant());
+    let lists: HashMap<String, Vec<String>> =
+        load_json(&broadcast_lists_path(db.tenant()), HashMap::new());
+    let jids = resolve_targets(&groups, &lists, target)?;
+
+    let mut report = BroadcastReport::new();
+    for jid in jids {
+        let channel = channel_for_jid(&jid);
+        Outbox::new(db.clone(), channel).enqueue(&jid, text)?;
+        *report.entry(channel).or_default() += 1;
+    }
+
+    info!("Broadcast to '{}' queued: {:?}", target, report);
+    Ok(report)
+}
+
+/// Resolve a comma-separated target into chat JIDs, sorted and deduplicated
+///
+/// Each part is `all`, a broadcast list name, or a group folder.
+pub fn resolve_targets(
+    groups: &HashMap<String, RegisteredGroup>,
+    lists: &HashMap<String, Vec<String>>,
+    target: &str,
+) -> Result<Vec<String>> {
+    let mut folders: Vec<&str> = vec![];
+    for part in target.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        if part == ALL_TARGET {
+            folders.extend(groups.values().map(|g| g.folder.as_str()));
+        } else if let Some(list) = lists.get(part) {
+            folders.extend(list.iter().map(String::as_str));
+        } else if groups.values().any(|g| g.folder == part) {
+            folders.push(part);
+        } else {
+            return Err(NuClawError::Validation {
+                message: format!("Unknown broadcast target '{}'", part),
+            });
+        }
+    }
+
+    let mut jids: Vec<String> = groups
+        .iter()
+        .filter(|(_, g)| folders.contains(&g.folder.as_str()))
+        .map(|(jid, _)| jid.clone())
+        .collect();
+    if jids.is_empty() {
+        return Err(NuClawError::Validation {
+            message: format!("Broadcast target '{}' matches no registered chats", target),
+        });
+    }
+    jids.sort();
+    Ok(jids)
+}
+
+/// An operation requested by an agent through its IPC directory
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum IpcRequest {
+    Broadcast {
+        target: String,
+        text: String,
+    },
+    Remember {
+        text: String,
+    },
+    SetState {
+        key: String,
+        value: serde_json::Value,
+    },
+    DeleteState {
+        key: String,
+    },
+}
+
+impl IpcRequest {
+    fn op(&self) -> &'static str {
+        match self {
+            IpcRequest::Broadcast { .. } => "broadcast",
+            IpcRequest::Remember { .. } => "remember",
+            IpcRequest::SetState { .. } => "set_state",
+            IpcRequest::DeleteState { .. } => "delete_state",
+        }
+    }
+}
+
+/// Group folders allowed to broadcast through IPC (`BROADCAST_IPC_FOLDERS`)
+fn ipc_broadcast_folders() -> Vec<String> {
+    std::env::var("BROADCAST_IPC_FOLDERS")
+        .ok()
+        .map(|s| {
+            s.split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Execute and remove the IPC requests a group's agent left behind
+///
+/// Returns the number of requests executed. Invalid or unauthorized
+/// requests are logged and dropped.
+pub fn process_ipc_requests(db: &Database, group_folder: &str) -> Result<usize> {
+    let dir = create_group_ipc_directory(group_folder)?.join(IPC_REQUESTS_DIR);
+    let allowed = ipc_broadcast_folders().iter().any(|f| f == group_folder);
+    process_ipc_requests_in(&dir, group_folder, allowed, |request| match request {
+        IpcRequest::Broadcast { target, text } => broadcast(db, &target, &text).map(|_| ()),
+        IpcRequest::Remember { text } => add_memory(db, group_folder, &text).map(|_| ()),
+        IpcRequest::SetState { key, value } => set_state(db, group_folder, &key, &value),
+        IpcRequest::DeleteState { key } => delete_state(db, group_folder, &key).map(|_| ()),
+    })
+}
+
+fn process_ipc_requests_in(
+    dir: &Path,
+    group_folder: &str,
+    broadcast_allowed: bool,
+    mut execute: impl FnMut(IpcRequest) -> Result<()>,
+) -> Result<usize> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .map_err(|e| NuClawError::FileSystem {
+            message: format!("Failed to read IPC requests: {}", e),
+        })?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort();
+
+    let mut executed = 0;
+    for path in paths {
+        let request = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| serde_json::from_str::<IpcRequest>(&s).map_err(|e| e.to_string()));
+        let _ = std::fs::remove_file(&path);
+
+        match request {
+            Ok(IpcRequest::Broadcast { .. }) if !broadcast_allowed => {
+                warn!("Group {} is not allowed to broadcast", group_folder);
+            }
+            Ok(request) => {
+                let op = request.op();
+                match execute(request) {
+                    Ok(()) => executed += 1,
+                    Err(e) => warn!("{} requested by {} failed: {}", op, group_folder, e),
+                }
+            }
+            Err(e) => warn!("Invalid IPC request {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(executed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn group(folder: &str) -> RegisteredGroup {
+        RegisteredGroup {
+            name: folder.to_string(),
+            folder: folder.to_string(),
+            trigger: "@Andy".to_string(),
+            added_at: String::new(),
+            aliases: vec![],
+            read_receipts: None,
+            presence: None,
+            container_config: None,
+        }
+    }
+
+    fn registry() -> HashMap<String, RegisteredGroup> {
+        HashMap::from([
+            ("telegram:group:-1".to_string(), group("family")),
+            ("123@g.us".to_string(), group("cousins")),
+            ("456@g.us".to_string(), group("work")),
+        ])
+    }
+
+    #[test]
+    fn test_resolve_targets() {
+        let groups = registry();
+        let lists = HashMap::from([(
+            "relatives".to_string(),
+            vec!["family".to_string(), "cousins".to_string()],
+        )]);
+
+        assert_eq!(resolve_targets(&groups, &lists, "all").unwrap().len(), 3);
+        assert_eq!(
+            resolve_targets(&groups, &lists, "relatives").unwrap(),
+            vec!["123@g.us".to_string(), "telegram:group:-1".to_string()]
+        );
+        assert_eq!(
+            resolve_targets(&groups, &lists, "work, relatives, work")
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(resolve_targets(&groups, &lists, "nobody").is_err());
+        assert!(resolve_targets(&groups, &lists, "").is_err());
+    }
+
+    #[test]
+    fn test_channel_for_jid() {
+        assert_eq!(channel_for_jid("telegram:group:-1"), "telegram");
+        assert_eq!(channel_for_jid("123@g.us"), "whatsapp");
+    }
+
+    #[test]
+    fn test_process_ipc_requests_in() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("1.json"),
+            r#"{"op": "broadcast", "target": "all", "text": "Digest"}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("2.json"), r#"{"op": "unknown"}"#).unwrap();
+        std::fs::write(
+            dir.path().join("3.json"),
+            r#"{"op": "remember", "text": "Digest goes out on Mondays"}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("4.json"),
+            r#"{"op": "set_state", "key": "last_digest", "value": {"id": 7}}"#,
+        )
+        .unwrap();
+
+        let mut sent = vec![];
+        let mut ops = vec![];
+        let executed = process_ipc_requests_in(dir.path(), "news", true, |request| {
+            ops.push(request.op());
+            if let IpcRequest::Broadcast { target, text } = request {
+                sent.push((target, text));
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(executed, 3);
+        assert_eq!(ops, vec!["broadcast", "remember", "set_state"]);
+        assert_eq!(sent, vec![("all".to_string(), "Digest".to_string())]);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_process_ipc_requests_requires_permission() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("1.json"),
+            r#"{"op": "broadcast", "target": "all", "text": "Spam"}"#,
+        )
+        .unwrap();
+
+        let executed =
+            process_ipc_requests_in(dir.path(), "kids", false, |_| panic!("must not send"))
+                .unwrap();
+        assert_eq!(executed, 0);
+        assert!(!dir.path().join("1.json").exists());
+    }
+}
@@ -0,0 +1,210 @@
+//! Fair Agent Run Scheduling for NuClaw
+//!
+//! Agent runs are queued per chat. Each chat runs at most one job at a
+//! time, so its replies keep their order, and chats take turns in
+//! round-robin order under a global cap (`MAX_CONCURRENT_RUNS`), so one
+//! busy group cannot starve the others.
+//!
+//! Once NuClaw is shutting down, waiting jobs are no longer started (see
+//! `shutdown`).
+
+use crate::shutdown;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+/// Default number of agent runs executing at once
+const DEFAULT_MAX_CONCURRENT_RUNS: usize = 4;
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+#[derive(Default)]
+struct QueueState {
+    /// Waiting jobs per chat
+    jobs: HashMap<String, VecDeque<Job>>,
+    /// Chats with waiting jobs and nothing running, in turn order
+    ready: VecDeque<String>,
+    /// Chats with a job running
+    active: HashSet<String>,
+}
+
+/// Per-chat job queue with round-robin fairness and a concurrency cap
+#[derive(Clone)]
+pub struct ChatQueue {
+    state: Arc<Mutex<QueueState>>,
+    max_concurrent: usize,
+}
+
+impl ChatQueue {
+    /// Create a queue running at most `max_concurrent` jobs at once
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(QueueState::default())),
+            max_concurrent: max_concurrent.max(1),
+        }
+    }
+
+    /// Create a queue capped by `MAX_CONCURRENT_RUNS`
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("MAX_CONCURRENT_RUNS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_RUNS),
+        )
+    }
+
+    /// Queue a job for a chat; it starts once the chat's turn comes
+    pub fn push<F>(&self, chat_jid: &str, job: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        {
+            let mut state = self.state.lock().unwrap();
+            let waiting = state.jobs.contains_key(chat_jid);
+            state
+                .jobs
+                .entry(chat_jid.to_string())
+                .or_default()
+                .push_back(Box::pin(job));
+            if !waiting && !state.active.contains(chat_jid) {
+                state.ready.push_back(chat_jid.to_string());
+            }
+        }
+        self.dispatch();
+    }
+
+    /// Number of jobs waiting to start
+    pub fn waiting(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.jobs.values().map(VecDeque::len).sum()
+    }
+
+    /// Start jobs while there is capacity, one per chat in turn order
+    fn dispatch(&self) {
+        if shutdown::is_requested() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        while state.active.len() < self.max_concurrent {
+            let Some(chat_jid) = state.ready.pop_front() else {
+                break;
+            };
+            let Some(job) = take_job(&mut state.jobs, &chat_jid) else {
+                continue;
+            };
+            state.active.insert(chat_jid.clone());
+
+            let queue = self.clone();
+            let run = shutdown::agent_runs().start();
+            tokio::spawn(async move {
+                let _run = run;
+                // A panicking job must still free its chat's slot
+                if let Err(e) = tokio::spawn(job).await {
+                    error!("Agent run for {} panicked: {}", chat_jid, e);
+                }
+                queue.finish(&chat_jid);
+            });
+        }
+    }
+
+    /// Release a chat after its job ended and let the next one start
+    fn finish(&self, chat_jid: &str) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.active.remove(chat_jid);
+            if state.jobs.contains_key(chat_jid) {
+                state.ready.push_back(chat_jid.to_string());
+            }
+        }
+        self.dispatch();
+    }
+}
+
+fn take_job(jobs: &mut HashMap<String, VecDeque<Job>>, chat_jid: &str) -> Option<Job> {
+    let queue = jobs.get_mut(chat_jid)?;
+    let job = queue.pop_front();
+    if queue.is_empty() {
+        jobs.remove(chat_jid);
+    }
+    job
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::time::{sleep, timeout, Duration};
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        timeout(Duration::from_secs(5), async {
+            while !condition() {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("jobs did not finish");
+    }
+
+    #[tokio::test]
+    async fn test_chats_take_turns() {
+        let queue = ChatQueue::new(1);
+        let order = Arc::new(Mutex::new(vec![]));
+
+        for (chat, label) in [
+            ("a", "a1"),
+            ("a", "a2"),
+            ("a", "a3"),
+            ("b", "b1"),
+            ("c", "c1"),
+        ] {
+            let order = order.clone();
+            queue.push(chat, async move {
+                sleep(Duration::from_millis(5)).await;
+                order.lock().unwrap().push(label);
+            });
+        }
+
+        wait_until(|| order.lock().unwrap().len() == 5).await;
+        assert_eq!(*order.lock().unwrap(), vec!["a1", "b1", "c1", "a2", "a3"]);
+        assert_eq!(queue.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_cap() {
+        let queue = ChatQueue::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicUsize::new(0));
+
+        for i in 0..6 {
+            let (running, peak, done) = (running.clone(), peak.clone(), done.clone());
+            queue.push(&format!("chat{}", i), async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                sleep(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        wait_until(|| done.load(Ordering::SeqCst) == 6).await;
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_job_frees_its_chat() {
+        let queue = ChatQueue::new(1);
+        let done = Arc::new(AtomicUsize::new(0));
+
+        queue.push("a", async { panic!("boom") });
+        let counter = done.clone();
+        queue.push("a", async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        wait_until(|| done.load(Ordering::SeqCst) == 1).await;
+    }
+}
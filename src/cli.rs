@@ -0,0 +1,433 @@
+//! Command-Line Interface of NuClaw
+//!
+//! The `nuclaw` binary's arguments and subcommand tree. Each setting in
+//! `settings::SETTINGS` adds a flag on top of these (see `command`).
+//!
+//! This module only depends on `settings`, so the build script can
+//! include both to render the man pages; `nuclaw completions <shell>`
+//! prints the shell completions from the same definition.
+
+use crate::settings;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+
+/// Personal Claude assistant. Lightweight, secure, customizable.
+#[derive(Parser, Debug)]
+#[command(name = "nuclaw", version)]
+pub struct Cli {
+    /// Only run, or run commands for, this tenant (default: all tenants
+    /// when serving, the default tenant for commands)
+    #[arg(long, global = true)]
+    pub tenant: Option<String>,
+
+    /// Don't read `.env` and `.env.local` from the working directory
+    #[arg(long)]
+    pub no_dotenv: bool,
+
+    /// What to do; `serve` if none is given
+    #[command(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the task scheduler and the bots
+    Serve(ServeArgs),
+    /// Set up access to NuClaw
+    #[command(subcommand)]
+    Auth(AuthCommand),
+    /// Manage scheduled tasks
+    #[command(subcommand)]
+    Task(TaskCommand),
+    /// Manage registered groups
+    #[command(subcommand)]
+    Group(GroupCommand),
+    /// Maintain the database
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Manage the roles of chat users
+    #[command(subcommand)]
+    Role(RoleCommand),
+    /// Queue a message to a group folder, a named list, or `all`
+    Send {
+        /// Group folder, broadcast list, or `all`
+        target: String,
+        /// Message text
+        #[arg(required = true)]
+        text: Vec<String>,
+    },
+    /// Chat with the agent of a group folder in the terminal
+    Repl {
+        /// Group folder the agent runs in
+        #[arg(long, default_value = "main")]
+        group: String,
+    },
+    /// Issue a one-time DM pairing code, or manage paired users
+    Pair {
+        #[command(subcommand)]
+        command: Option<PairCommand>,
+    },
+    /// Pause task runs and agent runs for maintenance
+    Pause,
+    /// End maintenance mode
+    Resume,
+    /// Print usage statistics
+    Stats {
+        /// Days covered
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+    },
+    /// Print agent runs, tokens, and cost per calendar month (UTC)
+    Usage {
+        /// Months covered, the current one included
+        #[arg(long, default_value_t = 3)]
+        months: u32,
+
+        /// Roll up by chat or by user
+        #[arg(long, value_parser = ["chat", "user"], default_value = "chat")]
+        by: String,
+
+        /// Only count the runs of this chat
+        #[arg(long)]
+        chat: Option<String>,
+    },
+    /// List the audit log of security-relevant events, newest first
+    Audit {
+        /// Only list events of this kind
+        #[arg(long, value_parser = ["denied", "allowlist", "role", "command", "pairing", "container", "guard"])]
+        kind: Option<String>,
+
+        /// Only list events caused by this actor, e.g. `telegram:12345`,
+        /// `cli`, or `api`
+        #[arg(long)]
+        actor: Option<String>,
+
+        /// Only list events in this chat
+        #[arg(long)]
+        chat: Option<String>,
+
+        /// Only list events at or after this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Only list events before this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long)]
+        until: Option<String>,
+
+        /// Maximum number of events to list
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+    },
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Manage the encrypted credential store
+    #[command(subcommand)]
+    Credentials(CredentialsCommand),
+    /// Move the store, groups, and data of an earlier version from the
+    /// working directory to their current locations
+    MigrateHome,
+    /// Print the completion script of a shell
+    ///
+    /// For example, `nuclaw completions bash > /etc/bash_completion.d/nuclaw`
+    /// or `nuclaw completions zsh > ~/.zfunc/_nuclaw`.
+    Completions {
+        /// bash, elvish, fish, powershell, or zsh
+        shell: Shell,
+    },
+}
+
+impl Command {
+    /// Whether the command changes tasks, groups, the database, or
+    /// maintenance mode, so running it is recorded in the audit log
+    pub fn is_audited(&self) -> bool {
+        match self {
+            Command::Task(task) => !matches!(
+                task,
+                TaskCommand::List { .. }
+                    | TaskCommand::Show { .. }
+                    | TaskCommand::Runs { .. }
+                    | TaskCommand::CheckSchedule { .. }
+            ),
+            Command::Group(group) => !matches!(group, GroupCommand::List),
+            Command::Db(db) => !matches!(db, DbCommand::Status),
+            Command::Role(role) => !matches!(role, RoleCommand::List),
+            Command::Pair { command } => matches!(command, Some(PairCommand::Revoke { .. })),
+            Command::Send { .. } | Command::Pause | Command::Resume => true,
+            _ => false,
+        }
+    }
+}
+
+/// Parts `serve` runs; without any of these flags, all of them run
+#[derive(clap::Args, Debug, Default)]
+pub struct ServeArgs {
+    /// Run the task scheduler
+    #[arg(long)]
+    pub scheduler: bool,
+
+    /// Run the Telegram bot of each tenant with a bot token
+    #[arg(long)]
+    pub telegram: bool,
+
+    /// Run the WhatsApp bot of each tenant with an MCP server
+    #[arg(long)]
+    pub whatsapp: bool,
+}
+
+impl ServeArgs {
+    pub fn all(&self) -> bool {
+        !(self.scheduler || self.telegram || self.whatsapp)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AuthCommand {
+    /// Show the QR code linking the WhatsApp MCP server to a phone
+    Whatsapp,
+    /// Issue a one-time DM pairing code (same as `nuclaw pair`)
+    Pair,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PairCommand {
+    /// List the users paired through the pairing DM policy
+    List,
+    /// Remove a pairing; the user needs a new code to DM the bot again
+    Revoke {
+        /// Channel the user paired on: `telegram` or `whatsapp`
+        channel: String,
+        /// User ID on that channel
+        user_id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TaskCommand {
+    /// Schedule a task in a registered group
+    Add {
+        /// Folder of the group the task runs in and reports to
+        #[arg(long)]
+        group: String,
+
+        /// `cron`, `interval`, or `once`
+        schedule_type: String,
+
+        /// Cron expression (with seconds), interval such as `2h`, or time
+        schedule_value: String,
+
+        /// Prompt of each run
+        #[arg(required = true)]
+        prompt: Vec<String>,
+
+        /// Time limit of a run in seconds (default: `TASK_TIMEOUT`)
+        #[arg(long)]
+        timeout: Option<u64>,
+
+        /// When runs are reported to the chat: always, on_change,
+        /// on_failure, or never
+        #[arg(long)]
+        notify: Option<String>,
+    },
+    /// List scheduled tasks
+    List {
+        /// Only list the tasks of this group folder
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Show a task with its next and latest runs
+    Show { task_id: String },
+    /// Stop a task from running until it is resumed
+    Pause { task_id: String },
+    /// Schedule a paused, failed, or completed task again
+    Resume { task_id: String },
+    /// Delete a task and its run history
+    Delete { task_id: String },
+    /// Start a task at the running scheduler's next pass
+    RunNow { task_id: String },
+    /// List the latest runs of a task, or of all tasks
+    Runs {
+        /// Task ID; all tasks if omitted
+        task_id: Option<String>,
+
+        /// Only list runs with this status (success, error, timeout, cancelled)
+        #[arg(long)]
+        status: Option<String>,
+
+        /// Only list runs at or after this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Only list runs before this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long)]
+        until: Option<String>,
+
+        /// Maximum number of runs to list
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Validate a schedule and print its next runs in the `TZ` timezone
+    CheckSchedule {
+        /// `cron`, `interval`, or `once`
+        schedule_type: String,
+
+        /// Cron expression, interval, or time
+        schedule_value: String,
+
+        /// Number of runs listed
+        #[arg(long, default_value_t = 5)]
+        count: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GroupCommand {
+    /// Register a chat under a group folder, creating the folder
+    Register {
+        /// Chat JID, e.g. `telegram:group:-100123` or `123456@g.us`
+        chat_jid: String,
+
+        /// Group folder
+        folder: String,
+
+        /// Trigger words, the primary one first (default:
+        /// `@<ASSISTANT_NAME>`)
+        #[arg(long = "trigger")]
+        triggers: Vec<String>,
+    },
+    /// List the registered groups
+    List,
+    /// Unregister a group, deleting its tasks and archiving its folder
+    Unregister { folder: String },
+    /// Move a group to another folder
+    Rename { folder: String, new_folder: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RoleCommand {
+    /// List the granted roles; `ADMIN_USERS` are owners without one
+    List,
+    /// Give a chat user a role, for all groups or in one group
+    Set {
+        /// Channel of the user: `telegram` or `whatsapp`
+        channel: String,
+        /// User ID on that channel
+        user_id: String,
+        #[arg(value_parser = ["owner", "admin", "member"])]
+        role: String,
+        /// Only in this group folder
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Revoke a chat user's role, for all groups or in one group
+    Remove {
+        /// Channel of the user: `telegram` or `whatsapp`
+        channel: String,
+        /// User ID on that channel
+        user_id: String,
+        /// Only in this group folder
+        #[arg(long)]
+        group: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// Print the backend, location, and connection pool of the database
+    Status,
+    /// Create or upgrade the database schema
+    Migrate,
+    /// Reclaim the space of deleted rows
+    Vacuum,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the effective value and source of every setting, with
+    /// credentials masked
+    Show,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CredentialsCommand {
+    /// Print a new master key, to set as `NUCLAW_MASTER_KEY`
+    GenerateKey,
+    /// List the names of the stored credentials
+    List,
+    /// Store a credential setting, reading its value from standard input
+    Set {
+        /// Setting name, e.g. `TELEGRAM_BOT_TOKEN`
+        name: String,
+    },
+    /// Remove a stored credential
+    Remove {
+        /// Setting name
+        name: String,
+    },
+    /// Encrypt the WhatsApp session in `store/auth/` into `auth.enc` and
+    /// delete the plain-text files; run it while NuClaw is stopped
+    SealAuth,
+}
+
+/// The full command line: the arguments above and the setting flags
+pub fn command() -> clap::Command {
+    settings::setting_args(Cli::command())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::FromArgMatches;
+
+    #[test]
+    fn test_command() {
+        command().debug_assert();
+
+        // `--tenant` is accepted after the subcommand too
+        let matches = command()
+            .try_get_matches_from([
+                "nuclaw", "task", "list", "--group", "main", "--tenant", "acme",
+            ])
+            .unwrap();
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        assert_eq!(cli.tenant.as_deref(), Some("acme"));
+        assert!(matches!(
+            cli.cmd,
+            Some(Command::Task(TaskCommand::List { group: Some(ref group) })) if group == "main"
+        ));
+
+        let matches = command()
+            .try_get_matches_from(["nuclaw", "completions", "zsh"])
+            .unwrap();
+        assert!(matches!(
+            Cli::from_arg_matches(&matches).unwrap().cmd,
+            Some(Command::Completions { shell: Shell::Zsh })
+        ));
+        assert!(command()
+            .try_get_matches_from(["nuclaw", "completions", "tcsh"])
+            .is_err());
+
+        let matches = command().try_get_matches_from(["nuclaw", "pair"]).unwrap();
+        assert!(matches!(
+            Cli::from_arg_matches(&matches).unwrap().cmd,
+            Some(Command::Pair { command: None })
+        ));
+        let matches = command()
+            .try_get_matches_from(["nuclaw", "pair", "revoke", "telegram", "42"])
+            .unwrap();
+        let cmd = Cli::from_arg_matches(&matches).unwrap().cmd.unwrap();
+        assert!(cmd.is_audited());
+        assert!(matches!(
+            cmd,
+            Command::Pair { command: Some(PairCommand::Revoke { ref user_id, .. }) } if user_id == "42"
+        ));
+
+        let matches = command()
+            .try_get_matches_from(["nuclaw", "credentials", "set", "TELEGRAM_BOT_TOKEN"])
+            .unwrap();
+        assert!(matches!(
+            Cli::from_arg_matches(&matches).unwrap().cmd,
+            Some(Command::Credentials(CredentialsCommand::Set { ref name })) if name == "TELEGRAM_BOT_TOKEN"
+        ));
+    }
+}
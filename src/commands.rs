@@ -0,0 +1,1603 @@
+//! Chat Commands for NuClaw
+//!
+//! Parses slash commands sent to the bot (e.g. `/pair`, `/allow`, `/status`, `/chats`, `/task`, `/template`, `/pause`, `/loglevel`, `/usage`, `/broadcast`, `/role`, `/block`) and executes them
+//! for senders whose role permits them (see `roles`). Commands are
+//! channel-agnostic: each channel client parses the incoming text, builds
+//! a `CommandContext`, and sends back the reply returned by
+//! `execute_command`.
+
+use crate::allowlist::{self, AllowlistKind};
+use crate::audit::{self, chat_actor, AuditEvent, AuditKind};
+use crate::blocklist;
+use crate::broadcast::broadcast;
+use crate::config::uptime;
+use crate::container_runner::{cancel_chat, container_limiter, running_containers};
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use crate::groups::{load_registered_groups, register_group, set_triggers};
+use crate::leader::{current_leader, instance_id};
+use crate::logging::{log_filter, set_log_filter};
+use crate::maintenance::{self, pause_state};
+use crate::outbox::queue_stats;
+use crate::pairing::{create_pairing_code, pairing_code_ttl};
+use crate::pending::{approve_held, reject_held};
+use crate::roles::{
+    format_roles, is_permitted, list_roles, remove_role, role_of, set_role, Permission, Role,
+};
+use crate::task_scheduler::{
+    cancel_task_run, create_task, format_duration, recent_runs, set_task_paused, NewTask,
+};
+use crate::task_templates::{
+    delete_template, list_templates, placeholders, save_template, task_from_template, TaskTemplate,
+    TemplateUse,
+};
+use crate::usage::{
+    format_monthly_usage, monthly_usage, UsageGroup, DEFAULT_USAGE_MONTHS, MAX_USAGE_MONTHS,
+};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// A parsed chat command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatCommand {
+    /// Issue a one-time DM pairing code
+    Pair,
+    /// Add an allowlist entry
+    Allow(AllowTarget),
+    /// Remove an allowlist entry
+    Deny(AllowTarget),
+    /// Show the allowlists for this channel
+    AllowlistShow,
+    /// List, block, or unblock users of this channel
+    Block(BlockCommand),
+    /// Register the current chat under a group folder
+    Register(String),
+    /// Show or replace the trigger words of the current chat
+    Triggers(Vec<String>),
+    /// Show uptime, database pool and running containers
+    Status,
+    /// Show the outbound queue of this channel
+    Queue,
+    /// Show the most recent scheduled task runs
+    Runs(usize),
+    /// Show the most recently active chats and their names
+    Chats(usize),
+    /// Stop the agent running for this chat, or a scheduled task's run
+    Cancel(Option<String>),
+    /// Create, list, pause, resume, or delete this chat's scheduled tasks
+    Task(TaskCommand),
+    /// List, save, or delete task templates
+    Template(TemplateCommand),
+    /// Stop starting agent runs and scheduled tasks for maintenance
+    Pause,
+    /// End maintenance mode
+    Resume,
+    /// Show or replace the log filter of the process
+    LogLevel(Option<String>),
+    /// Show the agent runs, tokens, and cost of recent months, of this
+    /// chat by user or of all chats
+    UsageReport { all_chats: bool, months: u32 },
+    /// Send a message to a group folder, a broadcast list, or `all`
+    Broadcast { target: String, text: String },
+    /// List, grant, or revoke roles
+    Role(RoleCommand),
+    /// Run a message held by the content guard in this chat
+    Approve(i64),
+    /// Drop a message held by the content guard in this chat
+    Reject(i64),
+    /// Recognized command with invalid arguments; carries the usage text
+    Usage(&'static str),
+}
+
+/// Target of an `/allow` or `/deny` command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowTarget {
+    /// The chat the command was sent in
+    CurrentChat,
+    /// An explicit user or group ID
+    Entry(AllowlistKind, String),
+}
+
+/// A `/task` subcommand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskCommand {
+    /// `/task add <cron|interval|once> <schedule> | <prompt>`
+    Add {
+        schedule_type: String,
+        schedule_value: String,
+        prompt: String,
+    },
+    /// `/task from <template> [name=value ...]`
+    From {
+        template: String,
+        params: BTreeMap<String, String>,
+    },
+    List,
+    Pause(String),
+    Resume(String),
+    Delete(String),
+}
+
+/// A `/role` subcommand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoleCommand {
+    List,
+    /// `/role <user> <owner|admin|member|none> [here]`; `None` revokes,
+    /// `here` limits it to this chat's group
+    Set {
+        user_id: String,
+        role: Option<Role>,
+        here: bool,
+    },
+}
+
+/// A `/block` or `/unblock` command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockCommand {
+    List,
+    /// `/block <user> [reason]`
+    Block {
+        user_id: String,
+        reason: Option<String>,
+    },
+    /// `/unblock <user>`
+    Unblock(String),
+}
+
+/// A `/template` subcommand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateCommand {
+    List,
+    /// `/template add <name> [<cron|interval> <schedule>] | <prompt>`
+    Add {
+        name: String,
+        schedule: Option<(String, String)>,
+        prompt: String,
+    },
+    Delete(String),
+}
+
+const ALLOW_USAGE: &str = "Usage: /allow [user|group] <id> (no arguments allows this group)";
+const DENY_USAGE: &str = "Usage: /deny [user|group] <id> (no arguments denies this group)";
+const REGISTER_USAGE: &str = "Usage: /register <folder>";
+const RUNS_USAGE: &str = "Usage: /runs [count]";
+const CHATS_USAGE: &str = "Usage: /chats [count]";
+const CANCEL_USAGE: &str = "Usage: /cancel [task id]";
+const PAUSE_USAGE: &str = "Usage: /pause or /resume (to pause a single task, use /task pause <id>)";
+const TASK_USAGE: &str = "Usage: /task add <cron|interval|once> <schedule> | <prompt>\n\
+    /task from <template> [name=value ...]\n/task list\n/task pause|resume|delete <id>";
+const USAGE_USAGE: &str = "Usage: /usage [all] [months]";
+const BROADCAST_USAGE: &str = "Usage: /broadcast <folder|list|all> <message>";
+const APPROVE_USAGE: &str = "Usage: /approve <id> or /reject <id>";
+const BLOCK_USAGE: &str = "Usage: /block [list]\n/block <user> [reason]\n/unblock <user>";
+const ROLE_USAGE: &str = "Usage: /role [list]\n/role <user> <owner|admin|member|none> [here]";
+const TEMPLATE_USAGE: &str =
+    "Usage: /template add <name> [<cron|interval> <schedule>] | <prompt>\n\
+    /template list\n/template delete <name>";
+
+/// Task runs shown by `/runs` without a count
+const DEFAULT_RUNS_SHOWN: usize = 5;
+/// Upper bound for the `/runs` count
+const MAX_RUNS_SHOWN: usize = 20;
+
+/// Chats shown by `/chats` without a count
+const DEFAULT_CHATS_SHOWN: usize = 10;
+/// Upper bound for the `/chats` count
+const MAX_CHATS_SHOWN: usize = 50;
+
+/// Who sent a command and where
+#[derive(Debug, Clone)]
+pub struct CommandContext<'a> {
+    /// Channel name ("telegram", "whatsapp")
+    pub channel: &'a str,
+    /// Sender ID as seen by the channel
+    pub sender: &'a str,
+    /// Chat the command was sent in
+    pub chat_jid: &'a str,
+    /// Channel-native chat ID, as stored in the group allowlist
+    pub chat_id: &'a str,
+    /// Whether the chat is a private (DM) chat
+    pub is_private: bool,
+    /// The command as sent, for the audit log
+    pub text: &'a str,
+}
+
+/// Parse a chat command from message content
+///
+/// Accepts Telegram-style `/command@botname` suffixes. Unknown commands
+/// return `None` so they flow through as regular messages.
+pub fn parse_command(content: &str) -> Option<ChatCommand> {
+    let content = content.trim();
+    let rest = content.strip_prefix('/')?;
+    let mut parts = rest.split_whitespace();
+    let name = parts.next()?;
+    let name = name.split('@').next().unwrap_or(name).to_lowercase();
+    let args: Vec<&str> = parts.collect();
+
+    match name.as_str() {
+        "pair" => Some(ChatCommand::Pair),
+        "allow" => Some(
+            parse_allow_target(&args)
+                .map(ChatCommand::Allow)
+                .unwrap_or(ChatCommand::Usage(ALLOW_USAGE)),
+        ),
+        "deny" => Some(
+            parse_allow_target(&args)
+                .map(ChatCommand::Deny)
+                .unwrap_or(ChatCommand::Usage(DENY_USAGE)),
+        ),
+        "allowlist" => match args.as_slice() {
+            [] | ["show"] => Some(ChatCommand::AllowlistShow),
+            _ => Some(ChatCommand::Usage("Usage: /allowlist show")),
+        },
+        "triggers" | "trigger" => Some(ChatCommand::Triggers(
+            args.join(" ")
+                .split(',')
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect(),
+        )),
+        "status" => Some(ChatCommand::Status),
+        "pause" => match args.as_slice() {
+            [] => Some(ChatCommand::Pause),
+            _ => Some(ChatCommand::Usage(PAUSE_USAGE)),
+        },
+        "resume" => match args.as_slice() {
+            [] => Some(ChatCommand::Resume),
+            _ => Some(ChatCommand::Usage(PAUSE_USAGE)),
+        },
+        "queue" => Some(ChatCommand::Queue),
+        "loglevel" => Some(ChatCommand::LogLevel(
+            Some(args.join("")).filter(|f| !f.is_empty()),
+        )),
+        "runs" => match args.as_slice() {
+            [] => Some(ChatCommand::Runs(DEFAULT_RUNS_SHOWN)),
+            [count] => Some(
+                count
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .map(|n| ChatCommand::Runs(n.min(MAX_RUNS_SHOWN)))
+                    .unwrap_or(ChatCommand::Usage(RUNS_USAGE)),
+            ),
+            _ => Some(ChatCommand::Usage(RUNS_USAGE)),
+        },
+        "chats" => match args.as_slice() {
+            [] => Some(ChatCommand::Chats(DEFAULT_CHATS_SHOWN)),
+            [count] => Some(
+                count
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .map(|n| ChatCommand::Chats(n.min(MAX_CHATS_SHOWN)))
+                    .unwrap_or(ChatCommand::Usage(CHATS_USAGE)),
+            ),
+            _ => Some(ChatCommand::Usage(CHATS_USAGE)),
+        },
+        "usage" => Some(parse_usage_report(&args).unwrap_or(ChatCommand::Usage(USAGE_USAGE))),
+        "broadcast" => {
+            Some(parse_broadcast(content).unwrap_or(ChatCommand::Usage(BROADCAST_USAGE)))
+        }
+        "block" => Some(ChatCommand::Block(match args.as_slice() {
+            [] | ["list"] => BlockCommand::List,
+            [user_id, reason @ ..] => BlockCommand::Block {
+                user_id: user_id.to_string(),
+                reason: Some(reason.join(" ")).filter(|r| !r.is_empty()),
+            },
+        })),
+        "unblock" => match args.as_slice() {
+            [user_id] => Some(ChatCommand::Block(BlockCommand::Unblock(
+                user_id.to_string(),
+            ))),
+            _ => Some(ChatCommand::Usage(BLOCK_USAGE)),
+        },
+        "role" | "roles" => Some(
+            parse_role_command(&args)
+                .map(ChatCommand::Role)
+                .unwrap_or(ChatCommand::Usage(ROLE_USAGE)),
+        ),
+        "approve" | "reject" => match args.as_slice() {
+            [id] => match id.trim_start_matches('#').parse() {
+                Ok(id) if name == "approve" => Some(ChatCommand::Approve(id)),
+                Ok(id) => Some(ChatCommand::Reject(id)),
+                Err(_) => Some(ChatCommand::Usage(APPROVE_USAGE)),
+            },
+            _ => Some(ChatCommand::Usage(APPROVE_USAGE)),
+        },
+        "cancel" | "stop" => match args.as_slice() {
+            [] => Some(ChatCommand::Cancel(None)),
+            [task_id] => Some(ChatCommand::Cancel(Some(task_id.to_string()))),
+            _ => Some(ChatCommand::Usage(CANCEL_USAGE)),
+        },
+        "task" | "tasks" => Some(
+            parse_task_command(content, &args)
+                .map(ChatCommand::Task)
+                .unwrap_or(ChatCommand::Usage(TASK_USAGE)),
+        ),
+        "template" | "templates" => Some(
+            parse_template_command(content, &args)
+                .map(ChatCommand::Template)
+                .unwrap_or(ChatCommand::Usage(TEMPLATE_USAGE)),
+        ),
+        "register" => match args.as_slice() {
+            [folder] => Some(ChatCommand::Register(folder.to_string())),
+            _ => Some(ChatCommand::Usage(REGISTER_USAGE)),
+        },
+        _ => None,
+    }
+}
+
+/// Parse `/allow` and `/deny` arguments
+fn parse_allow_target(args: &[&str]) -> Option<AllowTarget> {
+    match args {
+        [] => Some(AllowTarget::CurrentChat),
+        [kind, id] => AllowlistKind::parse(kind).map(|k| AllowTarget::Entry(k, id.to_string())),
+        _ => None,
+    }
+}
+
+/// Parse `/usage` arguments
+fn parse_usage_report(args: &[&str]) -> Option<ChatCommand> {
+    let (all_chats, months) = match args {
+        ["all", rest @ ..] => (true, rest),
+        rest => (false, rest),
+    };
+    let months = match months {
+        [] => DEFAULT_USAGE_MONTHS,
+        [months] => months
+            .parse::<u32>()
+            .ok()
+            .filter(|n| *n > 0)?
+            .min(MAX_USAGE_MONTHS),
+        _ => return None,
+    };
+    Some(ChatCommand::UsageReport { all_chats, months })
+}
+
+/// Parse `/broadcast` arguments; the message keeps its line breaks
+fn parse_broadcast(content: &str) -> Option<ChatCommand> {
+    let (_, rest) = content.split_once(char::is_whitespace)?;
+    let (target, text) = rest.trim_start().split_once(char::is_whitespace)?;
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some(ChatCommand::Broadcast {
+        target: target.to_string(),
+        text: text.to_string(),
+    })
+}
+
+/// Parse `/role` arguments
+fn parse_role_command(args: &[&str]) -> Option<RoleCommand> {
+    let (user_id, role, here) = match args {
+        [] | ["list"] => return Some(RoleCommand::List),
+        [user_id, role] => (user_id, role, false),
+        [user_id, role, "here"] => (user_id, role, true),
+        _ => return None,
+    };
+    let role = match role.to_lowercase().as_str() {
+        "none" => None,
+        role => Some(Role::parse(role)?),
+    };
+    Some(RoleCommand::Set {
+        user_id: user_id.to_string(),
+        role,
+        here,
+    })
+}
+
+/// Parse `/task` arguments; the prompt of `add` keeps its line breaks
+fn parse_task_command(content: &str, args: &[&str]) -> Option<TaskCommand> {
+    match args {
+        [] | ["list"] => Some(TaskCommand::List),
+        ["pause", id] => Some(TaskCommand::Pause(id.to_string())),
+        ["resume", id] => Some(TaskCommand::Resume(id.to_string())),
+        ["delete", id] | ["remove", id] => Some(TaskCommand::Delete(id.to_string())),
+        ["from", template, params @ ..] => {
+            let params = params
+                .iter()
+                .map(|p| {
+                    p.split_once('=')
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                })
+                .collect::<Option<BTreeMap<_, _>>>()?;
+            Some(TaskCommand::From {
+                template: template.to_string(),
+                params,
+            })
+        }
+        ["add", schedule_type, ..] => {
+            let (head, prompt) = content.split_once('|')?;
+            let schedule_value = head
+                .split_whitespace()
+                .skip(3)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let prompt = prompt.trim();
+            if schedule_value.is_empty() || prompt.is_empty() {
+                return None;
+            }
+            Some(TaskCommand::Add {
+                schedule_type: schedule_type.to_lowercase(),
+                schedule_value,
+                prompt: prompt.to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Parse `/template` arguments; the prompt of `add` keeps its line breaks
+fn parse_template_command(content: &str, args: &[&str]) -> Option<TemplateCommand> {
+    match args {
+        [] | ["list"] => Some(TemplateCommand::List),
+        ["delete", name] | ["remove", name] => Some(TemplateCommand::Delete(name.to_string())),
+        ["add", name, ..] => {
+            let (head, prompt) = content.split_once('|')?;
+            let schedule: Vec<&str> = head.split_whitespace().skip(3).collect();
+            let schedule = match schedule.as_slice() {
+                [] => None,
+                [schedule_type, value @ ..] if !value.is_empty() => {
+                    Some((schedule_type.to_lowercase(), value.join(" ")))
+                }
+                _ => return None,
+            };
+            let prompt = prompt.trim();
+            if prompt.is_empty() || name.contains('|') {
+                return None;
+            }
+            Some(TemplateCommand::Add {
+                name: name.to_string(),
+                schedule,
+                prompt: prompt.to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+impl ChatCommand {
+    /// Admin function the command uses
+    ///
+    /// `/role` is not among them: only owners manage roles.
+    pub fn permission(&self) -> Permission {
+        match self {
+            ChatCommand::Task(_) | ChatCommand::Usage(TASK_USAGE) => Permission::Tasks,
+            ChatCommand::Allow(_)
+            | ChatCommand::Deny(_)
+            | ChatCommand::Block(_)
+            | ChatCommand::Usage(ALLOW_USAGE)
+            | ChatCommand::Usage(DENY_USAGE)
+            | ChatCommand::Usage(BLOCK_USAGE) => Permission::Allowlist,
+            ChatCommand::Cancel(_) | ChatCommand::Usage(CANCEL_USAGE) => Permission::Cancel,
+            ChatCommand::Broadcast { .. } | ChatCommand::Usage(BROADCAST_USAGE) => {
+                Permission::Broadcast
+            }
+            _ => Permission::Admin,
+        }
+    }
+}
+
+/// Whether the sender of a command may run it in the chat it was sent in
+fn is_command_permitted(
+    db: &Database,
+    ctx: &CommandContext,
+    command: &ChatCommand,
+) -> Result<bool> {
+    if matches!(
+        command,
+        ChatCommand::Role(_) | ChatCommand::Usage(ROLE_USAGE)
+    ) {
+        return Ok(role_of(db, ctx.channel, ctx.sender, None)? == Role::Owner);
+    }
+    let group_folder = load_registered_groups(db.tenant())
+        .remove(ctx.chat_jid)
+        .map(|group| group.folder);
+    is_permitted(
+        db,
+        ctx.channel,
+        ctx.sender,
+        group_folder.as_deref(),
+        command.permission(),
+    )
+}
+
+/// Execute a command and return the reply text
+///
+/// Commands the sender's role does not permit are ignored and yield
+/// `None`. Both are recorded in the audit log.
+pub fn execute_command(
+    db: &Database,
+    ctx: &CommandContext,
+    command: ChatCommand,
+) -> Result<Option<String>> {
+    let actor = chat_actor(ctx.channel, ctx.sender);
+    if !is_command_permitted(db, ctx, &command)? {
+        tracing::debug!(
+            "Ignoring {:?} from {} on {}: not permitted",
+            command,
+            ctx.sender,
+            ctx.channel
+        );
+        audit::record(
+            db,
+            AuditEvent::new(AuditKind::Denied, actor, ctx.text).in_chat(ctx.chat_jid),
+        );
+        return Ok(None);
+    }
+    audit::record(
+        db,
+        AuditEvent::new(AuditKind::Command, actor.as_str(), ctx.text).in_chat(ctx.chat_jid),
+    );
+
+    let reply = match command {
+        ChatCommand::Usage(usage) => usage.to_string(),
+        ChatCommand::Allow(target) => match resolve_target(ctx, target) {
+            Some((kind, id)) => {
+                if allowlist::allow(db, ctx.channel, kind, &id, ctx.sender)? {
+                    audit::record(
+                        db,
+                        AuditEvent::new(
+                            AuditKind::Allowlist,
+                            actor,
+                            format!("Allowed {} {} on {}", kind.as_str(), id, ctx.channel),
+                        )
+                        .in_chat(ctx.chat_jid),
+                    );
+                    format!("Allowed {} {}", kind.as_str(), id)
+                } else {
+                    format!("{} {} is already allowed", kind.as_str(), id)
+                }
+            }
+            None => ALLOW_USAGE.to_string(),
+        },
+        ChatCommand::Deny(target) => match resolve_target(ctx, target) {
+            Some((kind, id)) => {
+                if allowlist::deny(db, ctx.channel, kind, &id)? {
+                    audit::record(
+                        db,
+                        AuditEvent::new(
+                            AuditKind::Allowlist,
+                            actor,
+                            format!("Removed {} {} on {}", kind.as_str(), id, ctx.channel),
+                        )
+                        .in_chat(ctx.chat_jid),
+                    );
+                    format!("Removed {} {} from the allowlist", kind.as_str(), id)
+                } else {
+                    format!("{} {} is not on the allowlist", kind.as_str(), id)
+                }
+            }
+            None => DENY_USAGE.to_string(),
+        },
+        ChatCommand::AllowlistShow => {
+            let users = allowlist::list(db, ctx.channel, AllowlistKind::User)?;
+            let groups = allowlist::list(db, ctx.channel, AllowlistKind::Group)?;
+            format!(
+                "Allowed users: {}\nAllowed groups: {}",
+                format_entries(&users),
+                format_entries(&groups)
+            )
+        }
+        ChatCommand::Register(folder) => match register_group(db.tenant(), ctx.chat_jid, &folder) {
+            Ok(group) => format!(
+                "Registered this chat as '{}'. Mention {} to talk to me.",
+                group.folder, group.trigger
+            ),
+            Err(NuClawError::Validation { message }) => message,
+            Err(e) => return Err(e),
+        },
+        ChatCommand::Triggers(triggers) if triggers.is_empty() => {
+            match load_registered_groups(db.tenant()).get(ctx.chat_jid) {
+                Some(group) => format!("Triggers: {}", group.triggers().join(", ")),
+                None => "This chat is not registered; use /register <folder> first".to_string(),
+            }
+        }
+        ChatCommand::Triggers(triggers) => match set_triggers(db.tenant(), ctx.chat_jid, &triggers)
+        {
+            Ok(group) => format!("Triggers set to: {}", group.triggers().join(", ")),
+            Err(NuClawError::Validation { message }) => message,
+            Err(e) => return Err(e),
+        },
+        ChatCommand::Status => {
+            let pool = db.pool_status();
+            let running = running_containers();
+            let limiter = container_limiter();
+            let mut reply = format!(
+                "Uptime: {}\nDB pool: {} active, {} idle, max {}\nRunning containers: {}/{} ({} queued)",
+                format_uptime(uptime()),
+                pool.connections_active,
+                pool.connections_idle,
+                pool.max_size,
+                running.len(),
+                limiter.max(),
+                limiter.waiting()
+            );
+            for container in running {
+                reply.push_str(&format!(
+                    "\n- {} in {} for {}",
+                    container.group_folder,
+                    db.chats().label(&container.chat_jid),
+                    format_uptime(container.started_at.elapsed())
+                ));
+            }
+            match current_leader(db)? {
+                Some(lease) => reply.push_str(&format!(
+                    "\nScheduler leader: {}{} since {}",
+                    lease.holder,
+                    if lease.holder == instance_id() {
+                        " (this instance)"
+                    } else {
+                        ""
+                    },
+                    lease.acquired_at
+                )),
+                None => reply.push_str("\nScheduler leader: none"),
+            }
+            if let Some(pause) = pause_state(db)? {
+                reply.push_str(&format!(
+                    "\nPaused for maintenance since {} by {}",
+                    pause.since, pause.by
+                ));
+            }
+            reply
+        }
+        ChatCommand::Queue => {
+            let stats = queue_stats(db, ctx.channel)?;
+            let mut reply = format!(
+                "Outbound queue ({}): {} pending, {} failed",
+                ctx.channel, stats.pending, stats.failed
+            );
+            if let Some(oldest) = stats.oldest_pending {
+                reply.push_str(&format!("\nOldest pending since {}", oldest));
+            }
+            reply
+        }
+        ChatCommand::Runs(limit) => {
+            let runs = recent_runs(db, limit)?;
+            if runs.is_empty() {
+                "No task runs yet".to_string()
+            } else {
+                runs.iter()
+                    .map(|run| {
+                        let detail = run.error.as_deref().or(run.result.as_deref()).unwrap_or("");
+                        format!(
+                            "{} {} {} ({}) {}",
+                            run.run_at,
+                            run.task_id,
+                            run.status,
+                            format_duration(run.duration_ms),
+                            truncate_chars(detail, 80)
+                        )
+                        .trim_end()
+                        .to_string()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        ChatCommand::Chats(limit) => {
+            let chats = db.chats().recent(limit)?;
+            if chats.is_empty() {
+                "No chats yet".to_string()
+            } else {
+                chats
+                    .iter()
+                    .map(|chat| {
+                        let name = chat.name.as_deref().unwrap_or("(unnamed)");
+                        match &chat.last_message_time {
+                            Some(time) => format!("{} ({}), last message {}", name, chat.jid, time),
+                            None => format!("{} ({})", name, chat.jid),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        ChatCommand::UsageReport { all_chats, months } => {
+            let rows = if all_chats {
+                monthly_usage(db, UsageGroup::Chat, None, months)?
+            } else {
+                monthly_usage(db, UsageGroup::User, Some(ctx.chat_jid), months)?
+            };
+            format!(
+                "Usage of {} by month (UTC)\n{}",
+                if all_chats { "all chats" } else { "this chat" },
+                format_monthly_usage(&rows)
+            )
+        }
+        ChatCommand::Cancel(None) => match cancel_chat(ctx.chat_jid) {
+            0 => "Nothing is running in this chat".to_string(),
+            1 => "Stopping the running request".to_string(),
+            n => format!("Stopping {} running requests", n),
+        },
+        ChatCommand::Cancel(Some(task_id)) => {
+            if cancel_task_run(&task_id) {
+                format!("Stopping the run of task {}", task_id)
+            } else {
+                format!("Task {} is not running", task_id)
+            }
+        }
+        ChatCommand::Broadcast { target, text } => match broadcast(db, &target, &text) {
+            Ok(report) if report.is_empty() => format!("No chats to send to in '{}'", target),
+            Ok(report) => format!(
+                "Queued for {}",
+                report
+                    .iter()
+                    .map(|(channel, count)| format!("{} {} chat(s)", count, channel))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Err(NuClawError::Validation { message }) => message,
+            Err(e) => return Err(e),
+        },
+        ChatCommand::Role(role) => execute_role_command(db, ctx, &actor, role)?,
+        ChatCommand::Block(block) => execute_block_command(db, ctx, &actor, block)?,
+        ChatCommand::Approve(id) => {
+            if approve_held(db, ctx.chat_jid, id)? {
+                audit::record(
+                    db,
+                    AuditEvent::new(AuditKind::Guard, &actor, format!("approved #{}", id))
+                        .in_chat(ctx.chat_jid),
+                );
+                format!("Approved #{}; running it now", id)
+            } else {
+                format!("No message #{} is held in this chat", id)
+            }
+        }
+        ChatCommand::Reject(id) => {
+            if reject_held(db, ctx.chat_jid, id)? {
+                audit::record(
+                    db,
+                    AuditEvent::new(AuditKind::Guard, &actor, format!("rejected #{}", id))
+                        .in_chat(ctx.chat_jid),
+                );
+                format!("Rejected #{}", id)
+            } else {
+                format!("No message #{} is held in this chat", id)
+            }
+        }
+        ChatCommand::Task(task) => execute_task_command(db, ctx, task)?,
+        ChatCommand::Template(template) => execute_template_command(db, template)?,
+        ChatCommand::Pause => {
+            if maintenance::pause(db, &format!("{}:{}", ctx.channel, ctx.sender))? {
+                "Paused: no new agent runs or scheduled tasks will start. Runs in progress will finish. Use /resume to continue.".to_string()
+            } else {
+                "Already paused".to_string()
+            }
+        }
+        ChatCommand::Resume => {
+            if maintenance::resume(db)? {
+                "Resumed".to_string()
+            } else {
+                "Not paused".to_string()
+            }
+        }
+        ChatCommand::LogLevel(None) => format!("Log filter: {}", log_filter()),
+        ChatCommand::LogLevel(Some(directives)) => match set_log_filter(&directives) {
+            Ok(previous) => format!("Log filter changed from {} to {}", previous, directives),
+            Err(NuClawError::Validation { message }) => message,
+            Err(e) => return Err(e),
+        },
+        ChatCommand::Pair => {
+            if !ctx.is_private {
+                "Use /pair in a private chat so the code isn't visible to the group.".to_string()
+            } else {
+                let code = create_pairing_code(db, &actor)?;
+                format!(
+                    "Pairing code: {}\nValid for {} minutes. Send it to the bot in a private chat to pair.",
+                    code,
+                    pairing_code_ttl().num_minutes()
+                )
+            }
+        }
+    };
+
+    Ok(Some(reply))
+}
+
+fn execute_task_command(
+    db: &Database,
+    ctx: &CommandContext,
+    command: TaskCommand,
+) -> Result<String> {
+    let reply = match command {
+        TaskCommand::Add {
+            schedule_type,
+            schedule_value,
+            prompt,
+        } => {
+            let Some(group) = load_registered_groups(db.tenant()).remove(ctx.chat_jid) else {
+                return Ok("This chat is not registered; use /register <folder> first".to_string());
+            };
+            let new_task = NewTask {
+                group_folder: group.folder,
+                chat_jid: ctx.chat_jid.to_string(),
+                prompt,
+                schedule_type,
+                schedule_value,
+                max_retries: None,
+                jitter_secs: None,
+                timeout_secs: None,
+                notify: None,
+                lock: None,
+                overlap: None,
+                priority: 0,
+            };
+            match create_task(db, new_task) {
+                Ok(task) => format!(
+                    "Created task {}, next run {}",
+                    task.id,
+                    task.next_run.as_deref().unwrap_or("-")
+                ),
+                Err(NuClawError::Validation { message }) => message,
+                Err(e) => return Err(e),
+            }
+        }
+        TaskCommand::From { template, params } => {
+            let Some(group) = load_registered_groups(db.tenant()).remove(ctx.chat_jid) else {
+                return Ok("This chat is not registered; use /register <folder> first".to_string());
+            };
+            let with = TemplateUse {
+                group_folder: Some(group.folder),
+                chat_jid: Some(ctx.chat_jid.to_string()),
+                params,
+                ..Default::default()
+            };
+            match task_from_template(db, &template, with).and_then(|t| create_task(db, t)) {
+                Ok(task) => format!(
+                    "Created task {} from '{}', next run {}",
+                    task.id,
+                    template,
+                    task.next_run.as_deref().unwrap_or("-")
+                ),
+                Err(NuClawError::Validation { message }) => message,
+                Err(e) => return Err(e),
+            }
+        }
+        TaskCommand::List => {
+            let tasks = db.tasks().list(Some(ctx.chat_jid))?;
+            if tasks.is_empty() {
+                "No scheduled tasks in this chat".to_string()
+            } else {
+                tasks
+                    .iter()
+                    .map(|task| {
+                        format!(
+                            "{} [{}] {} {} (next {}) {}",
+                            task.id,
+                            task.status,
+                            task.schedule_type,
+                            task.schedule_value,
+                            task.next_run.as_deref().unwrap_or("-"),
+                            truncate_chars(&task.prompt, 60)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        TaskCommand::Pause(id) | TaskCommand::Resume(id) | TaskCommand::Delete(id)
+            if !db
+                .tasks()
+                .list(Some(ctx.chat_jid))?
+                .iter()
+                .any(|t| t.id == id) =>
+        {
+            format!("No task {} in this chat", id)
+        }
+        TaskCommand::Pause(id) => {
+            set_task_paused(db, &id, true)?;
+            format!("Paused task {}", id)
+        }
+        TaskCommand::Resume(id) => {
+            set_task_paused(db, &id, false)?;
+            format!("Resumed task {}", id)
+        }
+        TaskCommand::Delete(id) => {
+            db.tasks().delete(&id)?;
+            format!("Deleted task {}", id)
+        }
+    };
+    Ok(reply)
+}
+
+fn execute_role_command(
+    db: &Database,
+    ctx: &CommandContext,
+    actor: &str,
+    command: RoleCommand,
+) -> Result<String> {
+    let (user_id, role, here) = match command {
+        RoleCommand::List => return Ok(format_roles(&list_roles(db)?)),
+        RoleCommand::Set {
+            user_id,
+            role,
+            here,
+        } => (user_id, role, here),
+    };
+    let group_folder = if here {
+        match load_registered_groups(db.tenant()).remove(ctx.chat_jid) {
+            Some(group) => Some(group.folder),
+            None => {
+                return Ok("This chat is not registered; use /register <folder> first".to_string())
+            }
+        }
+    } else {
+        None
+    };
+    let scope = group_folder
+        .as_deref()
+        .map(|folder| format!(" in {}", folder))
+        .unwrap_or_default();
+    let user = chat_actor(ctx.channel, &user_id);
+    let detail = match role {
+        Some(role) => {
+            set_role(
+                db,
+                ctx.channel,
+                &user_id,
+                group_folder.as_deref(),
+                role,
+                actor,
+            )?;
+            format!("Made {} {}{}", user, role.as_str(), scope)
+        }
+        None => {
+            if !remove_role(db, ctx.channel, &user_id, group_folder.as_deref())? {
+                return Ok(format!("{} has no role{}", user, scope));
+            }
+            format!("Revoked the role of {}{}", user, scope)
+        }
+    };
+    audit::record(
+        db,
+        AuditEvent::new(AuditKind::Role, actor, detail.as_str()).in_chat(ctx.chat_jid),
+    );
+    Ok(detail)
+}
+
+fn execute_block_command(
+    db: &Database,
+    ctx: &CommandContext,
+    actor: &str,
+    command: BlockCommand,
+) -> Result<String> {
+    let detail = match command {
+        BlockCommand::List => {
+            let blocked = blocklist::list(db, ctx.channel)?;
+            if blocked.is_empty() {
+                return Ok(format!("No users are blocked on {}", ctx.channel));
+            }
+            let mut reply = format!("Blocked users on {}:", ctx.channel);
+            for user in blocked {
+                reply.push_str(&format!("\n- {}", user.user_id));
+                if let Some(reason) = user.reason {
+                    reply.push_str(&format!(": {}", reason));
+                }
+                reply.push_str(&format!(" (by {})", user.blocked_by));
+            }
+            return Ok(reply);
+        }
+        BlockCommand::Block { user_id, reason } => {
+            let user = chat_actor(ctx.channel, &user_id);
+            // Owners cannot lock themselves or each other out
+            if user_id == ctx.sender || role_of(db, ctx.channel, &user_id, None)? == Role::Owner {
+                return Ok(format!("{} cannot be blocked", user));
+            }
+            if !blocklist::block(db, ctx.channel, &user_id, reason.as_deref(), actor)? {
+                return Ok(format!("{} is already blocked", user));
+            }
+            format!("Blocked {}; their messages are ignored", user)
+        }
+        BlockCommand::Unblock(user_id) => {
+            let user = chat_actor(ctx.channel, &user_id);
+            if !blocklist::unblock(db, ctx.channel, &user_id)? {
+                return Ok(format!("{} is not blocked", user));
+            }
+            format!("Unblocked {}", user)
+        }
+    };
+    audit::record(
+        db,
+        AuditEvent::new(AuditKind::Allowlist, actor, detail.as_str()).in_chat(ctx.chat_jid),
+    );
+    Ok(detail)
+}
+
+fn execute_template_command(db: &Database, command: TemplateCommand) -> Result<String> {
+    let reply = match command {
+        TemplateCommand::List => {
+            let templates = list_templates(db)?;
+            if templates.is_empty() {
+                "No task templates".to_string()
+            } else {
+                templates
+                    .iter()
+                    .map(|t| {
+                        let mut parts = vec![t.name.clone()];
+                        if let (Some(schedule_type), Some(value)) =
+                            (&t.schedule_type, &t.schedule_value)
+                        {
+                            parts.push(format!("[{} {}]", schedule_type, value));
+                        }
+                        parts.extend(
+                            placeholders(&t.prompt)
+                                .into_iter()
+                                .filter(|p| p != "group")
+                                .map(|p| format!("{}=", p)),
+                        );
+                        parts.push(format!("- {}", truncate_chars(&t.prompt, 60)));
+                        parts.join(" ")
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        TemplateCommand::Add {
+            name,
+            schedule,
+            prompt,
+        } => {
+            let (schedule_type, schedule_value) = schedule.unzip();
+            let template = TaskTemplate {
+                name,
+                prompt,
+                schedule_type,
+                schedule_value,
+                group_folder: None,
+                created_at: String::new(),
+            };
+            match save_template(db, template) {
+                Ok(saved) => format!("Saved template '{}'", saved.name),
+                Err(NuClawError::Validation { message }) => message,
+                Err(e) => return Err(e),
+            }
+        }
+        TemplateCommand::Delete(name) => {
+            if delete_template(db, &name)? {
+                format!("Deleted template '{}'", name)
+            } else {
+                format!("No template '{}'", name)
+            }
+        }
+    };
+    Ok(reply)
+}
+
+/// Resolve an allowlist target; the current chat only makes sense in groups
+fn resolve_target(ctx: &CommandContext, target: AllowTarget) -> Option<(AllowlistKind, String)> {
+    match target {
+        AllowTarget::CurrentChat if ctx.is_private => None,
+        AllowTarget::CurrentChat => Some((AllowlistKind::Group, ctx.chat_id.to_string())),
+        AllowTarget::Entry(kind, id) => Some((kind, id)),
+    }
+}
+
+/// Format a duration as e.g. `2d 3h 4m` or `45s`
+fn format_uptime(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, minutes) = (secs / 86_400, secs % 86_400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Cut text to `max` characters, marking the cut with an ellipsis
+fn truncate_chars(s: &str, max: usize) -> String {
+    let first_line = s.lines().next().unwrap_or("");
+    if first_line.chars().count() <= max && first_line.len() == s.len() {
+        s.to_string()
+    } else {
+        format!("{}…", first_line.chars().take(max).collect::<String>())
+    }
+}
+
+fn format_entries(entries: &[String]) -> String {
+    if entries.is_empty() {
+        "(none)".to_string()
+    } else {
+        entries.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("/pair"), Some(ChatCommand::Pair));
+        assert_eq!(parse_command("  /PAIR  "), Some(ChatCommand::Pair));
+        assert_eq!(parse_command("/pair@nuclaw_bot"), Some(ChatCommand::Pair));
+        assert_eq!(parse_command("/unknown"), None);
+        assert_eq!(parse_command("pair"), None);
+        assert_eq!(parse_command("@Andy /pair"), None);
+        assert_eq!(parse_command("/"), None);
+    }
+
+    #[test]
+    fn test_parse_allowlist_commands() {
+        assert_eq!(
+            parse_command("/allow"),
+            Some(ChatCommand::Allow(AllowTarget::CurrentChat))
+        );
+        assert_eq!(
+            parse_command("/allow user 42"),
+            Some(ChatCommand::Allow(AllowTarget::Entry(
+                AllowlistKind::User,
+                "42".to_string()
+            )))
+        );
+        assert_eq!(
+            parse_command("/deny group -100123"),
+            Some(ChatCommand::Deny(AllowTarget::Entry(
+                AllowlistKind::Group,
+                "-100123".to_string()
+            )))
+        );
+        assert_eq!(
+            parse_command("/allow 42"),
+            Some(ChatCommand::Usage(ALLOW_USAGE))
+        );
+        assert_eq!(
+            parse_command("/deny chat 42"),
+            Some(ChatCommand::Usage(DENY_USAGE))
+        );
+        assert_eq!(
+            parse_command("/allowlist show"),
+            Some(ChatCommand::AllowlistShow)
+        );
+        assert_eq!(
+            parse_command("/allowlist"),
+            Some(ChatCommand::AllowlistShow)
+        );
+        assert_eq!(
+            parse_command("/block 42 posts spam"),
+            Some(ChatCommand::Block(BlockCommand::Block {
+                user_id: "42".to_string(),
+                reason: Some("posts spam".to_string()),
+            }))
+        );
+        assert_eq!(
+            parse_command("/block"),
+            Some(ChatCommand::Block(BlockCommand::List))
+        );
+        assert_eq!(
+            parse_command("/unblock 42"),
+            Some(ChatCommand::Block(BlockCommand::Unblock("42".to_string())))
+        );
+        assert_eq!(
+            parse_command("/unblock"),
+            Some(ChatCommand::Usage(BLOCK_USAGE))
+        );
+        assert_eq!(
+            parse_command("/block 42").unwrap().permission(),
+            Permission::Allowlist
+        );
+    }
+
+    #[test]
+    fn test_parse_register_command() {
+        assert_eq!(
+            parse_command("/register family"),
+            Some(ChatCommand::Register("family".to_string()))
+        );
+        assert_eq!(
+            parse_command("/register"),
+            Some(ChatCommand::Usage(REGISTER_USAGE))
+        );
+        assert_eq!(
+            parse_command("/register a b"),
+            Some(ChatCommand::Usage(REGISTER_USAGE))
+        );
+    }
+
+    #[test]
+    fn test_parse_triggers_command() {
+        assert_eq!(
+            parse_command("/triggers"),
+            Some(ChatCommand::Triggers(vec![]))
+        );
+        assert_eq!(
+            parse_command("/triggers @Andy, hey bot ,"),
+            Some(ChatCommand::Triggers(vec![
+                "@Andy".to_string(),
+                "hey bot".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_status_commands() {
+        assert_eq!(parse_command("/status"), Some(ChatCommand::Status));
+        assert_eq!(parse_command("/queue"), Some(ChatCommand::Queue));
+        assert_eq!(
+            parse_command("/runs"),
+            Some(ChatCommand::Runs(DEFAULT_RUNS_SHOWN))
+        );
+        assert_eq!(parse_command("/runs 3"), Some(ChatCommand::Runs(3)));
+        assert_eq!(
+            parse_command("/runs 1000"),
+            Some(ChatCommand::Runs(MAX_RUNS_SHOWN))
+        );
+        assert_eq!(
+            parse_command("/runs 0"),
+            Some(ChatCommand::Usage(RUNS_USAGE))
+        );
+        assert_eq!(
+            parse_command("/runs x"),
+            Some(ChatCommand::Usage(RUNS_USAGE))
+        );
+        assert_eq!(
+            parse_command("/chats"),
+            Some(ChatCommand::Chats(DEFAULT_CHATS_SHOWN))
+        );
+        assert_eq!(
+            parse_command("/chats 500"),
+            Some(ChatCommand::Chats(MAX_CHATS_SHOWN))
+        );
+        assert_eq!(
+            parse_command("/chats all"),
+            Some(ChatCommand::Usage(CHATS_USAGE))
+        );
+        assert_eq!(
+            parse_command("/usage"),
+            Some(ChatCommand::UsageReport {
+                all_chats: false,
+                months: DEFAULT_USAGE_MONTHS
+            })
+        );
+        assert_eq!(
+            parse_command("/usage all 100"),
+            Some(ChatCommand::UsageReport {
+                all_chats: true,
+                months: MAX_USAGE_MONTHS
+            })
+        );
+        assert_eq!(
+            parse_command("/usage 0"),
+            Some(ChatCommand::Usage(USAGE_USAGE))
+        );
+    }
+
+    #[test]
+    fn test_parse_pause_commands() {
+        assert_eq!(parse_command("/pause"), Some(ChatCommand::Pause));
+        assert_eq!(parse_command("/resume"), Some(ChatCommand::Resume));
+        assert_eq!(
+            parse_command("/pause task-42"),
+            Some(ChatCommand::Usage(PAUSE_USAGE))
+        );
+    }
+
+    #[test]
+    fn test_parse_loglevel_command() {
+        assert_eq!(
+            parse_command("/loglevel"),
+            Some(ChatCommand::LogLevel(None))
+        );
+        assert_eq!(
+            parse_command("/loglevel info, nuclaw::telegram=debug"),
+            Some(ChatCommand::LogLevel(Some(
+                "info,nuclaw::telegram=debug".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn test_parse_cancel_command() {
+        assert_eq!(parse_command("/cancel"), Some(ChatCommand::Cancel(None)));
+        assert_eq!(
+            parse_command("/approve #12"),
+            Some(ChatCommand::Approve(12))
+        );
+        assert_eq!(parse_command("/reject 12"), Some(ChatCommand::Reject(12)));
+        assert_eq!(
+            parse_command("/approve all"),
+            Some(ChatCommand::Usage(APPROVE_USAGE))
+        );
+        assert_eq!(
+            parse_command("/cancel task-42"),
+            Some(ChatCommand::Cancel(Some("task-42".to_string())))
+        );
+        assert_eq!(
+            parse_command("/cancel a b"),
+            Some(ChatCommand::Usage(CANCEL_USAGE))
+        );
+    }
+
+    #[test]
+    fn test_parse_role_and_broadcast_commands() {
+        assert_eq!(
+            parse_command("/roles"),
+            Some(ChatCommand::Role(RoleCommand::List))
+        );
+        assert_eq!(
+            parse_command("/role 42 Admin here"),
+            Some(ChatCommand::Role(RoleCommand::Set {
+                user_id: "42".to_string(),
+                role: Some(Role::Admin),
+                here: true,
+            }))
+        );
+        assert_eq!(
+            parse_command("/role 42 none"),
+            Some(ChatCommand::Role(RoleCommand::Set {
+                user_id: "42".to_string(),
+                role: None,
+                here: false,
+            }))
+        );
+        assert_eq!(
+            parse_command("/role 42 root"),
+            Some(ChatCommand::Usage(ROLE_USAGE))
+        );
+
+        assert_eq!(
+            parse_command("/broadcast family  Dinner at 7\nBring dessert"),
+            Some(ChatCommand::Broadcast {
+                target: "family".to_string(),
+                text: "Dinner at 7\nBring dessert".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_command("/broadcast all"),
+            Some(ChatCommand::Usage(BROADCAST_USAGE))
+        );
+        assert_eq!(
+            parse_command("/broadcast all hi").unwrap().permission(),
+            Permission::Broadcast
+        );
+        assert_eq!(
+            parse_command("/task").unwrap().permission(),
+            Permission::Tasks
+        );
+        assert_eq!(
+            parse_command("/pause").unwrap().permission(),
+            Permission::Admin
+        );
+    }
+
+    #[test]
+    fn test_parse_task_command() {
+        assert_eq!(
+            parse_command("/task"),
+            Some(ChatCommand::Task(TaskCommand::List))
+        );
+        assert_eq!(
+            parse_command("/task pause ab12cd34"),
+            Some(ChatCommand::Task(TaskCommand::Pause(
+                "ab12cd34".to_string()
+            )))
+        );
+        assert_eq!(
+            parse_command("/task add cron 0 0 9 * * * | Summarize the news\nin three bullets"),
+            Some(ChatCommand::Task(TaskCommand::Add {
+                schedule_type: "cron".to_string(),
+                schedule_value: "0 0 9 * * *".to_string(),
+                prompt: "Summarize the news\nin three bullets".to_string(),
+            }))
+        );
+        assert_eq!(
+            parse_command("/task add interval 2h"),
+            Some(ChatCommand::Usage(TASK_USAGE))
+        );
+        assert_eq!(
+            parse_command("/task delete"),
+            Some(ChatCommand::Usage(TASK_USAGE))
+        );
+    }
+
+    #[test]
+    fn test_parse_template_commands() {
+        assert_eq!(
+            parse_command("/task from standup team=backend"),
+            Some(ChatCommand::Task(TaskCommand::From {
+                template: "standup".to_string(),
+                params: BTreeMap::from([("team".to_string(), "backend".to_string())]),
+            }))
+        );
+        assert_eq!(
+            parse_command("/task from standup backend"),
+            Some(ChatCommand::Usage(TASK_USAGE))
+        );
+        assert_eq!(
+            parse_command("/template add standup cron 0 0 9 * * MON-FRI | Summarize {team}"),
+            Some(ChatCommand::Template(TemplateCommand::Add {
+                name: "standup".to_string(),
+                schedule: Some(("cron".to_string(), "0 0 9 * * MON-FRI".to_string())),
+                prompt: "Summarize {team}".to_string(),
+            }))
+        );
+        assert_eq!(
+            parse_command("/template add digest | Summarize the news"),
+            Some(ChatCommand::Template(TemplateCommand::Add {
+                name: "digest".to_string(),
+                schedule: None,
+                prompt: "Summarize the news".to_string(),
+            }))
+        );
+        assert_eq!(
+            parse_command("/template add digest interval | Summarize"),
+            Some(ChatCommand::Usage(TEMPLATE_USAGE))
+        );
+        assert_eq!(
+            parse_command("/templates"),
+            Some(ChatCommand::Template(TemplateCommand::List))
+        );
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(42)), "42s");
+        assert_eq!(format_uptime(Duration::from_secs(125)), "2m");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 3600 + 60)), "3h 1m");
+        assert_eq!(
+            format_uptime(Duration::from_secs(2 * 86_400 + 3 * 3600 + 4 * 60)),
+            "2d 3h 4m"
+        );
+    }
+
+    #[test]
+    fn test_truncate_chars() {
+        assert_eq!(truncate_chars("short", 10), "short");
+        assert_eq!(truncate_chars("ünïcödé text", 6), "ünïcöd…");
+        assert_eq!(truncate_chars("line one\nline two", 80), "line one…");
+    }
+
+    #[test]
+    fn test_resolve_target() {
+        let group_ctx = CommandContext {
+            channel: "telegram",
+            sender: "1",
+            chat_jid: "telegram:group:-100",
+            chat_id: "-100",
+            is_private: false,
+            text: "/allow",
+        };
+        assert_eq!(
+            resolve_target(&group_ctx, AllowTarget::CurrentChat),
+            Some((AllowlistKind::Group, "-100".to_string()))
+        );
+
+        let dm_ctx = CommandContext {
+            is_private: true,
+            ..group_ctx.clone()
+        };
+        assert_eq!(resolve_target(&dm_ctx, AllowTarget::CurrentChat), None);
+        assert_eq!(
+            resolve_target(
+                &dm_ctx,
+                AllowTarget::Entry(AllowlistKind::User, "7".to_string())
+            ),
+            Some((AllowlistKind::User, "7".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_execute_command_requires_admin() {
+        let (db, _dir) = test_database();
+        let ctx = CommandContext {
+            channel: "telegram",
+            sender: "not-an-admin-sender",
+            chat_jid: "telegram:group:1",
+            chat_id: "1",
+            is_private: true,
+            text: "/pair",
+        };
+        assert_eq!(execute_command(&db, &ctx, ChatCommand::Pair).unwrap(), None);
+
+        let denied = audit::query(&db, &Default::default(), 10).unwrap();
+        assert_eq!(denied.len(), 1);
+        assert_eq!(denied[0].kind, "denied");
+        assert_eq!(denied[0].actor, "telegram:not-an-admin-sender");
+        assert_eq!(denied[0].detail, "/pair");
+    }
+
+    #[test]
+    fn test_execute_command_checks_roles() {
+        let (db, _dir) = test_database();
+        let ctx = CommandContext {
+            channel: "telegram",
+            sender: "role-test-admin",
+            chat_jid: "telegram:group:1",
+            chat_id: "1",
+            is_private: false,
+            text: "/chats",
+        };
+        assert_eq!(
+            execute_command(&db, &ctx, ChatCommand::Chats(5)).unwrap(),
+            None
+        );
+
+        set_role(&db, "telegram", "role-test-admin", None, Role::Admin, "cli").unwrap();
+        assert!(execute_command(&db, &ctx, ChatCommand::Chats(5))
+            .unwrap()
+            .is_some());
+        // Only owners manage roles
+        let grant = ChatCommand::Role(RoleCommand::Set {
+            user_id: "role-test-member".to_string(),
+            role: Some(Role::Admin),
+            here: false,
+        });
+        assert_eq!(execute_command(&db, &ctx, grant.clone()).unwrap(), None);
+
+        set_role(&db, "telegram", "role-test-admin", None, Role::Owner, "cli").unwrap();
+        assert_eq!(
+            execute_command(&db, &ctx, grant).unwrap().as_deref(),
+            Some("Made telegram:role-test-member admin")
+        );
+        assert_eq!(
+            role_of(&db, "telegram", "role-test-member", None).unwrap(),
+            Role::Admin
+        );
+        let filter = audit::AuditFilter {
+            kind: Some("role".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(audit::query(&db, &filter, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_execute_block_command() {
+        let (db, _dir) = test_database();
+        set_role(
+            &db,
+            "telegram",
+            "block-test-admin",
+            None,
+            Role::Admin,
+            "cli",
+        )
+        .unwrap();
+        let ctx = CommandContext {
+            channel: "telegram",
+            sender: "block-test-admin",
+            chat_jid: "telegram:group:1",
+            chat_id: "1",
+            is_private: false,
+            text: "/block",
+        };
+        let run = |command| {
+            execute_command(&db, &ctx, ChatCommand::Block(command))
+                .unwrap()
+                .unwrap()
+        };
+        let block = |user_id: &str| BlockCommand::Block {
+            user_id: user_id.to_string(),
+            reason: Some("spam".to_string()),
+        };
+
+        assert_eq!(
+            run(block("block-test-member")),
+            "Blocked telegram:block-test-member; their messages are ignored"
+        );
+        assert!(blocklist::is_blocked(&db, "telegram", "block-test-member").unwrap());
+        assert_eq!(
+            run(block("block-test-member")),
+            "telegram:block-test-member is already blocked"
+        );
+        assert_eq!(
+            run(block("block-test-admin")),
+            "telegram:block-test-admin cannot be blocked"
+        );
+        assert_eq!(
+            run(BlockCommand::List),
+            "Blocked users on telegram:\n- block-test-member: spam (by telegram:block-test-admin)"
+        );
+        assert_eq!(
+            run(BlockCommand::Unblock("block-test-member".to_string())),
+            "Unblocked telegram:block-test-member"
+        );
+        assert_eq!(run(BlockCommand::List), "No users are blocked on telegram");
+    }
+}
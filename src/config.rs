@@ -1,33 +1,167 @@
 //! Configuration for NuClaw
+//!
+//! NuClaw keeps its files in the directory named by `NUCLAW_HOME`, as
+//! `store/`, `groups/`, `data/`, and `nuclaw.toml`. Without it they follow
+//! the XDG base directories: `store/` and `groups/` in
+//! `$XDG_DATA_HOME/nuclaw` (`~/.local/share/nuclaw`), `data/` as
+//! `$XDG_STATE_HOME/nuclaw` (`~/.local/state/nuclaw`), and `nuclaw.toml`
+//! in `$XDG_CONFIG_HOME/nuclaw` (`~/.config/nuclaw`). Either way the
+//! paths do not depend on the working directory.
+//!
+//! Earlier versions kept the tree in the working directory;
+//! `legacy_layout` finds such a tree and `migrate_legacy_layout` moves it.
 
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
-pub fn project_root() -> PathBuf {
-    env::current_dir().expect("Failed to get current directory")
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+
+/// Record the process start time; later calls have no effect
+pub fn mark_started() {
+    STARTED_AT.get_or_init(Instant::now);
+}
+
+/// Time since `mark_started` was first called
+pub fn uptime() -> Duration {
+    STARTED_AT.get_or_init(Instant::now).elapsed()
+}
+
+/// Directory of all NuClaw files (`NUCLAW_HOME`), if set
+pub fn nuclaw_home() -> Option<PathBuf> {
+    if cfg!(test) {
+        // Unit tests keep their files in the working directory
+        return Some(env::current_dir().expect("Failed to get current directory"));
+    }
+    env::var_os("NUCLAW_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
+
+/// NuClaw's directory under an XDG base directory: `$<var>/nuclaw`, or
+/// `~/<default>/nuclaw` when the variable is unset or not absolute
+fn xdg_dir(var: &str, default: &str) -> PathBuf {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| home_dir().join(default))
+        .join("nuclaw")
+}
+
+fn home_dir() -> PathBuf {
+    home::home_dir().unwrap_or_else(|| PathBuf::from("/Users/user"))
+}
+
+/// Directory of the config file
+pub fn config_dir() -> PathBuf {
+    nuclaw_home().unwrap_or_else(|| xdg_dir("XDG_CONFIG_HOME", ".config"))
 }
 
 pub fn store_dir() -> PathBuf {
-    project_root().join("store")
+    nuclaw_home()
+        .unwrap_or_else(|| xdg_dir("XDG_DATA_HOME", ".local/share"))
+        .join("store")
 }
 
 pub fn groups_dir() -> PathBuf {
-    project_root().join("groups")
+    nuclaw_home()
+        .unwrap_or_else(|| xdg_dir("XDG_DATA_HOME", ".local/share"))
+        .join("groups")
 }
 
 pub fn data_dir() -> PathBuf {
-    project_root().join("data")
+    match nuclaw_home() {
+        Some(home) => home.join("data"),
+        None => xdg_dir("XDG_STATE_HOME", ".local/state"),
+    }
 }
 
 pub fn logs_dir() -> PathBuf {
     groups_dir().join("logs")
 }
 
+/// Mount allowlist, outside of everything mounted into containers: always
+/// in the XDG config directory, even with `NUCLAW_HOME`
 pub fn mount_allowlist_path() -> PathBuf {
-    let home = home::home_dir().unwrap_or_else(|| PathBuf::from("/Users/user"));
-    home.join(".config")
-        .join("nuclaw")
-        .join("mount-allowlist.json")
+    xdg_dir("XDG_CONFIG_HOME", ".config").join("mount-allowlist.json")
+}
+
+/// A tree of an earlier version in the working directory, which is no
+/// longer used unless `NUCLAW_HOME` points to it
+pub fn legacy_layout() -> Option<PathBuf> {
+    let dir = env::current_dir().ok()?;
+    let is_legacy = dir.join("store").join("nuclaw.db").is_file()
+        && dir.join("store") != store_dir()
+        && nuclaw_home().is_none();
+    is_legacy.then_some(dir)
+}
+
+/// Move a tree of an earlier version from `from` to the current
+/// locations; returns the moves made
+///
+/// Refuses to replace anything that already exists at a new location.
+pub fn migrate_legacy_layout(from: &Path) -> std::io::Result<Vec<(PathBuf, PathBuf)>> {
+    let moves: Vec<(PathBuf, PathBuf)> = [
+        (from.join("store"), store_dir()),
+        (from.join("groups"), groups_dir()),
+        (from.join("data"), data_dir()),
+        (from.join("nuclaw.toml"), config_dir().join("nuclaw.toml")),
+    ]
+    .into_iter()
+    .filter(|(old, new)| old.exists() && old != new)
+    .collect();
+    move_paths(&moves)?;
+    Ok(moves)
+}
+
+fn move_paths(moves: &[(PathBuf, PathBuf)]) -> std::io::Result<()> {
+    if let Some((_, new)) = moves.iter().find(|(_, new)| is_in_use(new)) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", new.display()),
+        ));
+    }
+
+    for (old, new) in moves {
+        if let Some(parent) = new.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // An empty directory left by an earlier start is replaced
+        if new.is_dir() {
+            std::fs::remove_dir(new)?;
+        }
+        if std::fs::rename(old, new).is_err() {
+            // Across filesystems, copy and then remove
+            copy_recursively(old, new)?;
+            if old.is_dir() {
+                std::fs::remove_dir_all(old)?;
+            } else {
+                std::fs::remove_file(old)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether a path holds anything: a file, or a directory with entries
+fn is_in_use(path: &Path) -> bool {
+    match std::fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_some(),
+        Err(_) => path.exists(),
+    }
+}
+
+fn copy_recursively(from: &Path, to: &Path) -> std::io::Result<()> {
+    if !from.is_dir() {
+        return std::fs::copy(from, to).map(|_| ());
+    }
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
 }
 
 pub fn assistant_name() -> String {
@@ -46,6 +180,43 @@ pub fn claude_model() -> Option<String> {
     env::var("CLAUDE_MODEL").ok()
 }
 
+/// Sender IDs allowed to run admin chat commands (`ADMIN_USERS`, comma-separated)
+pub fn admin_users() -> Vec<String> {
+    env::var("ADMIN_USERS")
+        .ok()
+        .map(|s| {
+            s.split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reaction set on a triggered message once it is accepted (`ACK_REACTION`)
+pub fn ack_reaction() -> Option<String> {
+    reaction_from_env("ACK_REACTION", "👀")
+}
+
+/// Reaction replacing the ack after a successful reply (`DONE_REACTION`)
+pub fn done_reaction() -> Option<String> {
+    reaction_from_env("DONE_REACTION", "👍")
+}
+
+/// Reaction replacing the ack after a failed run (`ERROR_REACTION`)
+pub fn error_reaction() -> Option<String> {
+    reaction_from_env("ERROR_REACTION", "👎")
+}
+
+/// Read a reaction emoji; an empty value disables the reaction
+fn reaction_from_env(key: &str, default: &str) -> Option<String> {
+    match env::var(key) {
+        Ok(v) if v.trim().is_empty() => None,
+        Ok(v) => Some(v.trim().to_string()),
+        Err(_) => Some(default.to_string()),
+    }
+}
+
 pub fn timezone() -> String {
     env::var("TZ").unwrap_or_else(|_| "UTC".to_string())
 }
@@ -69,6 +240,52 @@ pub fn ensure_directories() -> std::io::Result<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_xdg_dir() {
+        std::env::set_var("NUCLAW_TEST_XDG_HOME", "/srv/xdg");
+        assert_eq!(
+            xdg_dir("NUCLAW_TEST_XDG_HOME", ".local/share"),
+            PathBuf::from("/srv/xdg/nuclaw")
+        );
+        // Relative paths are ignored, as the spec requires
+        std::env::set_var("NUCLAW_TEST_XDG_HOME", "xdg");
+        assert_eq!(
+            xdg_dir("NUCLAW_TEST_XDG_HOME", ".local/share"),
+            home_dir().join(".local/share/nuclaw")
+        );
+        std::env::remove_var("NUCLAW_TEST_XDG_HOME");
+    }
+
+    #[test]
+    fn test_move_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old");
+        std::fs::create_dir_all(old.join("store")).unwrap();
+        std::fs::write(old.join("store/nuclaw.db"), "db").unwrap();
+        std::fs::write(old.join("nuclaw.toml"), "tz = 'UTC'").unwrap();
+        let new = dir.path().join("new");
+        // Left empty by a start before migrating
+        std::fs::create_dir_all(new.join("share/store")).unwrap();
+
+        let moves = vec![
+            (old.join("store"), new.join("share/store")),
+            (old.join("nuclaw.toml"), new.join("config/nuclaw.toml")),
+        ];
+        move_paths(&moves).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(new.join("share/store/nuclaw.db")).unwrap(),
+            "db"
+        );
+        assert!(new.join("config/nuclaw.toml").is_file());
+        assert!(!old.join("store").exists());
+
+        // Nothing is replaced
+        std::fs::create_dir_all(old.join("store")).unwrap();
+        let err = move_paths(&moves[..1]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(new.join("share/store/nuclaw.db").is_file());
+    }
+
     #[test]
     fn test_anthropic_api_key_from_env() {
         std::env::remove_var("ANTHROPIC_API_KEY");
@@ -117,4 +334,38 @@ mod tests {
 
         std::env::remove_var("CLAUDE_MODEL");
     }
+
+    #[test]
+    fn test_admin_users_from_env() {
+        std::env::remove_var("ADMIN_USERS");
+        assert!(admin_users().is_empty());
+
+        std::env::set_var("ADMIN_USERS", "123, 456@s.whatsapp.net,,");
+        assert_eq!(
+            admin_users(),
+            vec!["123".to_string(), "456@s.whatsapp.net".to_string()]
+        );
+
+        std::env::remove_var("ADMIN_USERS");
+    }
+
+    #[test]
+    fn test_reaction_from_env() {
+        std::env::remove_var("NUCLAW_TEST_REACTION");
+        assert_eq!(
+            reaction_from_env("NUCLAW_TEST_REACTION", "👀"),
+            Some("👀".to_string())
+        );
+
+        std::env::set_var("NUCLAW_TEST_REACTION", " 🔥 ");
+        assert_eq!(
+            reaction_from_env("NUCLAW_TEST_REACTION", "👀"),
+            Some("🔥".to_string())
+        );
+
+        std::env::set_var("NUCLAW_TEST_REACTION", "");
+        assert_eq!(reaction_from_env("NUCLAW_TEST_REACTION", "👀"), None);
+
+        std::env::remove_var("NUCLAW_TEST_REACTION");
+    }
 }
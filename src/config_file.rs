@@ -0,0 +1,486 @@
+//! Configuration File for NuClaw
+//!
+//! Settings can be kept in `nuclaw.toml` in the config directory (see
+//! `config`), or the file named by `NUCLAW_CONFIG`, instead of the
+//! environment. Keys are the names of the environment variables, in any
+//! case; arrays become comma-separated lists:
+//!
+//! ```toml
+//! assistant_name = "Jarvis"
+//! container_timeout = 600000
+//! admin_users = ["123456", "789012"]
+//! ```
+//!
+//! A variable set in the environment wins over the file.
+//!
+//! `[profile.<name>]` sections hold settings that apply on top of the
+//! others when NuClaw runs with `--profile <name>` (or `NUCLAW_PROFILE`),
+//! e.g. to run a staging bot next to the production one:
+//!
+//! ```toml
+//! [profile.staging]
+//! nuclaw_home = "/srv/nuclaw-staging"
+//! telegram_bot_token = "123:staging"
+//! rust_log = "debug"
+//! ```
+//!
+//! While NuClaw runs, the file is watched. Changes to the settings that
+//! are read at each use (`RELOADABLE`: trigger name, admins, chunk
+//! limits, timeouts, the log filter, and the like) apply at once; changes
+//! to others are logged as waiting for a restart. Each reload logs what
+//! changed, with secrets masked.
+
+use crate::config::config_dir;
+use crate::error::{NuClawError, Result};
+use crate::logging::{self, Level, LOG_FILTER_VARS};
+use crate::settings::{forget_source, is_secret, record_source, Source};
+use notify::{RecursiveMode, Watcher};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Settings a reload applies while running
+const RELOADABLE: &[&str] = &[
+    "ACK_REACTION",
+    "ADMIN_USERS",
+    "ASSISTANT_NAME",
+    "CONTAINER_MAX_OUTPUT_SIZE",
+    "CONTAINER_TIMEOUT",
+    "CONTEXT_MESSAGES",
+    "DONE_REACTION",
+    "EMBEDDING_TIMEOUT",
+    "ERROR_REACTION",
+    "MEMORY_TOP_K",
+    "NUCLAW_LOG_LEVEL",
+    "PAIRING_CODE_TTL",
+    "RUST_LOG",
+    "SESSION_IDLE_HOURS",
+    "TASK_MAX_RETRIES",
+    "TASK_MAX_TIMEOUT",
+    "TASK_TIMEOUT",
+    "TELEGRAM_TEXT_CHUNK_LIMIT",
+    "TRANSCRIPTION_TIMEOUT",
+];
+/// Time for the events of one save to settle before reloading
+const RELOAD_DELAY: Duration = Duration::from_millis(500);
+/// Table holding the profile sections
+const PROFILE_TABLE: &str = "profile";
+
+/// Settings taken from the file, with the values last applied
+static APPLIED: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+/// Config file read at startup; a profile may move the config directory
+/// by setting `NUCLAW_HOME`, but reloads keep reading this file
+static LOADED_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// A setting changed in the config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+    /// Applied while running, rather than at the next start
+    pub applied: bool,
+}
+
+impl Change {
+    fn describe(&self) -> String {
+        let show = |value: &Option<String>| match value {
+            None => "(unset)".to_string(),
+            Some(_) if is_secret(&self.key) => "***".to_string(),
+            Some(v) => format!("{:?}", v),
+        };
+        format!("{}: {} -> {}", self.key, show(&self.old), show(&self.new))
+    }
+}
+
+/// Path of the config file
+pub fn config_file_path() -> PathBuf {
+    if let Some(path) = LOADED_PATH.get() {
+        return path.clone();
+    }
+    std::env::var("NUCLAW_CONFIG")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config_dir().join("nuclaw.toml"))
+}
+
+/// Profile selected with `--profile` or `NUCLAW_PROFILE`
+pub fn profile() -> Option<String> {
+    std::env::var("NUCLAW_PROFILE")
+        .ok()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+}
+
+/// Parse the settings of a config file, keyed by variable name, with
+/// those of `profile` applied on top
+pub fn parse_settings(
+    path: &Path,
+    contents: &str,
+    profile: Option<&str>,
+) -> Result<BTreeMap<String, String>> {
+    let invalid = |message: String| NuClawError::Config {
+        message: format!("Invalid {}: {}", path.display(), message),
+    };
+    let mut table: toml::Table = contents.parse().map_err(|e| invalid(format!("{}", e)))?;
+
+    let profiles = match table.remove(PROFILE_TABLE) {
+        Some(toml::Value::Table(profiles)) => profiles,
+        Some(_) => return Err(invalid(format!("'{}' must be a table", PROFILE_TABLE))),
+        None => toml::Table::new(),
+    };
+    let mut settings = table_settings(path, table)?;
+    let mut selected = None;
+    // Every profile is checked, not only the selected one
+    for (name, section) in profiles {
+        let toml::Value::Table(section) = section else {
+            return Err(invalid(format!(
+                "'{}.{}' must be a table",
+                PROFILE_TABLE, name
+            )));
+        };
+        let section = table_settings(path, section)?;
+        if profile == Some(name.as_str()) {
+            selected = Some(section);
+        }
+    }
+    match (profile, selected) {
+        (Some(profile), None) => Err(NuClawError::Config {
+            message: format!("Profile '{}' is not defined in {}", profile, path.display()),
+        }),
+        (_, selected) => {
+            settings.extend(selected.unwrap_or_default());
+            Ok(settings)
+        }
+    }
+}
+
+fn table_settings(path: &Path, table: toml::Table) -> Result<BTreeMap<String, String>> {
+    let invalid = |message: String| NuClawError::Config {
+        message: format!("Invalid {}: {}", path.display(), message),
+    };
+    let mut settings = BTreeMap::new();
+    for (key, value) in table {
+        let value = match &value {
+            toml::Value::Array(items) => items
+                .iter()
+                .map(scalar)
+                .collect::<Option<Vec<_>>>()
+                .map(|items| items.join(",")),
+            value => scalar(value),
+        }
+        .ok_or_else(|| invalid(format!("'{}' must be a value or a list of values", key)))?;
+        settings.insert(key.to_ascii_uppercase(), value);
+    }
+    Ok(settings)
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        toml::Value::Datetime(d) => Some(d.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => None,
+    }
+}
+
+/// The config file's settings, with the selected profile's; `None` if
+/// there is no file
+fn read_settings(path: &Path) -> Result<Option<BTreeMap<String, String>>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => parse_settings(path, &contents, profile().as_deref()).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => match profile() {
+            Some(profile) => Err(NuClawError::Config {
+                message: format!(
+                    "Profile '{}' is selected, but there is no {}",
+                    profile,
+                    path.display()
+                ),
+            }),
+            None => Ok(None),
+        },
+        Err(e) => Err(NuClawError::Config {
+            message: format!("Failed to read {}: {}", path.display(), e),
+        }),
+    }
+}
+
+/// Put the config file's settings into the environment, except those the
+/// environment already sets; returns the file's path if there is one
+///
+/// Runs at startup, before anything reads the environment.
+pub fn load_config_file() -> Result<Option<PathBuf>> {
+    let path = config_file_path();
+    let _ = LOADED_PATH.set(path.clone());
+    let Some(settings) = read_settings(&path)? else {
+        return Ok(None);
+    };
+    let mut applied = APPLIED.lock().unwrap();
+    for (key, value) in settings {
+        if std::env::var_os(&key).is_none() {
+            std::env::set_var(&key, &value);
+            record_source(&key, Source::File(path.clone()));
+            applied.insert(key, value);
+        }
+    }
+    Ok(Some(path))
+}
+
+/// Changes from the applied settings to the file's, leaving out settings
+/// the environment sets
+fn changes(
+    applied: &BTreeMap<String, String>,
+    file: &BTreeMap<String, String>,
+    in_environment: impl Fn(&str) -> bool,
+) -> Vec<Change> {
+    let mut keys: Vec<&String> = applied.keys().chain(file.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|key| applied.contains_key(*key) || !in_environment(key))
+        .filter(|key| applied.get(*key) != file.get(*key))
+        .map(|key| Change {
+            key: key.clone(),
+            old: applied.get(key).cloned(),
+            new: file.get(key).cloned(),
+            applied: RELOADABLE.contains(&key.as_str()),
+        })
+        .collect()
+}
+
+/// Read the config file again and apply the changes that take effect
+/// while running
+pub fn reload_config_file() -> Result<Vec<Change>> {
+    let path = config_file_path();
+    let settings = read_settings(&path)?.unwrap_or_default();
+    let mut applied = APPLIED.lock().unwrap();
+    let changes = changes(&applied, &settings, |key| std::env::var_os(key).is_some());
+    for change in changes.iter().filter(|c| c.applied) {
+        match &change.new {
+            Some(value) => {
+                std::env::set_var(&change.key, value);
+                record_source(&change.key, Source::File(path.clone()));
+                applied.insert(change.key.clone(), value.clone());
+            }
+            None => {
+                std::env::remove_var(&change.key);
+                forget_source(&change.key);
+                applied.remove(&change.key);
+            }
+        }
+    }
+    Ok(changes)
+}
+
+/// Switch to the log filter of the settings if a reload changed them
+fn apply_log_filter(changes: &[Change]) {
+    let changed = changes
+        .iter()
+        .any(|c| c.applied && LOG_FILTER_VARS.contains(&c.key.as_str()));
+    if changed {
+        if let Err(e) = logging::set_log_filter(&logging::filter_from_env(Level::Info)) {
+            warn!("Keeping the log filter '{}': {}", logging::log_filter(), e);
+        }
+    }
+}
+
+fn log_reload(path: &Path, changes: Vec<Change>) {
+    let (applied, pending): (Vec<_>, Vec<_>) = changes.into_iter().partition(|c| c.applied);
+    if applied.is_empty() && pending.is_empty() {
+        debug!("{} changed without changing any setting", path.display());
+    }
+    if !applied.is_empty() {
+        let described: Vec<_> = applied.iter().map(Change::describe).collect();
+        info!("Reloaded {}: {}", path.display(), described.join("; "));
+    }
+    for change in pending {
+        warn!(
+            "Restart NuClaw to apply this change to {}: {}",
+            path.display(),
+            change.describe()
+        );
+    }
+}
+
+/// Watch the config file and reload it when it changes, until the
+/// returned watcher is dropped
+pub fn watch_config_file() -> Result<notify::RecommendedWatcher> {
+    let path = config_file_path();
+    let name = path
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let failed = |e: notify::Error| NuClawError::FileSystem {
+        message: format!("Failed to watch {}: {}", path.display(), e),
+    };
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if !event.kind.is_access() && event.paths.iter().any(|p| p.file_name() == Some(&*name))
+            {
+                let _ = tx.send(());
+            }
+        }
+    })
+    .map_err(failed)?;
+    // Editors often replace the file rather than write to it, which a
+    // watch on the file itself would not survive
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .map_err(failed)?;
+
+    let watched = path.clone();
+    tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(RELOAD_DELAY).await;
+            while rx.try_recv().is_ok() {}
+            match reload_config_file() {
+                Ok(changes) => {
+                    apply_log_filter(&changes);
+                    log_reload(&watched, changes);
+                }
+                Err(e) => warn!("Keeping the current configuration: {}", e),
+            }
+        }
+    });
+    info!("Watching {} for changes", path.display());
+    Ok(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let path = Path::new("nuclaw.toml");
+        let parsed = parse_settings(
+            path,
+            r#"
+            assistant_name = "Jarvis"
+            CONTAINER_TIMEOUT = 600000
+            telegram_streaming = false
+            admin_users = ["123", 456]
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            settings(&[
+                ("ADMIN_USERS", "123,456"),
+                ("ASSISTANT_NAME", "Jarvis"),
+                ("CONTAINER_TIMEOUT", "600000"),
+                ("TELEGRAM_STREAMING", "false"),
+            ])
+        );
+
+        for invalid in [
+            "assistant_name = ",
+            "[telegram]\ndm_policy = \"open\"",
+            "a = [[1]]",
+            "profile = 1",
+            "[profile.dev]\na = { b = 1 }",
+        ] {
+            assert!(matches!(
+                parse_settings(path, invalid, None),
+                Err(NuClawError::Config { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_profiles() {
+        let path = Path::new("nuclaw.toml");
+        let contents = r#"
+            assistant_name = "Andy"
+            rust_log = "info"
+
+            [profile.staging]
+            rust_log = "debug"
+            telegram_bot_token = "123:staging"
+
+            [profile.prod]
+            "#;
+        assert_eq!(
+            parse_settings(path, contents, Some("staging")).unwrap(),
+            settings(&[
+                ("ASSISTANT_NAME", "Andy"),
+                ("RUST_LOG", "debug"),
+                ("TELEGRAM_BOT_TOKEN", "123:staging"),
+            ])
+        );
+        let base = settings(&[("ASSISTANT_NAME", "Andy"), ("RUST_LOG", "info")]);
+        assert_eq!(parse_settings(path, contents, None).unwrap(), base);
+        assert_eq!(parse_settings(path, contents, Some("prod")).unwrap(), base);
+        assert!(matches!(
+            parse_settings(path, contents, Some("dev")),
+            Err(NuClawError::Config { .. })
+        ));
+    }
+
+    #[test]
+    fn test_changes() {
+        let applied = settings(&[
+            ("ASSISTANT_NAME", "Andy"),
+            ("CONTAINER_IMAGE", "claw:1"),
+            ("ADMIN_USERS", "1"),
+        ]);
+        let file = settings(&[
+            ("ASSISTANT_NAME", "Jarvis"),
+            ("CONTAINER_IMAGE", "claw:2"),
+            ("TASK_TIMEOUT", "60"),
+            ("TELEGRAM_BOT_TOKEN", "123:abc"),
+        ]);
+        let changes = changes(&applied, &file, |key| key == "TELEGRAM_BOT_TOKEN");
+
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|c| (c.key.as_str(), c.new.as_deref(), c.applied))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("ADMIN_USERS", None, true),
+                ("ASSISTANT_NAME", Some("Jarvis"), true),
+                ("CONTAINER_IMAGE", Some("claw:2"), false),
+                ("TASK_TIMEOUT", Some("60"), true),
+            ]
+        );
+        assert_eq!(
+            changes[1].describe(),
+            r#"ASSISTANT_NAME: "Andy" -> "Jarvis""#
+        );
+    }
+
+    #[test]
+    fn test_secrets_are_masked() {
+        let change = Change {
+            key: "TELEGRAM_BOT_TOKEN".to_string(),
+            old: None,
+            new: Some("123:abc".to_string()),
+            applied: false,
+        };
+        assert_eq!(change.describe(), "TELEGRAM_BOT_TOKEN: (unset) -> ***");
+        assert!(is_secret("ANTHROPIC_API_KEY"));
+        assert!(is_secret("DATABASE_URL"));
+        assert!(!is_secret("ASSISTANT_NAME"));
+    }
+}
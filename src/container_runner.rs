@@ -8,16 +8,59 @@
 //! - Filesystem isolation per group
 //! - IPC namespace isolation
 //! - Configurable timeout
+//! - Input passed as JSON on the container's stdin
 //! - Output parsing with sentinel markers
-
-use crate::config::{anthropic_api_key, anthropic_base_url, assistant_name, claude_model, data_dir, groups_dir, logs_dir};
+//! - Optional streaming of partial output while the agent runs
+//! - Registry of running containers for status reporting
+//! - Global cap on containers running at once (`MAX_CONTAINERS`)
+//! - Per-run metrics recorded in the `container_runs` table, and token
+//!   usage per chat and user in the `usage` table (see `usage`)
+//! - Live stdout/stderr log per session under `logs/<group>/`, rotated,
+//!   compressed, and deleted per `log_retention`
+//! - Correlation ID of the message or task run passed to the agent and
+//!   noted in its logs (see `correlation`)
+//! - Memory, CPU, and process limits, overridable per group
+//! - Additional per-group mounts checked against the mount allowlist
+//! - Per-group image, entrypoint, environment, timeout, and network
+//! - Per-group overrides from `groups/<folder>.toml` in the config
+//!   directory (see `group_config`), read at each run
+//! - Host variables forwarded only through an allowlist and denylist
+//! - Dependency cache volume per group or shared between groups
+//! - Optional warm pool of long-lived containers per group
+//! - Retries with backoff after infrastructure failures
+//! - gVisor, Kata, or Firecracker isolation per group
+//! - WASM backend for hosts without Docker (see `wasm_runner`)
+//! - Local process backend without a container (see `process_runner`)
+
+use crate::agent_state::write_state_file;
+use crate::audit::{self, prompt_hash, AuditEvent, AuditKind};
+use crate::config::{assistant_name, data_dir, groups_dir, logs_dir};
+use crate::db::Database;
 use crate::error::{NuClawError, Result};
-use crate::types::{ContainerInput, ContainerOutput};
+use crate::group_config::with_group_file;
+use crate::groups::load_all_registered_groups;
+use crate::log_retention::{rotate_if_large, LogRetention};
+use crate::metrics::{record_container_run, status, ContainerRunRecord};
+use crate::mounts::{resolve_mounts, EXTRA_MOUNT_ROOT};
+use crate::process_runner;
+use crate::redact::redact;
+use crate::types::{
+    CacheScope, ContainerBackend, ContainerConfig, ContainerInput, ContainerOutput, Isolation,
+};
+use crate::usage::{record_usage, UsageRecord};
+use crate::warm_pool;
+use crate::wasm_runner;
+use std::collections::HashMap;
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{ChildStdout, Command as AsyncCommand};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Notify, Semaphore, SemaphorePermit};
 use tokio::time::{timeout, Duration, Instant};
 
 /// Default container timeout: 5 minutes
@@ -27,6 +70,38 @@ const DEFAULT_MAX_OUTPUT: usize = 10 * 1024 * 1024;
 /// Sentinel markers for output parsing
 const OUTPUT_START_MARKER: &str = "--NANOCLAW_OUTPUT_START--";
 const OUTPUT_END_MARKER: &str = "--NANOCLAW_OUTPUT_END--";
+/// Time a container gets to exit after printing its end marker
+const EXIT_GRACE_SECS: u64 = 5;
+/// Default resource limits for agent containers
+const DEFAULT_CONTAINER_MEMORY: &str = "2g";
+const DEFAULT_CONTAINER_CPUS: &str = "2";
+const DEFAULT_CONTAINER_PIDS_LIMIT: u32 = 512;
+/// Default number of containers running at once across all channels
+const DEFAULT_MAX_CONTAINERS: usize = 8;
+/// Reply sent when a chat's request has to wait for a container
+pub const QUEUED_NOTICE: &str = "Your request is queued and will start shortly.";
+/// Reply to a request whose run was cancelled
+pub const CANCELLED_NOTICE: &str = "Cancelled.";
+/// Default retries after an infrastructure failure
+const DEFAULT_CONTAINER_RETRIES: u32 = 2;
+/// Default delay before the first retry
+const DEFAULT_RETRY_DELAY_MS: u64 = 1000;
+/// Longest delay between retries
+const MAX_RETRY_DELAY_MS: u64 = 30_000;
+/// Exit code of `docker run`/`docker exec` when docker itself fails
+const DOCKER_ERROR_EXIT_CODE: i32 = 125;
+/// Agent CLI run in the container when the group sets no entrypoint; it
+/// reads the `ContainerInput` JSON from stdin
+const DEFAULT_AGENT_COMMAND: &str = "/usr/local/bin/claude";
+/// Image used when neither the group nor `CONTAINER_IMAGE` names one
+const DEFAULT_CONTAINER_IMAGE: &str = "anthropic/claude-code:latest";
+/// Host variables forwarded to agents when no allowlist is configured
+const DEFAULT_FORWARDED_ENV: &[&str] = &[
+    "CLAUDE_CODE_OAUTH_TOKEN",
+    "ANTHROPIC_API_KEY",
+    "ANTHROPIC_BASE_URL",
+    "CLAUDE_MODEL",
+];
 
 /// Get container timeout from environment or default
 pub fn container_timeout() -> Duration {
@@ -37,6 +112,15 @@ pub fn container_timeout() -> Duration {
     Duration::from_millis(timeout_ms)
 }
 
+/// Time limit of a run: the input's own, the group's, or
+/// `CONTAINER_TIMEOUT`
+fn run_timeout(input: &ContainerInput, config: &ContainerConfig) -> Duration {
+    input
+        .timeout
+        .or_else(|| config.timeout.map(Duration::from_millis))
+        .unwrap_or_else(container_timeout)
+}
+
 /// Get max output size from environment or default
 pub fn max_output_size() -> usize {
     std::env::var("CONTAINER_MAX_OUTPUT_SIZE")
@@ -45,8 +129,508 @@ pub fn max_output_size() -> usize {
         .unwrap_or(DEFAULT_MAX_OUTPUT)
 }
 
+/// How container runs that failed for infrastructure reasons are retried
+///
+/// Only `Container` errors (the runtime failed to start or run the
+/// container) are retried. Agent errors and timeouts are returned as is,
+/// since running the same prompt again would most likely fail again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Policy for a channel from `<CHANNEL>_CONTAINER_RETRIES`, falling
+    /// back to `CONTAINER_RETRIES`, and `CONTAINER_RETRY_DELAY_MS`
+    pub fn for_channel(channel: &str) -> Self {
+        let env_u64 = |key: &str| std::env::var(key).ok().and_then(|v| v.parse::<u64>().ok());
+        let retries = env_u64(&format!("{}_CONTAINER_RETRIES", channel.to_uppercase()))
+            .or_else(|| env_u64("CONTAINER_RETRIES"))
+            .map_or(DEFAULT_CONTAINER_RETRIES, |n| n as u32);
+        Self {
+            retries,
+            base_delay: Duration::from_millis(
+                env_u64("CONTAINER_RETRY_DELAY_MS").unwrap_or(DEFAULT_RETRY_DELAY_MS),
+            ),
+        }
+    }
+
+    /// Same policy with a different number of retries
+    pub fn with_retries(self, retries: u32) -> Self {
+        Self { retries, ..self }
+    }
+
+    /// Delay before retry number `retry` (starting at 1)
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.base_delay
+            .saturating_mul(factor)
+            .min(Duration::from_millis(MAX_RETRY_DELAY_MS))
+    }
+}
+
+/// Whether an error came from the container infrastructure rather than
+/// the agent
+pub fn is_infrastructure_error(error: &NuClawError) -> bool {
+    matches!(error, NuClawError::Container { .. })
+}
+
+/// Container settings of a group folder, as registered and overridden by
+/// the group's config file
+fn group_container_config(group_folder: &str) -> ContainerConfig {
+    load_all_registered_groups()
+        .into_iter()
+        .find(|g| g.folder == group_folder)
+        .and_then(|g| with_group_file(&g).container_config)
+        .unwrap_or_default()
+}
+
+/// `docker run` flags limiting memory, CPUs, and processes
+///
+/// Group settings take precedence over `CONTAINER_MEMORY`,
+/// `CONTAINER_CPUS`, and `CONTAINER_PIDS_LIMIT`. An empty memory or CPU
+/// value, or a pids limit of 0, leaves that resource unlimited.
+pub fn resource_limit_args(config: &ContainerConfig) -> Vec<String> {
+    let memory = config.memory.clone().unwrap_or_else(|| {
+        std::env::var("CONTAINER_MEMORY").unwrap_or_else(|_| DEFAULT_CONTAINER_MEMORY.to_string())
+    });
+    let cpus = config.cpus.clone().unwrap_or_else(|| {
+        std::env::var("CONTAINER_CPUS").unwrap_or_else(|_| DEFAULT_CONTAINER_CPUS.to_string())
+    });
+    let pids_limit = config.pids_limit.unwrap_or_else(|| {
+        std::env::var("CONTAINER_PIDS_LIMIT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_CONTAINER_PIDS_LIMIT)
+    });
+
+    let mut args = vec![];
+    if !memory.trim().is_empty() {
+        args.extend(["--memory".to_string(), memory.trim().to_string()]);
+    }
+    if !cpus.trim().is_empty() {
+        args.extend(["--cpus".to_string(), cpus.trim().to_string()]);
+    }
+    if pids_limit > 0 {
+        args.extend(["--pids-limit".to_string(), pids_limit.to_string()]);
+    }
+    args
+}
+
+/// `docker run` flags selecting the container's network
+///
+/// The group setting takes precedence over `CONTAINER_NETWORK`; an empty
+/// value keeps Docker's default network. An agent without network
+/// permission gets none.
+pub fn network_args(config: &ContainerConfig) -> Vec<String> {
+    let permissions = config.permissions.as_ref();
+    if permissions.and_then(|p| p.network_allowed) == Some(false) {
+        return vec!["--network".to_string(), "none".to_string()];
+    }
+    let network = config
+        .network
+        .clone()
+        .unwrap_or_else(|| std::env::var("CONTAINER_NETWORK").unwrap_or_default());
+    if network.trim().is_empty() {
+        vec![]
+    } else {
+        vec!["--network".to_string(), network.trim().to_string()]
+    }
+}
+
+/// Docker runtime implementing an isolation level, `None` for the default
+///
+/// Runtime names can be changed with `GVISOR_RUNTIME`, `KATA_RUNTIME`, and
+/// `FIRECRACKER_RUNTIME` to match the names registered in the daemon.
+fn isolation_runtime(isolation: Isolation) -> Option<String> {
+    let (var, default) = match isolation {
+        Isolation::Docker => return None,
+        Isolation::Gvisor => ("GVISOR_RUNTIME", "runsc"),
+        Isolation::Kata => ("KATA_RUNTIME", "kata-runtime"),
+        Isolation::Firecracker => ("FIRECRACKER_RUNTIME", "kata-fc"),
+    };
+    Some(
+        std::env::var(var)
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .unwrap_or_else(|| default.to_string()),
+    )
+}
+
+/// `docker run` flags selecting the group's sandbox runtime
+///
+/// The group setting takes precedence over `CONTAINER_ISOLATION`. An
+/// unknown `CONTAINER_ISOLATION` is an error rather than a silent fallback
+/// to the default runtime, and Docker refuses to start a container whose
+/// runtime is not installed, so a hardened group never runs unsandboxed.
+pub fn isolation_args(config: &ContainerConfig) -> Result<Vec<String>> {
+    let isolation = match config.isolation {
+        Some(isolation) => isolation,
+        None => {
+            let value = std::env::var("CONTAINER_ISOLATION").unwrap_or_default();
+            Isolation::parse(&value).ok_or_else(|| NuClawError::Config {
+                message: format!("Invalid CONTAINER_ISOLATION '{}'", value),
+            })?
+        }
+    };
+    Ok(isolation_runtime(isolation)
+        .map(|runtime| vec![format!("--runtime={}", runtime)])
+        .unwrap_or_default())
+}
+
+/// Names must be non-empty and free of `=` and whitespace
+fn check_env_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('=') || name.contains(char::is_whitespace) {
+        return Err(NuClawError::Validation {
+            message: format!("Invalid container environment variable '{}'", name),
+        });
+    }
+    Ok(())
+}
+
+/// The group's extra environment variables as `NAME=value`
+pub(crate) fn group_env(config: &ContainerConfig) -> Result<Vec<String>> {
+    let mut vars = vec![];
+    for (key, value) in &config.env {
+        check_env_name(key)?;
+        vars.push(format!("{}={}", key, value));
+    }
+    Ok(vars)
+}
+
+/// Where the cache volume is mounted in the container
+const CACHE_MOUNT: &str = "/workspace/cache";
+/// Where the group folder is mounted in the container
+const GROUP_MOUNT: &str = "/workspace/group";
+/// Cache locations of common tools, under `CACHE_MOUNT`
+const CACHE_ENV: &[(&str, &str)] = &[
+    ("XDG_CACHE_HOME", ""),
+    ("npm_config_cache", "npm"),
+    ("PIP_CACHE_DIR", "pip"),
+];
+
+/// `docker run` flags mounting the dependency cache volume
+///
+/// The group setting takes precedence over `CONTAINER_CACHE` (`group` by
+/// default). Volumes are named `nuclaw-cache-<group>` or
+/// `nuclaw-cache-shared` and are created by Docker on first use.
+pub fn cache_volume_args(config: &ContainerConfig, group_folder: &str) -> Result<Vec<String>> {
+    let scope = match config.cache {
+        Some(scope) => scope,
+        None => {
+            let value = std::env::var("CONTAINER_CACHE").unwrap_or_default();
+            CacheScope::parse(&value).ok_or_else(|| NuClawError::Config {
+                message: format!("Invalid CONTAINER_CACHE '{}'", value),
+            })?
+        }
+    };
+    let volume = match scope {
+        CacheScope::None => return Ok(vec![]),
+        CacheScope::Group => format!("nuclaw-cache-{}", volume_name_part(group_folder)),
+        CacheScope::Shared => "nuclaw-cache-shared".to_string(),
+    };
+
+    let mut args = vec![
+        "-v".to_string(),
+        format!(
+            "{}:{}{}",
+            volume,
+            CACHE_MOUNT,
+            mount_mode(config, CACHE_MOUNT)
+        ),
+    ];
+    for (name, dir) in CACHE_ENV {
+        let path = if dir.is_empty() {
+            CACHE_MOUNT.to_string()
+        } else {
+            format!("{}/{}", CACHE_MOUNT, dir)
+        };
+        args.extend(["-e".to_string(), format!("{}={}", name, path)]);
+    }
+    Ok(args)
+}
+
+/// Characters Docker accepts in volume names
+fn volume_name_part(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// `-e` flags for the group's extra environment variables
+fn group_env_args(config: &ContainerConfig) -> Result<Vec<String>> {
+    Ok(group_env(config)?
+        .into_iter()
+        .flat_map(|var| ["-e".to_string(), var])
+        .collect())
+}
+
+/// Comma-separated variable names from an environment variable
+fn env_name_list(var: &str) -> Option<Vec<String>> {
+    std::env::var(var).ok().map(|v| {
+        v.split(',')
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .collect()
+    })
+}
+
+/// Host variables forwarded to a group's agent
+///
+/// The group's `env_allowlist`, else `CONTAINER_ENV_ALLOWLIST`, else the
+/// agent credentials (`DEFAULT_FORWARDED_ENV`) are candidates. Names in
+/// the group's `env_denylist` or in `CONTAINER_ENV_DENYLIST` are never
+/// forwarded, and neither are variables not set on the host.
+pub(crate) fn forwarded_env(config: &ContainerConfig) -> Result<Vec<String>> {
+    let allowlist = config
+        .env_allowlist
+        .clone()
+        .or_else(|| env_name_list("CONTAINER_ENV_ALLOWLIST"))
+        .unwrap_or_else(|| {
+            DEFAULT_FORWARDED_ENV
+                .iter()
+                .map(|n| n.to_string())
+                .collect()
+        });
+    let denylist: Vec<String> = config
+        .env_denylist
+        .iter()
+        .cloned()
+        .chain(env_name_list("CONTAINER_ENV_DENYLIST").unwrap_or_default())
+        .collect();
+
+    let mut names = vec![];
+    for name in allowlist.iter().chain(&denylist) {
+        check_env_name(name)?;
+    }
+    for name in allowlist {
+        if !denylist.contains(&name) && std::env::var_os(&name).is_some() && !names.contains(&name)
+        {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Check the environment settings of every registered group
+///
+/// Run at startup so a bad allowlist, denylist, or variable name is
+/// reported before the first message instead of failing each run.
+pub fn validate_container_env() -> Result<()> {
+    forwarded_env(&ContainerConfig::default())?;
+    for group in load_all_registered_groups() {
+        let config = group.container_config.unwrap_or_default();
+        forwarded_env(&config)
+            .and_then(|_| group_env(&config))
+            .map_err(|e| NuClawError::Config {
+                message: format!("Group {}: {}", group.folder, e),
+            })?;
+    }
+    Ok(())
+}
+
+/// Backend for a group's runs
+///
+/// The group setting takes precedence over `CONTAINER_RUNNER`; an unknown
+/// `CONTAINER_RUNNER` is an error.
+pub fn container_backend(config: &ContainerConfig) -> Result<ContainerBackend> {
+    match config.runner {
+        Some(backend) => Ok(backend),
+        None => {
+            let value = std::env::var("CONTAINER_RUNNER").unwrap_or_default();
+            ContainerBackend::parse(&value).ok_or_else(|| NuClawError::Config {
+                message: format!("Invalid CONTAINER_RUNNER '{}'", value),
+            })
+        }
+    }
+}
+
+/// Image for a group's agent container
+fn agent_image(config: &ContainerConfig) -> String {
+    config
+        .image
+        .clone()
+        .filter(|i| !i.trim().is_empty())
+        .unwrap_or_else(|| {
+            std::env::var("CONTAINER_IMAGE").unwrap_or_else(|_| DEFAULT_CONTAINER_IMAGE.to_string())
+        })
+}
+
+/// Command that runs the agent inside the container, program first
+///
+/// The bundled CLI is limited to the group's allowed tools; other
+/// entrypoints find them in the `permissions` of their input.
+pub(crate) fn agent_command(config: &ContainerConfig) -> Vec<String> {
+    match config
+        .entrypoint
+        .as_deref()
+        .filter(|e| !e.trim().is_empty())
+    {
+        Some(entrypoint) => vec![entrypoint.to_string()],
+        None => {
+            let mut command = vec![DEFAULT_AGENT_COMMAND.to_string()];
+            let allowed_tools = config
+                .permissions
+                .as_ref()
+                .and_then(|p| p.allowed_tools.as_ref());
+            if let Some(tools) = allowed_tools {
+                command.extend(["--allowedTools".to_string(), tools.join(",")]);
+            }
+            command
+        }
+    }
+}
+
+/// Arguments resuming the chat's agent session with the bundled CLI;
+/// other entrypoints find it in the `session_id` of their input
+fn resume_args(config: &ContainerConfig, input: &ContainerInput) -> Vec<String> {
+    let bundled = config
+        .entrypoint
+        .as_deref()
+        .is_none_or(|e| e.trim().is_empty());
+    match input.session_id.as_deref() {
+        Some(session_id) if input.resume_session && bundled => {
+            vec!["--resume".to_string(), session_id.to_string()]
+        }
+        _ => vec![],
+    }
+}
+
+/// `:ro` for a mount the agent may not write, else nothing
+fn mount_mode(config: &ContainerConfig, container_path: &str) -> &'static str {
+    match &config.permissions {
+        Some(permissions) if !permissions.may_write(container_path) => ":ro",
+        _ => "",
+    }
+}
+
+/// `docker run` flags mounting the writable paths inside a read-only
+/// group folder, such as `/workspace/group/notes`, over it
+fn writable_group_dir_args(config: &ContainerConfig, group_dir: &Path) -> Result<Vec<String>> {
+    let Some(paths) = config
+        .permissions
+        .as_ref()
+        .and_then(|p| p.writable_paths.as_ref())
+    else {
+        return Ok(vec![]);
+    };
+    if mount_mode(config, GROUP_MOUNT).is_empty() {
+        return Ok(vec![]);
+    }
+    let mut args = Vec::new();
+    for path in paths {
+        let Some(relative) = path
+            .trim()
+            .trim_end_matches('/')
+            .strip_prefix(GROUP_MOUNT)
+            .and_then(|rest| rest.strip_prefix('/'))
+        else {
+            continue;
+        };
+        if relative
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            return Err(NuClawError::Validation {
+                message: format!("Invalid writable path '{}'", path),
+            });
+        }
+        let host_dir = group_dir.join(relative);
+        fs::create_dir_all(&host_dir).map_err(|e| NuClawError::FileSystem {
+            message: format!("Failed to create {}: {}", host_dir.display(), e),
+        })?;
+        args.extend([
+            "-v".to_string(),
+            format!("{}:{}/{}", host_dir.display(), GROUP_MOUNT, relative),
+        ]);
+    }
+    Ok(args)
+}
+
+/// `docker run` arguments from the group's environment through the
+/// command: `-e` flags, entrypoint, image, and the command's arguments
+pub fn agent_launch_args(config: &ContainerConfig) -> Result<Vec<String>> {
+    let mut args = group_env_args(config)?;
+    let mut command = agent_command(config).into_iter();
+    args.push("--entrypoint".to_string());
+    args.extend(command.next());
+    args.push(agent_image(config));
+    args.extend(command);
+    Ok(args)
+}
+
+/// `docker run` flags shared by one-off and warm containers: mounts,
+/// resource limits, and environment
+fn container_setup_args(
+    input: &ContainerInput,
+    group_dir: &Path,
+    config: &ContainerConfig,
+) -> Result<Vec<String>> {
+    let extra_mounts = resolve_mounts(&config.additional_mounts, &input.group_folder)?;
+
+    // The IPC directory stays writable: it is how the agent talks to NuClaw
+    let mut args = vec![
+        "-v".to_string(),
+        format!(
+            "{}:{}{}",
+            group_dir.display(),
+            GROUP_MOUNT,
+            mount_mode(config, GROUP_MOUNT)
+        ),
+        "-v".to_string(),
+        format!(
+            "{}:/workspace/ipc",
+            create_group_ipc_directory(&input.group_folder)?.display()
+        ),
+    ];
+    args.extend(isolation_args(config)?);
+    args.extend(network_args(config));
+    args.extend(resource_limit_args(config));
+    args.extend(writable_group_dir_args(config, group_dir)?);
+    args.extend(cache_volume_args(config, &input.group_folder)?);
+    for mount in &extra_mounts {
+        let mut mount = mount.clone();
+        let container_path = format!("{}/{}", EXTRA_MOUNT_ROOT, mount.container_path);
+        mount.readonly |= !mount_mode(config, &container_path).is_empty();
+        args.extend(["-v".to_string(), mount.volume_arg()]);
+    }
+
+    for name in forwarded_env(config)? {
+        args.extend(["-e".to_string(), name]);
+    }
+    Ok(args)
+}
+
+/// `docker run` arguments for a group's warm container, which idles
+/// until agent runs are started in it with `docker exec`
+fn warm_container_spec(
+    input: &ContainerInput,
+    group_dir: &Path,
+    config: &ContainerConfig,
+) -> Result<Vec<String>> {
+    let mut args = container_setup_args(input, group_dir, config)?;
+    args.extend(group_env_args(config)?);
+    args.extend([
+        "--entrypoint".to_string(),
+        "tail".to_string(),
+        agent_image(config),
+        "-f".to_string(),
+        "/dev/null".to_string(),
+    ]);
+    Ok(args)
+}
+
 /// Get the container command based on platform
-fn get_container_command() -> &'static str {
+pub(crate) fn get_container_command() -> &'static str {
     if cfg!(target_os = "macos") {
         "container"
     } else {
@@ -116,41 +700,462 @@ fn prepare_group_context(group_folder: &str) -> Result<PathBuf> {
     Ok(group_dir)
 }
 
+/// A container run in progress
+#[derive(Debug, Clone)]
+pub struct RunningContainer {
+    pub group_folder: String,
+    pub chat_jid: String,
+    pub session_id: Option<String>,
+    pub started_at: Instant,
+    cancel: Arc<Notify>,
+}
+
+static NEXT_RUN_ID: AtomicU64 = AtomicU64::new(0);
+
+fn running_registry() -> &'static Mutex<HashMap<u64, RunningContainer>> {
+    static RUNNING: OnceLock<Mutex<HashMap<u64, RunningContainer>>> = OnceLock::new();
+    RUNNING.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Containers currently running, oldest first
+pub fn running_containers() -> Vec<RunningContainer> {
+    let mut running: Vec<RunningContainer> = running_registry()
+        .lock()
+        .map(|r| r.values().cloned().collect())
+        .unwrap_or_default();
+    running.sort_by_key(|c| c.started_at);
+    running
+}
+
+/// Stop the running containers matching `filter`, returning how many
+///
+/// Each run kills its container and fails with a `Cancelled` error.
+fn cancel_runs(filter: impl Fn(&RunningContainer) -> bool) -> usize {
+    running_registry()
+        .lock()
+        .map(|running| {
+            running
+                .values()
+                .filter(|c| filter(c))
+                .map(|c| c.cancel.notify_one())
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+/// Cancel the runs of a session, e.g. `scheduled_<task id>`
+pub fn cancel_session(session_id: &str) -> usize {
+    cancel_runs(|c| c.session_id.as_deref() == Some(session_id))
+}
+
+/// Cancel the runs answering a chat
+pub fn cancel_chat(chat_jid: &str) -> usize {
+    cancel_runs(|c| c.chat_jid == chat_jid)
+}
+
+/// Cancel every running container, e.g. when shutting down
+pub fn cancel_all() -> usize {
+    cancel_runs(|_| true)
+}
+
+/// Caps the containers running at once across channels and the scheduler
+pub struct ContainerLimiter {
+    slots: Semaphore,
+    max: usize,
+    waiting: AtomicUsize,
+}
+
+impl ContainerLimiter {
+    /// Create a limiter allowing `max` containers at once
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            slots: Semaphore::new(max),
+            max,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait for a free slot; it is released when the permit is dropped
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        // Counted until the permit is granted or the wait is cancelled
+        let _waiting = WaitingGuard(&self.waiting);
+        self.slots
+            .acquire()
+            .await
+            .expect("container semaphore is never closed")
+    }
+
+    /// Whether a new run would have to wait
+    pub fn is_saturated(&self) -> bool {
+        self.slots.available_permits() == 0
+    }
+
+    /// Runs waiting for a slot
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::SeqCst)
+    }
+
+    /// Maximum number of containers at once
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+struct WaitingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The process-wide limiter, sized by `MAX_CONTAINERS`
+pub fn container_limiter() -> &'static ContainerLimiter {
+    static LIMITER: OnceLock<ContainerLimiter> = OnceLock::new();
+    LIMITER.get_or_init(|| {
+        ContainerLimiter::new(
+            std::env::var("MAX_CONTAINERS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_CONTAINERS),
+        )
+    })
+}
+
+/// Keeps a run in the registry until dropped, including on cancellation
+struct RunGuard {
+    id: u64,
+    cancel: Arc<Notify>,
+}
+
+impl RunGuard {
+    fn register(input: &ContainerInput) -> Self {
+        let id = NEXT_RUN_ID.fetch_add(1, Ordering::Relaxed);
+        let cancel = Arc::new(Notify::new());
+        if let Ok(mut running) = running_registry().lock() {
+            running.insert(
+                id,
+                RunningContainer {
+                    group_folder: input.group_folder.clone(),
+                    chat_jid: input.chat_jid.clone(),
+                    session_id: input.session_id.clone(),
+                    started_at: Instant::now(),
+                    cancel: cancel.clone(),
+                },
+            );
+        }
+        RunGuard { id, cancel }
+    }
+
+    /// Name for the run's one-off container
+    fn container_name(&self) -> String {
+        format!("nuclaw-run-{}-{}", std::process::id(), self.id)
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        if let Ok(mut running) = running_registry().lock() {
+            running.remove(&self.id);
+        }
+    }
+}
+
+/// Per-run state shared with the code driving the container process
+#[derive(Clone, Default)]
+struct RunContext {
+    log: RunLog,
+    /// Notified when the run is cancelled
+    cancel: Arc<Notify>,
+    /// Container to remove when the run is stopped early; killing the
+    /// `docker run` client alone would leave it running
+    container_name: Option<String>,
+}
+
+impl RunContext {
+    /// Kill the process and its container after a timeout or cancellation
+    async fn stop(&self, child: &mut tokio::process::Child) {
+        let _ = child.kill().await;
+        if let Some(name) = self.container_name.clone() {
+            std::thread::spawn(move || {
+                let _ = Command::new(get_container_command())
+                    .args(["rm", "-f", &name])
+                    .output();
+            });
+        }
+    }
+}
+
 /// Run a container with the given input
 pub async fn run_container(input: ContainerInput) -> Result<ContainerOutput> {
+    run_container_with_progress(input, None).await
+}
+
+/// Run a container, forwarding each stdout line to `progress` as it arrives
+///
+/// Only lines before the output start marker are forwarded, so the
+/// structured result block is never shown as partial output. The result
+/// is returned as soon as the end marker is read; a container that does
+/// not exit shortly afterwards is killed.
+pub async fn run_container_with_progress(
+    input: ContainerInput,
+    progress: Option<UnboundedSender<String>>,
+) -> Result<ContainerOutput> {
+    run_measured(input, progress, &mut RunMeasurements::default()).await
+}
+
+/// Measurements taken while a container runs
+#[derive(Debug, Clone, Default)]
+struct RunMeasurements {
+    queue_wait: Duration,
+    /// Time from the container being spawned to its output being read
+    duration: Duration,
+    exit_code: Option<i32>,
+    output_bytes: usize,
+    timed_out: bool,
+    cancelled: bool,
+}
+
+impl RunMeasurements {
+    /// Record for the `container_runs` table
+    fn record(
+        &self,
+        input: &ContainerInput,
+        result: &Result<ContainerOutput>,
+    ) -> ContainerRunRecord {
+        let run_status = match result {
+            Err(e) if is_infrastructure_error(e) => status::INFRASTRUCTURE_ERROR,
+            _ if self.timed_out => status::TIMEOUT,
+            _ if self.cancelled => status::CANCELLED,
+            Ok(output) if output.status == "success" => status::SUCCESS,
+            _ => status::ERROR,
+        };
+        ContainerRunRecord {
+            group_folder: input.group_folder.clone(),
+            chat_jid: input.chat_jid.clone(),
+            status: run_status,
+            exit_code: self.exit_code,
+            duration_ms: self.duration.as_millis() as u64,
+            queue_wait_ms: self.queue_wait.as_millis() as u64,
+            output_bytes: self.output_bytes,
+        }
+    }
+}
+
+async fn run_measured(
+    mut input: ContainerInput,
+    progress: Option<UnboundedSender<String>>,
+    measurements: &mut RunMeasurements,
+) -> Result<ContainerOutput> {
+    let queued_at = Instant::now();
+    let _slot = container_limiter().acquire().await;
+    measurements.queue_wait = queued_at.elapsed();
+    let config = group_container_config(&input.group_folder);
+    input.permissions = config.permissions.clone();
+    let guard = RunGuard::register(&input);
+    let mut run = RunContext {
+        log: RunLog::open(&input),
+        cancel: guard.cancel.clone(),
+        container_name: None,
+    };
     let group_folder = &input.group_folder;
     let group_dir = prepare_group_context(group_folder)?;
     write_ipc_files(group_folder, &input)?;
-    let (mut cmd, input_path) = build_container_command(&input, &group_dir).await?;
-    let timeout_duration = container_timeout();
-    let output = run_container_with_output(&mut cmd, timeout_duration).await?;
-    let _ = fs::remove_file(&input_path);
-    Ok(output)
+    let input_json = serde_json::to_vec(&input).map_err(|e| NuClawError::Container {
+        message: format!("Failed to serialize input: {}", e),
+    })?;
+    let backend_command = match container_backend(&config)? {
+        ContainerBackend::Docker => None,
+        ContainerBackend::Wasm => Some(wasm_runner::build_wasm_command(
+            &input, &group_dir, &config,
+        )?),
+        ContainerBackend::Process => Some(process_runner::build_process_command(
+            &input, &group_dir, &config,
+        )?),
+    };
+    if let Some(mut cmd) = backend_command {
+        return run_container_with_output(
+            &mut cmd,
+            input_json,
+            run_timeout(&input, &config),
+            progress,
+            &run,
+            measurements,
+        )
+        .await;
+    }
+    if !cfg!(target_os = "macos") && warm_pool::enabled(&config) {
+        return run_in_warm_container(
+            &input,
+            input_json,
+            &group_dir,
+            &config,
+            progress,
+            &run,
+            measurements,
+        )
+        .await;
+    }
+    if !cfg!(target_os = "macos") {
+        run.container_name = Some(guard.container_name());
+    }
+    let (mut cmd, input_file) = build_container_command(
+        &input,
+        &input_json,
+        &group_dir,
+        &config,
+        run.container_name.as_deref(),
+    )
+    .await?;
+    let output = run_container_with_output(
+        &mut cmd,
+        input_json,
+        run_timeout(&input, &config),
+        progress,
+        &run,
+        measurements,
+    )
+    .await;
+    if let Some(input_file) = input_file {
+        let _ = fs::remove_file(&input_file);
+    }
+    output
+}
+
+/// Run a container, retrying infrastructure failures per `policy`
+///
+/// The group's key-value state is written to its IPC directory first (see
+/// `agent_state`). Each attempt is recorded in the audit log when it
+/// starts, and in the `container_runs` and `usage` tables when it ends.
+pub async fn run_container_with_retry(
+    db: &Database,
+    input: ContainerInput,
+    policy: RetryPolicy,
+    progress: Option<UnboundedSender<String>>,
+) -> Result<ContainerOutput> {
+    let group_folder = input.group_folder.clone();
+    if let Err(e) = db.call(move |db| write_state_file(db, &group_folder)).await {
+        tracing::warn!("Failed to write the state of {}: {}", input.group_folder, e);
+    }
+    let started = AuditEvent::new(
+        AuditKind::Container,
+        if input.is_scheduled_task {
+            "scheduler"
+        } else {
+            "chat"
+        },
+        format!(
+            "Started the agent of {} with prompt {}",
+            input.group_folder,
+            prompt_hash(&input.prompt)
+        ),
+    )
+    .in_chat(&input.chat_jid);
+    let mut retry = 0;
+    loop {
+        let event = started.clone();
+        if let Err(e) = db.call(move |db| audit::append(db, &event)).await {
+            tracing::warn!("{}", e);
+        }
+        let mut measurements = RunMeasurements::default();
+        let result = run_measured(input.clone(), progress.clone(), &mut measurements).await;
+        let record = measurements.record(&input, &result);
+        let usage = UsageRecord {
+            chat_jid: record.chat_jid.clone(),
+            group_folder: record.group_folder.clone(),
+            user_id: input
+                .sender
+                .clone()
+                .unwrap_or_else(|| "scheduler".to_string()),
+            status: record.status,
+            duration_ms: record.duration_ms,
+            tokens: result.as_ref().ok().and_then(|output| output.usage),
+        };
+        if let Err(e) = db.call(move |db| record_container_run(db, &record)).await {
+            tracing::warn!("Failed to record container run: {}", e);
+        }
+        if let Err(e) = db.call(move |db| record_usage(db, &usage)).await {
+            tracing::warn!("{}", e);
+        }
+
+        match result {
+            Err(e) if is_infrastructure_error(&e) && retry < policy.retries => {
+                retry += 1;
+                let delay = policy.delay(retry);
+                tracing::warn!(
+                    "Container run for {} failed ({}), retry {}/{} in {:?}",
+                    input.group_folder,
+                    e,
+                    retry,
+                    policy.retries,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
 }
 
+/// Run the agent with `docker exec` in the group's warm container
+async fn run_in_warm_container(
+    input: &ContainerInput,
+    input_json: Vec<u8>,
+    group_dir: &Path,
+    config: &ContainerConfig,
+    progress: Option<UnboundedSender<String>>,
+    run: &RunContext,
+    measurements: &mut RunMeasurements,
+) -> Result<ContainerOutput> {
+    let spec = warm_container_spec(input, group_dir, config)?;
+    let lease = warm_pool::acquire(&input.group_folder, spec).await?;
+
+    let mut cmd = AsyncCommand::new(get_container_command());
+    cmd.arg("exec")
+        .arg("-i")
+        .arg(lease.name())
+        .args(agent_command(config))
+        .args(resume_args(config, input))
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    let output = run_container_with_output(
+        &mut cmd,
+        input_json,
+        run_timeout(input, config),
+        progress,
+        run,
+        measurements,
+    )
+    .await;
+
+    // A failed run may have left the agent running or the container broken
+    lease.release(matches!(&output, Ok(o) if o.status == "success"));
+    output
+}
+
+/// Build the command for a one-off container
+///
+/// The input is written to the container's stdin by
+/// `run_container_with_output`. Apple Container reads it from a file
+/// instead, whose path is returned so it can be removed after the run.
 async fn build_container_command(
     input: &ContainerInput,
+    input_json: &[u8],
     group_dir: &Path,
-) -> Result<(AsyncCommand, PathBuf)> {
-    let temp_dir = data_dir().join("temp");
-    fs::create_dir_all(&temp_dir).map_err(|e| NuClawError::FileSystem {
-        message: format!("Failed to create temp directory: {}", e),
-    })?;
-    let input_path = temp_dir.join(format!(
-        "input_{}.json",
-        input
-            .session_id
-            .clone()
-            .unwrap_or_else(|| "default".to_string())
-    ));
-    let input_json = serde_json::to_string(input).map_err(|e| NuClawError::Container {
-        message: format!("Failed to serialize input: {}", e),
-    })?;
-    fs::write(&input_path, &input_json).map_err(|e| NuClawError::FileSystem {
-        message: format!("Failed to write input file: {}", e),
-    })?;
+    config: &ContainerConfig,
+    container_name: Option<&str>,
+) -> Result<(AsyncCommand, Option<PathBuf>)> {
+    let setup_args = container_setup_args(input, group_dir, config)?;
+    let mut launch_args = agent_launch_args(config)?;
+    launch_args.extend(resume_args(config, input));
+
     let mut cmd = AsyncCommand::new(get_container_command());
+    let mut input_file = None;
     if cfg!(target_os = "macos") {
+        let input_path = write_input_file(input_json)?;
         cmd.arg("exec")
             .arg("--workspace")
             .arg(group_dir)
@@ -158,93 +1163,147 @@ async fn build_container_command(
             .arg(&input_path)
             .arg("--name")
             .arg(assistant_name());
+        input_file = Some(input_path);
     } else {
-        let image = std::env::var("CONTAINER_IMAGE")
-            .unwrap_or_else(|_| "anthropic/claude-code:latest".to_string());
-        cmd.arg("run")
-            .arg("--rm")
-            .arg("-v")
-            .arg(format!("{}:/workspace/group", group_dir.display()))
-            .arg("-e")
-            .arg("CLAUDE_CODE_OAUTH_TOKEN");
-        
-        if anthropic_api_key().is_some() {
-            cmd.arg("-e").arg("ANTHROPIC_API_KEY");
-        }
-        
-        if anthropic_base_url().is_some() {
-            cmd.arg("-e").arg("ANTHROPIC_BASE_URL");
-        }
-        
-        if claude_model().is_some() {
-            cmd.arg("-e").arg("CLAUDE_MODEL");
-        }
-        
-        cmd.arg("--entrypoint")
-            .arg("/bin/sh")
-            .arg(image)
-            .arg("-c")
-            .arg("cat /workspace/input.json | /usr/local/bin/claude");
+        cmd.arg("run").arg("--rm").arg("-i");
+        if let Some(name) = container_name {
+            cmd.arg("--name").arg(name);
+        }
+        cmd.args(setup_args).args(launch_args);
     }
     cmd.stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped());
-    Ok((cmd, input_path))
+    Ok((cmd, input_file))
+}
+
+/// Write a run's input to a file no other run uses
+fn write_input_file(input_json: &[u8]) -> Result<PathBuf> {
+    static NEXT_INPUT_ID: AtomicU64 = AtomicU64::new(0);
+
+    let temp_dir = data_dir().join("temp");
+    fs::create_dir_all(&temp_dir).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to create temp directory: {}", e),
+    })?;
+    let input_path = temp_dir.join(format!(
+        "input_{}_{}.json",
+        std::process::id(),
+        NEXT_INPUT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&input_path, input_json).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to write input file: {}", e),
+    })?;
+    Ok(input_path)
 }
 
+/// Spawn `cmd`, feed `input` to its stdin, and collect its output
 async fn run_container_with_output(
     cmd: &mut AsyncCommand,
+    input: Vec<u8>,
     timeout_duration: Duration,
+    progress: Option<UnboundedSender<String>>,
+    run: &RunContext,
+    measurements: &mut RunMeasurements,
 ) -> Result<ContainerOutput> {
     let mut child = cmd.spawn().map_err(|e| NuClawError::Container {
         message: format!("Failed to spawn container: {}", e),
     })?;
     let start_time = Instant::now();
     if let Some(mut stdin) = child.stdin.take() {
-        let input_path = data_dir().join("temp/input.json");
-        if input_path.exists() {
-            let input_content = fs::read_to_string(&input_path).unwrap_or_default();
-            stdin
-                .write_all(input_content.as_bytes())
-                .await
-                .map_err(|e| NuClawError::Container {
-                    message: format!("Failed to write to stdin: {}", e),
-                })?;
-        }
-        stdin.shutdown().await.map_err(|e| NuClawError::Container {
-            message: format!("Failed to close stdin: {}", e),
-        })?;
+        // Written concurrently so a large input cannot deadlock against
+        // output the container produces before reading all of it
+        tokio::spawn(async move {
+            if let Err(e) = stdin.write_all(&input).await {
+                tracing::warn!("Failed to write container input: {}", e);
+            }
+            let _ = stdin.shutdown().await;
+        });
+    }
+    if let Some(stderr) = child.stderr.take() {
+        // Drained so a chatty agent cannot block on a full pipe
+        let log = run.log.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                log.write("stderr", &line);
+            }
+        });
     }
     let stdout = child.stdout.take().unwrap();
-    let output_result = timeout(timeout_duration, capture_output(stdout)).await;
-    let exit_status = child.wait().await.map_err(|e| NuClawError::Container {
-        message: format!("Failed to wait for container: {}", e),
-    })?;
-    let duration_ms = start_time.elapsed().as_millis() as i64;
-    match output_result {
-        Ok(output) => {
-            let output = output?;
-            parse_container_output(&output, exit_status.success(), duration_ms)
+    let output_result = tokio::select! {
+        result = timeout(timeout_duration, capture_output(stdout, progress, &run.log)) => result,
+        _ = run.cancel.notified() => {
+            measurements.duration = start_time.elapsed();
+            measurements.cancelled = true;
+            run.stop(&mut child).await;
+            return Err(NuClawError::Cancelled {
+                message: "the run was stopped".to_string(),
+            });
+        }
+    };
+    measurements.duration = start_time.elapsed();
+    let duration_ms = measurements.duration.as_millis() as i64;
+    let output = match output_result {
+        Ok(output) => output?,
+        Err(_) => {
+            measurements.timed_out = true;
+            run.stop(&mut child).await;
+            return parse_container_output("", false, duration_ms);
+        }
+    };
+    measurements.output_bytes = output.len();
+
+    let success = match timeout(Duration::from_secs(EXIT_GRACE_SECS), child.wait()).await {
+        Ok(status) => {
+            let status = status.map_err(|e| NuClawError::Container {
+                message: format!("Failed to wait for container: {}", e),
+            })?;
+            measurements.exit_code = status.code();
+            if status.code() == Some(DOCKER_ERROR_EXIT_CODE)
+                && extract_marked_output(&output).is_none()
+            {
+                return Err(NuClawError::Container {
+                    message: "Container runtime failed to run the container".to_string(),
+                });
+            }
+            status.success()
         }
         Err(_) => {
+            tracing::warn!("Container still running after its output ended, killing it");
             let _ = child.kill().await;
-            parse_container_output("", false, duration_ms)
+            extract_marked_output(&output).is_some()
         }
-    }
+    };
+    parse_container_output(&output, success, duration_ms)
 }
 
-async fn capture_output(stdout: ChildStdout) -> Result<String> {
+async fn capture_output(
+    stdout: ChildStdout,
+    mut progress: Option<UnboundedSender<String>>,
+    log: &RunLog,
+) -> Result<String> {
     let reader = BufReader::new(stdout);
     let mut lines = reader.lines();
     let mut output = String::new();
     let max_size = max_output_size();
     while let Some(line) = lines.next_line().await.ok().flatten() {
+        log.write("stdout", &line);
         if output.len() + line.len() > max_size {
             output.push_str("\n[OUTPUT TRUNCATED - exceeded max size]");
             break;
         }
+        if line.contains(OUTPUT_START_MARKER) {
+            progress = None;
+        }
+        if let Some(tx) = &progress {
+            // The receiver may have gone away; output is still captured
+            let _ = tx.send(line.clone());
+        }
         output.push_str(&line);
         output.push('\n');
+        if line.contains(OUTPUT_END_MARKER) {
+            break;
+        }
     }
     Ok(output)
 }
@@ -274,6 +1333,7 @@ fn parse_container_output(
         },
         result: Some(output.to_string()),
         new_session_id: None,
+        usage: None,
         error: if success {
             None
         } else {
@@ -304,6 +1364,7 @@ fn parse_marked_content(content: &str, success: bool) -> Result<ContainerOutput>
         },
         result: Some(content.to_string()),
         new_session_id: None,
+        usage: None,
         error: if success {
             None
         } else {
@@ -332,9 +1393,102 @@ pub fn ensure_container_system_running() -> Result<()> {
     }
 }
 
+/// Live log of a run's output at `logs/<group>/<session>.log`
+///
+/// Lines are appended with a timestamp and stream name as they arrive, so
+/// a stuck run can be followed with `tail -f`. Each run starts with a line
+/// noting its correlation ID. Runs without a session yet log to
+/// `new-<correlation ID>.log`, or `new-<timestamp>.log` without one. A
+/// log over `CONTAINER_LOG_MAX_SIZE` is
+/// rotated before a run appends to it (see `log_retention`). Logging
+/// failures never fail the run.
+#[derive(Clone, Default)]
+struct RunLog {
+    file: Option<Arc<Mutex<fs::File>>>,
+}
+
+impl RunLog {
+    fn open(input: &ContainerInput) -> Self {
+        let log = Self::at(&run_log_path(
+            &input.group_folder,
+            input.session_id.as_deref(),
+            input.correlation_id.as_deref(),
+        ));
+        if let Some(id) = &input.correlation_id {
+            log.write("nuclaw", &format!("run started (ref: {})", id));
+        }
+        log
+    }
+
+    fn at(path: &Path) -> Self {
+        if let Err(e) = rotate_if_large(path, LogRetention::from_env().max_size) {
+            tracing::warn!("{}", e);
+        }
+        let file = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::OpenOptions::new().create(true).append(true).open(path));
+        match file {
+            Ok(file) => Self {
+                file: Some(Arc::new(Mutex::new(file))),
+            },
+            Err(e) => {
+                tracing::warn!("Failed to open run log {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    fn write(&self, stream: &str, line: &str) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(
+                    file,
+                    "{} [{}] {}",
+                    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                    stream,
+                    redact(line)
+                );
+            }
+        }
+    }
+}
+
+/// `s` with everything but ASCII letters, digits, `-`, and `_` replaced,
+/// for use in a file name
+fn file_name_part(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Path of the live log for a group's session, or for a run of
+/// `correlation_id` that has no session yet
+pub fn run_log_path(
+    group_folder: &str,
+    session_id: Option<&str>,
+    correlation_id: Option<&str>,
+) -> PathBuf {
+    let name = match (session_id, correlation_id) {
+        (Some(id), _) => file_name_part(id),
+        (None, Some(id)) => format!("new-{}", file_name_part(id)),
+        (None, None) => format!("new-{}", chrono::Utc::now().format("%Y%m%d_%H%M%S")),
+    };
+    logs_dir().join(group_folder).join(format!("{}.log", name))
+}
+
+/// Record a run's output at
+/// `logs/<group>/container_<session>_<time>[_<correlation ID>].log`
 pub fn log_container_output(
     group_folder: &str,
     session_id: &str,
+    correlation_id: Option<&str>,
     output: &ContainerOutput,
 ) -> Result<()> {
     let log_dir = logs_dir().join(group_folder);
@@ -342,11 +1496,18 @@ pub fn log_container_output(
         message: format!("Failed to create log directory: {}", e),
     })?;
     let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
-    let log_path = log_dir.join(format!("container_{}_{}.log", session_id, timestamp));
+    let suffix = correlation_id
+        .map(|id| format!("_{}", file_name_part(id)))
+        .unwrap_or_default();
+    let log_path = log_dir.join(format!(
+        "container_{}_{}{}.log",
+        session_id, timestamp, suffix
+    ));
     let log_data = serde_json::json!({
         "timestamp": chrono::Utc::now().to_rfc3339(),
         "group_folder": group_folder,
         "session_id": session_id,
+        "correlation_id": correlation_id,
         "status": output.status,
         "result": output.result,
         "error": output.error,
@@ -354,7 +1515,7 @@ pub fn log_container_output(
     });
     fs::write(
         &log_path,
-        serde_json::to_string_pretty(&log_data).unwrap_or_default(),
+        redact(&serde_json::to_string_pretty(&log_data).unwrap_or_default()).as_ref(),
     )
     .map_err(|e| NuClawError::FileSystem {
         message: format!("Failed to write log file: {}", e),
@@ -365,6 +1526,305 @@ pub fn log_container_output(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::AgentPermissions;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_resource_limit_args() {
+        let config = ContainerConfig {
+            memory: Some("512m".to_string()),
+            cpus: Some("0.5".to_string()),
+            pids_limit: Some(64),
+            ..Default::default()
+        };
+        assert_eq!(
+            resource_limit_args(&config),
+            vec!["--memory", "512m", "--cpus", "0.5", "--pids-limit", "64"]
+        );
+
+        let unlimited = ContainerConfig {
+            memory: Some(String::new()),
+            cpus: Some(" ".to_string()),
+            pids_limit: Some(0),
+            ..Default::default()
+        };
+        assert!(resource_limit_args(&unlimited).is_empty());
+    }
+
+    #[test]
+    fn test_network_args() {
+        let none = ContainerConfig {
+            network: Some("none".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(network_args(&none), vec!["--network", "none"]);
+        let default = ContainerConfig {
+            network: Some(" ".to_string()),
+            ..Default::default()
+        };
+        assert!(network_args(&default).is_empty());
+
+        // An agent without network permission gets none, whatever the group
+        let offline = ContainerConfig {
+            network: Some("bridge".to_string()),
+            permissions: Some(AgentPermissions {
+                network_allowed: Some(false),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(network_args(&offline), vec!["--network", "none"]);
+    }
+
+    #[test]
+    fn test_group_config_is_not_mounted() {
+        let folder = "test_config_mount_group";
+        let input = ContainerInput {
+            prompt: "hi".to_string(),
+            session_id: None,
+            resume_session: false,
+            group_folder: folder.to_string(),
+            chat_jid: "test@chat".to_string(),
+            is_main: false,
+            is_scheduled_task: false,
+            context: vec![],
+            reply_to_id: None,
+            quoted_content: None,
+            memories: vec![],
+            timeout: None,
+            sender: None,
+            correlation_id: None,
+            permissions: None,
+        };
+        let group_dir = groups_dir().join(folder);
+        let config_path = crate::group_config::group_config_path(folder);
+        let writable = ContainerConfig {
+            permissions: Some(AgentPermissions {
+                writable_paths: Some(vec!["/workspace/group/notes".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        for config in [ContainerConfig::default(), writable] {
+            let args = container_setup_args(&input, &group_dir, &config).unwrap();
+            // The agent must not be able to change its own group's settings
+            for mount in args.windows(2).filter(|pair| pair[0] == "-v") {
+                let host = mount[1].split(':').next().unwrap();
+                assert!(
+                    !config_path.starts_with(host),
+                    "{} exposes {}",
+                    mount[1],
+                    config_path.display()
+                );
+            }
+        }
+
+        let _ = fs::remove_dir_all(&group_dir);
+        let _ = fs::remove_dir_all(create_group_ipc_directory(folder).unwrap());
+    }
+
+    #[test]
+    fn test_isolation_args() {
+        let gvisor = ContainerConfig {
+            isolation: Some(Isolation::Gvisor),
+            ..Default::default()
+        };
+        assert_eq!(isolation_args(&gvisor).unwrap(), vec!["--runtime=runsc"]);
+
+        let docker = ContainerConfig {
+            isolation: Some(Isolation::Docker),
+            ..Default::default()
+        };
+        assert!(isolation_args(&docker).unwrap().is_empty());
+
+        let config: ContainerConfig =
+            serde_json::from_str(r#"{"isolation": "firecracker"}"#).unwrap();
+        assert_eq!(config.isolation, Some(Isolation::Firecracker));
+        assert_eq!(Isolation::parse("runsc"), Some(Isolation::Gvisor));
+        assert_eq!(Isolation::parse("gvsor"), None);
+    }
+
+    #[test]
+    fn test_container_backend_per_group() {
+        let wasm = ContainerConfig {
+            runner: Some(ContainerBackend::Wasm),
+            ..Default::default()
+        };
+        assert_eq!(container_backend(&wasm).unwrap(), ContainerBackend::Wasm);
+        assert_eq!(
+            ContainerBackend::parse("wasmtime"),
+            Some(ContainerBackend::Wasm)
+        );
+        assert_eq!(ContainerBackend::parse("podman"), None);
+    }
+
+    #[test]
+    fn test_forwarded_env() {
+        // PATH and HOME are set on any test host
+        let config = ContainerConfig {
+            env_allowlist: Some(vec![
+                "PATH".to_string(),
+                "HOME".to_string(),
+                "NUCLAW_TEST_UNSET_VAR".to_string(),
+            ]),
+            env_denylist: vec!["HOME".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(forwarded_env(&config).unwrap(), vec!["PATH"]);
+
+        let invalid = ContainerConfig {
+            env_denylist: vec!["BAD NAME".to_string()],
+            ..Default::default()
+        };
+        assert!(forwarded_env(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_cache_volume_args() {
+        let group = ContainerConfig {
+            cache: Some(CacheScope::Group),
+            ..Default::default()
+        };
+        let args = cache_volume_args(&group, "family chat").unwrap();
+        assert_eq!(
+            args[..2],
+            ["-v", "nuclaw-cache-family-chat:/workspace/cache"]
+        );
+        assert!(args.contains(&"npm_config_cache=/workspace/cache/npm".to_string()));
+
+        let shared = ContainerConfig {
+            cache: Some(CacheScope::Shared),
+            ..Default::default()
+        };
+        assert_eq!(
+            cache_volume_args(&shared, "work").unwrap()[1],
+            "nuclaw-cache-shared:/workspace/cache"
+        );
+
+        let none = ContainerConfig {
+            cache: Some(CacheScope::None),
+            ..Default::default()
+        };
+        assert!(cache_volume_args(&none, "work").unwrap().is_empty());
+
+        let read_only = ContainerConfig {
+            permissions: Some(AgentPermissions {
+                writable_paths: Some(vec!["/workspace/group/notes".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(mount_mode(&read_only, GROUP_MOUNT), ":ro");
+        assert_eq!(mount_mode(&read_only, "/workspace/group/notes/a.md"), "");
+        assert_eq!(
+            cache_volume_args(&read_only, "work").unwrap()[1],
+            "nuclaw-cache-work:/workspace/cache:ro"
+        );
+    }
+
+    #[test]
+    fn test_retry_delay_backs_off() {
+        let policy = RetryPolicy {
+            retries: 10,
+            base_delay: Duration::from_millis(500),
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_millis(1000));
+        assert_eq!(policy.delay(3), Duration::from_millis(2000));
+        assert_eq!(policy.delay(40), Duration::from_millis(MAX_RETRY_DELAY_MS));
+        assert_eq!(policy.with_retries(0).retries, 0);
+    }
+
+    #[test]
+    fn test_only_infrastructure_errors_are_retried() {
+        assert!(is_infrastructure_error(&NuClawError::Container {
+            message: "Failed to spawn container".to_string()
+        }));
+        assert!(!is_infrastructure_error(&NuClawError::Validation {
+            message: "Mount rejected".to_string()
+        }));
+    }
+
+    #[test]
+    fn test_agent_launch_args() {
+        let config = ContainerConfig {
+            image: Some("ghcr.io/example/agent-python:1".to_string()),
+            entrypoint: Some("/opt/agent/run".to_string()),
+            env: BTreeMap::from([(
+                "PIP_INDEX_URL".to_string(),
+                "https://pypi.example".to_string(),
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            agent_launch_args(&config).unwrap(),
+            vec![
+                "-e",
+                "PIP_INDEX_URL=https://pypi.example",
+                "--entrypoint",
+                "/opt/agent/run",
+                "ghcr.io/example/agent-python:1",
+            ]
+        );
+
+        // Without an entrypoint the bundled CLI runs, reading stdin
+        let default_args = agent_launch_args(&ContainerConfig::default()).unwrap();
+        assert_eq!(default_args[..2], ["--entrypoint", DEFAULT_AGENT_COMMAND]);
+        assert_eq!(default_args.len(), 3);
+
+        let limited = ContainerConfig {
+            permissions: Some(AgentPermissions {
+                allowed_tools: Some(vec!["Read".to_string(), "Grep".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            agent_launch_args(&limited).unwrap()[3..],
+            ["--allowedTools", "Read,Grep"]
+        );
+
+        let invalid = ContainerConfig {
+            env: BTreeMap::from([("BAD KEY".to_string(), "x".to_string())]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            agent_launch_args(&invalid),
+            Err(NuClawError::Validation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_resume_args() {
+        let mut input: ContainerInput = serde_json::from_value(serde_json::json!({
+            "prompt": "And tomorrow?",
+            "session_id": "sess_123",
+            "group_folder": "family",
+            "chat_jid": "family@g.us",
+            "is_main": true,
+            "is_scheduled_task": false,
+        }))
+        .unwrap();
+        let bundled = ContainerConfig::default();
+        // A session ID that only names the run is not resumed
+        assert!(resume_args(&bundled, &input).is_empty());
+
+        input.resume_session = true;
+        assert_eq!(resume_args(&bundled, &input), ["--resume", "sess_123"]);
+        let launch = [
+            agent_launch_args(&bundled).unwrap(),
+            resume_args(&bundled, &input),
+        ]
+        .concat();
+        assert_eq!(launch[launch.len() - 2..], ["--resume", "sess_123"]);
+
+        // A custom entrypoint reads the session from its input instead
+        let custom = ContainerConfig {
+            entrypoint: Some("/opt/agent/run".to_string()),
+            ..Default::default()
+        };
+        assert!(resume_args(&custom, &input).is_empty());
+    }
 
     #[test]
     fn test_parse_marked_output() {
@@ -377,6 +1837,240 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_output_is_returned_at_end_marker() {
+        let script = format!(
+            "echo thinking; echo '{}'; echo '{}'; echo '{}'; sleep 60",
+            OUTPUT_START_MARKER,
+            r#"{"status":"success","result":"done","new_session_id":null,"error":null}"#,
+            OUTPUT_END_MARKER
+        );
+        let mut cmd = AsyncCommand::new("sh");
+        cmd.arg("-c")
+            .arg(script)
+            .stdout(std::process::Stdio::piped());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let started = Instant::now();
+        let mut measurements = RunMeasurements::default();
+        let output = run_container_with_output(
+            &mut cmd,
+            vec![],
+            Duration::from_secs(30),
+            Some(tx),
+            &RunContext::default(),
+            &mut measurements,
+        )
+        .await
+        .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(30));
+        assert_eq!(output.result, Some("done".to_string()));
+        assert!(measurements.output_bytes > 0);
+        assert!(!measurements.timed_out);
+        assert_eq!(rx.recv().await, Some("thinking".to_string()));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_runs_receive_their_own_input() {
+        // Echoes the prompt it reads from stdin as the result
+        let script = format!(
+            r#"prompt=$(sed 's/.*"prompt":"\([^"]*\)".*/\1/'); echo '{}'; echo "{{\"status\":\"success\",\"result\":\"$prompt\"}}"; echo '{}'"#,
+            OUTPUT_START_MARKER, OUTPUT_END_MARKER
+        );
+        let run = |prompt: &str| {
+            let input = ContainerInput {
+                prompt: prompt.to_string(),
+                session_id: None,
+                resume_session: false,
+                group_folder: "main".to_string(),
+                chat_jid: "chat".to_string(),
+                is_main: false,
+                is_scheduled_task: false,
+                context: vec![],
+                reply_to_id: None,
+                quoted_content: None,
+                memories: vec![],
+                timeout: None,
+                sender: None,
+                correlation_id: None,
+                permissions: None,
+            };
+            let script = script.clone();
+            async move {
+                let mut cmd = AsyncCommand::new("sh");
+                cmd.arg("-c")
+                    .arg(script)
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped());
+                let input_json = serde_json::to_vec(&input).unwrap();
+                run_container_with_output(
+                    &mut cmd,
+                    input_json,
+                    Duration::from_secs(30),
+                    None,
+                    &RunContext::default(),
+                    &mut RunMeasurements::default(),
+                )
+                .await
+                .unwrap()
+                .result
+            }
+        };
+
+        let results = tokio::join!(run("alpha"), run("beta"), run("gamma"));
+        assert_eq!(
+            results,
+            (
+                Some("alpha".to_string()),
+                Some("beta".to_string()),
+                Some("gamma".to_string())
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_log_streams_stdout_and_stderr() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let log_path = dir.path().join("family/sess_1.log");
+        let mut cmd = AsyncCommand::new("sh");
+        cmd.arg("-c")
+            .arg(format!(
+                "echo working; echo oops >&2; echo '{}'; echo '{{\"status\":\"success\"}}'; echo '{}'",
+                OUTPUT_START_MARKER, OUTPUT_END_MARKER
+            ))
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+
+        run_container_with_output(
+            &mut cmd,
+            vec![],
+            Duration::from_secs(30),
+            None,
+            &RunContext {
+                log: RunLog::at(&log_path),
+                ..Default::default()
+            },
+            &mut RunMeasurements::default(),
+        )
+        .await
+        .unwrap();
+        // stderr is drained on its own task
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert!(log.contains("[stdout] working"));
+        assert!(log.contains("[stderr] oops"));
+        assert!(run_log_path("family", Some("../x"), None).ends_with("family/___x.log"));
+        assert!(run_log_path("family", None, Some("abc12345")).ends_with("family/new-abc12345.log"));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_run_is_killed() {
+        let input = ContainerInput {
+            prompt: "long job".to_string(),
+            session_id: Some("scheduled_cancel_test".to_string()),
+            resume_session: false,
+            group_folder: "cancel_test".to_string(),
+            chat_jid: "cancel_test@g.us".to_string(),
+            is_main: false,
+            is_scheduled_task: true,
+            context: vec![],
+            reply_to_id: None,
+            quoted_content: None,
+            memories: vec![],
+            timeout: None,
+            sender: None,
+            correlation_id: None,
+            permissions: None,
+        };
+        let guard = RunGuard::register(&input);
+        let run = RunContext {
+            cancel: guard.cancel.clone(),
+            ..Default::default()
+        };
+        assert_eq!(cancel_session("scheduled_other"), 0);
+        assert_eq!(cancel_session("scheduled_cancel_test"), 1);
+
+        let mut cmd = AsyncCommand::new("sh");
+        cmd.arg("-c")
+            .arg("sleep 30")
+            .stdout(std::process::Stdio::piped());
+        let started = Instant::now();
+        let mut measurements = RunMeasurements::default();
+        let result = run_container_with_output(
+            &mut cmd,
+            vec![],
+            Duration::from_secs(60),
+            None,
+            &run,
+            &mut measurements,
+        )
+        .await;
+
+        assert!(matches!(result, Err(NuClawError::Cancelled { .. })));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(
+            measurements.record(&input, &result).status,
+            status::CANCELLED
+        );
+        drop(guard);
+        assert_eq!(cancel_chat("cancel_test@g.us"), 0);
+    }
+
+    #[test]
+    fn test_input_files_are_unique() {
+        let first = write_input_file(b"{}").unwrap();
+        let second = write_input_file(b"{}").unwrap();
+        assert_ne!(first, second);
+        let _ = fs::remove_file(first);
+        let _ = fs::remove_file(second);
+    }
+
+    #[tokio::test]
+    async fn test_docker_failure_is_an_infrastructure_error() {
+        let mut cmd = AsyncCommand::new("sh");
+        cmd.arg("-c")
+            .arg("echo 'Cannot connect to the Docker daemon' >&2; exit 125")
+            .stdout(std::process::Stdio::piped());
+
+        let mut measurements = RunMeasurements::default();
+        let result = run_container_with_output(
+            &mut cmd,
+            vec![],
+            Duration::from_secs(30),
+            None,
+            &RunContext::default(),
+            &mut measurements,
+        )
+        .await;
+        assert!(matches!(&result, Err(e) if is_infrastructure_error(e)));
+        assert_eq!(measurements.exit_code, Some(DOCKER_ERROR_EXIT_CODE));
+
+        let input = ContainerInput {
+            prompt: "hi".to_string(),
+            session_id: None,
+            resume_session: false,
+            group_folder: "main".to_string(),
+            chat_jid: "chat".to_string(),
+            is_main: false,
+            is_scheduled_task: false,
+            context: vec![],
+            reply_to_id: None,
+            quoted_content: None,
+            memories: vec![],
+            timeout: None,
+            sender: None,
+            correlation_id: None,
+            permissions: None,
+        };
+        assert_eq!(
+            measurements.record(&input, &result).status,
+            status::INFRASTRUCTURE_ERROR
+        );
+    }
+
     #[test]
     fn test_extract_marked_output_no_markers() {
         let output = "No markers here";
@@ -583,10 +2277,19 @@ mod tests {
         let input = ContainerInput {
             prompt: "test prompt".to_string(),
             session_id: Some("test_session".to_string()),
+            resume_session: false,
             group_folder: "test_ipc_group".to_string(),
             chat_jid: "test@chat".to_string(),
             is_main: true,
             is_scheduled_task: false,
+            context: vec![],
+            reply_to_id: None,
+            quoted_content: None,
+            memories: vec![],
+            timeout: None,
+            sender: None,
+            correlation_id: None,
+            permissions: None,
         };
 
         let result = write_ipc_files("test_ipc_group", &input);
@@ -608,15 +2311,24 @@ mod tests {
             status: "success".to_string(),
             result: Some("test result".to_string()),
             new_session_id: Some("sess_123".to_string()),
+            usage: None,
             error: None,
         };
 
-        let result = log_container_output("test_log_group", "test_session", &output);
+        let result =
+            log_container_output("test_log_group", "test_session", Some("abc12345"), &output);
         assert!(result.is_ok());
 
         // Verify log file was created
         let log_dir = logs_dir().join("test_log_group");
         assert!(log_dir.exists());
+        let name = fs::read_dir(&log_dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .file_name();
+        assert!(name.to_string_lossy().ends_with("_abc12345.log"));
 
         // Cleanup
         let _ = fs::remove_dir_all(&log_dir);
@@ -628,14 +2340,71 @@ mod tests {
             status: "error".to_string(),
             result: None,
             new_session_id: None,
+            usage: None,
             error: Some("test error".to_string()),
         };
 
-        let result = log_container_output("test_log_error_group", "test_session", &output);
+        let result = log_container_output("test_log_error_group", "test_session", None, &output);
         assert!(result.is_ok());
 
         // Cleanup
         let log_dir = logs_dir().join("test_log_error_group");
         let _ = fs::remove_dir_all(&log_dir);
     }
+
+    #[tokio::test]
+    async fn test_container_limiter_queues_runs() {
+        let limiter = std::sync::Arc::new(ContainerLimiter::new(1));
+        let first = limiter.acquire().await;
+        assert!(limiter.is_saturated());
+
+        let waiter = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                let _slot = limiter.acquire().await;
+            })
+        };
+        while limiter.waiting() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        drop(first);
+        timeout(Duration::from_secs(5), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(limiter.waiting(), 0);
+        assert!(!limiter.is_saturated());
+    }
+
+    #[test]
+    fn test_run_guard_tracks_running_containers() {
+        let input = ContainerInput {
+            prompt: "hi".to_string(),
+            session_id: None,
+            resume_session: false,
+            group_folder: "guard-test".to_string(),
+            chat_jid: "guard@g.us".to_string(),
+            is_main: false,
+            is_scheduled_task: false,
+            context: vec![],
+            reply_to_id: None,
+            quoted_content: None,
+            memories: vec![],
+            timeout: None,
+            sender: None,
+            correlation_id: None,
+            permissions: None,
+        };
+        let is_listed = || {
+            running_containers()
+                .iter()
+                .any(|c| c.group_folder == "guard-test")
+        };
+
+        let guard = RunGuard::register(&input);
+        assert!(is_listed());
+        drop(guard);
+        assert!(!is_listed());
+    }
 }
@@ -0,0 +1,80 @@
+//! Conversation Context for NuClaw
+//!
+//! Agent runs receive the last `CONTEXT_MESSAGES` messages stored for the
+//! chat alongside the triggered prompt, so follow-ups like "what do you
+//! think about that?" make sense to the agent. A group's config file can
+//! set its own `context_messages` (see `group_config`).
+
+use crate::db::Database;
+use crate::group_config::group_settings;
+use crate::types::{ContextMessage, NewMessage};
+use tracing::warn;
+
+/// Default number of earlier messages passed to the agent
+const DEFAULT_CONTEXT_MESSAGES: usize = 20;
+
+fn context_messages() -> usize {
+    std::env::var("CONTEXT_MESSAGES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_CONTEXT_MESSAGES)
+}
+
+/// Messages preceding `msg` in its chat, oldest first
+///
+/// Failures are logged and yield no context rather than failing the run.
+pub fn conversation_context(
+    db: &Database,
+    msg: &NewMessage,
+    group_folder: &str,
+) -> Vec<ContextMessage> {
+    let limit = group_settings(group_folder)
+        .context_messages
+        .unwrap_or_else(context_messages);
+    db.messages()
+        .recent(&msg.chat_jid, &msg.id, limit)
+        .unwrap_or_else(|e| {
+            warn!("Failed to load context for {}: {}", msg.chat_jid, e);
+            vec![]
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    fn message(id: &str, chat_jid: &str, content: &str, timestamp: &str) -> NewMessage {
+        NewMessage {
+            id: id.to_string(),
+            chat_jid: chat_jid.to_string(),
+            sender: "u1".to_string(),
+            sender_name: "Alice".to_string(),
+            content: content.to_string(),
+            timestamp: timestamp.to_string(),
+            reply_to_id: None,
+            quoted_content: None,
+            chat_name: None,
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn test_conversation_context() {
+        let (db, _dir) = test_database();
+        for msg in [
+            message("1", "chat", "first", "1700000001"),
+            message("2", "chat", "second", "1700000002"),
+            message("3", "other", "elsewhere", "1700000003"),
+        ] {
+            db.messages().store(&msg, false).unwrap();
+        }
+        let trigger = message("4", "chat", "@Andy summarize", "1700000004");
+        db.messages().store(&trigger, false).unwrap();
+
+        let context = conversation_context(&db, &trigger, "family");
+        let contents: Vec<_> = context.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["first", "second"]);
+        assert_eq!(context[0].sender_name, "Alice");
+    }
+}
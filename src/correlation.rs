@@ -0,0 +1,55 @@
+//! Correlation IDs for NuClaw
+//!
+//! Each accepted message, and each scheduled task run, gets a short
+//! random ID that follows it through the pipeline:
+//!
+//! - log lines written while handling it carry `correlation_id=<id>`
+//! - the agent receives it as `correlation_id` in its input
+//! - its run log notes it, and log files created for it are named after it
+//! - error replies end with `(ref: <id>)`, so users can quote it when
+//!   reporting a problem
+//!
+//! The ID of a message is stored with it in the pending queue, so a run
+//! replayed after a restart keeps it.
+
+use tracing::Span;
+
+/// A new correlation ID: 8 hex digits
+pub fn new_correlation_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..8].to_string()
+}
+
+/// Span whose events carry the correlation ID
+///
+/// It is an error-level span, so it stays enabled, and its ID shows up on
+/// errors, under the quietest log filter.
+pub fn correlation_span(id: &str) -> Span {
+    tracing::error_span!("request", correlation_id = %id)
+}
+
+/// Text of an error reply with the correlation ID to quote
+pub fn with_ref(text: &str, id: Option<&str>) -> String {
+    match id {
+        Some(id) => format!("{} (ref: {})", text, id),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_correlation_id() {
+        let id = new_correlation_id();
+        assert_eq!(id.len(), 8);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(id, new_correlation_id());
+
+        assert_eq!(
+            with_ref("Sorry, the request timed out.", Some("abc12345")),
+            "Sorry, the request timed out. (ref: abc12345)"
+        );
+        assert_eq!(with_ref("Error", None), "Error");
+    }
+}
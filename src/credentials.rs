@@ -0,0 +1,476 @@
+//! Credential Store for NuClaw
+//!
+//! Channel credentials such as `TELEGRAM_BOT_TOKEN`,
+//! `TELEGRAM_WEBHOOK_SECRET`, or `ADMIN_API_TOKENS` can be kept encrypted
+//! in `credentials.enc` in the store directory instead of in plain text in
+//! `.env` or the config file. The store holds setting names and values,
+//! sealed with AES-256-GCM under a master key taken from, in this order:
+//!
+//! - `NUCLAW_MASTER_KEY`, 64 hex characters
+//! - the file named by `NUCLAW_MASTER_KEY_FILE`, e.g. a Docker secret
+//! - `nuclaw-master-key` in the credentials directory systemd passes to a
+//!   unit with `LoadCredential=`
+//!
+//! At startup the stored values are decrypted into the environment,
+//! except for settings the environment or the config file already set,
+//! so they are used like any other setting. `nuclaw credentials` creates
+//! the key and manages the entries.
+//!
+//! The WhatsApp session the MCP server keeps in `store/auth/` can be sealed
+//! under the same key into `auth.enc` with `nuclaw credentials seal-auth`.
+//! `nuclaw serve` then decrypts it into a directory only its user can read
+//! in `$XDG_RUNTIME_DIR` (a tmpfs under systemd), and refuses to start
+//! without one rather than leave the session in plain text on disk. On
+//! shutdown it seals the session again, so changes the MCP server made are
+//! kept, and deletes the decrypted files.
+
+use crate::config::store_dir;
+use crate::error::{NuClawError, Result};
+use crate::settings::{is_secret, record_source, Source, SETTINGS};
+use crate::utils::json::save_json;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// File of the store, in the store directory
+pub const CREDENTIALS_FILE: &str = "credentials.enc";
+/// Name of the master key among the systemd credentials
+const SYSTEMD_CREDENTIAL: &str = "nuclaw-master-key";
+/// Format of the store file
+const STORE_VERSION: u32 = 1;
+/// File of the sealed WhatsApp session, in the store directory
+pub const AUTH_FILE: &str = "auth.enc";
+
+/// Path of the store
+pub fn credentials_path() -> PathBuf {
+    store_dir().join(CREDENTIALS_FILE)
+}
+
+/// Key the store is sealed with
+#[derive(Clone, PartialEq, Eq)]
+pub struct MasterKey([u8; 32]);
+
+impl MasterKey {
+    /// A new random key
+    pub fn generate() -> Self {
+        Self(Aes256Gcm::generate_key(OsRng).into())
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        hex::decode(s.trim()).ok()?.try_into().ok().map(Self)
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0))
+    }
+}
+
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MasterKey(***)")
+    }
+}
+
+/// The configured master key, and where it came from; `None` if there is
+/// none
+pub fn master_key() -> Result<Option<(MasterKey, String)>> {
+    let invalid = |source: &str| NuClawError::Config {
+        message: format!("{} must hold a key of 64 hex characters", source),
+    };
+    let env = |var: &str| {
+        std::env::var(var)
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    if let Some(key) = env("NUCLAW_MASTER_KEY") {
+        let key = MasterKey::parse(&key).ok_or_else(|| invalid("NUCLAW_MASTER_KEY"))?;
+        return Ok(Some((key, "NUCLAW_MASTER_KEY".to_string())));
+    }
+    let file = env("NUCLAW_MASTER_KEY_FILE")
+        .map(PathBuf::from)
+        .or_else(|| {
+            env("CREDENTIALS_DIRECTORY")
+                .map(|dir| Path::new(&dir).join(SYSTEMD_CREDENTIAL))
+                .filter(|path| path.exists())
+        });
+    let Some(file) = file else {
+        return Ok(None);
+    };
+    let contents = std::fs::read_to_string(&file).map_err(|e| NuClawError::Config {
+        message: format!("Failed to read the master key {}: {}", file.display(), e),
+    })?;
+    let source = file.display().to_string();
+    let key = MasterKey::parse(&contents).ok_or_else(|| invalid(&source))?;
+    Ok(Some((key, source)))
+}
+
+/// The store file: the sealed entries and the nonce they were sealed with
+#[derive(Debug, Serialize, Deserialize)]
+struct SealedStore {
+    version: u32,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Entries of the store, by setting name
+#[derive(Debug)]
+pub struct CredentialStore {
+    path: PathBuf,
+    key: MasterKey,
+    entries: BTreeMap<String, String>,
+}
+
+impl CredentialStore {
+    /// Open the store at `path`, empty if there is no file yet
+    pub fn open(path: &Path, key: MasterKey) -> Result<Self> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(contents) => unseal(path, &key, &contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(NuClawError::Config {
+                    message: format!("Failed to read {}: {}", path.display(), e),
+                })
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            key,
+            entries,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &BTreeMap<String, String> {
+        &self.entries
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.get(name).map(String::as_str)
+    }
+
+    /// Store a credential setting's value
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let name = name.trim().to_ascii_uppercase();
+        if !SETTINGS.iter().any(|s| s.var == name)
+            || !is_secret(&name)
+            || name.starts_with("NUCLAW_MASTER_KEY")
+        {
+            return Err(NuClawError::Validation {
+                message: format!(
+                    "{} is not a credential setting; see `nuclaw config show`",
+                    name
+                ),
+            });
+        }
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(NuClawError::Validation {
+                message: format!("The value of {} is empty", name),
+            });
+        }
+        self.entries.insert(name, value.to_string());
+        Ok(())
+    }
+
+    /// Remove a credential; returns `false` if it was not stored
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries
+            .remove(&name.trim().to_ascii_uppercase())
+            .is_some()
+    }
+
+    /// Seal the entries with a fresh nonce and write them out
+    pub fn save(&self) -> Result<()> {
+        seal(&self.path, &self.key, &self.entries)
+    }
+}
+
+/// Seal `entries` under `key` with a fresh nonce and write them to `path`
+fn seal(path: &Path, key: &MasterKey, entries: &BTreeMap<String, String>) -> Result<()> {
+    let plaintext = serde_json::to_vec(entries).expect("string maps serialize");
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher()
+        .encrypt(&nonce, plaintext.as_slice())
+        .map_err(|_| NuClawError::Config {
+            message: format!("Failed to encrypt {}", path.display()),
+        })?;
+    let sealed = SealedStore {
+        version: STORE_VERSION,
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    };
+    save_json(path, &sealed).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to write {}: {}", path.display(), e),
+    })
+}
+
+fn unseal(path: &Path, key: &MasterKey, contents: &str) -> Result<BTreeMap<String, String>> {
+    let invalid = |message: &str| NuClawError::Config {
+        message: format!("Invalid {}: {}", path.display(), message),
+    };
+    let sealed: SealedStore =
+        serde_json::from_str(contents).map_err(|e| invalid(&e.to_string()))?;
+    if sealed.version != STORE_VERSION {
+        return Err(invalid(&format!("unknown version {}", sealed.version)));
+    }
+    let nonce = hex::decode(&sealed.nonce)
+        .ok()
+        .filter(|nonce| nonce.len() == 12)
+        .ok_or_else(|| invalid("malformed nonce"))?;
+    let ciphertext = hex::decode(&sealed.ciphertext).map_err(|_| invalid("malformed data"))?;
+    let plaintext = key
+        .cipher()
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| NuClawError::Config {
+            message: format!(
+                "Failed to decrypt {}: wrong master key, or the file was changed",
+                path.display()
+            ),
+        })?;
+    serde_json::from_slice(&plaintext).map_err(|e| invalid(&e.to_string()))
+}
+
+/// Put the stored credentials into the environment, except those already
+/// set; returns the store's path if there is one
+///
+/// Runs at startup, after the config file is read.
+pub fn load_credentials() -> Result<Option<PathBuf>> {
+    let path = credentials_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let store = CredentialStore::open(&path, required_master_key(&path)?)?;
+    for (name, value) in store.entries() {
+        if std::env::var_os(name).is_none() {
+            std::env::set_var(name, value);
+            record_source(name, Source::File(path.clone()));
+        }
+    }
+    Ok(Some(path))
+}
+
+/// Path of the sealed WhatsApp session
+pub fn sealed_auth_path() -> PathBuf {
+    store_dir().join(AUTH_FILE)
+}
+
+/// Directory the sealed WhatsApp session is decrypted into:
+/// `nuclaw/auth` in `$XDG_RUNTIME_DIR`, or `None` if that is not set
+fn runtime_auth_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("nuclaw").join("auth"))
+}
+
+/// Directory of the WhatsApp session: `store/auth/`, or once the session
+/// is sealed, `nuclaw/auth` in `$XDG_RUNTIME_DIR`
+pub fn auth_dir() -> PathBuf {
+    match runtime_auth_dir() {
+        Some(dir) if sealed_auth_path().exists() => dir,
+        _ => store_dir().join("auth"),
+    }
+}
+
+/// Seal the files of the session in `dir` into `path` and delete `dir`;
+/// returns how many files there were
+pub fn seal_auth(dir: &Path, path: &Path, key: &MasterKey) -> Result<usize> {
+    let fs_error = |e: std::io::Error| NuClawError::FileSystem {
+        message: format!("Failed to read {}: {}", dir.display(), e),
+    };
+    let mut files = BTreeMap::new();
+    for entry in std::fs::read_dir(dir).map_err(fs_error)? {
+        let entry = entry.map_err(fs_error)?;
+        if !entry.file_type().map_err(fs_error)?.is_file() {
+            continue;
+        }
+        let contents = std::fs::read(entry.path()).map_err(fs_error)?;
+        files.insert(
+            entry.file_name().to_string_lossy().into_owned(),
+            hex::encode(contents),
+        );
+    }
+    seal(path, key, &files)?;
+    std::fs::remove_dir_all(dir).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to delete {}: {}", dir.display(), e),
+    })?;
+    Ok(files.len())
+}
+
+/// Decrypt the session sealed in `path` into `dir`, readable by the
+/// current user only; returns how many files there were
+pub fn unseal_auth(path: &Path, key: &MasterKey, dir: &Path) -> Result<usize> {
+    let contents = std::fs::read_to_string(path).map_err(|e| NuClawError::Config {
+        message: format!("Failed to read {}: {}", path.display(), e),
+    })?;
+    let files = unseal(path, key, &contents)?;
+    let fs_error = |e: std::io::Error| NuClawError::FileSystem {
+        message: format!("Failed to write {}: {}", dir.display(), e),
+    };
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir).map_err(fs_error)?;
+    // The directory may be left from a run that did not stop cleanly
+    #[cfg(unix)]
+    std::fs::set_permissions(dir, std::os::unix::fs::PermissionsExt::from_mode(0o700))
+        .map_err(fs_error)?;
+    for (name, contents) in &files {
+        let name = Path::new(name)
+            .file_name()
+            .filter(|file_name| *file_name == name.as_str())
+            .ok_or_else(|| NuClawError::Config {
+                message: format!("Invalid {}: bad file name {}", path.display(), name),
+            })?;
+        let contents = hex::decode(contents).map_err(|_| NuClawError::Config {
+            message: format!("Invalid {}: malformed data", path.display()),
+        })?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        std::io::Write::write_all(
+            &mut options.open(dir.join(name)).map_err(fs_error)?,
+            &contents,
+        )
+        .map_err(fs_error)?;
+    }
+    Ok(files.len())
+}
+
+/// The master key, which a sealed file at `path` cannot be used without
+fn required_master_key(path: &Path) -> Result<MasterKey> {
+    match master_key()? {
+        Some((key, _)) => Ok(key),
+        None => Err(NuClawError::Config {
+            message: format!(
+                "{} needs the master key; set NUCLAW_MASTER_KEY or NUCLAW_MASTER_KEY_FILE",
+                path.display()
+            ),
+        }),
+    }
+}
+
+/// Decrypt the sealed WhatsApp session into `auth_dir()`; returns the
+/// directory if there is a sealed session
+///
+/// Runs when `nuclaw serve` starts, and fails without `$XDG_RUNTIME_DIR`.
+pub fn load_auth() -> Result<Option<PathBuf>> {
+    let path = sealed_auth_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let dir = runtime_auth_dir().ok_or_else(|| NuClawError::Config {
+        message: format!(
+            "{} is only decrypted into $XDG_RUNTIME_DIR, which is not set; \
+             set it to a directory only NuClaw's user can read, e.g. a tmpfs",
+            path.display()
+        ),
+    })?;
+    unseal_auth(&path, &required_master_key(&path)?, &dir)?;
+    Ok(Some(dir))
+}
+
+/// Seal the WhatsApp session in `auth_dir()` again and delete the
+/// decrypted files, if it was sealed
+///
+/// Runs when `nuclaw serve` stops.
+pub fn save_auth() -> Result<()> {
+    let path = sealed_auth_path();
+    if !path.exists() {
+        return Ok(());
+    }
+    seal_auth(&auth_dir(), &path, &required_master_key(&path)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credential_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CREDENTIALS_FILE);
+        let key = MasterKey::generate();
+        assert_eq!(MasterKey::parse(&key.to_hex()), Some(key.clone()));
+        assert_eq!(MasterKey::parse("abcd"), None);
+
+        let mut store = CredentialStore::open(&path, key.clone()).unwrap();
+        assert!(store.entries().is_empty());
+        store
+            .set("telegram_bot_token", " 123456:secret-token ")
+            .unwrap();
+        assert!(matches!(
+            store.set("ASSISTANT_NAME", "Andy"),
+            Err(NuClawError::Validation { .. })
+        ));
+        store.save().unwrap();
+
+        // Nothing is readable without the key
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("secret-token"));
+        assert!(!contents.contains("TELEGRAM_BOT_TOKEN"));
+
+        let mut store = CredentialStore::open(&path, key.clone()).unwrap();
+        assert_eq!(store.get("TELEGRAM_BOT_TOKEN"), Some("123456:secret-token"));
+        assert!(matches!(
+            CredentialStore::open(&path, MasterKey::generate()),
+            Err(NuClawError::Config { .. })
+        ));
+
+        assert!(store.remove("TELEGRAM_BOT_TOKEN"));
+        assert!(!store.remove("TELEGRAM_BOT_TOKEN"));
+        store.save().unwrap();
+        assert!(CredentialStore::open(&path, key)
+            .unwrap()
+            .entries()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sealed_auth() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = dir.path().join("auth");
+        std::fs::create_dir(&auth).unwrap();
+        std::fs::write(auth.join("creds.json"), r#"{"noiseKey":"secret-key"}"#).unwrap();
+        std::fs::write(auth.join("pre-key-1.json"), "{}").unwrap();
+        let path = dir.path().join(AUTH_FILE);
+        let key = MasterKey::generate();
+
+        assert_eq!(seal_auth(&auth, &path, &key).unwrap(), 2);
+        assert!(!auth.exists());
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("creds"));
+        assert!(!contents.contains(&hex::encode("secret-key")));
+
+        let runtime = dir.path().join("run").join("auth");
+        assert_eq!(unseal_auth(&path, &key, &runtime).unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(runtime.join("creds.json")).unwrap(),
+            r#"{"noiseKey":"secret-key"}"#
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&runtime), 0o700);
+            assert_eq!(mode(&runtime.join("creds.json")), 0o600);
+        }
+        assert!(matches!(
+            unseal_auth(&path, &MasterKey::generate(), &runtime),
+            Err(NuClawError::Config { .. })
+        ));
+    }
+}
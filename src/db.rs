@@ -1,24 +1,57 @@
 //! Database module for NuClaw
 //!
-//! Provides SQLite database operations with connection pooling.
-//! Uses r2d2 for connection management and rusqlite for SQLite access.
+//! Provides connection pooling over two storage backends: a local SQLite
+//! file (the default, via rusqlite) and PostgreSQL (`DATABASE_URL`, see
+//! `postgres_backend`), both pooled with r2d2.
+//!
+//! SQLite allows one writer at a time, and concurrent writers on separate
+//! connections fail with `SQLITE_BUSY` once the busy timeout runs out. So
+//! SQLite gets one writer connection, which writers queue for in the pool,
+//! and a pool of read-only connections (`PRAGMA query_only`) that read
+//! from the write-ahead log alongside it. `get_connection` hands out the
+//! writer and `read_connection` a reader; the repositories and other
+//! lookups use readers for their queries. On Postgres both come from the
+//! same pool.
+//!
+//! All calls block. Async code goes through `Database::call` or
+//! `blocking`, which run them on tokio's blocking thread pool.
+//!
+//! A `Database` handle belongs to one tenant (see `tenants`); the
+//! tenant-scoped tables carry a `tenant` column and every query filters on
+//! `Database::tenant`. `for_tenant` gives a handle of another tenant over
+//! the same pool.
+//!
+//! Callers use the backend-neutral API of `dyn Storage`: `execute`,
+//! `query_map`, `query_row`, and `query_opt` with `params![...]`, and
+//! `Connection::transaction`. SQL is written with SQLite's `?`
+//! placeholders and kept to what both databases understand (e.g.
+//! `ON CONFLICT ... DO UPDATE` rather than `INSERT OR REPLACE`, and a
+//! `CAST` on parameters that are only compared with NULL so Postgres can
+//! infer their type); the Postgres backend translates the placeholders.
 
 use crate::config::store_dir;
 use crate::error::NuClawError;
+use crate::postgres_backend::PostgresManager;
+use crate::tenants::DEFAULT_TENANT;
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::Connection;
+use std::ops::Deref;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 /// Database configuration
 #[derive(Debug, Clone)]
 pub struct DatabaseConfig {
-    /// Maximum pool size
+    /// Maximum pool size; on SQLite, the writer connection and
+    /// `pool_size - 1` readers
     pub pool_size: u32,
     /// Connection timeout in milliseconds
     pub connection_timeout_ms: u64,
     /// Database file path
     pub db_path: PathBuf,
+    /// `postgres://` URL of a PostgreSQL database to use instead of the
+    /// SQLite file (`DATABASE_URL`)
+    pub database_url: Option<String>,
 }
 
 impl Default for DatabaseConfig {
@@ -33,15 +66,45 @@ impl Default for DatabaseConfig {
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(30000),
             db_path: store_dir().join("nuclaw.db"),
+            database_url: std::env::var("DATABASE_URL")
+                .ok()
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty()),
+        }
+    }
+}
+
+/// Storage backend of a database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Sqlite => "sqlite",
+            Backend::Postgres => "postgres",
         }
     }
 }
 
+#[derive(Clone, Debug)]
+enum DbPool {
+    Sqlite {
+        writer: Pool<SqliteConnectionManager>,
+        readers: Pool<SqliteConnectionManager>,
+    },
+    Postgres(Pool<PostgresManager>),
+}
+
 /// Database wrapper with connection pool
 #[derive(Clone, Debug)]
 pub struct Database {
-    pool: Pool<SqliteConnectionManager>,
+    pool: DbPool,
     config: DatabaseConfig,
+    tenant: Arc<str>,
 }
 
 impl Database {
@@ -52,36 +115,128 @@ impl Database {
 
     /// Create a new Database with custom config
     pub fn with_config(config: DatabaseConfig) -> Result<Self, NuClawError> {
-        let manager = SqliteConnectionManager::file(&config.db_path).with_init(|conn| {
-            conn.pragma_update(None, "foreign_keys", "ON")?;
-            conn.pragma_update(None, "journal_mode", "WAL")?;
-            conn.pragma_update(None, "synchronous", "NORMAL")?;
-            Ok(())
-        });
-
-        let pool = Pool::builder()
-            .max_size(config.pool_size)
+        let pool = match config.database_url.as_deref() {
+            Some(url) if is_postgres_url(url) => {
+                let pool =
+                    Self::build_pool(&config, PostgresManager::new(url)?, config.pool_size, false)?;
+                let conn = Connection(Pooled::Postgres(checkout(&pool)?));
+                // Replicas starting at the same time must not race each
+                // other creating the tables
+                conn.execute("SELECT pg_advisory_lock(?)", [SCHEMA_LOCK_ID])?;
+                let initialized = initialize_schema(&*conn);
+                conn.execute("SELECT pg_advisory_unlock(?)", [SCHEMA_LOCK_ID])?;
+                initialized?;
+                DbPool::Postgres(pool)
+            }
+            Some(_) => {
+                return Err(NuClawError::Config {
+                    message:
+                        "Unsupported DATABASE_URL; use a postgres:// URL, or unset it to use SQLite"
+                            .to_string(),
+                })
+            }
+            None => {
+                let writer = SqliteConnectionManager::file(&config.db_path).with_init(|conn| {
+                    // Takes effect on a new file at once, and on an existing
+                    // one at its next full VACUUM
+                    conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+                    conn.pragma_update(None, "foreign_keys", "ON")?;
+                    conn.pragma_update(None, "journal_mode", "WAL")?;
+                    conn.pragma_update(None, "synchronous", "NORMAL")?;
+                    Ok(())
+                });
+                let writer = Self::build_pool(&config, writer, 1, true)?;
+                initialize_schema(&*Connection(Pooled::Sqlite(checkout(&writer)?)))?;
+
+                // Opened once the writer has created the file and its schema
+                let readers = SqliteConnectionManager::file(&config.db_path)
+                    .with_init(|conn| conn.pragma_update(None, "query_only", "ON"));
+                let readers = Self::build_pool(
+                    &config,
+                    readers,
+                    config.pool_size.saturating_sub(1).max(1),
+                    true,
+                )?;
+                DbPool::Sqlite { writer, readers }
+            }
+        };
+
+        Ok(Database {
+            pool,
+            config,
+            tenant: Arc::from(DEFAULT_TENANT),
+        })
+    }
+
+    fn build_pool<M: r2d2::ManageConnection>(
+        config: &DatabaseConfig,
+        manager: M,
+        max_size: u32,
+        test_on_check_out: bool,
+    ) -> Result<Pool<M>, NuClawError> {
+        Pool::builder()
+            .max_size(max_size)
             .connection_timeout(std::time::Duration::from_millis(
                 config.connection_timeout_ms,
             ))
+            .test_on_check_out(test_on_check_out)
             .build(manager)
             .map_err(|e| NuClawError::Database {
                 message: format!("Failed to create connection pool: {}", e),
-            })?;
+            })
+    }
 
-        let conn = pool.get().map_err(|e| NuClawError::Database {
-            message: format!("Failed to get connection: {}", e),
-        })?;
-        initialize_schema(&conn)?;
+    /// Tenant whose rows this handle reads and writes
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
 
-        Ok(Database { pool, config })
+    /// Handle of another tenant sharing this handle's connection pool
+    pub fn for_tenant(&self, tenant: &str) -> Database {
+        Database {
+            pool: self.pool.clone(),
+            config: self.config.clone(),
+            tenant: Arc::from(tenant),
+        }
     }
 
-    /// Get a connection from the pool
-    pub fn get_connection(&self) -> Result<PooledConnection<SqliteConnectionManager>, NuClawError> {
-        self.pool.get().map_err(|e| NuClawError::Database {
-            message: format!("Failed to get connection from pool: {}", e),
-        })
+    /// Get a connection from the pool that can write
+    ///
+    /// On SQLite this is the single writer connection: holding it blocks
+    /// other writers, so it should not be held while waiting for another.
+    pub fn get_connection(&self) -> Result<Connection, NuClawError> {
+        let pooled = match &self.pool {
+            DbPool::Sqlite { writer, .. } => checkout(writer).map(Pooled::Sqlite),
+            DbPool::Postgres(pool) => checkout(pool).map(Pooled::Postgres),
+        };
+        pooled.map(Connection)
+    }
+
+    /// Get a read-only connection from the pool, for queries
+    pub fn read_connection(&self) -> Result<Connection, NuClawError> {
+        let pooled = match &self.pool {
+            DbPool::Sqlite { readers, .. } => checkout(readers).map(Pooled::Sqlite),
+            DbPool::Postgres(pool) => checkout(pool).map(Pooled::Postgres),
+        };
+        pooled.map(Connection)
+    }
+
+    /// Run database work from async code; see `blocking`
+    pub async fn call<T, F>(&self, f: F) -> Result<T, NuClawError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> Result<T, NuClawError> + Send + 'static,
+    {
+        let db = self.clone();
+        blocking(move || f(&db)).await
+    }
+
+    /// Storage backend in use
+    pub fn backend(&self) -> Backend {
+        match self.pool {
+            DbPool::Sqlite { .. } => Backend::Sqlite,
+            DbPool::Postgres(_) => Backend::Postgres,
+        }
     }
 
     /// Get the database configuration
@@ -89,17 +244,99 @@ impl Database {
         &self.config
     }
 
-    /// Get pool status
+    /// Reclaim the space of deleted rows (`VACUUM` on both backends)
+    pub fn vacuum(&self) -> Result<(), NuClawError> {
+        self.get_connection()?
+            .execute_batch("VACUUM")
+            .map_err(|e| NuClawError::Database {
+                message: format!("Failed to vacuum database: {}", e),
+            })
+    }
+
+    /// Return the free pages of the SQLite file to the filesystem and
+    /// truncate its write-ahead log
+    ///
+    /// Cheap compared to `vacuum`, so it can run often; a checkpoint
+    /// blocked by a reader is retried at the next run. Postgres manages its
+    /// WAL and free space itself (autovacuum), so there it does nothing and
+    /// returns `None`.
+    pub fn checkpoint(&self) -> Result<Option<Checkpoint>, NuClawError> {
+        let conn = self.get_connection()?;
+        if conn.backend() != Backend::Sqlite {
+            return Ok(None);
+        }
+        let failed = |e: NuClawError| NuClawError::Database {
+            message: format!("Failed to checkpoint database: {}", e),
+        };
+        let freed_pages: i64 = conn
+            .query_row("PRAGMA freelist_count", (), |row| row.get(0))
+            .map_err(failed)?;
+        conn.query_values("PRAGMA incremental_vacuum", Vec::new())
+            .map_err(failed)?;
+        let mut wal_path = self.config.db_path.clone().into_os_string();
+        wal_path.push("-wal");
+        let wal_bytes = std::fs::metadata(&wal_path).map_or(0, |m| m.len());
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", (), |row| {
+            Ok(Checkpoint {
+                busy: row.get::<i64>(0)? != 0,
+                wal_bytes,
+                freed_pages: freed_pages.max(0) as u64,
+            })
+        })
+        .map(Some)
+        .map_err(failed)
+    }
+
+    /// Get pool status, over the writer and the readers on SQLite
     pub fn pool_status(&self) -> PoolStatus {
-        let state = self.pool.state();
+        let (states, max_size) = match &self.pool {
+            DbPool::Sqlite { writer, readers } => (
+                vec![writer.state(), readers.state()],
+                writer.max_size() + readers.max_size(),
+            ),
+            DbPool::Postgres(pool) => (vec![pool.state()], pool.max_size()),
+        };
+        let connections: u32 = states.iter().map(|s| s.connections).sum();
+        let idle: u32 = states.iter().map(|s| s.idle_connections).sum();
         PoolStatus {
-            connections_idle: state.idle_connections,
-            connections_active: state.connections - state.idle_connections,
-            max_size: self.config.pool_size,
+            connections_idle: idle,
+            connections_active: connections - idle,
+            max_size,
         }
     }
 }
 
+fn checkout<M: r2d2::ManageConnection>(pool: &Pool<M>) -> Result<PooledConnection<M>, NuClawError> {
+    pool.get().map_err(|e| NuClawError::Database {
+        message: format!("Failed to get connection from pool: {}", e),
+    })
+}
+
+/// Run blocking database work on tokio's blocking thread pool
+///
+/// Queries block their thread while waiting for the pool, a lock, or the
+/// disk. Async code runs them through here (or `Database::call`) so they
+/// never stall the runtime's worker threads.
+pub async fn blocking<T, F>(f: F) -> Result<T, NuClawError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, NuClawError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| NuClawError::Database {
+            message: format!("Database task failed: {}", e),
+        })?
+}
+
+/// Advisory lock held by Postgres replicas while creating the schema
+const SCHEMA_LOCK_ID: i64 = 0x6e75_636c_6177;
+
+/// Whether `url` selects the Postgres backend
+pub fn is_postgres_url(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
 /// Pool status information
 #[derive(Debug, Clone)]
 pub struct PoolStatus {
@@ -108,38 +345,456 @@ pub struct PoolStatus {
     pub max_size: u32,
 }
 
+/// Outcome of `Database::checkpoint`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// A reader kept the log from being fully checkpointed
+    pub busy: bool,
+    /// Size of the write-ahead log before it was truncated
+    pub wal_bytes: u64,
+    /// Free pages returned to the filesystem
+    pub freed_pages: u64,
+}
+
+/// A value bound to or read from a statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Types that can be bound as statement parameters
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+impl ToValue for [u8] {
+    fn to_value(&self) -> Value {
+        Value::Blob(self.to_vec())
+    }
+}
+
+impl ToValue for Vec<u8> {
+    fn to_value(&self) -> Value {
+        Value::Blob(self.clone())
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Integer(*self as i64)
+    }
+}
+
+impl ToValue for f64 {
+    fn to_value(&self) -> Value {
+        Value::Real(*self)
+    }
+}
+
+macro_rules! integer_to_value {
+    ($($ty:ty),*) => {$(
+        impl ToValue for $ty {
+            fn to_value(&self) -> Value {
+                // Saturate the few unsigned values beyond i64
+                Value::Integer(i64::try_from(*self).unwrap_or(i64::MAX))
+            }
+        }
+    )*};
+}
+integer_to_value!(i32, i64, u32, u64, usize);
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        self.as_ref().map_or(Value::Null, ToValue::to_value)
+    }
+}
+
+/// Types that can be read from a result column
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, String>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Text(s) => Ok(s.clone()),
+            other => Err(format!("expected text, got {:?}", other)),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Blob(b) => Ok(b.clone()),
+            Value::Text(s) => Ok(s.as_bytes().to_vec()),
+            other => Err(format!("expected a blob, got {:?}", other)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        i64::from_value(value).map(|i| i != 0)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Real(f) => Ok(*f),
+            Value::Integer(i) => Ok(*i as f64),
+            other => Err(format!("expected a number, got {:?}", other)),
+        }
+    }
+}
+
+macro_rules! integer_from_value {
+    ($($ty:ty),*) => {$(
+        impl FromValue for $ty {
+            fn from_value(value: &Value) -> Result<Self, String> {
+                match value {
+                    Value::Integer(i) => <$ty>::try_from(*i)
+                        .map_err(|_| format!("{} is out of range for {}", i, stringify!($ty))),
+                    other => Err(format!("expected an integer, got {:?}", other)),
+                }
+            }
+        }
+    )*};
+}
+integer_from_value!(i32, i64, u32, u64, usize);
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// One result row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    values: Vec<Value>,
+}
+
+impl Row {
+    pub fn new(values: Vec<Value>) -> Self {
+        Row { values }
+    }
+
+    /// Read column `idx`, converted to `T`
+    pub fn get<T: FromValue>(&self, idx: usize) -> Result<T, NuClawError> {
+        let value = self.values.get(idx).ok_or_else(|| NuClawError::Database {
+            message: format!("No column {} in result row", idx),
+        })?;
+        T::from_value(value).map_err(|e| NuClawError::Database {
+            message: format!("Invalid value in column {}: {}", idx, e),
+        })
+    }
+}
+
+/// Parameters of a statement: `()`, an array, or `params![...]`
+pub trait Params {
+    fn into_values(self) -> Vec<Value>;
+}
+
+impl Params for () {
+    fn into_values(self) -> Vec<Value> {
+        Vec::new()
+    }
+}
+
+impl Params for Vec<Value> {
+    fn into_values(self) -> Vec<Value> {
+        self
+    }
+}
+
+impl Params for &[&dyn ToValue] {
+    fn into_values(self) -> Vec<Value> {
+        self.iter().map(|p| p.to_value()).collect()
+    }
+}
+
+impl<T: ToValue, const N: usize> Params for [T; N] {
+    fn into_values(self) -> Vec<Value> {
+        self.iter().map(ToValue::to_value).collect()
+    }
+}
+
+/// Statement parameters of mixed types, e.g. `params![id, 3, None::<String>]`
+#[macro_export]
+macro_rules! params {
+    () => {
+        ()
+    };
+    ($($param:expr),+ $(,)?) => {
+        &[$(&$param as &dyn $crate::db::ToValue),+] as &[&dyn $crate::db::ToValue]
+    };
+}
+pub use params;
+
+/// A connection of a storage backend
+///
+/// Backends implement the raw calls; the typed helpers (`execute`,
+/// `query_map`, ...) are provided for every `dyn Storage`.
+pub trait Storage {
+    fn backend(&self) -> Backend;
+
+    /// Run a statement; returns the number of changed rows
+    fn execute_values(&self, sql: &str, params: Vec<Value>) -> Result<usize, NuClawError>;
+
+    /// Run a query and return all its rows
+    fn query_values(&self, sql: &str, params: Vec<Value>) -> Result<Vec<Row>, NuClawError>;
+
+    /// Run statements separated by `;` without parameters
+    fn execute_batch(&self, sql: &str) -> Result<(), NuClawError>;
+}
+
+impl dyn Storage + '_ {
+    /// Run a statement; returns the number of changed rows
+    pub fn execute<P: Params>(&self, sql: &str, params: P) -> Result<usize, NuClawError> {
+        self.execute_values(sql, params.into_values())
+    }
+
+    /// Run a query and map every row
+    pub fn query_map<T, P, F>(&self, sql: &str, params: P, f: F) -> Result<Vec<T>, NuClawError>
+    where
+        P: Params,
+        F: FnMut(&Row) -> Result<T, NuClawError>,
+    {
+        self.query_values(sql, params.into_values())?
+            .iter()
+            .map(f)
+            .collect()
+    }
+
+    /// Map the first row of a query, if there is one
+    pub fn query_opt<T, P, F>(&self, sql: &str, params: P, f: F) -> Result<Option<T>, NuClawError>
+    where
+        P: Params,
+        F: FnOnce(&Row) -> Result<T, NuClawError>,
+    {
+        self.query_values(sql, params.into_values())?
+            .first()
+            .map(f)
+            .transpose()
+    }
+
+    /// Map the first row of a query; an error if there is none
+    pub fn query_row<T, P, F>(&self, sql: &str, params: P, f: F) -> Result<T, NuClawError>
+    where
+        P: Params,
+        F: FnOnce(&Row) -> Result<T, NuClawError>,
+    {
+        self.query_opt(sql, params, f)?
+            .ok_or_else(|| NuClawError::Database {
+                message: "Query returned no rows".to_string(),
+            })
+    }
+}
+
+impl Storage for rusqlite::Connection {
+    fn backend(&self) -> Backend {
+        Backend::Sqlite
+    }
+
+    fn execute_values(&self, sql: &str, params: Vec<Value>) -> Result<usize, NuClawError> {
+        Ok(self
+            .prepare_cached(sql)?
+            .execute(rusqlite::params_from_iter(params))?)
+    }
+
+    fn query_values(&self, sql: &str, params: Vec<Value>) -> Result<Vec<Row>, NuClawError> {
+        let mut stmt = self.prepare_cached(sql)?;
+        let columns = stmt.column_count();
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                (0..columns)
+                    .map(|i| row.get_ref(i).map(Value::from))
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                    .map(Row::new)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    fn execute_batch(&self, sql: &str) -> Result<(), NuClawError> {
+        Ok(rusqlite::Connection::execute_batch(self, sql)?)
+    }
+}
+
+impl rusqlite::ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        use rusqlite::types::ValueRef;
+        Ok(rusqlite::types::ToSqlOutput::Borrowed(match self {
+            Value::Null => ValueRef::Null,
+            Value::Integer(i) => ValueRef::Integer(*i),
+            Value::Real(f) => ValueRef::Real(*f),
+            Value::Text(s) => ValueRef::Text(s.as_bytes()),
+            Value::Blob(b) => ValueRef::Blob(b),
+        }))
+    }
+}
+
+impl From<rusqlite::types::ValueRef<'_>> for Value {
+    fn from(value: rusqlite::types::ValueRef<'_>) -> Self {
+        use rusqlite::types::ValueRef;
+        match value {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(i) => Value::Integer(i),
+            ValueRef::Real(f) => Value::Real(f),
+            ValueRef::Text(t) => Value::Text(String::from_utf8_lossy(t).into_owned()),
+            ValueRef::Blob(b) => Value::Blob(b.to_vec()),
+        }
+    }
+}
+
+enum Pooled {
+    Sqlite(PooledConnection<SqliteConnectionManager>),
+    Postgres(PooledConnection<PostgresManager>),
+}
+
+/// A connection taken from the pool; returned when dropped
+pub struct Connection(Pooled);
+
+impl Deref for Connection {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.0 {
+            Pooled::Sqlite(conn) => &**conn as &rusqlite::Connection,
+            Pooled::Postgres(conn) => &**conn,
+        }
+    }
+}
+
+impl Connection {
+    /// Start a transaction; it is rolled back unless committed
+    pub fn transaction(&mut self) -> Result<Transaction<'_>, NuClawError> {
+        self.execute_batch("BEGIN")?;
+        Ok(Transaction {
+            conn: self,
+            committed: false,
+        })
+    }
+}
+
+/// A transaction on a pooled connection
+pub struct Transaction<'a> {
+    conn: &'a mut Connection,
+    committed: bool,
+}
+
+impl Transaction<'_> {
+    pub fn commit(mut self) -> Result<(), NuClawError> {
+        self.conn.execute_batch("COMMIT")?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Deref for Transaction<'_> {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        &**self.conn
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            if let Err(e) = self.conn.execute_batch("ROLLBACK") {
+                tracing::warn!("Failed to roll back transaction: {}", e);
+            }
+        }
+    }
+}
+
+/// Run a `CREATE` statement written in SQLite's dialect
+///
+/// On Postgres, integer columns become `BIGINT` (SQLite integers are 64
+/// bits) and `INTEGER PRIMARY KEY AUTOINCREMENT` becomes `BIGSERIAL`.
+fn create(conn: &dyn Storage, ddl: &str) -> Result<usize, NuClawError> {
+    match conn.backend() {
+        Backend::Sqlite => conn.execute(ddl, ()),
+        Backend::Postgres => conn.execute(
+            &ddl.replace("INTEGER PRIMARY KEY AUTOINCREMENT", "BIGSERIAL PRIMARY KEY")
+                .replace("INTEGER", "BIGINT"),
+            (),
+        ),
+    }
+}
+
 /// Initialize database schema
-fn initialize_schema(conn: &Connection) -> Result<(), NuClawError> {
-    conn.execute(
+fn initialize_schema(conn: &dyn Storage) -> Result<(), NuClawError> {
+    create_keyed_by_tenant(
+        conn,
+        "chats",
         "CREATE TABLE IF NOT EXISTS chats (
-            jid TEXT PRIMARY KEY,
+            tenant TEXT NOT NULL DEFAULT 'default',
+            jid TEXT NOT NULL,
             name TEXT,
-            last_message_time TEXT
+            last_message_time TEXT,
+            PRIMARY KEY (tenant, jid)
         )",
-        [],
-    )
-    .map_err(|e| NuClawError::Database {
-        message: format!("Failed to create chats table: {}", e),
-    })?;
+    )?;
 
-    conn.execute(
+    create_keyed_by_tenant(
+        conn,
+        "messages",
         "CREATE TABLE IF NOT EXISTS messages (
-            id TEXT,
-            chat_jid TEXT,
+            tenant TEXT NOT NULL DEFAULT 'default',
+            id TEXT NOT NULL,
+            chat_jid TEXT NOT NULL,
             sender TEXT,
             sender_name TEXT,
             content TEXT,
             timestamp TEXT,
             is_from_me INTEGER DEFAULT 0,
-            PRIMARY KEY (id, chat_jid)
+            PRIMARY KEY (tenant, id, chat_jid)
         )",
-        [],
-    )
-    .map_err(|e| NuClawError::Database {
-        message: format!("Failed to create messages table: {}", e),
-    })?;
+    )?;
 
-    conn.execute(
+    create(
+        conn,
         "CREATE TABLE IF NOT EXISTS scheduled_tasks (
             id TEXT PRIMARY KEY,
             group_folder TEXT NOT NULL,
@@ -152,15 +807,55 @@ fn initialize_schema(conn: &Connection) -> Result<(), NuClawError> {
             last_result TEXT,
             status TEXT DEFAULT 'active',
             created_at TEXT NOT NULL,
-            context_mode TEXT DEFAULT 'isolated'
+            context_mode TEXT DEFAULT 'isolated',
+            container_retries INTEGER,
+            max_retries INTEGER,
+            consecutive_failures INTEGER NOT NULL DEFAULT 0,
+            jitter_secs INTEGER,
+            timeout_secs INTEGER,
+            notify TEXT NOT NULL DEFAULT 'always',
+            lock TEXT,
+            overlap TEXT NOT NULL DEFAULT 'skip',
+            priority INTEGER NOT NULL DEFAULT 0,
+            tenant TEXT NOT NULL DEFAULT 'default'
         )",
-        [],
     )
     .map_err(|e| NuClawError::Database {
         message: format!("Failed to create scheduled_tasks table: {}", e),
     })?;
+    add_column_if_missing(conn, "scheduled_tasks", "container_retries", "INTEGER")?;
+    add_column_if_missing(conn, "scheduled_tasks", "max_retries", "INTEGER")?;
+    add_column_if_missing(
+        conn,
+        "scheduled_tasks",
+        "consecutive_failures",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    add_column_if_missing(conn, "scheduled_tasks", "jitter_secs", "INTEGER")?;
+    add_column_if_missing(conn, "scheduled_tasks", "timeout_secs", "INTEGER")?;
+    add_column_if_missing(
+        conn,
+        "scheduled_tasks",
+        "notify",
+        "TEXT NOT NULL DEFAULT 'always'",
+    )?;
+    add_column_if_missing(conn, "scheduled_tasks", "lock", "TEXT")?;
+    add_column_if_missing(
+        conn,
+        "scheduled_tasks",
+        "overlap",
+        "TEXT NOT NULL DEFAULT 'skip'",
+    )?;
+    add_column_if_missing(
+        conn,
+        "scheduled_tasks",
+        "priority",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    add_tenant_column(conn, "scheduled_tasks")?;
 
-    conn.execute(
+    create(
+        conn,
         "CREATE TABLE IF NOT EXISTS task_run_logs (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
             task_id TEXT NOT NULL,
@@ -168,17 +863,542 @@ fn initialize_schema(conn: &Connection) -> Result<(), NuClawError> {
             duration_ms INTEGER NOT NULL,
             status TEXT NOT NULL,
             result TEXT,
-            error TEXT
+            error TEXT,
+            tenant TEXT NOT NULL DEFAULT 'default'
         )",
-        [],
     )
     .map_err(|e| NuClawError::Database {
         message: format!("Failed to create task_run_logs table: {}", e),
     })?;
+    add_tenant_column(conn, "task_run_logs")?;
 
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS pairing_codes (
+            code TEXT PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            expires_at TEXT NOT NULL,
+            tenant TEXT NOT NULL DEFAULT 'default'
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create pairing_codes table: {}", e),
+    })?;
+    add_tenant_column(conn, "pairing_codes")?;
+
+    create_keyed_by_tenant(
+        conn,
+        "paired_users",
+        "CREATE TABLE IF NOT EXISTS paired_users (
+            tenant TEXT NOT NULL DEFAULT 'default',
+            channel TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            paired_at TEXT NOT NULL,
+            PRIMARY KEY (tenant, channel, user_id)
+        )",
+    )?;
+
+    create_keyed_by_tenant(
+        conn,
+        "allowlist",
+        "CREATE TABLE IF NOT EXISTS allowlist (
+            tenant TEXT NOT NULL DEFAULT 'default',
+            channel TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            entry_id TEXT NOT NULL,
+            added_by TEXT,
+            added_at TEXT NOT NULL,
+            PRIMARY KEY (tenant, channel, kind, entry_id)
+        )",
+    )?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS outbox (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            channel TEXT NOT NULL,
+            chat_jid TEXT NOT NULL,
+            content TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT,
+            created_at TEXT NOT NULL,
+            next_attempt_at TEXT NOT NULL,
+            tenant TEXT NOT NULL DEFAULT 'default'
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create outbox table: {}", e),
+    })?;
+    add_tenant_column(conn, "outbox")?;
+
+    create_keyed_by_tenant(
+        conn,
+        "processed_messages",
+        "CREATE TABLE IF NOT EXISTS processed_messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tenant TEXT NOT NULL DEFAULT 'default',
+            chat_jid TEXT NOT NULL,
+            message_id TEXT NOT NULL,
+            processed_at TEXT NOT NULL,
+            UNIQUE (tenant, chat_jid, message_id)
+        )",
+    )?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS pending_messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            channel TEXT NOT NULL,
+            chat_jid TEXT NOT NULL,
+            message TEXT NOT NULL,
+            prompt TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            tenant TEXT NOT NULL DEFAULT 'default'
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create pending_messages table: {}", e),
+    })?;
+    add_tenant_column(conn, "pending_messages")?;
+    add_column_if_missing(
+        conn,
+        "pending_messages",
+        "held",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS container_runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            group_folder TEXT NOT NULL,
+            chat_jid TEXT NOT NULL,
+            started_at TEXT NOT NULL,
+            status TEXT NOT NULL,
+            exit_code INTEGER,
+            duration_ms INTEGER NOT NULL,
+            queue_wait_ms INTEGER NOT NULL,
+            output_bytes INTEGER NOT NULL,
+            tenant TEXT NOT NULL DEFAULT 'default'
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create container_runs table: {}", e),
+    })?;
+    add_tenant_column(conn, "container_runs")?;
+
+    create(
+        conn,
+        "CREATE INDEX IF NOT EXISTS idx_container_runs_started ON container_runs(started_at)",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create container_runs index: {}", e),
+    })?;
+
+    create_keyed_by_tenant(
+        conn,
+        "task_templates",
+        "CREATE TABLE IF NOT EXISTS task_templates (
+            tenant TEXT NOT NULL DEFAULT 'default',
+            name TEXT NOT NULL,
+            prompt TEXT NOT NULL,
+            schedule_type TEXT,
+            schedule_value TEXT,
+            group_folder TEXT,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (tenant, name)
+        )",
+    )?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS maintenance (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            since TEXT NOT NULL,
+            paused_by TEXT NOT NULL
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create maintenance table: {}", e),
+    })?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS scheduler_lease (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            holder TEXT NOT NULL,
+            acquired_at TEXT NOT NULL,
+            expires_at TEXT NOT NULL
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create scheduler_lease table: {}", e),
+    })?;
+
+    create_keyed_by_tenant(
+        conn,
+        "sessions",
+        "CREATE TABLE IF NOT EXISTS sessions (
+            tenant TEXT NOT NULL DEFAULT 'default',
+            chat_jid TEXT NOT NULL,
+            session_id TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            last_used_at TEXT NOT NULL,
+            expires_at TEXT NOT NULL,
+            PRIMARY KEY (tenant, chat_jid)
+        )",
+    )?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS memories (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tenant TEXT NOT NULL DEFAULT 'default',
+            group_folder TEXT NOT NULL,
+            content TEXT NOT NULL,
+            embedding TEXT,
+            embedding_model TEXT,
+            created_at TEXT NOT NULL
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create memories table: {}", e),
+    })?;
+
+    create(
+        conn,
+        "CREATE INDEX IF NOT EXISTS idx_memories_group ON memories(tenant, group_folder)",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create memories index: {}", e),
+    })?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS agent_state (
+            tenant TEXT NOT NULL DEFAULT 'default',
+            group_folder TEXT NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            PRIMARY KEY (tenant, group_folder, key)
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create agent_state table: {}", e),
+    })?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS router_state (
+            tenant TEXT NOT NULL DEFAULT 'default',
+            channel TEXT NOT NULL,
+            chat_jid TEXT NOT NULL,
+            last_timestamp TEXT NOT NULL,
+            PRIMARY KEY (tenant, channel, chat_jid)
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create router_state table: {}", e),
+    })?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tenant TEXT NOT NULL DEFAULT 'default',
+            created_at TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            actor TEXT NOT NULL,
+            chat_jid TEXT,
+            detail TEXT NOT NULL
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create audit_log table: {}", e),
+    })?;
+    create_append_only_guard(conn, "audit_log")?;
+
+    create(
+        conn,
+        "CREATE INDEX IF NOT EXISTS idx_audit_log_time ON audit_log(tenant, created_at)",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create audit_log index: {}", e),
+    })?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS usage (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tenant TEXT NOT NULL DEFAULT 'default',
+            started_at TEXT NOT NULL,
+            chat_jid TEXT NOT NULL,
+            group_folder TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            status TEXT NOT NULL,
+            duration_ms INTEGER NOT NULL,
+            input_tokens INTEGER,
+            output_tokens INTEGER,
+            cost_usd DOUBLE PRECISION
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create usage table: {}", e),
+    })?;
+
+    create(
+        conn,
+        "CREATE INDEX IF NOT EXISTS idx_usage_time ON usage(tenant, started_at)",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create usage index: {}", e),
+    })?;
+
+    // Roles of chat users; an empty group folder means all groups
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS users (
+            tenant TEXT NOT NULL DEFAULT 'default',
+            channel TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            group_folder TEXT NOT NULL DEFAULT '',
+            role TEXT NOT NULL,
+            granted_by TEXT NOT NULL,
+            granted_at TEXT NOT NULL,
+            PRIMARY KEY (tenant, channel, user_id, group_folder)
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create users table: {}", e),
+    })?;
+
+    create(
+        conn,
+        "CREATE TABLE IF NOT EXISTS blocked_users (
+            tenant TEXT NOT NULL DEFAULT 'default',
+            channel TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            reason TEXT,
+            blocked_by TEXT NOT NULL,
+            blocked_at TEXT NOT NULL,
+            PRIMARY KEY (tenant, channel, user_id)
+        )",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create blocked_users table: {}", e),
+    })?;
+
+    // Indexes from before tenants, replaced by the ones below
+    conn.execute_batch(
+        "DROP INDEX IF EXISTS idx_outbox_due;
+         DROP INDEX IF EXISTS idx_scheduled_tasks_due",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to drop old indexes: {}", e),
+    })?;
+
+    create(
+        conn,
+        "CREATE INDEX IF NOT EXISTS idx_outbox_tenant_due
+            ON outbox(tenant, channel, status, next_attempt_at)",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create outbox index: {}", e),
+    })?;
+
+    // Context lookups read the latest messages of one chat
+    create(
+        conn,
+        "CREATE INDEX IF NOT EXISTS idx_messages_chat_time
+            ON messages(tenant, chat_jid, timestamp)",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create messages index: {}", e),
+    })?;
+
+    // The scheduler polls the active tasks by next run
+    create(
+        conn,
+        "CREATE INDEX IF NOT EXISTS idx_scheduled_tasks_tenant_due
+            ON scheduled_tasks(tenant, status, next_run)",
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to create scheduled_tasks index: {}", e),
+    })?;
+
+    Ok(())
+}
+
+/// Make the database reject updates and deletes of a table's rows
+fn create_append_only_guard(conn: &dyn Storage, table: &str) -> Result<(), NuClawError> {
+    let ddl = match conn.backend() {
+        Backend::Sqlite => format!(
+            "CREATE TRIGGER IF NOT EXISTS {0}_no_update BEFORE UPDATE ON {0}
+             BEGIN SELECT RAISE(ABORT, '{0} is append-only'); END;
+             CREATE TRIGGER IF NOT EXISTS {0}_no_delete BEFORE DELETE ON {0}
+             BEGIN SELECT RAISE(ABORT, '{0} is append-only'); END;",
+            table
+        ),
+        Backend::Postgres => format!(
+            "CREATE OR REPLACE FUNCTION {0}_append_only() RETURNS trigger AS $$
+             BEGIN RAISE EXCEPTION '{0} is append-only'; END $$ LANGUAGE plpgsql;
+             DROP TRIGGER IF EXISTS {0}_append_only ON {0};
+             CREATE TRIGGER {0}_append_only BEFORE UPDATE OR DELETE ON {0}
+             FOR EACH ROW EXECUTE FUNCTION {0}_append_only();",
+            table
+        ),
+    };
+    conn.execute_batch(&ddl).map_err(|e| NuClawError::Database {
+        message: format!("Failed to make {} append-only: {}", table, e),
+    })
+}
+
+/// Columns of a table; empty if it does not exist
+fn table_columns(conn: &dyn Storage, table: &str) -> Result<Vec<String>, NuClawError> {
+    match conn.backend() {
+        Backend::Sqlite => conn.query_map(&format!("PRAGMA table_info({})", table), (), |row| {
+            row.get::<String>(1)
+        }),
+        Backend::Postgres => conn.query_map(
+            "SELECT column_name::text FROM information_schema.columns
+             WHERE table_schema = current_schema() AND table_name = ?
+             ORDER BY ordinal_position",
+            [table],
+            |row| row.get(0),
+        ),
+    }
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to inspect {} table: {}", table, e),
+    })
+}
+
+/// Add the `tenant` column to a table from before tenants; its rows belong
+/// to the default tenant
+fn add_tenant_column(conn: &dyn Storage, table: &str) -> Result<(), NuClawError> {
+    add_column_if_missing(conn, table, "tenant", "TEXT NOT NULL DEFAULT 'default'")
+}
+
+/// Create a table whose key includes the tenant
+///
+/// A table from before tenants is rebuilt under `ddl` (a key cannot be
+/// changed in place on SQLite), its rows going to the default tenant.
+fn create_keyed_by_tenant(conn: &dyn Storage, table: &str, ddl: &str) -> Result<(), NuClawError> {
+    let failed = |e: NuClawError| NuClawError::Database {
+        message: format!("Failed to create {} table: {}", table, e),
+    };
+    let columns = table_columns(conn, table)?;
+    if columns.is_empty() || columns.iter().any(|c| c == "tenant") {
+        return create(conn, ddl).map(|_| ()).map_err(failed);
+    }
+
+    tracing::info!("Adding tenants to the {} table", table);
+    let staging = format!("{}_tenant", table);
+    let staging_ddl = ddl.replacen(
+        &format!("EXISTS {} (", table),
+        &format!("EXISTS {} (", staging),
+        1,
+    );
+    // Generated IDs are not copied, so the new table's sequence starts
+    // clear of them
+    let columns = columns
+        .into_iter()
+        .filter(|c| c != "id" || !ddl.contains("AUTOINCREMENT"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    conn.execute_batch("BEGIN").map_err(failed)?;
+    let rebuilt = create(conn, &staging_ddl)
+        .and_then(|_| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO {} ({}) SELECT {} FROM {}",
+                    staging, columns, columns, table
+                ),
+                (),
+            )
+        })
+        .and_then(|_| conn.execute(&format!("DROP TABLE {}", table), ()))
+        .and_then(|_| conn.execute(&format!("ALTER TABLE {} RENAME TO {}", staging, table), ()));
+    match rebuilt {
+        Ok(_) => conn.execute_batch("COMMIT").map_err(failed),
+        Err(e) => {
+            let _ = conn.execute_batch("ROLLBACK");
+            Err(failed(e))
+        }
+    }
+}
+
+/// Add a column to a table created by an older version of the schema
+fn add_column_if_missing(
+    conn: &dyn Storage,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), NuClawError> {
+    if conn.backend() == Backend::Postgres {
+        let definition = definition.replace("INTEGER", "BIGINT");
+        conn.execute(
+            &format!(
+                "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}",
+                table, column, definition
+            ),
+            (),
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to add {}.{}: {}", table, column, e),
+        })?;
+        return Ok(());
+    }
+
+    if !table_columns(conn, table)?.iter().any(|c| c == column) {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            (),
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to add {}.{}: {}", table, column, e),
+        })?;
+    }
     Ok(())
 }
 
+/// Create a database in a fresh temporary directory for tests
+///
+/// With `NUCLAW_TEST_DATABASE_URL` set to a Postgres URL, each test gets a
+/// fresh schema in that database instead.
+#[cfg(test)]
+pub(crate) fn test_database() -> (Database, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = DatabaseConfig {
+        db_path: dir.path().join("nuclaw.db"),
+        pool_size: 2,
+        connection_timeout_ms: 5000,
+        database_url: None,
+    };
+    if let Ok(url) = std::env::var("NUCLAW_TEST_DATABASE_URL") {
+        let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+        let admin = Database::with_config(DatabaseConfig {
+            pool_size: 1,
+            database_url: Some(url.clone()),
+            ..config.clone()
+        })
+        .unwrap();
+        admin
+            .get_connection()
+            .unwrap()
+            .execute_batch(&format!("CREATE SCHEMA {}", schema))
+            .unwrap();
+        let separator = if url.contains('?') { '&' } else { '?' };
+        config.database_url = Some(format!(
+            "{}{}options=-c%20search_path%3D{}",
+            url, separator, schema
+        ));
+    }
+    (Database::with_config(config).unwrap(), dir)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -197,6 +1417,88 @@ mod tests {
         let _ = fs::remove_file(path.with_extension("db-shm"));
     }
 
+    #[test]
+    fn test_schema_upgrade_adds_new_columns() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE scheduled_tasks (
+                id TEXT PRIMARY KEY,
+                group_folder TEXT NOT NULL,
+                chat_jid TEXT NOT NULL,
+                prompt TEXT NOT NULL,
+                schedule_type TEXT NOT NULL,
+                schedule_value TEXT NOT NULL,
+                next_run TEXT,
+                last_run TEXT,
+                last_result TEXT,
+                status TEXT DEFAULT 'active',
+                created_at TEXT NOT NULL,
+                context_mode TEXT DEFAULT 'isolated'
+            )",
+            [],
+        )
+        .unwrap();
+
+        initialize_schema(&conn).unwrap();
+        // Running it again must not try to add the column twice
+        initialize_schema(&conn).unwrap();
+
+        conn.prepare(
+            "SELECT container_retries, max_retries, consecutive_failures, jitter_secs,
+                timeout_secs, notify, lock, overlap, priority
+             FROM scheduled_tasks",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_schema_upgrade_moves_rows_to_default_tenant() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE chats (
+                jid TEXT PRIMARY KEY,
+                name TEXT,
+                last_message_time TEXT
+            );
+            CREATE TABLE processed_messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_jid TEXT NOT NULL,
+                message_id TEXT NOT NULL,
+                processed_at TEXT NOT NULL,
+                UNIQUE (chat_jid, message_id)
+            );
+            INSERT INTO chats (jid, name) VALUES ('a@g.us', 'Family');
+            INSERT INTO processed_messages (chat_jid, message_id, processed_at)
+                VALUES ('a@g.us', 'm1', '2026-01-01T00:00:00+00:00');",
+        )
+        .unwrap();
+
+        initialize_schema(&conn).unwrap();
+        initialize_schema(&conn).unwrap();
+
+        let chats: Vec<(String, String)> = conn
+            .prepare("SELECT tenant, name FROM chats WHERE jid = 'a@g.us'")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(chats, vec![("default".to_string(), "Family".to_string())]);
+        // The same chat and message can now exist for another tenant
+        conn.execute_batch(
+            "INSERT INTO chats (tenant, jid, name) VALUES ('alice', 'a@g.us', 'Alice');
+             INSERT INTO processed_messages (tenant, chat_jid, message_id, processed_at)
+                VALUES ('alice', 'a@g.us', 'm1', '2026-01-01T00:00:00+00:00');",
+        )
+        .unwrap();
+        let processed: i64 = conn
+            .query_row("SELECT COUNT(*) FROM processed_messages", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(processed, 2);
+    }
+
     #[test]
     fn test_database_new() {
         let db_path = test_db_path();
@@ -206,6 +1508,7 @@ mod tests {
             db_path: db_path.clone(),
             pool_size: 5,
             connection_timeout_ms: 5000,
+            database_url: None,
         };
 
         let result = Database::with_config(config);
@@ -229,6 +1532,7 @@ mod tests {
             db_path: db_path.clone(),
             pool_size: 3,
             connection_timeout_ms: 5000,
+            database_url: None,
         };
 
         let db = Database::with_config(config).unwrap();
@@ -246,20 +1550,24 @@ mod tests {
             db_path: db_path.clone(),
             pool_size: 5,
             connection_timeout_ms: 10000,
+            database_url: None,
         };
 
         let db = Database::with_config(config).unwrap();
 
+        // The writer and four readers
+        let writer = db.get_connection();
         let mut handles = Vec::new();
-        for _ in 0..5 {
+        for _ in 0..4 {
             let db_clone = db.clone();
-            handles.push(std::thread::spawn(move || db_clone.get_connection()));
+            handles.push(std::thread::spawn(move || db_clone.read_connection()));
         }
 
-        let results: Vec<Result<PooledConnection<SqliteConnectionManager>, NuClawError>> =
+        let results: Vec<Result<Connection, NuClawError>> =
             handles.into_iter().map(|h| h.join().unwrap()).collect();
 
-        assert_eq!(results.len(), 5);
+        assert_eq!(results.len(), 4);
+        assert!(writer.is_ok());
         assert!(
             results.into_iter().all(|r| r.is_ok()),
             "All connections should succeed"
@@ -267,6 +1575,23 @@ mod tests {
         cleanup_test_db(&db_path);
     }
 
+    #[test]
+    fn test_read_connections_are_read_only() {
+        let (db, _dir) = test_database();
+        let insert = "INSERT INTO pairing_codes (code, created_at, expires_at) VALUES (?, '', '')";
+        let writer = db.get_connection().unwrap();
+        writer.execute(insert, ["written"]).unwrap();
+
+        let reader = db.read_connection().unwrap();
+        let codes: Vec<String> = reader
+            .query_map("SELECT code FROM pairing_codes", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(codes, vec!["written"]);
+        if reader.backend() == Backend::Sqlite {
+            assert!(reader.execute(insert, ["refused"]).is_err());
+        }
+    }
+
     #[test]
     fn test_pool_status() {
         let db_path = test_db_path();
@@ -276,6 +1601,7 @@ mod tests {
             db_path: db_path.clone(),
             pool_size: 10,
             connection_timeout_ms: 5000,
+            database_url: None,
         };
 
         let db = Database::with_config(config).unwrap();
@@ -329,27 +1655,107 @@ mod tests {
             db_path: db_path.clone(),
             pool_size: 3,
             connection_timeout_ms: 5000,
+            database_url: None,
         };
 
         let db = Database::with_config(config).unwrap();
         let conn = db.get_connection().unwrap();
 
         let tables: Vec<String> = conn
-            .prepare("SELECT name FROM sqlite_master WHERE type='table'")
-            .unwrap()
-            .query_map([], |row| row.get::<_, String>(0))
-            .unwrap()
-            .collect::<Result<Vec<String>, _>>()
+            .query_map(
+                "SELECT name FROM sqlite_master WHERE type='table'",
+                (),
+                |row| row.get(0),
+            )
             .unwrap();
 
         assert!(tables.contains(&"chats".to_string()));
         assert!(tables.contains(&"messages".to_string()));
         assert!(tables.contains(&"scheduled_tasks".to_string()));
         assert!(tables.contains(&"task_run_logs".to_string()));
+        assert!(tables.contains(&"pairing_codes".to_string()));
+        assert!(tables.contains(&"paired_users".to_string()));
+        assert!(tables.contains(&"users".to_string()));
+        assert!(tables.contains(&"allowlist".to_string()));
+        assert!(tables.contains(&"blocked_users".to_string()));
+        assert!(tables.contains(&"outbox".to_string()));
+        assert!(tables.contains(&"processed_messages".to_string()));
+        assert!(tables.contains(&"pending_messages".to_string()));
+        assert!(tables.contains(&"memories".to_string()));
+        assert!(tables.contains(&"agent_state".to_string()));
+        assert!(tables.contains(&"router_state".to_string()));
 
         cleanup_test_db(&db_path);
     }
 
+    #[test]
+    fn test_database_url_selects_backend() {
+        assert!(is_postgres_url("postgres://nuclaw@db/nuclaw"));
+        assert!(is_postgres_url("postgresql://nuclaw@db/nuclaw"));
+        assert!(!is_postgres_url("mysql://nuclaw@db/nuclaw"));
+
+        let config = DatabaseConfig {
+            database_url: Some("mysql://nuclaw:secret@db/nuclaw".to_string()),
+            ..DatabaseConfig::default()
+        };
+        match Database::with_config(config) {
+            Err(NuClawError::Config { message }) => assert!(!message.contains("secret")),
+            other => panic!("Expected a config error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_truncates_wal_and_frees_pages() {
+        let (db, dir) = test_database();
+        let conn = db.get_connection().unwrap();
+        if conn.backend() != Backend::Sqlite {
+            assert_eq!(db.checkpoint().unwrap(), None);
+            return;
+        }
+        let auto_vacuum: i64 = conn
+            .query_row("PRAGMA auto_vacuum", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(auto_vacuum, 2, "new databases use incremental vacuum");
+
+        let content = "x".repeat(4000);
+        for i in 0..200 {
+            conn.execute(
+                "INSERT INTO pairing_codes (code, created_at, expires_at) VALUES (?, ?, '')",
+                crate::params![i.to_string(), content],
+            )
+            .unwrap();
+        }
+        conn.execute("DELETE FROM pairing_codes", ()).unwrap();
+        drop(conn);
+
+        let wal = dir.path().join("nuclaw.db-wal");
+        assert!(fs::metadata(&wal).unwrap().len() > 0);
+        let checkpoint = db.checkpoint().unwrap().unwrap();
+        assert!(!checkpoint.busy);
+        assert!(checkpoint.wal_bytes > 0);
+        assert!(checkpoint.freed_pages >= 200);
+        assert_eq!(fs::metadata(&wal).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_unless_committed() {
+        let (db, _dir) = test_database();
+        let mut conn = db.get_connection().unwrap();
+        let insert = "INSERT INTO pairing_codes (code, created_at, expires_at) VALUES (?, '', '')";
+        {
+            let tx = conn.transaction().unwrap();
+            tx.execute(insert, ["dropped"]).unwrap();
+        }
+        let tx = conn.transaction().unwrap();
+        tx.execute(insert, ["kept"]).unwrap();
+        tx.commit().unwrap();
+
+        let codes: Vec<String> = conn
+            .query_map("SELECT code FROM pairing_codes", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(codes, vec!["kept"]);
+    }
+
     #[test]
     fn test_clone_database() {
         let db_path = test_db_path();
@@ -359,6 +1765,7 @@ mod tests {
             db_path: db_path.clone(),
             pool_size: 3,
             connection_timeout_ms: 5000,
+            database_url: None,
         };
 
         let db1 = Database::with_config(config.clone()).unwrap();
@@ -0,0 +1,115 @@
+//! Inbound Message Deduplication for NuClaw
+//!
+//! Remembers the IDs of processed messages in the `processed_messages`
+//! table, keyed by tenant and chat JID so all channels share one store.
+//! Only the most recently seen `DEDUP_CAPACITY` IDs of each tenant are
+//! kept; seeing an ID again moves it to the front, so redelivered messages
+//! stay recognised across restarts while the table stays bounded.
+
+use crate::db::{Database, Storage};
+use crate::error::Result;
+
+/// Default number of message IDs remembered
+const DEFAULT_DEDUP_CAPACITY: usize = 10_000;
+
+pub(crate) fn dedup_capacity() -> usize {
+    std::env::var("DEDUP_CAPACITY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_DEDUP_CAPACITY)
+}
+
+/// Record a message as processed
+///
+/// Returns `true` the first time a message ID is seen in a chat and
+/// `false` for duplicates.
+pub fn mark_processed(db: &Database, chat_jid: &str, message_id: &str) -> Result<bool> {
+    mark_processed_with_capacity(db, chat_jid, message_id, dedup_capacity())
+}
+
+fn mark_processed_with_capacity(
+    db: &Database,
+    chat_jid: &str,
+    message_id: &str,
+    capacity: usize,
+) -> Result<bool> {
+    let mut conn = db.get_connection()?;
+    let tx = conn.transaction()?;
+    let first = mark_processed_in(&*tx, db.tenant(), chat_jid, message_id, capacity)?;
+    tx.commit()?;
+    Ok(first)
+}
+
+/// `mark_processed` on a given connection, e.g. in a transaction
+pub(crate) fn mark_processed_in(
+    conn: &dyn Storage,
+    tenant: &str,
+    chat_jid: &str,
+    message_id: &str,
+    capacity: usize,
+) -> Result<bool> {
+    // Re-inserting gives the entry a new id, which is its recency rank
+    let seen = conn.execute(
+        "DELETE FROM processed_messages WHERE tenant = ? AND chat_jid = ? AND message_id = ?",
+        [tenant, chat_jid, message_id],
+    )? > 0;
+    conn.execute(
+        "INSERT INTO processed_messages (tenant, chat_jid, message_id, processed_at)
+         VALUES (?, ?, ?, ?)",
+        [
+            tenant,
+            chat_jid,
+            message_id,
+            &chrono::Utc::now().to_rfc3339(),
+        ],
+    )?;
+    conn.execute(
+        "DELETE FROM processed_messages WHERE tenant = ?1 AND id <= (
+            SELECT id FROM processed_messages WHERE tenant = ?1
+            ORDER BY id DESC LIMIT 1 OFFSET ?2
+        )",
+        crate::params![tenant, capacity as i64],
+    )?;
+    Ok(!seen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_mark_processed_detects_duplicates() {
+        let (db, _dir) = test_database();
+
+        assert!(mark_processed(&db, "telegram:group:-1", "42").unwrap());
+        assert!(!mark_processed(&db, "telegram:group:-1", "42").unwrap());
+        // Same ID in another chat, and another ID sent in the same second
+        assert!(mark_processed(&db, "123@g.us", "42").unwrap());
+        assert!(mark_processed(&db, "telegram:group:-1", "43").unwrap());
+    }
+
+    #[test]
+    fn test_mark_processed_evicts_least_recently_seen() {
+        let (db, _dir) = test_database();
+
+        for id in ["a", "b", "c"] {
+            assert!(mark_processed_with_capacity(&db, "chat", id, 3).unwrap());
+        }
+        // Touch "a" so "b" becomes the oldest
+        assert!(!mark_processed_with_capacity(&db, "chat", "a", 3).unwrap());
+        assert!(mark_processed_with_capacity(&db, "chat", "d", 3).unwrap());
+
+        assert!(!mark_processed_with_capacity(&db, "chat", "a", 3).unwrap());
+        assert!(mark_processed_with_capacity(&db, "chat", "b", 3).unwrap());
+
+        let conn = db.get_connection().unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM processed_messages", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+}
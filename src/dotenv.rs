@@ -0,0 +1,120 @@
+//! `.env` Files for NuClaw
+//!
+//! At startup NuClaw reads `.env` and `.env.local` from the working
+//! directory, before anything reads the environment, so settings don't
+//! have to be exported. `--no-dotenv` skips them.
+//!
+//! Precedence, highest first:
+//!
+//! 1. Command-line flags (see `settings`)
+//! 2. Variables exported in the environment
+//! 3. `.env.local`, for machine-specific values kept out of version control
+//! 4. `.env`
+//! 5. The config file (see `config_file`)
+//!
+//! A file never replaces a variable that is already set, so each source
+//! only fills in what the ones above it leave unset. Unlike the config
+//! file, `.env` files are read once and not watched.
+
+use crate::error::{NuClawError, Result};
+use crate::settings::{record_source, Source};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// `.env` files, the one that wins first
+pub const DOTENV_FILES: &[&str] = &[".env.local", ".env"];
+
+/// Values by variable, with the file each came from
+type DotenvVars = BTreeMap<String, (String, PathBuf)>;
+
+/// Variables of the `.env` files in `dir`, and the files that exist
+fn read_dotenv(dir: &Path) -> Result<(Vec<PathBuf>, DotenvVars)> {
+    let mut files = vec![];
+    let mut vars = BTreeMap::new();
+    for name in DOTENV_FILES {
+        let path = dir.join(name);
+        let iter = match dotenvy::from_path_iter(&path) {
+            Ok(iter) => iter,
+            Err(e) if e.not_found() => continue,
+            Err(e) => return Err(invalid(&path, e)),
+        };
+        for item in iter {
+            let (key, value) = item.map_err(|e| invalid(&path, e))?;
+            vars.entry(key).or_insert((value, path.clone()));
+        }
+        files.push(path);
+    }
+    Ok((files, vars))
+}
+
+fn invalid(path: &Path, e: dotenvy::Error) -> NuClawError {
+    NuClawError::Config {
+        message: format!("Invalid {}: {}", path.display(), e),
+    }
+}
+
+/// Put the variables of the `.env` files in `dir` into the environment,
+/// except those the environment already sets; returns the files read
+pub fn load_dotenv(dir: &Path) -> Result<Vec<PathBuf>> {
+    let (files, vars) = read_dotenv(dir)?;
+    for (key, (value, path)) in vars {
+        if std::env::var_os(&key).is_none() {
+            std::env::set_var(&key, value);
+            record_source(&key, Source::File(path));
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_file_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(".env"),
+            "# shared\nASSISTANT_NAME=Andy\nexport CONTAINER_TIMEOUT=600000\nTZ='Europe/Berlin'\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join(".env.local"), "ASSISTANT_NAME=\"Jarvis\"\n").unwrap();
+
+        let (files, vars) = read_dotenv(dir.path()).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            vars["ASSISTANT_NAME"],
+            ("Jarvis".to_string(), dir.path().join(".env.local"))
+        );
+        assert_eq!(vars["CONTAINER_TIMEOUT"].0, "600000");
+        assert_eq!(vars["TZ"].0, "Europe/Berlin");
+    }
+
+    #[test]
+    fn test_environment_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(".env"),
+            "NUCLAW_TEST_DOTENV_SET=file\nNUCLAW_TEST_DOTENV_UNSET=file\n",
+        )
+        .unwrap();
+        std::env::set_var("NUCLAW_TEST_DOTENV_SET", "env");
+        std::env::remove_var("NUCLAW_TEST_DOTENV_UNSET");
+
+        assert_eq!(
+            load_dotenv(dir.path()).unwrap(),
+            vec![dir.path().join(".env")]
+        );
+        assert_eq!(std::env::var("NUCLAW_TEST_DOTENV_SET").unwrap(), "env");
+        assert_eq!(std::env::var("NUCLAW_TEST_DOTENV_UNSET").unwrap(), "file");
+        std::env::remove_var("NUCLAW_TEST_DOTENV_SET");
+        std::env::remove_var("NUCLAW_TEST_DOTENV_UNSET");
+
+        assert!(load_dotenv(&dir.path().join("missing")).unwrap().is_empty());
+        std::fs::write(dir.path().join(".env.local"), "NOT VALID\n").unwrap();
+        assert!(matches!(
+            load_dotenv(dir.path()),
+            Err(NuClawError::Config { .. })
+        ));
+    }
+}
@@ -28,11 +28,20 @@ pub enum NuClawError {
     #[error("Timeout error: {operation}")]
     Timeout { operation: String },
 
+    #[error("Cancelled: {message}")]
+    Cancelled { message: String },
+
     #[error("Authentication error: {message}")]
     Auth { message: String },
 
     #[error("Scheduler error: {message}")]
     Scheduler { message: String },
+
+    #[error("Transcription error: {message}")]
+    Transcription { message: String },
+
+    #[error("Embedding error: {message}")]
+    Embedding { message: String },
 }
 
 pub type Result<T> = std::result::Result<T, NuClawError>;
@@ -45,6 +54,17 @@ impl From<rusqlite::Error> for NuClawError {
     }
 }
 
+impl From<postgres::Error> for NuClawError {
+    fn from(e: postgres::Error) -> Self {
+        // The server's message is more useful than the generic "db error"
+        let message = match e.as_db_error() {
+            Some(db) => format!("{}: {}", db.severity(), db.message()),
+            None => e.to_string(),
+        };
+        NuClawError::Database { message }
+    }
+}
+
 impl From<std::io::Error> for NuClawError {
     fn from(e: std::io::Error) -> Self {
         NuClawError::FileSystem {
@@ -130,5 +150,11 @@ mod tests {
         let _ = NuClawError::Scheduler {
             message: "test".to_string(),
         };
+        let _ = NuClawError::Transcription {
+            message: "test".to_string(),
+        };
+        let _ = NuClawError::Embedding {
+            message: "test".to_string(),
+        };
     }
 }
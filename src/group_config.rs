@@ -0,0 +1,275 @@
+//! Per-Group Configuration Files for NuClaw
+//!
+//! A group can keep settings of its own in `groups/<folder>.toml` in the
+//! config directory, overriding the global ones for that group only:
+//!
+//! ```toml
+//! trigger = "@Jarvis"
+//! container_image = "ghcr.io/example/agent-python:1"
+//! container_timeout = 600000
+//! container_network = "none"
+//! context_messages = 50
+//! ack_reaction = ""
+//! read_receipts = false
+//! prompt_guard = "flag"
+//!
+//! [permissions]
+//! tasks = "member"
+//!
+//! [agent]
+//! allowed_tools = ["Read", "Grep", "WebSearch"]
+//! network_allowed = false
+//! writable_paths = ["/workspace/group/notes"]
+//! ```
+//!
+//! The file is read each time a message is handled or an agent runs, so
+//! edits apply to the next message without a restart. An invalid file is
+//! logged and ignored, leaving the group on its registered settings.
+//!
+//! It is kept out of the group folder, which the agent can write to: an
+//! agent could otherwise lift its own limits for the next run. A
+//! `nuclaw.toml` left in a group folder by earlier versions is not read.
+
+use crate::config::{self, config_dir, groups_dir};
+use crate::error::{NuClawError, Result};
+use crate::prompt_guard::GuardAction;
+use crate::roles::PermissionSettings;
+use crate::types::{AgentPermissions, RegisteredGroup};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Name of the config file earlier versions read from a group folder
+pub const LEGACY_GROUP_CONFIG_FILE: &str = "nuclaw.toml";
+
+/// Settings of a group's config file; unset fields keep the group's
+/// registered settings or the global defaults
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroupSettings {
+    /// Primary trigger word, replacing the registered one; aliases stay
+    pub trigger: Option<String>,
+    /// Image of the agent container
+    pub container_image: Option<String>,
+    /// Time limit of agent runs in milliseconds
+    pub container_timeout: Option<u64>,
+    /// Docker network of the agent container, e.g. `"none"`
+    pub container_network: Option<String>,
+    /// Earlier messages passed to the agent, instead of `CONTEXT_MESSAGES`
+    pub context_messages: Option<usize>,
+    /// Reactions on triggered messages; an empty value disables one
+    pub ack_reaction: Option<String>,
+    pub done_reaction: Option<String>,
+    pub error_reaction: Option<String>,
+    /// Mark triggered messages as read
+    pub read_receipts: Option<bool>,
+    /// Show "typing" while the agent runs
+    pub presence: Option<bool>,
+    /// Roles needed for admin functions in this group (see `roles`)
+    pub permissions: PermissionSettings,
+    /// What is done with messages matching the content guard, instead of
+    /// `PROMPT_GUARD` (see `prompt_guard`)
+    pub prompt_guard: Option<GuardAction>,
+    /// Tools, network, and writable paths of the group's agent
+    pub agent: Option<AgentPermissions>,
+}
+
+impl GroupSettings {
+    /// A registered group with these settings applied
+    pub fn apply(&self, group: &RegisteredGroup) -> RegisteredGroup {
+        let mut group = group.clone();
+        if let Some(trigger) = self.trigger.as_ref().filter(|t| !t.trim().is_empty()) {
+            group.trigger = trigger.trim().to_string();
+        }
+        group.read_receipts = self.read_receipts.or(group.read_receipts);
+        group.presence = self.presence.or(group.presence);
+        if self.container_image.is_some()
+            || self.container_timeout.is_some()
+            || self.container_network.is_some()
+            || self.agent.is_some()
+        {
+            let container = group.container_config.get_or_insert_with(Default::default);
+            if let Some(image) = &self.container_image {
+                container.image = Some(image.clone());
+            }
+            if let Some(timeout) = self.container_timeout {
+                container.timeout = Some(timeout);
+            }
+            if let Some(network) = &self.container_network {
+                container.network = Some(network.clone());
+            }
+            if let Some(agent) = &self.agent {
+                container.permissions = Some(agent.clone());
+            }
+        }
+        group
+    }
+
+    /// Reaction set on a triggered message once it is accepted
+    pub fn ack_reaction(&self) -> Option<String> {
+        reaction(&self.ack_reaction, config::ack_reaction)
+    }
+
+    /// Reaction replacing the ack after a successful reply
+    pub fn done_reaction(&self) -> Option<String> {
+        reaction(&self.done_reaction, config::done_reaction)
+    }
+
+    /// Reaction replacing the ack after a failed run
+    pub fn error_reaction(&self) -> Option<String> {
+        reaction(&self.error_reaction, config::error_reaction)
+    }
+}
+
+fn reaction(value: &Option<String>, global: fn() -> Option<String>) -> Option<String> {
+    match value {
+        Some(v) if v.trim().is_empty() => None,
+        Some(v) => Some(v.trim().to_string()),
+        None => global(),
+    }
+}
+
+/// Path of a group folder's config file
+pub fn group_config_path(group_folder: &str) -> PathBuf {
+    config_dir()
+        .join("groups")
+        .join(format!("{}.toml", group_folder))
+}
+
+/// Config files of registered groups in their group folders, where earlier
+/// versions kept them, with the path each belongs at now
+pub fn legacy_group_config_files() -> Vec<(PathBuf, PathBuf)> {
+    crate::groups::load_all_registered_groups()
+        .into_iter()
+        .map(|group| {
+            (
+                groups_dir()
+                    .join(&group.folder)
+                    .join(LEGACY_GROUP_CONFIG_FILE),
+                group_config_path(&group.folder),
+            )
+        })
+        .filter(|(legacy, _)| legacy.is_file())
+        .collect()
+}
+
+/// Parse the contents of a group config file
+pub fn parse_group_settings(path: &Path, contents: &str) -> Result<GroupSettings> {
+    toml::from_str(contents).map_err(|e| NuClawError::Config {
+        message: format!("Invalid {}: {}", path.display(), e),
+    })
+}
+
+/// Settings of a group folder's config file; defaults if there is none or
+/// it is invalid
+pub fn group_settings(group_folder: &str) -> GroupSettings {
+    let path = group_config_path(group_folder);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return GroupSettings::default(),
+        Err(e) => {
+            warn!("Failed to read {}: {}", path.display(), e);
+            return GroupSettings::default();
+        }
+    };
+    parse_group_settings(&path, &contents).unwrap_or_else(|e| {
+        warn!("Ignoring the group settings: {}", e);
+        GroupSettings::default()
+    })
+}
+
+/// A registered group with its folder's config file applied
+pub fn with_group_file(group: &RegisteredGroup) -> RegisteredGroup {
+    group_settings(&group.folder).apply(group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ContainerConfig;
+
+    fn group() -> RegisteredGroup {
+        RegisteredGroup {
+            name: "Family".to_string(),
+            folder: "family".to_string(),
+            trigger: "@Andy".to_string(),
+            added_at: String::new(),
+            aliases: vec!["@bot".to_string()],
+            read_receipts: Some(true),
+            presence: None,
+            container_config: Some(ContainerConfig {
+                memory: Some("4g".to_string()),
+                image: Some("claw:1".to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_apply_group_settings() {
+        let path = Path::new("groups/family/nuclaw.toml");
+        let settings = parse_group_settings(
+            path,
+            r#"
+            trigger = "@Jarvis"
+            container_image = "claw:2"
+            container_timeout = 600000
+            container_network = "none"
+            context_messages = 50
+            presence = false
+            prompt_guard = "block"
+
+            [permissions]
+            tasks = "member"
+
+            [agent]
+            allowed_tools = ["Read", "Grep"]
+            network_allowed = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.context_messages, Some(50));
+        assert_eq!(settings.permissions.tasks, Some(crate::roles::Role::Member));
+        assert_eq!(settings.prompt_guard, Some(GuardAction::Block));
+
+        let applied = settings.apply(&group());
+        assert_eq!(applied.triggers(), vec!["@Jarvis", "@bot"]);
+        assert_eq!(applied.read_receipts, Some(true));
+        assert_eq!(applied.presence, Some(false));
+        let container = applied.container_config.unwrap();
+        assert_eq!(container.image.as_deref(), Some("claw:2"));
+        assert_eq!(container.timeout, Some(600000));
+        assert_eq!(container.network.as_deref(), Some("none"));
+        assert_eq!(container.memory.as_deref(), Some("4g"));
+        let permissions = container.permissions.unwrap();
+        assert_eq!(permissions.network_allowed, Some(false));
+        assert_eq!(permissions.writable_paths, None);
+
+        let unchanged = GroupSettings::default().apply(&group());
+        assert_eq!(unchanged.trigger, "@Andy");
+        assert_eq!(unchanged.container_config, group().container_config);
+    }
+
+    #[test]
+    fn test_invalid_group_settings() {
+        let path = Path::new("groups/family/nuclaw.toml");
+        for invalid in ["triger = \"@Jarvis\"", "container_timeout = \"soon\""] {
+            assert!(matches!(
+                parse_group_settings(path, invalid),
+                Err(NuClawError::Config { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_group_reactions() {
+        let settings = GroupSettings {
+            ack_reaction: Some(String::new()),
+            done_reaction: Some(" ✅ ".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(settings.ack_reaction(), None);
+        assert_eq!(settings.done_reaction().as_deref(), Some("✅"));
+        assert_eq!(settings.error_reaction(), config::error_reaction());
+    }
+}
@@ -0,0 +1,668 @@
+//! Group Registration for NuClaw
+//!
+//! Registered groups live in `data/registered_groups.json`, keyed by chat
+//! JID. Each group gets its own folder under `groups/` that is mounted
+//! into the agent container, and its own trigger words.
+//!
+//! Each tenant has its own registry in its data directory (see
+//! `tenants`), and the folders of its groups are prefixed with its ID.
+//!
+//! A private chat whose user passes the DM policy is registered on its
+//! own under `dm-<channel>-<user>`, so the user has a folder to talk to.
+//!
+//! Unregistering a group moves its folder to `groups/.archive/`; renaming
+//! one moves its folder and the rows that refer to it. Either applies to
+//! the registry, the folder, and the database together, or to none.
+
+use crate::config::groups_dir;
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use crate::group_config::group_config_path;
+use crate::tenants::{load_tenants, tenant, tenant_data_dir};
+use crate::types::RegisteredGroup;
+use crate::utils::json::{load_json, save_json};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Path of a tenant's registered groups file
+pub fn registered_groups_path(tenant_id: &str) -> PathBuf {
+    tenant_data_dir(tenant_id).join("registered_groups.json")
+}
+
+/// Load a tenant's registered groups from file
+pub fn load_registered_groups(tenant_id: &str) -> HashMap<String, RegisteredGroup> {
+    load_json(&registered_groups_path(tenant_id), HashMap::new())
+}
+
+/// Chat JID and entry of a tenant's group registered under `folder`,
+/// given with or without the tenant's prefix
+pub fn find_group(tenant_id: &str, folder: &str) -> Option<(String, RegisteredGroup)> {
+    find_group_in(&registered_groups_path(tenant_id), tenant_id, folder)
+}
+
+pub(crate) fn find_group_in(
+    registry_path: &Path,
+    tenant_id: &str,
+    folder: &str,
+) -> Option<(String, RegisteredGroup)> {
+    let prefixed = tenant(tenant_id).group_folder(folder);
+    load_json::<HashMap<String, RegisteredGroup>>(registry_path, HashMap::new())
+        .into_iter()
+        .find(|(_, group)| group.folder == folder || group.folder == prefixed)
+}
+
+/// Registered groups of every tenant
+///
+/// Folders are unique across tenants, so settings can be looked up by
+/// folder here.
+pub fn load_all_registered_groups() -> Vec<RegisteredGroup> {
+    let ids = match load_tenants() {
+        Ok(tenants) => tenants.into_iter().map(|t| t.id).collect(),
+        Err(e) => {
+            tracing::warn!("{}", e);
+            vec![crate::tenants::DEFAULT_TENANT.to_string()]
+        }
+    };
+    ids.iter()
+        .flat_map(|id| load_registered_groups(id).into_values())
+        .collect()
+}
+
+/// Register a chat of a tenant under a group folder
+///
+/// Creates `groups/<folder>` (prefixed with the tenant ID for other
+/// tenants than the default one) and persists the entry. The group name
+/// defaults to the folder name and the trigger to `@<assistant name>`.
+pub fn register_group(tenant_id: &str, chat_jid: &str, folder: &str) -> Result<RegisteredGroup> {
+    validate_folder_name(folder)?;
+    let tenant = tenant(tenant_id);
+    register_group_in(
+        &registered_groups_path(tenant_id),
+        &groups_dir(),
+        chat_jid,
+        &tenant.group_folder(folder),
+        &tenant.assistant_name(),
+    )
+}
+
+fn register_group_in(
+    registry_path: &Path,
+    groups_root: &Path,
+    chat_jid: &str,
+    folder: &str,
+    assistant_name: &str,
+) -> Result<RegisteredGroup> {
+    validate_folder_name(folder)?;
+
+    let mut groups: HashMap<String, RegisteredGroup> = load_json(registry_path, HashMap::new());
+    if let Some(existing) = groups.get(chat_jid) {
+        return Err(NuClawError::Validation {
+            message: format!(
+                "This chat is already registered to folder '{}'",
+                existing.folder
+            ),
+        });
+    }
+
+    std::fs::create_dir_all(groups_root.join(folder)).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to create group directory: {}", e),
+    })?;
+
+    let group = RegisteredGroup {
+        name: folder.to_string(),
+        folder: folder.to_string(),
+        trigger: format!("@{}", assistant_name),
+        added_at: chrono::Utc::now().to_rfc3339(),
+        aliases: vec![],
+        read_receipts: None,
+        presence: None,
+        container_config: None,
+    };
+    groups.insert(chat_jid.to_string(), group.clone());
+
+    save_json(registry_path, &groups).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to save registered groups: {}", e),
+    })?;
+
+    Ok(group)
+}
+
+/// Folder of a user's private chat: `dm-<channel>-<user>`, with the
+/// user ID's domain dropped and other characters folders cannot hold
+/// replaced by `_`
+pub fn dm_folder(channel: &str, user_id: &str) -> String {
+    let user: String = user_id
+        .split('@')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("dm-{}-{}", channel, user)
+}
+
+/// Register a tenant's private chat under its user's `dm_folder`, unless
+/// the chat is registered already; its registration either way
+pub fn register_dm_chat(
+    tenant_id: &str,
+    channel: &str,
+    chat_jid: &str,
+    user_id: &str,
+) -> Result<RegisteredGroup> {
+    let registry_path = registered_groups_path(tenant_id);
+    let groups: HashMap<String, RegisteredGroup> = load_json(&registry_path, HashMap::new());
+    if let Some(group) = groups.get(chat_jid) {
+        return Ok(group.clone());
+    }
+    let tenant = tenant(tenant_id);
+    let mut folder = tenant.group_folder(&dm_folder(channel, user_id));
+    folder.truncate(64);
+    register_group_in(
+        &registry_path,
+        &groups_dir(),
+        chat_jid,
+        &folder,
+        &tenant.assistant_name(),
+    )
+}
+
+/// Replace the trigger words of a tenant's registered chat
+///
+/// The first trigger becomes the primary one, the rest are aliases.
+pub fn set_triggers(
+    tenant_id: &str,
+    chat_jid: &str,
+    triggers: &[String],
+) -> Result<RegisteredGroup> {
+    set_triggers_in(&registered_groups_path(tenant_id), chat_jid, triggers)
+}
+
+fn set_triggers_in(
+    registry_path: &Path,
+    chat_jid: &str,
+    triggers: &[String],
+) -> Result<RegisteredGroup> {
+    let (primary, aliases) = triggers
+        .split_first()
+        .ok_or_else(|| NuClawError::Validation {
+            message: "At least one trigger is required".to_string(),
+        })?;
+
+    let mut groups: HashMap<String, RegisteredGroup> = load_json(registry_path, HashMap::new());
+    let group = groups
+        .get_mut(chat_jid)
+        .ok_or_else(|| NuClawError::Validation {
+            message: "This chat is not registered; use /register <folder> first".to_string(),
+        })?;
+    group.trigger = primary.clone();
+    group.aliases = aliases.to_vec();
+    let group = group.clone();
+
+    save_json(registry_path, &groups).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to save registered groups: {}", e),
+    })?;
+
+    Ok(group)
+}
+
+/// Directory of unregistered groups' folders, under `groups/`
+const ARCHIVE_DIR: &str = ".archive";
+
+/// A group removed by `unregister_group`
+#[derive(Debug, Clone)]
+pub struct Unregistered {
+    pub chat_jid: String,
+    pub group: RegisteredGroup,
+    /// Scheduled tasks deleted with the group
+    pub deleted_tasks: usize,
+    /// Where the group's folder was moved, if it had one
+    pub archived_to: Option<PathBuf>,
+}
+
+/// Unregister the group of the database handle's tenant registered under
+/// `folder`
+///
+/// The group's scheduled tasks are deleted with their runs and its folder
+/// is moved to `groups/.archive/<folder>-<time>`. Its memories and agent
+/// state stay, for a group registered under the folder again.
+pub fn unregister_group(db: &Database, folder: &str) -> Result<Unregistered> {
+    let (_, group) = find_group(db.tenant(), folder).ok_or_else(|| not_registered(folder))?;
+    unregister_group_in(
+        db,
+        &registered_groups_path(db.tenant()),
+        &groups_dir(),
+        &group.folder,
+    )
+}
+
+fn unregister_group_in(
+    db: &Database,
+    registry_path: &Path,
+    groups_root: &Path,
+    folder: &str,
+) -> Result<Unregistered> {
+    let mut groups: HashMap<String, RegisteredGroup> = load_json(registry_path, HashMap::new());
+    let chat_jid = groups
+        .iter()
+        .find(|(_, group)| group.folder == folder)
+        .map(|(chat_jid, _)| chat_jid.clone())
+        .ok_or_else(|| not_registered(folder))?;
+    let Some(group) = groups.remove(&chat_jid) else {
+        return Err(not_registered(folder));
+    };
+
+    let mut conn = db.get_connection()?;
+    let tx = conn.transaction()?;
+    let tenant = db.tenant();
+    tx.execute(
+        "DELETE FROM task_run_logs WHERE tenant = ? AND task_id IN
+            (SELECT id FROM scheduled_tasks WHERE tenant = ? AND group_folder = ?)",
+        [tenant, tenant, folder],
+    )?;
+    let deleted_tasks = tx.execute(
+        "DELETE FROM scheduled_tasks WHERE tenant = ? AND group_folder = ?",
+        [tenant, folder],
+    )?;
+
+    let from = groups_root.join(folder);
+    let archived_to = if from.is_dir() {
+        let stamp = chrono::Utc::now().format("%Y%m%d%H%M%S");
+        let to = groups_root
+            .join(ARCHIVE_DIR)
+            .join(format!("{}-{}", folder, stamp));
+        std::fs::create_dir_all(groups_root.join(ARCHIVE_DIR))
+            .and_then(|_| std::fs::rename(&from, &to))
+            .map_err(|e| NuClawError::FileSystem {
+                message: format!("Failed to archive {}: {}", from.display(), e),
+            })?;
+        Some(to)
+    } else {
+        None
+    };
+    let undo_move = || {
+        if let Some(to) = &archived_to {
+            let _ = std::fs::rename(to, &from);
+        }
+    };
+
+    if let Err(e) = save_json(registry_path, &groups) {
+        undo_move();
+        return Err(NuClawError::FileSystem {
+            message: format!("Failed to save registered groups: {}", e),
+        });
+    }
+    if let Err(e) = tx.commit() {
+        groups.insert(chat_jid, group);
+        let _ = save_json(registry_path, &groups);
+        undo_move();
+        return Err(e);
+    }
+
+    Ok(Unregistered {
+        chat_jid,
+        group,
+        deleted_tasks,
+        archived_to,
+    })
+}
+
+/// Move the group of the database handle's tenant registered under
+/// `folder` to `new_folder`
+///
+/// Renames the group's folder and config file, and points its
+/// registration, scheduled tasks, templates, memories, and agent state at
+/// the new one. The group's name follows when it was the folder name.
+pub fn rename_group(db: &Database, folder: &str, new_folder: &str) -> Result<RegisteredGroup> {
+    validate_folder_name(new_folder)?;
+    let (_, group) = find_group(db.tenant(), folder).ok_or_else(|| not_registered(folder))?;
+    let new_folder = tenant(db.tenant()).group_folder(new_folder);
+    let renamed = rename_group_in(
+        db,
+        &registered_groups_path(db.tenant()),
+        &groups_dir(),
+        &group.folder,
+        &new_folder,
+    )?;
+    let config = group_config_path(&group.folder);
+    if config.exists() {
+        if let Err(e) = std::fs::rename(&config, group_config_path(&new_folder)) {
+            tracing::warn!("Failed to move {}: {}", config.display(), e);
+        }
+    }
+    Ok(renamed)
+}
+
+fn rename_group_in(
+    db: &Database,
+    registry_path: &Path,
+    groups_root: &Path,
+    folder: &str,
+    new_folder: &str,
+) -> Result<RegisteredGroup> {
+    validate_folder_name(new_folder)?;
+    let mut groups: HashMap<String, RegisteredGroup> = load_json(registry_path, HashMap::new());
+    let to = groups_root.join(new_folder);
+    if groups.values().any(|g| g.folder == new_folder) || to.exists() {
+        return Err(NuClawError::Validation {
+            message: format!("Folder '{}' is already in use", new_folder),
+        });
+    }
+    let old_groups = groups.clone();
+    let group = groups
+        .values_mut()
+        .find(|group| group.folder == folder)
+        .ok_or_else(|| not_registered(folder))?;
+    if group.name == group.folder {
+        group.name = new_folder.to_string();
+    }
+    group.folder = new_folder.to_string();
+    let group = group.clone();
+
+    let mut conn = db.get_connection()?;
+    let tx = conn.transaction()?;
+    for table in [
+        "scheduled_tasks",
+        "task_templates",
+        "memories",
+        "agent_state",
+    ] {
+        tx.execute(
+            &format!(
+                "UPDATE {} SET group_folder = ? WHERE tenant = ? AND group_folder = ?",
+                table
+            ),
+            [new_folder, db.tenant(), folder],
+        )?;
+    }
+
+    let from = groups_root.join(folder);
+    let moved = from.is_dir();
+    let created = if moved {
+        std::fs::rename(&from, &to)
+    } else {
+        std::fs::create_dir_all(&to)
+    };
+    created.map_err(|e| NuClawError::FileSystem {
+        message: format!(
+            "Failed to move {} to {}: {}",
+            from.display(),
+            to.display(),
+            e
+        ),
+    })?;
+    let undo_move = || {
+        let _ = if moved {
+            std::fs::rename(&to, &from)
+        } else {
+            std::fs::remove_dir(&to)
+        };
+    };
+
+    if let Err(e) = save_json(registry_path, &groups) {
+        undo_move();
+        return Err(NuClawError::FileSystem {
+            message: format!("Failed to save registered groups: {}", e),
+        });
+    }
+    if let Err(e) = tx.commit() {
+        let _ = save_json(registry_path, &old_groups);
+        undo_move();
+        return Err(e);
+    }
+    Ok(group)
+}
+
+fn not_registered(folder: &str) -> NuClawError {
+    NuClawError::Validation {
+        message: format!("No group is registered under folder '{}'", folder),
+    }
+}
+
+/// Find the first trigger word in a message
+///
+/// Matching is case-insensitive and the trigger must end at a word
+/// boundary, so `@Andy` does not fire on `@Andyson`. Returns the
+/// configured trigger and the text following it.
+pub fn match_trigger(content: &str, triggers: &[String]) -> Option<(String, String)> {
+    content
+        .char_indices()
+        .filter(|(idx, _)| *idx == 0 || !is_word_char(content[..*idx].chars().last()))
+        .find_map(|(idx, _)| {
+            let rest = &content[idx..];
+            triggers.iter().find_map(|trigger| {
+                let len = prefix_len_ignore_case(rest, trigger)?;
+                let after = &rest[len..];
+                if is_word_char(after.chars().next()) && is_word_char(trigger.chars().last()) {
+                    return None;
+                }
+                Some((trigger.clone(), after.trim().to_string()))
+            })
+        })
+}
+
+/// Byte length of `prefix` at the start of `s`, compared case-insensitively
+fn prefix_len_ignore_case(s: &str, prefix: &str) -> Option<usize> {
+    if prefix.is_empty() {
+        return None;
+    }
+    let mut chars = s.char_indices();
+    for p in prefix.chars() {
+        let (_, c) = chars.next()?;
+        if !c.to_lowercase().eq(p.to_lowercase()) {
+            return None;
+        }
+    }
+    Some(chars.next().map(|(i, _)| i).unwrap_or(s.len()))
+}
+
+fn is_word_char(c: Option<char>) -> bool {
+    c.is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Check that a folder name is safe to use as a single path component
+pub fn validate_folder_name(folder: &str) -> Result<()> {
+    let valid = !folder.is_empty()
+        && folder.len() <= 64
+        && !folder.starts_with('.')
+        && folder
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(NuClawError::Validation {
+            message: format!(
+                "Invalid folder name '{}': use letters, digits, '-', '_' or '.'",
+                folder
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_validate_folder_name() {
+        assert!(validate_folder_name("family-chat").is_ok());
+        assert!(validate_folder_name("team_1.dev").is_ok());
+        assert!(validate_folder_name("").is_err());
+        assert!(validate_folder_name("..").is_err());
+        assert!(validate_folder_name(".hidden").is_err());
+        assert!(validate_folder_name("a/b").is_err());
+        assert!(validate_folder_name("a b").is_err());
+        assert!(validate_folder_name(&"x".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn test_dm_folder() {
+        assert_eq!(dm_folder("telegram", "12345"), "dm-telegram-12345");
+        assert_eq!(
+            dm_folder("whatsapp", "4915100@s.whatsapp.net"),
+            "dm-whatsapp-4915100"
+        );
+        assert_eq!(dm_folder("telegram", "a/b"), "dm-telegram-a_b");
+    }
+
+    #[test]
+    fn test_register_group_persists_and_creates_folder() {
+        let dir = TempDir::new().unwrap();
+        let registry = dir.path().join("data").join("registered_groups.json");
+        let groups_root = dir.path().join("groups");
+
+        let group = register_group_in(
+            &registry,
+            &groups_root,
+            "telegram:group:-100",
+            "family",
+            "Andy",
+        )
+        .unwrap();
+        assert_eq!(group.folder, "family");
+        assert_eq!(group.trigger, "@Andy");
+        assert!(groups_root.join("family").is_dir());
+
+        let saved: HashMap<String, RegisteredGroup> = load_json(&registry, HashMap::new());
+        assert_eq!(saved["telegram:group:-100"].folder, "family");
+    }
+
+    fn triggers(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn test_match_trigger_case_insensitive() {
+        let t = triggers(&["@Andy"]);
+        assert_eq!(
+            match_trigger("@andy hello", &t),
+            Some(("@Andy".to_string(), "hello".to_string()))
+        );
+        assert_eq!(
+            match_trigger("hey @ANDY help me", &t),
+            Some(("@Andy".to_string(), "help me".to_string()))
+        );
+        assert_eq!(match_trigger("hello", &t), None);
+    }
+
+    #[test]
+    fn test_match_trigger_word_boundary() {
+        let t = triggers(&["@Andy"]);
+        assert!(match_trigger("@Andyson hi", &t).is_none());
+        assert!(match_trigger("mail@Andy.com", &t).is_none());
+        assert!(match_trigger("(@Andy) hi", &t).is_some());
+        assert!(match_trigger("@Andy, hi", &t).is_some());
+        assert!(match_trigger("x@Andy hi", &t).is_none());
+    }
+
+    #[test]
+    fn test_match_trigger_aliases() {
+        let t = triggers(&["@Andy", "hey bot", "Ассистент"]);
+        assert_eq!(
+            match_trigger("Hey Bot what's up", &t),
+            Some(("hey bot".to_string(), "what's up".to_string()))
+        );
+        assert_eq!(
+            match_trigger("ассистент привет", &t),
+            Some(("Ассистент".to_string(), "привет".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_set_triggers() {
+        let dir = TempDir::new().unwrap();
+        let registry = dir.path().join("registered_groups.json");
+
+        assert!(set_triggers_in(&registry, "chat@g.us", &triggers(&["@Bot"])).is_err());
+
+        register_group_in(&registry, dir.path(), "chat@g.us", "one", "Andy").unwrap();
+        assert!(set_triggers_in(&registry, "chat@g.us", &[]).is_err());
+
+        let group =
+            set_triggers_in(&registry, "chat@g.us", &triggers(&["@Bot", "@Helper"])).unwrap();
+        assert_eq!(group.trigger, "@Bot");
+        assert_eq!(group.aliases, vec!["@Helper".to_string()]);
+
+        let saved: HashMap<String, RegisteredGroup> = load_json(&registry, HashMap::new());
+        assert_eq!(
+            saved["chat@g.us"].triggers(),
+            triggers(&["@Bot", "@Helper"])
+        );
+    }
+
+    #[test]
+    fn test_unregister_group() {
+        let (db, dir) = crate::db::test_database();
+        let registry = dir.path().join("registered_groups.json");
+        let groups_root = dir.path().join("groups");
+        register_group_in(&registry, &groups_root, "chat@g.us", "family", "Andy").unwrap();
+        let task = crate::task_scheduler::create_task(
+            &db,
+            crate::task_scheduler::NewTask {
+                group_folder: "family".to_string(),
+                chat_jid: "chat@g.us".to_string(),
+                prompt: "Summarize".to_string(),
+                schedule_type: "interval".to_string(),
+                schedule_value: "1h".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let removed = unregister_group_in(&db, &registry, &groups_root, "family").unwrap();
+        assert_eq!(removed.chat_jid, "chat@g.us");
+        assert_eq!(removed.deleted_tasks, 1);
+        assert!(!groups_root.join("family").exists());
+        assert!(removed.archived_to.unwrap().is_dir());
+        assert!(db.tasks().get(&task.id).unwrap().is_none());
+        assert!(
+            load_json::<HashMap<String, RegisteredGroup>>(&registry, HashMap::new()).is_empty()
+        );
+
+        assert!(unregister_group_in(&db, &registry, &groups_root, "family").is_err());
+    }
+
+    #[test]
+    fn test_rename_group() {
+        let (db, dir) = crate::db::test_database();
+        let registry = dir.path().join("registered_groups.json");
+        let groups_root = dir.path().join("groups");
+        register_group_in(&registry, &groups_root, "one@g.us", "family", "Andy").unwrap();
+        register_group_in(&registry, &groups_root, "two@g.us", "work", "Andy").unwrap();
+        std::fs::write(groups_root.join("family").join("CLAUDE.md"), "notes").unwrap();
+        crate::memory::add_memory(&db, "family", "Likes tea").unwrap();
+
+        assert!(rename_group_in(&db, &registry, &groups_root, "family", "work").is_err());
+        assert!(rename_group_in(&db, &registry, &groups_root, "family", "../x").is_err());
+
+        let group = rename_group_in(&db, &registry, &groups_root, "family", "home").unwrap();
+        assert_eq!(
+            (group.name.as_str(), group.folder.as_str()),
+            ("home", "home")
+        );
+        assert!(groups_root.join("home").join("CLAUDE.md").is_file());
+        assert!(!groups_root.join("family").exists());
+        assert_eq!(crate::memory::list_memories(&db, "home").unwrap().len(), 1);
+        let saved: HashMap<String, RegisteredGroup> = load_json(&registry, HashMap::new());
+        assert_eq!(saved["one@g.us"].folder, "home");
+        assert_eq!(saved["two@g.us"].folder, "work");
+    }
+
+    #[test]
+    fn test_register_group_rejects_duplicate_chat() {
+        let dir = TempDir::new().unwrap();
+        let registry = dir.path().join("registered_groups.json");
+
+        register_group_in(&registry, dir.path(), "chat@g.us", "one", "Andy").unwrap();
+        assert!(register_group_in(&registry, dir.path(), "chat@g.us", "two", "Andy").is_err());
+        assert!(!dir.path().join("two").exists());
+    }
+}
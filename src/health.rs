@@ -0,0 +1,414 @@
+//! Health Checks for NuClaw
+//!
+//! `GET /health` on the HTTP server (see `http_server`) reports each component with a
+//! status of `healthy`, `degraded`, or `unhealthy`:
+//!
+//! - `database` - a query on the pool; degraded while every connection
+//!   is busy
+//! - `container_runtime` - the Docker (or Apple Container) daemon answers,
+//!   checked at most every 30 seconds
+//! - `telegram`, `whatsapp` - the Bot API and the WhatsApp MCP bridge
+//!   answered the latest request; degraded while failing for less than
+//!   five minutes, then unhealthy
+//! - `scheduler` - the task scheduler loop made a pass recently
+//!
+//! Channels and schedulers of other tenants are listed as
+//! `<component>:<tenant>`. Components only appear once they ran, so a
+//! process serving just WhatsApp reports no Telegram component.
+//!
+//! The overall status is the worst of the components. Load balancers get
+//! `200` while it is healthy or degraded and `503` once it is unhealthy.
+
+use crate::container_runner::{container_backend, get_container_command};
+use crate::db::Database;
+use crate::tenants::DEFAULT_TENANT;
+use crate::types::{ContainerBackend, ContainerConfig};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Time a failing channel stays degraded before it is unhealthy
+const UNHEALTHY_AFTER: Duration = Duration::from_secs(300);
+/// Time a container runtime check is reused for
+const RUNTIME_CHECK_TTL: Duration = Duration::from_secs(30);
+/// Time limit of a container runtime check
+const RUNTIME_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Status of a component, or of NuClaw as a whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    pub detail: String,
+}
+
+impl ComponentHealth {
+    fn new(status: HealthStatus, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Body of `GET /health`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub components: BTreeMap<String, ComponentHealth>,
+}
+
+impl HealthReport {
+    fn new(components: BTreeMap<String, ComponentHealth>) -> Self {
+        let status = components
+            .values()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(HealthStatus::Healthy);
+        Self { status, components }
+    }
+
+    /// HTTP status for load balancers
+    pub fn status_code(&self) -> StatusCode {
+        match self.status {
+            HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::OK,
+        }
+    }
+}
+
+/// What a running component last reported
+#[derive(Debug, Clone)]
+enum Signal {
+    /// Outcome of the latest request to an external service
+    Probe {
+        failing_since: Option<Instant>,
+        error: String,
+    },
+    /// Latest pass of a loop expected every `stale_after` at most
+    Heartbeat { at: Instant, stale_after: Duration },
+}
+
+impl Signal {
+    fn health(&self, now: Instant) -> ComponentHealth {
+        match self {
+            Signal::Probe {
+                failing_since: None,
+                ..
+            } => ComponentHealth::new(HealthStatus::Healthy, "reachable"),
+            Signal::Probe {
+                failing_since: Some(since),
+                error,
+            } => {
+                let failing = now.saturating_duration_since(*since);
+                let status = if failing < UNHEALTHY_AFTER {
+                    HealthStatus::Degraded
+                } else {
+                    HealthStatus::Unhealthy
+                };
+                ComponentHealth::new(
+                    status,
+                    format!("unreachable for {}s: {}", failing.as_secs(), error),
+                )
+            }
+            Signal::Heartbeat { at, stale_after } => {
+                let age = now.saturating_duration_since(*at);
+                let status = if age <= *stale_after {
+                    HealthStatus::Healthy
+                } else if age <= *stale_after * 2 {
+                    HealthStatus::Degraded
+                } else {
+                    HealthStatus::Unhealthy
+                };
+                ComponentHealth::new(status, format!("last pass {}s ago", age.as_secs()))
+            }
+        }
+    }
+}
+
+fn signals() -> &'static Mutex<BTreeMap<String, Signal>> {
+    static SIGNALS: OnceLock<Mutex<BTreeMap<String, Signal>>> = OnceLock::new();
+    SIGNALS.get_or_init(Default::default)
+}
+
+/// Name of a tenant's component: `kind` for the default tenant,
+/// `kind:tenant` otherwise
+pub fn component(kind: &str, tenant: &str) -> String {
+    if tenant == DEFAULT_TENANT {
+        kind.to_string()
+    } else {
+        format!("{}:{}", kind, tenant)
+    }
+}
+
+/// Record a successful request to an external service
+pub fn reachable(component: &str) {
+    signals().lock().unwrap().insert(
+        component.to_string(),
+        Signal::Probe {
+            failing_since: None,
+            error: String::new(),
+        },
+    );
+}
+
+/// Record a failed request to an external service; it keeps failing
+/// since the first failure after a success
+pub fn unreachable(component: &str, error: impl std::fmt::Display) {
+    let mut signals = signals().lock().unwrap();
+    let since = match signals.get(component) {
+        Some(Signal::Probe {
+            failing_since: Some(since),
+            ..
+        }) => *since,
+        _ => Instant::now(),
+    };
+    signals.insert(
+        component.to_string(),
+        Signal::Probe {
+            failing_since: Some(since),
+            error: error.to_string(),
+        },
+    );
+}
+
+/// Record the response to a request to an external service; server
+/// errors count as unreachable, anything else the service answered as
+/// reachable
+pub fn observe(component: &str, response: &reqwest::Result<reqwest::Response>) {
+    match response {
+        Ok(response) if response.status().is_server_error() => {
+            unreachable(component, format!("status {}", response.status()))
+        }
+        Ok(_) => reachable(component),
+        Err(e) => unreachable(component, e),
+    }
+}
+
+/// Record a pass of a loop expected to pass again within `stale_after`
+pub fn heartbeat(component: &str, stale_after: Duration) {
+    signals().lock().unwrap().insert(
+        component.to_string(),
+        Signal::Heartbeat {
+            at: Instant::now(),
+            stale_after,
+        },
+    );
+}
+
+/// Check the database pool
+fn database_health(db: &Database) -> ComponentHealth {
+    let probe = db
+        .read_connection()
+        .and_then(|conn| conn.query_row("SELECT 1", (), |row| row.get::<i64>(0)));
+    if let Err(e) = probe {
+        return ComponentHealth::new(HealthStatus::Unhealthy, e.to_string());
+    }
+    let pool = db.pool_status();
+    let detail = format!(
+        "{} ({} active, {} idle, {} max)",
+        db.backend().as_str(),
+        pool.connections_active,
+        pool.connections_idle,
+        pool.max_size
+    );
+    if pool.connections_idle == 0 && pool.connections_active >= pool.max_size {
+        ComponentHealth::new(
+            HealthStatus::Degraded,
+            format!("pool exhausted: {}", detail),
+        )
+    } else {
+        ComponentHealth::new(HealthStatus::Healthy, detail)
+    }
+}
+
+/// Check the container runtime agents run in
+async fn check_container_runtime() -> ComponentHealth {
+    match container_backend(&ContainerConfig::default()) {
+        Err(e) => ComponentHealth::new(HealthStatus::Unhealthy, e.to_string()),
+        Ok(ContainerBackend::Process) => {
+            ComponentHealth::new(HealthStatus::Healthy, "agents run as local processes")
+        }
+        Ok(ContainerBackend::Wasm) => {
+            ComponentHealth::new(HealthStatus::Healthy, "agents run under wasmtime")
+        }
+        Ok(ContainerBackend::Docker) => {
+            let command = get_container_command();
+            let args: &[&str] = if command == "docker" {
+                &["version", "--format", "{{.Server.Version}}"]
+            } else {
+                &["system", "status"]
+            };
+            let output = tokio::process::Command::new(command)
+                .args(args)
+                .kill_on_drop(true)
+                .output();
+            match tokio::time::timeout(RUNTIME_CHECK_TIMEOUT, output).await {
+                Err(_) => ComponentHealth::new(
+                    HealthStatus::Unhealthy,
+                    format!(
+                        "{} did not answer within {:?}",
+                        command, RUNTIME_CHECK_TIMEOUT
+                    ),
+                ),
+                Ok(Err(e)) => ComponentHealth::new(
+                    HealthStatus::Unhealthy,
+                    format!("failed to run {}: {}", command, e),
+                ),
+                Ok(Ok(output)) if !output.status.success() => ComponentHealth::new(
+                    HealthStatus::Unhealthy,
+                    String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                ),
+                Ok(Ok(output)) => {
+                    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                    ComponentHealth::new(HealthStatus::Healthy, format!("{} {}", command, version))
+                }
+            }
+        }
+    }
+}
+
+/// Check the container runtime, reusing a recent result
+async fn container_runtime_health() -> ComponentHealth {
+    static LAST: OnceLock<Mutex<Option<(Instant, ComponentHealth)>>> = OnceLock::new();
+    let last = LAST.get_or_init(Default::default);
+    if let Some((at, health)) = last.lock().unwrap().as_ref() {
+        if at.elapsed() < RUNTIME_CHECK_TTL {
+            return health.clone();
+        }
+    }
+    let health = check_container_runtime().await;
+    *last.lock().unwrap() = Some((Instant::now(), health.clone()));
+    health
+}
+
+/// Check every component
+pub async fn check(db: &Database) -> HealthReport {
+    let now = Instant::now();
+    let mut components: BTreeMap<String, ComponentHealth> = signals()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, signal)| (name.clone(), signal.health(now)))
+        .collect();
+    let database = db
+        .call(|db| Ok(database_health(db)))
+        .await
+        .unwrap_or_else(|e| ComponentHealth::new(HealthStatus::Unhealthy, e.to_string()));
+    components.insert("database".to_string(), database);
+    components.insert(
+        "container_runtime".to_string(),
+        container_runtime_health().await,
+    );
+    HealthReport::new(components)
+}
+
+async fn health(State(db): State<Database>) -> (StatusCode, Json<HealthReport>) {
+    let report = check(&db).await;
+    (report.status_code(), Json(report))
+}
+
+/// Router serving `GET /health`
+pub fn router(db: Database) -> Router {
+    Router::new().route("/health", get(health)).with_state(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_health() {
+        let now = Instant::now();
+        let failing = |secs| Signal::Probe {
+            failing_since: Some(now - Duration::from_secs(secs)),
+            error: "connection refused".to_string(),
+        };
+        assert_eq!(failing(10).health(now).status, HealthStatus::Degraded);
+        assert_eq!(
+            failing(10).health(now).detail,
+            "unreachable for 10s: connection refused"
+        );
+        assert_eq!(failing(600).health(now).status, HealthStatus::Unhealthy);
+
+        let beat = |secs| Signal::Heartbeat {
+            at: now - Duration::from_secs(secs),
+            stale_after: Duration::from_secs(60),
+        };
+        assert_eq!(beat(30).health(now).status, HealthStatus::Healthy);
+        assert_eq!(beat(90).health(now).status, HealthStatus::Degraded);
+        assert_eq!(beat(300).health(now).status, HealthStatus::Unhealthy);
+    }
+
+    #[test]
+    fn test_probe_keeps_first_failure() {
+        let name = component("telegram", "health-test");
+        assert_eq!(name, "telegram:health-test");
+        assert_eq!(component("telegram", DEFAULT_TENANT), "telegram");
+
+        unreachable(&name, "timed out");
+        let first = match signals().lock().unwrap().get(&name) {
+            Some(Signal::Probe { failing_since, .. }) => *failing_since,
+            _ => None,
+        };
+        unreachable(&name, "connection refused");
+        match signals().lock().unwrap().get(&name) {
+            Some(Signal::Probe {
+                failing_since,
+                error,
+            }) => {
+                assert_eq!(*failing_since, first);
+                assert_eq!(error, "connection refused");
+            }
+            signal => panic!("unexpected signal {:?}", signal),
+        }
+        reachable(&name);
+        assert_eq!(
+            signals().lock().unwrap()[&name]
+                .health(Instant::now())
+                .status,
+            HealthStatus::Healthy
+        );
+    }
+
+    #[test]
+    fn test_report_status() {
+        let components = BTreeMap::from([
+            (
+                "database".to_string(),
+                ComponentHealth::new(HealthStatus::Healthy, "sqlite"),
+            ),
+            (
+                "telegram".to_string(),
+                ComponentHealth::new(HealthStatus::Degraded, "unreachable"),
+            ),
+        ]);
+        let report = HealthReport::new(components.clone());
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.status_code(), StatusCode::OK);
+
+        let mut components = components;
+        components.insert(
+            "scheduler".to_string(),
+            ComponentHealth::new(HealthStatus::Unhealthy, "last pass 900s ago"),
+        );
+        let report = HealthReport::new(components);
+        assert_eq!(report.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap()["components"]["scheduler"]["status"],
+            "unhealthy"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! HTTP Server for NuClaw
+//!
+//! `nuclaw serve` answers HTTP requests on `HTTP_BIND` (`0.0.0.0:8788` by
+//! default) whichever channels it runs and however Telegram receives its
+//! updates:
+//!
+//! - `GET /health` (see `health`)
+//! - the admin API under `/api`, when a token is configured (see
+//!   `admin_api`); `/health` then needs one of its tokens
+//! - the inbound webhooks under `/webhooks`, when `WEBHOOK_SECRET` is set
+//!   (see `webhooks`)
+//!
+//! An empty `HTTP_BIND` turns the server off. Telegram's own webhook is
+//! served apart, on `TELEGRAM_WEBHOOK_BIND`.
+
+use crate::admin_api;
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use crate::health;
+use crate::shutdown;
+use crate::webhooks;
+use axum::Router;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// Address the server listens on when `HTTP_BIND` is unset
+const DEFAULT_HTTP_BIND: &str = "0.0.0.0:8788";
+
+/// Address from `HTTP_BIND`, or `None` when it is set empty
+pub fn bind_address() -> Result<Option<SocketAddr>> {
+    let bind = std::env::var("HTTP_BIND").unwrap_or_else(|_| DEFAULT_HTTP_BIND.to_string());
+    if bind.trim().is_empty() {
+        return Ok(None);
+    }
+    bind.trim()
+        .parse()
+        .map(Some)
+        .map_err(|_| NuClawError::Config {
+            message: format!("Invalid HTTP_BIND '{}'", bind),
+        })
+}
+
+/// Routes of the server for the database handle's tenant
+pub fn router(db: Database) -> Result<Router> {
+    let mut app = match admin_api::router(db.clone())? {
+        Some(admin) => {
+            info!("Admin API enabled under /api");
+            admin
+        }
+        None => health::router(db.clone()),
+    };
+    if let Some(hooks) = webhooks::router(db)? {
+        info!("Inbound webhooks enabled under /webhooks");
+        app = app.merge(hooks);
+    }
+    Ok(app)
+}
+
+/// Serve the routes of the database handle's tenant on `HTTP_BIND`;
+/// `started` is dropped once the server listens
+///
+/// Stops taking requests once NuClaw is shutting down.
+pub async fn serve(db: Database, started: mpsc::Sender<()>) -> Result<()> {
+    let Some(addr) = bind_address()? else {
+        info!("HTTP_BIND is empty, so /health and the admin API are not served");
+        return Ok(());
+    };
+    let app = router(db)?;
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .map_err(|e| NuClawError::Config {
+            message: format!("Failed to bind to {} (HTTP_BIND): {}", addr, e),
+        })?;
+    info!("Starting HTTP server on {}", addr);
+    drop(started);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown::requested())
+        .await
+        .map_err(|e| NuClawError::Config {
+            message: format!("HTTP server error: {}", e),
+        })?;
+
+    info!("HTTP server stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_router_serves_health() {
+        let (db, _dir) = test_database();
+        let app = router(db).unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        // No container runtime here, so `/health` may be 503
+        assert!(!response.status().is_client_error());
+        let response = app
+            .oneshot(
+                Request::get("/telegram-webhook")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,217 @@
+//! Inbound Message Persistence for NuClaw
+//!
+//! Accepting a message writes its ID to `processed_messages`, the message
+//! and its chat to `messages` and `chats`, and the channel's router state
+//! (when the last message arrived, overall and per chat) to
+//! `router_state`. `accept_message` does all of it in one transaction, so
+//! a crash never leaves a message marked processed but missing from the
+//! history, or stored without the router state moving past it.
+//!
+//! Router state used to be kept in `router_state.json` in the tenant's
+//! data directory; that file seeds the table for channels without state.
+
+use crate::db::{Database, Storage};
+use crate::dedup::{dedup_capacity, mark_processed_in};
+use crate::error::Result;
+use crate::repository::{store_message, upsert_chat};
+use crate::types::{NewMessage, RouterState};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// `router_state` row of a channel's last message, as opposed to a chat's
+const CHANNEL_ROW: &str = "";
+/// Router state file of earlier versions
+const ROUTER_STATE_FILE: &str = "router_state.json";
+
+/// Record an incoming message
+///
+/// Returns `false` for a message that was already accepted; only its
+/// deduplication entry is refreshed then.
+pub fn accept_message(
+    db: &Database,
+    channel: &str,
+    msg: &NewMessage,
+    is_from_me: bool,
+) -> Result<bool> {
+    let mut conn = db.get_connection()?;
+    let tx = conn.transaction()?;
+    let tenant = db.tenant();
+    if !mark_processed_in(&*tx, tenant, &msg.chat_jid, &msg.id, dedup_capacity())? {
+        tx.commit()?;
+        return Ok(false);
+    }
+    store_message(&*tx, tenant, msg, is_from_me)?;
+    upsert_chat(
+        &*tx,
+        tenant,
+        &msg.chat_jid,
+        msg.chat_name.as_deref(),
+        &msg.timestamp,
+    )?;
+    for row in [CHANNEL_ROW, msg.chat_jid.as_str()] {
+        set_last_timestamp(&*tx, tenant, channel, row, &msg.timestamp)?;
+    }
+    tx.commit()?;
+    Ok(true)
+}
+
+fn set_last_timestamp(
+    conn: &dyn Storage,
+    tenant: &str,
+    channel: &str,
+    chat_jid: &str,
+    timestamp: &str,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO router_state (tenant, channel, chat_jid, last_timestamp)
+         VALUES (?, ?, ?, ?)
+         ON CONFLICT (tenant, channel, chat_jid) DO UPDATE SET
+            last_timestamp = excluded.last_timestamp",
+        [tenant, channel, chat_jid, timestamp],
+    )?;
+    Ok(())
+}
+
+/// A channel's router state
+///
+/// If the channel has none yet, the `router_state.json` of earlier
+/// versions in `state_dir` is imported first.
+pub fn load_router_state(db: &Database, channel: &str, state_dir: &Path) -> Result<RouterState> {
+    let conn = db.read_connection()?;
+    let rows = conn.query_map(
+        "SELECT chat_jid, last_timestamp FROM router_state WHERE tenant = ? AND channel = ?",
+        [db.tenant(), channel],
+        |row| Ok((row.get::<String>(0)?, row.get::<String>(1)?)),
+    )?;
+    if rows.is_empty() {
+        drop(conn);
+        return import_router_state_file(db, channel, &state_dir.join(ROUTER_STATE_FILE));
+    }
+
+    let mut state = RouterState::default();
+    for (chat_jid, timestamp) in rows {
+        if chat_jid == CHANNEL_ROW {
+            state.last_timestamp = timestamp;
+        } else {
+            state.last_agent_timestamp.insert(chat_jid, timestamp);
+        }
+    }
+    Ok(state)
+}
+
+fn import_router_state_file(db: &Database, channel: &str, path: &Path) -> Result<RouterState> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Ok(RouterState::default());
+    };
+    let state: RouterState = match serde_json::from_str(&contents) {
+        Ok(state) => state,
+        Err(e) => {
+            warn!("Ignoring invalid {}: {}", path.display(), e);
+            return Ok(RouterState::default());
+        }
+    };
+
+    let mut conn = db.get_connection()?;
+    let tx = conn.transaction()?;
+    if !state.last_timestamp.is_empty() {
+        set_last_timestamp(
+            &*tx,
+            db.tenant(),
+            channel,
+            CHANNEL_ROW,
+            &state.last_timestamp,
+        )?;
+    }
+    for (chat_jid, timestamp) in &state.last_agent_timestamp {
+        set_last_timestamp(&*tx, db.tenant(), channel, chat_jid, timestamp)?;
+    }
+    tx.commit()?;
+    info!("Imported {} router state from {}", channel, path.display());
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use std::collections::HashMap;
+
+    fn message(id: &str, chat_jid: &str, timestamp: &str) -> NewMessage {
+        NewMessage {
+            id: id.to_string(),
+            chat_jid: chat_jid.to_string(),
+            sender: "u1".to_string(),
+            sender_name: "Alice".to_string(),
+            content: "hello".to_string(),
+            timestamp: timestamp.to_string(),
+            reply_to_id: None,
+            quoted_content: None,
+            chat_name: Some("Family".to_string()),
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn test_accept_message() {
+        let (db, dir) = test_database();
+        assert!(
+            accept_message(&db, "telegram", &message("1", "chat", "1700000001"), false).unwrap()
+        );
+        assert!(
+            accept_message(&db, "telegram", &message("2", "other", "1700000002"), false).unwrap()
+        );
+        assert!(
+            !accept_message(&db, "telegram", &message("1", "chat", "1700000003"), false).unwrap()
+        );
+
+        assert_eq!(db.messages().recent("chat", "", 10).unwrap().len(), 1);
+        assert_eq!(
+            db.chats().get("chat").unwrap().unwrap().name.as_deref(),
+            Some("Family")
+        );
+        let state = load_router_state(&db, "telegram", dir.path()).unwrap();
+        assert_eq!(state.last_timestamp, "1700000002");
+        assert_eq!(state.last_agent_timestamp["chat"], "1700000001");
+        assert_eq!(
+            load_router_state(&db, "whatsapp", dir.path()).unwrap(),
+            RouterState::default()
+        );
+    }
+
+    #[test]
+    fn test_failed_accept_writes_nothing() {
+        let (db, dir) = test_database();
+        db.get_connection()
+            .unwrap()
+            .execute("DROP TABLE router_state", ())
+            .unwrap();
+        assert!(
+            accept_message(&db, "telegram", &message("1", "chat", "1700000001"), false).is_err()
+        );
+
+        // The message is neither stored nor marked processed
+        assert!(db.messages().recent("chat", "", 10).unwrap().is_empty());
+        assert!(crate::dedup::mark_processed(&db, "chat", "1").unwrap());
+        drop(dir);
+    }
+
+    #[test]
+    fn test_router_state_file_is_imported() {
+        let (db, dir) = test_database();
+        let legacy = RouterState {
+            last_timestamp: "1700000005".to_string(),
+            last_agent_timestamp: HashMap::from([("chat".to_string(), "1700000004".to_string())]),
+        };
+        crate::utils::json::save_json(&dir.path().join(ROUTER_STATE_FILE), &legacy).unwrap();
+
+        assert_eq!(
+            load_router_state(&db, "whatsapp", dir.path()).unwrap(),
+            legacy
+        );
+        std::fs::remove_file(dir.path().join(ROUTER_STATE_FILE)).unwrap();
+        assert_eq!(
+            load_router_state(&db, "whatsapp", dir.path()).unwrap(),
+            legacy
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Scheduler Leader Election for NuClaw
+//!
+//! Several NuClaw instances can share one database for redundancy, but only
+//! one of them may run scheduled tasks. The scheduler holds a lease in the
+//! `scheduler_lease` table and renews it every third of
+//! `SCHEDULER_LEASE_SECS`. An instance whose scheduler finds the lease
+//! held by another instance stands by; once the holder stops renewing and
+//! the lease expires, the next instance to try takes over.
+//!
+//! Instances are told apart by `NUCLAW_INSTANCE_ID`, by default the host
+//! name and process ID.
+
+use crate::db::Database;
+use crate::error::Result;
+use serde::Serialize;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Default lease duration: 30 seconds
+const DEFAULT_LEASE_SECS: u64 = 30;
+
+/// The instance holding the scheduler lease
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Lease {
+    pub holder: String,
+    /// When the holder took the lease over
+    pub acquired_at: String,
+    pub expires_at: String,
+}
+
+/// Name of this instance (`NUCLAW_INSTANCE_ID`)
+pub fn instance_id() -> &'static str {
+    static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+    INSTANCE_ID.get_or_init(|| {
+        std::env::var("NUCLAW_INSTANCE_ID")
+            .ok()
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| {
+                let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "nuclaw".to_string());
+                format!("{}-{}", host, std::process::id())
+            })
+    })
+}
+
+/// How long a lease lasts without renewal (`SCHEDULER_LEASE_SECS`)
+pub fn lease_duration() -> Duration {
+    Duration::from_secs(
+        std::env::var("SCHEDULER_LEASE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(DEFAULT_LEASE_SECS),
+    )
+}
+
+/// How often the leader renews its lease and standbys check for an
+/// expired one
+pub fn renew_interval() -> Duration {
+    (lease_duration() / 3).max(Duration::from_secs(1))
+}
+
+/// Take or renew the lease for `holder`; `false` if another instance holds
+/// an unexpired lease
+pub fn try_acquire(db: &Database, holder: &str, ttl: Duration) -> Result<bool> {
+    let now = chrono::Utc::now();
+    let ttl = chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+    let expires_at = now.checked_add_signed(ttl).unwrap_or(now);
+    let changed = db.get_connection()?.execute(
+        "INSERT INTO scheduler_lease (id, holder, acquired_at, expires_at)
+         VALUES (1, ?1, ?2, ?3)
+         ON CONFLICT(id) DO UPDATE SET
+            holder = excluded.holder,
+            acquired_at = CASE WHEN scheduler_lease.holder = excluded.holder
+                               THEN scheduler_lease.acquired_at
+                               ELSE excluded.acquired_at END,
+            expires_at = excluded.expires_at
+         WHERE scheduler_lease.holder = excluded.holder
+            OR scheduler_lease.expires_at <= excluded.acquired_at",
+        [holder, &now.to_rfc3339(), &expires_at.to_rfc3339()],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Give up the lease if `holder` has it, so a standby takes over at once
+pub fn release(db: &Database, holder: &str) -> Result<bool> {
+    let deleted = db
+        .get_connection()?
+        .execute("DELETE FROM scheduler_lease WHERE holder = ?", [holder])?;
+    Ok(deleted > 0)
+}
+
+/// The current lease, if one has not expired
+pub fn current_leader(db: &Database) -> Result<Option<Lease>> {
+    let conn = db.read_connection()?;
+    conn.query_opt(
+        "SELECT holder, acquired_at, expires_at FROM scheduler_lease
+         WHERE id = 1 AND expires_at > ?",
+        [chrono::Utc::now().to_rfc3339()],
+        |row| {
+            Ok(Lease {
+                holder: row.get(0)?,
+                acquired_at: row.get(1)?,
+                expires_at: row.get(2)?,
+            })
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_lease_failover() {
+        let (db, _dir) = test_database();
+        let ttl = Duration::from_secs(60);
+        assert!(current_leader(&db).unwrap().is_none());
+
+        assert!(try_acquire(&db, "a", ttl).unwrap());
+        let lease = current_leader(&db).unwrap().unwrap();
+        assert_eq!(lease.holder, "a");
+        // The holder renews; another instance stands by
+        assert!(try_acquire(&db, "a", ttl).unwrap());
+        assert!(!try_acquire(&db, "b", ttl).unwrap());
+        assert_eq!(
+            current_leader(&db).unwrap().unwrap().acquired_at,
+            lease.acquired_at
+        );
+
+        // An expired lease is taken over
+        assert!(try_acquire(&db, "a", Duration::ZERO).unwrap());
+        assert!(current_leader(&db).unwrap().is_none());
+        assert!(try_acquire(&db, "b", ttl).unwrap());
+        assert!(!try_acquire(&db, "a", ttl).unwrap());
+
+        assert!(!release(&db, "a").unwrap());
+        assert!(release(&db, "b").unwrap());
+        assert!(try_acquire(&db, "a", ttl).unwrap());
+    }
+}
@@ -7,22 +7,73 @@
 //! - Scheduled task management
 //! - SQLite persistence
 
+pub mod admin_api;
+pub mod agent_state;
+pub mod allowlist;
+pub mod analytics;
+pub mod attachments;
+pub mod audit;
+pub mod blocklist;
+pub mod broadcast;
+pub mod chat_queue;
+pub mod cli;
+pub mod commands;
 pub mod config;
+pub mod config_file;
 pub mod container_runner;
+pub mod context;
+pub mod correlation;
+pub mod credentials;
 pub mod db;
+pub mod dedup;
+pub mod dotenv;
 pub mod error;
+pub mod group_config;
+pub mod groups;
+pub mod health;
+pub mod http_server;
+pub mod inbound;
+pub mod leader;
+pub mod log_retention;
+pub mod log_stream;
 pub mod logging;
+pub mod maintenance;
+pub mod memory;
+pub mod metrics;
+pub mod mounts;
+pub mod outbox;
+pub mod pairing;
+pub mod pending;
+pub mod postgres_backend;
+pub mod process_runner;
+pub mod prompt_guard;
+pub mod rate_limiter;
+pub mod redact;
+pub mod repository;
+pub mod roles;
+pub mod sessions;
+pub mod settings;
+pub mod shutdown;
+pub mod systemd;
 pub mod task_scheduler;
+pub mod task_templates;
 pub mod telegram;
+pub mod tenants;
+pub mod transcription;
 pub mod types;
+pub mod usage;
 pub mod utils;
+pub mod warm_pool;
+pub mod wasm_runner;
+pub mod webhook_auth;
+pub mod webhooks;
 pub mod whatsapp;
 
 // Re-exports for convenience
 pub use config::ensure_directories;
 pub use container_runner::{
     container_timeout, create_group_ipc_directory, ensure_container_system_running,
-    max_output_size, run_container,
+    max_output_size, run_container, run_container_with_progress,
 };
 pub use error::{NuClawError, Result};
 pub use task_scheduler::TaskScheduler;
@@ -0,0 +1,298 @@
+//! Container Log Retention for NuClaw
+//!
+//! Agent runs leave logs under `logs_dir()`: the live log of each session
+//! (`<group>/<session>.log`, appended to by every run of the session) and
+//! the output record of each scheduled task run
+//! (`<group>/container_<session>_<time>.log`). Without limits these grow
+//! forever, so:
+//!
+//! - a live log larger than `CONTAINER_LOG_MAX_SIZE` is rotated to
+//!   `<session>.<time>.log.gz` before the next run appends to it
+//! - logs not written to for `CONTAINER_LOG_COMPRESS_AFTER_DAYS` are
+//!   gzipped in place (`<name>.log.gz`)
+//! - logs, compressed or not, not written to for
+//!   `CONTAINER_LOG_RETENTION_DAYS` are deleted
+//!
+//! The scheduler runs the cleanup (`maintain_container_logs`) at start
+//! and once a day. Compressed logs keep the modification time of the
+//! original, so retention counts from the last write.
+
+use crate::config::logs_dir;
+use crate::error::{NuClawError, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Default size at which a live log is rotated: 10 MiB
+const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Default days after which idle logs are compressed
+const DEFAULT_LOG_COMPRESS_AFTER_DAYS: u64 = 1;
+/// Default days logs are kept
+const DEFAULT_LOG_RETENTION_DAYS: u64 = 30;
+
+const DAY: Duration = Duration::from_secs(86_400);
+
+/// Limits of the container logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRetention {
+    /// Size at which a live log is rotated; `None` never rotates
+    pub max_size: Option<u64>,
+    /// Idle time after which a log is compressed; `None` never compresses
+    pub compress_after: Option<Duration>,
+    /// Idle time after which a log is deleted; `None` keeps logs forever
+    pub retention: Option<Duration>,
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+impl LogRetention {
+    /// Limits from `CONTAINER_LOG_MAX_SIZE`,
+    /// `CONTAINER_LOG_COMPRESS_AFTER_DAYS`, and
+    /// `CONTAINER_LOG_RETENTION_DAYS`; zero disables each
+    pub fn from_env() -> Self {
+        let days = |name, default| {
+            Some(env_u64(name, default))
+                .filter(|days| *days > 0)
+                .map(|days| DAY * days.min(36_500) as u32)
+        };
+        Self {
+            max_size: Some(env_u64("CONTAINER_LOG_MAX_SIZE", DEFAULT_LOG_MAX_SIZE))
+                .filter(|size| *size > 0),
+            compress_after: days(
+                "CONTAINER_LOG_COMPRESS_AFTER_DAYS",
+                DEFAULT_LOG_COMPRESS_AFTER_DAYS,
+            ),
+            retention: days("CONTAINER_LOG_RETENTION_DAYS", DEFAULT_LOG_RETENTION_DAYS),
+        }
+    }
+}
+
+/// What a cleanup did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogCleanup {
+    pub compressed: usize,
+    pub deleted: usize,
+}
+
+fn fs_error(action: &str, path: &Path, e: io::Error) -> NuClawError {
+    NuClawError::FileSystem {
+        message: format!("Failed to {} {}: {}", action, path.display(), e),
+    }
+}
+
+/// Gzip `from` into `to` and remove `from`, keeping its modification time
+fn compress(from: &Path, to: &Path) -> Result<()> {
+    let modified = fs::metadata(from)
+        .and_then(|meta| meta.modified())
+        .map_err(|e| fs_error("read", from, e))?;
+    let result = (|| {
+        let mut input = fs::File::open(from)?;
+        let mut encoder = GzEncoder::new(fs::File::create(to)?, Compression::default());
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.set_modified(modified)
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(to);
+        return Err(fs_error("compress", from, e));
+    }
+    fs::remove_file(from).map_err(|e| fs_error("remove", from, e))
+}
+
+/// Rotate a live log larger than `max_size` to a compressed
+/// `<stem>.<time>.log.gz` next to it; `true` if it was rotated
+pub fn rotate_if_large(path: &Path, max_size: Option<u64>) -> Result<bool> {
+    let Some(max_size) = max_size else {
+        return Ok(false);
+    };
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() > max_size => {}
+        _ => return Ok(false),
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let rotated = path.with_file_name(format!(
+        "{}.{}.log.gz",
+        stem,
+        chrono::Utc::now().format("%Y%m%d_%H%M%S")
+    ));
+    compress(path, &rotated)?;
+    Ok(true)
+}
+
+/// Log files under `dir`, at any depth
+fn log_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => dirs.push(path),
+                Ok(kind) if kind.is_file() => {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    if name.ends_with(".log") || name.ends_with(".log.gz") {
+                        files.push(path);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    files
+}
+
+/// Compress and delete the logs under `dir` per `policy`, as of `now`
+///
+/// A file that cannot be handled is skipped with a warning.
+pub fn clean_logs(dir: &Path, policy: &LogRetention, now: SystemTime) -> LogCleanup {
+    let mut cleanup = LogCleanup::default();
+    for path in log_files(dir) {
+        let Ok(idle) = fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .map(|modified| now.duration_since(modified).unwrap_or_default())
+        else {
+            continue;
+        };
+        let result = if policy.retention.is_some_and(|retention| idle > retention) {
+            fs::remove_file(&path)
+                .map(|_| cleanup.deleted += 1)
+                .map_err(|e| fs_error("remove", &path, e))
+        } else if path.extension().is_some_and(|ext| ext == "log")
+            && policy.compress_after.is_some_and(|after| idle > after)
+        {
+            let mut compressed = path.clone().into_os_string();
+            compressed.push(".gz");
+            compress(&path, Path::new(&compressed)).map(|_| cleanup.compressed += 1)
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            tracing::warn!("{}", e);
+        }
+    }
+    cleanup
+}
+
+/// Clean up the container logs per the environment; a cleanup already in
+/// progress, e.g. of another tenant's scheduler, is not repeated
+pub fn maintain_container_logs() -> LogCleanup {
+    static RUNNING: Mutex<()> = Mutex::new(());
+    let Ok(_running) = RUNNING.try_lock() else {
+        return LogCleanup::default();
+    };
+    let policy = LogRetention::from_env();
+    let cleanup = clean_logs(&logs_dir(), &policy, SystemTime::now());
+    if cleanup != LogCleanup::default() {
+        tracing::info!(
+            "Compressed {} and deleted {} container logs",
+            cleanup.compressed,
+            cleanup.deleted
+        );
+    }
+    cleanup
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn write_log(path: &Path, content: &str, age: Duration) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        fs::File::options()
+            .append(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+
+    fn gunzip(path: &Path) -> String {
+        let mut text = String::new();
+        GzDecoder::new(fs::File::open(path).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn test_rotate_if_large() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main").join("session-1.log");
+        write_log(&path, "0123456789", Duration::ZERO);
+
+        assert!(!rotate_if_large(&path, Some(10)).unwrap());
+        assert!(!rotate_if_large(&path, None).unwrap());
+        assert!(rotate_if_large(&path, Some(5)).unwrap());
+        assert!(!path.exists());
+        let rotated: Vec<_> = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(rotated.len(), 1);
+        let name = rotated[0].file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("session-1.") && name.ends_with(".log.gz"));
+        assert_eq!(gunzip(&rotated[0]), "0123456789");
+
+        assert!(!rotate_if_large(&dir.path().join("missing.log"), Some(5)).unwrap());
+    }
+
+    #[test]
+    fn test_clean_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let fresh = dir.path().join("main").join("fresh.log");
+        let idle = dir.path().join("main").join("idle.log");
+        let old = dir.path().join("ops").join("old.log.gz");
+        let other = dir.path().join("main").join("notes.txt");
+        write_log(&fresh, "fresh", Duration::from_secs(60));
+        write_log(&idle, "idle", DAY * 2);
+        write_log(&old, "old", DAY * 40);
+        write_log(&other, "notes", DAY * 40);
+
+        let policy = LogRetention {
+            max_size: None,
+            compress_after: Some(DAY),
+            retention: Some(DAY * 30),
+        };
+        let cleanup = clean_logs(dir.path(), &policy, SystemTime::now());
+        assert_eq!(
+            cleanup,
+            LogCleanup {
+                compressed: 1,
+                deleted: 1
+            }
+        );
+        assert!(fresh.exists());
+        assert!(!idle.exists());
+        let compressed = dir.path().join("main").join("idle.log.gz");
+        assert_eq!(gunzip(&compressed), "idle");
+        // Retention still counts from the last write
+        let modified = fs::metadata(&compressed).unwrap().modified().unwrap();
+        assert!(SystemTime::now().duration_since(modified).unwrap() > DAY);
+        assert!(!old.exists());
+        assert!(other.exists());
+
+        let keep = LogRetention {
+            max_size: None,
+            compress_after: None,
+            retention: None,
+        };
+        assert_eq!(
+            clean_logs(dir.path(), &keep, SystemTime::now() + DAY * 365),
+            LogCleanup::default()
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Live Log Streaming for NuClaw
+//!
+//! Log events are also published to in-process subscribers, which the
+//! admin API serves as a stream of server-sent events
+//! (`GET /api/logs/stream`), so what NuClaw is doing can be followed from
+//! a browser. Events are published only while someone is subscribed.
+//!
+//! A subscriber sees the events the log filter lets through (see
+//! `logging`); `level` and `module` narrow them down further, while
+//! raising the log filter (`PUT /api/log-level`) makes more of them
+//! available. A subscriber that falls more than `LOG_STREAM_CAPACITY`
+//! events behind skips the ones it missed.
+
+use crate::error::{NuClawError, Result};
+use crate::redact::redact;
+use serde::Serialize;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::fmt::format::DefaultFields;
+use tracing_subscriber::fmt::FormattedFields;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Events buffered for each subscriber
+pub const LOG_STREAM_CAPACITY: usize = 1024;
+
+/// A published log event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogRecord {
+    pub timestamp: String,
+    pub level: String,
+    /// Module, or target, the event was logged from
+    pub module: String,
+    pub message: String,
+    /// Fields of the spans the event is in, such as its `correlation_id`
+    pub context: Option<String>,
+}
+
+fn sender() -> &'static broadcast::Sender<LogRecord> {
+    static SENDER: OnceLock<broadcast::Sender<LogRecord>> = OnceLock::new();
+    SENDER.get_or_init(|| broadcast::channel(LOG_STREAM_CAPACITY).0)
+}
+
+/// Receive the log events published from now on
+pub fn subscribe() -> broadcast::Receiver<LogRecord> {
+    sender().subscribe()
+}
+
+/// Which published events a subscriber wants
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogStreamFilter {
+    /// Events at this level or more severe
+    pub level: Option<Level>,
+    /// Events logged from this module or one of its submodules
+    pub module: Option<String>,
+}
+
+impl LogStreamFilter {
+    /// Filter from the `level` and `module` query parameters
+    pub fn parse(level: Option<&str>, module: Option<&str>) -> Result<Self> {
+        let level = level
+            .map(str::trim)
+            .filter(|level| !level.is_empty())
+            .map(|level| {
+                Level::from_str(level).map_err(|_| NuClawError::Validation {
+                    message: format!(
+                        "Unknown log level '{}'; use trace, debug, info, warn, or error",
+                        level
+                    ),
+                })
+            })
+            .transpose()?;
+        let module = module
+            .map(str::trim)
+            .filter(|module| !module.is_empty())
+            .map(str::to_string);
+        Ok(Self { level, module })
+    }
+
+    pub fn matches(&self, record: &LogRecord) -> bool {
+        let level_ok = self
+            .level
+            .is_none_or(|wanted| Level::from_str(&record.level).is_ok_and(|level| level <= wanted));
+        let module_ok = self.module.as_deref().is_none_or(|wanted| {
+            record
+                .module
+                .strip_prefix(wanted)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        });
+        level_ok && module_ok
+    }
+}
+
+/// Collects an event's message and other fields
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => {
+                let _ = write!(self.message, "{:?}", value);
+            }
+            // Origin of records forwarded from `log`, already in the metadata
+            name if name.starts_with("log.") => {}
+            name => {
+                let _ = write!(self.fields, " {}={:?}", name, value);
+            }
+        }
+    }
+}
+
+/// Layer publishing log events to the subscribers
+pub struct LogStreamLayer;
+
+impl<S> Layer<S> for LogStreamLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let sender = sender();
+        if sender.receiver_count() == 0 {
+            return;
+        }
+        let normalized = event.normalized_metadata();
+        let meta = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let context: Vec<String> = ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .filter_map(|span| {
+                span.extensions()
+                    .get::<FormattedFields<DefaultFields>>()
+                    .map(|fields| fields.fields.clone())
+                    .filter(|fields| !fields.is_empty())
+            })
+            .collect();
+        let _ = sender.send(LogRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            level: meta.level().to_string(),
+            module: meta
+                .module_path()
+                .unwrap_or_else(|| meta.target())
+                .to_string(),
+            message: redact(&(visitor.message + &visitor.fields)).into_owned(),
+            context: (!context.is_empty()).then(|| context.join(" ")),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    fn record(level: &str, module: &str) -> LogRecord {
+        LogRecord {
+            timestamp: String::new(),
+            level: level.to_string(),
+            module: module.to_string(),
+            message: String::new(),
+            context: None,
+        }
+    }
+
+    #[test]
+    fn test_log_stream_filter() {
+        let filter = LogStreamFilter::parse(Some("info"), Some("nuclaw::telegram")).unwrap();
+        assert!(filter.matches(&record("WARN", "nuclaw::telegram")));
+        assert!(filter.matches(&record("INFO", "nuclaw::telegram::api")));
+        assert!(!filter.matches(&record("DEBUG", "nuclaw::telegram")));
+        assert!(!filter.matches(&record("INFO", "nuclaw::telegram_bot")));
+        assert!(!filter.matches(&record("ERROR", "nuclaw::whatsapp")));
+
+        let all = LogStreamFilter::parse(None, Some(" ")).unwrap();
+        assert_eq!(all, LogStreamFilter::default());
+        assert!(all.matches(&record("TRACE", "hyper")));
+
+        assert!(matches!(
+            LogStreamFilter::parse(Some("loud"), None),
+            Err(NuClawError::Validation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_log_stream_layer() {
+        let subscriber = tracing_subscriber::registry()
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(std::io::sink),
+            )
+            .with(LogStreamLayer);
+        let mut receiver = subscribe();
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = crate::correlation::correlation_span("abc12345").entered();
+            tracing::warn!(chat = "tg:1", "Slow reply from {}", "agent");
+        });
+
+        // Other tests may publish too; find this one's event
+        let record = std::iter::from_fn(|| receiver.try_recv().ok())
+            .find(|record| record.message.starts_with("Slow reply"))
+            .unwrap();
+        assert_eq!(record.level, "WARN");
+        assert_eq!(record.module, "nuclaw::log_stream::tests");
+        assert_eq!(record.message, "Slow reply from agent chat=\"tg:1\"");
+        assert_eq!(record.context.as_deref(), Some("correlation_id=abc12345"));
+    }
+}
@@ -1,18 +1,49 @@
 //! Logging module for NuClaw
 //!
-//! Provides unified logging initialization with support for both
-//! env_logger and tracing integration.
-
-use log::LevelFilter;
-use std::sync::OnceLock;
+//! Logs go through a `tracing` subscriber, which also receives the records
+//! of crates logging with `log`. What gets logged is decided by a filter:
+//! a level such as `debug`, or `target=level` directives such as
+//! `info,nuclaw::telegram=debug`. It is taken from `--log-level`
+//! (`NUCLAW_LOG_LEVEL`), else `RUST_LOG`, else the configured level.
+//!
+//! The filter can be changed while NuClaw runs: with the `/loglevel` chat
+//! command, through the admin API (`PUT /api/log-level`), or by editing
+//! either setting in the config file.
+//!
+//! Events inside spans are prefixed with the spans' fields, such as the
+//! `correlation_id` of the message being handled (see `correlation`).
+//! Events are also published to live subscribers (see `log_stream`).
+//! Secrets in messages are masked before they are written (see `redact`).
+
+use crate::error::{NuClawError, Result};
+use crate::log_stream::LogStreamLayer;
+use crate::redact::redact;
+use std::sync::{Mutex, OnceLock};
+use tracing::{Event, Subscriber};
+use tracing_log::{AsLog, NormalizeEvent};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::format::{self, FormatEvent, FormatFields};
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::fmt::FormattedFields;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::{LookupSpan, Registry};
+use tracing_subscriber::reload;
 
 /// Global logging initialization status
 static LOG_INIT: OnceLock<()> = OnceLock::new();
+/// Changes the filter of the installed subscriber
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+/// Directives of the filter in use
+static CURRENT_FILTER: Mutex<String> = Mutex::new(String::new());
+
+/// Settings holding the log filter, the one that wins first
+pub const LOG_FILTER_VARS: &[&str] = &["NUCLAW_LOG_LEVEL", "RUST_LOG"];
 
 /// Logging configuration
 #[derive(Debug, Clone)]
 pub struct LoggingConfig {
-    /// Default log level
+    /// Log level used when neither `NUCLAW_LOG_LEVEL` nor `RUST_LOG` set
+    /// a filter
     pub level: Level,
     /// Whether to use JSON formatting
     pub json_format: bool,
@@ -69,18 +100,6 @@ impl Level {
             _ => None,
         }
     }
-
-    /// Convert to LevelFilter
-    fn to_filter(self) -> LevelFilter {
-        match self {
-            Level::Trace => LevelFilter::Trace,
-            Level::Debug => LevelFilter::Debug,
-            Level::Info => LevelFilter::Info,
-            Level::Warn => LevelFilter::Warn,
-            Level::Error => LevelFilter::Error,
-            Level::Off => LevelFilter::Off,
-        }
-    }
 }
 
 /// Initialize logging with default configuration
@@ -98,59 +117,174 @@ pub fn init_with_config(config: LoggingConfig) {
 
 /// Setup logging based on configuration
 fn setup_logging(config: &LoggingConfig) {
-    // Set RUST_LOG for env_logger
-    std::env::set_var("RUST_LOG", format!("{}", config.level).to_lowercase());
-
-    // Clone config for the closure
-    let config = config.clone();
-
-    // Initialize env_logger with custom format
-    env_logger::Builder::from_default_env()
-        .format_timestamp(None)
-        .format(move |buf, record| {
-            use std::io::Write;
-
-            let timestamp = if config.include_timestamp {
-                let now = chrono::Utc::now();
-                Some(format!("[{}]", now.to_rfc3339()))
-            } else {
-                None
-            };
-
-            let level = match record.level() {
-                log::Level::Error => "ERROR",
-                log::Level::Warn => "WARN",
-                log::Level::Info => "INFO",
-                log::Level::Debug => "DEBUG",
-                log::Level::Trace => "TRACE",
-            };
-
-            if config.json_format {
-                // JSON format for structured logging
-                let output = serde_json::json!({
-                    "timestamp": chrono::Utc::now().to_rfc3339(),
-                    "level": level,
-                    "message": record.args(),
-                    "module": record.module_path().unwrap_or("unknown"),
-                    "file": record.file().unwrap_or("unknown"),
-                    "line": record.line(),
-                });
-                writeln!(buf, "{}", output)
-            } else {
-                // Human-readable format
-                let mut output = String::new();
-                if let Some(ts) = timestamp {
-                    output.push_str(&ts);
-                    output.push(' ');
-                }
-                output.push_str(level);
-                output.push_str(": ");
-                output.push_str(&format!("{}", record.args()));
-                writeln!(buf, "{}", output)
+    let mut directives = filter_from_env(config.level);
+    let filter = parse_filter(&directives).unwrap_or_else(|e| {
+        eprintln!("{}; logging at {}", e, config.level);
+        directives = config.level.to_string();
+        EnvFilter::new(&directives)
+    });
+    let (filter, handle) = reload::Layer::new(filter);
+    let output = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(std::io::stderr)
+        .event_format(LineFormat {
+            json: config.json_format,
+            include_timestamp: config.include_timestamp,
+        });
+    if tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(LogStreamLayer)
+        .try_init()
+        .is_ok()
+    {
+        let _ = FILTER_HANDLE.set(handle);
+        *CURRENT_FILTER.lock().unwrap() = directives;
+        sync_log_max_level();
+    }
+}
+
+/// Writes each event as `[timestamp] LEVEL: [span fields: ]message`, or
+/// as a JSON line
+struct LineFormat {
+    json: bool,
+    include_timestamp: bool,
+}
+
+impl<S, N> FormatEvent<S, N> for LineFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        // Records forwarded from `log` carry their origin separately
+        let normalized = event.normalized_metadata();
+        let meta = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let level = meta.level().as_str();
+        let context = span_fields::<S, N>(ctx);
+        let mut message = String::new();
+        ctx.format_fields(format::Writer::new(&mut message), event)?;
+        let message = redact(&message);
+
+        if self.json {
+            // JSON format for structured logging
+            let output = serde_json::json!({
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "level": level,
+                "message": message,
+                "context": context,
+                "module": meta.module_path().unwrap_or("unknown"),
+                "file": meta.file().unwrap_or("unknown"),
+                "line": meta.line(),
+            });
+            writeln!(writer, "{}", output)
+        } else {
+            // Human-readable format
+            if self.include_timestamp {
+                write!(writer, "[{}] ", chrono::Utc::now().to_rfc3339())?;
             }
+            write!(writer, "{}: ", level)?;
+            if let Some(context) = &context {
+                write!(writer, "{}: ", context)?;
+            }
+            writeln!(writer, "{}", message)
+        }
+    }
+}
+
+/// Fields of the spans an event is in, outermost first; `None` outside of
+/// spans with fields
+fn span_fields<S, N>(ctx: &FmtContext<'_, S, N>) -> Option<String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    let fields: Vec<String> = ctx
+        .event_scope()?
+        .from_root()
+        .filter_map(|span| {
+            span.extensions()
+                .get::<FormattedFields<N>>()
+                .map(|fields| fields.fields.clone())
+                .filter(|fields| !fields.is_empty())
+        })
+        .collect();
+    (!fields.is_empty()).then(|| fields.join(" "))
+}
+
+/// Let `log` records through up to the most verbose level the filter
+/// enables
+fn sync_log_max_level() {
+    log::set_max_level(LevelFilter::current().as_log());
+}
+
+/// Filter directives from `NUCLAW_LOG_LEVEL`, else `RUST_LOG`, else
+/// `default`
+pub fn filter_from_env(default: Level) -> String {
+    LOG_FILTER_VARS
+        .iter()
+        .find_map(|var| {
+            std::env::var(var)
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
         })
-        .filter(None, config.level.to_filter())
-        .init();
+        .unwrap_or_else(|| default.to_string())
+}
+
+/// Parse filter directives: levels, and `target=level` pairs
+///
+/// A bare word that is not a level is refused, where `EnvFilter` would
+/// take it for a target and silence everything else.
+pub fn parse_filter(directives: &str) -> Result<EnvFilter> {
+    let invalid = |message: String| NuClawError::Validation {
+        message: format!("Invalid log filter '{}': {}", directives, message),
+    };
+    let parts: Vec<&str> = directives
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect();
+    if parts.is_empty() {
+        return Err(invalid("no level given".to_string()));
+    }
+    for part in &parts {
+        if !part.contains('=') && !part.contains('[') && part.parse::<LevelFilter>().is_err() {
+            return Err(invalid(format!(
+                "'{}' is not a level (trace, debug, info, warn, error, off)",
+                part
+            )));
+        }
+    }
+    EnvFilter::builder()
+        .parse(parts.join(","))
+        .map_err(|e| invalid(e.to_string()))
+}
+
+/// Directives of the log filter in use
+pub fn log_filter() -> String {
+    CURRENT_FILTER.lock().unwrap().clone()
+}
+
+/// Replace the log filter while running; returns the one it replaced
+pub fn set_log_filter(directives: &str) -> Result<String> {
+    let filter = parse_filter(directives)?;
+    let handle = FILTER_HANDLE.get().ok_or_else(|| NuClawError::Config {
+        message: "Logging is not initialized".to_string(),
+    })?;
+    handle.reload(filter).map_err(|e| NuClawError::Config {
+        message: format!("Failed to change the log filter: {}", e),
+    })?;
+    sync_log_max_level();
+    let directives = directives.trim().to_string();
+    let previous = std::mem::replace(&mut *CURRENT_FILTER.lock().unwrap(), directives.clone());
+    tracing::info!("Log filter changed from '{}' to '{}'", previous, directives);
+    Ok(previous)
 }
 
 /// Check if logging has been initialized
@@ -251,6 +385,88 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_filter() {
+        for valid in [
+            "debug",
+            " WARN ",
+            "info,nuclaw::telegram=debug",
+            "off,hyper=warn",
+        ] {
+            assert!(parse_filter(valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["", " , ", "verbose", "info,nuclaw::telegram", "nuclaw=loud"] {
+            assert!(
+                matches!(parse_filter(invalid), Err(NuClawError::Validation { .. })),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_filter_from_env() {
+        let saved: Vec<_> = LOG_FILTER_VARS
+            .iter()
+            .map(|var| (var, std::env::var(var).ok()))
+            .collect();
+
+        std::env::remove_var("NUCLAW_LOG_LEVEL");
+        std::env::remove_var("RUST_LOG");
+        assert_eq!(filter_from_env(Level::Warn), "warn");
+        std::env::set_var("RUST_LOG", "debug,hyper=info");
+        assert_eq!(filter_from_env(Level::Warn), "debug,hyper=info");
+        std::env::set_var("NUCLAW_LOG_LEVEL", "trace");
+        assert_eq!(filter_from_env(Level::Warn), "trace");
+
+        for (var, value) in saved {
+            match value {
+                Some(v) => std::env::set_var(var, v),
+                None => std::env::remove_var(var),
+            }
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_line_format_span_fields() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .event_format(LineFormat {
+                json: false,
+                include_timestamp: false,
+            })
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside");
+            let _span = crate::correlation::correlation_span("abc12345").entered();
+            tracing::info!("inside");
+            tracing::warn!(url = "https://x", "Bearer abcdefghijklmnop1234 rejected");
+        });
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "INFO: outside\nINFO: correlation_id=abc12345: inside\n\
+             WARN: correlation_id=abc12345: Bearer [REDACTED] rejected url=\"https://x\"\n"
+        );
+    }
+
     #[test]
     fn test_init_with_config() {
         let config = LoggingConfig {
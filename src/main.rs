@@ -7,153 +7,570 @@
 //! - Scheduled task management
 //! - SQLite persistence
 
+use nuclaw::analytics;
+use nuclaw::audit::{self, AuditEvent, AuditFilter, AuditKind};
+use nuclaw::broadcast;
+use nuclaw::cli::{
+    self, AuthCommand, Cli, Command, ConfigCommand, CredentialsCommand, DbCommand, GroupCommand,
+    PairCommand, RoleCommand, ServeArgs, TaskCommand,
+};
 use nuclaw::config;
-use nuclaw::container_runner::{self, ensure_container_system_running};
+use nuclaw::config_file;
+use nuclaw::container_runner::{
+    ensure_container_system_running, run_container_with_retry, validate_container_env, RetryPolicy,
+};
+use nuclaw::credentials::{self, CredentialStore, MasterKey};
 use nuclaw::db;
+use nuclaw::dotenv;
 use nuclaw::error::{NuClawError, Result};
+use nuclaw::group_config;
+use nuclaw::groups;
+use nuclaw::http_server;
+use nuclaw::leader;
 use nuclaw::logging;
-use nuclaw::task_scheduler::TaskScheduler;
+use nuclaw::maintenance;
+use nuclaw::memory::relevant_memories;
+use nuclaw::pairing;
+use nuclaw::roles;
+use nuclaw::sessions;
+use nuclaw::settings;
+use nuclaw::shutdown;
+use nuclaw::systemd;
+use nuclaw::task_scheduler::{
+    create_task, poll_interval, preview_schedule, query_runs, run_task_now, set_task_paused,
+    NewTask, RunFilter, TaskScheduler,
+};
 use nuclaw::telegram;
+use nuclaw::tenants::{self, Tenant};
+use nuclaw::types::{ContainerInput, RegisteredGroup, ScheduledTask, TaskRunLog};
+use nuclaw::usage::{self, UsageGroup};
 use nuclaw::whatsapp;
 
-use structopt::StructOpt;
-use tokio::signal;
-use tracing::info;
+use clap::FromArgMatches;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
 
-#[derive(StructOpt, Debug)]
-struct Args {
-    #[structopt(long)]
-    auth: bool,
+/// Channel of `repl` runs, for their retry policy
+const REPL_CHANNEL: &str = "repl";
+/// Prefix of the chat JID `repl` keeps its session under
+const REPL_CHAT_PREFIX: &str = "repl:";
+/// Upcoming runs listed for a task
+const SHOWN_NEXT_RUNS: usize = 3;
+/// Latest runs listed by `task show`
+const SHOWN_TASK_RUNS: usize = 5;
 
-    #[structopt(long)]
-    scheduler: bool,
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Setting flags are applied before anything reads the environment
+    let matches = cli::command().get_matches();
+    settings::apply_setting_flags(&matches);
+    let args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(Command::Completions { shell }) = args.cmd {
+        let mut script = vec![];
+        clap_complete::generate(shell, &mut cli::command(), "nuclaw", &mut script);
+        // Fails if the reader went away, e.g. `head`
+        let _ = std::io::stdout().write_all(&script);
+        return Ok(());
+    }
+    config::mark_started();
+    // Before the first read of NUCLAW_HOME
+    let dotenv_files = if args.no_dotenv {
+        vec![]
+    } else {
+        dotenv::load_dotenv(Path::new("."))?
+    };
+    let cmd = args.cmd.unwrap_or(Command::Serve(ServeArgs::default()));
 
-    #[structopt(long)]
-    whatsapp: bool,
+    // Files used to be kept in the working directory; move them, or
+    // refuse to start over with an empty tree
+    if let Command::MigrateHome = cmd {
+        return run_migrate_home();
+    }
+    if let Some(dir) = config::legacy_layout() {
+        return Err(NuClawError::Config {
+            message: format!(
+                "Found NuClaw files from an earlier version in {0}. Run `nuclaw migrate-home` \
+                 to move them to the XDG base directories, or set NUCLAW_HOME={0} to keep \
+                 using them there",
+                dir.display()
+            ),
+        });
+    }
 
-    #[structopt(long)]
-    telegram: bool,
-}
+    // Read before anything else reads the environment
+    let config_path = config_file::load_config_file()?;
+    if let Command::Credentials(command) = cmd {
+        return run_credentials(command);
+    }
+    let credentials_path = credentials::load_credentials()?;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::from_args();
+    if let Command::Config(ConfigCommand::Show) = cmd {
+        run_config_show(config_path.as_deref(), &dotenv_files);
+        return Ok(());
+    }
 
     // Initialize logging
     logging::init();
 
     info!("Starting NuClaw v1.0.0");
     info!("This is a Rust port of NanoClaw");
+    for path in &dotenv_files {
+        info!("Loaded environment from {}", path.display());
+    }
+    match (&config_path, config_file::profile()) {
+        (Some(path), Some(profile)) => info!(
+            "Loaded configuration from {} with profile {}",
+            path.display(),
+            profile
+        ),
+        (Some(path), None) => info!("Loaded configuration from {}", path.display()),
+        (None, _) => {}
+    }
+    if let Some(path) = &credentials_path {
+        info!("Loaded credentials from {}", path.display());
+    }
 
     // Ensure directories exist
     config::ensure_directories().map_err(|e| NuClawError::FileSystem {
         message: e.to_string(),
     })?;
 
+    for (legacy, path) in group_config::legacy_group_config_files() {
+        warn!(
+            "{} is no longer read, since the agent can change it; move it to {}",
+            legacy.display(),
+            path.display()
+        );
+    }
+
     // Initialize database
     let db = db::Database::new().map_err(|e| NuClawError::Database {
         message: e.to_string(),
     })?;
     info!("Database initialized successfully");
 
-    // Handle different modes
-    if args.scheduler {
-        // Run task scheduler
-        run_scheduler(db).await?;
-    } else if args.whatsapp {
-        // Run WhatsApp bot
-        run_whatsapp_bot(db).await?;
-    } else if args.telegram {
-        // Run Telegram bot
-        run_telegram_bot(db).await?;
-    } else if args.auth {
-        // Show authentication QR code
-        run_auth_flow().await?;
-    } else {
-        // Default: run main application with all features
-        run_main_application(db).await?;
+    // Tenants to run, and the one commands apply to
+    let mut tenants = tenants::load_tenants()?;
+    if let Some(id) = &args.tenant {
+        tenants.retain(|t| &t.id == id);
+        if tenants.is_empty() {
+            return Err(NuClawError::Config {
+                message: format!("Unknown tenant '{}'", id),
+            });
+        }
+    }
+    let db = db.for_tenant(&tenants[0].id);
+
+    if cmd.is_audited() {
+        let command_line = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+        audit::record(
+            &db,
+            AuditEvent::new(AuditKind::Command, "cli", command_line),
+        );
+    }
+
+    match cmd {
+        Command::Serve(serve) => {
+            // Refuse to start agents with a broken container environment setup
+            validate_container_env()?;
+            run_serve(db, tenants, serve).await?;
+        }
+        Command::Auth(AuthCommand::Whatsapp) => run_auth_flow().await?,
+        Command::Auth(AuthCommand::Pair) | Command::Pair { command: None } => run_pair(db)?,
+        Command::Pair {
+            command: Some(command),
+        } => run_pairings(db, command)?,
+        Command::Task(task) => run_task(db, &tenants[0], task)?,
+        Command::Group(group) => run_group(db, &tenants[0], group)?,
+        Command::Db(db_command) => run_db(db, db_command)?,
+        Command::Role(role) => run_role(db, role)?,
+        Command::Send { target, text } => run_broadcast(db, &target, &text.join(" "))?,
+        Command::Repl { group } => {
+            validate_container_env()?;
+            run_repl(db, &tenants[0], &group).await?;
+        }
+        Command::Pause => run_maintenance(db, true)?,
+        Command::Resume => run_maintenance(db, false)?,
+        Command::Stats { days } => run_stats(db, days)?,
+        Command::Usage { months, by, chat } => {
+            let group = UsageGroup::parse(&by).unwrap_or(UsageGroup::Chat);
+            let rows = usage::monthly_usage(&db, group, chat.as_deref(), months)?;
+            println!("{}", usage::format_monthly_usage(&rows));
+        }
+        Command::Audit {
+            kind,
+            actor,
+            chat,
+            since,
+            until,
+            limit,
+        } => {
+            let filter = AuditFilter {
+                kind,
+                actor,
+                chat_jid: chat,
+                since,
+                until,
+            };
+            run_audit(db, &filter, limit)?
+        }
+        // Handled before the database is opened
+        Command::Config(_)
+        | Command::Credentials(_)
+        | Command::MigrateHome
+        | Command::Completions { .. } => {}
     }
 
     Ok(())
 }
 
-/// Run the main application with all features
-async fn run_main_application(db: db::Database) -> Result<()> {
-    info!("Running main application...");
+/// Run the scheduler and the bots `serve` selects until one fails or
+/// NuClaw is stopped
+async fn run_serve(db: db::Database, tenants: Vec<Tenant>, serve: ServeArgs) -> Result<()> {
+    info!("Running NuClaw...");
 
     // Ensure container system is running
     ensure_container_system_running().ok();
 
-    // Setup signal handlers for graceful shutdown
-    let (shutdown_tx, mut shutdown_rx) = tokio::sync::mpsc::channel::<()>(1);
+    if let Some(dir) = credentials::load_auth()? {
+        info!("Decrypted the WhatsApp session into {}", dir.display());
+    }
+
+    // Apply config file changes while running
+    let _config_watcher = config_file::watch_config_file()
+        .map_err(|e| warn!("{}", e))
+        .ok();
 
-    // Clone db for the scheduler
-    let scheduler_db = db.clone();
+    // Each part holds a clone of `started` until it is up; NuClaw is
+    // ready once all of them are dropped
+    let (started, mut starting) = mpsc::channel::<()>(1);
+    let mut parts = tokio::task::JoinSet::new();
+    parts.spawn(http_server::serve(db.clone(), started.clone()));
+    if serve.all() || serve.scheduler {
+        parts.spawn(run_scheduler(db.clone(), tenants.clone(), started.clone()));
+    }
+    if serve.all() || serve.telegram {
+        parts.spawn(run_telegram_bot(
+            db.clone(),
+            tenants.clone(),
+            started.clone(),
+        ));
+    }
+    if serve.all() || serve.whatsapp {
+        parts.spawn(run_whatsapp_bot(db.clone(), tenants, started.clone()));
+    }
+    drop(started);
 
-    // Run scheduler in background
-    let scheduler_handle = tokio::spawn(async move {
-        let mut scheduler = TaskScheduler::new(scheduler_db);
-        let _ = scheduler.run().await;
-    });
+    info!("NuClaw is running. Press Ctrl+C to stop.");
 
-    // Run WhatsApp bot in background
-    let _whatsapp_handle = tokio::spawn(async move {
-        loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+    // A bot without credentials returns at once; the others keep running
+    let mut watchdog = systemd::Watchdog::new();
+    let mut ready = false;
+    let result = loop {
+        tokio::select! {
+            joined = parts.join_next() => match joined {
+                Some(Ok(Ok(()))) => {}
+                Some(Ok(Err(e))) => break Err(e),
+                Some(Err(e)) => break Err(NuClawError::Scheduler { message: e.to_string() }),
+                None => break Ok(()),
+            },
+            _ = starting.recv(), if !ready => {
+                ready = true;
+                systemd::ready();
+            }
+            _ = watchdog.ping() => {}
+            name = shutdown::signal() => {
+                info!("Received {}, shutting down...", name);
+                break Ok(());
+            }
         }
-    });
+    };
 
-    info!("NuClaw is running. Press Ctrl+C to stop.");
+    // Graceful shutdown: stop taking messages, let the runs in progress
+    // finish, and deliver their replies
+    shutdown::request();
+    systemd::stopping();
+    let drain = shutdown::drain(&db, shutdown::grace_period());
+    tokio::pin!(drain);
+    loop {
+        tokio::select! {
+            _ = &mut drain => break,
+            _ = watchdog.ping() => {}
+            name = shutdown::signal() => {
+                warn!("Received {} again, stopping at once", name);
+                break;
+            }
+        }
+    }
+    parts.abort_all();
+    if let Err(e) = credentials::save_auth() {
+        warn!("Failed to seal the WhatsApp session: {}", e);
+    }
+    // Let a standby instance take over the schedule right away
+    let _ = leader::release(&db, leader::instance_id());
+    match db.checkpoint() {
+        Ok(_) => info!("Database closed"),
+        Err(e) => warn!("Failed to close the database cleanly: {}", e),
+    }
+
+    info!("NuClaw shutdown complete.");
+    result
+}
+
+/// Print the effective configuration
+fn run_config_show(config_path: Option<&Path>, dotenv_files: &[PathBuf]) {
+    let config_path = config_path
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| {
+            format!(
+                "none ({} not found)",
+                config_file::config_file_path().display()
+            )
+        });
+    println!("Config file: {}", config_path);
+    if let Some(profile) = config_file::profile() {
+        println!("Profile: {}", profile);
+    }
+    let dotenv: Vec<_> = dotenv_files
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect();
+    if !dotenv.is_empty() {
+        println!("Environment files: {}", dotenv.join(", "));
+    }
+    println!("Store: {}", config::store_dir().display());
+    println!("Groups: {}", config::groups_dir().display());
+    println!("Data: {}", config::data_dir().display());
+    println!();
+    print!(
+        "{}",
+        settings::format_effective(&settings::effective_settings())
+    );
+}
 
-    // Wait for shutdown signal
-    tokio::select! {
-        _ = signal::ctrl_c() => {
-            info!("Received shutdown signal...");
+/// Manage the encrypted credential store
+fn run_credentials(command: CredentialsCommand) -> Result<()> {
+    if let CredentialsCommand::GenerateKey = command {
+        println!("{}", MasterKey::generate().to_hex());
+        return Ok(());
+    }
+    let Some((key, key_source)) = credentials::master_key()? else {
+        return Err(NuClawError::Config {
+            message: "No master key; create one with `nuclaw credentials generate-key` and set \
+                      NUCLAW_MASTER_KEY or NUCLAW_MASTER_KEY_FILE"
+                .to_string(),
+        });
+    };
+    if let CredentialsCommand::SealAuth = command {
+        return run_seal_auth(&key, &key_source);
+    }
+    let mut store = CredentialStore::open(&credentials::credentials_path(), key)?;
+    match command {
+        CredentialsCommand::List => {
+            if store.entries().is_empty() {
+                println!("No credentials in {}", store.path().display());
+            }
+            for name in store.entries().keys() {
+                println!("{}", name);
+            }
+        }
+        CredentialsCommand::Set { name } => {
+            let name = name.trim().to_ascii_uppercase();
+            let mut value = String::new();
+            std::io::stdin().read_line(&mut value)?;
+            store.set(&name, &value)?;
+            store.save()?;
+            println!(
+                "Stored {} in {}, sealed with the key from {}",
+                name,
+                store.path().display(),
+                key_source
+            );
+            if std::env::var_os(&name).is_some() {
+                println!(
+                    "{} is also set in the environment or the config file, which wins over \
+                     the store; remove it there",
+                    name
+                );
+            }
         }
-        _ = shutdown_rx.recv() => {
-            info!("Received shutdown signal...");
+        CredentialsCommand::Remove { name } => {
+            if !store.remove(&name) {
+                return Err(NuClawError::Validation {
+                    message: format!("{} is not in the credential store", name),
+                });
+            }
+            store.save()?;
+            println!("Removed {} from {}", name, store.path().display());
         }
+        CredentialsCommand::GenerateKey | CredentialsCommand::SealAuth => {}
     }
+    Ok(())
+}
 
-    // Graceful shutdown
-    let _ = shutdown_tx.send(()).await;
-    scheduler_handle.abort();
+/// Seal the WhatsApp session and delete its plain-text files
+fn run_seal_auth(key: &MasterKey, key_source: &str) -> Result<()> {
+    let dir = credentials::auth_dir();
+    let path = credentials::sealed_auth_path();
+    let count = credentials::seal_auth(&dir, &path, key)?;
+    println!(
+        "Sealed {} files of {} into {} with the key from {}",
+        count,
+        dir.display(),
+        path.display(),
+        key_source
+    );
+    Ok(())
+}
 
-    info!("NuClaw shutdown complete.");
+/// Move the files of an earlier version out of the working directory
+fn run_migrate_home() -> Result<()> {
+    let Some(dir) = config::legacy_layout() else {
+        println!(
+            "Nothing to migrate; NuClaw keeps its store in {}",
+            config::store_dir().display()
+        );
+        return Ok(());
+    };
+    let moves = config::migrate_legacy_layout(&dir).map_err(|e| NuClawError::FileSystem {
+        message: format!("Failed to migrate {}: {}", dir.display(), e),
+    })?;
+    for (old, new) in moves {
+        println!("Moved {} to {}", old.display(), new.display());
+    }
     Ok(())
 }
 
-/// Run the task scheduler
-async fn run_scheduler(db: db::Database) -> Result<()> {
+/// Run the task scheduler of each tenant
+async fn run_scheduler(
+    db: db::Database,
+    tenants: Vec<Tenant>,
+    started: mpsc::Sender<()>,
+) -> Result<()> {
     info!("Starting task scheduler...");
 
-    let mut scheduler = TaskScheduler::new(db);
-    scheduler.run().await?;
+    let mut schedulers = tokio::task::JoinSet::new();
+    for tenant in &tenants {
+        let mut scheduler = TaskScheduler::new(db.for_tenant(&tenant.id));
+        schedulers.spawn(async move { scheduler.run().await });
+    }
+    drop(started);
+    while let Some(result) = schedulers.join_next().await {
+        result.map_err(|e| NuClawError::Scheduler {
+            message: e.to_string(),
+        })??;
+    }
 
     Ok(())
 }
 
-/// Run the WhatsApp bot
-async fn run_whatsapp_bot(db: db::Database) -> Result<()> {
+/// Run the WhatsApp bot of each tenant with an MCP server
+async fn run_whatsapp_bot(
+    db: db::Database,
+    tenants: Vec<Tenant>,
+    started: mpsc::Sender<()>,
+) -> Result<()> {
     info!("Starting WhatsApp bot...");
 
     // Check if WhatsApp MCP is configured
-    if std::env::var("WHATSAPP_MCP_URL").is_err() {
-        info!("WHATSAPP_MCP_URL not set. Run with --auth to set up authentication.");
-        info!("Then start the WhatsApp MCP server and run with --whatsapp.");
+    let tenants: Vec<_> = tenants
+        .iter()
+        .filter(|t| t.whatsapp_mcp_url().is_some())
+        .collect();
+    if tenants.is_empty() {
+        info!("WHATSAPP_MCP_URL not set. Run `nuclaw auth whatsapp` to set up authentication.");
+        info!("Then start the WhatsApp MCP server and run `nuclaw serve`.");
         return Ok(());
     }
 
-    // Create WhatsApp client
-    let mut client = whatsapp::WhatsAppClient::new(db);
+    let mut listeners = tokio::task::JoinSet::new();
+    for tenant in tenants {
+        // Create WhatsApp client
+        let mut client = whatsapp::WhatsAppClient::for_tenant(db.clone(), tenant);
 
-    // Connect to WhatsApp
-    client.connect().await?;
-    info!("Connected to WhatsApp");
+        // Connect to WhatsApp
+        client.connect().await?;
+        info!("Connected to WhatsApp for tenant {}", tenant.id);
 
-    // Start message listener
-    client.start_message_listener().await;
+        // Start message listener
+        listeners.spawn(async move { client.start_message_listener().await });
+    }
+    drop(started);
+    while listeners.join_next().await.is_some() {}
+
+    Ok(())
+}
+
+/// List, grant, or revoke roles
+fn run_role(db: db::Database, command: RoleCommand) -> Result<()> {
+    let (channel, user_id, group, role) = match command {
+        RoleCommand::List => {
+            println!("{}", roles::format_roles(&roles::list_roles(&db)?));
+            return Ok(());
+        }
+        RoleCommand::Set {
+            channel,
+            user_id,
+            role,
+            group,
+        } => (channel, user_id, group, roles::Role::parse(&role)),
+        RoleCommand::Remove {
+            channel,
+            user_id,
+            group,
+        } => (channel, user_id, group, None),
+    };
+    let scope = group
+        .as_deref()
+        .map(|folder| format!(" in {}", folder))
+        .unwrap_or_default();
+    let user = audit::chat_actor(&channel, &user_id);
+    let detail = match role {
+        Some(role) => {
+            roles::set_role(&db, &channel, &user_id, group.as_deref(), role, "cli")?;
+            format!("Made {} {}{}", user, role.as_str(), scope)
+        }
+        None => {
+            if !roles::remove_role(&db, &channel, &user_id, group.as_deref())? {
+                return Err(NuClawError::Validation {
+                    message: format!("{} has no role{}", user, scope),
+                });
+            }
+            format!("Revoked the role of {}{}", user, scope)
+        }
+    };
+    audit::record(
+        &db,
+        AuditEvent::new(AuditKind::Role, "cli", detail.as_str()),
+    );
+    println!("{}", detail);
+    Ok(())
+}
 
+/// List or revoke pairings
+fn run_pairings(db: db::Database, command: PairCommand) -> Result<()> {
+    match command {
+        PairCommand::List => {
+            let paired = pairing::list_paired_users(&db)?;
+            if paired.is_empty() {
+                println!("No paired users");
+            }
+            for user in paired {
+                println!("{}  {:<9}  {}", user.paired_at, user.channel, user.user_id);
+            }
+        }
+        PairCommand::Revoke { channel, user_id } => {
+            if !pairing::unpair(&db, &channel, &user_id)? {
+                return Err(NuClawError::Validation {
+                    message: format!("{} user {} is not paired", channel, user_id),
+                });
+            }
+            println!("Unpaired {} user {}", channel, user_id);
+        }
+    }
     Ok(())
 }
 
@@ -167,29 +584,516 @@ async fn run_auth_flow() -> Result<()> {
     Ok(())
 }
 
-/// Run the Telegram bot
-async fn run_telegram_bot(db: db::Database) -> Result<()> {
+/// Issue a DM pairing code
+fn run_pair(db: db::Database) -> Result<()> {
+    let code = pairing::create_pairing_code(&db, "cli")?;
+    println!("Pairing code: {}", code);
+    println!(
+        "Valid for {} minutes. Send it to the bot in a private chat to pair.",
+        pairing::pairing_code_ttl().num_minutes()
+    );
+    Ok(())
+}
+
+/// Pause or resume every NuClaw process sharing the database
+fn run_maintenance(db: db::Database, pause: bool) -> Result<()> {
+    if pause {
+        if maintenance::pause(&db, "cli")? {
+            println!("Paused. No new task or agent runs will start; runs in progress will finish.");
+        } else {
+            println!("Already paused.");
+        }
+    } else if maintenance::resume(&db)? {
+        println!(
+            "Resumed. Running schedulers pick this up within SCHEDULER_POLL_INTERVAL seconds."
+        );
+    } else {
+        println!("Not paused.");
+    }
+    Ok(())
+}
+
+/// Run a task management command
+fn run_task(db: db::Database, tenant: &Tenant, command: TaskCommand) -> Result<()> {
+    let not_found = |task_id: &str| NuClawError::Validation {
+        message: format!("No task {}", task_id),
+    };
+    match command {
+        TaskCommand::Add {
+            group,
+            schedule_type,
+            schedule_value,
+            prompt,
+            timeout,
+            notify,
+        } => {
+            let (chat_jid, group) = find_group(tenant, &group)?;
+            let task = create_task(
+                &db,
+                NewTask {
+                    group_folder: group.folder,
+                    chat_jid,
+                    prompt: prompt.join(" "),
+                    schedule_type,
+                    schedule_value,
+                    timeout_secs: timeout,
+                    notify,
+                    ..Default::default()
+                },
+            )?;
+            println!("Created task {}", task.id);
+            print_next_runs(&task);
+        }
+        TaskCommand::List { group } => {
+            let chat_jid = match group {
+                Some(group) => Some(find_group(tenant, &group)?.0),
+                None => None,
+            };
+            let tasks = db.tasks().list(chat_jid.as_deref())?;
+            if tasks.is_empty() {
+                println!("No scheduled tasks");
+            }
+            for task in tasks {
+                println!(
+                    "{}  {:<9}  {:<20}  {:<25}  {:<20}  {}",
+                    task.id,
+                    task.status,
+                    task.group_folder,
+                    task.next_run.as_deref().unwrap_or("-"),
+                    format!("{} {}", task.schedule_type, task.schedule_value),
+                    first_line(&task.prompt, 60)
+                );
+            }
+        }
+        TaskCommand::Show { task_id } => {
+            let task = db
+                .tasks()
+                .get(&task_id)?
+                .ok_or_else(|| not_found(&task_id))?;
+            println!("Task {} ({})", task.id, task.status);
+            println!("Group: {} ({})", task.group_folder, task.chat_jid);
+            println!("Schedule: {} {}", task.schedule_type, task.schedule_value);
+            println!("Prompt: {}", task.prompt);
+            println!("Created: {}", task.created_at);
+            println!("Last run: {}", task.last_run.as_deref().unwrap_or("-"));
+            println!(
+                "Notify: {}, overlap: {}, priority: {}",
+                task.notify, task.overlap, task.priority
+            );
+            if let Some(timeout) = task.timeout_secs {
+                println!("Timeout: {}s", timeout);
+            }
+            if let Some(lock) = &task.lock {
+                println!("Lock: {}", lock);
+            }
+            if task.status == "active" {
+                print_next_runs(&task);
+            }
+            let filter = RunFilter {
+                task_id: Some(task.id),
+                ..Default::default()
+            };
+            let runs = query_runs(&db, &filter, SHOWN_TASK_RUNS)?;
+            println!("Latest runs:{}", if runs.is_empty() { " none" } else { "" });
+            print_runs(&runs);
+        }
+        TaskCommand::Pause { task_id } => {
+            if !set_task_paused(&db, &task_id, true)? {
+                return Err(not_found(&task_id));
+            }
+            println!("Paused task {}", task_id);
+        }
+        TaskCommand::Resume { task_id } => {
+            if !set_task_paused(&db, &task_id, false)? {
+                return Err(not_found(&task_id));
+            }
+            println!("Resumed task {}", task_id);
+        }
+        TaskCommand::Delete { task_id } => {
+            if !db.tasks().delete(&task_id)? {
+                return Err(not_found(&task_id));
+            }
+            println!("Deleted task {}", task_id);
+        }
+        TaskCommand::RunNow { task_id } => {
+            if !run_task_now(&db, &task_id)? {
+                return Err(not_found(&task_id));
+            }
+            println!(
+                "Task {} is due now; the running scheduler starts it within {} seconds",
+                task_id,
+                poll_interval().as_secs()
+            );
+        }
+        TaskCommand::Runs {
+            task_id,
+            status,
+            since,
+            until,
+            limit,
+        } => {
+            let filter = RunFilter {
+                task_id,
+                status,
+                since,
+                until,
+            };
+            let runs = query_runs(&db, &filter, limit)?;
+            if runs.is_empty() {
+                println!("No matching task runs");
+            }
+            print_runs(&runs);
+        }
+        TaskCommand::CheckSchedule {
+            schedule_type,
+            schedule_value,
+            count,
+        } => run_check_schedule(&schedule_type, &schedule_value, count)?,
+    }
+    Ok(())
+}
+
+/// A group of the tenant registered under a folder
+fn find_group(tenant: &Tenant, folder: &str) -> Result<(String, RegisteredGroup)> {
+    groups::find_group(&tenant.id, folder).ok_or_else(|| NuClawError::Validation {
+        message: format!("No group is registered under folder '{}'", folder),
+    })
+}
+
+/// Print the next runs of a task's schedule
+fn print_next_runs(task: &ScheduledTask) {
+    match preview_schedule(&task.schedule_type, &task.schedule_value, SHOWN_NEXT_RUNS) {
+        Ok(preview) => {
+            println!("Next runs ({}):", preview.timezone);
+            for at in &preview.next_runs {
+                println!("  {}", at);
+            }
+        }
+        Err(_) => println!("Next run: {}", task.next_run.as_deref().unwrap_or("-")),
+    }
+}
+
+/// First line of a text, cut to `max` characters
+fn first_line(text: &str, max: usize) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    match line.char_indices().nth(max) {
+        Some((i, _)) => format!("{}…", &line[..i]),
+        None => line.to_string(),
+    }
+}
+
+/// Print the next runs of a schedule, or fail if it is invalid
+fn run_check_schedule(schedule_type: &str, schedule_value: &str, count: usize) -> Result<()> {
+    let preview = preview_schedule(schedule_type, schedule_value, count)?;
+    println!(
+        "Valid {} schedule '{}'. Next runs ({}):",
+        preview.schedule_type, preview.schedule_value, preview.timezone
+    );
+    for at in &preview.next_runs {
+        println!("  {}", at);
+    }
+    Ok(())
+}
+
+/// Queue a broadcast to the resolved groups
+fn run_broadcast(db: db::Database, target: &str, text: &str) -> Result<()> {
+    let report = broadcast::broadcast(&db, target, text)?;
+    for (channel, count) in &report {
+        println!("Queued for {} {} chat(s)", count, channel);
+    }
+    println!("Running bots deliver queued messages from the outbox.");
+    Ok(())
+}
+
+/// Print task runs, newest first
+fn print_runs(runs: &[TaskRunLog]) {
+    for run in runs {
+        let detail = run.error.as_ref().or(run.result.as_ref());
+        let detail = detail.and_then(|d| d.lines().next()).unwrap_or_default();
+        println!(
+            "{}  {}  {:<9}  {:>8}ms  {}",
+            run.run_at, run.task_id, run.status, run.duration_ms, detail
+        );
+    }
+}
+
+/// Print the audit log events matching `filter`
+fn run_audit(db: db::Database, filter: &AuditFilter, limit: usize) -> Result<()> {
+    let events = audit::query(&db, filter, limit)?;
+    if events.is_empty() {
+        println!("No matching audit events");
+    }
+    for event in events {
+        println!(
+            "{}  {:<9}  {}  {}{}",
+            event.created_at,
+            event.kind,
+            event.actor,
+            event
+                .chat_jid
+                .map(|jid| format!("in {}  ", jid))
+                .unwrap_or_default(),
+            event.detail
+        );
+    }
+    Ok(())
+}
+
+/// Run a group management command
+fn run_group(db: db::Database, tenant: &Tenant, command: GroupCommand) -> Result<()> {
+    match command {
+        GroupCommand::Register {
+            chat_jid,
+            folder,
+            triggers,
+        } => {
+            let mut group = groups::register_group(&tenant.id, &chat_jid, &folder)?;
+            if !triggers.is_empty() {
+                group = groups::set_triggers(&tenant.id, &chat_jid, &triggers)?;
+            }
+            println!(
+                "Registered {} as '{}' in {}, triggered by {}",
+                chat_jid,
+                group.folder,
+                config::groups_dir().join(&group.folder).display(),
+                group.triggers().join(", ")
+            );
+        }
+        GroupCommand::List => {
+            print_groups(tenant);
+            return Ok(());
+        }
+        GroupCommand::Unregister { folder } => {
+            let removed = groups::unregister_group(&db, &folder)?;
+            println!(
+                "Unregistered {} ('{}') and deleted {} task(s)",
+                removed.chat_jid, removed.group.folder, removed.deleted_tasks
+            );
+            if let Some(path) = removed.archived_to {
+                println!("Archived its folder to {}", path.display());
+            }
+        }
+        GroupCommand::Rename { folder, new_folder } => {
+            let group = groups::rename_group(&db, &folder, &new_folder)?;
+            println!("Moved '{}' to '{}'", folder, group.folder);
+        }
+    }
+    println!("Running bots load registered groups at startup; restart them to apply this.");
+    Ok(())
+}
+
+/// Print the registered groups of a tenant
+fn print_groups(tenant: &Tenant) {
+    let mut groups: Vec<_> = groups::load_registered_groups(&tenant.id)
+        .into_iter()
+        .collect();
+    if groups.is_empty() {
+        println!("No registered groups");
+    }
+    groups.sort_by(|a, b| a.1.folder.cmp(&b.1.folder));
+    for (chat_jid, group) in groups {
+        println!(
+            "{:<20}  {:<20}  {:<30}  {}",
+            group.folder,
+            group.name,
+            group.triggers().join(", "),
+            chat_jid
+        );
+    }
+}
+
+/// Run a database maintenance command
+fn run_db(db: db::Database, command: DbCommand) -> Result<()> {
+    match command {
+        DbCommand::Status => {
+            println!("Backend: {}", db.backend().as_str());
+            match &db.config().database_url {
+                Some(_) => println!("Location: DATABASE_URL"),
+                None => println!("Location: {}", db.config().db_path.display()),
+            }
+            let pool = db.pool_status();
+            println!(
+                "Connections: {} active, {} idle, {} at most",
+                pool.connections_active, pool.connections_idle, pool.max_size
+            );
+            println!(
+                "Chats: {}, scheduled tasks: {}",
+                db.chats().list()?.len(),
+                db.tasks().list(None)?.len()
+            );
+        }
+        // Opening the database has created or upgraded the schema
+        DbCommand::Migrate => println!("The {} schema is up to date", db.backend().as_str()),
+        DbCommand::Vacuum => {
+            db.vacuum()?;
+            println!("Vacuumed the database");
+        }
+    }
+    Ok(())
+}
+
+/// Run the agent of a group folder on prompts read from the terminal
+async fn run_repl(db: db::Database, tenant: &Tenant, group: &str) -> Result<()> {
+    groups::validate_folder_name(group)?;
+    let group_folder = tenant.group_folder(group);
+    std::fs::create_dir_all(config::groups_dir().join(&group_folder)).map_err(|e| {
+        NuClawError::FileSystem {
+            message: format!("Failed to create group directory: {}", e),
+        }
+    })?;
+    // Sessions are kept per chat, so the REPL continues its own
+    let chat_jid = format!("{}{}", REPL_CHAT_PREFIX, group_folder);
+    ensure_container_system_running().ok();
+
+    println!(
+        "Chatting with the agent of {}. /new starts a new session; /exit or Ctrl+D quits.",
+        group_folder
+    );
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        let line = lines
+            .next_line()
+            .await
+            .map_err(|e| NuClawError::FileSystem {
+                message: format!("Failed to read the prompt: {}", e),
+            })?;
+        let Some(line) = line else {
+            println!();
+            break;
+        };
+        let prompt = line.trim();
+        match prompt {
+            "" => continue,
+            "/exit" => break,
+            "/new" => {
+                sessions::clear_session(&db, &chat_jid)?;
+                println!("Started a new session");
+                continue;
+            }
+            _ => {}
+        }
+
+        let resumed = sessions::resume_session_id(&db, &chat_jid);
+        let input = ContainerInput {
+            prompt: prompt.to_string(),
+            session_id: resumed.clone(),
+            resume_session: resumed.is_some(),
+            group_folder: group_folder.clone(),
+            chat_jid: chat_jid.clone(),
+            is_main: true,
+            is_scheduled_task: false,
+            context: vec![],
+            reply_to_id: None,
+            quoted_content: None,
+            memories: relevant_memories(&db, &group_folder, prompt).await,
+            timeout: None,
+            sender: Some("cli".to_string()),
+            correlation_id: None,
+            permissions: None,
+        };
+        let policy = RetryPolicy::for_channel(REPL_CHANNEL);
+        match run_container_with_retry(&db, input, policy, None).await {
+            Ok(output) => {
+                if let Err(e) =
+                    sessions::record_run_session(&db, &chat_jid, resumed.as_deref(), &output)
+                {
+                    warn!("Failed to store the session: {}", e);
+                }
+                println!("{}", output.result.or(output.error).unwrap_or_default());
+            }
+            Err(e) => println!("Error: {}", e),
+        }
+        if let Err(e) = broadcast::process_ipc_requests(&db, &group_folder) {
+            warn!("Failed to process IPC requests for {}: {}", group_folder, e);
+        }
+    }
+    Ok(())
+}
+
+/// Print usage over the last `days` days
+fn run_stats(db: db::Database, days: u32) -> Result<()> {
+    let stats = analytics::usage_stats(&db, days)?;
+    println!("Usage since {} ({} days)", stats.since, stats.days);
+    println!(
+        "Messages: {}, triggered: {} ({:.0}%)",
+        stats.messages,
+        stats.triggered,
+        stats.trigger_rate * 100.0
+    );
+    println!(
+        "Container runs: {}, average duration: {}ms",
+        stats.container_runs, stats.avg_container_duration_ms
+    );
+    println!(
+        "Task runs: {}, succeeded: {} ({:.0}%)",
+        stats.task_runs,
+        stats.task_successes,
+        stats.task_success_rate * 100.0
+    );
+    for (group, usage) in &stats.groups {
+        println!(
+            "\n{}: {} message(s), {:.0}% triggered",
+            group,
+            usage.messages,
+            usage.trigger_rate * 100.0
+        );
+        for (day, count) in &usage.per_day {
+            println!("  {}  {}", day, count);
+        }
+    }
+    Ok(())
+}
+
+/// Run the Telegram bot of each tenant with a bot token
+async fn run_telegram_bot(
+    db: db::Database,
+    tenants: Vec<Tenant>,
+    started: mpsc::Sender<()>,
+) -> Result<()> {
     info!("Starting Telegram bot...");
 
     // Check if Telegram bot token is configured
-    if std::env::var("TELEGRAM_BOT_TOKEN").is_err() {
+    let tenants: Vec<_> = tenants
+        .iter()
+        .filter(|t| t.telegram_bot_token().is_some())
+        .collect();
+    if tenants.is_empty() {
         info!("TELEGRAM_BOT_TOKEN not set. Configure it to use Telegram bot.");
         info!("Usage:");
         info!("  export TELEGRAM_BOT_TOKEN=your_bot_token");
         info!("  export TELEGRAM_WEBHOOK_URL=https://your-domain.com");
-        info!("  ./nuclaw --telegram");
+        info!("  ./nuclaw serve --telegram");
         return Ok(());
     }
 
-    // Create Telegram client
-    let mut client = telegram::TelegramClient::new(db)?;
+    let mut clients = Vec::new();
+    for tenant in tenants {
+        // Create Telegram client
+        let mut client = telegram::TelegramClient::for_tenant(db.clone(), tenant)?;
 
-    // Connect to Telegram
-    client.connect().await?;
-    info!("Connected to Telegram");
+        // Connect to Telegram
+        client.connect().await?;
+        info!("Connected to Telegram for tenant {}", tenant.id);
+        clients.push(client);
+    }
+    drop(started);
 
-    // Start webhook server
-    client.start_webhook_server().await?;
+    // Receive updates through the webhook if configured, otherwise poll
+    if std::env::var("TELEGRAM_WEBHOOK_URL").is_ok() {
+        telegram::serve_webhooks(clients).await?;
+    } else {
+        let mut pollers = tokio::task::JoinSet::new();
+        for client in clients {
+            pollers.spawn(client.start_polling());
+        }
+        while let Some(result) = pollers.join_next().await {
+            result.map_err(|e| NuClawError::Telegram {
+                message: e.to_string(),
+            })??;
+        }
+    }
 
     Ok(())
 }
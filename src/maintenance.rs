@@ -0,0 +1,96 @@
+//! Maintenance Mode for NuClaw
+//!
+//! Pausing NuClaw (`nuclaw pause`, the `/pause` chat command, or
+//! `POST /api/maintenance/pause`) stops the scheduler from starting task
+//! runs and answers triggered chat messages with a notice instead of
+//! starting an agent. Runs already in flight finish normally, so the host
+//! can be upgraded once they are done.
+//!
+//! The switch is stored in the `maintenance` table, so every NuClaw process
+//! sharing the database sees it.
+
+use crate::db::Database;
+use crate::error::Result;
+use serde::Serialize;
+
+/// Reply to triggered messages while paused
+pub const PAUSED_NOTICE: &str = "I'm paused for maintenance. Please try again later.";
+
+/// When and by whom NuClaw was paused
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Pause {
+    pub since: String,
+    pub by: String,
+}
+
+/// Current pause, if any
+pub fn pause_state(db: &Database) -> Result<Option<Pause>> {
+    let conn = db.read_connection()?;
+    conn.query_opt(
+        "SELECT since, paused_by FROM maintenance WHERE id = 1",
+        (),
+        |row| {
+            Ok(Pause {
+                since: row.get(0)?,
+                by: row.get(1)?,
+            })
+        },
+    )
+}
+
+/// Whether NuClaw is paused; a failed lookup counts as not paused
+pub fn is_paused(db: &Database) -> bool {
+    match pause_state(db) {
+        Ok(state) => state.is_some(),
+        Err(e) => {
+            tracing::warn!("Failed to read maintenance state: {}", e);
+            false
+        }
+    }
+}
+
+/// Pause NuClaw; `false` if it was already paused
+pub fn pause(db: &Database, by: &str) -> Result<bool> {
+    let conn = db.get_connection()?;
+    let changed = conn.execute(
+        "INSERT INTO maintenance (id, since, paused_by) VALUES (1, ?, ?) ON CONFLICT DO NOTHING",
+        [chrono::Utc::now().to_rfc3339().as_str(), by],
+    )?;
+    if changed > 0 {
+        tracing::info!("Paused for maintenance by {}", by);
+    }
+    Ok(changed > 0)
+}
+
+/// Resume NuClaw; `false` if it was not paused
+pub fn resume(db: &Database) -> Result<bool> {
+    let conn = db.get_connection()?;
+    let changed = conn.execute("DELETE FROM maintenance", ())?;
+    if changed > 0 {
+        tracing::info!("Resumed after maintenance");
+        crate::task_scheduler::wake_all_schedulers();
+    }
+    Ok(changed > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_pause_and_resume() {
+        let (db, _dir) = test_database();
+        assert!(!is_paused(&db));
+        assert!(!resume(&db).unwrap());
+
+        assert!(pause(&db, "cli").unwrap());
+        assert!(!pause(&db, "admin-api").unwrap());
+        let state = pause_state(&db).unwrap().unwrap();
+        assert_eq!(state.by, "cli");
+        assert!(is_paused(&db));
+
+        assert!(resume(&db).unwrap());
+        assert!(!is_paused(&db));
+    }
+}
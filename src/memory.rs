@@ -0,0 +1,400 @@
+//! Long-Term Memory for NuClaw
+//!
+//! Facts worth keeping across sessions are stored per tenant and group
+//! folder in the `memories` table. Agents add them by writing
+//! `{"op": "remember", "text": "..."}` files to `/workspace/ipc/requests/`.
+//! Before each agent run, the `MEMORY_TOP_K` memories closest to the
+//! prompt are passed to the agent in `ContainerInput.memories`.
+//!
+//! Closeness is the cosine similarity of embeddings from an
+//! OpenAI-compatible `/embeddings` endpoint. Memories are embedded when
+//! they are first needed and the vectors are stored next to them, so
+//! writing a memory never waits on the API. Retrieval is disabled unless
+//! `EMBEDDING_API_KEY` is set.
+
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use serde::Serialize;
+use std::time::Duration;
+use tracing::warn;
+
+/// Default embedding endpoint
+const DEFAULT_EMBEDDING_API_URL: &str = "https://api.openai.com/v1/embeddings";
+/// Default embedding model
+const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+/// Default request timeout: 30 seconds
+const DEFAULT_EMBEDDING_TIMEOUT_SECS: u64 = 30;
+/// Default number of memories passed to the agent
+const DEFAULT_MEMORY_TOP_K: usize = 5;
+/// Longest memory accepted, in characters
+const MAX_MEMORY_CHARS: usize = 2000;
+/// Memories embedded per API request
+const EMBEDDING_BATCH_SIZE: usize = 64;
+
+/// Embedding settings read from the environment
+#[derive(Debug, Clone)]
+pub struct EmbeddingConfig {
+    pub api_url: String,
+    pub api_key: String,
+    pub model: String,
+    pub timeout: Duration,
+}
+
+impl EmbeddingConfig {
+    /// Load the configuration; `None` if embeddings are not set up
+    pub fn from_env() -> Option<Self> {
+        let api_key = std::env::var("EMBEDDING_API_KEY")
+            .ok()
+            .filter(|k| !k.is_empty())?;
+        Some(Self {
+            api_url: std::env::var("EMBEDDING_API_URL")
+                .unwrap_or_else(|_| DEFAULT_EMBEDDING_API_URL.to_string()),
+            api_key,
+            model: std::env::var("EMBEDDING_MODEL")
+                .unwrap_or_else(|_| DEFAULT_EMBEDDING_MODEL.to_string()),
+            timeout: Duration::from_secs(
+                std::env::var("EMBEDDING_TIMEOUT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_EMBEDDING_TIMEOUT_SECS),
+            ),
+        })
+    }
+}
+
+/// Memories passed to the agent per run (`MEMORY_TOP_K`); 0 disables
+/// retrieval
+pub fn memory_top_k() -> usize {
+    std::env::var("MEMORY_TOP_K")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MEMORY_TOP_K)
+}
+
+/// A stored memory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Memory {
+    pub id: i64,
+    pub group_folder: String,
+    pub content: String,
+    pub created_at: String,
+}
+
+/// Store a memory for a group folder; it is embedded on its first recall
+pub fn add_memory(db: &Database, group_folder: &str, content: &str) -> Result<i64> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Err(NuClawError::Validation {
+            message: "Memory is empty".to_string(),
+        });
+    }
+    if content.chars().count() > MAX_MEMORY_CHARS {
+        return Err(NuClawError::Validation {
+            message: format!("Memory is longer than {} characters", MAX_MEMORY_CHARS),
+        });
+    }
+    db.get_connection()?.query_row(
+        "INSERT INTO memories (tenant, group_folder, content, created_at)
+         VALUES (?, ?, ?, ?) RETURNING id",
+        [
+            db.tenant(),
+            group_folder,
+            content,
+            &chrono::Utc::now().to_rfc3339(),
+        ],
+        |row| row.get(0),
+    )
+}
+
+/// A group folder's memories, oldest first
+pub fn list_memories(db: &Database, group_folder: &str) -> Result<Vec<Memory>> {
+    db.read_connection()?.query_map(
+        "SELECT id, group_folder, content, created_at FROM memories
+         WHERE tenant = ? AND group_folder = ? ORDER BY id",
+        [db.tenant(), group_folder],
+        |row| {
+            Ok(Memory {
+                id: row.get(0)?,
+                group_folder: row.get(1)?,
+                content: row.get(2)?,
+                created_at: row.get(3)?,
+            })
+        },
+    )
+}
+
+/// Delete a memory; `false` if there was none
+pub fn delete_memory(db: &Database, id: i64) -> Result<bool> {
+    let deleted = db.get_connection()?.execute(
+        "DELETE FROM memories WHERE tenant = ? AND id = ?",
+        crate::params![db.tenant(), id],
+    )?;
+    Ok(deleted > 0)
+}
+
+/// A memory with its stored embedding, if it has one for the model
+struct StoredMemory {
+    id: i64,
+    content: String,
+    embedding: Option<Vec<f32>>,
+}
+
+fn load_for_recall(db: &Database, group_folder: &str, model: &str) -> Result<Vec<StoredMemory>> {
+    db.read_connection()?.query_map(
+        "SELECT id, content, embedding, embedding_model FROM memories
+         WHERE tenant = ? AND group_folder = ? ORDER BY id",
+        [db.tenant(), group_folder],
+        |row| {
+            // Vectors of another model are not comparable and are replaced
+            let embedding = match row.get::<Option<String>>(3)? {
+                Some(m) if m == model => row
+                    .get::<Option<String>>(2)?
+                    .and_then(|v| serde_json::from_str(&v).ok()),
+                _ => None,
+            };
+            Ok(StoredMemory {
+                id: row.get(0)?,
+                content: row.get(1)?,
+                embedding,
+            })
+        },
+    )
+}
+
+fn store_embeddings(db: &Database, model: &str, embedded: &[(i64, Vec<f32>)]) -> Result<()> {
+    let conn = db.get_connection()?;
+    for (id, vector) in embedded {
+        let vector = serde_json::to_string(vector).map_err(|e| NuClawError::Database {
+            message: format!("Failed to serialize embedding: {}", e),
+        })?;
+        conn.execute(
+            "UPDATE memories SET embedding = ?, embedding_model = ? WHERE tenant = ? AND id = ?",
+            crate::params![vector, model, db.tenant(), *id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Embed texts, returning one vector per input in order
+pub async fn embed(config: &EmbeddingConfig, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+    let response = reqwest::Client::new()
+        .post(&config.api_url)
+        .bearer_auth(&config.api_key)
+        .json(&serde_json::json!({ "model": config.model, "input": inputs }))
+        .timeout(config.timeout)
+        .send()
+        .await
+        .map_err(|e| NuClawError::Embedding {
+            message: format!("Failed to call embedding API: {}", e),
+        })?;
+
+    if !response.status().is_success() {
+        return Err(NuClawError::Embedding {
+            message: format!(
+                "Embedding API returned {}: {}",
+                response.status(),
+                response.text().await.unwrap_or_default()
+            ),
+        });
+    }
+
+    let body: serde_json::Value = response.json().await.map_err(|e| NuClawError::Embedding {
+        message: format!("Failed to parse embeddings: {}", e),
+    })?;
+    parse_embeddings(&body, inputs.len())
+}
+
+/// Extract the vectors from an API response, ordered by input index
+fn parse_embeddings(body: &serde_json::Value, expected: usize) -> Result<Vec<Vec<f32>>> {
+    let invalid = |message: &str| NuClawError::Embedding {
+        message: message.to_string(),
+    };
+    let mut data: Vec<(u64, Vec<f32>)> = body
+        .get("data")
+        .and_then(|d| d.as_array())
+        .ok_or_else(|| invalid("Embedding response has no data"))?
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let index = item
+                .get("index")
+                .and_then(|i| i.as_u64())
+                .unwrap_or(i as u64);
+            let vector = item
+                .get("embedding")
+                .and_then(|e| e.as_array())
+                .ok_or_else(|| invalid("Embedding response item has no vector"))?
+                .iter()
+                .map(|v| v.as_f64().map(|v| v as f32))
+                .collect::<Option<Vec<f32>>>()
+                .ok_or_else(|| invalid("Embedding vector is not numeric"))?;
+            Ok((index, vector))
+        })
+        .collect::<Result<_>>()?;
+    if data.len() != expected {
+        return Err(NuClawError::Embedding {
+            message: format!(
+                "Embedding API returned {} vectors for {} inputs",
+                data.len(),
+                expected
+            ),
+        });
+    }
+    data.sort_by_key(|(index, _)| *index);
+    Ok(data.into_iter().map(|(_, vector)| vector).collect())
+}
+
+/// Cosine similarity of two vectors; 0 if they cannot be compared
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+/// The `k` memories most similar to `query`, most similar first
+fn top_k(query: &[f32], memories: Vec<(String, Vec<f32>)>, k: usize) -> Vec<String> {
+    let mut scored: Vec<(f32, String)> = memories
+        .into_iter()
+        .map(|(content, vector)| (cosine_similarity(query, &vector), content))
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored
+        .into_iter()
+        .take(k)
+        .map(|(_, content)| content)
+        .collect()
+}
+
+/// Memories of a group folder relevant to a prompt, most relevant first
+///
+/// Failures are logged and yield no memories rather than failing the run.
+pub async fn relevant_memories(db: &Database, group_folder: &str, prompt: &str) -> Vec<String> {
+    let Some(config) = EmbeddingConfig::from_env() else {
+        return vec![];
+    };
+    let k = memory_top_k();
+    if k == 0 {
+        return vec![];
+    }
+    recall(db, &config, group_folder, prompt, k)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Failed to recall memories for {}: {}", group_folder, e);
+            vec![]
+        })
+}
+
+async fn recall(
+    db: &Database,
+    config: &EmbeddingConfig,
+    group_folder: &str,
+    prompt: &str,
+    k: usize,
+) -> Result<Vec<String>> {
+    let memories = {
+        let (group_folder, model) = (group_folder.to_string(), config.model.clone());
+        db.call(move |db| load_for_recall(db, &group_folder, &model))
+            .await?
+    };
+    if memories.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let missing: Vec<&StoredMemory> = memories.iter().filter(|m| m.embedding.is_none()).collect();
+    let mut embedded: Vec<(i64, Vec<f32>)> = Vec::with_capacity(missing.len());
+    for batch in missing.chunks(EMBEDDING_BATCH_SIZE) {
+        let texts: Vec<String> = batch.iter().map(|m| m.content.clone()).collect();
+        let vectors = embed(config, &texts).await?;
+        embedded.extend(batch.iter().map(|m| m.id).zip(vectors));
+    }
+    if !embedded.is_empty() {
+        let (model, embedded) = (config.model.clone(), embedded.clone());
+        db.call(move |db| store_embeddings(db, &model, &embedded))
+            .await?;
+    }
+
+    let query = embed(config, &[prompt.to_string()])
+        .await?
+        .pop()
+        .unwrap_or_default();
+    let mut new_vectors: std::collections::HashMap<i64, Vec<f32>> = embedded.into_iter().collect();
+    let candidates = memories
+        .into_iter()
+        .filter_map(|m| {
+            let vector = m.embedding.or_else(|| new_vectors.remove(&m.id))?;
+            Some((m.content, vector))
+        })
+        .collect();
+    Ok(top_k(&query, candidates, k))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_memories() {
+        let (db, _dir) = test_database();
+        assert!(add_memory(&db, "family", "  ").is_err());
+        assert!(add_memory(&db, "family", &"x".repeat(MAX_MEMORY_CHARS + 1)).is_err());
+
+        let id = add_memory(&db, "family", " Alice is allergic to peanuts ").unwrap();
+        add_memory(&db, "work", "Standups are at 9:30").unwrap();
+        add_memory(&db.for_tenant("bob"), "family", "Bob's cat is called Miso").unwrap();
+        let family = list_memories(&db, "family").unwrap();
+        assert_eq!(family.len(), 1);
+        assert_eq!(family[0].content, "Alice is allergic to peanuts");
+
+        // Vectors are stored per model; another model re-embeds
+        store_embeddings(&db, "model-a", &[(id, vec![0.5, 0.25])]).unwrap();
+        let stored = load_for_recall(&db, "family", "model-a").unwrap();
+        assert_eq!(stored[0].embedding, Some(vec![0.5, 0.25]));
+        assert_eq!(
+            load_for_recall(&db, "family", "model-b").unwrap()[0].embedding,
+            None
+        );
+
+        assert!(delete_memory(&db, id).unwrap());
+        assert!(!delete_memory(&db, id).unwrap());
+    }
+
+    #[test]
+    fn test_parse_embeddings() {
+        let body = serde_json::json!({
+            "data": [
+                { "index": 1, "embedding": [0.0, 1.0] },
+                { "index": 0, "embedding": [1.0, 0.0] }
+            ]
+        });
+        assert_eq!(
+            parse_embeddings(&body, 2).unwrap(),
+            vec![vec![1.0, 0.0], vec![0.0, 1.0]]
+        );
+        assert!(parse_embeddings(&body, 3).is_err());
+        assert!(parse_embeddings(&serde_json::json!({ "error": "bad" }), 1).is_err());
+    }
+
+    #[test]
+    fn test_top_k() {
+        let memories = vec![
+            ("north".to_string(), vec![0.0, 1.0]),
+            ("east".to_string(), vec![1.0, 0.0]),
+            ("north-east".to_string(), vec![1.0, 1.0]),
+        ];
+        assert_eq!(
+            top_k(&[0.1, 1.0], memories.clone(), 2),
+            vec!["north", "north-east"]
+        );
+        assert_eq!(top_k(&[1.0, 0.0], memories, 1), vec!["east"]);
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+    }
+}
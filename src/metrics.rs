@@ -0,0 +1,212 @@
+//! Container Run Metrics for NuClaw
+//!
+//! Every container run started through `run_container_with_retry` is
+//! recorded for its tenant in the `container_runs` table with its queue
+//! wait, duration, exit status, and output size. `container_run_stats`
+//! aggregates them (latency percentiles, failure rate) for the admin API.
+
+use crate::db::Database;
+use crate::error::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Outcome of a container run as stored in `container_runs.status`
+pub mod status {
+    pub const SUCCESS: &str = "success";
+    /// The agent reported an error
+    pub const ERROR: &str = "error";
+    pub const TIMEOUT: &str = "timeout";
+    /// Stopped with `/cancel` or the scheduler
+    pub const CANCELLED: &str = "cancelled";
+    /// The container runtime failed to start or run the container
+    pub const INFRASTRUCTURE_ERROR: &str = "infrastructure_error";
+}
+
+/// Measurements of one container run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerRunRecord {
+    pub group_folder: String,
+    pub chat_jid: String,
+    pub status: &'static str,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    pub queue_wait_ms: u64,
+    pub output_bytes: usize,
+}
+
+/// Store a run's measurements
+pub fn record_container_run(db: &Database, run: &ContainerRunRecord) -> Result<()> {
+    let conn = db.get_connection()?;
+    conn.execute(
+        "INSERT INTO container_runs
+            (tenant, group_folder, chat_jid, started_at, status, exit_code, duration_ms, queue_wait_ms, output_bytes)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        crate::params![
+            db.tenant(),
+            run.group_folder,
+            run.chat_jid,
+            chrono::Utc::now().to_rfc3339(),
+            run.status,
+            run.exit_code,
+            run.duration_ms as i64,
+            run.queue_wait_ms as i64,
+            run.output_bytes as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Aggregates over a set of container runs
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RunStats {
+    pub runs: usize,
+    pub failures: usize,
+    /// Share of runs that did not succeed, from 0 to 1
+    pub failure_rate: f64,
+    pub p50_duration_ms: u64,
+    pub p95_duration_ms: u64,
+    pub avg_queue_wait_ms: u64,
+    pub p95_queue_wait_ms: u64,
+    pub avg_output_bytes: u64,
+}
+
+/// Aggregates for all runs and per group folder
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ContainerRunStats {
+    pub overall: RunStats,
+    pub groups: BTreeMap<String, RunStats>,
+    /// Runs per status
+    pub statuses: BTreeMap<String, usize>,
+}
+
+/// Aggregate the runs started at or after `since` (RFC 3339), or all runs
+pub fn container_run_stats(db: &Database, since: Option<&str>) -> Result<ContainerRunStats> {
+    let conn = db.read_connection()?;
+    let rows = conn.query_map(
+        "SELECT group_folder, status, duration_ms, queue_wait_ms, output_bytes
+         FROM container_runs WHERE tenant = ? AND started_at >= ?",
+        [db.tenant(), since.unwrap_or("")],
+        |row| {
+            Ok(RunRow {
+                group_folder: row.get(0)?,
+                status: row.get(1)?,
+                duration_ms: row.get::<i64>(2)?.max(0) as u64,
+                queue_wait_ms: row.get::<i64>(3)?.max(0) as u64,
+                output_bytes: row.get::<i64>(4)?.max(0) as u64,
+            })
+        },
+    )?;
+
+    let mut stats = ContainerRunStats {
+        overall: aggregate(rows.iter()),
+        ..Default::default()
+    };
+    let mut by_group: BTreeMap<&str, Vec<&RunRow>> = BTreeMap::new();
+    for row in &rows {
+        by_group.entry(&row.group_folder).or_default().push(row);
+        *stats.statuses.entry(row.status.clone()).or_default() += 1;
+    }
+    stats.groups = by_group
+        .into_iter()
+        .map(|(group, rows)| (group.to_string(), aggregate(rows.into_iter())))
+        .collect();
+    Ok(stats)
+}
+
+struct RunRow {
+    group_folder: String,
+    status: String,
+    duration_ms: u64,
+    queue_wait_ms: u64,
+    output_bytes: u64,
+}
+
+fn aggregate<'a>(rows: impl Iterator<Item = &'a RunRow>) -> RunStats {
+    let rows: Vec<&RunRow> = rows.collect();
+    if rows.is_empty() {
+        return RunStats::default();
+    }
+
+    let runs = rows.len();
+    let failures = rows.iter().filter(|r| r.status != status::SUCCESS).count();
+    let mut durations: Vec<u64> = rows.iter().map(|r| r.duration_ms).collect();
+    let mut waits: Vec<u64> = rows.iter().map(|r| r.queue_wait_ms).collect();
+    durations.sort_unstable();
+    waits.sort_unstable();
+
+    RunStats {
+        runs,
+        failures,
+        failure_rate: failures as f64 / runs as f64,
+        p50_duration_ms: percentile(&durations, 50),
+        p95_duration_ms: percentile(&durations, 95),
+        avg_queue_wait_ms: waits.iter().sum::<u64>() / runs as u64,
+        p95_queue_wait_ms: percentile(&waits, 95),
+        avg_output_bytes: rows.iter().map(|r| r.output_bytes).sum::<u64>() / runs as u64,
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[u64], pct: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    sorted[rank.min(sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    fn run(group: &str, status: &'static str, duration_ms: u64) -> ContainerRunRecord {
+        ContainerRunRecord {
+            group_folder: group.to_string(),
+            chat_jid: format!("{}@g.us", group),
+            status,
+            exit_code: Some(0),
+            duration_ms,
+            queue_wait_ms: 100,
+            output_bytes: 2048,
+        }
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&values, 50), 50);
+        assert_eq!(percentile(&values, 95), 95);
+        assert_eq!(percentile(&[7], 95), 7);
+        assert_eq!(percentile(&[], 50), 0);
+    }
+
+    #[test]
+    fn test_container_run_stats() {
+        let (db, _dir) = test_database();
+        for duration in [1000, 2000, 3000] {
+            record_container_run(&db, &run("family", status::SUCCESS, duration)).unwrap();
+        }
+        record_container_run(&db, &run("work", status::TIMEOUT, 300_000)).unwrap();
+
+        let stats = container_run_stats(&db, None).unwrap();
+        assert_eq!(stats.overall.runs, 4);
+        assert_eq!(stats.overall.failures, 1);
+        assert_eq!(stats.overall.failure_rate, 0.25);
+        assert_eq!(stats.overall.p50_duration_ms, 2000);
+        assert_eq!(stats.overall.p95_duration_ms, 300_000);
+        assert_eq!(stats.overall.avg_queue_wait_ms, 100);
+        assert_eq!(stats.groups["family"].p95_duration_ms, 3000);
+        assert_eq!(stats.groups["work"].failure_rate, 1.0);
+        assert_eq!(stats.statuses["timeout"], 1);
+
+        let future = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+        assert_eq!(
+            container_run_stats(&db, Some(&future))
+                .unwrap()
+                .overall
+                .runs,
+            0
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Mount Allowlist for NuClaw
+//!
+//! Groups may ask for extra host directories in their container through
+//! `container_config.additional_mounts`. Every such mount must lie under a
+//! root in `~/.config/nuclaw/mount-allowlist.json` and must not match a
+//! blocked pattern, otherwise the run is rejected. Without an allowlist
+//! file no additional mounts are permitted.
+//!
+//! Mounts are read-only unless the group asks for write access and the
+//! matching root allows it; with `nonMainReadOnly`, only the `main` group
+//! folder can get write access.
+
+use crate::config::mount_allowlist_path;
+use crate::error::{NuClawError, Result};
+use crate::types::AdditionalMount;
+use crate::utils::json::load_json;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+
+/// Directory in the container holding additional mounts
+pub const EXTRA_MOUNT_ROOT: &str = "/workspace/extra";
+/// Group folder exempt from `nonMainReadOnly`
+const MAIN_GROUP_FOLDER: &str = "main";
+/// Path fragments that are never mounted, whatever the allowlist says
+const DEFAULT_BLOCKED_PATTERNS: &[&str] = &[".ssh", ".gnupg", ".aws", ".docker", ".kube"];
+
+/// Contents of the mount allowlist file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MountAllowlist {
+    #[serde(default)]
+    pub allowed_roots: Vec<AllowedRoot>,
+    /// Case-insensitive fragments rejected anywhere in a mount path
+    #[serde(default)]
+    pub blocked_patterns: Vec<String>,
+    /// Only the main group may get read-write mounts
+    #[serde(default)]
+    pub non_main_read_only: bool,
+}
+
+/// A host directory under which mounts are allowed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllowedRoot {
+    pub path: String,
+    #[serde(default)]
+    pub allow_read_write: bool,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// A mount that passed the allowlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidatedMount {
+    pub host_path: PathBuf,
+    pub container_path: String,
+    pub readonly: bool,
+}
+
+impl ValidatedMount {
+    /// `docker run -v` value for this mount
+    pub fn volume_arg(&self) -> String {
+        let mut arg = format!(
+            "{}:{}/{}",
+            self.host_path.display(),
+            EXTRA_MOUNT_ROOT,
+            self.container_path
+        );
+        if self.readonly {
+            arg.push_str(":ro");
+        }
+        arg
+    }
+}
+
+/// Load the allowlist; a missing or unreadable file allows nothing
+pub fn load_mount_allowlist() -> MountAllowlist {
+    load_json(&mount_allowlist_path(), MountAllowlist::default())
+}
+
+/// Check a group's requested mounts against the allowlist file
+pub fn resolve_mounts(
+    mounts: &[AdditionalMount],
+    group_folder: &str,
+) -> Result<Vec<ValidatedMount>> {
+    if mounts.is_empty() {
+        return Ok(vec![]);
+    }
+    validate_mounts(mounts, &load_mount_allowlist(), group_folder)
+}
+
+/// Check requested mounts against an allowlist
+///
+/// Fails with a `Validation` error on the first mount that is not allowed.
+pub fn validate_mounts(
+    mounts: &[AdditionalMount],
+    allowlist: &MountAllowlist,
+    group_folder: &str,
+) -> Result<Vec<ValidatedMount>> {
+    let roots: Vec<(PathBuf, &AllowedRoot)> = allowlist
+        .allowed_roots
+        .iter()
+        .filter_map(|root| {
+            // Roots that do not exist cannot contain anything
+            std::fs::canonicalize(expand_home(&root.path))
+                .ok()
+                .map(|path| (path, root))
+        })
+        .collect();
+
+    let mut validated = vec![];
+    for mount in mounts {
+        let rejected = |reason: &str| NuClawError::Validation {
+            message: format!("Mount '{}' rejected: {}", mount.host_path, reason),
+        };
+
+        let host_path = std::fs::canonicalize(expand_home(&mount.host_path))
+            .map_err(|_| rejected("path does not exist"))?;
+
+        let blocked = DEFAULT_BLOCKED_PATTERNS
+            .iter()
+            .copied()
+            .chain(allowlist.blocked_patterns.iter().map(String::as_str))
+            .find(|pattern| matches_blocked_pattern(&host_path, pattern));
+        if let Some(pattern) = blocked {
+            return Err(rejected(&format!("matches blocked pattern '{}'", pattern)));
+        }
+
+        let (_, root) = roots
+            .iter()
+            .find(|(root_path, _)| host_path.starts_with(root_path))
+            .ok_or_else(|| rejected("not under an allowed root"))?;
+
+        let container_path = match &mount.container_path {
+            Some(path) => path.trim().trim_matches('/').to_string(),
+            None => host_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        if !is_safe_container_path(&container_path) {
+            return Err(rejected(&format!(
+                "invalid container path '{}'",
+                container_path
+            )));
+        }
+
+        let may_write = root.allow_read_write
+            && (!allowlist.non_main_read_only || group_folder == MAIN_GROUP_FOLDER);
+        if !mount.readonly && !may_write {
+            warn!(
+                "Mount '{}' for group {} is not allowed read-write; mounting read-only",
+                mount.host_path, group_folder
+            );
+        }
+
+        validated.push(ValidatedMount {
+            host_path,
+            container_path,
+            readonly: mount.readonly || !may_write,
+        });
+    }
+    Ok(validated)
+}
+
+/// Replace a leading `~` with the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = home::home_dir().unwrap_or_default();
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+fn matches_blocked_pattern(path: &Path, pattern: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    !pattern.is_empty()
+        && path.components().any(|c| {
+            c.as_os_str()
+                .to_string_lossy()
+                .to_lowercase()
+                .contains(&pattern)
+        })
+}
+
+/// Relative, non-empty, and without `..`, so it stays under the extra root
+fn is_safe_container_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.contains(':')
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn mount(host_path: &Path, readonly: bool) -> AdditionalMount {
+        AdditionalMount {
+            host_path: host_path.display().to_string(),
+            container_path: None,
+            readonly,
+        }
+    }
+
+    fn allowlist(root: &Path, allow_read_write: bool) -> MountAllowlist {
+        MountAllowlist {
+            allowed_roots: vec![AllowedRoot {
+                path: root.display().to_string(),
+                allow_read_write,
+                description: None,
+            }],
+            blocked_patterns: vec!["secret".to_string()],
+            non_main_read_only: true,
+        }
+    }
+
+    #[test]
+    fn test_validate_mounts_allows_paths_under_roots() {
+        let dir = TempDir::new().unwrap();
+        let project = dir.path().join("projects/site");
+        std::fs::create_dir_all(&project).unwrap();
+        let list = allowlist(&dir.path().join("projects"), true);
+
+        let validated = validate_mounts(&[mount(&project, false)], &list, "main").unwrap();
+        assert_eq!(validated[0].container_path, "site");
+        assert!(!validated[0].readonly);
+        assert!(validated[0]
+            .volume_arg()
+            .ends_with(":/workspace/extra/site"));
+
+        // Other groups only get read-only access
+        let validated = validate_mounts(&[mount(&project, false)], &list, "family").unwrap();
+        assert!(validated[0].readonly);
+        assert!(validated[0].volume_arg().ends_with(":ro"));
+    }
+
+    #[test]
+    fn test_validate_mounts_rejects_disallowed_paths() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("projects");
+        for sub in ["projects/secret-keys", "projects/.ssh", "other"] {
+            std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        let list = allowlist(&root, true);
+
+        for path in [
+            dir.path().join("other"),
+            dir.path().join("projects/secret-keys"),
+            dir.path().join("projects/.ssh"),
+            dir.path().join("projects/../other"),
+            dir.path().join("missing"),
+        ] {
+            let err = validate_mounts(&[mount(&path, true)], &list, "main").unwrap_err();
+            assert!(matches!(err, NuClawError::Validation { .. }), "{:?}", path);
+        }
+
+        let escaping = AdditionalMount {
+            container_path: Some("../group".to_string()),
+            ..mount(&root, true)
+        };
+        assert!(validate_mounts(&[escaping], &list, "main").is_err());
+
+        // No allowlist means no additional mounts
+        assert!(
+            validate_mounts(&[mount(&root, true)], &MountAllowlist::default(), "main").is_err()
+        );
+    }
+
+    #[test]
+    fn test_mount_allowlist_file_format() {
+        let list: MountAllowlist = serde_json::from_str(
+            r#"{
+                "allowedRoots": [{"path": "~/projects", "allowReadWrite": true}],
+                "blockedPatterns": ["password"],
+                "nonMainReadOnly": true
+            }"#,
+        )
+        .unwrap();
+        assert!(list.allowed_roots[0].allow_read_write);
+        assert_eq!(list.blocked_patterns, vec!["password"]);
+        assert!(list.non_main_read_only);
+        assert!(!expand_home("~/projects").starts_with("~"));
+    }
+}
@@ -0,0 +1,422 @@
+//! Outbound Message Queue for NuClaw
+//!
+//! Replies are written to the `outbox` table and delivered by a sender
+//! worker per tenant and channel, so message handling never waits on the
+//! channel API and queued sends survive restarts. Failed sends are retried
+//! with exponential backoff and parked as `failed` after too many attempts.
+
+use crate::db::{blocking, Database};
+use crate::error::{NuClawError, Result};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::time::Duration;
+use tracing::{debug, error, warn};
+
+/// Default maximum pending messages per channel
+const DEFAULT_OUTBOX_MAX_PENDING: usize = 1000;
+/// Default delivery attempts before a message is parked as failed
+const DEFAULT_OUTBOX_MAX_ATTEMPTS: u32 = 5;
+/// Worker poll interval when not woken by a new message
+const OUTBOX_POLL_INTERVAL_MS: u64 = 1000;
+/// Messages fetched per worker pass
+const OUTBOX_BATCH_SIZE: usize = 20;
+/// Upper bound for the retry backoff
+const OUTBOX_MAX_BACKOFF_SECS: i64 = 300;
+
+/// A queued outbound message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub channel: String,
+    pub chat_jid: String,
+    pub content: String,
+    pub attempts: u32,
+}
+
+/// Queue counts for one channel
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutboxStats {
+    pub pending: usize,
+    pub failed: usize,
+    /// Creation time of the oldest pending message
+    pub oldest_pending: Option<String>,
+}
+
+/// Count pending and failed messages of a channel
+pub fn queue_stats(db: &Database, channel: &str) -> Result<OutboxStats> {
+    let conn = db.get_connection()?;
+    let (pending, failed, oldest_pending): (i64, i64, Option<String>) = conn.query_row(
+        "SELECT
+            COUNT(CASE WHEN status = 'pending' THEN 1 END),
+            COUNT(CASE WHEN status = 'failed' THEN 1 END),
+            MIN(CASE WHEN status = 'pending' THEN created_at END)
+         FROM outbox WHERE tenant = ? AND channel = ?",
+        [db.tenant(), channel],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    Ok(OutboxStats {
+        pending: pending as usize,
+        failed: failed as usize,
+        oldest_pending,
+    })
+}
+
+/// Count the pending messages of every tenant and channel whose next
+/// attempt is due
+pub fn count_due(db: &Database) -> Result<usize> {
+    let conn = db.get_connection()?;
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM outbox WHERE status = 'pending' AND next_attempt_at <= ?",
+        [chrono::Utc::now().to_rfc3339()],
+        |row| row.get(0),
+    )?;
+    Ok(count as usize)
+}
+
+/// Persistent outbound queue for one channel
+#[derive(Clone)]
+pub struct Outbox {
+    db: Database,
+    channel: &'static str,
+    notify: Arc<Notify>,
+    max_pending: usize,
+    max_attempts: u32,
+}
+
+impl Outbox {
+    /// Create the queue for a channel
+    pub fn new(db: Database, channel: &'static str) -> Self {
+        Self {
+            db,
+            channel,
+            notify: Arc::new(Notify::new()),
+            max_pending: std::env::var("OUTBOX_MAX_PENDING")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_OUTBOX_MAX_PENDING),
+            max_attempts: std::env::var("OUTBOX_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_OUTBOX_MAX_ATTEMPTS),
+        }
+    }
+
+    /// Queue a message for delivery
+    ///
+    /// Fails once `OUTBOX_MAX_PENDING` messages are waiting, so a stuck
+    /// channel pushes back on callers instead of growing without bound.
+    pub fn enqueue(&self, chat_jid: &str, content: &str) -> Result<i64> {
+        if self.is_full()? {
+            return Err(NuClawError::Validation {
+                message: format!("Outbound queue for {} is full", self.channel),
+            });
+        }
+
+        let conn = self.db.get_connection()?;
+        let now = chrono::Utc::now().to_rfc3339();
+        let id: i64 = conn
+            .query_row(
+                "INSERT INTO outbox
+                    (tenant, channel, chat_jid, content, created_at, next_attempt_at)
+                 VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+                [
+                    self.db.tenant(),
+                    self.channel,
+                    chat_jid,
+                    content,
+                    &now,
+                    &now,
+                ],
+                |row| row.get(0),
+            )
+            .map_err(|e| NuClawError::Database {
+                message: format!("Failed to queue message: {}", e),
+            })?;
+
+        self.notify.notify_one();
+        Ok(id)
+    }
+
+    /// Number of messages waiting to be sent
+    pub fn pending_count(&self) -> Result<usize> {
+        let conn = self.db.get_connection()?;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM outbox WHERE tenant = ? AND channel = ? AND status = 'pending'",
+            [self.db.tenant(), self.channel],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Whether the queue has reached its pending limit
+    pub fn is_full(&self) -> Result<bool> {
+        Ok(self.pending_count()? >= self.max_pending)
+    }
+
+    /// Pending messages whose next attempt is due, oldest first
+    pub fn next_due(&self, limit: usize) -> Result<Vec<OutboxMessage>> {
+        let conn = self.db.get_connection()?;
+        conn.query_map(
+            "SELECT id, channel, chat_jid, content, attempts FROM outbox
+             WHERE tenant = ? AND channel = ? AND status = 'pending' AND next_attempt_at <= ?
+             ORDER BY id LIMIT ?",
+            crate::params![
+                self.db.tenant(),
+                self.channel,
+                chrono::Utc::now().to_rfc3339(),
+                limit as i64
+            ],
+            |row| {
+                Ok(OutboxMessage {
+                    id: row.get(0)?,
+                    channel: row.get(1)?,
+                    chat_jid: row.get(2)?,
+                    content: row.get(3)?,
+                    attempts: row.get(4)?,
+                })
+            },
+        )
+    }
+
+    /// Remove a delivered message
+    pub fn mark_sent(&self, id: i64) -> Result<()> {
+        let conn = self.db.get_connection()?;
+        conn.execute("DELETE FROM outbox WHERE id = ?", [id])?;
+        Ok(())
+    }
+
+    /// Record a failed attempt and schedule a retry
+    ///
+    /// Returns `false` if the message has used up its attempts and was
+    /// parked as `failed`.
+    pub fn mark_failed(&self, message: &OutboxMessage, error: &str) -> Result<bool> {
+        let attempts = message.attempts + 1;
+        let retry = attempts < self.max_attempts;
+        let next_attempt_at = chrono::Utc::now() + retry_backoff(attempts);
+
+        let conn = self.db.get_connection()?;
+        conn.execute(
+            "UPDATE outbox SET attempts = ?, last_error = ?, next_attempt_at = ?, status = ?
+             WHERE id = ?",
+            crate::params![
+                attempts,
+                error,
+                next_attempt_at.to_rfc3339(),
+                if retry { "pending" } else { "failed" },
+                message.id
+            ],
+        )?;
+        Ok(retry)
+    }
+
+    /// Deliver queued messages forever using `deliver`
+    ///
+    /// Messages are sent one at a time in queue order, so replies to the
+    /// same chat keep their order.
+    pub async fn run<F, Fut>(self, deliver: F)
+    where
+        F: Fn(OutboxMessage) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        loop {
+            let outbox = self.clone();
+            match blocking(move || outbox.next_due(OUTBOX_BATCH_SIZE)).await {
+                Ok(batch) => {
+                    for message in batch {
+                        self.deliver_one(&deliver, message).await;
+                    }
+                }
+                Err(e) => error!("Failed to read {} outbox: {}", self.channel, e),
+            }
+
+            let _ = tokio::time::timeout(
+                Duration::from_millis(OUTBOX_POLL_INTERVAL_MS),
+                self.notify.notified(),
+            )
+            .await;
+        }
+    }
+
+    async fn deliver_one<F, Fut>(&self, deliver: &F, message: OutboxMessage)
+    where
+        F: Fn(OutboxMessage) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let id = message.id;
+        let outbox = self.clone();
+        let error = match deliver(message.clone()).await {
+            Ok(()) => {
+                debug!("Delivered outbox message {} to {}", id, message.chat_jid);
+                if let Err(e) = blocking(move || outbox.mark_sent(id)).await {
+                    error!("Failed to remove delivered outbox message {}: {}", id, e);
+                }
+                return;
+            }
+            Err(e) => e.to_string(),
+        };
+
+        let chat_jid = message.chat_jid.clone();
+        let reason = error.clone();
+        match blocking(move || outbox.mark_failed(&message, &reason)).await {
+            Ok(true) => warn!(
+                "Delivery of outbox message {} failed, will retry: {}",
+                id, error
+            ),
+            Ok(false) => error!(
+                "Giving up on outbox message {} to {}: {}",
+                id, chat_jid, error
+            ),
+            Err(db_err) => error!("Failed to update outbox message {}: {}", id, db_err),
+        }
+    }
+}
+
+/// Backoff before retry number `attempts`: 2, 4, 8, ... seconds, capped
+fn retry_backoff(attempts: u32) -> chrono::Duration {
+    let secs = 2_i64.saturating_pow(attempts).min(OUTBOX_MAX_BACKOFF_SECS);
+    chrono::Duration::seconds(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_enqueue_and_next_due() {
+        let (db, _dir) = test_database();
+        let outbox = Outbox::new(db, "telegram");
+
+        outbox.enqueue("telegram:group:1", "first").unwrap();
+        outbox.enqueue("telegram:group:1", "second").unwrap();
+
+        let due = outbox.next_due(10).unwrap();
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].content, "first");
+        assert_eq!(due[1].content, "second");
+        assert_eq!(outbox.pending_count().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_channels_are_separate() {
+        let (db, _dir) = test_database();
+        let telegram = Outbox::new(db.clone(), "telegram");
+        let whatsapp = Outbox::new(db, "whatsapp");
+
+        telegram.enqueue("telegram:group:1", "hi").unwrap();
+        assert!(whatsapp.next_due(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mark_sent_removes_message() {
+        let (db, _dir) = test_database();
+        let outbox = Outbox::new(db, "telegram");
+
+        let id = outbox.enqueue("telegram:group:1", "hi").unwrap();
+        outbox.mark_sent(id).unwrap();
+        assert_eq!(outbox.pending_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_mark_failed_backs_off_then_parks() {
+        let (db, _dir) = test_database();
+        let mut outbox = Outbox::new(db, "telegram");
+        outbox.max_attempts = 2;
+
+        outbox.enqueue("telegram:group:1", "hi").unwrap();
+        let message = outbox.next_due(10).unwrap().remove(0);
+
+        assert!(outbox.mark_failed(&message, "boom").unwrap());
+        // Not due again until the backoff has passed
+        assert!(outbox.next_due(10).unwrap().is_empty());
+        assert_eq!(outbox.pending_count().unwrap(), 1);
+
+        let message = OutboxMessage {
+            attempts: 1,
+            ..message
+        };
+        assert!(!outbox.mark_failed(&message, "boom").unwrap());
+        assert_eq!(outbox.pending_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_enqueue_rejects_when_full() {
+        let (db, _dir) = test_database();
+        let mut outbox = Outbox::new(db, "telegram");
+        outbox.max_pending = 1;
+
+        outbox.enqueue("telegram:group:1", "one").unwrap();
+        assert!(outbox.is_full().unwrap());
+        assert!(outbox.enqueue("telegram:group:1", "two").is_err());
+    }
+
+    #[test]
+    fn test_queue_stats() {
+        let (db, _dir) = test_database();
+        let mut outbox = Outbox::new(db.clone(), "telegram");
+        outbox.max_attempts = 1;
+
+        assert_eq!(
+            queue_stats(&db, "telegram").unwrap(),
+            OutboxStats::default()
+        );
+
+        outbox.enqueue("telegram:group:1", "one").unwrap();
+        outbox.enqueue("telegram:group:1", "two").unwrap();
+        let message = outbox.next_due(1).unwrap().remove(0);
+        outbox.mark_failed(&message, "boom").unwrap();
+
+        let stats = queue_stats(&db, "telegram").unwrap();
+        assert_eq!(stats.pending, 1);
+        assert_eq!(stats.failed, 1);
+        assert!(stats.oldest_pending.is_some());
+        assert_eq!(queue_stats(&db, "whatsapp").unwrap().pending, 0);
+    }
+
+    #[test]
+    fn test_count_due() {
+        let (db, _dir) = test_database();
+        let telegram = Outbox::new(db.clone(), "telegram");
+        let whatsapp = Outbox::new(db.for_tenant("acme"), "whatsapp");
+        assert_eq!(count_due(&db).unwrap(), 0);
+
+        telegram.enqueue("telegram:group:1", "one").unwrap();
+        whatsapp.enqueue("123@g.us", "two").unwrap();
+        assert_eq!(count_due(&db).unwrap(), 2);
+
+        // A message backing off is not due
+        let message = telegram.next_due(1).unwrap().remove(0);
+        telegram.mark_failed(&message, "boom").unwrap();
+        assert_eq!(count_due(&db).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(1), chrono::Duration::seconds(2));
+        assert_eq!(retry_backoff(3), chrono::Duration::seconds(8));
+        assert_eq!(retry_backoff(20), chrono::Duration::seconds(300));
+    }
+
+    #[tokio::test]
+    async fn test_run_delivers_queued_messages() {
+        let (db, _dir) = test_database();
+        let outbox = Outbox::new(db, "telegram");
+        outbox.enqueue("telegram:group:1", "hi").unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let worker = tokio::spawn(outbox.clone().run(move |m| {
+            let tx = tx.clone();
+            async move {
+                tx.send(m.content).unwrap();
+                Ok(())
+            }
+        }));
+
+        let delivered = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap();
+        assert_eq!(delivered.as_deref(), Some("hi"));
+        worker.abort();
+    }
+}
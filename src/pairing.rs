@@ -0,0 +1,363 @@
+//! DM Pairing for NuClaw
+//!
+//! Implements the `pairing` DM policy. An admin issues a one-time code
+//! (via `nuclaw pair` or the `/pair` chat command); a user who sends
+//! that code to the bot in a private chat is recorded in `paired_users`
+//! and may talk to the assistant from then on. Other DMs are answered
+//! with `UNPAIRED_NOTICE`, at most once per `UNPAIRED_NOTICE_INTERVAL`
+//! per user, and otherwise ignored. `nuclaw pair list` and
+//! `nuclaw pair revoke` show and remove pairings.
+//!
+//! Issued codes, pairings, rejected codes, and DMs refused for want of a
+//! pairing are recorded in the audit log.
+
+use crate::audit::{self, chat_actor, AuditEvent, AuditKind};
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Default pairing code lifetime: 1 hour
+const DEFAULT_PAIRING_CODE_TTL_SECS: i64 = 3600;
+/// Pairing code length
+const PAIRING_CODE_LEN: usize = 8;
+/// Pairing code alphabet (no 0/O or 1/I to avoid misreads)
+const PAIRING_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+/// Time between notices to the same unpaired user
+const UNPAIRED_NOTICE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Reply to a DM from a user who is not paired
+pub const UNPAIRED_NOTICE: &str = "Sorry, I only talk to people I've been paired with. \
+    Ask my owner for a pairing code and send it to me here.";
+
+/// Result of checking a DM sender against the pairing policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairingStatus {
+    /// Sender was paired before this message
+    Paired,
+    /// This message carried a valid code and the sender is now paired
+    JustPaired,
+    /// This message looked like a code but it was unknown or expired
+    InvalidCode,
+    /// Sender is not paired and did not send a code
+    Unpaired,
+}
+
+/// Get pairing code lifetime from environment or default
+pub fn pairing_code_ttl() -> chrono::Duration {
+    let secs = std::env::var("PAIRING_CODE_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_PAIRING_CODE_TTL_SECS);
+    chrono::Duration::seconds(secs)
+}
+
+/// Generate and store a new one-time pairing code, issued by `issued_by`
+/// (an audit log actor)
+pub fn create_pairing_code(db: &Database, issued_by: &str) -> Result<String> {
+    let conn = db.get_connection()?;
+    let now = chrono::Utc::now();
+
+    conn.execute(
+        "DELETE FROM pairing_codes WHERE expires_at <= ?",
+        [now.to_rfc3339()],
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to purge pairing codes: {}", e),
+    })?;
+
+    let code = generate_code();
+    conn.execute(
+        "INSERT INTO pairing_codes (tenant, code, created_at, expires_at) VALUES (?, ?, ?, ?)",
+        crate::params![
+            db.tenant(),
+            code,
+            now.to_rfc3339(),
+            (now + pairing_code_ttl()).to_rfc3339()
+        ],
+    )
+    .map_err(|e| NuClawError::Database {
+        message: format!("Failed to store pairing code: {}", e),
+    })?;
+    // The audit log needs the writer connection
+    drop(conn);
+    audit::record(
+        db,
+        AuditEvent::new(AuditKind::Pairing, issued_by, "Issued a pairing code"),
+    );
+
+    Ok(code)
+}
+
+/// Consume a pairing code and pair the user with it
+///
+/// Returns `false` if the code is unknown or expired.
+pub fn redeem_pairing_code(
+    db: &Database,
+    channel: &str,
+    user_id: &str,
+    code: &str,
+) -> Result<bool> {
+    let mut conn = db.get_connection()?;
+    let now = chrono::Utc::now().to_rfc3339();
+
+    let tx = conn.transaction()?;
+    let consumed = tx.execute(
+        "DELETE FROM pairing_codes WHERE tenant = ? AND code = ? AND expires_at > ?",
+        [db.tenant(), code, now.as_str()],
+    )?;
+    if consumed == 0 {
+        return Ok(false);
+    }
+    tx.execute(
+        "INSERT INTO paired_users (tenant, channel, user_id, paired_at) VALUES (?, ?, ?, ?)
+         ON CONFLICT (tenant, channel, user_id) DO UPDATE SET paired_at = excluded.paired_at",
+        [db.tenant(), channel, user_id, now.as_str()],
+    )?;
+    tx.commit()?;
+
+    Ok(true)
+}
+
+/// Check whether a user is paired on a channel
+pub fn is_paired(db: &Database, channel: &str, user_id: &str) -> Result<bool> {
+    let conn = db.read_connection()?;
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM paired_users WHERE tenant = ? AND channel = ? AND user_id = ?",
+        [db.tenant(), channel, user_id],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Remove a pairing
+pub fn unpair(db: &Database, channel: &str, user_id: &str) -> Result<bool> {
+    let conn = db.get_connection()?;
+    let removed = conn.execute(
+        "DELETE FROM paired_users WHERE tenant = ? AND channel = ? AND user_id = ?",
+        [db.tenant(), channel, user_id],
+    )?;
+    Ok(removed > 0)
+}
+
+/// A paired user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PairedUser {
+    pub channel: String,
+    pub user_id: String,
+    pub paired_at: String,
+}
+
+/// List the paired users, most recently paired first
+pub fn list_paired_users(db: &Database) -> Result<Vec<PairedUser>> {
+    db.read_connection()?.query_map(
+        "SELECT channel, user_id, paired_at FROM paired_users
+         WHERE tenant = ? ORDER BY paired_at DESC, channel, user_id",
+        crate::params![db.tenant()],
+        |row| {
+            Ok(PairedUser {
+                channel: row.get(0)?,
+                user_id: row.get(1)?,
+                paired_at: row.get(2)?,
+            })
+        },
+    )
+}
+
+/// Whether an unpaired user should be sent `UNPAIRED_NOTICE` now; `false`
+/// if they were sent it in the last `UNPAIRED_NOTICE_INTERVAL`, so a
+/// chatty sender is not answered every time
+pub fn unpaired_notice_due(channel: &str, user_id: &str) -> bool {
+    static NOTIFIED: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+    let mut notified = NOTIFIED.get_or_init(Default::default).lock().unwrap();
+    let now = Instant::now();
+    notified.retain(|_, at| now.duration_since(*at) < UNPAIRED_NOTICE_INTERVAL);
+    let key = chat_actor(channel, user_id);
+    if notified.contains_key(&key) {
+        return false;
+    }
+    notified.insert(key, now);
+    true
+}
+
+/// Check a DM sender against the pairing policy, redeeming a code if sent
+pub fn check_pairing(
+    db: &Database,
+    channel: &str,
+    user_id: &str,
+    content: &str,
+) -> Result<PairingStatus> {
+    if is_paired(db, channel, user_id)? {
+        return Ok(PairingStatus::Paired);
+    }
+
+    let (status, detail) = match normalize_pairing_code(content) {
+        Some(code) => {
+            if redeem_pairing_code(db, channel, user_id, &code)? {
+                (PairingStatus::JustPaired, "Paired with a code")
+            } else {
+                (
+                    PairingStatus::InvalidCode,
+                    "Sent an invalid or expired code",
+                )
+            }
+        }
+        None => (PairingStatus::Unpaired, "DM refused: sender is not paired"),
+    };
+    let kind = match status {
+        PairingStatus::Unpaired => AuditKind::Denied,
+        _ => AuditKind::Pairing,
+    };
+    audit::record(
+        db,
+        AuditEvent::new(kind, chat_actor(channel, user_id), detail),
+    );
+    Ok(status)
+}
+
+/// Normalize user input into a pairing code, if it looks like one
+pub fn normalize_pairing_code(content: &str) -> Option<String> {
+    let code = content.trim().to_uppercase();
+    if code.len() == PAIRING_CODE_LEN && code.bytes().all(|b| PAIRING_CODE_ALPHABET.contains(&b)) {
+        Some(code)
+    } else {
+        None
+    }
+}
+
+/// Generate a random pairing code
+fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..PAIRING_CODE_LEN)
+        .map(|_| PAIRING_CODE_ALPHABET[rng.gen_range(0..PAIRING_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_generate_code_format() {
+        let code = generate_code();
+        assert_eq!(code.len(), PAIRING_CODE_LEN);
+        assert_eq!(normalize_pairing_code(&code), Some(code));
+    }
+
+    #[test]
+    fn test_normalize_pairing_code() {
+        assert_eq!(
+            normalize_pairing_code("  abcd2345 "),
+            Some("ABCD2345".to_string())
+        );
+        assert_eq!(normalize_pairing_code("hello"), None);
+        assert_eq!(normalize_pairing_code("ABCD0123"), None);
+        assert_eq!(normalize_pairing_code("@Andy hello there"), None);
+    }
+
+    #[test]
+    fn test_redeem_pairing_code_once() {
+        let (db, _dir) = test_database();
+        let code = create_pairing_code(&db, "cli").unwrap();
+
+        assert!(!is_paired(&db, "telegram", "42").unwrap());
+        assert!(redeem_pairing_code(&db, "telegram", "42", &code).unwrap());
+        assert!(is_paired(&db, "telegram", "42").unwrap());
+
+        // Codes are single-use
+        assert!(!redeem_pairing_code(&db, "telegram", "43", &code).unwrap());
+        assert!(!is_paired(&db, "telegram", "43").unwrap());
+    }
+
+    #[test]
+    fn test_expired_code_is_rejected() {
+        let (db, _dir) = test_database();
+        let past = (chrono::Utc::now() - chrono::Duration::minutes(5)).to_rfc3339();
+        db.get_connection()
+            .unwrap()
+            .execute(
+                "INSERT INTO pairing_codes (code, created_at, expires_at) VALUES ('ABCDEFGH', ?, ?)",
+                [&past, &past],
+            )
+            .unwrap();
+
+        assert!(!redeem_pairing_code(&db, "telegram", "42", "ABCDEFGH").unwrap());
+    }
+
+    #[test]
+    fn test_check_pairing_flow() {
+        let (db, _dir) = test_database();
+
+        assert_eq!(
+            check_pairing(&db, "whatsapp", "user@s.whatsapp.net", "hi").unwrap(),
+            PairingStatus::Unpaired
+        );
+        assert_eq!(
+            check_pairing(&db, "whatsapp", "user@s.whatsapp.net", "ZZZZZZZZ").unwrap(),
+            PairingStatus::InvalidCode
+        );
+
+        let code = create_pairing_code(&db, "cli").unwrap();
+        assert_eq!(
+            check_pairing(&db, "whatsapp", "user@s.whatsapp.net", &code.to_lowercase()).unwrap(),
+            PairingStatus::JustPaired
+        );
+        assert_eq!(
+            check_pairing(&db, "whatsapp", "user@s.whatsapp.net", "hi").unwrap(),
+            PairingStatus::Paired
+        );
+
+        // Pairings are per channel
+        assert!(!is_paired(&db, "telegram", "user@s.whatsapp.net").unwrap());
+
+        let kinds: Vec<String> = audit::query(&db, &Default::default(), 10)
+            .unwrap()
+            .into_iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(kinds, ["pairing", "pairing", "pairing", "denied"]);
+    }
+
+    #[test]
+    fn test_unpair() {
+        let (db, _dir) = test_database();
+        let code = create_pairing_code(&db, "cli").unwrap();
+        redeem_pairing_code(&db, "telegram", "42", &code).unwrap();
+
+        assert!(unpair(&db, "telegram", "42").unwrap());
+        assert!(!is_paired(&db, "telegram", "42").unwrap());
+        assert!(!unpair(&db, "telegram", "42").unwrap());
+    }
+
+    #[test]
+    fn test_list_paired_users() {
+        let (db, _dir) = test_database();
+        assert!(list_paired_users(&db).unwrap().is_empty());
+
+        for (channel, user_id) in [("telegram", "42"), ("whatsapp", "user@s.whatsapp.net")] {
+            let code = create_pairing_code(&db, "cli").unwrap();
+            redeem_pairing_code(&db, channel, user_id, &code).unwrap();
+        }
+        let paired = list_paired_users(&db).unwrap();
+        assert_eq!(paired.len(), 2);
+        assert!(paired
+            .iter()
+            .any(|user| user.channel == "telegram" && user.user_id == "42"));
+
+        // Pairings are per tenant
+        assert!(list_paired_users(&db.for_tenant("acme"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_unpaired_notice_due() {
+        assert!(unpaired_notice_due("telegram", "notice-test"));
+        assert!(!unpaired_notice_due("telegram", "notice-test"));
+        assert!(unpaired_notice_due("whatsapp", "notice-test"));
+    }
+}
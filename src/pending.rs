@@ -0,0 +1,289 @@
+//! Inbound Message Queue for NuClaw
+//!
+//! Triggered messages are written to the `pending_messages` table before
+//! the agent runs and removed once it has answered. Whatever is left at
+//! startup was interrupted by a crash or restart and is replayed, so a
+//! user's request is never silently lost. Entries that keep interrupting
+//! the process are dropped after `MAX_REPLAY_ATTEMPTS` tries.
+//!
+//! Messages held by the content guard (see `prompt_guard`) wait in the
+//! same table, marked `held`, until an admin approves or rejects them.
+
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use crate::types::NewMessage;
+use tracing::error;
+
+/// Runs started for a message before it is given up on
+const MAX_REPLAY_ATTEMPTS: u32 = 3;
+
+/// A triggered message waiting for its agent run to finish
+#[derive(Debug, Clone)]
+pub struct PendingMessage {
+    pub id: i64,
+    pub message: NewMessage,
+    /// Prompt for the agent, with the trigger removed
+    pub prompt: String,
+    pub attempts: u32,
+}
+
+/// Persistent queue of accepted messages for one tenant and channel
+#[derive(Clone)]
+pub struct PendingQueue {
+    db: Database,
+    channel: &'static str,
+}
+
+impl PendingQueue {
+    /// Create the queue for a channel
+    pub fn new(db: Database, channel: &'static str) -> Self {
+        Self { db, channel }
+    }
+
+    /// Channel of the queue
+    pub fn channel(&self) -> &'static str {
+        self.channel
+    }
+
+    /// Record a message whose agent run is about to start
+    pub fn enqueue(&self, message: &NewMessage, prompt: &str) -> Result<i64> {
+        self.insert(message, prompt, false)
+    }
+
+    /// Record a message held for an admin's approval
+    pub fn hold(&self, message: &NewMessage, prompt: &str) -> Result<i64> {
+        self.insert(message, prompt, true)
+    }
+
+    fn insert(&self, message: &NewMessage, prompt: &str, held: bool) -> Result<i64> {
+        let payload = serde_json::to_string(message).map_err(|e| NuClawError::Database {
+            message: format!("Failed to serialize pending message: {}", e),
+        })?;
+
+        let conn = self.db.get_connection()?;
+        conn.query_row(
+            "INSERT INTO pending_messages
+                (tenant, channel, chat_jid, message, prompt, attempts, held, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            crate::params![
+                self.db.tenant(),
+                self.channel,
+                message.chat_jid,
+                payload,
+                prompt,
+                if held { 0 } else { 1 },
+                held as i64,
+                chrono::Utc::now().to_rfc3339()
+            ],
+            |row| row.get(0),
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to queue pending message: {}", e),
+        })
+    }
+
+    /// Claim an approved message for its agent run; `None` if it is not
+    /// approved or its run has started already
+    pub fn claim_approved(&self, id: i64) -> Result<Option<PendingMessage>> {
+        let conn = self.db.get_connection()?;
+        let claimed = conn.execute(
+            "UPDATE pending_messages SET attempts = 1
+             WHERE id = ? AND tenant = ? AND channel = ? AND held = 0 AND attempts = 0",
+            crate::params![id, self.db.tenant(), self.channel],
+        )?;
+        if claimed == 0 {
+            return Ok(None);
+        }
+        let (payload, prompt): (String, String) = conn.query_row(
+            "SELECT message, prompt FROM pending_messages WHERE id = ?",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let message = serde_json::from_str(&payload).map_err(|e| NuClawError::Database {
+            message: format!("Failed to read pending message {}: {}", id, e),
+        })?;
+        Ok(Some(PendingMessage {
+            id,
+            message,
+            prompt,
+            attempts: 1,
+        }))
+    }
+
+    /// Remove a message once its run has finished
+    pub fn complete(&self, id: i64) -> Result<()> {
+        let conn = self.db.get_connection()?;
+        conn.execute("DELETE FROM pending_messages WHERE id = ?", [id])?;
+        Ok(())
+    }
+
+    /// Claim every interrupted message for replay, oldest first
+    ///
+    /// Each claimed message counts as a new attempt; messages that have
+    /// used up their attempts are dropped instead.
+    pub fn claim_interrupted(&self) -> Result<Vec<PendingMessage>> {
+        let conn = self.db.get_connection()?;
+
+        let abandoned = conn.query_map(
+            "SELECT id, chat_jid FROM pending_messages
+             WHERE tenant = ? AND channel = ? AND held = 0 AND attempts >= ?",
+            crate::params![self.db.tenant(), self.channel, MAX_REPLAY_ATTEMPTS],
+            |row| Ok((row.get::<i64>(0)?, row.get::<String>(1)?)),
+        )?;
+        for (id, chat_jid) in abandoned {
+            error!(
+                "Giving up on pending message {} from {} after {} attempts",
+                id, chat_jid, MAX_REPLAY_ATTEMPTS
+            );
+            conn.execute("DELETE FROM pending_messages WHERE id = ?", [id])?;
+        }
+
+        conn.execute(
+            "UPDATE pending_messages SET attempts = attempts + 1
+             WHERE tenant = ? AND channel = ? AND held = 0",
+            [self.db.tenant(), self.channel],
+        )?;
+
+        let rows = conn.query_map(
+            "SELECT id, message, prompt, attempts FROM pending_messages
+             WHERE tenant = ? AND channel = ? AND held = 0 ORDER BY id",
+            [self.db.tenant(), self.channel],
+            |row| {
+                Ok((
+                    row.get::<i64>(0)?,
+                    row.get::<String>(1)?,
+                    row.get::<String>(2)?,
+                    row.get::<u32>(3)?,
+                ))
+            },
+        )?;
+
+        let mut messages = vec![];
+        for (id, payload, prompt, attempts) in rows {
+            match serde_json::from_str(&payload) {
+                Ok(message) => messages.push(PendingMessage {
+                    id,
+                    message,
+                    prompt,
+                    attempts,
+                }),
+                Err(e) => {
+                    error!("Dropping unreadable pending message {}: {}", id, e);
+                    conn.execute("DELETE FROM pending_messages WHERE id = ?", [id])?;
+                }
+            }
+        }
+        Ok(messages)
+    }
+}
+
+/// Approve a message held in a chat, so its channel can claim it; `false`
+/// if the chat has no such held message
+pub fn approve_held(db: &Database, chat_jid: &str, id: i64) -> Result<bool> {
+    let approved = db.get_connection()?.execute(
+        "UPDATE pending_messages SET held = 0, attempts = 0
+         WHERE id = ? AND tenant = ? AND chat_jid = ? AND held = 1",
+        crate::params![id, db.tenant(), chat_jid],
+    )?;
+    Ok(approved > 0)
+}
+
+/// Drop a message held in a chat; `false` if the chat has no such held
+/// message
+pub fn reject_held(db: &Database, chat_jid: &str, id: i64) -> Result<bool> {
+    let rejected = db.get_connection()?.execute(
+        "DELETE FROM pending_messages
+         WHERE id = ? AND tenant = ? AND chat_jid = ? AND held = 1",
+        crate::params![id, db.tenant(), chat_jid],
+    )?;
+    Ok(rejected > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    fn message(id: &str) -> NewMessage {
+        NewMessage {
+            id: id.to_string(),
+            chat_jid: "telegram:group:-1".to_string(),
+            sender: "42".to_string(),
+            sender_name: "Alice".to_string(),
+            content: format!("@Andy request {}", id),
+            timestamp: "1700000000".to_string(),
+            reply_to_id: None,
+            quoted_content: None,
+            chat_name: None,
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn test_completed_messages_are_not_replayed() {
+        let (db, _dir) = test_database();
+        let queue = PendingQueue::new(db, "telegram");
+
+        let done = queue.enqueue(&message("1"), "request 1").unwrap();
+        queue.enqueue(&message("2"), "request 2").unwrap();
+        queue.complete(done).unwrap();
+
+        let interrupted = queue.claim_interrupted().unwrap();
+        assert_eq!(interrupted.len(), 1);
+        assert_eq!(interrupted[0].message.id, "2");
+        assert_eq!(interrupted[0].prompt, "request 2");
+        assert_eq!(interrupted[0].attempts, 2);
+    }
+
+    #[test]
+    fn test_channels_are_separate() {
+        let (db, _dir) = test_database();
+        PendingQueue::new(db.clone(), "telegram")
+            .enqueue(&message("1"), "request 1")
+            .unwrap();
+
+        assert!(PendingQueue::new(db, "whatsapp")
+            .claim_interrupted()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_held_messages() {
+        let (db, _dir) = test_database();
+        let queue = PendingQueue::new(db.clone(), "telegram");
+        let held = queue.hold(&message("1"), "request 1").unwrap();
+        let rejected = queue.hold(&message("2"), "request 2").unwrap();
+
+        // Held messages are not replayed, nor claimed before approval
+        assert!(queue.claim_interrupted().unwrap().is_empty());
+        assert!(queue.claim_approved(held).unwrap().is_none());
+
+        assert!(!approve_held(&db, "telegram:group:-2", held).unwrap());
+        assert!(approve_held(&db, "telegram:group:-1", held).unwrap());
+        assert!(!approve_held(&db, "telegram:group:-1", held).unwrap());
+        let claimed = queue.claim_approved(held).unwrap().unwrap();
+        assert_eq!(claimed.message.id, "1");
+        assert_eq!(claimed.prompt, "request 1");
+        assert!(queue.claim_approved(held).unwrap().is_none());
+
+        assert!(reject_held(&db, "telegram:group:-1", rejected).unwrap());
+        assert!(!reject_held(&db, "telegram:group:-1", held).unwrap());
+        // The approved message is replayed if its run is interrupted
+        let interrupted = queue.claim_interrupted().unwrap();
+        assert_eq!(interrupted.len(), 1);
+        assert_eq!(interrupted[0].id, held);
+    }
+
+    #[test]
+    fn test_repeatedly_interrupted_messages_are_dropped() {
+        let (db, _dir) = test_database();
+        let queue = PendingQueue::new(db, "telegram");
+        queue.enqueue(&message("1"), "request 1").unwrap();
+
+        for _ in 1..MAX_REPLAY_ATTEMPTS {
+            assert_eq!(queue.claim_interrupted().unwrap().len(), 1);
+        }
+        assert!(queue.claim_interrupted().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! Local Process Runner for NuClaw
+//!
+//! On development machines and in CI, starting a container per message is
+//! overkill. With `CONTAINER_RUNNER=process` (or `"runner": "process"` in a
+//! group's `container_config`) the agent CLI runs directly as a subprocess.
+//!
+//! There is no chroot, so this is a jail by convention only:
+//! - the working directory and `HOME` are the group folder, which must
+//!   resolve to a directory inside the groups directory
+//! - the environment is cleared; only `PATH`, locale settings, the
+//!   forwarded variables, the group's `env`, and `NUCLAW_GROUP_DIR` /
+//!   `NUCLAW_IPC_DIR` are passed
+//! - additional mounts are not supported
+//! - of the group's agent permissions, only `allowed_tools` is applied,
+//!   as `--allowedTools` for the default `claude` command; a group whose
+//!   agent may not use the network or write everywhere is refused
+//!
+//! Do not use it for groups whose members you do not trust.
+
+use crate::config::groups_dir;
+use crate::container_runner::{create_group_ipc_directory, forwarded_env, group_env};
+use crate::error::{NuClawError, Result};
+use crate::types::{ContainerConfig, ContainerInput};
+use std::path::{Path, PathBuf};
+use tokio::process::Command as AsyncCommand;
+
+/// Agent command used when `PROCESS_AGENT_COMMAND` is unset
+const DEFAULT_PROCESS_COMMAND: &str = "claude";
+/// Host variables every agent process keeps
+const PASSTHROUGH_ENV: &[&str] = &["PATH", "LANG", "LC_ALL", "TZ"];
+
+fn process_command() -> String {
+    std::env::var("PROCESS_AGENT_COMMAND")
+        .ok()
+        .filter(|c| !c.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PROCESS_COMMAND.to_string())
+}
+
+/// Resolve a group directory, refusing anything outside `root`
+///
+/// Both paths are canonicalized, so `..` components and symlinks cannot
+/// lead out of the groups directory.
+pub fn jailed_group_dir(root: &Path, group_dir: &Path) -> Result<PathBuf> {
+    let escape = || NuClawError::Validation {
+        message: format!(
+            "Group directory {} is outside {}",
+            group_dir.display(),
+            root.display()
+        ),
+    };
+    let root = std::fs::canonicalize(root).map_err(|_| escape())?;
+    let dir = std::fs::canonicalize(group_dir).map_err(|_| escape())?;
+    if dir == root || !dir.starts_with(&root) || !dir.is_dir() {
+        return Err(escape());
+    }
+    Ok(dir)
+}
+
+/// Refuse agent permissions a process outside a container cannot enforce
+fn check_permissions(config: &ContainerConfig) -> Result<()> {
+    let Some(permissions) = &config.permissions else {
+        return Ok(());
+    };
+    let unsupported = if permissions.network_allowed == Some(false) {
+        "Denying network access"
+    } else if permissions.writable_paths.is_some() {
+        "Limiting writable paths"
+    } else {
+        return Ok(());
+    };
+    Err(NuClawError::Validation {
+        message: format!("{} is not supported by the process runner", unsupported),
+    })
+}
+
+/// Arguments of the agent command: the allowed tools, for the default
+/// command only
+fn process_args(command: &str, config: &ContainerConfig) -> Vec<String> {
+    let allowed_tools = config
+        .permissions
+        .as_ref()
+        .and_then(|p| p.allowed_tools.as_ref());
+    match allowed_tools {
+        Some(tools) if command == DEFAULT_PROCESS_COMMAND => {
+            vec!["--allowedTools".to_string(), tools.join(",")]
+        }
+        _ => vec![],
+    }
+}
+
+/// Environment of an agent process: a scrubbed copy of the host's
+pub fn process_env(
+    config: &ContainerConfig,
+    group_dir: &Path,
+    ipc_dir: &Path,
+) -> Result<Vec<(String, String)>> {
+    let mut env: Vec<(String, String)> = PASSTHROUGH_ENV
+        .iter()
+        .map(|name| name.to_string())
+        .chain(forwarded_env(config)?)
+        .filter_map(|name| std::env::var(&name).ok().map(|v| (name, v)))
+        .collect();
+    env.push(("HOME".to_string(), group_dir.display().to_string()));
+    env.push((
+        "NUCLAW_GROUP_DIR".to_string(),
+        group_dir.display().to_string(),
+    ));
+    env.push(("NUCLAW_IPC_DIR".to_string(), ipc_dir.display().to_string()));
+    for var in group_env(config)? {
+        if let Some((key, value)) = var.split_once('=') {
+            env.push((key.to_string(), value.to_string()));
+        }
+    }
+    Ok(env)
+}
+
+/// Build the agent subprocess for a run; the input goes to its stdin
+pub fn build_process_command(
+    input: &ContainerInput,
+    group_dir: &Path,
+    config: &ContainerConfig,
+) -> Result<AsyncCommand> {
+    if !config.additional_mounts.is_empty() {
+        return Err(NuClawError::Validation {
+            message: "Additional mounts are not supported by the process runner".to_string(),
+        });
+    }
+    check_permissions(config)?;
+    let group_dir = jailed_group_dir(&groups_dir(), group_dir)?;
+    let ipc_dir = create_group_ipc_directory(&input.group_folder)?;
+
+    let command = process_command();
+    let mut cmd = AsyncCommand::new(&command);
+    cmd.args(process_args(&command, config))
+        .current_dir(&group_dir)
+        .env_clear()
+        .envs(process_env(config, &group_dir, &ipc_dir)?)
+        .kill_on_drop(true)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AgentPermissions;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_jailed_group_dir() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("groups");
+        std::fs::create_dir_all(root.join("family")).unwrap();
+        std::fs::create_dir_all(dir.path().join("outside")).unwrap();
+
+        assert!(jailed_group_dir(&root, &root.join("family")).is_ok());
+        assert!(jailed_group_dir(&root, &root.join("../outside")).is_err());
+        assert!(jailed_group_dir(&root, &root).is_err());
+        assert!(jailed_group_dir(&root, &root.join("missing")).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("outside"), root.join("link")).unwrap();
+            assert!(jailed_group_dir(&root, &root.join("link")).is_err());
+        }
+    }
+
+    #[test]
+    fn test_process_env_is_scrubbed() {
+        let config = ContainerConfig {
+            env: BTreeMap::from([("LANG".to_string(), "C".to_string())]),
+            env_allowlist: Some(vec![]),
+            ..Default::default()
+        };
+        let env = process_env(
+            &config,
+            Path::new("/data/groups/family"),
+            Path::new("/data/ipc/family"),
+        )
+        .unwrap();
+        let names: Vec<&str> = env.iter().map(|(k, _)| k.as_str()).collect();
+
+        assert!(names.iter().all(|n| PASSTHROUGH_ENV.contains(n)
+            || ["HOME", "NUCLAW_GROUP_DIR", "NUCLAW_IPC_DIR"].contains(n)));
+        assert!(env.contains(&("HOME".to_string(), "/data/groups/family".to_string())));
+        // Group variables come last and win
+        assert_eq!(env.last().unwrap(), &("LANG".to_string(), "C".to_string()));
+    }
+
+    #[test]
+    fn test_process_permissions() {
+        let config = |permissions| ContainerConfig {
+            permissions: Some(permissions),
+            ..Default::default()
+        };
+        let offline = config(AgentPermissions {
+            network_allowed: Some(false),
+            ..Default::default()
+        });
+        let read_only = config(AgentPermissions {
+            writable_paths: Some(vec!["/workspace/group/notes".to_string()]),
+            ..Default::default()
+        });
+        for refused in [&offline, &read_only] {
+            assert!(matches!(
+                check_permissions(refused),
+                Err(NuClawError::Validation { .. })
+            ));
+        }
+
+        let limited = config(AgentPermissions {
+            allowed_tools: Some(vec!["Read".to_string(), "Grep".to_string()]),
+            ..Default::default()
+        });
+        assert!(check_permissions(&limited).is_ok());
+        assert_eq!(
+            process_args(DEFAULT_PROCESS_COMMAND, &limited),
+            ["--allowedTools", "Read,Grep"]
+        );
+        assert!(process_args("/opt/agent/run", &limited).is_empty());
+    }
+}
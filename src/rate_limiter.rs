@@ -0,0 +1,156 @@
+//! Outbound Rate Limiter for NuClaw
+//!
+//! Spaces out outbound API calls with a global and a per-chat interval.
+//! Callers reserve a send slot before each request, so concurrent senders
+//! queue up in order instead of racing into the channel's rate limits.
+//! When the remote API answers with a 429, `penalize` pushes the next
+//! slot back by the `retry_after` the server asked for.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// Rate limiter with a global and a per-chat send interval
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Minimum spacing between any two sends
+    global_interval: Duration,
+    /// Minimum spacing between two sends to the same chat
+    chat_interval: Duration,
+    /// Next free slots
+    state: Mutex<RateLimiterState>,
+}
+
+#[derive(Debug)]
+struct RateLimiterState {
+    next_global: Instant,
+    next_per_chat: HashMap<String, Instant>,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing `global_per_sec` sends overall and
+    /// `chat_per_sec` sends per chat
+    pub fn new(global_per_sec: f64, chat_per_sec: f64) -> Self {
+        Self {
+            global_interval: interval_for_rate(global_per_sec),
+            chat_interval: interval_for_rate(chat_per_sec),
+            state: Mutex::new(RateLimiterState {
+                next_global: Instant::now(),
+                next_per_chat: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Reserve the next send slot for a chat and return how long to wait for it
+    pub fn reserve(&self, chat_id: &str) -> Duration {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let chat_next = state.next_per_chat.get(chat_id).copied().unwrap_or(now);
+        let slot = now.max(state.next_global).max(chat_next);
+
+        state.next_global = slot + self.global_interval;
+        state
+            .next_per_chat
+            .insert(chat_id.to_string(), slot + self.chat_interval);
+
+        // Forget chats whose slots are long gone
+        state.next_per_chat.retain(|_, next| *next > now);
+
+        slot - now
+    }
+
+    /// Wait until a send slot for the chat is available
+    pub async fn acquire(&self, chat_id: &str) {
+        let wait = self.reserve(chat_id);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Push back sends after the API reported a rate limit hit
+    ///
+    /// A 429 on one chat means the bot as a whole is being throttled,
+    /// so both the chat slot and the global slot are delayed.
+    pub fn penalize(&self, chat_id: &str, retry_after: Duration) {
+        let until = Instant::now() + retry_after;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        state.next_global = state.next_global.max(until);
+        let chat_next = state
+            .next_per_chat
+            .entry(chat_id.to_string())
+            .or_insert(until);
+        *chat_next = (*chat_next).max(until);
+    }
+}
+
+/// Convert a rate in sends per second to a spacing interval
+fn interval_for_rate(per_sec: f64) -> Duration {
+    if per_sec <= 0.0 || !per_sec.is_finite() {
+        Duration::ZERO
+    } else {
+        Duration::from_secs_f64(1.0 / per_sec)
+    }
+}
+
+/// Extract `parameters.retry_after` (seconds) from a Bot API error body
+pub fn parse_retry_after(body: &str) -> Option<u64> {
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    value
+        .get("parameters")
+        .and_then(|p| p.get("retry_after"))
+        .and_then(|v| v.as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_reservation_is_immediate() {
+        let limiter = RateLimiter::new(30.0, 1.0);
+        assert!(limiter.reserve("chat_1").is_zero());
+    }
+
+    #[test]
+    fn test_same_chat_is_spaced() {
+        let limiter = RateLimiter::new(1000.0, 1.0);
+        limiter.reserve("chat_1");
+        let wait = limiter.reserve("chat_1");
+        assert!(wait > Duration::from_millis(900));
+        assert!(wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_different_chats_use_global_interval() {
+        let limiter = RateLimiter::new(10.0, 1.0);
+        limiter.reserve("chat_1");
+        let wait = limiter.reserve("chat_2");
+        assert!(wait > Duration::from_millis(50));
+        assert!(wait <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_penalize_delays_all_chats() {
+        let limiter = RateLimiter::new(1000.0, 1000.0);
+        limiter.penalize("chat_1", Duration::from_secs(5));
+        assert!(limiter.reserve("chat_1") > Duration::from_secs(4));
+        assert!(limiter.reserve("chat_2") > Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_zero_rate_disables_limit() {
+        let limiter = RateLimiter::new(0.0, 0.0);
+        limiter.reserve("chat_1");
+        assert!(limiter.reserve("chat_1").is_zero());
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let body = r#"{"ok":false,"error_code":429,"description":"Too Many Requests: retry after 7","parameters":{"retry_after":7}}"#;
+        assert_eq!(parse_retry_after(body), Some(7));
+        assert_eq!(parse_retry_after(r#"{"ok":false}"#), None);
+        assert_eq!(parse_retry_after("not json"), None);
+    }
+}
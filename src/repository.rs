@@ -0,0 +1,948 @@
+//! Repositories for NuClaw
+//!
+//! Typed access to the messages, chats, and scheduled tasks tables, so
+//! channels and the scheduler do not write SQL themselves. Get one from the
+//! database, e.g. `db.tasks().get(id)`. Like all database calls they block;
+//! async code runs them through `Database::call`. Every repository reads
+//! and writes the rows of the database handle's tenant only.
+//!
+//! Queries run on `Database::read_connection` and changes on
+//! `Database::get_connection`, so on SQLite reads never wait for the
+//! single writer connection.
+
+use crate::db::{Backend, Database, Row, Storage};
+use crate::error::Result;
+use crate::redact::redact;
+use crate::task_scheduler::RunFilter;
+use crate::types::{ContextMessage, NewMessage, ScheduledTask, TaskRunLog};
+use serde::Serialize;
+
+impl Database {
+    /// Stored chat messages
+    pub fn messages(&self) -> MessageRepository<'_> {
+        MessageRepository { db: self }
+    }
+
+    /// Chats seen by the channels
+    pub fn chats(&self) -> ChatRepository<'_> {
+        ChatRepository { db: self }
+    }
+
+    /// Scheduled tasks and their run history
+    pub fn tasks(&self) -> TaskRepository<'_> {
+        TaskRepository { db: self }
+    }
+}
+
+/// Stored chat messages
+pub struct MessageRepository<'a> {
+    db: &'a Database,
+}
+
+impl MessageRepository<'_> {
+    /// Store a message, replacing an earlier copy with the same ID
+    pub fn store(&self, msg: &NewMessage, is_from_me: bool) -> Result<()> {
+        store_message(
+            &*self.db.get_connection()?,
+            self.db.tenant(),
+            msg,
+            is_from_me,
+        )
+    }
+
+    /// The last `limit` messages of a chat with content, other than
+    /// `exclude_id`, oldest first
+    pub fn recent(
+        &self,
+        chat_jid: &str,
+        exclude_id: &str,
+        limit: usize,
+    ) -> Result<Vec<ContextMessage>> {
+        if limit == 0 {
+            return Ok(vec![]);
+        }
+
+        let conn = self.db.read_connection()?;
+        let mut messages = conn.query_map(
+            &recent_messages_sql(conn.backend()),
+            crate::params![self.db.tenant(), chat_jid, exclude_id, limit as i64],
+            |row| {
+                Ok(ContextMessage {
+                    sender_name: row.get::<Option<String>>(0)?.unwrap_or_default(),
+                    content: row.get(1)?,
+                    timestamp: row.get(2)?,
+                })
+            },
+        )?;
+        messages.reverse();
+        Ok(messages)
+    }
+}
+
+/// `MessageRepository::store` on a given connection, e.g. in a transaction
+pub(crate) fn store_message(
+    conn: &dyn Storage,
+    tenant: &str,
+    msg: &NewMessage,
+    is_from_me: bool,
+) -> Result<()> {
+    // Pasted credentials are not kept in the history
+    let content = redact(&msg.content).into_owned();
+    conn.execute(
+        "INSERT INTO messages
+            (tenant, id, chat_jid, sender, sender_name, content, timestamp, is_from_me)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (tenant, id, chat_jid) DO UPDATE SET
+            sender = excluded.sender,
+            sender_name = excluded.sender_name,
+            content = excluded.content,
+            timestamp = excluded.timestamp,
+            is_from_me = excluded.is_from_me",
+        crate::params![
+            tenant,
+            msg.id,
+            msg.chat_jid,
+            msg.sender,
+            msg.sender_name,
+            content,
+            msg.timestamp,
+            is_from_me as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Latest messages of a chat, served by `idx_messages_chat_time`
+fn recent_messages_sql(backend: Backend) -> String {
+    // Timestamps have second resolution; ties go by insertion order
+    let insertion_order = match backend {
+        Backend::Sqlite => "rowid",
+        Backend::Postgres => "ctid",
+    };
+    format!(
+        "SELECT sender_name, content, timestamp FROM messages
+         WHERE tenant = ? AND chat_jid = ? AND id != ? AND content != ''
+         ORDER BY timestamp DESC, {} DESC LIMIT ?",
+        insertion_order
+    )
+}
+
+/// `ChatRepository::upsert` on a given connection, e.g. in a transaction
+pub(crate) fn upsert_chat(
+    conn: &dyn Storage,
+    tenant: &str,
+    jid: &str,
+    name: Option<&str>,
+    message_time: &str,
+) -> Result<()> {
+    let name = name.map(str::trim).filter(|n| !n.is_empty());
+    conn.execute(
+        "INSERT INTO chats (tenant, jid, name, last_message_time) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (tenant, jid) DO UPDATE SET
+            name = COALESCE(excluded.name, chats.name),
+            last_message_time = CASE
+                WHEN chats.last_message_time IS NULL
+                     OR chats.last_message_time < excluded.last_message_time
+                THEN excluded.last_message_time
+                ELSE chats.last_message_time END",
+        crate::params![tenant, jid, name, normalize_message_time(message_time)],
+    )?;
+    Ok(())
+}
+
+/// A chat and when it last had a message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Chat {
+    pub jid: String,
+    pub name: Option<String>,
+    pub last_message_time: Option<String>,
+}
+
+/// Chats seen by the channels
+pub struct ChatRepository<'a> {
+    db: &'a Database,
+}
+
+impl ChatRepository<'_> {
+    /// Record a message in a chat, adding the chat if it is new
+    ///
+    /// A known name is kept when the message carries none, and an older
+    /// (e.g. replayed) message does not move `last_message_time` back.
+    pub fn upsert(&self, jid: &str, name: Option<&str>, message_time: &str) -> Result<()> {
+        upsert_chat(
+            &*self.db.get_connection()?,
+            self.db.tenant(),
+            jid,
+            name,
+            message_time,
+        )
+    }
+
+    /// Set the display name of a chat, adding the chat if it is new
+    pub fn set_name(&self, jid: &str, name: &str) -> Result<()> {
+        self.db.get_connection()?.execute(
+            "INSERT INTO chats (tenant, jid, name) VALUES (?, ?, ?)
+             ON CONFLICT (tenant, jid) DO UPDATE SET name = excluded.name",
+            [self.db.tenant(), jid, name],
+        )?;
+        Ok(())
+    }
+
+    /// Look up a chat
+    pub fn get(&self, jid: &str) -> Result<Option<Chat>> {
+        self.db.read_connection()?.query_opt(
+            "SELECT jid, name, last_message_time FROM chats WHERE tenant = ? AND jid = ?",
+            [self.db.tenant(), jid],
+            chat_from_row,
+        )
+    }
+
+    /// Name to show for a chat: its display name, or its JID when the name
+    /// is unknown or the lookup fails
+    pub fn label(&self, jid: &str) -> String {
+        match self.get(jid) {
+            Ok(Some(Chat {
+                name: Some(name), ..
+            })) => format!("{} ({})", name, jid),
+            Ok(_) => jid.to_string(),
+            Err(e) => {
+                tracing::debug!("Failed to look up chat {}: {}", jid, e);
+                jid.to_string()
+            }
+        }
+    }
+
+    /// All chats, the most recently active first
+    pub fn list(&self) -> Result<Vec<Chat>> {
+        self.recent(i64::MAX as usize)
+    }
+
+    /// The `limit` most recently active chats
+    pub fn recent(&self, limit: usize) -> Result<Vec<Chat>> {
+        self.db.read_connection()?.query_map(
+            "SELECT jid, name, last_message_time FROM chats WHERE tenant = ?
+             ORDER BY last_message_time IS NULL, last_message_time DESC, jid LIMIT ?",
+            crate::params![self.db.tenant(), limit.min(i64::MAX as usize) as i64],
+            chat_from_row,
+        )
+    }
+}
+
+/// Time of a stored message; Telegram sends Unix seconds, WhatsApp RFC 3339
+pub fn parse_message_time(timestamp: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    match timestamp.parse::<i64>() {
+        Ok(secs) => chrono::DateTime::from_timestamp(secs, 0),
+        Err(_) => chrono::DateTime::parse_from_rfc3339(timestamp)
+            .ok()
+            .map(|time| time.with_timezone(&chrono::Utc)),
+    }
+}
+
+/// Message timestamps as RFC 3339, so chats of all channels sort together
+fn normalize_message_time(timestamp: &str) -> String {
+    parse_message_time(timestamp)
+        .unwrap_or_else(chrono::Utc::now)
+        .to_rfc3339()
+}
+
+fn chat_from_row(row: &Row) -> Result<Chat> {
+    Ok(Chat {
+        jid: row.get(0)?,
+        name: row.get(1)?,
+        last_message_time: row.get(2)?,
+    })
+}
+
+/// Scheduled tasks and their run history
+pub struct TaskRepository<'a> {
+    db: &'a Database,
+}
+
+/// Columns read into a `ScheduledTask` by `task_from_row`
+const TASK_COLUMNS: &str = "id, group_folder, chat_jid, prompt, schedule_type, schedule_value,
+    next_run, last_run, last_result, status, created_at, context_mode, container_retries,
+    max_retries, consecutive_failures, jitter_secs, timeout_secs, notify, lock, overlap,
+    priority";
+
+fn task_from_row(row: &Row) -> Result<ScheduledTask> {
+    Ok(ScheduledTask {
+        id: row.get(0)?,
+        group_folder: row.get(1)?,
+        chat_jid: row.get(2)?,
+        prompt: row.get(3)?,
+        schedule_type: row.get(4)?,
+        schedule_value: row.get(5)?,
+        next_run: row.get(6)?,
+        last_run: row.get(7)?,
+        last_result: row.get(8)?,
+        status: row.get(9)?,
+        created_at: row.get(10)?,
+        context_mode: row.get(11)?,
+        container_retries: row.get(12)?,
+        max_retries: row.get(13)?,
+        consecutive_failures: row.get(14)?,
+        jitter_secs: row.get(15)?,
+        timeout_secs: row.get(16)?,
+        notify: row.get(17)?,
+        lock: row.get(18)?,
+        overlap: row.get(19)?,
+        priority: row.get(20)?,
+    })
+}
+
+/// Due tasks of a tenant, found through `idx_scheduled_tasks_tenant_due`
+fn due_tasks_sql() -> String {
+    format!(
+        "SELECT {} FROM scheduled_tasks
+         WHERE tenant = ? AND status = 'active'
+           AND (next_run IS NULL OR next_run <= ?)
+         ORDER BY priority DESC, next_run ASC",
+        TASK_COLUMNS
+    )
+}
+
+/// Next runs of a tenant's active tasks; read in
+/// `idx_scheduled_tasks_tenant_due` order, without sorting
+const NEXT_RUNS_SQL: &str = "SELECT id, next_run FROM scheduled_tasks
+     WHERE tenant = ? AND status = 'active'
+     ORDER BY next_run NULLS FIRST";
+
+impl TaskRepository<'_> {
+    /// Load one task
+    pub fn get(&self, task_id: &str) -> Result<Option<ScheduledTask>> {
+        self.db.read_connection()?.query_opt(
+            &format!(
+                "SELECT {} FROM scheduled_tasks WHERE tenant = ? AND id = ?",
+                TASK_COLUMNS
+            ),
+            [self.db.tenant(), task_id],
+            task_from_row,
+        )
+    }
+
+    /// Tasks of a chat, or all tasks, oldest first
+    pub fn list(&self, chat_jid: Option<&str>) -> Result<Vec<ScheduledTask>> {
+        self.db.read_connection()?.query_map(
+            &format!(
+                "SELECT {} FROM scheduled_tasks
+                 WHERE tenant = ?2 AND (CAST(?1 AS TEXT) IS NULL OR chat_jid = ?1)
+                 ORDER BY created_at",
+                TASK_COLUMNS
+            ),
+            [chat_jid, Some(self.db.tenant())],
+            task_from_row,
+        )
+    }
+
+    /// Active tasks due at `now` (RFC 3339), highest priority first
+    pub fn due(&self, now: &str) -> Result<Vec<ScheduledTask>> {
+        self.db.read_connection()?.query_map(
+            &due_tasks_sql(),
+            [self.db.tenant(), now],
+            task_from_row,
+        )
+    }
+
+    /// IDs and next runs of the active tasks, soonest first; tasks without a
+    /// next run come first
+    pub fn next_runs(&self) -> Result<Vec<(String, Option<String>)>> {
+        self.db
+            .read_connection()?
+            .query_map(NEXT_RUNS_SQL, [self.db.tenant()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+    }
+
+    /// Store a new task
+    pub fn insert(&self, task: &ScheduledTask) -> Result<()> {
+        self.db.get_connection()?.execute(
+            "INSERT INTO scheduled_tasks
+                (tenant, id, group_folder, chat_jid, prompt, schedule_type, schedule_value,
+                 next_run, status, created_at, context_mode, max_retries, jitter_secs, timeout_secs,
+                 notify, lock, overlap, priority)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            crate::params![
+                self.db.tenant(),
+                task.id,
+                task.group_folder,
+                task.chat_jid,
+                task.prompt,
+                task.schedule_type,
+                task.schedule_value,
+                task.next_run,
+                task.status,
+                task.created_at,
+                task.context_mode,
+                task.max_retries,
+                task.jitter_secs,
+                task.timeout_secs,
+                task.notify,
+                task.lock,
+                task.overlap,
+                task.priority,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Store the settings of a task: its prompt, schedule, and run options
+    ///
+    /// Status, run state, and history are left alone.
+    pub fn update(&self, task: &ScheduledTask) -> Result<bool> {
+        let changed = self.db.get_connection()?.execute(
+            "UPDATE scheduled_tasks
+             SET prompt = ?, schedule_type = ?, schedule_value = ?, next_run = ?, max_retries = ?,
+                 jitter_secs = ?, timeout_secs = ?, notify = ?, lock = ?, overlap = ?, priority = ?
+             WHERE tenant = ? AND id = ?",
+            crate::params![
+                task.prompt,
+                task.schedule_type,
+                task.schedule_value,
+                task.next_run,
+                task.max_retries,
+                task.jitter_secs,
+                task.timeout_secs,
+                task.notify,
+                task.lock,
+                task.overlap,
+                task.priority,
+                self.db.tenant(),
+                task.id,
+            ],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Set when a task runs next
+    pub fn set_next_run(&self, task_id: &str, next_run: &str) -> Result<()> {
+        self.db.get_connection()?.execute(
+            "UPDATE scheduled_tasks SET next_run = ? WHERE tenant = ? AND id = ?",
+            [next_run, self.db.tenant(), task_id],
+        )?;
+        Ok(())
+    }
+
+    /// Store the number of consecutive failed runs of a task
+    pub fn set_consecutive_failures(&self, task_id: &str, failures: u32) -> Result<()> {
+        self.db.get_connection()?.execute(
+            "UPDATE scheduled_tasks SET consecutive_failures = ? WHERE tenant = ? AND id = ?",
+            crate::params![failures, self.db.tenant(), task_id],
+        )?;
+        Ok(())
+    }
+
+    /// Mark a one-off task as done
+    pub fn complete(&self, task_id: &str) -> Result<()> {
+        self.db.get_connection()?.execute(
+            "UPDATE scheduled_tasks SET status = 'completed', next_run = NULL
+             WHERE tenant = ? AND id = ?",
+            [self.db.tenant(), task_id],
+        )?;
+        Ok(())
+    }
+
+    /// Mark a task as failed; it no longer runs until resumed
+    pub fn fail(&self, task_id: &str) -> Result<()> {
+        self.db.get_connection()?.execute(
+            "UPDATE scheduled_tasks SET status = 'failed' WHERE tenant = ? AND id = ?",
+            [self.db.tenant(), task_id],
+        )?;
+        Ok(())
+    }
+
+    /// Pause a task; false if there is no such task
+    pub fn pause(&self, task_id: &str) -> Result<bool> {
+        let changed = self.db.get_connection()?.execute(
+            "UPDATE scheduled_tasks SET status = 'paused' WHERE tenant = ? AND id = ?",
+            [self.db.tenant(), task_id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Make a task active again from `next_run`, clearing its failures;
+    /// false if there is no such task
+    pub fn activate(&self, task_id: &str, next_run: Option<&str>) -> Result<bool> {
+        let changed = self.db.get_connection()?.execute(
+            "UPDATE scheduled_tasks SET status = 'active', next_run = ?, consecutive_failures = 0
+             WHERE tenant = ? AND id = ?",
+            crate::params![next_run, self.db.tenant(), task_id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Delete a task and its run history; false if there is no such task
+    pub fn delete(&self, task_id: &str) -> Result<bool> {
+        let conn = self.db.get_connection()?;
+        let tenant = self.db.tenant();
+        conn.execute(
+            "DELETE FROM task_run_logs WHERE tenant = ? AND task_id = ?",
+            [tenant, task_id],
+        )?;
+        Ok(conn.execute(
+            "DELETE FROM scheduled_tasks WHERE tenant = ? AND id = ?",
+            [tenant, task_id],
+        )? > 0)
+    }
+
+    /// Add a run to the history and store its outcome as the task's last
+    /// result
+    pub fn log_run(&self, run: &TaskRunLog, last_result: Option<&str>) -> Result<()> {
+        let conn = self.db.get_connection()?;
+        conn.execute(
+            "INSERT INTO task_run_logs (tenant, task_id, run_at, duration_ms, status, result, error)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            crate::params![
+                self.db.tenant(),
+                run.task_id,
+                run.run_at,
+                run.duration_ms,
+                run.status,
+                run.result.clone().unwrap_or_default(),
+                run.error.clone().unwrap_or_default(),
+            ],
+        )?;
+        conn.execute(
+            "UPDATE scheduled_tasks SET last_run = ?, last_result = ? WHERE tenant = ? AND id = ?",
+            crate::params![run.run_at, last_result, self.db.tenant(), run.task_id],
+        )?;
+        Ok(())
+    }
+
+    /// Runs matching `filter`, newest first
+    ///
+    /// `since` and `until` are compared as stored, so they must already be
+    /// in the RFC 3339 format of `run_at`.
+    pub fn runs(&self, filter: &RunFilter, limit: usize) -> Result<Vec<TaskRunLog>> {
+        let non_empty = |s: Option<String>| s.filter(|s| !s.is_empty());
+        self.db.read_connection()?.query_map(
+            "SELECT task_id, run_at, duration_ms, status, result, error
+             FROM task_run_logs
+             WHERE tenant = ?6
+               AND (CAST(?1 AS TEXT) IS NULL OR task_id = ?1)
+               AND (CAST(?2 AS TEXT) IS NULL OR status = ?2)
+               AND (CAST(?3 AS TEXT) IS NULL OR run_at >= ?3)
+               AND (CAST(?4 AS TEXT) IS NULL OR run_at < ?4)
+             ORDER BY id DESC LIMIT ?5",
+            crate::params![
+                filter.task_id,
+                filter.status,
+                filter.since,
+                filter.until,
+                limit as i64,
+                self.db.tenant()
+            ],
+            |row| {
+                Ok(TaskRunLog {
+                    task_id: row.get(0)?,
+                    run_at: row.get(1)?,
+                    duration_ms: row.get(2)?,
+                    status: row.get(3)?,
+                    result: non_empty(row.get(4)?),
+                    error: non_empty(row.get(5)?),
+                })
+            },
+        )
+    }
+
+    /// Delete runs from before `cutoff` (RFC 3339); returns how many
+    pub fn prune_runs(&self, cutoff: &str) -> Result<usize> {
+        self.db.get_connection()?.execute(
+            "DELETE FROM task_run_logs WHERE tenant = ? AND run_at < ?",
+            [self.db.tenant(), cutoff],
+        )
+    }
+
+    /// Result of a task's latest successful run still in the run history
+    pub fn last_successful_result(&self, task_id: &str) -> Result<Option<String>> {
+        let result = self.db.read_connection()?.query_opt(
+            "SELECT result FROM task_run_logs
+             WHERE tenant = ? AND task_id = ? AND status = 'success'
+             ORDER BY id DESC LIMIT 1",
+            [self.db.tenant(), task_id],
+            |row| row.get::<Option<String>>(0),
+        )?;
+        Ok(result.flatten())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{test_database, Storage, Value};
+    use std::time::Instant;
+
+    fn message(id: &str, chat_jid: &str, content: &str, timestamp: &str) -> NewMessage {
+        NewMessage {
+            id: id.to_string(),
+            chat_jid: chat_jid.to_string(),
+            sender: "u1".to_string(),
+            sender_name: "Alice".to_string(),
+            content: content.to_string(),
+            timestamp: timestamp.to_string(),
+            reply_to_id: None,
+            quoted_content: None,
+            chat_name: None,
+            correlation_id: None,
+        }
+    }
+
+    fn task(id: &str, next_run: &str) -> ScheduledTask {
+        ScheduledTask {
+            id: id.to_string(),
+            group_folder: "family".to_string(),
+            chat_jid: "123@g.us".to_string(),
+            prompt: "Summarize".to_string(),
+            schedule_type: "interval".to_string(),
+            schedule_value: "3600000".to_string(),
+            next_run: Some(next_run.to_string()),
+            last_run: None,
+            last_result: None,
+            status: "active".to_string(),
+            created_at: "2026-01-01T00:00:00+00:00".to_string(),
+            context_mode: "isolated".to_string(),
+            container_retries: None,
+            max_retries: None,
+            consecutive_failures: 0,
+            jitter_secs: None,
+            timeout_secs: None,
+            notify: "always".to_string(),
+            lock: None,
+            overlap: "skip".to_string(),
+            priority: 0,
+        }
+    }
+
+    fn run(task_id: &str, run_at: &str, status: &str, result: &str) -> TaskRunLog {
+        TaskRunLog {
+            task_id: task_id.to_string(),
+            run_at: run_at.to_string(),
+            duration_ms: 1000,
+            status: status.to_string(),
+            result: Some(result.to_string()),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        let (db, _dir) = test_database();
+        let messages = db.messages();
+        messages
+            .store(&message("1", "chat", "first", "1700000001"), false)
+            .unwrap();
+        messages
+            .store(&message("2", "chat", "second", "1700000002"), false)
+            .unwrap();
+        messages
+            .store(&message("3", "chat", "", "1700000003"), false)
+            .unwrap();
+        messages
+            .store(&message("4", "chat", "@Andy hi", "1700000004"), false)
+            .unwrap();
+        messages
+            .store(&message("5", "other", "elsewhere", "1700000005"), false)
+            .unwrap();
+        // Storing a message again replaces it
+        messages
+            .store(&message("1", "chat", "edited", "1700000001"), true)
+            .unwrap();
+
+        let recent = messages.recent("chat", "4", 10).unwrap();
+        let contents: Vec<_> = recent.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["edited", "second"]);
+        assert_eq!(
+            messages.recent("chat", "4", 1).unwrap()[0].content,
+            "second"
+        );
+        assert!(messages.recent("chat", "4", 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_store_message_redacts_secrets() {
+        let (db, _dir) = test_database();
+        let messages = db.messages();
+        messages
+            .store(
+                &message("1", "chat", "my password=hunter2hunter2", "1700000001"),
+                false,
+            )
+            .unwrap();
+        messages
+            .store(&message("2", "chat", "@Andy hi", "1700000002"), false)
+            .unwrap();
+
+        let recent = messages.recent("chat", "2", 10).unwrap();
+        assert_eq!(recent[0].content, "my password=[REDACTED]");
+    }
+
+    #[test]
+    fn test_chats() {
+        let (db, _dir) = test_database();
+        let chats = db.chats();
+        assert_eq!(chats.get("a@g.us").unwrap(), None);
+
+        chats
+            .upsert("a@g.us", None, "2026-01-01T09:00:00+00:00")
+            .unwrap();
+        chats.upsert("b@g.us", Some("Work"), "1767344400").unwrap();
+        chats.set_name("a@g.us", "Family").unwrap();
+        chats
+            .upsert("a@g.us", None, "2026-01-03T09:00:00+00:00")
+            .unwrap();
+        // A replayed message keeps the latest time
+        chats
+            .upsert("a@g.us", Some(" "), "2026-01-02T09:00:00+00:00")
+            .unwrap();
+        chats.set_name("c@g.us", "Book club").unwrap();
+
+        let a = chats.get("a@g.us").unwrap().unwrap();
+        assert_eq!(a.name.as_deref(), Some("Family"));
+        assert_eq!(
+            a.last_message_time.as_deref(),
+            Some("2026-01-03T09:00:00+00:00")
+        );
+        let b = chats.get("b@g.us").unwrap().unwrap();
+        assert_eq!(
+            b.last_message_time.as_deref(),
+            Some("2026-01-02T09:00:00+00:00")
+        );
+        assert_eq!(chats.label("b@g.us"), "Work (b@g.us)");
+        assert_eq!(chats.label("d@g.us"), "d@g.us");
+        let order: Vec<_> = chats.list().unwrap().into_iter().map(|c| c.jid).collect();
+        assert_eq!(order, vec!["a@g.us", "b@g.us", "c@g.us"]);
+        assert_eq!(chats.recent(1).unwrap()[0].jid, "a@g.us");
+    }
+
+    #[test]
+    fn test_tasks() {
+        let (db, _dir) = test_database();
+        let tasks = db.tasks();
+        let later = task("later", "2026-01-02T09:00:00+00:00");
+        let sooner = ScheduledTask {
+            priority: 5,
+            ..task("sooner", "2026-01-01T09:00:00+00:00")
+        };
+        tasks.insert(&later).unwrap();
+        tasks.insert(&sooner).unwrap();
+        let stored = tasks.get("sooner").unwrap().unwrap();
+        assert_eq!(stored.priority, 5);
+        assert_eq!(stored.next_run, sooner.next_run);
+        assert_eq!(tasks.list(Some("123@g.us")).unwrap().len(), 2);
+        assert!(tasks.list(Some("456@g.us")).unwrap().is_empty());
+
+        let due: Vec<_> = tasks
+            .due("2026-01-03T00:00:00+00:00")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(due, vec!["sooner", "later"]);
+        assert_eq!(tasks.due("2026-01-01T10:00:00+00:00").unwrap().len(), 1);
+        assert_eq!(tasks.next_runs().unwrap()[0].0, "sooner");
+
+        let edited = ScheduledTask {
+            prompt: "Summarize briefly".to_string(),
+            status: "paused".to_string(),
+            ..later.clone()
+        };
+        assert!(tasks.update(&edited).unwrap());
+        let stored = tasks.get("later").unwrap().unwrap();
+        assert_eq!(stored.prompt, "Summarize briefly");
+        assert_eq!(stored.status, "active");
+
+        tasks.set_consecutive_failures("later", 2).unwrap();
+        assert!(tasks.pause("later").unwrap());
+        assert_eq!(tasks.next_runs().unwrap().len(), 1);
+        assert!(tasks
+            .activate("later", Some("2026-01-05T09:00:00+00:00"))
+            .unwrap());
+        let stored = tasks.get("later").unwrap().unwrap();
+        assert_eq!(stored.consecutive_failures, 0);
+        assert_eq!(
+            stored.next_run.as_deref(),
+            Some("2026-01-05T09:00:00+00:00")
+        );
+
+        tasks.complete("sooner").unwrap();
+        let stored = tasks.get("sooner").unwrap().unwrap();
+        assert_eq!(stored.status, "completed");
+        assert_eq!(stored.next_run, None);
+        tasks.fail("later").unwrap();
+        assert!(tasks.next_runs().unwrap().is_empty());
+
+        assert!(tasks.delete("later").unwrap());
+        assert!(!tasks.delete("later").unwrap());
+        assert!(!tasks.pause("later").unwrap());
+    }
+
+    #[test]
+    fn test_task_runs() {
+        let (db, _dir) = test_database();
+        let tasks = db.tasks();
+        tasks
+            .insert(&task("a", "2026-01-01T09:00:00+00:00"))
+            .unwrap();
+        for (run_at, status, result) in [
+            ("2026-01-01T09:00:00+00:00", "success", "old"),
+            ("2026-01-02T09:00:00+00:00", "success", "new"),
+            ("2026-01-03T09:00:00+00:00", "error", ""),
+        ] {
+            tasks
+                .log_run(&run("a", run_at, status, result), Some(result))
+                .unwrap();
+        }
+
+        assert_eq!(
+            tasks.last_successful_result("a").unwrap().as_deref(),
+            Some("new")
+        );
+        assert_eq!(tasks.last_successful_result("b").unwrap(), None);
+        let stored = tasks.get("a").unwrap().unwrap();
+        assert_eq!(
+            stored.last_run.as_deref(),
+            Some("2026-01-03T09:00:00+00:00")
+        );
+
+        let all = tasks.runs(&RunFilter::default(), 10).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].status, "error");
+        assert_eq!(all[0].result, None);
+        let since = RunFilter {
+            since: Some("2026-01-02T00:00:00+00:00".to_string()),
+            status: Some("success".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(tasks.runs(&since, 10).unwrap().len(), 1);
+
+        assert_eq!(tasks.prune_runs("2026-01-03T00:00:00+00:00").unwrap(), 2);
+        assert!(tasks.delete("a").unwrap());
+        assert!(tasks.runs(&RunFilter::default(), 10).unwrap().is_empty());
+    }
+
+    /// Steps of SQLite's plan for `sql`, one per line
+    fn query_plan(conn: &dyn Storage, sql: &str, params: Vec<Value>) -> String {
+        conn.query_map(&format!("EXPLAIN QUERY PLAN {}", sql), params, |row| {
+            row.get::<String>(3)
+        })
+        .unwrap()
+        .join("\n")
+    }
+
+    fn store_messages(db: &Database, chats: usize, per_chat: usize) {
+        let mut conn = db.get_connection().unwrap();
+        let tx = conn.transaction().unwrap();
+        for chat in 0..chats {
+            for i in 0..per_chat {
+                tx.execute(
+                    "INSERT INTO messages (id, chat_jid, sender, sender_name, content, timestamp)
+                     VALUES (?, ?, 'u1', 'Alice', 'hello', ?)",
+                    crate::params![
+                        i.to_string(),
+                        format!("{}@g.us", chat),
+                        (1_700_000_000 + i).to_string()
+                    ],
+                )
+                .unwrap();
+            }
+        }
+        tx.commit().unwrap();
+    }
+
+    #[test]
+    fn test_lookups_use_indexes() {
+        let (db, _dir) = test_database();
+        let conn = db.get_connection().unwrap();
+        // Postgres plans depend on table statistics; only SQLite's are stable
+        if conn.backend() != Backend::Sqlite {
+            return;
+        }
+
+        let recent = query_plan(
+            &*conn,
+            &recent_messages_sql(Backend::Sqlite),
+            vec![
+                Value::Text("default".to_string()),
+                Value::Text("0@g.us".to_string()),
+                Value::Text("1".to_string()),
+                Value::Integer(20),
+            ],
+        );
+        assert!(
+            recent.contains("USING INDEX idx_messages_chat_time"),
+            "{}",
+            recent
+        );
+        assert!(!recent.contains("TEMP B-TREE"), "{}", recent);
+
+        let due = query_plan(
+            &*conn,
+            &due_tasks_sql(),
+            vec![
+                Value::Text("default".to_string()),
+                Value::Text("2026".to_string()),
+            ],
+        );
+        assert!(
+            due.contains("USING INDEX idx_scheduled_tasks_tenant_due"),
+            "{}",
+            due
+        );
+
+        let next_runs = query_plan(
+            &*conn,
+            NEXT_RUNS_SQL,
+            vec![Value::Text("default".to_string())],
+        );
+        assert!(
+            next_runs.contains("USING INDEX idx_scheduled_tasks_tenant_due"),
+            "{}",
+            next_runs
+        );
+        assert!(!next_runs.contains("TEMP B-TREE"), "{}", next_runs);
+    }
+
+    /// Context lookups in a busy group, with and without the index
+    ///
+    /// Run with `cargo test --release recent_messages_lookup -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_recent_messages_lookup() {
+        const LOOKUPS: u32 = 200;
+        let (db, _dir) = test_database();
+        store_messages(&db, 5, 20_000);
+
+        let time_lookups = || {
+            let start = Instant::now();
+            for i in 0..LOOKUPS {
+                let recent = db
+                    .messages()
+                    .recent(&format!("{}@g.us", i % 5), "", 20)
+                    .unwrap();
+                assert_eq!(recent.len(), 20);
+            }
+            start.elapsed() / LOOKUPS
+        };
+        let conn = db.get_connection().unwrap();
+        let sql = recent_messages_sql(conn.backend());
+
+        let indexed = time_lookups();
+        let indexed_plan = if conn.backend() == Backend::Sqlite {
+            let params = vec![
+                Value::Text("default".to_string()),
+                Value::Text("0@g.us".to_string()),
+                Value::Text(String::new()),
+                Value::Integer(20),
+            ];
+            query_plan(&*conn, &sql, params)
+        } else {
+            String::new()
+        };
+        conn.execute("DROP INDEX idx_messages_chat_time", ())
+            .unwrap();
+        let unindexed = time_lookups();
+        println!(
+            "100000 messages in 5 chats: {:?} per lookup with idx_messages_chat_time ({}), {:?} without",
+            indexed, indexed_plan, unindexed
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Roles for NuClaw
+//!
+//! Chat users hold one of three roles, per tenant and channel:
+//!
+//! - `owner` - everything, including granting and revoking roles
+//! - `admin` - admin commands: task management, allowlist edits,
+//!   cancelling runs, broadcasts, maintenance, and the rest
+//! - `member` - talking to the assistant; the role of everyone not in the
+//!   `users` table
+//!
+//! The senders listed in `ADMIN_USERS` (or a tenant's `admin_users`) are
+//! owners. Other roles are stored in the `users` table, either for all
+//! groups or for one group folder, where the higher of the two applies.
+//!
+//! Each admin function is a `Permission`. Most need `admin`; a group can
+//! lower or raise that for commands sent in it in the `[permissions]`
+//! table of its `nuclaw.toml`:
+//!
+//! ```toml
+//! [permissions]
+//! tasks = "member"
+//! cancel = "member"
+//! ```
+//!
+//! Commands that affect every group (`Permission::Admin`) always need a
+//! role that holds for all groups, and roles are only managed by owners.
+
+use crate::audit::chat_actor;
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use crate::group_config::group_settings;
+use crate::tenants::tenant;
+use serde::{Deserialize, Serialize};
+
+/// Role of a chat user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Member,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Member => "member",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "member" => Some(Role::Member),
+            "admin" => Some(Role::Admin),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+/// An admin function gated by a role
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Create, pause, resume, and delete the chat's scheduled tasks
+    Tasks,
+    /// Add and remove allowlist and blocklist entries
+    Allowlist,
+    /// Stop the agent running for the chat, or a task's run
+    Cancel,
+    /// Send a message to several groups
+    Broadcast,
+    /// Everything else: maintenance, log level, pairing, registration,
+    /// templates, and status commands
+    Admin,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Tasks => "tasks",
+            Permission::Allowlist => "allowlist",
+            Permission::Cancel => "cancel",
+            Permission::Broadcast => "broadcast",
+            Permission::Admin => "admin",
+        }
+    }
+}
+
+/// Roles a group requires for its permissions; unset ones need `admin`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermissionSettings {
+    pub tasks: Option<Role>,
+    pub allowlist: Option<Role>,
+    pub cancel: Option<Role>,
+    pub broadcast: Option<Role>,
+}
+
+impl PermissionSettings {
+    /// Role needed for `permission` in a group with these settings
+    pub fn required_role(&self, permission: Permission) -> Role {
+        let configured = match permission {
+            Permission::Tasks => self.tasks,
+            Permission::Allowlist => self.allowlist,
+            Permission::Cancel => self.cancel,
+            Permission::Broadcast => self.broadcast,
+            Permission::Admin => None,
+        };
+        configured.unwrap_or(Role::Admin)
+    }
+}
+
+/// Whether a sender is an owner through `ADMIN_USERS` or the tenant's
+/// `admin_users`
+pub fn is_configured_owner(tenant_id: &str, sender: &str) -> bool {
+    tenant(tenant_id).admin_users().iter().any(|a| a == sender)
+}
+
+/// A stored role
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserRole {
+    pub channel: String,
+    pub user_id: String,
+    /// Group folder it holds in; `None` for all groups
+    pub group_folder: Option<String>,
+    pub role: Role,
+    pub granted_by: String,
+    pub granted_at: String,
+}
+
+/// Give a user a role, for all groups or in one group folder
+pub fn set_role(
+    db: &Database,
+    channel: &str,
+    user_id: &str,
+    group_folder: Option<&str>,
+    role: Role,
+    granted_by: &str,
+) -> Result<()> {
+    db.get_connection()?
+        .execute(
+            "INSERT INTO users (tenant, channel, user_id, group_folder, role, granted_by, granted_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT (tenant, channel, user_id, group_folder) DO UPDATE SET
+                role = excluded.role,
+                granted_by = excluded.granted_by,
+                granted_at = excluded.granted_at",
+            crate::params![
+                db.tenant(),
+                channel,
+                user_id,
+                group_folder.unwrap_or_default(),
+                role.as_str(),
+                granted_by,
+                chrono::Utc::now().to_rfc3339()
+            ],
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to store role: {}", e),
+        })?;
+    Ok(())
+}
+
+/// Remove a user's role for all groups or in one group folder; returns
+/// `false` if there was none
+pub fn remove_role(
+    db: &Database,
+    channel: &str,
+    user_id: &str,
+    group_folder: Option<&str>,
+) -> Result<bool> {
+    let removed = db
+        .get_connection()?
+        .execute(
+            "DELETE FROM users
+             WHERE tenant = ? AND channel = ? AND user_id = ? AND group_folder = ?",
+            [
+                db.tenant(),
+                channel,
+                user_id,
+                group_folder.unwrap_or_default(),
+            ],
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to remove role: {}", e),
+        })?;
+    Ok(removed > 0)
+}
+
+/// List the stored roles, highest first
+pub fn list_roles(db: &Database) -> Result<Vec<UserRole>> {
+    let rows: Vec<(String, String, String, String, String, String)> =
+        db.read_connection()?.query_map(
+            "SELECT channel, user_id, group_folder, role, granted_by, granted_at
+             FROM users WHERE tenant = ?
+             ORDER BY channel, user_id, group_folder",
+            crate::params![db.tenant()],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            },
+        )?;
+    let mut roles: Vec<UserRole> = rows
+        .into_iter()
+        .filter_map(
+            |(channel, user_id, group_folder, role, granted_by, granted_at)| {
+                Some(UserRole {
+                    channel,
+                    user_id,
+                    group_folder: Some(group_folder).filter(|f| !f.is_empty()),
+                    role: Role::parse(&role)?,
+                    granted_by,
+                    granted_at,
+                })
+            },
+        )
+        .collect();
+    roles.sort_by_key(|role| std::cmp::Reverse(role.role));
+    Ok(roles)
+}
+
+/// Role of a user, in `group_folder` if given, else for all groups
+pub fn role_of(
+    db: &Database,
+    channel: &str,
+    user_id: &str,
+    group_folder: Option<&str>,
+) -> Result<Role> {
+    if is_configured_owner(db.tenant(), user_id) {
+        return Ok(Role::Owner);
+    }
+    let roles: Vec<String> = db.read_connection()?.query_map(
+        "SELECT role FROM users
+         WHERE tenant = ? AND channel = ? AND user_id = ?
+           AND (group_folder = '' OR group_folder = ?)",
+        crate::params![
+            db.tenant(),
+            channel,
+            user_id,
+            group_folder.unwrap_or_default()
+        ],
+        |row| row.get(0),
+    )?;
+    Ok(roles
+        .iter()
+        .filter_map(|role| Role::parse(role))
+        .max()
+        .unwrap_or(Role::Member))
+}
+
+/// Whether a user holds `admin` or `owner` for all groups
+pub fn is_admin(db: &Database, channel: &str, user_id: &str) -> Result<bool> {
+    Ok(role_of(db, channel, user_id, None)? >= Role::Admin)
+}
+
+/// Whether a user may use `permission` in `group_folder`, or outside of a
+/// registered group if `None`
+pub fn is_permitted(
+    db: &Database,
+    channel: &str,
+    user_id: &str,
+    group_folder: Option<&str>,
+    permission: Permission,
+) -> Result<bool> {
+    let (scope, required) = match (permission, group_folder) {
+        (Permission::Admin, _) | (_, None) => (None, Role::Admin),
+        (permission, Some(folder)) => (
+            Some(folder),
+            group_settings(folder).permissions.required_role(permission),
+        ),
+    };
+    Ok(role_of(db, channel, user_id, scope)? >= required)
+}
+
+/// Text of a role listing, one role per line
+pub fn format_roles(roles: &[UserRole]) -> String {
+    if roles.is_empty() {
+        return "No roles granted; ADMIN_USERS are owners".to_string();
+    }
+    roles
+        .iter()
+        .map(|role| {
+            format!(
+                "{} {}{} (by {})",
+                role.role.as_str(),
+                chat_actor(&role.channel, &role.user_id),
+                role.group_folder
+                    .as_deref()
+                    .map(|folder| format!(" in {}", folder))
+                    .unwrap_or_default(),
+                role.granted_by
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[test]
+    fn test_role_parse_and_order() {
+        assert_eq!(Role::parse(" Admin "), Some(Role::Admin));
+        assert_eq!(Role::parse("root"), None);
+        assert!(Role::Owner > Role::Admin && Role::Admin > Role::Member);
+    }
+
+    #[test]
+    fn test_role_of() {
+        let (db, _dir) = test_database();
+        assert_eq!(role_of(&db, "telegram", "42", None).unwrap(), Role::Member);
+
+        set_role(&db, "telegram", "42", Some("family"), Role::Admin, "cli").unwrap();
+        assert_eq!(role_of(&db, "telegram", "42", None).unwrap(), Role::Member);
+        assert_eq!(
+            role_of(&db, "telegram", "42", Some("family")).unwrap(),
+            Role::Admin
+        );
+        assert_eq!(
+            role_of(&db, "telegram", "42", Some("work")).unwrap(),
+            Role::Member
+        );
+        // Roles are per channel
+        assert_eq!(
+            role_of(&db, "whatsapp", "42", Some("family")).unwrap(),
+            Role::Member
+        );
+
+        set_role(&db, "telegram", "42", None, Role::Owner, "cli").unwrap();
+        assert_eq!(
+            role_of(&db, "telegram", "42", Some("family")).unwrap(),
+            Role::Owner
+        );
+        assert!(is_admin(&db, "telegram", "42").unwrap());
+
+        let roles = list_roles(&db).unwrap();
+        assert_eq!(roles.len(), 2);
+        assert_eq!(roles[0].role, Role::Owner);
+        assert_eq!(roles[1].group_folder.as_deref(), Some("family"));
+
+        assert!(remove_role(&db, "telegram", "42", None).unwrap());
+        assert!(!remove_role(&db, "telegram", "42", None).unwrap());
+        assert!(!is_admin(&db, "telegram", "42").unwrap());
+    }
+
+    #[test]
+    fn test_is_permitted() {
+        let (db, _dir) = test_database();
+        set_role(&db, "telegram", "7", Some("family"), Role::Admin, "cli").unwrap();
+
+        assert!(is_permitted(&db, "telegram", "7", Some("family"), Permission::Tasks).unwrap());
+        assert!(!is_permitted(&db, "telegram", "7", Some("work"), Permission::Tasks).unwrap());
+        // Group admins do not get functions affecting every group
+        assert!(!is_permitted(&db, "telegram", "7", Some("family"), Permission::Admin).unwrap());
+        assert!(!is_permitted(&db, "telegram", "8", Some("family"), Permission::Cancel).unwrap());
+
+        let settings = PermissionSettings {
+            cancel: Some(Role::Member),
+            ..Default::default()
+        };
+        assert_eq!(settings.required_role(Permission::Cancel), Role::Member);
+        assert_eq!(settings.required_role(Permission::Tasks), Role::Admin);
+    }
+}
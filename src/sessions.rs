@@ -0,0 +1,182 @@
+//! Agent Sessions for NuClaw
+//!
+//! The agent reports the ID of its session with each answer. It is stored
+//! per tenant and chat in the `sessions` table, and the chat's next run
+//! resumes it, so the agent keeps its memory of the conversation across
+//! messages. A session unused for `SESSION_IDLE_HOURS` expires; the chat's
+//! next run then starts a new one.
+
+use crate::db::Database;
+use crate::error::Result;
+use crate::types::ContainerOutput;
+use serde::Serialize;
+
+/// Default idle time after which a session expires: one day
+const DEFAULT_SESSION_IDLE_HOURS: u64 = 24;
+
+/// The agent session a chat resumes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Session {
+    pub chat_jid: String,
+    pub session_id: String,
+    pub created_at: String,
+    pub last_used_at: String,
+    pub expires_at: String,
+}
+
+/// Hours a session lasts without use (`SESSION_IDLE_HOURS`); 0 starts a new
+/// session for every run
+pub fn session_idle_hours() -> u64 {
+    std::env::var("SESSION_IDLE_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SESSION_IDLE_HOURS)
+}
+
+/// The chat's session, unless it has expired
+pub fn current_session(db: &Database, chat_jid: &str) -> Result<Option<Session>> {
+    db.read_connection()?.query_opt(
+        "SELECT chat_jid, session_id, created_at, last_used_at, expires_at FROM sessions
+         WHERE tenant = ? AND chat_jid = ? AND expires_at > ?",
+        [db.tenant(), chat_jid, &chrono::Utc::now().to_rfc3339()],
+        |row| {
+            Ok(Session {
+                chat_jid: row.get(0)?,
+                session_id: row.get(1)?,
+                created_at: row.get(2)?,
+                last_used_at: row.get(3)?,
+                expires_at: row.get(4)?,
+            })
+        },
+    )
+}
+
+/// Session ID the chat's next run resumes; a failed lookup starts a new
+/// session
+pub fn resume_session_id(db: &Database, chat_jid: &str) -> Option<String> {
+    if session_idle_hours() == 0 {
+        return None;
+    }
+    match current_session(db, chat_jid) {
+        Ok(session) => session.map(|s| s.session_id),
+        Err(e) => {
+            tracing::warn!("Failed to load the session of {}: {}", chat_jid, e);
+            None
+        }
+    }
+}
+
+/// Make `session_id` the chat's session, used now
+pub fn save_session(db: &Database, chat_jid: &str, session_id: &str) -> Result<()> {
+    let now = chrono::Utc::now();
+    let idle = chrono::Duration::try_hours(session_idle_hours().min(i64::MAX as u64) as i64)
+        .unwrap_or(chrono::Duration::MAX);
+    let expires_at = now.checked_add_signed(idle).unwrap_or(now);
+    let now = now.to_rfc3339();
+    db.get_connection()?.execute(
+        "INSERT INTO sessions (tenant, chat_jid, session_id, created_at, last_used_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?4, ?5)
+         ON CONFLICT (tenant, chat_jid) DO UPDATE SET
+            session_id = excluded.session_id,
+            created_at = CASE WHEN sessions.session_id = excluded.session_id
+                              THEN sessions.created_at
+                              ELSE excluded.created_at END,
+            last_used_at = excluded.last_used_at,
+            expires_at = excluded.expires_at",
+        [
+            db.tenant(),
+            chat_jid,
+            session_id,
+            &now,
+            &expires_at.to_rfc3339(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Store the session a run ended in: the one the agent reported, or else
+/// the one it resumed
+pub fn record_run_session(
+    db: &Database,
+    chat_jid: &str,
+    resumed: Option<&str>,
+    output: &ContainerOutput,
+) -> Result<()> {
+    match output.new_session_id.as_deref().or(resumed) {
+        Some(session_id) if session_idle_hours() > 0 => save_session(db, chat_jid, session_id),
+        _ => Ok(()),
+    }
+}
+
+/// Forget the chat's session; `false` if it had none
+pub fn clear_session(db: &Database, chat_jid: &str) -> Result<bool> {
+    let deleted = db.get_connection()?.execute(
+        "DELETE FROM sessions WHERE tenant = ? AND chat_jid = ?",
+        [db.tenant(), chat_jid],
+    )?;
+    Ok(deleted > 0)
+}
+
+/// Delete expired sessions; returns how many
+pub fn prune_expired_sessions(db: &Database) -> Result<usize> {
+    db.get_connection()?.execute(
+        "DELETE FROM sessions WHERE tenant = ? AND expires_at <= ?",
+        [db.tenant(), &chrono::Utc::now().to_rfc3339()],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    fn output(new_session_id: Option<&str>) -> ContainerOutput {
+        ContainerOutput {
+            status: "success".to_string(),
+            result: Some("done".to_string()),
+            new_session_id: new_session_id.map(str::to_string),
+            usage: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_sessions() {
+        let (db, _dir) = test_database();
+        assert_eq!(resume_session_id(&db, "chat"), None);
+
+        record_run_session(&db, "chat", None, &output(None)).unwrap();
+        assert_eq!(resume_session_id(&db, "chat"), None);
+
+        record_run_session(&db, "chat", None, &output(Some("sess_1"))).unwrap();
+        let first = current_session(&db, "chat").unwrap().unwrap();
+        assert_eq!(first.session_id, "sess_1");
+        assert!(first.expires_at > first.last_used_at);
+
+        // Resuming keeps the session and its creation time
+        record_run_session(&db, "chat", Some("sess_1"), &output(None)).unwrap();
+        let resumed = current_session(&db, "chat").unwrap().unwrap();
+        assert_eq!(resumed.created_at, first.created_at);
+        record_run_session(&db, "chat", Some("sess_1"), &output(Some("sess_2"))).unwrap();
+        assert_eq!(resume_session_id(&db, "chat").as_deref(), Some("sess_2"));
+        assert_eq!(resume_session_id(&db, "other"), None);
+
+        assert!(clear_session(&db, "chat").unwrap());
+        assert!(!clear_session(&db, "chat").unwrap());
+    }
+
+    #[test]
+    fn test_expired_sessions_are_not_resumed() {
+        let (db, _dir) = test_database();
+        save_session(&db, "chat", "sess_1").unwrap();
+        db.get_connection()
+            .unwrap()
+            .execute(
+                "UPDATE sessions SET expires_at = '2026-01-01T00:00:00+00:00'",
+                (),
+            )
+            .unwrap();
+        assert_eq!(current_session(&db, "chat").unwrap(), None);
+        assert_eq!(prune_expired_sessions(&db).unwrap(), 1);
+    }
+}
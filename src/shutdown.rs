@@ -0,0 +1,220 @@
+//! Graceful Shutdown for NuClaw
+//!
+//! On SIGINT or SIGTERM, `nuclaw serve` stops in stages instead of dying
+//! in the middle of a run:
+//!
+//! 1. The webhook listeners and polling loops stop taking updates, the
+//!    scheduler stops starting tasks, and queued agent runs are held
+//!    back; their messages stay pending and are replayed at the next start
+//! 2. Agent runs in progress get `SHUTDOWN_GRACE_PERIOD` seconds (30 by
+//!    default) to finish; containers still running then are cancelled
+//! 3. Queued replies are delivered, for what is left of the grace period
+//!    but at least a few seconds; undelivered ones are sent after restart
+//! 4. The scheduler lease is released and the database checkpointed
+//!
+//! A second signal skips what is left.
+
+use crate::container_runner::cancel_all;
+use crate::db::{blocking, Database};
+use crate::outbox::count_due;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Default time agent runs get to finish after a shutdown signal
+const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS: u64 = 30;
+/// Time cancelled runs get to wind down and queue their replies
+const CANCEL_WAIT: Duration = Duration::from_secs(10);
+/// Time left for delivering queued replies, however long the drain took
+const MIN_FLUSH_TIME: Duration = Duration::from_secs(5);
+/// Interval between checks of the outbound queues while flushing
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+fn requested_flag() -> &'static watch::Sender<bool> {
+    static REQUESTED: OnceLock<watch::Sender<bool>> = OnceLock::new();
+    REQUESTED.get_or_init(|| watch::channel(false).0)
+}
+
+/// Start shutting down: listeners, pollers, and the scheduler stop
+pub fn request() {
+    requested_flag().send_replace(true);
+}
+
+/// Whether shutting down has started
+pub fn is_requested() -> bool {
+    *requested_flag().borrow()
+}
+
+/// Wait until shutting down starts
+pub async fn requested() {
+    let mut requested = requested_flag().subscribe();
+    let _ = requested.wait_for(|requested| *requested).await;
+}
+
+/// Wait for SIGINT or SIGTERM, returning the signal's name
+pub async fn signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = terminate.recv() => "SIGTERM",
+            },
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
+    }
+}
+
+/// Time agent runs get to finish, from `SHUTDOWN_GRACE_PERIOD` in seconds
+pub fn grace_period() -> Duration {
+    Duration::from_secs(
+        std::env::var("SHUTDOWN_GRACE_PERIOD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS),
+    )
+}
+
+/// Counts the agent runs in progress, so shutting down can wait for them
+#[derive(Clone)]
+pub struct RunTracker {
+    running: Arc<watch::Sender<usize>>,
+}
+
+impl Default for RunTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunTracker {
+    pub fn new() -> Self {
+        Self {
+            running: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Count a run until the returned guard is dropped
+    pub fn start(&self) -> TrackedRun {
+        self.running.send_modify(|running| *running += 1);
+        TrackedRun {
+            tracker: self.clone(),
+        }
+    }
+
+    /// Runs in progress
+    pub fn running(&self) -> usize {
+        *self.running.borrow()
+    }
+
+    /// Wait up to `timeout` for the runs to finish; `false` if some are
+    /// still going
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        let mut running = self.running.subscribe();
+        let idle = async move { running.wait_for(|running| *running == 0).await.is_ok() };
+        tokio::time::timeout(timeout, idle).await.unwrap_or(false)
+    }
+}
+
+/// A counted agent run; stops counting when dropped, including on panic
+pub struct TrackedRun {
+    tracker: RunTracker,
+}
+
+impl Drop for TrackedRun {
+    fn drop(&mut self) {
+        self.tracker
+            .running
+            .send_modify(|running| *running = running.saturating_sub(1));
+    }
+}
+
+/// The process-wide tracker of agent runs, for channels and the scheduler
+pub fn agent_runs() -> &'static RunTracker {
+    static RUNS: OnceLock<RunTracker> = OnceLock::new();
+    RUNS.get_or_init(RunTracker::new)
+}
+
+/// Let the agent runs in progress finish within `grace`, cancelling the
+/// rest, then deliver the queued replies
+pub async fn drain(db: &Database, grace: Duration) {
+    let started = Instant::now();
+    let runs = agent_runs();
+    if runs.running() > 0 {
+        info!(
+            "Waiting up to {:?} for {} agent runs to finish",
+            grace,
+            runs.running()
+        );
+    }
+    if !runs.wait_idle(grace).await {
+        warn!(
+            "Grace period over; cancelled {} container runs",
+            cancel_all()
+        );
+        if !runs.wait_idle(CANCEL_WAIT).await {
+            warn!("{} agent runs did not stop", runs.running());
+        }
+    }
+    let flush_time = grace.saturating_sub(started.elapsed()).max(MIN_FLUSH_TIME);
+    flush_outboxes(db, flush_time).await;
+}
+
+/// Wait up to `timeout` for the outbox workers to deliver what is due
+async fn flush_outboxes(db: &Database, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let db = db.clone();
+        match blocking(move || count_due(&db)).await {
+            Ok(0) => {
+                info!("Outbound queues flushed");
+                return;
+            }
+            Ok(due) if Instant::now() >= deadline => {
+                warn!("{} queued replies will be sent after restart", due);
+                return;
+            }
+            Ok(_) => tokio::time::sleep(FLUSH_POLL_INTERVAL).await,
+            Err(e) => {
+                warn!("Failed to check the outbound queues: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_tracker() {
+        let tracker = RunTracker::new();
+        assert!(tracker.wait_idle(Duration::ZERO).await);
+
+        let first = tracker.start();
+        let second = tracker.start();
+        assert_eq!(tracker.running(), 2);
+        assert!(!tracker.wait_idle(Duration::from_millis(10)).await);
+
+        drop(first);
+        let finishing = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(second);
+        });
+        assert!(tracker.wait_idle(Duration::from_secs(5)).await);
+        assert_eq!(tracker.running(), 0);
+        finishing.await.unwrap();
+    }
+}
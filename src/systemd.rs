@@ -0,0 +1,171 @@
+//! systemd Integration for NuClaw
+//!
+//! Under a `Type=notify` unit, `nuclaw serve` reports its state to
+//! systemd through `$NOTIFY_SOCKET` (see `sd_notify(3)`):
+//!
+//! - `READY=1` once the channels are connected and the scheduler runs,
+//!   so units ordered after NuClaw start only then
+//! - `WATCHDOG=1` at half the unit's `WatchdogSec` from the main loop, so
+//!   systemd restarts a process that stopped responding
+//! - `STOPPING=1` when shutting down; pings go on while runs are drained
+//!
+//! Outside systemd, `NOTIFY_SOCKET` is unset and nothing is sent.
+
+use std::ffi::OsStr;
+use tokio::time::{Duration, Interval, MissedTickBehavior};
+use tracing::debug;
+
+/// Send a state change to systemd; `false` if not running under it or
+/// the message could not be sent
+pub fn notify(state: &str) -> bool {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return false;
+    };
+    match send(&socket, state) {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("Failed to notify systemd: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        // Abstract socket names start with '@'
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "abstract sockets need Linux",
+            ))
+        }
+        None => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &OsStr, _state: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "systemd notifications need a Unix socket",
+    ))
+}
+
+/// Tell systemd NuClaw is up
+pub fn ready() {
+    if notify("READY=1\nSTATUS=Serving") {
+        debug!("Notified systemd that NuClaw is ready");
+    }
+}
+
+/// Tell systemd NuClaw is shutting down
+pub fn stopping() {
+    notify("STOPPING=1\nSTATUS=Finishing agent runs and delivering replies");
+}
+
+/// Interval of watchdog pings: half of `WatchdogSec`, if the unit sets
+/// one for this process
+pub fn watchdog_interval() -> Option<Duration> {
+    watchdog_interval_from(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn watchdog_interval_from(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    let usec: u64 = usec?.trim().parse().ok().filter(|usec| *usec > 0)?;
+    // Set for another process, e.g. inherited from a parent
+    if pid.is_some_and(|pid| pid.trim().parse() != Ok(own_pid)) {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Timer of the watchdog pings; never fires without a watchdog
+pub struct Watchdog {
+    interval: Option<Interval>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        let interval = watchdog_interval().map(|period| {
+            debug!("Pinging the systemd watchdog every {:?}", period);
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        Self { interval }
+    }
+
+    /// Wait for the next ping to be due, and send it
+    pub async fn ping(&mut self) {
+        match &mut self.interval {
+            Some(interval) => {
+                interval.tick().await;
+                notify("WATCHDOG=1");
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(
+            watchdog_interval_from(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval_from(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval_from(Some("30000000"), Some("7"), 42),
+            None
+        );
+        assert_eq!(watchdog_interval_from(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval_from(Some("soon"), None, 42), None);
+        assert_eq!(watchdog_interval_from(None, None, 42), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        send(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        assert!(send(dir.path().join("missing.sock").as_os_str(), "READY=1").is_err());
+    }
+}
@@ -1,35 +1,275 @@
 //! Task Scheduler - Runs scheduled tasks in isolated containers
 //!
 //! Supports three schedule types:
-//! - `cron`: Cron expression (e.g., "0 9 * * *" for daily at 9am)
+//! - `cron`: Cron expression (e.g., "0 0 9 * * *" for daily at 9am), evaluated
+//!   in the `TZ` timezone
 //! - `interval`: Fixed interval in milliseconds (e.g., "3600000" for 1 hour)
 //! - `once`: Single execution at specific timestamp
 //!
 //! Features:
 //! - Persistent task storage in SQLite
+//! - Sleeps until the soonest task is due; creating or changing a task wakes
+//!   the scheduler early
 //! - Task run logging
-//! - Concurrent task execution
-//! - Graceful shutdown
-
+//! - Concurrent task execution; runs are started without waiting for
+//!   earlier ones to finish
+//! - Named locks that keep tasks from running at the same time, and a
+//!   per-task choice to skip or queue a run that falls due while the
+//!   previous one is still in flight
+//! - Task priorities: when more tasks are due than there are run slots,
+//!   higher-priority tasks start first
+//! - Per-task timeouts, bounded by `TASK_MAX_TIMEOUT`
+//! - Delivery of results and failures to the task's chat (per task `notify`)
+//! - Retries after container infrastructure failures
+//! - Retries of failed runs with exponential backoff; a recurring task that
+//!   keeps failing skips to its next regular run instead of being disabled
+//! - Per-task jitter, and spreading of tasks that fall due on the same tick
+//! - Run history queries, and pruning of runs older than the retention period
+//! - Periodic WAL checkpoints and incremental vacuum of the SQLite file while
+//!   no agent is running
+//! - Creating, listing, pausing, and deleting tasks (e.g. from chat)
+//! - No new runs while NuClaw is paused for maintenance
+//! - Leader election: of several instances sharing the database, only the
+//!   one holding the scheduler lease runs tasks
+//! - One scheduler per tenant, each running only its tenant's tasks; they
+//!   share the lease and maintenance mode of the process
+//! - Graceful shutdown: no new runs once NuClaw is shutting down, while
+//!   runs in progress get the grace period to finish (see `shutdown`)
+
+use crate::broadcast::{channel_for_jid, process_ipc_requests};
 use crate::config::timezone;
-use crate::container_runner::{log_container_output, run_container};
-use crate::db::Database;
+use crate::container_runner::{
+    cancel_session, log_container_output, run_container_with_retry, running_containers, RetryPolicy,
+};
+use crate::correlation::{correlation_span, new_correlation_id, with_ref};
+use crate::db::{blocking, Database};
 use crate::error::{NuClawError, Result};
-use crate::types::{ContainerInput, ContainerOutput, ScheduledTask};
+use crate::health;
+use crate::leader::{self, instance_id, lease_duration, renew_interval};
+use crate::log_retention::maintain_container_logs;
+use crate::maintenance::is_paused;
+use crate::memory::relevant_memories;
+use crate::outbox::Outbox;
+use crate::sessions::prune_expired_sessions;
+use crate::shutdown;
+use crate::tenants::DEFAULT_TENANT;
+use crate::types::{ContainerInput, ContainerOutput, ScheduledTask, TaskRunLog};
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use cron::Schedule;
+use rand::Rng;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
-use tokio::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use tokio::sync::{mpsc, Notify};
 use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::Instrument;
 
 /// Default poll interval: 60 seconds
 const DEFAULT_POLL_INTERVAL_SECS: u64 = 60;
+/// Shortest sleep between scheduler passes
+const MIN_WAKEUP_DELAY: Duration = Duration::from_secs(1);
 /// Max concurrent tasks
 const MAX_CONCURRENT_TASKS: usize = 4;
 /// Default task timeout: 10 minutes
 const DEFAULT_TASK_TIMEOUT_SECS: u64 = 600;
+/// Default upper bound for per-task timeouts: 6 hours
+const DEFAULT_TASK_MAX_TIMEOUT_SECS: u64 = 21_600;
+/// Time past a task's timeout before its run is abandoned; the container
+/// itself is stopped at the timeout
+const TASK_TIMEOUT_GRACE: Duration = Duration::from_secs(30);
+/// Default retries of a failed task run
+const DEFAULT_TASK_MAX_RETRIES: u32 = 3;
+/// Default delay before the first retry of a failed run: 1 minute
+const DEFAULT_TASK_RETRY_DELAY_SECS: u64 = 60;
+/// Default upper bound for the retry delay: 1 hour
+const DEFAULT_TASK_RETRY_MAX_DELAY_SECS: u64 = 3600;
+/// Default seconds between starting tasks due on the same tick
+const DEFAULT_TASK_SPREAD_SECS: u64 = 2;
+/// Default days task runs are kept
+const DEFAULT_TASK_RUN_RETENTION_DAYS: u64 = 90;
+/// Seconds between prunes of the run history, expired sessions, and
+/// container logs: 1 day
+const RUN_HISTORY_MAINTENANCE_INTERVAL_SECS: u64 = 86_400;
+/// Default seconds between database checkpoints: 1 hour
+const DEFAULT_DB_CHECKPOINT_INTERVAL_SECS: u64 = 3600;
+
+/// When a task's runs are reported to its chat (`scheduled_tasks.notify`)
+pub mod notify {
+    /// Results of successful runs, and runs that failed for good
+    pub const ALWAYS: &str = "always";
+    /// Like `always`, but a result is only sent when it differs from the
+    /// previous successful run's, e.g. for tasks watching a web page
+    pub const ON_CHANGE: &str = "on_change";
+    /// Only runs that failed for good
+    pub const ON_FAILURE: &str = "on_failure";
+    pub const NEVER: &str = "never";
+
+    /// Whether `mode` is one of the above
+    pub fn is_valid(mode: &str) -> bool {
+        [ALWAYS, ON_CHANGE, ON_FAILURE, NEVER].contains(&mode)
+    }
+}
+
+fn check_task_notify(mode: Option<&str>) -> Result<()> {
+    match mode {
+        Some(mode) if !notify::is_valid(mode) => Err(NuClawError::Validation {
+            message: format!(
+                "Invalid notify mode '{}'; use always, on_change, on_failure or never",
+                mode
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// What happens when a task falls due while its previous run is still in
+/// flight (`scheduled_tasks.overlap`)
+pub mod overlap {
+    /// Drop the missed occurrence; the task next runs at its first
+    /// occurrence after the run finishes
+    pub const SKIP: &str = "skip";
+    /// Run once more as soon as the run finishes
+    pub const QUEUE: &str = "queue";
+
+    /// Whether `mode` is one of the above
+    pub fn is_valid(mode: &str) -> bool {
+        [SKIP, QUEUE].contains(&mode)
+    }
+}
+
+fn check_task_overlap(mode: Option<&str>) -> Result<()> {
+    match mode {
+        Some(mode) if !overlap::is_valid(mode) => Err(NuClawError::Validation {
+            message: format!("Invalid overlap mode '{}'; use skip or queue", mode),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Task lock name, or `None` for a blank one
+fn normalize_lock(lock: Option<String>) -> Option<String> {
+    lock.map(|l| l.trim().to_string()).filter(|l| !l.is_empty())
+}
+
+/// A task run in progress in this process
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InFlight {
+    /// Lock the run holds
+    pub lock: Option<String>,
+    /// Whether the task fell due again and runs once more afterwards
+    pub queued: bool,
+    /// The task's first occurrence after the one running; reaching it
+    /// queues another run
+    pub next_occurrence: Option<String>,
+}
+
+/// Runs in progress by task ID
+fn in_flight() -> &'static Mutex<HashMap<String, InFlight>> {
+    static IN_FLIGHT: OnceLock<Mutex<HashMap<String, InFlight>>> = OnceLock::new();
+    IN_FLIGHT.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Whether a due task can start, given the runs in progress
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dispatch {
+    Start,
+    /// The task's previous run is still in flight
+    Overlapping,
+    /// Another run holds the task's lock
+    Locked(String),
+    /// `MAX_CONCURRENT_TASKS` runs are in flight
+    Full,
+}
+
+/// Decide whether `task` can start at `now`, and record the run it
+/// starts, or the run it queues with `overlap` set to `queue`
+///
+/// The task stays due until its run finishes and moves `next_run`, so it is
+/// seen on every pass while it runs; only a pass after `next_occurrence`
+/// queues another run.
+pub fn claim_run(
+    runs: &mut HashMap<String, InFlight>,
+    task: &ScheduledTask,
+    next_occurrence: Option<String>,
+    now: DateTime<Utc>,
+) -> Dispatch {
+    let decision = dispatch(runs, task);
+    match &decision {
+        Dispatch::Start => {
+            runs.insert(
+                task.id.clone(),
+                InFlight {
+                    lock: task.lock.clone(),
+                    queued: false,
+                    next_occurrence,
+                },
+            );
+        }
+        Dispatch::Overlapping if task.overlap == overlap::QUEUE => {
+            if let Some(run) = runs.get_mut(&task.id) {
+                run.queued |= run
+                    .next_occurrence
+                    .as_deref()
+                    .and_then(|next| DateTime::parse_from_rfc3339(next).ok())
+                    .is_some_and(|next| next <= now);
+            }
+        }
+        _ => {}
+    }
+    decision
+}
+
+/// Decide whether `task` can start now
+pub fn dispatch(runs: &HashMap<String, InFlight>, task: &ScheduledTask) -> Dispatch {
+    if runs.contains_key(&task.id) {
+        return Dispatch::Overlapping;
+    }
+    if let Some(lock) = &task.lock {
+        if runs.values().any(|run| run.lock.as_ref() == Some(lock)) {
+            return Dispatch::Locked(lock.clone());
+        }
+    }
+    if runs.len() >= MAX_CONCURRENT_TASKS {
+        return Dispatch::Full;
+    }
+    Dispatch::Start
+}
+
+/// Upper bound for per-task timeouts from environment or default
+pub fn task_max_timeout() -> Duration {
+    Duration::from_secs(
+        std::env::var("TASK_MAX_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TASK_MAX_TIMEOUT_SECS),
+    )
+}
+
+/// Time limit of a task's runs: its own `timeout_secs`, or `default`,
+/// capped at `TASK_MAX_TIMEOUT`
+pub fn effective_task_timeout(task: &ScheduledTask, default: Duration) -> Duration {
+    task.timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(default)
+        .min(task_max_timeout())
+}
+
+fn check_task_timeout(timeout_secs: Option<u64>) -> Result<()> {
+    let max = task_max_timeout().as_secs();
+    match timeout_secs {
+        Some(secs) if secs == 0 || secs > max => Err(NuClawError::Validation {
+            message: format!("Task timeout must be between 1 and {} seconds", max),
+        }),
+        _ => Ok(()),
+    }
+}
 
-/// Get poll interval from environment or default
+/// Longest the scheduler sleeps between passes, from environment or default
+///
+/// Tasks added by another NuClaw process are picked up within this time.
 pub fn poll_interval() -> Duration {
     let interval_secs = std::env::var("SCHEDULER_POLL_INTERVAL")
         .ok()
@@ -47,21 +287,258 @@ pub fn task_timeout() -> Duration {
     Duration::from_secs(timeout_secs)
 }
 
+/// Wakeups of the schedulers by tenant
+fn scheduler_wakeups() -> &'static Mutex<HashMap<String, Arc<Notify>>> {
+    static WAKEUPS: OnceLock<Mutex<HashMap<String, Arc<Notify>>>> = OnceLock::new();
+    WAKEUPS.get_or_init(Mutex::default)
+}
+
+fn scheduler_wakeup(tenant: &str) -> Arc<Notify> {
+    scheduler_wakeups()
+        .lock()
+        .unwrap()
+        .entry(tenant.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Make a tenant's scheduler re-read its tasks now, e.g. after one was
+/// added
+pub fn wake_scheduler(tenant: &str) {
+    scheduler_wakeup(tenant).notify_one();
+}
+
+/// Wake the schedulers of all tenants, e.g. after maintenance ends
+pub fn wake_all_schedulers() {
+    for wakeup in scheduler_wakeups().lock().unwrap().values() {
+        wakeup.notify_one();
+    }
+}
+
+/// When the soonest active task is due; tasks without `next_run` are due now
+///
+/// Tasks in `exclude` (e.g. those with a run in flight) are not waited for.
+pub fn next_due_time(db: &Database, exclude: &HashSet<String>) -> Result<Option<DateTime<Utc>>> {
+    for (id, next_run) in db.tasks().next_runs()? {
+        if exclude.contains(&id) {
+            continue;
+        }
+        return Ok(match next_run {
+            None => Some(Utc::now()),
+            Some(at) => DateTime::parse_from_rfc3339(&at)
+                .ok()
+                .map(|at| at.with_timezone(&Utc)),
+        });
+    }
+    Ok(None)
+}
+
+/// How long to sleep until `next` is due, between `MIN_WAKEUP_DELAY` and `max`
+pub fn wakeup_delay(next: Option<DateTime<Utc>>, now: DateTime<Utc>, max: Duration) -> Duration {
+    match next {
+        None => max,
+        Some(at) => (at - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+            .min(max)
+            .max(MIN_WAKEUP_DELAY),
+    }
+}
+
+/// Delay between starting tasks due on the same tick (`TASK_SPREAD`)
+pub fn task_spread() -> Duration {
+    Duration::from_secs(
+        std::env::var("TASK_SPREAD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TASK_SPREAD_SECS),
+    )
+}
+
+/// Days task runs are kept (`TASK_RUN_RETENTION_DAYS`); 0 keeps them forever
+pub fn task_run_retention_days() -> u64 {
+    std::env::var("TASK_RUN_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TASK_RUN_RETENTION_DAYS)
+}
+
+/// Time between checkpoints of the database (`DB_CHECKPOINT_INTERVAL`, in
+/// seconds); zero disables them
+pub fn db_checkpoint_interval() -> Duration {
+    Duration::from_secs(
+        std::env::var("DB_CHECKPOINT_INTERVAL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_DB_CHECKPOINT_INTERVAL_SECS),
+    )
+}
+
+/// Default jitter of task runs in seconds (`TASK_JITTER`)
+pub fn task_jitter() -> u64 {
+    std::env::var("TASK_JITTER")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Move `at` by a random offset within ±`jitter_secs`, but not before `not_before`
+pub fn apply_jitter(
+    at: DateTime<Utc>,
+    jitter_secs: u64,
+    not_before: DateTime<Utc>,
+) -> DateTime<Utc> {
+    if jitter_secs == 0 {
+        return at;
+    }
+    let jitter = jitter_secs.min(i64::MAX as u64) as i64;
+    let offset = rand::thread_rng().gen_range(-jitter..=jitter);
+    (at + chrono::Duration::seconds(offset)).max(not_before)
+}
+
+/// Retries of a failed task run from environment or default
+pub fn task_max_retries() -> u32 {
+    std::env::var("TASK_MAX_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TASK_MAX_RETRIES)
+}
+
+/// Delay before retry number `attempt` (from 1) of a failed run
+///
+/// Starts at `TASK_RETRY_DELAY` seconds and doubles with every attempt, up
+/// to `TASK_RETRY_MAX_DELAY` seconds.
+pub fn task_retry_delay(attempt: u32) -> chrono::Duration {
+    let env_secs = |name: &str, default: u64| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    let secs = backoff_secs(
+        attempt,
+        env_secs("TASK_RETRY_DELAY", DEFAULT_TASK_RETRY_DELAY_SECS),
+        env_secs("TASK_RETRY_MAX_DELAY", DEFAULT_TASK_RETRY_MAX_DELAY_SECS),
+    );
+    chrono::Duration::seconds(secs.min(i64::MAX as u64) as i64)
+}
+
+fn backoff_secs(attempt: u32, base_secs: u64, max_secs: u64) -> u64 {
+    let factor = 1u64
+        .checked_shl(attempt.saturating_sub(1))
+        .unwrap_or(u64::MAX);
+    base_secs.saturating_mul(factor).min(max_secs)
+}
+
+/// What happens to a task after a failed run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureOutcome {
+    /// Run again at `next_run`
+    Retry { attempt: u32, next_run: String },
+    /// Give up on this run; the task continues with its next regular run
+    Skip { next_run: String },
+    /// The task cannot run again and is marked failed
+    Dead,
+}
+
+/// Decide how to continue after `failures` consecutive failed runs
+///
+/// `regular_next` is the task's next regular run (`None` for one-off
+/// tasks). A retry that would land after it is skipped in its favor.
+pub fn failure_outcome(
+    failures: u32,
+    max_retries: u32,
+    retry_at: DateTime<Utc>,
+    regular_next: Option<String>,
+) -> FailureOutcome {
+    let retry_at = retry_at.to_rfc3339();
+    match regular_next {
+        Some(next_run) if failures > max_retries || next_run <= retry_at => {
+            FailureOutcome::Skip { next_run }
+        }
+        None if failures > max_retries => FailureOutcome::Dead,
+        _ => FailureOutcome::Retry {
+            attempt: failures,
+            next_run: retry_at,
+        },
+    }
+}
+
 /// Task scheduler state
 #[derive(Clone)]
 pub struct TaskScheduler {
     db: Database,
     poll_interval: Duration,
     task_timeout: Duration,
+    task_spread: Duration,
+    /// Due tasks the last pass could not start; a finishing run wakes the
+    /// scheduler to try them again
+    waiting: HashSet<String>,
+    /// Whether this instance held the scheduler lease after the last pass
+    leader: bool,
+    checkpoint_interval: Duration,
+    last_checkpoint: Instant,
 }
 
 impl TaskScheduler {
-    /// Create a new task scheduler
+    /// Create a new task scheduler for the tenant of `db`
+    ///
+    /// The database is checkpointed by the default tenant's scheduler only.
     pub fn new(db: Database) -> Self {
+        let checkpoint_interval = if db.tenant() == DEFAULT_TENANT {
+            db_checkpoint_interval()
+        } else {
+            Duration::ZERO
+        };
         Self {
             db,
             poll_interval: poll_interval(),
             task_timeout: task_timeout(),
+            task_spread: task_spread(),
+            waiting: HashSet::new(),
+            leader: false,
+            checkpoint_interval,
+            last_checkpoint: Instant::now(),
+        }
+    }
+
+    /// Take or renew the scheduler lease, logging changes of leadership
+    ///
+    /// A failed lookup counts as not leading, so two instances never both
+    /// run tasks.
+    async fn hold_lease(&mut self) -> bool {
+        let leader = match self
+            .db
+            .call(|db| leader::try_acquire(db, instance_id(), lease_duration()))
+            .await
+        {
+            Ok(leader) => leader,
+            Err(e) => {
+                tracing::warn!("Failed to renew the scheduler lease: {}", e);
+                false
+            }
+        };
+        if leader != self.leader {
+            if leader {
+                tracing::info!("Instance {} is now the scheduler leader", instance_id());
+            } else {
+                tracing::warn!(
+                    "Instance {} lost the scheduler lease; standing by",
+                    instance_id()
+                );
+            }
+            self.leader = leader;
+        }
+        leader
+    }
+
+    /// Give up the scheduler lease, so a standby instance takes over at once
+    async fn release_lease(&mut self) {
+        if self.leader {
+            if let Err(e) = self.db.call(|db| leader::release(db, instance_id())).await {
+                tracing::warn!("Failed to release the scheduler lease: {}", e);
+            }
+            self.leader = false;
         }
     }
 
@@ -69,37 +546,146 @@ impl TaskScheduler {
     pub async fn run(&mut self) -> Result<()> {
         let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
 
-        let mut interval = interval(self.poll_interval);
-        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut maintenance = interval(Duration::from_secs(RUN_HISTORY_MAINTENANCE_INTERVAL_SECS));
+        maintenance.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
+        let wakeup = scheduler_wakeup(self.db.tenant());
+        let component = health::component("scheduler", self.db.tenant());
+        // Passes are at most this far apart, so a loop stuck for a few of
+        // them shows in `/health`
+        let stale_after = self.poll_interval.min(renew_interval()) * 3;
         tracing::info!(
-            "Task scheduler started with poll interval: {:?}",
+            "Task scheduler of tenant {} started; sleeping at most {:?} between passes",
+            self.db.tenant(),
             self.poll_interval
         );
 
-        loop {
+        while !shutdown::is_requested() {
+            health::heartbeat(&component, stale_after);
+            let leading = self.hold_lease().await;
+            let paused = self.db.call(|db| Ok(is_paused(db))).await.unwrap_or(false);
+            if !leading {
+                tracing::debug!("Another instance holds the scheduler lease");
+            } else if paused {
+                tracing::debug!("Paused for maintenance; not starting tasks");
+            } else if let Err(e) = self.poll_and_execute_tasks().await {
+                tracing::error!("Error executing tasks: {}", e);
+            }
+            self.checkpoint_if_idle().await;
+
+            let mut exclude = self.waiting.clone();
+            exclude.extend(in_flight().lock().unwrap().keys().cloned());
+            let next = self
+                .db
+                .call(move |db| next_due_time(db, &exclude))
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Failed to find the next due task: {}", e);
+                    None
+                });
+            // Resuming wakes the scheduler. The lease is renewed, or checked
+            // for expiry, well before it runs out.
+            let delay = if paused || !leading {
+                self.poll_interval
+            } else {
+                wakeup_delay(next, Utc::now(), self.poll_interval)
+            }
+            .min(renew_interval());
+            tracing::debug!("Scheduler sleeping for {:?}", delay);
+
             tokio::select! {
-                _ = interval.tick() => {
-                    if let Err(e) = self.poll_and_execute_tasks().await {
-                        tracing::error!("Error executing tasks: {}", e);
+                _ = tokio::time::sleep(delay) => {}
+                _ = wakeup.notified() => {
+                    tracing::debug!("Scheduler woken by a task change");
+                }
+                _ = maintenance.tick() => {
+                    if let Err(e) = self.prune_run_history().await {
+                        tracing::warn!("Failed to prune task run history: {}", e);
+                    }
+                    if let Err(e) = self.db.call(prune_expired_sessions).await {
+                        tracing::warn!("Failed to prune expired sessions: {}", e);
+                    }
+                    if let Err(e) = blocking(|| Ok(maintain_container_logs())).await {
+                        tracing::warn!("Failed to clean up container logs: {}", e);
                     }
                 }
                 _ = shutdown_rx.recv() => {
                     tracing::info!("Task scheduler shutting down");
                     break;
                 }
+                _ = shutdown::requested() => {
+                    tracing::info!("Task scheduler of tenant {} stopped", self.db.tenant());
+                    break;
+                }
                 _ = shutdown_tx.closed() => {
                     break;
                 }
             }
         }
 
+        self.release_lease().await;
         Ok(())
     }
 
-    /// Poll for due tasks and execute them
+    /// Checkpoint the database once the interval has passed and no agent is
+    /// running, so the write-ahead log does not grow on a busy instance
+    ///
+    /// A checkpoint that falls due during a run waits for the next pass
+    /// with nothing running.
+    async fn checkpoint_if_idle(&mut self) {
+        if self.checkpoint_interval.is_zero()
+            || self.last_checkpoint.elapsed() < self.checkpoint_interval
+        {
+            return;
+        }
+        if !running_containers().is_empty() || !in_flight().lock().unwrap().is_empty() {
+            tracing::debug!("Database checkpoint deferred until no agent is running");
+            return;
+        }
+        self.last_checkpoint = Instant::now();
+        match self.db.call(|db| db.checkpoint()).await {
+            Ok(Some(checkpoint)) if checkpoint.busy => tracing::info!(
+                "Database checkpoint incomplete: a reader is still using the WAL ({} bytes)",
+                checkpoint.wal_bytes
+            ),
+            Ok(Some(checkpoint)) => tracing::debug!(
+                "Checkpointed {} bytes of WAL and freed {} pages",
+                checkpoint.wal_bytes,
+                checkpoint.freed_pages
+            ),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to checkpoint the database: {}", e),
+        }
+    }
+
+    /// Delete runs older than the retention period and compact the database
+    async fn prune_run_history(&self) -> Result<()> {
+        let days = task_run_retention_days();
+        if days == 0 {
+            return Ok(());
+        }
+        let cutoff = Utc::now() - chrono::Duration::days(days.min(36_500) as i64);
+        self.db
+            .call(move |db| {
+                let pruned = db.tasks().prune_runs(&cutoff.to_rfc3339())?;
+                if pruned > 0 {
+                    tracing::info!("Pruned {} task runs older than {} days", pruned, days);
+                    db.vacuum()?;
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    /// Start the due tasks that can run now
+    ///
+    /// Runs are not waited for; each one wakes the scheduler when it
+    /// finishes, so tasks held back by a lock or the concurrency limit are
+    /// tried again then.
     async fn poll_and_execute_tasks(&mut self) -> Result<()> {
-        let now = Utc::now().to_rfc3339();
+        let started_at = Utc::now();
+        let now = started_at.to_rfc3339();
+        self.waiting.clear();
 
         // Load active tasks that are due
         let tasks = self.load_due_tasks(&now).await?;
@@ -109,41 +695,92 @@ impl TaskScheduler {
             return Ok(());
         }
 
-        tracing::info!("Found {} tasks due for execution", tasks.len());
+        tracing::debug!("Found {} tasks due for execution", tasks.len());
 
-        // Execute tasks concurrently with limit
-        let mut handles = Vec::new();
+        let mut started = 0;
         for task in tasks {
-            // Check if we've reached max concurrent tasks
-            while handles.len() >= MAX_CONCURRENT_TASKS {
-                // Wait for at least one to complete
-                let _ = tokio::join!(handles.remove(0));
+            let next_occurrence = self.calculate_next_run(&task);
+            let decision = claim_run(
+                &mut in_flight().lock().unwrap(),
+                &task,
+                next_occurrence,
+                started_at,
+            );
+            match decision {
+                Dispatch::Start => {}
+                Dispatch::Overlapping => {
+                    tracing::debug!("Task {} is still running", task.id);
+                    self.waiting.insert(task.id);
+                    continue;
+                }
+                Dispatch::Locked(lock) => {
+                    tracing::debug!("Task {} is waiting for lock '{}'", task.id, lock);
+                    self.waiting.insert(task.id);
+                    continue;
+                }
+                Dispatch::Full => {
+                    self.waiting.insert(task.id);
+                    continue;
+                }
             }
 
+            // Stagger tasks that fell due together instead of starting them at once
+            if started > 0 && !self.task_spread.is_zero() {
+                tokio::time::sleep(self.task_spread).await;
+            }
+            started += 1;
+
             let mut scheduler = TaskScheduler::new(self.db.clone());
-            let handle = tokio::spawn(async move {
-                let result = scheduler.execute_single_task(&task).await;
-                (task.id.clone(), result)
-            });
-            handles.push(handle);
+            let run = shutdown::agent_runs().start();
+            let correlation_id = new_correlation_id();
+            let span = correlation_span(&correlation_id);
+            tokio::spawn(
+                async move {
+                    let _run = run;
+                    if let Err(e) = scheduler.execute_single_task(&task, &correlation_id).await {
+                        tracing::error!("Task {} failed: {}", task.id, e);
+                    }
+                    scheduler.finish_run(&task).await;
+                }
+                .instrument(span),
+            );
         }
 
-        // Wait for remaining tasks
-        for handle in handles {
-            let (task_id, result) = handle.await.map_err(|e| NuClawError::Scheduler {
-                message: format!("Task execution panic: {}", e),
-            })?;
+        Ok(())
+    }
 
-            if let Err(e) = &result {
-                tracing::error!("Task {} failed: {}", task_id, e);
+    /// Release a finished run's slot and lock, and run the task again if it
+    /// reached a new occurrence in the meantime with `overlap` set to `queue`
+    async fn finish_run(&self, task: &ScheduledTask) {
+        let queued = in_flight()
+            .lock()
+            .unwrap()
+            .remove(&task.id)
+            .is_some_and(|run| run.queued);
+        if queued {
+            match self.load_task(&task.id).await {
+                Ok(Some(current)) if current.status == "active" => {
+                    tracing::info!("Running queued occurrence of task {}", task.id);
+                    if let Err(e) = self
+                        .update_next_run(&task.id, &Utc::now().to_rfc3339())
+                        .await
+                    {
+                        tracing::error!("Failed to queue task {}: {}", task.id, e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Failed to load task {}: {}", task.id, e),
             }
         }
-
-        Ok(())
+        wake_scheduler(self.db.tenant());
     }
 
-    /// Execute a single task
-    async fn execute_single_task(&mut self, task: &ScheduledTask) -> Result<()> {
+    /// Execute a single task as the run `correlation_id`
+    async fn execute_single_task(
+        &mut self,
+        task: &ScheduledTask,
+        correlation_id: &str,
+    ) -> Result<()> {
         tracing::info!("Executing task: {} (group: {})", task.id, task.group_folder);
 
         let start_time = chrono::Utc::now();
@@ -162,31 +799,112 @@ impl TaskScheduler {
         }
 
         // Create container input
-        let session_id = format!("scheduled_{}", task.id);
+        let session_id = task_session_id(&task.id);
+        let run_timeout = effective_task_timeout(&current_task, self.task_timeout);
+        let memories = relevant_memories(&self.db, &task.group_folder, &task.prompt).await;
         let input = ContainerInput {
             prompt: task.prompt.clone(),
             session_id: Some(session_id.clone()),
+            resume_session: false,
             group_folder: task.group_folder.clone(),
             chat_jid: task.chat_jid.clone(),
             is_main: false,
             is_scheduled_task: true,
+            context: vec![],
+            reply_to_id: None,
+            quoted_content: None,
+            memories,
+            timeout: Some(run_timeout),
+            sender: None,
+            correlation_id: Some(correlation_id.to_string()),
+            permissions: None,
         };
 
-        // Execute container with timeout
-        let result = tokio::time::timeout(self.task_timeout, run_container(input)).await;
+        // Execute container with timeout, retrying infrastructure failures
+        let mut retry_policy = RetryPolicy::for_channel("scheduler");
+        if let Some(retries) = current_task.container_retries {
+            retry_policy = retry_policy.with_retries(retries);
+        }
+        let result = tokio::time::timeout(
+            run_timeout + TASK_TIMEOUT_GRACE,
+            run_container_with_retry(&self.db, input, retry_policy, None),
+        )
+        .await;
+        let group_folder = task.group_folder.clone();
+        if let Err(e) = self
+            .db
+            .call(move |db| process_ipc_requests(db, &group_folder))
+            .await
+        {
+            tracing::warn!(
+                "Failed to process IPC requests for {}: {}",
+                task.group_folder,
+                e
+            );
+        }
 
         let end_time = chrono::Utc::now();
         let duration_ms = (end_time - start_time).num_milliseconds();
 
         // Process result and log
         match result {
+            Ok(Ok(output)) if output.status != "success" => {
+                // The agent failed, or the container hit the run's timeout
+                let status = if duration_ms >= run_timeout.as_millis() as i64 {
+                    "timeout"
+                } else {
+                    "error"
+                };
+                self.log_task_run(task, &output, duration_ms, status)
+                    .await?;
+                let _ = log_container_output(
+                    &task.group_folder,
+                    &session_id,
+                    Some(correlation_id),
+                    &output,
+                );
+                let error = output
+                    .error
+                    .as_deref()
+                    .unwrap_or("The agent reported an error");
+                self.handle_failed_run(&current_task, &with_ref(error, Some(correlation_id)))
+                    .await?;
+            }
             Ok(Ok(output)) => {
+                let previous = if current_task.notify == notify::ON_CHANGE {
+                    let task_id = task.id.clone();
+                    self.db
+                        .call(move |db| db.tasks().last_successful_result(&task_id))
+                        .await?
+                } else {
+                    None
+                };
+
                 // Log successful execution
                 self.log_task_run(task, &output, duration_ms, "success")
                     .await?;
 
                 // Log to file
-                let _ = log_container_output(&task.group_folder, &session_id, &output);
+                let _ = log_container_output(
+                    &task.group_folder,
+                    &session_id,
+                    Some(correlation_id),
+                    &output,
+                );
+
+                if current_task.consecutive_failures > 0 {
+                    self.set_consecutive_failures(&task.id, 0).await?;
+                }
+                if let Some(result) = output.result.as_deref().filter(|r| !r.trim().is_empty()) {
+                    let send = match current_task.notify.as_str() {
+                        notify::ALWAYS => true,
+                        notify::ON_CHANGE => result_changed(previous.as_deref(), result),
+                        _ => false,
+                    };
+                    if send {
+                        self.notify_chat(&current_task, result).await;
+                    }
+                }
 
                 // Calculate next run time
                 if task.schedule_type == "once" {
@@ -194,9 +912,24 @@ impl TaskScheduler {
                     self.mark_task_completed(&task.id).await?;
                 } else {
                     // Recurring task - calculate next run
-                    if let Some(next_run) = self.calculate_next_run(task) {
-                        self.update_next_run(&task.id, &next_run).await?;
-                    }
+                    self.schedule_next_run(task).await?;
+                }
+            }
+            Ok(Err(NuClawError::Cancelled { .. })) => {
+                tracing::info!("Run of task {} was cancelled", task.id);
+                let output = ContainerOutput {
+                    status: "cancelled".to_string(),
+                    result: None,
+                    new_session_id: None,
+                    usage: None,
+                    error: Some("Run cancelled".to_string()),
+                };
+                self.log_task_run(task, &output, duration_ms, "cancelled")
+                    .await?;
+                if task.schedule_type == "once" {
+                    self.mark_task_completed(&task.id).await?;
+                } else {
+                    self.schedule_next_run(task).await?;
                 }
             }
             Ok(Err(e)) => {
@@ -205,11 +938,16 @@ impl TaskScheduler {
                     status: "error".to_string(),
                     result: None,
                     new_session_id: None,
+                    usage: None,
                     error: Some(e.to_string()),
                 };
                 self.log_task_run(task, &output, duration_ms, "error")
                     .await?;
-                self.mark_task_failed(&task.id).await?;
+                self.handle_failed_run(
+                    &current_task,
+                    &with_ref(&e.to_string(), Some(correlation_id)),
+                )
+                .await?;
             }
             Err(_) => {
                 // Timeout
@@ -217,36 +955,152 @@ impl TaskScheduler {
                     status: "timeout".to_string(),
                     result: None,
                     new_session_id: None,
+                    usage: None,
                     error: Some("Task execution timed out".to_string()),
                 };
                 self.log_task_run(task, &output, duration_ms, "timeout")
                     .await?;
-                self.mark_task_failed(&task.id).await?;
+                self.handle_failed_run(
+                    &current_task,
+                    &with_ref("Task execution timed out", Some(correlation_id)),
+                )
+                .await?;
             }
         }
 
         Ok(())
     }
 
-    /// Calculate next run time for a task
-    pub fn calculate_next_run(&self, task: &ScheduledTask) -> Option<String> {
-        match task.schedule_type.as_str() {
-            "cron" => self.calculate_next_cron_run(task.schedule_value.clone()),
-            "interval" => self.calculate_next_interval_run(task.schedule_value.clone()),
-            "once" => None,
-            _ => None,
+    /// Set a recurring task's next regular run
+    ///
+    /// A task whose schedule yields no next run would otherwise stay due
+    /// forever, so it is marked failed.
+    async fn schedule_next_run(&self, task: &ScheduledTask) -> Result<()> {
+        match self.calculate_next_run(task) {
+            Some(next_run) => self.update_next_run(&task.id, &next_run).await,
+            None => {
+                tracing::error!(
+                    "Task {} has no next run for {} '{}'; marking it failed",
+                    task.id,
+                    task.schedule_type,
+                    task.schedule_value
+                );
+                self.mark_task_failed(&task.id).await
+            }
+        }
+    }
+
+    /// Schedule a retry of a failed run, skip to the next regular run, or
+    /// mark the task failed once it cannot run again
+    ///
+    /// Unless the task's `notify` is `never`, its chat is told when a run is
+    /// given up; retries are not reported.
+    async fn handle_failed_run(&self, task: &ScheduledTask, error: &str) -> Result<()> {
+        let failures = task.consecutive_failures + 1;
+        let max_retries = task.max_retries.unwrap_or_else(task_max_retries);
+        let outcome = failure_outcome(
+            failures,
+            max_retries,
+            Utc::now() + task_retry_delay(failures),
+            self.calculate_next_run(task),
+        );
+        match outcome {
+            FailureOutcome::Retry { attempt, next_run } => {
+                tracing::warn!(
+                    "Task {} failed; retry {} of {} at {}",
+                    task.id,
+                    attempt,
+                    max_retries,
+                    next_run
+                );
+                self.set_consecutive_failures(&task.id, failures).await?;
+                self.update_next_run(&task.id, &next_run).await
+            }
+            FailureOutcome::Skip { next_run } => {
+                tracing::warn!(
+                    "Task {} failed {} times in a row; skipping to its next run at {}",
+                    task.id,
+                    failures,
+                    next_run
+                );
+                self.set_consecutive_failures(&task.id, 0).await?;
+                self.update_next_run(&task.id, &next_run).await?;
+                if task.notify != notify::NEVER {
+                    self.notify_chat(
+                        task,
+                        &format!(
+                            "Scheduled task {} failed: {}\nIt will run again at {}.",
+                            task.id, error, next_run
+                        ),
+                    )
+                    .await;
+                }
+                Ok(())
+            }
+            FailureOutcome::Dead => {
+                tracing::error!(
+                    "Task {} failed {} times in a row; giving up",
+                    task.id,
+                    failures
+                );
+                self.mark_task_failed(&task.id).await?;
+                if task.notify != notify::NEVER {
+                    self.notify_chat(
+                        task,
+                        &format!(
+                            "Scheduled task {} failed: {}\nUse /task resume {} to run it again.",
+                            task.id, error, task.id
+                        ),
+                    )
+                    .await;
+                }
+                Ok(())
+            }
         }
     }
 
+    /// Queue a message to the task's chat on its channel's outbox
+    ///
+    /// Delivery problems are logged and do not affect the task.
+    async fn notify_chat(&self, task: &ScheduledTask, text: &str) {
+        let outbox = Outbox::new(self.db.clone(), channel_for_jid(&task.chat_jid));
+        let (chat_jid, text) = (task.chat_jid.clone(), text.to_string());
+        if let Err(e) = blocking(move || outbox.enqueue(&chat_jid, &text)).await {
+            tracing::error!("Failed to queue result of task {}: {}", task.id, e);
+        }
+    }
+
+    /// Calculate next run time for a task, including its jitter
+    pub fn calculate_next_run(&self, task: &ScheduledTask) -> Option<String> {
+        let jitter = task.jitter_secs.unwrap_or_else(task_jitter);
+        let now = Utc::now();
+        let next = match task.schedule_type.as_str() {
+            // Look past the jitter window, so a run that started early
+            // is not scheduled again for the same occurrence
+            "cron" => self.calculate_next_cron_run(
+                task.schedule_value.clone(),
+                now + chrono::Duration::seconds(jitter.min(i64::MAX as u64) as i64),
+            )?,
+            "interval" => {
+                let next = self.calculate_next_interval_run(task.schedule_value.clone())?;
+                DateTime::parse_from_rfc3339(&next)
+                    .ok()?
+                    .with_timezone(&Utc)
+            }
+            "once" => return None,
+            _ => return None,
+        };
+        Some(apply_jitter(next, jitter, now).to_rfc3339())
+    }
+
     /// Calculate next run time from cron expression
-    fn calculate_next_cron_run(&self, cron_expr: String) -> Option<String> {
-        let _tz = timezone();
+    fn calculate_next_cron_run(
+        &self,
+        cron_expr: String,
+        after: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
         match Schedule::from_str(&cron_expr) {
-            Ok(schedule) => {
-                // Get next run in the specified timezone
-                let next = schedule.after(&chrono::Utc::now()).next()?;
-                Some(next.to_rfc3339())
-            }
+            Ok(schedule) => next_cron_run(&schedule, after),
             Err(e) => {
                 tracing::error!("Invalid cron expression '{}': {}", cron_expr, e);
                 None
@@ -261,97 +1115,16 @@ impl TaskScheduler {
         Some(next_run.to_rfc3339())
     }
 
-    /// Load tasks that are due for execution
+    /// Load tasks that are due for execution, highest priority first
     async fn load_due_tasks(&self, now: &str) -> Result<Vec<ScheduledTask>> {
-        let conn = self
-            .db
-            .get_connection()
-            .map_err(|e| NuClawError::Database {
-                message: e.to_string(),
-            })?;
-
-        let mut stmt = conn
-            .prepare(
-                "SELECT id, group_folder, chat_jid, prompt, schedule_type, schedule_value,
-                    next_run, last_run, last_result, status, created_at, context_mode
-             FROM scheduled_tasks
-             WHERE status = 'active'
-               AND (next_run IS NULL OR next_run <= ?)
-             ORDER BY next_run ASC",
-            )
-            .map_err(|e| NuClawError::Database {
-                message: format!("Failed to prepare statement: {}", e),
-            })?;
-
-        let tasks: rusqlite::Result<Vec<ScheduledTask>> = stmt
-            .query_map([now], |row| {
-                Ok(ScheduledTask {
-                    id: row.get(0)?,
-                    group_folder: row.get(1)?,
-                    chat_jid: row.get(2)?,
-                    prompt: row.get(3)?,
-                    schedule_type: row.get(4)?,
-                    schedule_value: row.get(5)?,
-                    next_run: row.get(6)?,
-                    last_run: row.get(7)?,
-                    last_result: row.get(8)?,
-                    status: row.get(9)?,
-                    created_at: row.get(10)?,
-                    context_mode: row.get(11)?,
-                })
-            })?
-            .collect();
-
-        tasks.map_err(|e| NuClawError::Database {
-            message: format!("Failed to load tasks: {}", e),
-        })
+        let now = now.to_string();
+        self.db.call(move |db| db.tasks().due(&now)).await
     }
 
     /// Load a single task by ID
     async fn load_task(&self, task_id: &str) -> Result<Option<ScheduledTask>> {
-        let conn = self
-            .db
-            .get_connection()
-            .map_err(|e| NuClawError::Database {
-                message: e.to_string(),
-            })?;
-
-        let mut stmt = conn
-            .prepare(
-                "SELECT id, group_folder, chat_jid, prompt, schedule_type, schedule_value,
-                    next_run, last_run, last_result, status, created_at, context_mode
-             FROM scheduled_tasks WHERE id = ?",
-            )
-            .map_err(|e| NuClawError::Database {
-                message: format!("Failed to prepare statement: {}", e),
-            })?;
-
-        stmt.query_row([task_id], |row| {
-            Ok(ScheduledTask {
-                id: row.get(0)?,
-                group_folder: row.get(1)?,
-                chat_jid: row.get(2)?,
-                prompt: row.get(3)?,
-                schedule_type: row.get(4)?,
-                schedule_value: row.get(5)?,
-                next_run: row.get(6)?,
-                last_run: row.get(7)?,
-                last_result: row.get(8)?,
-                status: row.get(9)?,
-                created_at: row.get(10)?,
-                context_mode: row.get(11)?,
-            })
-        })
-        .map(Some)
-        .or_else(|e| {
-            if e == rusqlite::Error::QueryReturnedNoRows {
-                Ok(None)
-            } else {
-                Err(NuClawError::Database {
-                    message: format!("Failed to load task: {}", e),
-                })
-            }
-        })
+        let task_id = task_id.to_string();
+        self.db.call(move |db| db.tasks().get(&task_id)).await
     }
 
     /// Log a task run
@@ -362,108 +1135,63 @@ impl TaskScheduler {
         duration_ms: i64,
         run_status: &str,
     ) -> Result<()> {
-        let conn = self
-            .db
-            .get_connection()
-            .map_err(|e| NuClawError::Database {
-                message: e.to_string(),
-            })?;
-
-        let now = chrono::Utc::now().to_rfc3339();
-
-        conn.execute(
-            "INSERT INTO task_run_logs (task_id, run_at, duration_ms, status, result, error)
-             VALUES (?, ?, ?, ?, ?, ?)",
-            rusqlite::params![
-                task.id,
-                now,
-                duration_ms,
-                run_status,
-                output.result.clone().unwrap_or_default(),
-                output.error.clone().unwrap_or_default(),
-            ],
-        )
-        .map_err(|e| NuClawError::Database {
-            message: format!("Failed to log task run: {}", e),
-        })?;
-
-        // Update last_run and last_result
+        let run = TaskRunLog {
+            task_id: task.id.clone(),
+            run_at: Utc::now().to_rfc3339(),
+            duration_ms,
+            status: run_status.to_string(),
+            result: output.result.clone(),
+            error: output.error.clone(),
+        };
         let last_result = if output.status == "success" {
             output.result.clone()
         } else {
             output.error.clone()
         };
-
-        conn.execute(
-            "UPDATE scheduled_tasks SET last_run = ?, last_result = ? WHERE id = ?",
-            rusqlite::params![now, last_result, task.id],
-        )
-        .map_err(|e| NuClawError::Database {
-            message: format!("Failed to update task: {}", e),
-        })?;
-
-        Ok(())
+        self.db
+            .call(move |db| db.tasks().log_run(&run, last_result.as_deref()))
+            .await
     }
 
     /// Update next run time for a task
     async fn update_next_run(&self, task_id: &str, next_run: &str) -> Result<()> {
-        let conn = self
-            .db
-            .get_connection()
-            .map_err(|e| NuClawError::Database {
-                message: e.to_string(),
-            })?;
-
-        conn.execute(
-            "UPDATE scheduled_tasks SET next_run = ? WHERE id = ?",
-            [next_run, task_id],
-        )
-        .map_err(|e| NuClawError::Database {
-            message: format!("Failed to update next run: {}", e),
-        })?;
+        let (task_id, next_run) = (task_id.to_string(), next_run.to_string());
+        self.db
+            .call(move |db| db.tasks().set_next_run(&task_id, &next_run))
+            .await
+    }
 
-        Ok(())
+    /// Store the number of consecutive failed runs of a task
+    async fn set_consecutive_failures(&self, task_id: &str, failures: u32) -> Result<()> {
+        let task_id = task_id.to_string();
+        self.db
+            .call(move |db| db.tasks().set_consecutive_failures(&task_id, failures))
+            .await
     }
 
     /// Mark a task as completed (for once-type tasks)
     async fn mark_task_completed(&self, task_id: &str) -> Result<()> {
-        let conn = self
-            .db
-            .get_connection()
-            .map_err(|e| NuClawError::Database {
-                message: e.to_string(),
-            })?;
-
-        conn.execute(
-            "UPDATE scheduled_tasks SET status = 'completed', next_run = NULL WHERE id = ?",
-            [task_id],
-        )
-        .map_err(|e| NuClawError::Database {
-            message: format!("Failed to mark task completed: {}", e),
-        })?;
-
-        Ok(())
+        let task_id = task_id.to_string();
+        self.db.call(move |db| db.tasks().complete(&task_id)).await
     }
 
-    /// Mark a task as failed
+    /// Mark a task as failed; it no longer runs until resumed
     async fn mark_task_failed(&self, task_id: &str) -> Result<()> {
-        let conn = self
-            .db
-            .get_connection()
-            .map_err(|e| NuClawError::Database {
-                message: e.to_string(),
-            })?;
+        let task_id = task_id.to_string();
+        self.db.call(move |db| db.tasks().fail(&task_id)).await
+    }
+}
 
-        conn.execute(
-            "UPDATE scheduled_tasks SET status = 'failed' WHERE id = ?",
-            [task_id],
-        )
-        .map_err(|e| NuClawError::Database {
-            message: format!("Failed to mark task failed: {}", e),
-        })?;
+/// Session ID of a scheduled task's runs
+fn task_session_id(task_id: &str) -> String {
+    format!("scheduled_{}", task_id)
+}
 
-        Ok(())
-    }
+/// Stop a scheduled task's run in progress; false if it is not running
+///
+/// The run is logged as cancelled; recurring tasks keep their schedule.
+pub fn cancel_task_run(task_id: &str) -> bool {
+    cancel_session(&task_session_id(task_id)) > 0
 }
 
 /// Parse cron expression and get next run time
@@ -475,10 +1203,7 @@ pub fn parse_cron_expression(expr: &str) -> Result<Schedule> {
 
 /// Get next run time from schedule
 pub fn get_next_run_time(schedule: &Schedule) -> DateTime<Utc> {
-    schedule
-        .after(&chrono::Utc::now())
-        .next()
-        .unwrap_or_else(chrono::Utc::now)
+    next_cron_run(schedule, chrono::Utc::now()).unwrap_or_else(chrono::Utc::now)
 }
 
 /// Check if a task is due for execution
@@ -503,6 +1228,419 @@ pub fn determine_task_status(success: bool, is_once: bool) -> &'static str {
     }
 }
 
+/// Load the most recent task runs, newest first
+pub fn recent_runs(db: &Database, limit: usize) -> Result<Vec<TaskRunLog>> {
+    query_runs(db, &RunFilter::default(), limit)
+}
+
+/// Which task runs to load; unset fields match every run
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+pub struct RunFilter {
+    pub task_id: Option<String>,
+    /// `success`, `error`, `timeout`, or `cancelled`
+    pub status: Option<String>,
+    /// Runs at or after this time (RFC 3339, or a `YYYY-MM-DD` date)
+    pub since: Option<String>,
+    /// Runs before this time (RFC 3339, or a `YYYY-MM-DD` date)
+    pub until: Option<String>,
+}
+
+/// Normalize a filter bound (RFC 3339 or `YYYY-MM-DD`) to the format of
+/// the stored times, such as `task_run_logs.run_at`
+pub(crate) fn time_bound(value: &str) -> Result<String> {
+    let value = value.trim();
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|d| d.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        })
+        .map(|t| t.to_rfc3339())
+        .map_err(|_| NuClawError::Validation {
+            message: format!("Invalid time '{}'; use RFC 3339 or YYYY-MM-DD", value),
+        })
+}
+
+/// Load the runs matching `filter`, newest first
+pub fn query_runs(db: &Database, filter: &RunFilter, limit: usize) -> Result<Vec<TaskRunLog>> {
+    let filter = RunFilter {
+        task_id: filter.task_id.clone(),
+        status: filter.status.clone(),
+        since: filter.since.as_deref().map(time_bound).transpose()?,
+        until: filter.until.as_deref().map(time_bound).transpose()?,
+    };
+    db.tasks().runs(&filter, limit)
+}
+
+/// Whether a run's result differs from the previous one
+///
+/// Surrounding whitespace is ignored. The first result always counts as
+/// changed.
+pub fn result_changed(previous: Option<&str>, current: &str) -> bool {
+    previous.map(str::trim) != Some(current.trim())
+}
+
+/// A task to create; see `create_task`
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+pub struct NewTask {
+    pub group_folder: String,
+    pub chat_jid: String,
+    pub prompt: String,
+    pub schedule_type: String,
+    pub schedule_value: String,
+    /// Retries of a failed run; `None` uses `TASK_MAX_RETRIES`
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    /// Random offset of each run in seconds; `None` uses `TASK_JITTER`
+    #[serde(default)]
+    pub jitter_secs: Option<u64>,
+    /// Time limit of a run in seconds; `None` uses `TASK_TIMEOUT`
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// When runs are reported to the chat; `None` means `always`
+    #[serde(default)]
+    pub notify: Option<String>,
+    /// Name of a lock shared with tasks that must not run at the same time
+    #[serde(default)]
+    pub lock: Option<String>,
+    /// `skip` or `queue` a run due while the previous one is in flight;
+    /// `None` means `skip`
+    #[serde(default)]
+    pub overlap: Option<String>,
+    /// Tasks with a higher priority get free run slots first; default 0
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// Parse a duration like `90s`, `30m`, `2h`, `1d`, or plain milliseconds
+pub fn parse_interval_ms(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (number, unit_ms) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1000),
+        (i, 'm') => (&value[..i], 60_000),
+        (i, 'h') => (&value[..i], 3_600_000),
+        (i, 'd') => (&value[..i], 86_400_000),
+        _ => (value, 1),
+    };
+    number
+        .parse::<i64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .and_then(|n| n.checked_mul(unit_ms))
+}
+
+/// Timezone cron expressions are evaluated in (`TZ`); unknown names fall
+/// back to UTC
+pub fn schedule_timezone() -> Tz {
+    let name = timezone();
+    name.trim().parse().unwrap_or_else(|_| {
+        tracing::warn!("Unknown timezone '{}' in TZ, using UTC", name);
+        Tz::UTC
+    })
+}
+
+/// Next occurrence of a cron schedule after `after`, in the configured
+/// timezone
+pub fn next_cron_run(schedule: &Schedule, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedule
+        .after(&after.with_timezone(&schedule_timezone()))
+        .next()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+/// Validate a schedule and compute its next `count` runs
+///
+/// Returns the normalized schedule value (intervals in milliseconds,
+/// one-off times in RFC 3339) and the run times. A schedule that never
+/// fires again is rejected. Task creation goes through here too, so a
+/// schedule that previews fine is one the scheduler accepts.
+pub fn upcoming_runs(
+    schedule_type: &str,
+    schedule_value: &str,
+    count: usize,
+) -> Result<(String, Vec<DateTime<Utc>>)> {
+    let invalid = |message: String| NuClawError::Validation { message };
+    let now = Utc::now();
+    let (value, runs) = match schedule_type {
+        "cron" => {
+            let schedule = parse_cron_expression(schedule_value).map_err(|e| match e {
+                NuClawError::Scheduler { message } => invalid(message),
+                other => other,
+            })?;
+            let runs = schedule
+                .after(&now.with_timezone(&schedule_timezone()))
+                .take(count)
+                .map(|at| at.with_timezone(&Utc))
+                .collect();
+            (schedule_value.to_string(), runs)
+        }
+        "interval" => {
+            let millis = parse_interval_ms(schedule_value).ok_or_else(|| {
+                invalid(format!(
+                    "Invalid interval '{}'; use e.g. 30m, 2h or 1d",
+                    schedule_value
+                ))
+            })?;
+            let runs = (1..=count as i64)
+                .map_while(|n| {
+                    millis
+                        .checked_mul(n)
+                        .and_then(chrono::Duration::try_milliseconds)
+                        .and_then(|offset| now.checked_add_signed(offset))
+                })
+                .collect();
+            (millis.to_string(), runs)
+        }
+        "once" => {
+            let at = DateTime::parse_from_rfc3339(schedule_value)
+                .map_err(|_| {
+                    invalid(format!(
+                        "Invalid time '{}'; use RFC 3339, e.g. 2026-01-31T09:00:00Z",
+                        schedule_value
+                    ))
+                })?
+                .with_timezone(&Utc);
+            if at <= now {
+                return Err(invalid(format!("{} is in the past", schedule_value)));
+            }
+            (at.to_rfc3339(), vec![at])
+        }
+        other => {
+            return Err(invalid(format!(
+                "Unknown schedule type '{}'; use cron, interval or once",
+                other
+            )))
+        }
+    };
+    if runs.is_empty() && count > 0 {
+        return Err(invalid(format!("Schedule '{}' never runs", schedule_value)));
+    }
+    Ok((value, runs))
+}
+
+/// Validate a schedule and compute its first run
+pub(crate) fn first_run(schedule_type: &str, schedule_value: &str) -> Result<(String, String)> {
+    let (value, runs) = upcoming_runs(schedule_type, schedule_value, 1)?;
+    Ok((value, runs[0].to_rfc3339()))
+}
+
+/// Upper bound for the runs of a schedule preview
+pub const MAX_SCHEDULE_PREVIEW: usize = 50;
+
+/// A validated schedule with its next runs in the configured timezone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchedulePreview {
+    pub schedule_type: String,
+    /// Normalized value, as it would be stored
+    pub schedule_value: String,
+    pub timezone: String,
+    /// RFC 3339 times with the timezone's offset
+    pub next_runs: Vec<String>,
+}
+
+/// Validate a schedule and list its next `count` runs (at most
+/// `MAX_SCHEDULE_PREVIEW`)
+pub fn preview_schedule(
+    schedule_type: &str,
+    schedule_value: &str,
+    count: usize,
+) -> Result<SchedulePreview> {
+    let count = count.clamp(1, MAX_SCHEDULE_PREVIEW);
+    let (value, runs) = upcoming_runs(schedule_type, schedule_value.trim(), count)?;
+    let tz = schedule_timezone();
+    Ok(SchedulePreview {
+        schedule_type: schedule_type.to_string(),
+        schedule_value: value,
+        timezone: tz.name().to_string(),
+        next_runs: runs
+            .iter()
+            .map(|at| at.with_timezone(&tz).to_rfc3339())
+            .collect(),
+    })
+}
+
+/// Validate and store a new active task
+pub fn create_task(db: &Database, task: NewTask) -> Result<ScheduledTask> {
+    if task.prompt.trim().is_empty() {
+        return Err(NuClawError::Validation {
+            message: "Task prompt must not be empty".to_string(),
+        });
+    }
+    if task.group_folder.trim().is_empty() || task.chat_jid.trim().is_empty() {
+        return Err(NuClawError::Validation {
+            message: "Task needs a group folder and chat".to_string(),
+        });
+    }
+    check_task_timeout(task.timeout_secs)?;
+    check_task_notify(task.notify.as_deref())?;
+    check_task_overlap(task.overlap.as_deref())?;
+    let (schedule_value, mut next_run) =
+        first_run(&task.schedule_type, task.schedule_value.trim())?;
+    if task.schedule_type != "once" {
+        if let Ok(at) = DateTime::parse_from_rfc3339(&next_run) {
+            let jitter = task.jitter_secs.unwrap_or_else(task_jitter);
+            next_run = apply_jitter(at.with_timezone(&Utc), jitter, Utc::now()).to_rfc3339();
+        }
+    }
+    let created = ScheduledTask {
+        id: uuid::Uuid::new_v4().simple().to_string()[..8].to_string(),
+        group_folder: task.group_folder,
+        chat_jid: task.chat_jid,
+        prompt: task.prompt.trim().to_string(),
+        schedule_type: task.schedule_type,
+        schedule_value,
+        context_mode: "isolated".to_string(),
+        next_run: Some(next_run),
+        last_run: None,
+        last_result: None,
+        status: "active".to_string(),
+        created_at: Utc::now().to_rfc3339(),
+        container_retries: None,
+        max_retries: task.max_retries,
+        consecutive_failures: 0,
+        jitter_secs: task.jitter_secs,
+        timeout_secs: task.timeout_secs,
+        notify: task.notify.unwrap_or_else(|| notify::ALWAYS.to_string()),
+        lock: normalize_lock(task.lock),
+        overlap: task.overlap.unwrap_or_else(|| overlap::SKIP.to_string()),
+        priority: task.priority,
+    };
+
+    db.tasks().insert(&created)?;
+    wake_scheduler(db.tenant());
+    Ok(created)
+}
+
+/// Changes to a task; unset fields are kept
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+pub struct TaskUpdate {
+    pub prompt: Option<String>,
+    pub schedule_type: Option<String>,
+    pub schedule_value: Option<String>,
+    /// `active` or `paused`
+    pub status: Option<String>,
+    pub max_retries: Option<u32>,
+    pub jitter_secs: Option<u64>,
+    pub timeout_secs: Option<u64>,
+    /// `always`, `on_change`, `on_failure`, or `never`
+    pub notify: Option<String>,
+    /// Lock name; an empty one removes the task's lock
+    pub lock: Option<String>,
+    /// `skip` or `queue`
+    pub overlap: Option<String>,
+    /// Higher runs first
+    pub priority: Option<i32>,
+}
+
+/// Apply changes to a task; `None` if there is no such task
+///
+/// A changed schedule is validated like a new one and reschedules the task.
+pub fn update_task(
+    db: &Database,
+    task_id: &str,
+    update: TaskUpdate,
+) -> Result<Option<ScheduledTask>> {
+    let Some(mut task) = db.tasks().get(task_id)? else {
+        return Ok(None);
+    };
+    let paused = match update.status.as_deref() {
+        None => None,
+        Some("active") => Some(false),
+        Some("paused") => Some(true),
+        Some(other) => {
+            return Err(NuClawError::Validation {
+                message: format!("Invalid status '{}'; use active or paused", other),
+            })
+        }
+    };
+    if let Some(prompt) = update.prompt {
+        if prompt.trim().is_empty() {
+            return Err(NuClawError::Validation {
+                message: "Task prompt must not be empty".to_string(),
+            });
+        }
+        task.prompt = prompt.trim().to_string();
+    }
+    if update.schedule_type.is_some() || update.schedule_value.is_some() {
+        let schedule_type = update.schedule_type.unwrap_or(task.schedule_type);
+        let schedule_value = update.schedule_value.unwrap_or(task.schedule_value);
+        let (schedule_value, next_run) = first_run(&schedule_type, schedule_value.trim())?;
+        task.schedule_type = schedule_type;
+        task.schedule_value = schedule_value;
+        task.next_run = Some(next_run);
+    }
+    if update.max_retries.is_some() {
+        task.max_retries = update.max_retries;
+    }
+    if update.jitter_secs.is_some() {
+        task.jitter_secs = update.jitter_secs;
+    }
+    if update.timeout_secs.is_some() {
+        check_task_timeout(update.timeout_secs)?;
+        task.timeout_secs = update.timeout_secs;
+    }
+    if let Some(mode) = update.notify {
+        check_task_notify(Some(&mode))?;
+        task.notify = mode;
+    }
+    if update.lock.is_some() {
+        task.lock = normalize_lock(update.lock);
+    }
+    if let Some(mode) = update.overlap {
+        check_task_overlap(Some(&mode))?;
+        task.overlap = mode;
+    }
+    if let Some(priority) = update.priority {
+        task.priority = priority;
+    }
+
+    db.tasks().update(&task)?;
+    if let Some(paused) = paused {
+        set_task_paused(db, task_id, paused)?;
+    }
+    wake_scheduler(db.tenant());
+    db.tasks().get(task_id)
+}
+
+/// Pause or resume a task; false if there is no such task
+///
+/// Resuming a recurring task schedules its next run from now.
+pub fn set_task_paused(db: &Database, task_id: &str, paused: bool) -> Result<bool> {
+    let tasks = db.tasks();
+    let changed = if paused {
+        tasks.pause(task_id)?
+    } else {
+        let Some(task) = tasks.get(task_id)? else {
+            return Ok(false);
+        };
+        let next_run = match task.schedule_type.as_str() {
+            "once" => Some(task.schedule_value),
+            _ => first_run(&task.schedule_type, &task.schedule_value)
+                .ok()
+                .map(|(_, next)| next),
+        };
+        tasks.activate(task_id, next_run.as_deref())?
+    };
+    wake_scheduler(db.tenant());
+    Ok(changed)
+}
+
+/// Make an active task due now, so the leading scheduler starts it at its
+/// next pass; false if there is no such task
+pub fn run_task_now(db: &Database, task_id: &str) -> Result<bool> {
+    let Some(task) = db.tasks().get(task_id)? else {
+        return Ok(false);
+    };
+    if task.status != "active" {
+        return Err(NuClawError::Validation {
+            message: format!("Task {} is {}; resume it first", task_id, task.status),
+        });
+    }
+    db.tasks().set_next_run(task_id, &Utc::now().to_rfc3339())?;
+    wake_scheduler(db.tenant());
+    Ok(true)
+}
+
 /// Validate schedule type
 pub fn is_valid_schedule_type(schedule_type: &str) -> bool {
     matches!(schedule_type, "cron" | "interval" | "once")
@@ -557,6 +1695,44 @@ mod tests {
         assert!(next >= now);
     }
 
+    #[test]
+    fn test_preview_schedule() {
+        let preview = preview_schedule("cron", "0 0 9 * * *", 3).unwrap();
+        assert_eq!(preview.next_runs.len(), 3);
+        assert_eq!(preview.timezone, schedule_timezone().name());
+        let runs: Vec<_> = preview
+            .next_runs
+            .iter()
+            .map(|at| DateTime::parse_from_rfc3339(at).unwrap())
+            .collect();
+        assert_eq!(runs[1] - runs[0], chrono::Duration::days(1));
+
+        let preview = preview_schedule("interval", "2h", 2).unwrap();
+        assert_eq!(preview.schedule_value, "7200000");
+        let runs: Vec<_> = preview
+            .next_runs
+            .iter()
+            .map(|at| DateTime::parse_from_rfc3339(at).unwrap())
+            .collect();
+        assert_eq!(runs[1] - runs[0], chrono::Duration::hours(2));
+
+        assert_eq!(
+            preview_schedule("interval", "1m", 1000)
+                .unwrap()
+                .next_runs
+                .len(),
+            MAX_SCHEDULE_PREVIEW
+        );
+        assert!(matches!(
+            preview_schedule("cron", "every day", 5),
+            Err(NuClawError::Validation { .. })
+        ));
+        // Valid syntax, but the year is over: rejected instead of never firing
+        let err = preview_schedule("cron", "0 0 0 1 1 * 2020", 5).unwrap_err();
+        assert!(err.to_string().contains("never runs"));
+        assert!(first_run("cron", "0 0 0 1 1 * 2020").is_err());
+    }
+
     #[test]
     fn test_calculate_interval_next_run() {
         let scheduler = TaskScheduler::new(Database::new().unwrap());
@@ -567,7 +1743,7 @@ mod tests {
         let now = chrono::Utc::now();
         let diff = next_time.signed_duration_since(now).num_seconds();
         // Allow some tolerance
-        assert!(diff >= 3590 && diff <= 3610);
+        assert!((3590..=3610).contains(&diff));
     }
 
     #[test]
@@ -605,6 +1781,15 @@ mod tests {
             status: "active".to_string(),
             created_at: chrono::Utc::now().to_rfc3339(),
             context_mode: "isolated".to_string(),
+            notify: "always".to_string(),
+            lock: None,
+            overlap: "skip".to_string(),
+            priority: 0,
+            container_retries: None,
+            max_retries: None,
+            consecutive_failures: 0,
+            jitter_secs: None,
+            timeout_secs: None,
         };
         let next = scheduler.calculate_next_run(&task);
         assert!(next.is_some());
@@ -626,6 +1811,15 @@ mod tests {
             status: "active".to_string(),
             created_at: chrono::Utc::now().to_rfc3339(),
             context_mode: "isolated".to_string(),
+            notify: "always".to_string(),
+            lock: None,
+            overlap: "skip".to_string(),
+            priority: 0,
+            container_retries: None,
+            max_retries: None,
+            consecutive_failures: 0,
+            jitter_secs: None,
+            timeout_secs: None,
         };
         let next = scheduler.calculate_next_run(&task);
         assert!(next.is_none());
@@ -647,6 +1841,15 @@ mod tests {
             status: "active".to_string(),
             created_at: chrono::Utc::now().to_rfc3339(),
             context_mode: "isolated".to_string(),
+            notify: "always".to_string(),
+            lock: None,
+            overlap: "skip".to_string(),
+            priority: 0,
+            container_retries: None,
+            max_retries: None,
+            consecutive_failures: 0,
+            jitter_secs: None,
+            timeout_secs: None,
         };
         let next = scheduler.calculate_next_run(&task);
         assert!(next.is_none());
@@ -744,6 +1947,15 @@ mod tests {
             status: "active".to_string(),
             created_at: chrono::Utc::now().to_rfc3339(),
             context_mode: "isolated".to_string(),
+            notify: "always".to_string(),
+            lock: None,
+            overlap: "skip".to_string(),
+            priority: 0,
+            container_retries: None,
+            max_retries: None,
+            consecutive_failures: 0,
+            jitter_secs: None,
+            timeout_secs: None,
         };
         let now = chrono::Utc::now().to_rfc3339();
         assert!(is_task_due(&task, &now));
@@ -766,13 +1978,22 @@ mod tests {
             status: "active".to_string(),
             created_at: chrono::Utc::now().to_rfc3339(),
             context_mode: "isolated".to_string(),
+            notify: "always".to_string(),
+            lock: None,
+            overlap: "skip".to_string(),
+            priority: 0,
+            container_retries: None,
+            max_retries: None,
+            consecutive_failures: 0,
+            jitter_secs: None,
+            timeout_secs: None,
         };
         let now_str = now.to_rfc3339();
         assert!(is_task_due(&task, &now_str));
     }
 
     #[test]
-    fn test_is_task_due_active_with_future_nextRun() {
+    fn test_is_task_due_active_with_future_next_run() {
         let now = chrono::Utc::now();
         let future = (now + chrono::Duration::hours(1)).to_rfc3339();
         let task = ScheduledTask {
@@ -788,6 +2009,15 @@ mod tests {
             status: "active".to_string(),
             created_at: chrono::Utc::now().to_rfc3339(),
             context_mode: "isolated".to_string(),
+            notify: "always".to_string(),
+            lock: None,
+            overlap: "skip".to_string(),
+            priority: 0,
+            container_retries: None,
+            max_retries: None,
+            consecutive_failures: 0,
+            jitter_secs: None,
+            timeout_secs: None,
         };
         let now_str = now.to_rfc3339();
         assert!(!is_task_due(&task, &now_str));
@@ -809,6 +2039,15 @@ mod tests {
             status: "paused".to_string(),
             created_at: chrono::Utc::now().to_rfc3339(),
             context_mode: "isolated".to_string(),
+            notify: "always".to_string(),
+            lock: None,
+            overlap: "skip".to_string(),
+            priority: 0,
+            container_retries: None,
+            max_retries: None,
+            consecutive_failures: 0,
+            jitter_secs: None,
+            timeout_secs: None,
         };
         assert!(!is_task_due(&task, &now));
     }
@@ -846,4 +2085,531 @@ mod tests {
         assert_eq!(format_duration(60000), "1m");
         assert_eq!(format_duration(120000), "2m");
     }
+
+    #[test]
+    fn test_recent_runs() {
+        let (db, _dir) = crate::db::test_database();
+        let conn = db.get_connection().unwrap();
+        for (task_id, status, result, error) in
+            [("a", "success", "done", ""), ("b", "error", "", "boom")]
+        {
+            conn.execute(
+                "INSERT INTO task_run_logs (task_id, run_at, duration_ms, status, result, error)
+                 VALUES (?, '2026-01-01T00:00:00Z', 1500, ?, ?, ?)",
+                [task_id, status, result, error],
+            )
+            .unwrap();
+        }
+
+        let runs = recent_runs(&db, 1).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].task_id, "b");
+        assert_eq!(runs[0].result, None);
+        assert_eq!(runs[0].error.as_deref(), Some("boom"));
+        assert_eq!(recent_runs(&db, 10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_interval_ms() {
+        assert_eq!(parse_interval_ms("3600000"), Some(3_600_000));
+        assert_eq!(parse_interval_ms("90s"), Some(90_000));
+        assert_eq!(parse_interval_ms("30m"), Some(1_800_000));
+        assert_eq!(parse_interval_ms("2h"), Some(7_200_000));
+        assert_eq!(parse_interval_ms("1d"), Some(86_400_000));
+        assert_eq!(parse_interval_ms("0"), None);
+        assert_eq!(parse_interval_ms("-5m"), None);
+        assert_eq!(parse_interval_ms("soon"), None);
+    }
+
+    fn new_task(schedule_type: &str, schedule_value: &str) -> NewTask {
+        NewTask {
+            group_folder: "family".to_string(),
+            chat_jid: "123@g.us".to_string(),
+            prompt: "Summarize the news".to_string(),
+            schedule_type: schedule_type.to_string(),
+            schedule_value: schedule_value.to_string(),
+            max_retries: None,
+            jitter_secs: None,
+            timeout_secs: None,
+            notify: None,
+            lock: None,
+            overlap: None,
+            priority: 0,
+        }
+    }
+
+    #[test]
+    fn test_create_task_validates_schedule() {
+        let (db, _dir) = crate::db::test_database();
+
+        for (schedule_type, value) in [
+            ("cron", "not a cron"),
+            ("interval", "0"),
+            ("once", "2000-01-01T00:00:00Z"),
+            ("weekly", "monday"),
+        ] {
+            let err = create_task(&db, new_task(schedule_type, value)).unwrap_err();
+            assert!(matches!(err, NuClawError::Validation { .. }), "{}", value);
+        }
+        assert!(db.tasks().list(Some("123@g.us")).unwrap().is_empty());
+
+        let task = create_task(&db, new_task("interval", "2h")).unwrap();
+        assert_eq!(task.schedule_value, "7200000");
+        assert!(task.next_run.is_some());
+        assert!(create_task(&db, new_task("cron", "0 0 9 * * *")).is_ok());
+    }
+
+    #[test]
+    fn test_task_timeout_override() {
+        let (db, _dir) = crate::db::test_database();
+        let default = Duration::from_secs(600);
+
+        for timeout_secs in [0, DEFAULT_TASK_MAX_TIMEOUT_SECS + 1] {
+            let task = NewTask {
+                timeout_secs: Some(timeout_secs),
+                ..new_task("interval", "1d")
+            };
+            let err = create_task(&db, task).unwrap_err();
+            assert!(matches!(err, NuClawError::Validation { .. }));
+        }
+
+        let task = create_task(&db, new_task("interval", "1d")).unwrap();
+        assert_eq!(effective_task_timeout(&task, default), default);
+
+        let report = create_task(
+            &db,
+            NewTask {
+                timeout_secs: Some(3600),
+                ..new_task("cron", "0 0 9 * * MON")
+            },
+        )
+        .unwrap();
+        let stored = db.tasks().get(&report.id).unwrap().unwrap();
+        assert_eq!(stored.timeout_secs, Some(3600));
+        assert_eq!(
+            effective_task_timeout(&stored, default),
+            Duration::from_secs(3600)
+        );
+
+        // Rows written before the bound was lowered are still capped
+        let oversized = ScheduledTask {
+            timeout_secs: Some(u64::MAX),
+            ..stored
+        };
+        assert_eq!(
+            effective_task_timeout(&oversized, default),
+            Duration::from_secs(DEFAULT_TASK_MAX_TIMEOUT_SECS)
+        );
+    }
+
+    #[test]
+    fn test_pause_resume_delete_task() {
+        let (db, _dir) = crate::db::test_database();
+        let task = create_task(&db, new_task("interval", "30m")).unwrap();
+
+        assert!(set_task_paused(&db, &task.id, true).unwrap());
+        let paused = &db.tasks().list(Some("123@g.us")).unwrap()[0];
+        assert_eq!(paused.status, "paused");
+        assert!(!is_task_due(paused, "9999"));
+
+        assert!(set_task_paused(&db, &task.id, false).unwrap());
+        assert_eq!(
+            db.tasks().list(Some("123@g.us")).unwrap()[0].status,
+            "active"
+        );
+
+        assert!(db.tasks().delete(&task.id).unwrap());
+        assert!(!db.tasks().delete(&task.id).unwrap());
+        assert!(!set_task_paused(&db, &task.id, false).unwrap());
+        assert!(db.tasks().list(Some("123@g.us")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_run_task_now() {
+        let (db, _dir) = crate::db::test_database();
+        let task = create_task(&db, new_task("interval", "1d")).unwrap();
+        let now = Utc::now().to_rfc3339();
+        assert!(db.tasks().due(&now).unwrap().is_empty());
+
+        assert!(run_task_now(&db, &task.id).unwrap());
+        let now = Utc::now().to_rfc3339();
+        assert_eq!(db.tasks().due(&now).unwrap()[0].id, task.id);
+
+        set_task_paused(&db, &task.id, true).unwrap();
+        assert!(matches!(
+            run_task_now(&db, &task.id),
+            Err(NuClawError::Validation { .. })
+        ));
+        assert!(!run_task_now(&db, "missing").unwrap());
+    }
+
+    #[test]
+    fn test_backoff_secs() {
+        assert_eq!(backoff_secs(1, 60, 3600), 60);
+        assert_eq!(backoff_secs(2, 60, 3600), 120);
+        assert_eq!(backoff_secs(4, 60, 3600), 480);
+        assert_eq!(backoff_secs(7, 60, 3600), 3600);
+        assert_eq!(backoff_secs(200, 60, 3600), 3600);
+    }
+
+    #[test]
+    fn test_failure_outcome() {
+        let now = Utc::now();
+        let tomorrow = (now + chrono::Duration::days(1)).to_rfc3339();
+        let soon = (now + chrono::Duration::seconds(30)).to_rfc3339();
+        let retry_at = now + chrono::Duration::minutes(1);
+
+        assert_eq!(
+            failure_outcome(1, 3, retry_at, Some(tomorrow.clone())),
+            FailureOutcome::Retry {
+                attempt: 1,
+                next_run: retry_at.to_rfc3339()
+            }
+        );
+        // Out of retries: a recurring task waits for its next run
+        assert_eq!(
+            failure_outcome(4, 3, retry_at, Some(tomorrow.clone())),
+            FailureOutcome::Skip { next_run: tomorrow }
+        );
+        // The regular run comes before the retry would
+        assert_eq!(
+            failure_outcome(1, 3, retry_at, Some(soon.clone())),
+            FailureOutcome::Skip { next_run: soon }
+        );
+        assert!(matches!(
+            failure_outcome(3, 3, retry_at, None),
+            FailureOutcome::Retry { attempt: 3, .. }
+        ));
+        assert_eq!(failure_outcome(4, 3, retry_at, None), FailureOutcome::Dead);
+    }
+
+    #[tokio::test]
+    async fn test_due_tasks_are_loaded_by_priority() {
+        let (db, _dir) = crate::db::test_database();
+        let scheduler = TaskScheduler::new(db.clone());
+        let batch = create_task(&db, new_task("interval", "1h")).unwrap();
+        let alert = create_task(
+            &db,
+            NewTask {
+                priority: 10,
+                ..new_task("interval", "1h")
+            },
+        )
+        .unwrap();
+        let later = (Utc::now() + chrono::Duration::days(1)).to_rfc3339();
+
+        let due: Vec<String> = scheduler
+            .load_due_tasks(&later)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(due, vec![alert.id.clone(), batch.id.clone()]);
+
+        let update = TaskUpdate {
+            priority: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(
+            update_task(&db, &batch.id, update)
+                .unwrap()
+                .unwrap()
+                .priority,
+            20
+        );
+        let due = scheduler.load_due_tasks(&later).await.unwrap();
+        assert_eq!(due[0].id, batch.id);
+    }
+
+    #[tokio::test]
+    async fn test_failed_runs_retry_before_giving_up() {
+        let (db, _dir) = crate::db::test_database();
+        let scheduler = TaskScheduler::new(db.clone());
+
+        let daily = create_task(&db, new_task("interval", "1d")).unwrap();
+        scheduler.handle_failed_run(&daily, "boom").await.unwrap();
+        let retried = db.tasks().get(&daily.id).unwrap().unwrap();
+        assert_eq!(retried.status, "active");
+        assert_eq!(retried.consecutive_failures, 1);
+        assert!(retried.next_run < daily.next_run);
+
+        let once = create_task(
+            &db,
+            NewTask {
+                max_retries: Some(0),
+                ..new_task(
+                    "once",
+                    &(Utc::now() + chrono::Duration::hours(1)).to_rfc3339(),
+                )
+            },
+        )
+        .unwrap();
+        scheduler.handle_failed_run(&once, "boom").await.unwrap();
+        assert_eq!(db.tasks().get(&once.id).unwrap().unwrap().status, "failed");
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_reported_to_the_chat_once_given_up() {
+        let (db, _dir) = crate::db::test_database();
+        let scheduler = TaskScheduler::new(db.clone());
+        let outbox = Outbox::new(db.clone(), "whatsapp");
+        let once = |mode: &str| NewTask {
+            max_retries: Some(1),
+            notify: Some(mode.to_string()),
+            ..new_task(
+                "once",
+                &(Utc::now() + chrono::Duration::hours(1)).to_rfc3339(),
+            )
+        };
+
+        let task = create_task(&db, once(notify::ON_FAILURE)).unwrap();
+        scheduler.handle_failed_run(&task, "boom").await.unwrap();
+        // Retries are not reported
+        assert_eq!(outbox.pending_count().unwrap(), 0);
+        let task = db.tasks().get(&task.id).unwrap().unwrap();
+        scheduler.handle_failed_run(&task, "boom").await.unwrap();
+        let queued = outbox.next_due(10).unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].chat_jid, "123@g.us");
+        assert!(queued[0].content.contains("boom"));
+
+        let silent = create_task(
+            &db,
+            NewTask {
+                max_retries: Some(0),
+                ..once(notify::NEVER)
+            },
+        )
+        .unwrap();
+        scheduler.handle_failed_run(&silent, "boom").await.unwrap();
+        assert_eq!(outbox.pending_count().unwrap(), 1);
+
+        let err = create_task(&db, once("sometimes")).unwrap_err();
+        assert!(matches!(err, NuClawError::Validation { .. }));
+    }
+
+    #[test]
+    fn test_apply_jitter() {
+        let now = Utc::now();
+        let at = now + chrono::Duration::hours(1);
+        assert_eq!(apply_jitter(at, 0, now), at);
+        for _ in 0..100 {
+            let jittered = apply_jitter(at, 300, now);
+            assert!((jittered - at).num_seconds().abs() <= 300);
+            assert!(apply_jitter(now, 300, now) >= now);
+        }
+    }
+
+    #[test]
+    fn test_calculate_next_run_with_jitter_skips_current_occurrence() {
+        let (db, _dir) = crate::db::test_database();
+        let scheduler = TaskScheduler::new(db.clone());
+        let task = ScheduledTask {
+            jitter_secs: Some(3600),
+            ..create_task(&db, new_task("cron", "0 0 * * * *")).unwrap()
+        };
+        let upcoming = get_next_run_time(&parse_cron_expression("0 0 * * * *").unwrap());
+
+        // An hourly task with an hour of jitter that just ran early: its next
+        // run is never before the occurrence it ran for
+        for _ in 0..20 {
+            let next = scheduler.calculate_next_run(&task).unwrap();
+            assert!(DateTime::parse_from_rfc3339(&next).unwrap() >= upcoming);
+        }
+    }
+
+    #[test]
+    fn test_result_changed() {
+        assert!(result_changed(None, "Price: $10"));
+        assert!(!result_changed(Some("Price: $10\n"), "Price: $10"));
+        assert!(result_changed(Some("Price: $10"), "Price: $12"));
+    }
+
+    #[test]
+    fn test_query_and_prune_runs() {
+        let (db, _dir) = crate::db::test_database();
+        let conn = db.get_connection().unwrap();
+        for (task_id, run_at, status) in [
+            ("a", "2026-01-01T09:00:00+00:00", "success"),
+            ("a", "2026-01-02T09:00:00+00:00", "error"),
+            ("b", "2026-01-03T09:00:00+00:00", "success"),
+        ] {
+            conn.execute(
+                "INSERT INTO task_run_logs (task_id, run_at, duration_ms, status)
+                 VALUES (?, ?, 1000, ?)",
+                [task_id, run_at, status],
+            )
+            .unwrap();
+        }
+        drop(conn);
+
+        let filter = |task_id: Option<&str>, status: Option<&str>, since: Option<&str>| RunFilter {
+            task_id: task_id.map(str::to_string),
+            status: status.map(str::to_string),
+            since: since.map(str::to_string),
+            until: None,
+        };
+        assert_eq!(
+            query_runs(&db, &filter(Some("a"), None, None), 10)
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            query_runs(&db, &filter(None, Some("success"), None), 10)
+                .unwrap()
+                .len(),
+            2
+        );
+        let since = query_runs(&db, &filter(None, None, Some("2026-01-02")), 10).unwrap();
+        assert_eq!(since.len(), 2);
+        assert_eq!(since[0].task_id, "b");
+        let until = RunFilter {
+            until: Some("2026-01-02T09:00:00Z".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query_runs(&db, &until, 10).unwrap().len(), 1);
+        assert!(matches!(
+            query_runs(&db, &filter(None, None, Some("yesterday")), 10),
+            Err(NuClawError::Validation { .. })
+        ));
+
+        assert_eq!(
+            db.tasks().prune_runs("2026-01-03T00:00:00+00:00").unwrap(),
+            2
+        );
+        assert_eq!(recent_runs(&db, 10).unwrap().len(), 1);
+        db.vacuum().unwrap();
+    }
+
+    #[test]
+    fn test_wakeup_delay() {
+        let now = Utc::now();
+        let max = Duration::from_secs(60);
+        assert_eq!(wakeup_delay(None, now, max), max);
+        assert_eq!(
+            wakeup_delay(Some(now + chrono::Duration::seconds(10)), now, max),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            wakeup_delay(Some(now + chrono::Duration::hours(2)), now, max),
+            max
+        );
+        // Overdue tasks still leave a short pause between passes
+        assert_eq!(
+            wakeup_delay(Some(now - chrono::Duration::hours(1)), now, max),
+            MIN_WAKEUP_DELAY
+        );
+    }
+
+    #[test]
+    fn test_next_due_time() {
+        let (db, _dir) = crate::db::test_database();
+        let none = HashSet::new();
+        assert_eq!(next_due_time(&db, &none).unwrap(), None);
+
+        let later = create_task(&db, new_task("interval", "2h")).unwrap();
+        let sooner = create_task(&db, new_task("interval", "30m")).unwrap();
+        let due = next_due_time(&db, &none).unwrap().unwrap();
+        assert_eq!(Some(due.to_rfc3339()), sooner.next_run);
+
+        // Nor are tasks with a run in flight
+        let running = HashSet::from([sooner.id.clone()]);
+        let due = next_due_time(&db, &running).unwrap().unwrap();
+        assert_eq!(Some(due.to_rfc3339()), later.next_run);
+
+        // Paused tasks are not waited for
+        set_task_paused(&db, &sooner.id, true).unwrap();
+        let due = next_due_time(&db, &none).unwrap().unwrap();
+        assert_eq!(Some(due.to_rfc3339()), later.next_run);
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let (db, _dir) = crate::db::test_database();
+        let locked = |lock: &str| NewTask {
+            lock: Some(lock.to_string()),
+            ..new_task("interval", "1h")
+        };
+        let sync = create_task(&db, locked("repo-sync")).unwrap();
+        let other_sync = create_task(&db, locked(" repo-sync ")).unwrap();
+        let backup = create_task(&db, locked("backup")).unwrap();
+        assert_eq!(other_sync.lock.as_deref(), Some("repo-sync"));
+
+        let mut runs = HashMap::new();
+        assert_eq!(dispatch(&runs, &sync), Dispatch::Start);
+        runs.insert(
+            sync.id.clone(),
+            InFlight {
+                lock: sync.lock.clone(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(dispatch(&runs, &sync), Dispatch::Overlapping);
+        assert_eq!(
+            dispatch(&runs, &other_sync),
+            Dispatch::Locked("repo-sync".to_string())
+        );
+        assert_eq!(dispatch(&runs, &backup), Dispatch::Start);
+
+        for i in 0..MAX_CONCURRENT_TASKS {
+            runs.insert(format!("other-{}", i), InFlight::default());
+        }
+        assert_eq!(dispatch(&runs, &backup), Dispatch::Full);
+    }
+
+    #[test]
+    fn test_long_run_is_queued_only_at_a_new_occurrence() {
+        let (db, _dir) = crate::db::test_database();
+        let task = create_task(
+            &db,
+            NewTask {
+                overlap: Some("queue".to_string()),
+                jitter_secs: Some(0),
+                ..new_task("interval", "1h")
+            },
+        )
+        .unwrap();
+        let scheduler = TaskScheduler::new(db);
+        let start = Utc::now();
+        let mut runs = HashMap::new();
+        assert_eq!(
+            claim_run(&mut runs, &task, scheduler.calculate_next_run(&task), start),
+            Dispatch::Start
+        );
+
+        // The run outlasts many scheduler passes, all before the next hour
+        for minutes in [1, 10, 59] {
+            let now = start + chrono::Duration::minutes(minutes);
+            assert_eq!(
+                claim_run(&mut runs, &task, None, now),
+                Dispatch::Overlapping
+            );
+            assert!(!runs[&task.id].queued, "queued after {} minutes", minutes);
+        }
+
+        let now = start + chrono::Duration::minutes(61);
+        claim_run(&mut runs, &task, None, now);
+        assert!(runs[&task.id].queued);
+    }
+
+    #[test]
+    fn test_overlap_mode_is_validated() {
+        let (db, _dir) = crate::db::test_database();
+        let task = NewTask {
+            overlap: Some("queue".to_string()),
+            ..new_task("interval", "1h")
+        };
+        assert_eq!(create_task(&db, task).unwrap().overlap, overlap::QUEUE);
+
+        let task = NewTask {
+            overlap: Some("parallel".to_string()),
+            ..new_task("interval", "1h")
+        };
+        assert!(matches!(
+            create_task(&db, task),
+            Err(NuClawError::Validation { .. })
+        ));
+    }
 }
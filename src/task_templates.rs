@@ -0,0 +1,369 @@
+//! Task Templates for NuClaw
+//!
+//! A template is a reusable task prompt with `{placeholders}`, optionally
+//! with a default schedule and group folder. Creating a task from it only
+//! takes the placeholder values, e.g. `/task from standup team=backend`.
+//! `{group}` is always filled in with the task's group folder.
+//!
+//! Templates are stored per tenant in the `task_templates` table.
+
+use crate::db::{Database, Row};
+use crate::error::{NuClawError, Result};
+use crate::groups::load_registered_groups;
+use crate::task_scheduler::{first_run, NewTask};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A reusable task
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskTemplate {
+    pub name: String,
+    /// Prompt with `{name}` placeholders
+    pub prompt: String,
+    #[serde(default)]
+    pub schedule_type: Option<String>,
+    #[serde(default)]
+    pub schedule_value: Option<String>,
+    #[serde(default)]
+    pub group_folder: Option<String>,
+    #[serde(default)]
+    pub created_at: String,
+}
+
+/// How to create a task from a template; unset fields use its defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct TemplateUse {
+    #[serde(default)]
+    pub group_folder: Option<String>,
+    /// Chat the task reports to; by default the chat registered for the
+    /// group folder
+    #[serde(default)]
+    pub chat_jid: Option<String>,
+    #[serde(default)]
+    pub schedule_type: Option<String>,
+    #[serde(default)]
+    pub schedule_value: Option<String>,
+    /// Placeholder values
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+}
+
+fn invalid(message: String) -> NuClawError {
+    NuClawError::Validation { message }
+}
+
+/// Whether `name` can be used as a template or placeholder name
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Replace the placeholders of `template` with `values`
+///
+/// Returns the text and the names of placeholders without a value, which
+/// are left as they are. Braces around anything but a name (e.g. JSON in
+/// a prompt) are not placeholders.
+fn render(template: &str, values: &BTreeMap<String, String>) -> (String, BTreeSet<String>) {
+    let mut text = String::with_capacity(template.len());
+    let mut missing = BTreeSet::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after
+            .find('}')
+            .map(|end| (&after[..end], &after[end + 1..]))
+        {
+            Some((name, tail)) if is_valid_name(name) => {
+                match values.get(name) {
+                    Some(value) => text.push_str(value),
+                    None => {
+                        missing.insert(name.to_string());
+                        text.push_str(&rest[start..start + name.len() + 2]);
+                    }
+                }
+                rest = tail;
+            }
+            _ => {
+                text.push('{');
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    (text, missing)
+}
+
+/// Placeholder names used in a prompt, sorted
+pub fn placeholders(prompt: &str) -> Vec<String> {
+    render(prompt, &BTreeMap::new()).1.into_iter().collect()
+}
+
+/// Fill in every placeholder of a prompt
+pub fn fill_placeholders(prompt: &str, values: &BTreeMap<String, String>) -> Result<String> {
+    let (text, missing) = render(prompt, values);
+    if missing.is_empty() {
+        Ok(text)
+    } else {
+        Err(invalid(format!(
+            "Missing values for: {}",
+            missing.into_iter().collect::<Vec<_>>().join(", ")
+        )))
+    }
+}
+
+/// Validate and store a template, replacing one with the same name
+pub fn save_template(db: &Database, template: TaskTemplate) -> Result<TaskTemplate> {
+    let name = template.name.trim().to_lowercase();
+    if !is_valid_name(&name) {
+        return Err(invalid(format!(
+            "Invalid template name '{}'; use letters, digits, '-' and '_'",
+            template.name
+        )));
+    }
+    if template.prompt.trim().is_empty() {
+        return Err(invalid("Template prompt must not be empty".to_string()));
+    }
+    let schedule = match (template.schedule_type, template.schedule_value) {
+        (None, None) => None,
+        (Some(schedule_type), Some(value)) => {
+            if schedule_type == "once" {
+                return Err(invalid(
+                    "Templates cannot have a one-off schedule".to_string(),
+                ));
+            }
+            let (value, _) = first_run(&schedule_type, value.trim())?;
+            Some((schedule_type, value))
+        }
+        _ => {
+            return Err(invalid(
+                "Set both schedule_type and schedule_value, or neither".to_string(),
+            ))
+        }
+    };
+    let (schedule_type, schedule_value) = schedule.unzip();
+    let saved = TaskTemplate {
+        name,
+        prompt: template.prompt.trim().to_string(),
+        schedule_type,
+        schedule_value,
+        group_folder: template
+            .group_folder
+            .map(|g| g.trim().to_string())
+            .filter(|g| !g.is_empty()),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+
+    db.get_connection()?.execute(
+        "INSERT INTO task_templates
+            (tenant, name, prompt, schedule_type, schedule_value, group_folder, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (tenant, name) DO UPDATE SET
+            prompt = excluded.prompt,
+            schedule_type = excluded.schedule_type,
+            schedule_value = excluded.schedule_value,
+            group_folder = excluded.group_folder,
+            created_at = excluded.created_at",
+        crate::params![
+            db.tenant(),
+            saved.name,
+            saved.prompt,
+            saved.schedule_type,
+            saved.schedule_value,
+            saved.group_folder,
+            saved.created_at,
+        ],
+    )?;
+    Ok(saved)
+}
+
+fn template_from_row(row: &Row) -> Result<TaskTemplate> {
+    Ok(TaskTemplate {
+        name: row.get(0)?,
+        prompt: row.get(1)?,
+        schedule_type: row.get(2)?,
+        schedule_value: row.get(3)?,
+        group_folder: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+/// All templates by name
+pub fn list_templates(db: &Database) -> Result<Vec<TaskTemplate>> {
+    db.get_connection()?.query_map(
+        "SELECT name, prompt, schedule_type, schedule_value, group_folder, created_at
+         FROM task_templates WHERE tenant = ? ORDER BY name",
+        [db.tenant()],
+        template_from_row,
+    )
+}
+
+/// Look up a template by name
+pub fn get_template(db: &Database, name: &str) -> Result<Option<TaskTemplate>> {
+    db.get_connection()?.query_opt(
+        "SELECT name, prompt, schedule_type, schedule_value, group_folder, created_at
+         FROM task_templates WHERE tenant = ? AND name = ?",
+        [db.tenant(), &name.trim().to_lowercase()],
+        template_from_row,
+    )
+}
+
+/// Delete a template; `false` if there was none
+pub fn delete_template(db: &Database, name: &str) -> Result<bool> {
+    let deleted = db.get_connection()?.execute(
+        "DELETE FROM task_templates WHERE tenant = ? AND name = ?",
+        [db.tenant(), &name.trim().to_lowercase()],
+    )?;
+    Ok(deleted > 0)
+}
+
+/// Build the task a template describes; pass it to `create_task`
+pub fn task_from_template(db: &Database, name: &str, with: TemplateUse) -> Result<NewTask> {
+    let template =
+        get_template(db, name)?.ok_or_else(|| invalid(format!("No template '{}'", name)))?;
+
+    let group_folder = with
+        .group_folder
+        .or(template.group_folder)
+        .ok_or_else(|| invalid(format!("Template '{}' needs a group folder", name)))?;
+    let chat_jid = match with.chat_jid {
+        Some(chat_jid) => chat_jid,
+        None => load_registered_groups(db.tenant())
+            .into_iter()
+            .filter(|(_, group)| group.folder == group_folder)
+            .map(|(jid, _)| jid)
+            .min()
+            .ok_or_else(|| invalid(format!("No chat is registered as '{}'", group_folder)))?,
+    };
+    let (schedule_type, schedule_value) = match (with.schedule_type, with.schedule_value) {
+        (Some(schedule_type), Some(value)) => (schedule_type, value),
+        _ => template
+            .schedule_type
+            .zip(template.schedule_value)
+            .ok_or_else(|| invalid(format!("Template '{}' needs a schedule", name)))?,
+    };
+
+    let mut values = with.params;
+    values.insert("group".to_string(), group_folder.clone());
+    Ok(NewTask {
+        group_folder,
+        chat_jid,
+        prompt: fill_placeholders(&template.prompt, &values)?,
+        schedule_type,
+        schedule_value,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    fn template(name: &str, prompt: &str) -> TaskTemplate {
+        TaskTemplate {
+            name: name.to_string(),
+            prompt: prompt.to_string(),
+            schedule_type: Some("cron".to_string()),
+            schedule_value: Some("0 0 9 * * MON-FRI".to_string()),
+            group_folder: Some("family".to_string()),
+            created_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_fill_placeholders() {
+        let values = BTreeMap::from([
+            ("team".to_string(), "backend".to_string()),
+            ("group".to_string(), "work".to_string()),
+        ]);
+        assert_eq!(
+            fill_placeholders("Summarize {team}'s standup in {group}", &values).unwrap(),
+            "Summarize backend's standup in work"
+        );
+        // Braces that are not placeholders are kept
+        assert_eq!(
+            fill_placeholders("Reply with {\"ok\": true} for {team}", &values).unwrap(),
+            "Reply with {\"ok\": true} for backend"
+        );
+        let err = fill_placeholders("{team} {day} {week}", &values).unwrap_err();
+        assert!(err.to_string().contains("day, week"));
+        assert_eq!(placeholders("{b} {a} {b} {x y}"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_save_template_validates() {
+        let (db, _dir) = test_database();
+        for invalid in [
+            template("daily standup", "Summarize"),
+            template("standup", "  "),
+            TaskTemplate {
+                schedule_value: None,
+                ..template("standup", "Summarize")
+            },
+            TaskTemplate {
+                schedule_value: Some("every day".to_string()),
+                ..template("standup", "Summarize")
+            },
+        ] {
+            assert!(matches!(
+                save_template(&db, invalid),
+                Err(NuClawError::Validation { .. })
+            ));
+        }
+        assert!(list_templates(&db).unwrap().is_empty());
+
+        let saved = save_template(
+            &db,
+            TaskTemplate {
+                schedule_type: Some("interval".to_string()),
+                schedule_value: Some("1d".to_string()),
+                ..template("Standup", "Summarize")
+            },
+        )
+        .unwrap();
+        assert_eq!(saved.name, "standup");
+        assert_eq!(saved.schedule_value.as_deref(), Some("86400000"));
+        assert_eq!(get_template(&db, "STANDUP").unwrap(), Some(saved));
+        assert!(delete_template(&db, "standup").unwrap());
+        assert!(!delete_template(&db, "standup").unwrap());
+    }
+
+    #[test]
+    fn test_task_from_template() {
+        let (db, _dir) = test_database();
+        save_template(
+            &db,
+            template("standup", "Summarize {team}'s standup in {group}"),
+        )
+        .unwrap();
+
+        let task = task_from_template(
+            &db,
+            "standup",
+            TemplateUse {
+                chat_jid: Some("123@g.us".to_string()),
+                params: BTreeMap::from([("team".to_string(), "backend".to_string())]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(task.prompt, "Summarize backend's standup in family");
+        assert_eq!(task.group_folder, "family");
+        assert_eq!(task.schedule_type, "cron");
+        assert_eq!(task.schedule_value, "0 0 9 * * MON-FRI");
+
+        let missing = task_from_template(
+            &db,
+            "standup",
+            TemplateUse {
+                chat_jid: Some("123@g.us".to_string()),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(missing, Err(NuClawError::Validation { .. })));
+        assert!(task_from_template(&db, "retro", TemplateUse::default()).is_err());
+    }
+}
@@ -2,39 +2,94 @@
 //!
 //! Provides Telegram Bot connectivity via Bot API with webhook support.
 //! Follows OpenClaw Telegram specification for message handling.
-
-use crate::config::{assistant_name, data_dir};
-use crate::container_runner::run_container;
-use crate::db::Database;
+//!
+//! Each tenant with a bot token gets its own client (see `tenants`); all
+//! their webhooks are served by one server.
+
+use crate::allowlist::{self, AllowlistKind};
+use crate::audit::{self, chat_actor, AuditEvent, AuditKind};
+use crate::blocklist;
+use crate::broadcast::process_ipc_requests;
+use crate::chat_queue::ChatQueue;
+use crate::commands::{execute_command, parse_command, ChatCommand, CommandContext};
+use crate::container_runner::{
+    container_limiter, run_container_with_retry, RetryPolicy, CANCELLED_NOTICE, QUEUED_NOTICE,
+};
+use crate::context::conversation_context;
+use crate::correlation::{correlation_span, new_correlation_id, with_ref};
+use crate::db::{blocking, Database};
 use crate::error::{NuClawError, Result};
+use crate::group_config::{group_settings, with_group_file};
+pub use crate::groups::load_registered_groups;
+use crate::groups::{match_trigger, register_dm_chat};
+use crate::health;
+use crate::inbound;
+use crate::maintenance::{is_paused, PAUSED_NOTICE};
+use crate::memory::relevant_memories;
+use crate::outbox::Outbox;
+use crate::pairing::{
+    check_pairing, is_paired, unpaired_notice_due, PairingStatus, UNPAIRED_NOTICE,
+};
+use crate::pending::PendingQueue;
+use crate::prompt_guard::{self, Guarded};
+use crate::rate_limiter::{parse_retry_after, RateLimiter};
+use crate::roles;
+use crate::sessions::{record_run_session, resume_session_id};
+use crate::shutdown;
+use crate::tenants::{tenant, Tenant, DEFAULT_TENANT};
+pub use crate::types::DMPolicy;
 use crate::types::{ContainerInput, NewMessage, RegisteredGroup, RouterState};
 use crate::utils::json::{load_json, save_json};
-use axum::routing::{get, post};
+use crate::webhook_auth::{self, SignatureScheme, WebhookVerifier};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::post;
 use axum::Json;
 use axum::Router;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::mpsc;
 use tokio::time::{timeout, Duration};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn, Instrument};
+
+/// Channel name used for pairing and command context
+const CHANNEL: &str = "telegram";
 
 /// Default text chunk limit: 4000 characters
 const DEFAULT_TEXT_CHUNK_LIMIT: usize = 4000;
-
-/// DM policy enumeration
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
-pub enum DMPolicy {
-    #[serde(rename = "pairing")]
-    Pairing,
-    #[serde(rename = "allowlist")]
-    Allowlist,
-    #[serde(rename = "open")]
-    Open,
-    #[serde(rename = "disabled")]
-    Disabled,
-}
+/// Default global send rate: 30 messages per second (Bot API limit)
+const DEFAULT_GLOBAL_RATE_LIMIT: f64 = 30.0;
+/// Default per-chat send rate: 1 message per second
+const DEFAULT_CHAT_RATE_LIMIT: f64 = 1.0;
+/// Default retries for a chunk rejected with 429
+const DEFAULT_MAX_SEND_RETRIES: u32 = 3;
+/// Fallback wait when a 429 carries no retry_after
+const DEFAULT_RETRY_AFTER_SECS: u64 = 1;
+/// Default Bot API request timeout in seconds
+const DEFAULT_HTTP_TIMEOUT_SECS: u64 = 30;
+/// Default Bot API connect timeout in seconds
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+/// Default seconds between edits of a streamed reply
+const DEFAULT_STREAM_INTERVAL_SECS: u64 = 3;
+/// Default long-poll timeout for getUpdates, below the HTTP timeout
+const DEFAULT_POLL_TIMEOUT_SECS: u64 = 25;
+/// Delay before retrying a failed getUpdates call
+const POLL_RETRY_DELAY_SECS: u64 = 5;
+/// Default time an inline query may run before it is answered with an error
+const DEFAULT_INLINE_TIMEOUT_SECS: u64 = 10;
+/// Maximum length of an inline result title
+const INLINE_TITLE_LEN: usize = 64;
+/// Reply to non-text messages under the reject policy
+const NON_TEXT_HINT: &str = "Sorry, I can only read text messages.";
+/// Text of the placeholder a streamed reply starts from
+const STREAM_PLACEHOLDER: &str = "…";
+/// Default number of webhook updates waiting to be handled
+const DEFAULT_UPDATE_QUEUE_SIZE: usize = 100;
+/// Default idle pooled connections kept per host
+const DEFAULT_POOL_MAX_IDLE: usize = 8;
 
 /// Group policy enumeration
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -47,12 +102,38 @@ pub enum GroupPolicy {
     Disabled,
 }
 
+/// How to handle messages without text (stickers, polls, locations, ...)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NonTextPolicy {
+    /// Drop them silently
+    #[serde(rename = "ignore")]
+    Ignore,
+    /// Pass a text description to the agent
+    #[serde(rename = "describe")]
+    Describe,
+    /// Answer private chats with a hint; drop them in groups
+    #[serde(rename = "reject")]
+    Reject,
+}
+
 /// Telegram Update object (Telegram Bot API)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TelegramUpdate {
     pub update_id: i64,
     pub message: Option<TelegramMessage>,
     pub edited_message: Option<TelegramMessage>,
+    #[serde(default)]
+    pub inline_query: Option<TelegramInlineQuery>,
+}
+
+/// Telegram InlineQuery object (`@botname <query>` typed in any chat)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramInlineQuery {
+    pub id: String,
+    pub from: TelegramUser,
+    pub query: String,
+    #[serde(default)]
+    pub offset: String,
 }
 
 /// Telegram User object
@@ -72,6 +153,28 @@ pub struct TelegramChat {
     #[serde(rename = "type")]
     pub chat_type: String,
     pub title: Option<String>,
+    /// Set for private chats
+    #[serde(default)]
+    pub first_name: Option<String>,
+    #[serde(default)]
+    pub last_name: Option<String>,
+}
+
+impl TelegramChat {
+    /// Title of a group, or the name of the user in a private chat
+    pub fn display_name(&self) -> Option<String> {
+        if let Some(title) = self.title.as_ref().filter(|t| !t.trim().is_empty()) {
+            return Some(title.trim().to_string());
+        }
+        let name = [self.first_name.as_deref(), self.last_name.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        (!name.is_empty()).then_some(name)
+    }
 }
 
 /// Telegram Message object
@@ -82,63 +185,563 @@ pub struct TelegramMessage {
     pub chat: TelegramChat,
     pub date: i64,
     pub text: Option<String>,
+    /// Forum topic the message was posted in
+    #[serde(default)]
+    pub message_thread_id: Option<i64>,
+    /// True if the message was sent to a forum topic
+    #[serde(default)]
+    pub is_topic_message: Option<bool>,
+    /// Caption of a media message
+    #[serde(default)]
+    pub caption: Option<String>,
+    #[serde(default)]
+    pub sticker: Option<TelegramSticker>,
+    #[serde(default)]
+    pub animation: Option<TelegramAnimation>,
+    #[serde(default)]
+    pub poll: Option<TelegramPoll>,
+    #[serde(default)]
+    pub contact: Option<TelegramContact>,
+    #[serde(default)]
+    pub location: Option<TelegramLocation>,
+    /// Message this one replies to
+    #[serde(default)]
+    pub reply_to_message: Option<Box<TelegramMessage>>,
+    /// Part of the replied-to message the user selected as a quote
+    #[serde(default)]
+    pub quote: Option<TelegramTextQuote>,
 }
 
-/// Telegram client state
-pub struct TelegramClient {
+/// Telegram TextQuote object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramTextQuote {
+    pub text: String,
+}
+
+/// Telegram Sticker object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramSticker {
+    pub emoji: Option<String>,
+    pub set_name: Option<String>,
+}
+
+/// Telegram Animation (GIF) object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramAnimation {
+    pub file_name: Option<String>,
+    pub duration: Option<i64>,
+}
+
+/// Telegram Poll object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramPoll {
+    pub question: String,
+    pub options: Vec<TelegramPollOption>,
+}
+
+/// Telegram PollOption object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramPollOption {
+    pub text: String,
+}
+
+/// Telegram Contact object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramContact {
+    pub phone_number: String,
+    pub first_name: String,
+    pub last_name: Option<String>,
+}
+
+/// Telegram Location object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Bot API sender
+///
+/// Holds everything needed to call the Bot API, so it can be cloned into
+/// the outbox worker without touching the client's message state.
+#[derive(Clone)]
+pub struct TelegramApi {
     /// API URL
     api_url: String,
+    /// Shared HTTP client; clones reuse the same connection pool
+    http: reqwest::Client,
+    /// Text chunk limit; `None` reads `TELEGRAM_TEXT_CHUNK_LIMIT` at each
+    /// use, so a reloaded config file applies
+    text_chunk_limit: Option<usize>,
+    /// Outbound rate limiter
+    rate_limiter: Arc<RateLimiter>,
+    /// Max retries for a chunk rejected with 429
+    max_send_retries: u32,
+    /// Health component the reachability of the Bot API is reported as
+    health_component: String,
+}
+
+/// Telegram client state
+#[derive(Clone)]
+pub struct TelegramClient {
+    /// Bot API sender
+    api: TelegramApi,
+    /// Persistent outbound queue
+    outbox: Outbox,
+    /// Triggered messages whose agent run has not finished
+    pending: PendingQueue,
+    /// Agent runs waiting for their chat's turn
+    runs: ChatQueue,
     /// Webhook path
     webhook_path: String,
+    /// Secret Telegram sends with each webhook update
+    webhook_secret: Option<String>,
     /// DM policy
     dm_policy: DMPolicy,
     /// Group policy
     group_policy: GroupPolicy,
-    /// Text chunk limit
-    text_chunk_limit: usize,
-    /// Allowed group IDs
-    allowed_groups: Vec<String>,
-    /// Reference to registered groups
-    registered_groups: HashMap<String, RegisteredGroup>,
-    /// Router state for message deduplication
-    router_state: RouterState,
-    /// Database connection
+    /// Handling of messages without text
+    non_text_policy: NonTextPolicy,
+    /// Reference to registered groups, shared by all clones
+    registered_groups: Arc<RwLock<HashMap<String, RegisteredGroup>>>,
+    /// Router state of the last processed message, shared by all clones
+    router_state: Arc<Mutex<RouterState>>,
+    /// Database connection, scoped to the client's tenant
     db: Database,
-    /// Assistant name for trigger detection
-    assistant_name: String,
+    /// Directory of the tenant's router state and update position
+    state_dir: PathBuf,
+    /// Whether to stream partial output by editing a placeholder
+    stream_responses: bool,
+    /// Minimum time between edits of a streamed reply
+    stream_interval: Duration,
+    /// Time limit for answering an inline query
+    inline_timeout: Duration,
+}
+
+/// getUpdates position persisted across restarts
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PollingState {
+    /// ID of the next update to fetch
+    update_offset: i64,
+}
+
+/// Path of the persisted getUpdates position
+fn polling_state_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("telegram_polling.json")
+}
+
+/// A placeholder reply being edited with partial output
+struct ResponseStream {
+    /// Message ID of the placeholder
+    message_id: i64,
+    /// Task applying the partial output edits
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl TelegramApi {
+    fn text_chunk_limit(&self) -> usize {
+        self.text_chunk_limit.unwrap_or_else(|| {
+            std::env::var("TELEGRAM_TEXT_CHUNK_LIMIT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_TEXT_CHUNK_LIMIT)
+        })
+    }
+
+    /// Send a message to a chat
+    ///
+    /// Each chunk waits for a rate limiter slot. Chunks rejected with 429
+    /// are retried after the server-provided `retry_after`.
+    pub async fn send_message(&self, chat_id: &str, text: &str) -> Result<()> {
+        self.send_to_thread(chat_id, None, text).await
+    }
+
+    /// Send a message to the chat (and forum topic) a JID refers to
+    pub async fn send_to_jid(&self, jid: &str, text: &str) -> Result<()> {
+        let chat_id = chat_id_from_jid(jid)?;
+        self.send_to_thread(&chat_id, extract_topic_id_pure(jid), text)
+            .await
+    }
+
+    /// Send a message, optionally into a forum topic
+    async fn send_to_thread(&self, chat_id: &str, topic_id: Option<i64>, text: &str) -> Result<()> {
+        let cid: i64 = chat_id.parse().map_err(|_| NuClawError::Telegram {
+            message: format!("Invalid chat_id: {}", chat_id),
+        })?;
+
+        let chunks = self.chunk_text(text);
+
+        for chunk in chunks {
+            let mut payload = serde_json::json!({
+                "chat_id": cid,
+                "text": chunk,
+                "parse_mode": "HTML"
+            });
+            if let Some(topic_id) = topic_id {
+                payload["message_thread_id"] = serde_json::json!(topic_id);
+            }
+            self.send_chunk(chat_id, &payload).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Call setMessageReaction for a message
+    async fn set_reaction(&self, jid: &str, message_id: &str, emoji: &str) -> Result<()> {
+        let chat_id: i64 = chat_id_from_jid(jid)?
+            .parse()
+            .map_err(|_| NuClawError::Telegram {
+                message: format!("Invalid chat_id in jid: {}", jid),
+            })?;
+        let message_id: i64 = message_id.parse().map_err(|_| NuClawError::Telegram {
+            message: format!("Invalid message_id: {}", message_id),
+        })?;
+
+        let response = self
+            .http
+            .post(format!("{}/setMessageReaction", self.api_url))
+            .json(&serde_json::json!({
+                "chat_id": chat_id,
+                "message_id": message_id,
+                "reaction": [{ "type": "emoji", "emoji": emoji }],
+            }))
+            .send()
+            .await
+            .map_err(|e| NuClawError::Telegram {
+                message: format!("Failed to set reaction: {}", e),
+            })?;
+
+        if !response.status().is_success() {
+            return Err(NuClawError::Telegram {
+                message: format!(
+                    "setMessageReaction failed: {}",
+                    response.text().await.unwrap_or_default()
+                ),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Send a plain-text placeholder and return its message ID
+    async fn send_placeholder(&self, jid: &str, text: &str) -> Result<i64> {
+        let chat_id = chat_id_from_jid(jid)?;
+        let cid: i64 = chat_id.parse().map_err(|_| NuClawError::Telegram {
+            message: format!("Invalid chat_id: {}", chat_id),
+        })?;
+
+        let mut payload = serde_json::json!({ "chat_id": cid, "text": text });
+        if let Some(topic_id) = extract_topic_id_pure(jid) {
+            payload["message_thread_id"] = serde_json::json!(topic_id);
+        }
+
+        let result = self.send_chunk(&chat_id, &payload).await?;
+        result
+            .get("message_id")
+            .and_then(|v| v.as_i64())
+            .ok_or_else(|| NuClawError::Telegram {
+                message: "sendMessage response has no message_id".to_string(),
+            })
+    }
+
+    /// Replace the text of a message the bot sent
+    async fn edit_message(
+        &self,
+        jid: &str,
+        message_id: i64,
+        text: &str,
+        parse_mode: Option<&str>,
+    ) -> Result<()> {
+        let chat_id = chat_id_from_jid(jid)?;
+        let mut payload = serde_json::json!({
+            "chat_id": chat_id,
+            "message_id": message_id,
+            "text": text,
+        });
+        if let Some(parse_mode) = parse_mode {
+            payload["parse_mode"] = serde_json::json!(parse_mode);
+        }
+
+        self.rate_limiter.acquire(&chat_id).await;
+        match self.call("editMessageText", &payload).await {
+            // Editing to identical text is rejected but harmless
+            Err(NuClawError::Telegram { message })
+                if message.contains("message is not modified") =>
+            {
+                Ok(())
+            }
+            other => other.map(|_| ()),
+        }
+    }
+
+    /// Delete a message the bot sent
+    async fn delete_message(&self, jid: &str, message_id: i64) -> Result<()> {
+        let chat_id = chat_id_from_jid(jid)?;
+        self.call(
+            "deleteMessage",
+            &serde_json::json!({ "chat_id": chat_id, "message_id": message_id }),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Long-poll for updates starting at `offset`
+    async fn get_updates(&self, offset: i64, timeout_secs: u64) -> Result<Vec<TelegramUpdate>> {
+        let result = self
+            .call(
+                "getUpdates",
+                &serde_json::json!({
+                    "offset": offset,
+                    "timeout": timeout_secs,
+                    "allowed_updates": ["message", "inline_query"],
+                }),
+            )
+            .await?;
+        serde_json::from_value(result).map_err(|e| NuClawError::Telegram {
+            message: format!("Failed to parse updates: {}", e),
+        })
+    }
+
+    /// Answer an inline query; results are not cached since they are per user
+    async fn answer_inline_query(&self, query_id: &str, results: serde_json::Value) -> Result<()> {
+        self.call(
+            "answerInlineQuery",
+            &serde_json::json!({
+                "inline_query_id": query_id,
+                "results": results,
+                "cache_time": 0,
+                "is_personal": true,
+            }),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Call a Bot API method once, without 429 retries
+    async fn call(&self, method: &str, payload: &serde_json::Value) -> Result<serde_json::Value> {
+        let response = self
+            .http
+            .post(format!("{}/{}", self.api_url, method))
+            .json(payload)
+            .send()
+            .await;
+        health::observe(&self.health_component, &response);
+        let response = response.map_err(|e| NuClawError::Telegram {
+            message: format!("Failed to call {}: {}", method, e),
+        })?;
+
+        if !response.status().is_success() {
+            return Err(NuClawError::Telegram {
+                message: format!(
+                    "{} failed: {}",
+                    method,
+                    response.text().await.unwrap_or_default()
+                ),
+            });
+        }
+
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+        Ok(body.get("result").cloned().unwrap_or_default())
+    }
+
+    /// Edit a placeholder with partial output until the output ends
+    ///
+    /// Edits happen at most once per `interval`; the last partial output
+    /// is left for `finish_stream` to replace with the final answer.
+    async fn stream_progress(
+        &self,
+        jid: &str,
+        message_id: i64,
+        mut progress: mpsc::UnboundedReceiver<String>,
+        interval: Duration,
+    ) {
+        let mut partial = String::new();
+        let mut shown = String::new();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                line = progress.recv() => match line {
+                    Some(line) => {
+                        partial.push_str(&line);
+                        partial.push('\n');
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => {
+                    let preview = preview_text_pure(&partial, self.text_chunk_limit());
+                    if preview.is_empty() || preview == shown {
+                        continue;
+                    }
+                    match self.edit_message(jid, message_id, &preview, None).await {
+                        Ok(()) => shown = preview,
+                        Err(e) => debug!("Failed to update streamed reply: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send a single sendMessage payload, honoring rate limits
+    ///
+    /// Returns the `result` object of the Bot API response.
+    async fn send_chunk(
+        &self,
+        chat_id: &str,
+        payload: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let mut attempt = 0;
+
+        loop {
+            self.rate_limiter.acquire(chat_id).await;
+
+            let response = self
+                .http
+                .post(format!("{}/sendMessage", self.api_url))
+                .json(payload)
+                .send()
+                .await;
+            health::observe(&self.health_component, &response);
+            let response = response.map_err(|e| NuClawError::Telegram {
+                message: format!("Failed to send message: {}", e),
+            })?;
+
+            if response.status().is_success() {
+                let body: serde_json::Value = response.json().await.unwrap_or_default();
+                return Ok(body.get("result").cloned().unwrap_or_default());
+            }
+
+            let status = response.status();
+            let error = response.text().await.unwrap_or_default();
+
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS && attempt < self.max_send_retries {
+                let retry_after = parse_retry_after(&error).unwrap_or(DEFAULT_RETRY_AFTER_SECS);
+                warn!(
+                    "Telegram rate limit hit for chat {}, retrying in {}s",
+                    chat_id, retry_after
+                );
+                self.rate_limiter
+                    .penalize(chat_id, Duration::from_secs(retry_after));
+                attempt += 1;
+                continue;
+            }
+
+            return Err(NuClawError::Telegram {
+                message: format!("Failed to send message: {}", error),
+            });
+        }
+    }
+
+    /// Chunk text into smaller pieces
+    fn chunk_text(&self, text: &str) -> Vec<String> {
+        chunk_text_pure(text, self.text_chunk_limit())
+    }
 }
 
 impl TelegramClient {
-    /// Create a new Telegram client
+    /// Create a new Telegram client for the default tenant
     pub fn new(db: Database) -> Result<Self> {
-        let bot_token = std::env::var("TELEGRAM_BOT_TOKEN").map_err(|_| NuClawError::Config {
-            message: "TELEGRAM_BOT_TOKEN not set".to_string(),
-        })?;
+        Self::for_tenant(db, &tenant(DEFAULT_TENANT))
+    }
+
+    /// Create a Telegram client for a tenant's bot
+    pub fn for_tenant(db: Database, tenant: &Tenant) -> Result<Self> {
+        let bot_token = tenant
+            .telegram_bot_token()
+            .ok_or_else(|| NuClawError::Config {
+                message: if tenant.is_default() {
+                    "TELEGRAM_BOT_TOKEN not set".to_string()
+                } else {
+                    format!("Tenant {} has no telegram_bot_token", tenant.id)
+                },
+            })?;
+        let db = db.for_tenant(&tenant.id);
 
         let api_url = format!("https://api.telegram.org/bot{}", bot_token);
 
-        Ok(Self {
+        // TELEGRAM_WHITELIST_GROUPS seeds the default tenant's group allowlist
+        let env_groups: Vec<String> = std::env::var("TELEGRAM_WHITELIST_GROUPS")
+            .ok()
+            .filter(|_| tenant.is_default())
+            .map(|s| {
+                s.split(',')
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let seeded = allowlist::seed(&db, CHANNEL, AllowlistKind::Group, &env_groups)?;
+        if seeded > 0 {
+            info!("Imported {} groups from TELEGRAM_WHITELIST_GROUPS", seeded);
+        }
+        let state_dir = tenant.data_dir();
+
+        let api = TelegramApi {
             api_url,
-            webhook_path: std::env::var("TELEGRAM_WEBHOOK_PATH")
-                .unwrap_or_else(|_| "telegram-webhook".to_string()),
+            http: build_http_client()?,
+            text_chunk_limit: None,
+            rate_limiter: Arc::new(RateLimiter::new(
+                std::env::var("TELEGRAM_GLOBAL_RATE_LIMIT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_GLOBAL_RATE_LIMIT),
+                std::env::var("TELEGRAM_CHAT_RATE_LIMIT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_CHAT_RATE_LIMIT),
+            )),
+            max_send_retries: std::env::var("TELEGRAM_MAX_SEND_RETRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_SEND_RETRIES),
+            health_component: health::component(CHANNEL, &tenant.id),
+        };
+
+        Ok(Self {
+            api,
+            outbox: Outbox::new(db.clone(), CHANNEL),
+            pending: PendingQueue::new(db.clone(), CHANNEL),
+            runs: ChatQueue::from_env(),
+            webhook_path: tenant.telegram_webhook_path(),
+            webhook_secret: std::env::var("TELEGRAM_WEBHOOK_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
             dm_policy: DMPolicy::parse(
                 &std::env::var("TELEGRAM_DM_POLICY").unwrap_or_else(|_| "pairing".to_string()),
             ),
             group_policy: GroupPolicy::parse(
                 &std::env::var("TELEGRAM_GROUP_POLICY").unwrap_or_else(|_| "allowlist".to_string()),
             ),
-            text_chunk_limit: std::env::var("TELEGRAM_TEXT_CHUNK_LIMIT")
+            non_text_policy: NonTextPolicy::parse(
+                &std::env::var("TELEGRAM_NON_TEXT_POLICY")
+                    .unwrap_or_else(|_| "describe".to_string()),
+            ),
+            registered_groups: Arc::new(RwLock::new(load_registered_groups(&tenant.id))),
+            router_state: Arc::new(Mutex::new(
+                inbound::load_router_state(&db, CHANNEL, &state_dir).unwrap_or_else(|e| {
+                    warn!("Failed to load router state: {}", e);
+                    RouterState::default()
+                }),
+            )),
+            db,
+            state_dir,
+            stream_responses: std::env::var("TELEGRAM_STREAMING")
                 .ok()
                 .and_then(|v| v.parse().ok())
-                .unwrap_or(DEFAULT_TEXT_CHUNK_LIMIT),
-            allowed_groups: std::env::var("TELEGRAM_WHITELIST_GROUPS")
-                .ok()
-                .map(|s| s.split(',').map(|v| v.trim().to_string()).collect())
-                .unwrap_or_default(),
-            registered_groups: load_registered_groups(),
-            router_state: load_router_state(),
-            db,
-            assistant_name: assistant_name(),
+                .unwrap_or(true),
+            stream_interval: Duration::from_secs(
+                std::env::var("TELEGRAM_STREAM_INTERVAL")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_STREAM_INTERVAL_SECS),
+            ),
+            inline_timeout: Duration::from_secs(
+                std::env::var("TELEGRAM_INLINE_TIMEOUT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_INLINE_TIMEOUT_SECS),
+            ),
         })
     }
 
@@ -154,17 +757,87 @@ impl TelegramClient {
             info!("Webhook set to: {}", url);
         } else {
             info!("No webhook URL configured, using polling mode");
+            // getUpdates is refused while a webhook is set; pending updates are kept
+            self.api
+                .call(
+                    "deleteWebhook",
+                    &serde_json::json!({ "drop_pending_updates": false }),
+                )
+                .await?;
         }
 
         Ok(())
     }
 
+    /// Receive updates by long polling getUpdates
+    ///
+    /// The offset of the next update is persisted after each update, so
+    /// updates sent while NuClaw was down (kept by Telegram for 24 hours)
+    /// are processed on the next start.
+    pub async fn start_polling(self) -> Result<()> {
+        self.spawn_outbox_worker();
+        self.replay_pending().await;
+
+        let state_path = polling_state_path(&self.state_dir);
+        let mut state: PollingState = load_json(&state_path, PollingState::default());
+        let poll_timeout = std::env::var("TELEGRAM_POLL_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_POLL_TIMEOUT_SECS);
+        info!(
+            "Polling Telegram for updates from offset {}",
+            state.update_offset
+        );
+
+        loop {
+            let updates = tokio::select! {
+                updates = self.api.get_updates(state.update_offset, poll_timeout) => updates,
+                _ = shutdown::requested() => {
+                    info!("Stopped polling Telegram for tenant {}", self.db.tenant());
+                    return Ok(());
+                }
+            };
+            let updates = match updates {
+                Ok(updates) => updates,
+                Err(e) => {
+                    warn!("Failed to get updates: {}", e);
+                    tokio::time::sleep(Duration::from_secs(POLL_RETRY_DELAY_SECS)).await;
+                    continue;
+                }
+            };
+
+            for update in updates {
+                let update_id = update.update_id;
+                self.dispatch_update(update).await;
+                state.update_offset = update_id + 1;
+                if let Err(e) = save_json(&state_path, &state) {
+                    warn!("Failed to save update offset: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Deliver queued replies in the background
+    fn spawn_outbox_worker(&self) {
+        let api = self.api.clone();
+        tokio::spawn(self.outbox.clone().run(move |message| {
+            let api = api.clone();
+            async move { api.send_to_jid(&message.chat_jid, &message.content).await }
+        }));
+    }
+
     /// Set webhook URL
     async fn set_webhook(&self, url: &str) -> Result<()> {
         let full_url = format!("{}/webhook/{}", url, self.webhook_path);
-        let response = reqwest::Client::new()
-            .post(format!("{}/setWebhook", self.api_url))
-            .json(&serde_json::json!({ "url": full_url }))
+        let mut body = serde_json::json!({ "url": full_url });
+        if let Some(secret) = &self.webhook_secret {
+            body["secret_token"] = secret.clone().into();
+        }
+        let response = self
+            .api
+            .http
+            .post(format!("{}/setWebhook", self.api.api_url))
+            .json(&body)
             .send()
             .await
             .map_err(|e| NuClawError::Telegram {
@@ -185,41 +858,75 @@ impl TelegramClient {
 
     /// Start webhook server
     pub async fn start_webhook_server(self) -> Result<()> {
-        let addr: SocketAddr = std::env::var("TELEGRAM_WEBHOOK_BIND")
-            .unwrap_or_else(|_| "0.0.0.0:8787".to_string())
-            .parse()
-            .map_err(|_| NuClawError::Config {
-                message: "Invalid TELEGRAM_WEBHOOK_BIND".to_string(),
-            })?;
+        serve_webhooks(vec![self]).await
+    }
 
-        let client = Arc::new(Mutex::new(self));
-        let webhook_path = client.lock().await.webhook_path.clone();
+    /// Start handling this client's webhook updates; returns the route
+    /// receiving them, which checks the webhook secret if one is set
+    fn webhook_route(self) -> Router {
+        let webhook_path = self.webhook_path.clone();
+        let verifier = self
+            .webhook_secret
+            .clone()
+            .map(|secret| WebhookVerifier::new(SignatureScheme::Telegram, secret));
+        let db = self.db.clone();
+        let queue_size = std::env::var("TELEGRAM_UPDATE_QUEUE_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_UPDATE_QUEUE_SIZE);
+        let (updates, mut pending) = mpsc::channel::<TelegramUpdate>(queue_size);
+
+        // Replies are delivered by the outbox worker
+        self.spawn_outbox_worker();
+
+        // Updates are handled in order, off the request path
+        let state = WebhookState {
+            updates,
+            outbox: self.outbox.clone(),
+        };
+        let client = self;
+        tokio::spawn(async move {
+            client.replay_pending().await;
+            while let Some(update) = pending.recv().await {
+                client.dispatch_update(update).await;
+            }
+        });
 
-        let app = Router::new()
+        let route = Router::new()
             .route(&format!("/{}", webhook_path), post(handle_telegram_webhook))
-            .route("/health", get(health_check))
-            .with_state(client.clone());
-
-        info!("Starting Telegram webhook server on {}", addr);
-
-        let listener =
-            tokio::net::TcpListener::bind(&addr)
-                .await
-                .map_err(|e| NuClawError::Telegram {
-                    message: format!("Failed to bind to {}: {}", addr, e),
-                })?;
-
-        axum::serve(listener, app)
-            .await
-            .map_err(|e| NuClawError::Telegram {
-                message: format!("Webhook server error: {}", e),
-            })?;
+            .with_state(state);
+        match verifier {
+            Some(verifier) => webhook_auth::signed(route, verifier, db),
+            None => route,
+        }
+    }
 
-        Ok(())
+    /// Handle an update received by polling or webhook
+    ///
+    /// Inline queries are answered on their own task. Messages are handled
+    /// in arrival order, which only takes until their agent run is queued,
+    /// so one slow chat does not hold up the others.
+    async fn dispatch_update(&self, update: TelegramUpdate) {
+        if update.inline_query.is_some() {
+            let client = self.clone();
+            let run = shutdown::agent_runs().start();
+            tokio::spawn(async move {
+                let _run = run;
+                if let Err(e) = client.handle_update(&update).await {
+                    error!("Failed to answer inline query: {}", e);
+                }
+            });
+        } else if let Err(e) = self.handle_update(&update).await {
+            error!("Failed to handle telegram update: {}", e);
+        }
     }
 
     /// Handle a Telegram update
-    pub async fn handle_update(&mut self, update: &TelegramUpdate) -> Result<Option<String>> {
+    pub async fn handle_update(&self, update: &TelegramUpdate) -> Result<Option<String>> {
+        if let Some(query) = &update.inline_query {
+            return self.handle_inline_query(query).await;
+        }
+
         let message = match &update.message {
             Some(msg) => msg,
             None => {
@@ -228,10 +935,154 @@ impl TelegramClient {
             }
         };
 
+        if message.text.is_none() {
+            match self.non_text_policy {
+                NonTextPolicy::Describe => {}
+                NonTextPolicy::Ignore => {
+                    debug!("Ignoring non-text message {}", message.message_id);
+                    return Ok(None);
+                }
+                NonTextPolicy::Reject => {
+                    let jid = chat_jid_pure(message.chat.id, None);
+                    if is_private_chat(&jid) {
+                        self.reply(&jid, NON_TEXT_HINT).await?;
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+
         let new_message = self.parse_telegram_message(message).await?;
         self.handle_message(&new_message).await
     }
 
+    /// Answer an inline query with a single result from a short agent run
+    ///
+    /// The sender must pass the DM policy, and the query runs in the folder
+    /// registered for their private chat with the bot.
+    async fn handle_inline_query(&self, query: &TelegramInlineQuery) -> Result<Option<String>> {
+        let user_id = query.from.id.to_string();
+        let prompt = query.query.trim();
+        if prompt.is_empty() {
+            return Ok(None);
+        }
+        if !self.is_inline_user_allowed(&user_id).await? {
+            debug!("Inline query from unauthorized user: {}", user_id);
+            return Ok(None);
+        }
+
+        let chat_jid = chat_jid_pure(query.from.id, None);
+        let Some(group_folder) = self.get_group_folder(&chat_jid).await else {
+            let results = inline_results_pure(
+                "Not set up",
+                "Register a private chat with me first.",
+                self.api.text_chunk_limit(),
+            );
+            self.api.answer_inline_query(&query.id, results).await?;
+            return Ok(None);
+        };
+
+        if self.db.call(|db| Ok(is_paused(db))).await.unwrap_or(false) {
+            let results = inline_results_pure("Paused", PAUSED_NOTICE, self.api.text_chunk_limit());
+            self.api.answer_inline_query(&query.id, results).await?;
+            return Ok(None);
+        }
+
+        let correlation_id = new_correlation_id();
+        info!(
+            "Inline query from {} (ref {}): {}",
+            user_id,
+            correlation_id,
+            truncate(prompt, 50)
+        );
+
+        let input = ContainerInput {
+            prompt: prompt.to_string(),
+            session_id: Some(format!("telegram_inline_{}", query.id)),
+            resume_session: false,
+            group_folder,
+            chat_jid,
+            is_main: true,
+            is_scheduled_task: false,
+            context: vec![],
+            reply_to_id: None,
+            quoted_content: None,
+            memories: vec![],
+            timeout: None,
+            sender: Some(chat_actor(CHANNEL, &user_id)),
+            correlation_id: Some(correlation_id.clone()),
+            permissions: None,
+        };
+
+        // Inline answers are only useful right away, so failures are not retried
+        let policy = RetryPolicy::for_channel(CHANNEL).with_retries(0);
+        let run = run_container_with_retry(&self.db, input, policy, None)
+            .instrument(correlation_span(&correlation_id));
+        let (response, results) = match timeout(self.inline_timeout, run).await {
+            Ok(Ok(output)) => {
+                let text = output.result.clone().unwrap_or_default();
+                (
+                    output.result,
+                    inline_results_pure(prompt, &text, self.api.text_chunk_limit()),
+                )
+            }
+            Ok(Err(e)) => {
+                error!("Inline query container error: {}", e);
+                (
+                    None,
+                    inline_results_pure(
+                        "Error",
+                        &with_ref(&format!("Error: {}", e), Some(&correlation_id)),
+                        self.api.text_chunk_limit(),
+                    ),
+                )
+            }
+            Err(_) => {
+                warn!("Inline query {} timed out", query.id);
+                (
+                    None,
+                    inline_results_pure(
+                        "Timed out",
+                        &with_ref(
+                            "Sorry, that took too long. Ask me in a chat instead.",
+                            Some(&correlation_id),
+                        ),
+                        self.api.text_chunk_limit(),
+                    ),
+                )
+            }
+        };
+
+        self.api.answer_inline_query(&query.id, results).await?;
+        Ok(response)
+    }
+
+    /// Check an inline query sender against the DM policy
+    ///
+    /// Unlike private messages, inline queries cannot redeem pairing codes.
+    async fn is_inline_user_allowed(&self, user_id: &str) -> Result<bool> {
+        if self.is_admin(user_id).await? {
+            return Ok(true);
+        }
+        let user_id = user_id.to_string();
+        match self.dm_policy {
+            DMPolicy::Disabled => Ok(false),
+            DMPolicy::Open => Ok(true),
+            DMPolicy::Allowlist => {
+                self.db
+                    .call(move |db| {
+                        allowlist::is_allowed(db, CHANNEL, AllowlistKind::User, &user_id)
+                    })
+                    .await
+            }
+            DMPolicy::Pairing => {
+                self.db
+                    .call(move |db| is_paired(db, CHANNEL, &user_id))
+                    .await
+            }
+        }
+    }
+
     /// Parse Telegram message to unified format
     async fn parse_telegram_message(&self, msg: &TelegramMessage) -> Result<NewMessage> {
         let sender = msg
@@ -251,9 +1102,19 @@ impl TelegramClient {
             })
             .unwrap_or_else(|| "Unknown".to_string());
 
-        let chat_jid = format!("telegram:group:{}", msg.chat.id);
+        let topic_id = match msg.is_topic_message {
+            Some(true) => msg.message_thread_id,
+            _ => None,
+        };
+        let chat_jid = chat_jid_pure(msg.chat.id, topic_id);
+
+        let content = msg
+            .text
+            .clone()
+            .or_else(|| describe_non_text_pure(msg))
+            .unwrap_or_default();
 
-        let content = msg.text.clone().unwrap_or_else(|| "".to_string());
+        let (reply_to_id, quoted_content) = reply_metadata_pure(msg);
 
         Ok(NewMessage {
             id: msg.message_id.to_string(),
@@ -262,41 +1123,65 @@ impl TelegramClient {
             sender_name,
             content,
             timestamp: msg.date.to_string(),
+            reply_to_id,
+            quoted_content,
+            chat_name: msg.chat.display_name(),
+            correlation_id: None,
         })
     }
 
     /// Handle a single message
-    pub async fn handle_message(&mut self, msg: &NewMessage) -> Result<Option<String>> {
-        if self.is_duplicate_message(msg).await {
+    pub async fn handle_message(&self, msg: &NewMessage) -> Result<Option<String>> {
+        if self.is_blocked(&msg.sender).await? {
+            debug!("Ignoring message from blocked user: {}", msg.sender);
+            return Ok(None);
+        }
+        if !self.accept_message(msg).await? {
             debug!("Skipping duplicate message: {}", msg.id);
             return Ok(None);
         }
+        let id = new_correlation_id();
+        let msg = NewMessage {
+            correlation_id: Some(id.clone()),
+            ..msg.clone()
+        };
+        self.handle_accepted(&msg)
+            .instrument(correlation_span(&id))
+            .await
+    }
 
-        self.update_router_state(msg).await;
-        self.store_message(msg).await?;
+    /// Handle a message accepted for the first time
+    async fn handle_accepted(&self, msg: &NewMessage) -> Result<Option<String>> {
+        if let Some(command) = parse_command(&msg.content) {
+            return self.handle_command(msg, command).await;
+        }
 
-        // Check if it's a private message
-        if msg.chat_jid.starts_with("telegram:group:-") || !msg.chat_jid.contains(":group:") {
-            if !self.check_dm_policy(&msg.sender).await? {
+        // Private chats are governed by the DM policy, groups by the group policy
+        if is_private_chat(&msg.chat_jid) {
+            if !self.check_dm_policy(msg).await? {
                 debug!("Message from unauthorized user: {}", msg.sender);
                 return Ok(None);
             }
-        }
-
-        // Check if registered group
-        if !self.is_allowed_group(&msg.chat_jid).await? {
+            self.register_dm_chat(msg).await?;
+        } else if !self.is_allowed_group(&msg.chat_jid).await? {
             debug!("Message from unregistered group: {}", msg.chat_jid);
             return Ok(None);
         }
 
-        let (_, content) = match self.extract_trigger(&msg.content).await {
+        let (_, content) = match self.extract_trigger(&msg.chat_jid, &msg.content).await {
             Some((_, c)) => (String::new(), c),
             None => return Ok(None),
         };
 
+        let chat_jid = msg.chat_jid.clone();
+        let chat = self
+            .db
+            .call(move |db| Ok(db.chats().label(&chat_jid)))
+            .await?;
         info!(
-            "Received message from {}: {}",
+            "Received message from {} in {}: {}",
             msg.sender_name,
+            chat,
             truncate(&content, 50)
         );
 
@@ -307,93 +1192,444 @@ impl TelegramClient {
                     message: format!("Group not found: {}", msg.chat_jid),
                 })?;
 
-        let session_id = format!("telegram_{}", msg.id);
+        self.react(msg, group_settings(&group_folder).ack_reaction())
+            .await;
+        let content = match self.guard(msg, &group_folder, content).await? {
+            Guarded::Run(prompt) => prompt,
+            Guarded::Reply(reply) => {
+                self.reply(&msg.chat_jid, &reply).await?;
+                return Ok(Some(reply));
+            }
+        };
+        let pending = self.pending.clone();
+        let (message, prompt) = (msg.clone(), content.clone());
+        let pending_id = blocking(move || pending.enqueue(&message, &prompt)).await?;
+        self.queue_run(pending_id, msg.clone(), content, group_folder);
+        Ok(None)
+    }
+
+    /// Apply the content guard to a triggered message's prompt
+    async fn guard(&self, msg: &NewMessage, group_folder: &str, prompt: String) -> Result<Guarded> {
+        let (pending, message, group_folder) =
+            (self.pending.clone(), msg.clone(), group_folder.to_string());
+        self.db
+            .call(move |db| prompt_guard::apply(db, &pending, &message, &group_folder, &prompt))
+            .await
+    }
+
+    /// Start the agent run of a held message once an admin approved it
+    async fn run_approved(&self, id: i64) -> Result<()> {
+        let pending = self.pending.clone();
+        let Some(entry) = blocking(move || pending.claim_approved(id)).await? else {
+            return Ok(());
+        };
+        let Some(group_folder) = self.get_group_folder(&entry.message.chat_jid).await else {
+            let pending = self.pending.clone();
+            return blocking(move || pending.complete(entry.id)).await;
+        };
+        self.queue_run(entry.id, entry.message, entry.prompt, group_folder);
+        Ok(())
+    }
+
+    /// Re-run the agent for messages interrupted by a crash or restart
+    async fn replay_pending(&self) {
+        let pending = self.pending.clone();
+        let interrupted = match blocking(move || pending.claim_interrupted()).await {
+            Ok(interrupted) => interrupted,
+            Err(e) => {
+                error!("Failed to load pending messages: {}", e);
+                return;
+            }
+        };
+
+        for mut entry in interrupted {
+            // Pending messages of earlier versions have no correlation ID
+            entry
+                .message
+                .correlation_id
+                .get_or_insert_with(new_correlation_id);
+            info!(
+                "Replaying interrupted message {} from {} (attempt {})",
+                entry.message.id, entry.message.chat_jid, entry.attempts
+            );
+            let Some(group_folder) = self.get_group_folder(&entry.message.chat_jid).await else {
+                warn!(
+                    "Dropping pending message {}: {} is no longer registered",
+                    entry.message.id, entry.message.chat_jid
+                );
+                let pending = self.pending.clone();
+                if let Err(e) = blocking(move || pending.complete(entry.id)).await {
+                    error!("Failed to remove pending message {}: {}", entry.id, e);
+                }
+                continue;
+            };
+            self.queue_run(entry.id, entry.message, entry.prompt, group_folder);
+        }
+    }
+
+    /// Queue the agent run for an accepted message on the chat's turn
+    ///
+    /// The message leaves the pending queue once the run has finished.
+    fn queue_run(&self, pending_id: i64, msg: NewMessage, content: String, group_folder: String) {
+        let client = self.clone();
+        let chat_jid = msg.chat_jid.clone();
+        let span = correlation_span(msg.correlation_id.as_deref().unwrap_or_default());
+        self.runs.push(
+            &chat_jid,
+            async move {
+                if let Err(e) = client.run_agent(&msg, content, group_folder).await {
+                    error!("Failed to answer message {}: {}", msg.id, e);
+                }
+                let pending = client.pending.clone();
+                if let Err(e) = blocking(move || pending.complete(pending_id)).await {
+                    error!("Failed to remove pending message {}: {}", pending_id, e);
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Run the agent on a triggered message and deliver its answer
+    async fn run_agent(
+        &self,
+        msg: &NewMessage,
+        content: String,
+        group_folder: String,
+    ) -> Result<Option<String>> {
+        if self.db.call(|db| Ok(is_paused(db))).await.unwrap_or(false) {
+            self.reply(&msg.chat_jid, PAUSED_NOTICE).await?;
+            return Ok(None);
+        }
+        let settings = group_settings(&group_folder);
+        let context = {
+            let (msg, folder) = (msg.clone(), group_folder.clone());
+            self.db
+                .call(move |db| Ok(conversation_context(db, &msg, &folder)))
+                .await
+                .unwrap_or_default()
+        };
+        let resumed = {
+            let chat_jid = msg.chat_jid.clone();
+            self.db
+                .call(move |db| Ok(resume_session_id(db, &chat_jid)))
+                .await
+                .unwrap_or(None)
+        };
+        let memories = relevant_memories(&self.db, &group_folder, &content).await;
         let input = ContainerInput {
             prompt: content,
-            session_id: Some(session_id.clone()),
-            group_folder,
+            session_id: resumed.clone(),
+            resume_session: resumed.is_some(),
+            group_folder: group_folder.clone(),
             chat_jid: msg.chat_jid.clone(),
             is_main: true,
             is_scheduled_task: false,
+            context,
+            reply_to_id: msg.reply_to_id.clone(),
+            quoted_content: msg.quoted_content.clone(),
+            memories,
+            timeout: None,
+            sender: Some(chat_actor(CHANNEL, &msg.sender)),
+            correlation_id: msg.correlation_id.clone(),
+            permissions: None,
         };
 
-        let result = timeout(Duration::from_secs(300), run_container(input)).await;
+        if container_limiter().is_saturated() {
+            if let Err(e) = self.reply(&msg.chat_jid, QUEUED_NOTICE).await {
+                warn!("Failed to send queued notice to {}: {}", msg.chat_jid, e);
+            }
+        }
 
-        match result {
-            Ok(Ok(output)) => {
-                if let Some(response) = output.result {
-                    let chat_id = self.extract_chat_id(&msg.chat_jid)?;
-                    self.send_message(&chat_id.to_string(), &response).await?;
-                    return Ok(Some(response));
-                }
+        let (progress_tx, progress_rx) = mpsc::unbounded_channel();
+        let stream = if self.stream_responses {
+            self.start_stream(&msg.chat_jid, progress_rx).await
+        } else {
+            None
+        };
+        let progress = stream.as_ref().map(|_| progress_tx);
+
+        // Each attempt is bounded by the run's own timeout
+        let result =
+            run_container_with_retry(&self.db, input, RetryPolicy::for_channel(CHANNEL), progress)
+                .await;
+        let folder = group_folder.clone();
+        if let Err(e) = self
+            .db
+            .call(move |db| process_ipc_requests(db, &folder))
+            .await
+        {
+            warn!("Failed to process IPC requests for {}: {}", group_folder, e);
+        }
+        if let Ok(output) = &result {
+            let (chat_jid, output) = (msg.chat_jid.clone(), output.clone());
+            if let Err(e) = self
+                .db
+                .call(move |db| record_run_session(db, &chat_jid, resumed.as_deref(), &output))
+                .await
+            {
+                warn!("Failed to store the session of {}: {}", msg.chat_jid, e);
             }
-            Ok(Err(e)) => {
+        }
+
+        let (reply, response) = match result {
+            Ok(output) => {
+                self.react(msg, settings.done_reaction()).await;
+                (output.result.clone(), output.result)
+            }
+            Err(NuClawError::Cancelled { .. }) => {
+                info!("Run for {} was cancelled", msg.chat_jid);
+                self.react(msg, settings.error_reaction()).await;
+                (Some(CANCELLED_NOTICE.to_string()), None)
+            }
+            Err(e) => {
                 error!("Container error: {}", e);
-                let chat_id = self.extract_chat_id(&msg.chat_jid)?;
-                self.send_message(&chat_id.to_string(), &format!("Error: {}", e))
-                    .await?;
+                self.react(msg, settings.error_reaction()).await;
+                let reply = with_ref(&format!("Error: {}", e), msg.correlation_id.as_deref());
+                (Some(reply), None)
+            }
+        };
+
+        match (stream, reply) {
+            (Some(stream), reply) => {
+                self.finish_stream(&msg.chat_jid, stream, reply.as_deref())
+                    .await?
+            }
+            (None, Some(reply)) => self.reply(&msg.chat_jid, &reply).await?,
+            (None, None) => {}
+        }
+
+        Ok(response)
+    }
+
+    /// Post a placeholder reply and start editing it with partial output
+    ///
+    /// Returns `None` if the placeholder could not be sent; the reply is
+    /// then delivered in one piece as usual.
+    async fn start_stream(
+        &self,
+        jid: &str,
+        progress: mpsc::UnboundedReceiver<String>,
+    ) -> Option<ResponseStream> {
+        let message_id = match self.api.send_placeholder(jid, STREAM_PLACEHOLDER).await {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Failed to send placeholder, not streaming: {}", e);
+                return None;
+            }
+        };
+
+        let api = self.api.clone();
+        let jid = jid.to_string();
+        let interval = self.stream_interval;
+        let task = tokio::spawn(async move {
+            api.stream_progress(&jid, message_id, progress, interval)
+                .await
+        });
+
+        Some(ResponseStream { message_id, task })
+    }
+
+    /// Replace a streamed placeholder with the final answer
+    ///
+    /// The first chunk goes into the placeholder, the rest are queued as
+    /// follow-up messages. Without an answer the placeholder is removed.
+    async fn finish_stream(
+        &self,
+        jid: &str,
+        stream: ResponseStream,
+        text: Option<&str>,
+    ) -> Result<()> {
+        // Ends once the container has dropped its progress sender
+        let _ = stream.task.await;
+
+        let text = match text.filter(|t| !t.trim().is_empty()) {
+            Some(text) => text,
+            None => {
+                if let Err(e) = self.api.delete_message(jid, stream.message_id).await {
+                    warn!("Failed to remove placeholder: {}", e);
+                }
+                return Ok(());
             }
-            Err(_) => {
-                error!("Container timeout");
-                let chat_id = self.extract_chat_id(&msg.chat_jid)?;
-                self.send_message(&chat_id.to_string(), "Sorry, the request timed out.")
-                    .await?;
+        };
+
+        let chunks = self.api.chunk_text(text);
+        if let Some((first, rest)) = chunks.split_first() {
+            if let Err(e) = self
+                .api
+                .edit_message(jid, stream.message_id, first, Some("HTML"))
+                .await
+            {
+                warn!("Failed to finalize streamed reply, resending: {}", e);
+                self.reply(jid, first).await?;
+            }
+            for chunk in rest {
+                self.reply(jid, chunk).await?;
             }
         }
 
-        Ok(None)
+        Ok(())
     }
 
-    /// Send a message to a chat
+    /// Send a message to a chat right away, bypassing the outbox
     pub async fn send_message(&self, chat_id: &str, text: &str) -> Result<()> {
-        let cid: i64 = chat_id.parse().map_err(|_| NuClawError::Telegram {
-            message: format!("Invalid chat_id: {}", chat_id),
-        })?;
-
-        let chunks = self.chunk_text(text);
-
-        for chunk in chunks {
-            let payload = serde_json::json!({
-                "chat_id": cid,
-                "text": chunk,
-                "parse_mode": "HTML"
-            });
+        self.api.send_message(chat_id, text).await
+    }
 
-            let response = reqwest::Client::new()
-                .post(&format!("{}/sendMessage", self.api_url))
-                .json(&payload)
-                .timeout(Duration::from_secs(30))
-                .send()
-                .await
-                .map_err(|e| NuClawError::Telegram {
-                    message: format!("Failed to send message: {}", e),
-                })?;
+    /// Queue a reply to the chat (and forum topic) a JID refers to
+    ///
+    /// Delivery happens in the outbox worker, so handling a message
+    /// never waits on the Bot API.
+    pub async fn reply(&self, jid: &str, text: &str) -> Result<()> {
+        let outbox = self.outbox.clone();
+        let (jid, text) = (jid.to_string(), text.to_string());
+        blocking(move || outbox.enqueue(&jid, &text)).await?;
+        Ok(())
+    }
 
-            if !response.status().is_success() {
-                let error = response.text().await.unwrap_or_default();
-                return Err(NuClawError::Telegram {
-                    message: format!("Failed to send message: {}", error),
-                });
-            }
+    /// Set the bot's reaction on a message, replacing any previous one
+    ///
+    /// Reactions are feedback only, so failures are logged and ignored.
+    async fn react(&self, msg: &NewMessage, emoji: Option<String>) {
+        let Some(emoji) = emoji else {
+            return;
+        };
+        if let Err(e) = self.api.set_reaction(&msg.chat_jid, &msg.id, &emoji).await {
+            warn!("Failed to set reaction on {}: {}", msg.id, e);
         }
+    }
 
-        Ok(())
+    /// Whether a sender holds `admin` or `owner` for all groups; they
+    /// pass the DM policy
+    async fn is_admin(&self, sender: &str) -> Result<bool> {
+        let sender = sender.to_string();
+        self.db
+            .call(move |db| roles::is_admin(db, CHANNEL, &sender))
+            .await
     }
 
-    /// Chunk text into smaller pieces
-    fn chunk_text(&self, text: &str) -> Vec<String> {
-        chunk_text_pure(text, self.text_chunk_limit)
+    /// Whether the sender is on the blocklist
+    async fn is_blocked(&self, sender: &str) -> Result<bool> {
+        let sender = sender.to_string();
+        self.db
+            .call(move |db| blocklist::is_blocked(db, CHANNEL, &sender))
+            .await
     }
 
     /// Check DM policy
-    async fn check_dm_policy(&self, _user_id: &str) -> Result<bool> {
+    ///
+    /// Under the pairing policy, a DM carrying a valid pairing code pairs
+    /// the sender; the code itself is not forwarded to the agent.
+    async fn check_dm_policy(&self, msg: &NewMessage) -> Result<bool> {
         match self.dm_policy {
             DMPolicy::Disabled => Ok(false),
             DMPolicy::Open => Ok(true),
-            DMPolicy::Allowlist | DMPolicy::Pairing => {
-                // Allow for now (can be extended with database check)
-                Ok(true)
+            DMPolicy::Allowlist => {
+                if self.is_admin(&msg.sender).await? {
+                    return Ok(true);
+                }
+                let (sender, chat_jid) = (msg.sender.clone(), msg.chat_jid.clone());
+                self.db
+                    .call(move |db| {
+                        let allowed =
+                            allowlist::is_allowed(db, CHANNEL, AllowlistKind::User, &sender)?;
+                        if !allowed {
+                            audit::record(
+                                db,
+                                AuditEvent::new(
+                                    AuditKind::Denied,
+                                    chat_actor(CHANNEL, &sender),
+                                    "DM refused: sender is not on the allowlist",
+                                )
+                                .in_chat(&chat_jid),
+                            );
+                        }
+                        Ok(allowed)
+                    })
+                    .await
+            }
+            DMPolicy::Pairing => {
+                if self.is_admin(&msg.sender).await? {
+                    return Ok(true);
+                }
+                let (sender, content) = (msg.sender.clone(), msg.content.clone());
+                let status = self
+                    .db
+                    .call(move |db| check_pairing(db, CHANNEL, &sender, &content))
+                    .await?;
+                match status {
+                    PairingStatus::Paired => Ok(true),
+                    PairingStatus::JustPaired => {
+                        info!("Paired Telegram user {}", msg.sender);
+                        self.reply(
+                            &msg.chat_jid,
+                            "Paired successfully. You can now talk to me here.",
+                        )
+                        .await?;
+                        Ok(false)
+                    }
+                    PairingStatus::InvalidCode => {
+                        self.reply(
+                            &msg.chat_jid,
+                            "That pairing code is invalid or has expired.",
+                        )
+                        .await?;
+                        Ok(false)
+                    }
+                    PairingStatus::Unpaired => {
+                        if unpaired_notice_due(CHANNEL, &msg.sender) {
+                            self.reply(&msg.chat_jid, UNPAIRED_NOTICE).await?;
+                        }
+                        Ok(false)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Execute a chat command and reply with its result
+    async fn handle_command(
+        &self,
+        msg: &NewMessage,
+        command: ChatCommand,
+    ) -> Result<Option<String>> {
+        let registers = matches!(command, ChatCommand::Register(_));
+        let approved = match command {
+            ChatCommand::Approve(id) => Some(id),
+            _ => None,
+        };
+        let chat_id = chat_id_from_jid(&msg.chat_jid)?;
+        let (sender, chat_jid, text) = (
+            msg.sender.clone(),
+            msg.chat_jid.clone(),
+            msg.content.clone(),
+        );
+        let reply = self
+            .db
+            .call(move |db| {
+                let ctx = CommandContext {
+                    channel: CHANNEL,
+                    sender: &sender,
+                    chat_jid: &chat_jid,
+                    chat_id: &chat_id,
+                    is_private: is_private_chat(&chat_jid),
+                    text: &text,
+                };
+                execute_command(db, &ctx, command)
+            })
+            .await?;
+        if registers {
+            *self.registered_groups.write().unwrap() = load_registered_groups(self.db.tenant());
+        }
+        if let Some(id) = approved.filter(|_| reply.is_some()) {
+            self.run_approved(id).await?;
+        }
+
+        match reply {
+            Some(reply) => {
+                self.reply(&msg.chat_jid, &reply).await?;
+                Ok(Some(reply))
             }
+            None => Ok(None),
         }
     }
 
@@ -403,13 +1639,21 @@ impl TelegramClient {
             GroupPolicy::Disabled => Ok(false),
             GroupPolicy::Open => Ok(true),
             GroupPolicy::Allowlist => {
-                // Extract chat_id from jid
-                if let Some(chat_id) = chat_jid.strip_prefix("telegram:group:") {
-                    let result = self
-                        .allowed_groups
-                        .iter()
-                        .any(|g| g == chat_id || g == &format!("-{}", chat_id));
-                    Ok(result)
+                // Extract chat_id from jid; topics share their group's entry
+                if let Some(chat_id) = extract_chat_id_pure(chat_jid) {
+                    self.db
+                        .call(move |db| {
+                            Ok(
+                                allowlist::is_allowed(db, CHANNEL, AllowlistKind::Group, &chat_id)?
+                                    || allowlist::is_allowed(
+                                        db,
+                                        CHANNEL,
+                                        AllowlistKind::Group,
+                                        &format!("-{}", chat_id),
+                                    )?,
+                            )
+                        })
+                        .await
                 } else {
                     Ok(false)
                 }
@@ -417,123 +1661,193 @@ impl TelegramClient {
         }
     }
 
-    /// Get group folder for a chat JID
-    async fn get_group_folder(&self, jid: &str) -> Option<String> {
-        self.registered_groups.get(jid).map(|g| g.folder.clone())
-    }
-
-    /// Extract chat ID from jid
-    fn extract_chat_id(&self, jid: &str) -> Result<String> {
-        extract_chat_id_pure(jid).ok_or_else(|| NuClawError::Telegram {
-            message: format!("Invalid telegram jid format: {}", jid),
-        })
-    }
-
-    /// Check if message is duplicate
-    async fn is_duplicate_message(&self, msg: &NewMessage) -> bool {
-        let last_timestamp = &self.router_state.last_timestamp;
-        let last_agent = self.router_state.last_agent_timestamp.get(&msg.chat_jid);
-
-        if last_timestamp == &msg.timestamp {
-            return true;
-        }
-
-        if let Some(agent_ts) = last_agent {
-            if agent_ts == &msg.timestamp {
-                return true;
-            }
+    /// Register an accepted private chat, so its user has a group folder
+    async fn register_dm_chat(&self, msg: &NewMessage) -> Result<()> {
+        if self.get_group_folder(&msg.chat_jid).await.is_some() {
+            return Ok(());
         }
-
-        false
+        let (tenant_id, chat_jid, sender) = (
+            self.db.tenant().to_string(),
+            msg.chat_jid.clone(),
+            msg.sender.clone(),
+        );
+        let group =
+            blocking(move || register_dm_chat(&tenant_id, CHANNEL, &chat_jid, &sender)).await?;
+        info!(
+            "Registered the private chat of {} as {}",
+            msg.sender, group.folder
+        );
+        self.registered_groups
+            .write()
+            .unwrap()
+            .insert(msg.chat_jid.clone(), group);
+        Ok(())
     }
 
-    /// Update router state after processing
-    async fn update_router_state(&mut self, msg: &NewMessage) {
-        self.router_state.last_timestamp = msg.timestamp.clone();
-        self.router_state
-            .last_agent_timestamp
-            .insert(msg.chat_jid.clone(), msg.timestamp.clone());
-
-        let state_path = data_dir().join("router_state.json");
-        let _ = save_json(&state_path, &self.router_state);
+    /// Get group folder for a chat JID
+    ///
+    /// A forum topic uses its own registration if it has one, otherwise
+    /// the registration of the group it belongs to.
+    async fn get_group_folder(&self, jid: &str) -> Option<String> {
+        let groups = self.registered_groups.read().unwrap();
+        groups
+            .get(jid)
+            .or_else(|| groups.get(&group_jid_pure(jid)))
+            .map(|g| g.folder.clone())
     }
 
-    /// Store message in database
-    async fn store_message(&self, msg: &NewMessage) -> Result<()> {
-        let conn = self
+    /// Store a message with its chat and the router state, unless it is a
+    /// duplicate; `false` for duplicates
+    async fn accept_message(&self, msg: &NewMessage) -> Result<bool> {
+        let stored = msg.clone();
+        let accepted = self
             .db
-            .get_connection()
+            .call(move |db| {
+                inbound::accept_message(db, CHANNEL, &stored, stored.id.starts_with("self"))
+            })
+            .await
             .map_err(|e| NuClawError::Database {
-                message: e.to_string(),
+                message: format!("Failed to store message: {}", e),
             })?;
-
-        conn.execute(
-            "INSERT OR REPLACE INTO messages (id, chat_jid, sender, sender_name, content, timestamp, is_from_me)
-             VALUES (?, ?, ?, ?, ?, ?, ?)",
-            rusqlite::params![
-                msg.id,
-                msg.chat_jid,
-                msg.sender,
-                msg.sender_name,
-                msg.content,
-                msg.timestamp,
-                if msg.id.starts_with("self") { 1 } else { 0 },
-            ],
-        ).map_err(|e| NuClawError::Database {
-            message: format!("Failed to store message: {}", e),
-        })?;
-
-        Ok(())
+        if accepted {
+            let mut router_state = self.router_state.lock().unwrap();
+            router_state.last_timestamp = msg.timestamp.clone();
+            router_state
+                .last_agent_timestamp
+                .insert(msg.chat_jid.clone(), msg.timestamp.clone());
+        }
+        Ok(accepted)
     }
 
     /// Extract trigger and content from message
-    async fn extract_trigger(&self, content: &str) -> Option<(String, String)> {
-        let trigger_pattern = format!("@{}", self.assistant_name);
-
-        if let Some(idx) = content.find(&trigger_pattern) {
-            let after = &content[idx + trigger_pattern.len()..];
-            let c = after.trim().to_string();
-            return Some((trigger_pattern, c));
-        }
-
-        None
+    ///
+    /// Uses the chat's triggers, from its registration and its group
+    /// folder's config file (a topic falls back to its group's), or
+    /// `@<assistant name>` if none are configured.
+    async fn extract_trigger(&self, chat_jid: &str, content: &str) -> Option<(String, String)> {
+        let groups = self.registered_groups.read().unwrap();
+        let triggers = groups
+            .get(chat_jid)
+            .or_else(|| groups.get(&group_jid_pure(chat_jid)))
+            .map(|g| with_group_file(g).triggers())
+            .filter(|t| !t.is_empty())
+            .unwrap_or_else(|| vec![format!("@{}", tenant(self.db.tenant()).assistant_name())]);
+        match_trigger(content, &triggers)
     }
 }
 
+/// Shared state for the webhook handler
+#[derive(Clone)]
+struct WebhookState {
+    /// Queue of updates waiting to be handled
+    updates: mpsc::Sender<TelegramUpdate>,
+    /// Outbound queue, checked for backpressure
+    outbox: Outbox,
+}
+
 // Webhook handler
+//
+// Queues the update and returns immediately. When the update queue or the
+// outbox is full, answers 503 so Telegram redelivers the update later.
 async fn handle_telegram_webhook(
-    client: axum::extract::State<Arc<Mutex<TelegramClient>>>,
+    State(state): State<WebhookState>,
     Json(update): Json<TelegramUpdate>,
-) -> &'static str {
-    let mut client = client.lock().await;
-    if let Err(e) = client.handle_update(&update).await {
-        error!("Failed to handle telegram update: {}", e);
+) -> (StatusCode, &'static str) {
+    let outbox = state.outbox.clone();
+    if blocking(move || outbox.is_full()).await.unwrap_or(false) {
+        warn!("Outbox full, deferring update {}", update.update_id);
+        return (StatusCode::SERVICE_UNAVAILABLE, "Busy");
+    }
+
+    match state.updates.try_send(update) {
+        Ok(()) => (StatusCode::OK, "OK"),
+        Err(mpsc::error::TrySendError::Full(update)) => {
+            warn!("Update queue full, deferring update {}", update.update_id);
+            (StatusCode::SERVICE_UNAVAILABLE, "Busy")
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => {
+            error!("Update handler stopped");
+            (StatusCode::SERVICE_UNAVAILABLE, "Unavailable")
+        }
     }
-    "OK"
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+/// Serve the webhooks of several clients, e.g. one per tenant, on
+/// `TELEGRAM_WEBHOOK_BIND`
+///
+/// Stops taking requests once NuClaw is shutting down.
+pub async fn serve_webhooks(clients: Vec<TelegramClient>) -> Result<()> {
+    let addr: SocketAddr = std::env::var("TELEGRAM_WEBHOOK_BIND")
+        .unwrap_or_else(|_| "0.0.0.0:8787".to_string())
+        .parse()
+        .map_err(|_| NuClawError::Config {
+            message: "Invalid TELEGRAM_WEBHOOK_BIND".to_string(),
+        })?;
+
+    let mut app = Router::new();
+    for client in clients {
+        info!(
+            "Serving the Telegram webhook of tenant {} at /{}",
+            client.db.tenant(),
+            client.webhook_path
+        );
+        app = app.merge(client.webhook_route());
+    }
+
+    info!("Starting Telegram webhook server on {}", addr);
+
+    let listener =
+        tokio::net::TcpListener::bind(&addr)
+            .await
+            .map_err(|e| NuClawError::Telegram {
+                message: format!("Failed to bind to {}: {}", addr, e),
+            })?;
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown::requested())
+        .await
+        .map_err(|e| NuClawError::Telegram {
+            message: format!("Webhook server error: {}", e),
+        })?;
+
+    info!("Telegram webhook server stopped");
+    Ok(())
 }
 
 // Helper functions
 
-/// Load router state from file
-pub fn load_router_state() -> RouterState {
-    let state_path = data_dir().join("router_state.json");
-    load_json(
-        &state_path,
-        RouterState {
-            last_timestamp: String::new(),
-            last_agent_timestamp: HashMap::new(),
-        },
-    )
-}
+/// Build the Bot API HTTP client from environment settings
+///
+/// `TELEGRAM_PROXY` routes all Bot API traffic through the given proxy
+/// (http or https URL).
+fn build_http_client() -> Result<reqwest::Client> {
+    let timeout_secs = std::env::var("TELEGRAM_HTTP_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_HTTP_TIMEOUT_SECS);
+    let connect_timeout_secs = std::env::var("TELEGRAM_CONNECT_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS);
+
+    let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(timeout_secs))
+        .connect_timeout(Duration::from_secs(connect_timeout_secs))
+        .pool_max_idle_per_host(DEFAULT_POOL_MAX_IDLE)
+        .tcp_keepalive(Duration::from_secs(60));
+
+    if let Ok(proxy_url) = std::env::var("TELEGRAM_PROXY") {
+        if !proxy_url.trim().is_empty() {
+            let proxy = reqwest::Proxy::all(proxy_url.trim()).map_err(|e| NuClawError::Config {
+                message: format!("Invalid TELEGRAM_PROXY: {}", e),
+            })?;
+            builder = builder.proxy(proxy);
+        }
+    }
 
-/// Load registered groups from file
-pub fn load_registered_groups() -> HashMap<String, RegisteredGroup> {
-    let path = data_dir().join("registered_groups.json");
-    load_json(&path, HashMap::new())
+    builder.build().map_err(|e| NuClawError::Config {
+        message: format!("Failed to build HTTP client: {}", e),
+    })
 }
 
 /// Helper to truncate strings
@@ -575,28 +1889,136 @@ pub fn chunk_text_pure(text: &str, chunk_limit: usize) -> Vec<String> {
     chunks
 }
 
+/// Build a chat JID, with a topic suffix for forum topics (pure function)
+pub fn chat_jid_pure(chat_id: i64, topic_id: Option<i64>) -> String {
+    match topic_id {
+        Some(topic_id) => format!("telegram:group:{}:topic:{}", chat_id, topic_id),
+        None => format!("telegram:group:{}", chat_id),
+    }
+}
+
+/// Extract chat ID from jid, failing on malformed jids
+fn chat_id_from_jid(jid: &str) -> Result<String> {
+    extract_chat_id_pure(jid).ok_or_else(|| NuClawError::Telegram {
+        message: format!("Invalid telegram jid format: {}", jid),
+    })
+}
+
 /// Extract chat ID from jid (pure function)
 pub fn extract_chat_id_pure(jid: &str) -> Option<String> {
-    jid.strip_prefix("telegram:group:").map(|s| s.to_string())
+    let rest = jid.strip_prefix("telegram:group:")?;
+    let chat_id = rest.split(":topic:").next().unwrap_or(rest);
+    Some(chat_id.to_string())
 }
 
-/// Check if message is duplicate (pure function)
-pub fn is_duplicate_message_pure(
-    msg: &NewMessage,
-    last_timestamp: &str,
-    last_agent_timestamps: &std::collections::HashMap<String, String>,
-) -> bool {
-    if last_timestamp == msg.timestamp {
-        return true;
+/// Extract forum topic ID from jid (pure function)
+pub fn extract_topic_id_pure(jid: &str) -> Option<i64> {
+    jid.strip_prefix("telegram:group:")?
+        .split_once(":topic:")
+        .and_then(|(_, topic)| topic.parse().ok())
+}
+
+/// Strip the topic suffix from a jid (pure function)
+pub fn group_jid_pure(jid: &str) -> String {
+    match jid.split_once(":topic:") {
+        Some((group, _)) => group.to_string(),
+        None => jid.to_string(),
+    }
+}
+
+/// Check if a chat JID refers to a private chat
+///
+/// Telegram private chats share the user's (positive) ID; groups,
+/// supergroups, and channels have negative IDs.
+pub fn is_private_chat(jid: &str) -> bool {
+    extract_chat_id_pure(jid)
+        .map(|id| !id.starts_with('-'))
+        .unwrap_or(false)
+}
+
+/// Build `answerInlineQuery` results for a single article (pure function)
+///
+/// The message sent on selection is cut to `text_limit` characters.
+pub fn inline_results_pure(title: &str, text: &str, text_limit: usize) -> serde_json::Value {
+    let title: String = title.chars().take(INLINE_TITLE_LEN).collect();
+    let text: String = text.chars().take(text_limit).collect();
+    let description: String = text.chars().take(INLINE_TITLE_LEN * 2).collect();
+    serde_json::json!([{
+        "type": "article",
+        "id": "0",
+        "title": title,
+        "description": description,
+        "input_message_content": { "message_text": text },
+    }])
+}
+
+/// Describe a message without text for the agent (pure function)
+///
+/// The caption, if any, follows the description so triggers in captions
+/// still work. Returns `None` for kinds that are not recognized.
+/// ID and quoted text of the message a Telegram message replies to
+///
+/// In forum topics every message "replies" to the topic's first message,
+/// which is not a real reply and is ignored.
+pub fn reply_metadata_pure(msg: &TelegramMessage) -> (Option<String>, Option<String>) {
+    let Some(reply) = msg.reply_to_message.as_deref() else {
+        return (None, None);
+    };
+    if msg.is_topic_message == Some(true) && msg.message_thread_id == Some(reply.message_id) {
+        return (None, None);
     }
 
-    if let Some(agent_ts) = last_agent_timestamps.get(&msg.chat_jid) {
-        if agent_ts == &msg.timestamp {
-            return true;
+    let quoted = msg
+        .quote
+        .as_ref()
+        .map(|q| q.text.clone())
+        .or_else(|| reply.text.clone())
+        .or_else(|| describe_non_text_pure(reply));
+    (Some(reply.message_id.to_string()), quoted)
+}
+
+pub fn describe_non_text_pure(msg: &TelegramMessage) -> Option<String> {
+    let description = if let Some(sticker) = &msg.sticker {
+        match &sticker.emoji {
+            Some(emoji) => format!("[Sticker {}]", emoji),
+            None => "[Sticker]".to_string(),
+        }
+    } else if let Some(animation) = &msg.animation {
+        match &animation.file_name {
+            Some(name) => format!("[GIF: {}]", name),
+            None => "[GIF]".to_string(),
         }
+    } else if let Some(poll) = &msg.poll {
+        let options: Vec<&str> = poll.options.iter().map(|o| o.text.as_str()).collect();
+        format!("[Poll: {} Options: {}]", poll.question, options.join(" / "))
+    } else if let Some(contact) = &msg.contact {
+        let name = match &contact.last_name {
+            Some(last) => format!("{} {}", contact.first_name, last),
+            None => contact.first_name.clone(),
+        };
+        format!("[Contact: {}, {}]", name, contact.phone_number)
+    } else if let Some(location) = &msg.location {
+        format!("[Location: {}, {}]", location.latitude, location.longitude)
+    } else {
+        return msg.caption.clone();
+    };
+
+    Some(match &msg.caption {
+        Some(caption) => format!("{} {}", description, caption),
+        None => description,
+    })
+}
+
+/// Tail of partial output that fits in one message (pure function)
+pub fn preview_text_pure(text: &str, limit: usize) -> String {
+    let text = text.trim();
+    let len = text.chars().count();
+    if len <= limit {
+        return text.to_string();
     }
 
-    false
+    let tail: String = text.chars().skip(len - limit.saturating_sub(1)).collect();
+    format!("…{}", tail)
 }
 
 /// Check if group is allowed (pure function)
@@ -609,10 +2031,10 @@ pub fn is_allowed_group_pure(
         GroupPolicy::Disabled => false,
         GroupPolicy::Open => true,
         GroupPolicy::Allowlist => {
-            if let Some(chat_id) = chat_jid.strip_prefix("telegram:group:") {
+            if let Some(chat_id) = extract_chat_id_pure(chat_jid) {
                 allowed_groups
                     .iter()
-                    .any(|g| g == chat_id || g == &format!("-{}", chat_id))
+                    .any(|g| g == &chat_id || g == &format!("-{}", chat_id))
             } else {
                 false
             }
@@ -622,14 +2044,12 @@ pub fn is_allowed_group_pure(
 
 // Trait implementations for enums
 
-impl DMPolicy {
+impl NonTextPolicy {
     pub fn parse(s: &str) -> Self {
         match s.to_lowercase().as_str() {
-            "pairing" => DMPolicy::Pairing,
-            "allowlist" => DMPolicy::Allowlist,
-            "open" => DMPolicy::Open,
-            "disabled" => DMPolicy::Disabled,
-            _ => DMPolicy::Pairing,
+            "ignore" => NonTextPolicy::Ignore,
+            "reject" => NonTextPolicy::Reject,
+            _ => NonTextPolicy::Describe,
         }
     }
 }
@@ -649,6 +2069,131 @@ impl GroupPolicy {
 mod tests {
     use super::*;
 
+    fn test_client(
+        dm_policy: DMPolicy,
+        group_policy: GroupPolicy,
+        text_chunk_limit: usize,
+    ) -> TelegramClient {
+        let db = Database::new().unwrap();
+        TelegramClient {
+            api: TelegramApi {
+                api_url: "https://api.telegram.org/bottest".to_string(),
+                http: reqwest::Client::new(),
+                text_chunk_limit: Some(text_chunk_limit),
+                rate_limiter: Arc::new(RateLimiter::new(0.0, 0.0)),
+                max_send_retries: DEFAULT_MAX_SEND_RETRIES,
+                health_component: CHANNEL.to_string(),
+            },
+            outbox: Outbox::new(db.clone(), CHANNEL),
+            pending: PendingQueue::new(db.clone(), CHANNEL),
+            runs: ChatQueue::from_env(),
+            webhook_path: "webhook".to_string(),
+            webhook_secret: None,
+            dm_policy,
+            group_policy,
+            non_text_policy: NonTextPolicy::Describe,
+            registered_groups: Arc::default(),
+            router_state: Arc::default(),
+            db,
+            state_dir: crate::config::data_dir(),
+            stream_responses: false,
+            stream_interval: Duration::from_secs(DEFAULT_STREAM_INTERVAL_SECS),
+            inline_timeout: Duration::from_secs(DEFAULT_INLINE_TIMEOUT_SECS),
+        }
+    }
+
+    /// Webhook throughput while the update handler works the database
+    ///
+    /// Run with `cargo test --release webhook_throughput -- --ignored --nocapture`.
+    /// Also reports the longest a 1 ms timer on the same runtime was late,
+    /// which grows when database calls block the worker threads.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[ignore]
+    async fn bench_webhook_throughput() {
+        const UPDATES: i64 = 2000;
+        const CONCURRENCY: i64 = 32;
+
+        let client = test_client(DMPolicy::Open, GroupPolicy::Disabled, 4096);
+        let (updates, mut queued) = mpsc::channel::<TelegramUpdate>(UPDATES as usize);
+        let app = Router::new()
+            .route("/webhook", post(handle_telegram_webhook))
+            .with_state(WebhookState {
+                updates,
+                outbox: client.outbox.clone(),
+            });
+        let handler = tokio::spawn(async move {
+            for _ in 0..UPDATES {
+                let update = queued.recv().await.unwrap();
+                client.dispatch_update(update).await;
+            }
+        });
+
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let ticker = {
+            let done = done.clone();
+            tokio::spawn(async move {
+                let mut max_lag = Duration::ZERO;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    let start = std::time::Instant::now();
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    max_lag = max_lag.max(start.elapsed().saturating_sub(Duration::from_millis(1)));
+                }
+                max_lag
+            })
+        };
+
+        // A fresh chat per run, so messages are not skipped as duplicates
+        let chat_id = -(rand::random::<u32>() as i64) - 1;
+        let start = std::time::Instant::now();
+        let senders: Vec<_> = (0..CONCURRENCY)
+            .map(|worker| {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    for i in (worker..UPDATES).step_by(CONCURRENCY as usize) {
+                        let body = serde_json::json!({
+                            "update_id": i,
+                            "message": {
+                                "message_id": i,
+                                "from": {"id": 7, "is_bot": false, "first_name": "Bench"},
+                                "chat": {"id": chat_id, "type": "group", "title": "Bench"},
+                                "date": 1700000000,
+                                "text": format!("message {}", i),
+                            }
+                        });
+                        let request = axum::http::Request::post("/webhook")
+                            .header("content-type", "application/json")
+                            .body(axum::body::Body::from(body.to_string()))
+                            .unwrap();
+                        let response = tower::ServiceExt::oneshot(app.clone(), request)
+                            .await
+                            .unwrap();
+                        assert_eq!(response.status(), StatusCode::OK);
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.await.unwrap();
+        }
+        handler.await.unwrap();
+        let elapsed = start.elapsed();
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        let max_lag = ticker.await.unwrap();
+
+        println!(
+            "{} updates in {:?}: {:.0} updates/s, max timer lag {:?}",
+            UPDATES,
+            elapsed,
+            UPDATES as f64 / elapsed.as_secs_f64(),
+            max_lag
+        );
+    }
+
+    #[test]
+    fn test_build_http_client() {
+        assert!(build_http_client().is_ok());
+    }
+
     #[test]
     fn test_parse_telegram_update() {
         let json = r#"{
@@ -667,24 +2212,115 @@ mod tests {
         assert!(update.message.is_some());
     }
 
+    #[tokio::test]
+    async fn test_parse_forum_topic_message() {
+        let json = r#"{
+            "update_id": 124,
+            "message": {
+                "message_id": 457,
+                "message_thread_id": 42,
+                "is_topic_message": true,
+                "from": {"id": 789, "is_bot": false, "first_name": "Test"},
+                "chat": {"id": -100123, "type": "supergroup", "title": "Forum"},
+                "date": 1234567890,
+                "text": "@Andy hello"
+            }
+        }"#;
+
+        let update: TelegramUpdate = serde_json::from_str(json).unwrap();
+        let client = test_client(DMPolicy::Open, GroupPolicy::Open, 4000);
+        let msg = client
+            .parse_telegram_message(update.message.as_ref().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(msg.chat_jid, "telegram:group:-100123:topic:42");
+        assert_eq!(msg.chat_name.as_deref(), Some("Forum"));
+    }
+
+    #[test]
+    fn test_chat_display_name() {
+        let chat: TelegramChat = serde_json::from_str(
+            r#"{"id": 7, "type": "private", "first_name": "Ada", "last_name": "Lovelace"}"#,
+        )
+        .unwrap();
+        assert_eq!(chat.display_name().as_deref(), Some("Ada Lovelace"));
+        let chat: TelegramChat = serde_json::from_str(r#"{"id": 7, "type": "private"}"#).unwrap();
+        assert_eq!(chat.display_name(), None);
+    }
+
+    #[test]
+    fn test_reply_metadata_pure() {
+        let reply: TelegramMessage = serde_json::from_str(
+            r#"{
+                "message_id": 10,
+                "chat": {"id": -100123, "type": "supergroup"},
+                "date": 1234567890,
+                "text": "@Andy remind me tomorrow",
+                "reply_to_message": {
+                    "message_id": 9,
+                    "chat": {"id": -100123, "type": "supergroup"},
+                    "date": 1234567800,
+                    "text": "Dentist at 3pm, then groceries"
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            reply_metadata_pure(&reply),
+            (
+                Some("9".to_string()),
+                Some("Dentist at 3pm, then groceries".to_string())
+            )
+        );
+
+        let mut quoted = reply.clone();
+        quoted.quote = Some(TelegramTextQuote {
+            text: "Dentist at 3pm".to_string(),
+        });
+        assert_eq!(
+            reply_metadata_pure(&quoted).1.as_deref(),
+            Some("Dentist at 3pm")
+        );
+
+        // The implicit reply to a forum topic's first message is ignored
+        let mut topic = reply.clone();
+        topic.message_thread_id = Some(9);
+        topic.is_topic_message = Some(true);
+        assert_eq!(reply_metadata_pure(&topic), (None, None));
+    }
+
+    #[test]
+    fn test_topic_jids() {
+        let jid = chat_jid_pure(-100123, Some(42));
+        assert_eq!(jid, "telegram:group:-100123:topic:42");
+        assert_eq!(extract_chat_id_pure(&jid), Some("-100123".to_string()));
+        assert_eq!(extract_topic_id_pure(&jid), Some(42));
+        assert_eq!(group_jid_pure(&jid), "telegram:group:-100123");
+        assert!(!is_private_chat(&jid));
+
+        let jid = chat_jid_pure(-100123, None);
+        assert_eq!(jid, "telegram:group:-100123");
+        assert_eq!(extract_topic_id_pure(&jid), None);
+        assert_eq!(group_jid_pure(&jid), jid);
+    }
+
+    #[test]
+    fn test_is_allowed_group_pure_topic() {
+        let allowed = vec!["-100123".to_string()];
+        assert!(is_allowed_group_pure(
+            "telegram:group:-100123:topic:42",
+            GroupPolicy::Allowlist,
+            &allowed
+        ));
+    }
+
     #[test]
     fn test_extract_trigger_telegram() {
-        let client = TelegramClient {
-            api_url: "https://api.telegram.org/bottest".to_string(),
-            webhook_path: "webhook".to_string(),
-            dm_policy: DMPolicy::Pairing,
-            group_policy: GroupPolicy::Allowlist,
-            text_chunk_limit: 4000,
-            allowed_groups: vec![],
-            registered_groups: HashMap::new(),
-            router_state: RouterState::default(),
-            db: Database::new().unwrap(),
-            assistant_name: "Andy".to_string(),
-        };
+        let client = test_client(DMPolicy::Pairing, GroupPolicy::Allowlist, 4000);
 
         let result = std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(client.extract_trigger("@Andy hello world"))
+            rt.block_on(client.extract_trigger("telegram:group:-100", "@andy hello world"))
         })
         .join()
         .unwrap();
@@ -714,42 +2350,20 @@ mod tests {
 
     #[test]
     fn test_text_chunking_short() {
-        let client = TelegramClient {
-            api_url: "https://api.telegram.org/bottest".to_string(),
-            webhook_path: "webhook".to_string(),
-            dm_policy: DMPolicy::Open,
-            group_policy: GroupPolicy::Open,
-            text_chunk_limit: 4000,
-            allowed_groups: vec![],
-            registered_groups: HashMap::new(),
-            router_state: RouterState::default(),
-            db: Database::new().unwrap(),
-            assistant_name: "Andy".to_string(),
-        };
+        let client = test_client(DMPolicy::Open, GroupPolicy::Open, 4000);
 
-        let chunks = client.chunk_text("short text");
+        let chunks = client.api.chunk_text("short text");
         assert_eq!(chunks.len(), 1);
         assert_eq!(chunks[0], "short text");
     }
 
     #[test]
     fn test_text_chunking_long() {
-        let client = TelegramClient {
-            api_url: "https://api.telegram.org/bottest".to_string(),
-            webhook_path: "webhook".to_string(),
-            dm_policy: DMPolicy::Open,
-            group_policy: GroupPolicy::Open,
-            text_chunk_limit: 50,
-            allowed_groups: vec![],
-            registered_groups: HashMap::new(),
-            router_state: RouterState::default(),
-            db: Database::new().unwrap(),
-            assistant_name: "Andy".to_string(),
-        };
+        let client = test_client(DMPolicy::Open, GroupPolicy::Open, 50);
 
         // Create a text longer than 50 characters with multiple paragraphs
         let long_text = "This is paragraph one that is longer than fifty characters.\n\nThis is paragraph two that is also quite long and should create multiple chunks.\n\nThis is the third paragraph to ensure we have enough content.";
-        let chunks = client.chunk_text(long_text);
+        let chunks = client.api.chunk_text(long_text);
         assert!(
             chunks.len() > 1,
             "Expected multiple chunks but got {:?}",
@@ -757,6 +2371,207 @@ mod tests {
         );
     }
 
+    fn parse_message(json: &str) -> TelegramMessage {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_describe_non_text_pure() {
+        let base = r#""message_id": 1, "chat": {"id": 5, "type": "private"}, "date": 0"#;
+
+        let msg = parse_message(&format!(
+            r#"{{{}, "sticker": {{"emoji": "👍", "set_name": "pack"}}}}"#,
+            base
+        ));
+        assert_eq!(
+            describe_non_text_pure(&msg).as_deref(),
+            Some("[Sticker 👍]")
+        );
+
+        let msg = parse_message(&format!(
+            r#"{{{}, "poll": {{"question": "Lunch?", "options": [{{"text": "Yes"}}, {{"text": "No"}}]}}}}"#,
+            base
+        ));
+        assert_eq!(
+            describe_non_text_pure(&msg).as_deref(),
+            Some("[Poll: Lunch? Options: Yes / No]")
+        );
+
+        let msg = parse_message(&format!(
+            r#"{{{}, "location": {{"latitude": 52.5, "longitude": 13.4}}}}"#,
+            base
+        ));
+        assert_eq!(
+            describe_non_text_pure(&msg).as_deref(),
+            Some("[Location: 52.5, 13.4]")
+        );
+
+        let msg = parse_message(&format!(
+            r#"{{{}, "contact": {{"phone_number": "+123", "first_name": "Ann"}}}}"#,
+            base
+        ));
+        assert_eq!(
+            describe_non_text_pure(&msg).as_deref(),
+            Some("[Contact: Ann, +123]")
+        );
+
+        let msg = parse_message(&format!(
+            r#"{{{}, "animation": {{"duration": 3}}, "caption": "@Andy look"}}"#,
+            base
+        ));
+        assert_eq!(
+            describe_non_text_pure(&msg).as_deref(),
+            Some("[GIF] @Andy look")
+        );
+
+        let msg = parse_message(&format!("{{{}}}", base));
+        assert_eq!(describe_non_text_pure(&msg), None);
+    }
+
+    #[test]
+    fn test_parse_inline_query_update() {
+        let update: TelegramUpdate = serde_json::from_str(
+            r#"{"update_id": 1, "inline_query": {"id": "q1", "from": {"id": 42, "is_bot": false, "first_name": "Ann"}, "query": "weather?", "offset": ""}}"#,
+        )
+        .unwrap();
+        let query = update.inline_query.unwrap();
+        assert_eq!(query.id, "q1");
+        assert_eq!(query.from.id, 42);
+        assert_eq!(query.query, "weather?");
+        assert!(update.message.is_none());
+    }
+
+    #[test]
+    fn test_parse_get_updates_result() {
+        let result = serde_json::json!([
+            {"update_id": 10, "message": {"message_id": 1, "chat": {"id": 5, "type": "private"}, "date": 0, "text": "hi"}},
+            {"update_id": 11, "edited_message": {"message_id": 1, "chat": {"id": 5, "type": "private"}, "date": 0, "text": "hey"}}
+        ]);
+        let updates: Vec<TelegramUpdate> = serde_json::from_value(result).unwrap();
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[1].update_id, 11);
+        assert!(updates[1].message.is_none());
+    }
+
+    #[test]
+    fn test_inline_results_pure() {
+        let results = inline_results_pure(&"t".repeat(100), "hello world", 5);
+        let article = &results[0];
+        assert_eq!(article["type"], "article");
+        assert_eq!(article["title"].as_str().unwrap().len(), INLINE_TITLE_LEN);
+        assert_eq!(article["input_message_content"]["message_text"], "hello");
+    }
+
+    #[tokio::test]
+    async fn test_inline_query_from_unauthorized_user_is_ignored() {
+        let client = test_client(DMPolicy::Disabled, GroupPolicy::Open, 4000);
+        let query = TelegramInlineQuery {
+            id: "q1".to_string(),
+            from: TelegramUser {
+                id: 42,
+                is_bot: false,
+                first_name: "Ann".to_string(),
+                last_name: None,
+                username: None,
+            },
+            query: "hello".to_string(),
+            offset: String::new(),
+        };
+        assert!(!client.is_inline_user_allowed("42").await.unwrap());
+        assert_eq!(client.handle_inline_query(&query).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_clones_share_registered_groups() {
+        let client = test_client(DMPolicy::Open, GroupPolicy::Open, 4000);
+        let handler = client.clone();
+
+        client.registered_groups.write().unwrap().insert(
+            "telegram:group:-100".to_string(),
+            RegisteredGroup {
+                name: "Family".to_string(),
+                folder: "family".to_string(),
+                trigger: "@Andy".to_string(),
+                added_at: String::new(),
+                aliases: vec![],
+                read_receipts: None,
+                presence: None,
+                container_config: None,
+            },
+        );
+
+        assert_eq!(
+            handler.get_group_folder("telegram:group:-100").await,
+            Some("family".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_paired_user_reaches_the_agent() {
+        const TENANT: &str = "pairing_e2e";
+        let (db, _dir) = crate::db::test_database();
+        let db = db.for_tenant(TENANT);
+        let mut client = test_client(DMPolicy::Pairing, GroupPolicy::Allowlist, 4000);
+        client.api.api_url = "http://127.0.0.1:9/bottest".to_string();
+        client.outbox = Outbox::new(db.clone(), CHANNEL);
+        client.pending = PendingQueue::new(db.clone(), CHANNEL);
+        client.db = db.clone();
+        // Keep the run waiting behind another chat's, so it stays queued
+        client.runs = ChatQueue::new(1);
+        client
+            .runs
+            .push("telegram:group:-1", std::future::pending());
+
+        let code = crate::pairing::create_pairing_code(&db, "test").unwrap();
+        let dm = |id: &str, content: &str| NewMessage {
+            id: id.to_string(),
+            chat_jid: "telegram:group:42".to_string(),
+            sender: "42".to_string(),
+            sender_name: "ann".to_string(),
+            content: content.to_string(),
+            timestamp: "1".to_string(),
+            reply_to_id: None,
+            quoted_content: None,
+            chat_name: None,
+            correlation_id: None,
+        };
+        client.handle_message(&dm("1", &code)).await.unwrap();
+        client
+            .handle_message(&dm("2", "@Andy hello"))
+            .await
+            .unwrap();
+
+        let folder = format!("{}-dm-telegram-42", TENANT);
+        assert_eq!(
+            client.get_group_folder("telegram:group:42").await,
+            Some(folder.clone())
+        );
+        assert_eq!(
+            crate::groups::find_group(TENANT, "dm-telegram-42").map(|(jid, _)| jid),
+            Some("telegram:group:42".to_string())
+        );
+        assert_eq!(client.runs.waiting(), 1);
+
+        std::fs::remove_dir_all(crate::tenants::tenant_data_dir(TENANT)).unwrap();
+        std::fs::remove_dir_all(crate::config::groups_dir().join(folder)).unwrap();
+    }
+
+    #[test]
+    fn test_non_text_policy_from_str() {
+        assert_eq!(NonTextPolicy::parse("ignore"), NonTextPolicy::Ignore);
+        assert_eq!(NonTextPolicy::parse("REJECT"), NonTextPolicy::Reject);
+        assert_eq!(NonTextPolicy::parse("describe"), NonTextPolicy::Describe);
+        assert_eq!(NonTextPolicy::parse("unknown"), NonTextPolicy::Describe);
+    }
+
+    #[test]
+    fn test_preview_text_pure() {
+        assert_eq!(preview_text_pure("  hello\n", 10), "hello");
+        assert_eq!(preview_text_pure("", 10), "");
+        assert_eq!(preview_text_pure("abcdefghij", 5), "…ghij");
+        assert_eq!(preview_text_pure("ééééé", 3).chars().count(), 3);
+    }
+
     #[test]
     fn test_chunk_text_pure_short() {
         let chunks = chunk_text_pure("short text", 4000);
@@ -807,29 +2622,10 @@ mod tests {
     }
 
     #[test]
-    fn test_is_duplicate_message_pure() {
-        let msg = NewMessage {
-            id: "1".to_string(),
-            chat_jid: "telegram:group:123".to_string(),
-            sender: "user1".to_string(),
-            sender_name: "User".to_string(),
-            content: "hello".to_string(),
-            timestamp: "2025-01-01T00:00:00Z".to_string(),
-        };
-
-        let mut agent_ts = std::collections::HashMap::new();
-        agent_ts.insert(
-            "telegram:group:123".to_string(),
-            "2025-01-01T00:00:00Z".to_string(),
-        );
-
-        assert!(is_duplicate_message_pure(
-            &msg,
-            "2025-01-01T00:00:00Z",
-            &HashMap::new()
-        ));
-        assert!(is_duplicate_message_pure(&msg, "old", &agent_ts));
-        assert!(!is_duplicate_message_pure(&msg, "old", &HashMap::new()));
+    fn test_is_private_chat() {
+        assert!(is_private_chat("telegram:group:123456"));
+        assert!(!is_private_chat("telegram:group:-100123"));
+        assert!(!is_private_chat("invalid:jid"));
     }
 
     #[test]
@@ -887,6 +2683,8 @@ mod tests {
             id: -100123,
             chat_type: "supergroup".to_string(),
             title: Some("Test Group".to_string()),
+            first_name: None,
+            last_name: None,
         };
         let json = serde_json::to_string(&chat).unwrap();
         assert!(json.contains("supergroup"));
@@ -897,6 +2695,16 @@ mod tests {
             chat,
             date: 1234567890,
             text: Some("hello".to_string()),
+            message_thread_id: None,
+            is_topic_message: None,
+            caption: None,
+            sticker: None,
+            animation: None,
+            poll: None,
+            contact: None,
+            location: None,
+            reply_to_message: None,
+            quote: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("hello"));
@@ -0,0 +1,278 @@
+//! Tenants for NuClaw
+//!
+//! One NuClaw process and database can host several assistants, e.g. one
+//! per family member. Each tenant has its own assistant name, bot
+//! credentials, admins, and registered groups, and its own rows in every
+//! tenant-scoped table (see `db`).
+//!
+//! Tenants are listed in `data/tenants.json`:
+//!
+//! ```json
+//! [{"id": "alice", "assistant_name": "Ada", "telegram_bot_token": "123:abc"}]
+//! ```
+//!
+//! The `default` tenant always exists and is configured by the environment
+//! (`ASSISTANT_NAME`, `TELEGRAM_BOT_TOKEN`, `WHATSAPP_MCP_URL`,
+//! `ADMIN_USERS`); an entry with its ID overrides those settings. Other
+//! tenants fall back to the environment only for the assistant name and
+//! admins. Their registered groups and channel state live under
+//! `data/tenants/<id>/`, and their group folders are prefixed with the
+//! tenant ID so agents of different tenants never share one.
+
+use crate::config::{admin_users, assistant_name, data_dir};
+use crate::error::{NuClawError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Tenant of a single-assistant setup, and of rows from before tenants
+pub const DEFAULT_TENANT: &str = "default";
+
+/// An assistant hosted by this process
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tenant {
+    pub id: String,
+    /// Name the assistant answers to; `ASSISTANT_NAME` by default
+    #[serde(default)]
+    pub assistant_name: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub telegram_bot_token: Option<String>,
+    /// Path of the tenant's Telegram webhook; `telegram-webhook-<id>` by
+    /// default
+    #[serde(default)]
+    pub telegram_webhook_path: Option<String>,
+    #[serde(default)]
+    pub whatsapp_mcp_url: Option<String>,
+    /// Sender IDs allowed to run admin commands; `ADMIN_USERS` by default
+    #[serde(default)]
+    pub admin_users: Option<Vec<String>>,
+}
+
+impl Tenant {
+    pub fn is_default(&self) -> bool {
+        self.id == DEFAULT_TENANT
+    }
+
+    pub fn assistant_name(&self) -> String {
+        self.assistant_name.clone().unwrap_or_else(assistant_name)
+    }
+
+    pub fn admin_users(&self) -> Vec<String> {
+        self.admin_users.clone().unwrap_or_else(admin_users)
+    }
+
+    pub fn telegram_bot_token(&self) -> Option<String> {
+        self.telegram_bot_token
+            .clone()
+            .or_else(|| self.env_setting("TELEGRAM_BOT_TOKEN"))
+    }
+
+    pub fn telegram_webhook_path(&self) -> String {
+        self.telegram_webhook_path.clone().unwrap_or_else(|| {
+            if self.is_default() {
+                std::env::var("TELEGRAM_WEBHOOK_PATH")
+                    .unwrap_or_else(|_| "telegram-webhook".to_string())
+            } else {
+                format!("telegram-webhook-{}", self.id)
+            }
+        })
+    }
+
+    pub fn whatsapp_mcp_url(&self) -> Option<String> {
+        self.whatsapp_mcp_url
+            .clone()
+            .or_else(|| self.env_setting("WHATSAPP_MCP_URL"))
+    }
+
+    /// An environment variable, for the default tenant only
+    fn env_setting(&self, key: &str) -> Option<String> {
+        if !self.is_default() {
+            return None;
+        }
+        std::env::var(key)
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Directory of the tenant's registered groups and channel state
+    pub fn data_dir(&self) -> PathBuf {
+        tenant_data_dir(&self.id)
+    }
+
+    /// Folder under `groups/` of a group registered as `folder`
+    pub fn group_folder(&self, folder: &str) -> String {
+        if self.is_default() {
+            folder.to_string()
+        } else {
+            format!("{}-{}", self.id, folder)
+        }
+    }
+}
+
+/// Directory of a tenant's registered groups and channel state: `data/`
+/// for the default tenant, `data/tenants/<id>/` for the others
+pub fn tenant_data_dir(id: &str) -> PathBuf {
+    if id == DEFAULT_TENANT {
+        data_dir()
+    } else {
+        data_dir().join("tenants").join(id)
+    }
+}
+
+/// Path of the tenants file
+pub fn tenants_path() -> PathBuf {
+    data_dir().join("tenants.json")
+}
+
+/// Whether `id` can be used as a tenant ID
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 32
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// All tenants, the default one first
+///
+/// A missing tenants file means only the default tenant; an unreadable or
+/// invalid one is an error, so a typo never starts the wrong assistants.
+pub fn load_tenants() -> Result<Vec<Tenant>> {
+    let path = tenants_path();
+    let listed: Vec<Tenant> = match std::fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| NuClawError::Config {
+            message: format!("Invalid {}: {}", path.display(), e),
+        })?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            return Err(NuClawError::Config {
+                message: format!("Failed to read {}: {}", path.display(), e),
+            })
+        }
+    };
+    check_tenants(listed)
+}
+
+fn check_tenants(listed: Vec<Tenant>) -> Result<Vec<Tenant>> {
+    let mut seen = HashSet::new();
+    for tenant in &listed {
+        if !is_valid_id(&tenant.id) {
+            return Err(NuClawError::Config {
+                message: format!(
+                    "Invalid tenant ID '{}'; use lowercase letters, digits, and '_'",
+                    tenant.id
+                ),
+            });
+        }
+        if !seen.insert(tenant.id.as_str()) {
+            return Err(NuClawError::Config {
+                message: format!("Tenant '{}' is listed twice", tenant.id),
+            });
+        }
+    }
+
+    let mut tenants = listed;
+    match tenants.iter().position(Tenant::is_default) {
+        Some(i) => tenants[..=i].rotate_right(1),
+        None => tenants.insert(
+            0,
+            Tenant {
+                id: DEFAULT_TENANT.to_string(),
+                ..Default::default()
+            },
+        ),
+    }
+    Ok(tenants)
+}
+
+/// Settings of a tenant; an unknown ID gets the defaults
+pub fn tenant(id: &str) -> Tenant {
+    let tenants = load_tenants().unwrap_or_else(|e| {
+        tracing::warn!("{}", e);
+        Vec::new()
+    });
+    tenants
+        .into_iter()
+        .find(|t| t.id == id)
+        .unwrap_or_else(|| Tenant {
+            id: id.to_string(),
+            ..Default::default()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    fn named(id: &str) -> Tenant {
+        Tenant {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_tenants() {
+        let ids = |tenants: Vec<Tenant>| -> Vec<String> {
+            check_tenants(tenants)
+                .unwrap()
+                .into_iter()
+                .map(|t| t.id)
+                .collect()
+        };
+        assert_eq!(ids(vec![]), vec!["default"]);
+        assert_eq!(
+            ids(vec![named("alice"), named("bob")]),
+            vec!["default", "alice", "bob"]
+        );
+        assert_eq!(
+            ids(vec![named("alice"), named("default")]),
+            vec!["default", "alice"]
+        );
+        assert!(check_tenants(vec![named("Alice")]).is_err());
+        assert!(check_tenants(vec![named("alice"), named("alice")]).is_err());
+    }
+
+    #[test]
+    fn test_tenant_settings() {
+        let alice = Tenant {
+            assistant_name: Some("Ada".to_string()),
+            ..named("alice")
+        };
+        assert_eq!(alice.assistant_name(), "Ada");
+        assert_eq!(alice.group_folder("family"), "alice-family");
+        assert_eq!(alice.telegram_webhook_path(), "telegram-webhook-alice");
+        assert!(alice.data_dir().ends_with("tenants/alice"));
+        assert_eq!(named(DEFAULT_TENANT).group_folder("family"), "family");
+    }
+
+    #[test]
+    fn test_tenants_do_not_see_each_others_rows() {
+        let (db, _dir) = test_database();
+        let alice = db.for_tenant("alice");
+
+        db.chats()
+            .upsert("chat", Some("Family"), "1767344400")
+            .unwrap();
+        alice
+            .chats()
+            .upsert("chat", Some("Alice's family"), "1767344400")
+            .unwrap();
+        assert_eq!(
+            db.chats().get("chat").unwrap().unwrap().name.as_deref(),
+            Some("Family")
+        );
+        assert_eq!(
+            alice.chats().get("chat").unwrap().unwrap().name.as_deref(),
+            Some("Alice's family")
+        );
+        assert_eq!(db.for_tenant("bob").chats().list().unwrap(), vec![]);
+
+        // The same message reaches both assistants of a shared group
+        assert!(crate::dedup::mark_processed(&db, "chat", "m1").unwrap());
+        assert!(crate::dedup::mark_processed(&alice, "chat", "m1").unwrap());
+        assert!(!crate::dedup::mark_processed(&alice, "chat", "m1").unwrap());
+    }
+}
@@ -0,0 +1,132 @@
+//! Speech-to-Text for NuClaw
+//!
+//! Transcribes voice notes through an OpenAI-compatible
+//! `/audio/transcriptions` endpoint so spoken messages can be matched
+//! against triggers and passed to the agent as text. Transcription is
+//! disabled unless `TRANSCRIPTION_API_KEY` is set.
+
+use crate::error::{NuClawError, Result};
+use std::time::Duration;
+
+/// Default transcription endpoint
+const DEFAULT_TRANSCRIPTION_API_URL: &str = "https://api.openai.com/v1/audio/transcriptions";
+/// Default transcription model
+const DEFAULT_TRANSCRIPTION_MODEL: &str = "whisper-1";
+/// Default request timeout: 60 seconds
+const DEFAULT_TRANSCRIPTION_TIMEOUT_SECS: u64 = 60;
+
+/// Transcription settings read from the environment
+#[derive(Debug, Clone)]
+pub struct TranscriptionConfig {
+    pub api_url: String,
+    pub api_key: String,
+    pub model: String,
+    /// ISO-639-1 language hint, if any
+    pub language: Option<String>,
+    pub timeout: Duration,
+}
+
+impl TranscriptionConfig {
+    /// Load the configuration; `None` if transcription is not set up
+    pub fn from_env() -> Option<Self> {
+        let api_key = std::env::var("TRANSCRIPTION_API_KEY")
+            .ok()
+            .filter(|k| !k.is_empty())?;
+        Some(Self {
+            api_url: std::env::var("TRANSCRIPTION_API_URL")
+                .unwrap_or_else(|_| DEFAULT_TRANSCRIPTION_API_URL.to_string()),
+            api_key,
+            model: std::env::var("TRANSCRIPTION_MODEL")
+                .unwrap_or_else(|_| DEFAULT_TRANSCRIPTION_MODEL.to_string()),
+            language: std::env::var("TRANSCRIPTION_LANGUAGE")
+                .ok()
+                .filter(|l| !l.is_empty()),
+            timeout: Duration::from_secs(
+                std::env::var("TRANSCRIPTION_TIMEOUT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_TRANSCRIPTION_TIMEOUT_SECS),
+            ),
+        })
+    }
+}
+
+/// Transcribe an audio file to text
+pub async fn transcribe(
+    config: &TranscriptionConfig,
+    audio: Vec<u8>,
+    file_name: &str,
+    mime_type: &str,
+) -> Result<String> {
+    let part = reqwest::multipart::Part::bytes(audio)
+        .file_name(file_name.to_string())
+        .mime_str(mime_type)
+        .map_err(|e| NuClawError::Transcription {
+            message: format!("Invalid audio type {}: {}", mime_type, e),
+        })?;
+    let mut form = reqwest::multipart::Form::new()
+        .part("file", part)
+        .text("model", config.model.clone())
+        .text("response_format", "json");
+    if let Some(language) = &config.language {
+        form = form.text("language", language.clone());
+    }
+
+    let response = reqwest::Client::new()
+        .post(&config.api_url)
+        .bearer_auth(&config.api_key)
+        .multipart(form)
+        .timeout(config.timeout)
+        .send()
+        .await
+        .map_err(|e| NuClawError::Transcription {
+            message: format!("Failed to call transcription API: {}", e),
+        })?;
+
+    if !response.status().is_success() {
+        return Err(NuClawError::Transcription {
+            message: format!(
+                "Transcription API returned {}: {}",
+                response.status(),
+                response.text().await.unwrap_or_default()
+            ),
+        });
+    }
+
+    let body: serde_json::Value =
+        response
+            .json()
+            .await
+            .map_err(|e| NuClawError::Transcription {
+                message: format!("Failed to parse transcription: {}", e),
+            })?;
+    parse_transcript(&body)
+}
+
+/// Extract the transcript text from an API response
+fn parse_transcript(body: &serde_json::Value) -> Result<String> {
+    body.get("text")
+        .and_then(|t| t.as_str())
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| NuClawError::Transcription {
+            message: "Transcription was empty".to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_transcript() {
+        let body = serde_json::json!({ "text": "  @Andy what's the weather? " });
+        assert_eq!(
+            parse_transcript(&body).unwrap(),
+            "@Andy what's the weather?"
+        );
+
+        assert!(parse_transcript(&serde_json::json!({ "text": "" })).is_err());
+        assert!(parse_transcript(&serde_json::json!({ "error": "bad" })).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 //! Core types for NuClaw
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegisteredGroup {
@@ -9,6 +9,241 @@ pub struct RegisteredGroup {
     pub folder: String,
     pub trigger: String,
     pub added_at: String,
+    /// Additional trigger words accepted alongside `trigger`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Mark triggered messages as read; `None` uses the channel default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_receipts: Option<bool>,
+    /// Show "typing" while the agent runs; `None` uses the channel default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence: Option<bool>,
+    /// Container overrides; unset fields use the global defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_config: Option<ContainerConfig>,
+}
+
+/// Per-group container settings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContainerConfig {
+    /// Memory limit in `docker run --memory` syntax, e.g. `"2g"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    /// CPU limit in `docker run --cpus` syntax, e.g. `"1.5"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+    /// Maximum number of processes in the container
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pids_limit: Option<u32>,
+    /// Extra host directories to mount; each must be in the mount allowlist
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_mounts: Vec<AdditionalMount>,
+    /// Image to run instead of `CONTAINER_IMAGE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// Entrypoint of the image, which then receives the input on stdin;
+    /// unset runs the bundled `claude` CLI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<String>,
+    /// Extra environment variables for the container
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Host variables forwarded to the container, replacing
+    /// `CONTAINER_ENV_ALLOWLIST` and the default agent credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_allowlist: Option<Vec<String>>,
+    /// Host variables never forwarded to the container
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_denylist: Vec<String>,
+    /// Keep a long-lived container for this group; `None` uses
+    /// `CONTAINER_WARM_POOL`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warm_pool: Option<bool>,
+    /// Sandbox the container runs in; `None` uses `CONTAINER_ISOLATION`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isolation: Option<Isolation>,
+    /// Backend running the agent; `None` uses `CONTAINER_RUNNER`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runner: Option<ContainerBackend>,
+    /// Cache volume for package managers; `None` uses `CONTAINER_CACHE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheScope>,
+    /// Time limit of a run in milliseconds; `None` uses `CONTAINER_TIMEOUT`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Docker network the container joins, e.g. `"none"` to cut it off;
+    /// `None` uses `CONTAINER_NETWORK`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    /// What the agent may do; `None` allows everything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<AgentPermissions>,
+}
+
+/// What a group's agent may do, passed to it in its input and enforced by
+/// the container where possible
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentPermissions {
+    /// Tools the agent may use, e.g. `["Read", "Grep", "WebSearch"]`;
+    /// `None` allows all of them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_tools: Option<Vec<String>>,
+    /// Whether the container has a network; `false` runs it with
+    /// `--network none`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_allowed: Option<bool>,
+    /// Container paths the agent may write, e.g. `["/workspace/group"]`;
+    /// other mounts are read-only. `None` leaves every mount writable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writable_paths: Option<Vec<String>>,
+}
+
+impl AgentPermissions {
+    /// Whether the agent may write at `container_path`
+    pub fn may_write(&self, container_path: &str) -> bool {
+        self.writable_paths.as_ref().is_none_or(|paths| {
+            paths.iter().any(|path| {
+                let path = path.trim().trim_end_matches('/');
+                container_path == path
+                    || container_path
+                        .strip_prefix(path)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+        })
+    }
+}
+
+/// Which runs share a dependency cache volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheScope {
+    /// No cache volume
+    #[serde(rename = "none")]
+    None,
+    /// One volume per group
+    #[serde(rename = "group")]
+    Group,
+    /// One volume for all groups
+    #[serde(rename = "shared")]
+    Shared,
+}
+
+impl CacheScope {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "none" | "off" | "false" => Some(CacheScope::None),
+            "group" | "" => Some(CacheScope::Group),
+            "shared" => Some(CacheScope::Shared),
+            _ => None,
+        }
+    }
+}
+
+/// How an agent run is executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContainerBackend {
+    /// A Docker (or Apple Container) container
+    #[serde(rename = "docker")]
+    Docker,
+    /// A wasm32-wasi agent runtime under wasmtime
+    #[serde(rename = "wasm")]
+    Wasm,
+    /// The agent CLI as a local subprocess, without a container
+    #[serde(rename = "process")]
+    Process,
+}
+
+impl ContainerBackend {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "docker" | "container" | "" => Some(ContainerBackend::Docker),
+            "wasm" | "wasmtime" => Some(ContainerBackend::Wasm),
+            "process" | "local" => Some(ContainerBackend::Process),
+            _ => None,
+        }
+    }
+}
+
+/// Runtime isolating a group's container from the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Isolation {
+    /// Docker's default runtime (runc)
+    #[serde(rename = "docker")]
+    Docker,
+    /// gVisor user-space kernel (`runsc`)
+    #[serde(rename = "gvisor")]
+    Gvisor,
+    /// Kata Containers lightweight VM
+    #[serde(rename = "kata")]
+    Kata,
+    /// Firecracker microVM, through Kata's Firecracker runtime
+    #[serde(rename = "firecracker")]
+    Firecracker,
+}
+
+impl Isolation {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "docker" | "runc" | "" => Some(Isolation::Docker),
+            "gvisor" | "runsc" => Some(Isolation::Gvisor),
+            "kata" => Some(Isolation::Kata),
+            "firecracker" => Some(Isolation::Firecracker),
+            _ => None,
+        }
+    }
+}
+
+/// A host directory a group asks to have mounted into its container
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdditionalMount {
+    /// Path on the host; `~` expands to the home directory
+    pub host_path: String,
+    /// Name under `/workspace/extra/`; defaults to the host directory name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_path: Option<String>,
+    /// Mount read-only (the default)
+    #[serde(default = "default_readonly")]
+    pub readonly: bool,
+}
+
+fn default_readonly() -> bool {
+    true
+}
+
+impl RegisteredGroup {
+    /// All trigger words for this group, primary trigger first
+    pub fn triggers(&self) -> Vec<String> {
+        std::iter::once(&self.trigger)
+            .chain(self.aliases.iter())
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect()
+    }
+}
+
+/// DM policy enumeration
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DMPolicy {
+    #[serde(rename = "pairing")]
+    Pairing,
+    #[serde(rename = "allowlist")]
+    Allowlist,
+    #[serde(rename = "open")]
+    Open,
+    #[serde(rename = "disabled")]
+    Disabled,
+}
+
+impl DMPolicy {
+    pub fn parse(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "pairing" => DMPolicy::Pairing,
+            "allowlist" => DMPolicy::Allowlist,
+            "open" => DMPolicy::Open,
+            "disabled" => DMPolicy::Disabled,
+            _ => DMPolicy::Pairing,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -42,6 +277,42 @@ pub struct ScheduledTask {
     pub last_result: Option<String>,
     pub status: String,
     pub created_at: String,
+    /// Retries after infrastructure failures; `None` uses the scheduler default
+    #[serde(default)]
+    pub container_retries: Option<u32>,
+    /// Retries of a failed run; `None` uses `TASK_MAX_RETRIES`
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    /// Failed runs since the last success or skipped run
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    /// Random offset of each run in seconds; `None` uses `TASK_JITTER`
+    #[serde(default)]
+    pub jitter_secs: Option<u64>,
+    /// Time limit of a run in seconds; `None` uses `TASK_TIMEOUT`
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// When runs are reported to the chat: `always`, `on_failure`, or `never`
+    #[serde(default = "default_task_notify")]
+    pub notify: String,
+    /// Lock shared with tasks that must not run at the same time
+    #[serde(default)]
+    pub lock: Option<String>,
+    /// Whether a run due while the previous one is in flight is skipped or
+    /// queued: `skip` or `queue`
+    #[serde(default = "default_task_overlap")]
+    pub overlap: String,
+    /// Tasks with a higher priority get free run slots first
+    #[serde(default)]
+    pub priority: i32,
+}
+
+fn default_task_notify() -> String {
+    crate::task_scheduler::notify::ALWAYS.to_string()
+}
+
+fn default_task_overlap() -> String {
+    crate::task_scheduler::overlap::SKIP.to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -62,6 +333,19 @@ pub struct NewMessage {
     pub sender_name: String,
     pub content: String,
     pub timestamp: String,
+    /// ID of the message this one replies to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to_id: Option<String>,
+    /// Text of the replied-to message, or of the quoted part of it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quoted_content: Option<String>,
+    /// Display name of the chat (group title, or the contact's name)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_name: Option<String>,
+    /// Correlation ID given when the message was accepted for an agent run
+    /// (see `correlation`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -75,10 +359,48 @@ pub struct ChatInfo {
 pub struct ContainerInput {
     pub prompt: String,
     pub session_id: Option<String>,
+    /// Whether `session_id` is the chat's agent session, to be resumed;
+    /// scheduled tasks and inline queries only name their runs with it
+    #[serde(default)]
+    pub resume_session: bool,
     pub group_folder: String,
     pub chat_jid: String,
     pub is_main: bool,
     pub is_scheduled_task: bool,
+    /// Earlier messages of the chat, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context: Vec<ContextMessage>,
+    /// ID of the message the prompt replies to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to_id: Option<String>,
+    /// Text the prompt quotes from the replied-to message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quoted_content: Option<String>,
+    /// Stored memories of the group relevant to the prompt, most relevant
+    /// first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memories: Vec<String>,
+    /// Time limit of the run; `None` uses `CONTAINER_TIMEOUT`
+    #[serde(skip)]
+    pub timeout: Option<std::time::Duration>,
+    /// Who the run answers, as `<channel>:<sender>`; `None` for scheduled
+    /// tasks
+    #[serde(skip)]
+    pub sender: Option<String>,
+    /// Correlation ID of the message or task run (see `correlation`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// The group's agent permissions, filled in by the container runner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<AgentPermissions>,
+}
+
+/// A stored chat message passed to the agent as context
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContextMessage {
+    pub sender_name: String,
+    pub content: String,
+    pub timestamp: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,9 +409,28 @@ pub struct ContainerOutput {
     pub result: Option<String>,
     pub new_session_id: Option<String>,
     pub error: Option<String>,
+    /// Tokens used by the run, if the agent reports them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+}
+
+/// Token usage reported by an agent run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    /// Cost in US dollars, if the agent computes it
+    #[serde(
+        default,
+        alias = "total_cost_usd",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cost_usd: Option<f64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub struct RouterState {
     pub last_timestamp: String,
     pub last_agent_timestamp: HashMap<String, String>,
@@ -106,11 +447,28 @@ mod tests {
             folder: "test_group".to_string(),
             trigger: "@Andy".to_string(),
             added_at: "2025-01-01T00:00:00Z".to_string(),
+            aliases: vec![],
+            read_receipts: None,
+            presence: None,
+            container_config: None,
         };
         assert_eq!(group.name, "Test Group");
         assert_eq!(group.folder, "test_group");
     }
 
+    #[test]
+    fn test_registered_group_triggers() {
+        let json = r#"{"name":"g","folder":"g","trigger":"@Andy","added_at":""}"#;
+        let mut group: RegisteredGroup = serde_json::from_str(json).unwrap();
+        assert_eq!(group.triggers(), vec!["@Andy".to_string()]);
+
+        group.aliases = vec!["@bot".to_string(), " ".to_string()];
+        assert_eq!(
+            group.triggers(),
+            vec!["@Andy".to_string(), "@bot".to_string()]
+        );
+    }
+
     #[test]
     fn test_session() {
         let mut session = Session::new();
@@ -128,6 +486,15 @@ mod tests {
             schedule_type: "cron".to_string(),
             schedule_value: "0 0 9 * * *".to_string(),
             context_mode: "isolated".to_string(),
+            container_retries: None,
+            max_retries: None,
+            consecutive_failures: 0,
+            jitter_secs: None,
+            timeout_secs: None,
+            notify: "always".to_string(),
+            lock: None,
+            overlap: "skip".to_string(),
+            priority: 0,
             next_run: Some("2025-01-01T09:00:00Z".to_string()),
             last_run: None,
             last_result: None,
@@ -143,21 +510,44 @@ mod tests {
         let input = ContainerInput {
             prompt: "test".to_string(),
             session_id: Some("sess_123".to_string()),
+            resume_session: false,
             group_folder: "group_1".to_string(),
             chat_jid: "chat_1".to_string(),
             is_main: true,
             is_scheduled_task: false,
+            context: vec![],
+            reply_to_id: None,
+            quoted_content: None,
+            memories: vec![],
+            timeout: None,
+            sender: None,
+            correlation_id: None,
+            permissions: None,
         };
         assert!(input.session_id.is_some());
         assert!(input.is_main);
     }
 
+    #[test]
+    fn test_agent_permissions_may_write() {
+        let permissions = AgentPermissions {
+            writable_paths: Some(vec!["/workspace/group/notes/".to_string()]),
+            ..Default::default()
+        };
+        assert!(permissions.may_write("/workspace/group/notes"));
+        assert!(permissions.may_write("/workspace/group/notes/todo.md"));
+        assert!(!permissions.may_write("/workspace/group/notes2"));
+        assert!(!permissions.may_write("/workspace/group"));
+        assert!(AgentPermissions::default().may_write("/workspace/group"));
+    }
+
     #[test]
     fn test_container_output() {
         let output = ContainerOutput {
             status: "success".to_string(),
             result: Some("result".to_string()),
             new_session_id: Some("new_sess".to_string()),
+            usage: None,
             error: None,
         };
         assert_eq!(output.status, "success");
@@ -167,8 +557,10 @@ mod tests {
 
     #[test]
     fn test_router_state() {
-        let mut state = RouterState::default();
-        state.last_timestamp = "2025-01-01T00:00:00Z".to_string();
+        let mut state = RouterState {
+            last_timestamp: "2025-01-01T00:00:00Z".to_string(),
+            ..Default::default()
+        };
         state
             .last_agent_timestamp
             .insert("chat_1".to_string(), "2025-01-01T00:00:00Z".to_string());
@@ -184,6 +576,10 @@ mod tests {
             sender_name: "Test User".to_string(),
             content: "Hello".to_string(),
             timestamp: "2025-01-01T00:00:00Z".to_string(),
+            reply_to_id: None,
+            quoted_content: None,
+            chat_name: None,
+            correlation_id: None,
         };
         assert_eq!(msg.content, "Hello");
     }
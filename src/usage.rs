@@ -0,0 +1,313 @@
+//! Usage Tracking for NuClaw
+//!
+//! Every agent run is recorded in the `usage` table with the chat, the
+//! user it answered (`<channel>:<sender>`, `cli` for the REPL, or
+//! `scheduler` for scheduled tasks), its duration, and the tokens and
+//! cost the agent reports in its output (`usage.input_tokens`,
+//! `usage.output_tokens`, `usage.cost_usd`). Runs of agents that report
+//! nothing are still counted.
+//!
+//! `monthly_usage` rolls the runs up per calendar month (UTC) and chat or
+//! user, for `/usage` and `nuclaw usage`.
+
+use crate::db::Database;
+use crate::error::{NuClawError, Result};
+use crate::task_scheduler::format_duration;
+use crate::types::TokenUsage;
+use chrono::{Datelike, Utc};
+use std::collections::BTreeMap;
+
+/// Months covered without a count
+pub const DEFAULT_USAGE_MONTHS: u32 = 3;
+/// Upper bound for the months covered
+pub const MAX_USAGE_MONTHS: u32 = 24;
+
+/// One agent run
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageRecord {
+    pub chat_jid: String,
+    pub group_folder: String,
+    pub user_id: String,
+    /// Outcome, as in `container_runs.status`
+    pub status: &'static str,
+    pub duration_ms: u64,
+    pub tokens: Option<TokenUsage>,
+}
+
+/// Store an agent run
+pub fn record_usage(db: &Database, run: &UsageRecord) -> Result<()> {
+    db.get_connection()?
+        .execute(
+            "INSERT INTO usage
+                (tenant, started_at, chat_jid, group_folder, user_id, status, duration_ms,
+                 input_tokens, output_tokens, cost_usd)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            crate::params![
+                db.tenant(),
+                Utc::now().to_rfc3339(),
+                run.chat_jid,
+                run.group_folder,
+                run.user_id,
+                run.status,
+                run.duration_ms as i64,
+                run.tokens.map(|t| t.input_tokens as i64),
+                run.tokens.map(|t| t.output_tokens as i64),
+                run.tokens.and_then(|t| t.cost_usd),
+            ],
+        )
+        .map_err(|e| NuClawError::Database {
+            message: format!("Failed to record usage: {}", e),
+        })?;
+    Ok(())
+}
+
+/// What usage is rolled up by, besides the month
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageGroup {
+    Chat,
+    User,
+}
+
+impl UsageGroup {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "chat" => Some(UsageGroup::Chat),
+            "user" => Some(UsageGroup::User),
+            _ => None,
+        }
+    }
+
+    fn column(&self) -> &'static str {
+        match self {
+            UsageGroup::Chat => "chat_jid",
+            UsageGroup::User => "user_id",
+        }
+    }
+}
+
+/// Sums over a set of agent runs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageTotals {
+    pub runs: u64,
+    pub duration_ms: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// `None` if no run reported a cost
+    pub cost_usd: Option<f64>,
+}
+
+impl UsageTotals {
+    fn add(&mut self, other: &UsageTotals) {
+        self.runs += other.runs;
+        self.duration_ms += other.duration_ms;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cost_usd = match (self.cost_usd, other.cost_usd) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0.0) + b.unwrap_or(0.0)),
+        };
+    }
+}
+
+/// Usage of one chat or user in one month
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonthlyUsage {
+    /// `YYYY-MM`
+    pub month: String,
+    /// Chat JID or user, per the `UsageGroup`
+    pub key: String,
+    pub totals: UsageTotals,
+}
+
+/// First day of the month `months - 1` months before the current one
+fn first_month(months: u32) -> String {
+    let now = Utc::now();
+    let index = now.year() * 12 + now.month0() as i32 - (months.max(1) as i32 - 1);
+    format!(
+        "{:04}-{:02}-01",
+        index.div_euclid(12),
+        index.rem_euclid(12) + 1
+    )
+}
+
+/// Roll up the last `months` calendar months, the current one included,
+/// newest first, and within a month the chats or users with the most runs
+/// first; `chat_jid` limits it to one chat
+pub fn monthly_usage(
+    db: &Database,
+    group: UsageGroup,
+    chat_jid: Option<&str>,
+    months: u32,
+) -> Result<Vec<MonthlyUsage>> {
+    if months == 0 || months > MAX_USAGE_MONTHS {
+        return Err(NuClawError::Validation {
+            message: format!("Months must be between 1 and {}", MAX_USAGE_MONTHS),
+        });
+    }
+    db.read_connection()?.query_map(
+        &format!(
+            "SELECT SUBSTR(started_at, 1, 7), {key}, COUNT(*),
+                    CAST(COALESCE(SUM(duration_ms), 0) AS BIGINT),
+                    CAST(COALESCE(SUM(input_tokens), 0) AS BIGINT),
+                    CAST(COALESCE(SUM(output_tokens), 0) AS BIGINT),
+                    SUM(cost_usd)
+             FROM usage
+             WHERE tenant = ?1 AND started_at >= ?2
+               AND (CAST(?3 AS TEXT) IS NULL OR chat_jid = ?3)
+             GROUP BY 1, 2
+             ORDER BY 1 DESC, 3 DESC, 2",
+            key = group.column()
+        ),
+        crate::params![db.tenant(), first_month(months), chat_jid],
+        |row| {
+            Ok(MonthlyUsage {
+                month: row.get(0)?,
+                key: row.get(1)?,
+                totals: UsageTotals {
+                    runs: row.get(2)?,
+                    duration_ms: row.get(3)?,
+                    input_tokens: row.get(4)?,
+                    output_tokens: row.get(5)?,
+                    cost_usd: row.get(6)?,
+                },
+            })
+        },
+    )
+}
+
+/// Token count with a `k` or `M` suffix
+fn format_tokens(tokens: u64) -> String {
+    match tokens {
+        0..=999 => tokens.to_string(),
+        1_000..=999_999 => format!("{:.1}k", tokens as f64 / 1e3),
+        _ => format!("{:.1}M", tokens as f64 / 1e6),
+    }
+}
+
+/// One line of totals, e.g. `3 runs, 2m, 12.0k tokens in / 800 out, $0.42`
+pub fn format_totals(totals: &UsageTotals) -> String {
+    let mut line = format!(
+        "{} run{}, {}",
+        totals.runs,
+        if totals.runs == 1 { "" } else { "s" },
+        format_duration(totals.duration_ms as i64)
+    );
+    if totals.input_tokens > 0 || totals.output_tokens > 0 {
+        line.push_str(&format!(
+            ", {} tokens in / {} out",
+            format_tokens(totals.input_tokens),
+            format_tokens(totals.output_tokens)
+        ));
+    }
+    if let Some(cost) = totals.cost_usd {
+        line.push_str(&format!(", ${:.2}", cost));
+    }
+    line
+}
+
+/// Rolled-up usage as text: each month's totals, then its chats or users
+pub fn format_monthly_usage(rows: &[MonthlyUsage]) -> String {
+    if rows.is_empty() {
+        return "No agent runs recorded".to_string();
+    }
+    let mut months: BTreeMap<&str, (UsageTotals, Vec<&MonthlyUsage>)> = BTreeMap::new();
+    for row in rows {
+        let (totals, keys) = months.entry(&row.month).or_default();
+        totals.add(&row.totals);
+        keys.push(row);
+    }
+    let mut text = String::new();
+    for (month, (totals, keys)) in months.iter().rev() {
+        text.push_str(&format!("{}: {}\n", month, format_totals(totals)));
+        for row in keys {
+            text.push_str(&format!("  {}: {}\n", row.key, format_totals(&row.totals)));
+        }
+    }
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    fn run(chat_jid: &str, user_id: &str, tokens: Option<TokenUsage>) -> UsageRecord {
+        UsageRecord {
+            chat_jid: chat_jid.to_string(),
+            group_folder: "main".to_string(),
+            user_id: user_id.to_string(),
+            status: "success",
+            duration_ms: 30_000,
+            tokens,
+        }
+    }
+
+    #[test]
+    fn test_monthly_usage() {
+        let (db, _dir) = test_database();
+        let tokens = TokenUsage {
+            input_tokens: 12_000,
+            output_tokens: 800,
+            cost_usd: Some(0.25),
+        };
+        record_usage(&db, &run("tg:1", "telegram:42", Some(tokens))).unwrap();
+        record_usage(&db, &run("tg:1", "telegram:42", Some(tokens))).unwrap();
+        record_usage(&db, &run("tg:1", "scheduler", None)).unwrap();
+        record_usage(&db, &run("tg:2", "telegram:7", None)).unwrap();
+
+        let by_user = monthly_usage(&db, UsageGroup::User, Some("tg:1"), 1).unwrap();
+        assert_eq!(by_user.len(), 2);
+        assert_eq!(by_user[0].key, "telegram:42");
+        assert_eq!(
+            by_user[0].totals,
+            UsageTotals {
+                runs: 2,
+                duration_ms: 60_000,
+                input_tokens: 24_000,
+                output_tokens: 1_600,
+                cost_usd: Some(0.5),
+            }
+        );
+        assert_eq!(by_user[1].key, "scheduler");
+        assert_eq!(by_user[1].totals.cost_usd, None);
+
+        let by_chat = monthly_usage(&db, UsageGroup::Chat, None, 3).unwrap();
+        assert_eq!(by_chat.len(), 2);
+        assert_eq!(by_chat[0].key, "tg:1");
+        assert_eq!(by_chat[0].totals.runs, 3);
+
+        assert!(matches!(
+            monthly_usage(&db, UsageGroup::Chat, None, 0),
+            Err(NuClawError::Validation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_format_monthly_usage() {
+        let row = |month: &str, key: &str, runs, cost_usd| MonthlyUsage {
+            month: month.to_string(),
+            key: key.to_string(),
+            totals: UsageTotals {
+                runs,
+                duration_ms: runs * 60_000,
+                input_tokens: runs * 1_500_000,
+                output_tokens: runs * 500,
+                cost_usd,
+            },
+        };
+        let text = format_monthly_usage(&[
+            row("2026-10", "telegram:42", 2, Some(1.5)),
+            row("2026-10", "scheduler", 1, None),
+            row("2026-09", "telegram:42", 1, None),
+        ]);
+        assert_eq!(
+            text,
+            "2026-10: 3 runs, 3m, 4.5M tokens in / 1.5k out, $1.50\n\
+             \x20 telegram:42: 2 runs, 2m, 3.0M tokens in / 1.0k out, $1.50\n\
+             \x20 scheduler: 1 run, 1m, 1.5M tokens in / 500 out\n\
+             2026-09: 1 run, 1m, 1.5M tokens in / 500 out\n\
+             \x20 telegram:42: 1 run, 1m, 1.5M tokens in / 500 out"
+        );
+        assert_eq!(format_monthly_usage(&[]), "No agent runs recorded");
+    }
+}
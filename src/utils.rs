@@ -29,6 +29,10 @@ pub mod json {
         }
     }
 
+    /// Write `data` to `path` as JSON
+    ///
+    /// The JSON goes to a temporary file that then replaces `path`, so a
+    /// reader or a crash never sees a partly written file.
     pub fn save_json<T>(path: &Path, data: &T) -> std::io::Result<()>
     where
         T: Serialize,
@@ -38,13 +42,16 @@ pub mod json {
         }
 
         let json = serde_json::to_string_pretty(data)?;
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
         let mut file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(path)?;
+            .open(&tmp_path)?;
         file.write_all(json.as_bytes())?;
-        Ok(())
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)
     }
 }
 
@@ -62,7 +69,7 @@ mod tests {
     }
 
     fn test_dir() -> PathBuf {
-        tempdir().unwrap().into_path()
+        tempdir().unwrap().keep()
     }
 
     fn cleanup(path: &PathBuf) {
@@ -82,6 +89,7 @@ mod tests {
         let result = save_json(&path, &data);
         assert!(result.is_ok());
         assert!(path.exists());
+        assert!(!dir.join("test.json.tmp").exists());
 
         cleanup(&path);
     }
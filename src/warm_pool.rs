@@ -0,0 +1,397 @@
+//! Warm Container Pool for NuClaw
+//!
+//! Starting a container per message adds seconds of latency. In warm-pool
+//! mode (`CONTAINER_WARM_POOL=true`, or `"warm_pool": true` in a group's
+//! `container_config`) each group keeps a long-lived container and agent
+//! runs are started inside it with `docker exec`, which sends the prompt
+//! over the exec's stdin.
+//!
+//! A container is removed once idle for `CONTAINER_IDLE_TIMEOUT` seconds,
+//! and replaced after `CONTAINER_MAX_RUNS` runs, after a failed run, or
+//! when the group's container settings change. Containers still in use
+//! are only replaced once their runs finish.
+
+use crate::error::{NuClawError, Result};
+use crate::types::ContainerConfig;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use tokio::process::Command as AsyncCommand;
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Default seconds a warm container may sit idle
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 600;
+/// Default runs before a warm container is replaced
+const DEFAULT_MAX_RUNS: u32 = 50;
+/// Seconds between sweeps for idle containers
+const REAP_INTERVAL_SECS: u64 = 30;
+/// Label marking containers owned by the pool
+const POOL_LABEL: &str = "nuclaw.warm";
+
+/// Whether a group's runs use the warm pool
+pub fn enabled(config: &ContainerConfig) -> bool {
+    config.warm_pool.unwrap_or_else(|| {
+        std::env::var("CONTAINER_WARM_POOL")
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+fn idle_timeout() -> Duration {
+    Duration::from_secs(
+        std::env::var("CONTAINER_IDLE_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS),
+    )
+}
+
+fn max_runs() -> u32 {
+    std::env::var("CONTAINER_MAX_RUNS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_MAX_RUNS)
+}
+
+/// A long-lived container belonging to a group
+#[derive(Debug, Clone)]
+struct WarmContainer {
+    group_folder: String,
+    /// `docker run` arguments it was started with
+    spec: Vec<String>,
+    last_used: Instant,
+    runs: u32,
+    in_use: usize,
+    retired: bool,
+}
+
+impl WarmContainer {
+    /// Whether a new run for `spec` may use this container
+    fn reusable(
+        &self,
+        spec: &[String],
+        now: Instant,
+        idle_timeout: Duration,
+        max_runs: u32,
+    ) -> bool {
+        !self.retired
+            && self.spec == spec
+            && self.runs < max_runs
+            && now.duration_since(self.last_used) < idle_timeout
+    }
+
+    /// Whether the container can be removed now
+    fn expired(&self, now: Instant, idle_timeout: Duration) -> bool {
+        self.in_use == 0 && (self.retired || now.duration_since(self.last_used) >= idle_timeout)
+    }
+}
+
+/// Warm containers by container name
+fn pool() -> &'static Mutex<HashMap<String, WarmContainer>> {
+    static POOL: OnceLock<Mutex<HashMap<String, WarmContainer>>> = OnceLock::new();
+    POOL.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// A warm container reserved for one agent run
+///
+/// Dropping the lease without `release` (e.g. when the run is cancelled)
+/// retires the container, since the agent may still be running in it.
+pub struct Lease {
+    name: String,
+    released: bool,
+}
+
+impl Lease {
+    /// Name of the container to `docker exec` into
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the container to the pool; failed runs retire it
+    pub fn release(mut self, succeeded: bool) {
+        self.released = true;
+        finish_run(&self.name, succeeded);
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if !self.released {
+            finish_run(&self.name, false);
+        }
+    }
+}
+
+/// Reserve the group's warm container, starting one if needed
+///
+/// `spec` is the `docker run` arguments (mounts, limits, environment,
+/// image, and idle command) the container must have been started with.
+pub async fn acquire(group_folder: &str, spec: Vec<String>) -> Result<Lease> {
+    start_reaper();
+
+    let now = Instant::now();
+    let (idle_timeout, max_runs) = (idle_timeout(), max_runs());
+    {
+        let mut pool = pool().lock().unwrap();
+        for container in pool.values_mut() {
+            if container.group_folder == group_folder
+                && !container.reusable(&spec, now, idle_timeout, max_runs)
+            {
+                container.retired = true;
+            }
+        }
+        if let Some((name, container)) = pool
+            .iter_mut()
+            .find(|(_, c)| c.group_folder == group_folder && !c.retired)
+        {
+            container.in_use += 1;
+            return Ok(Lease {
+                name: name.clone(),
+                released: false,
+            });
+        }
+    }
+    remove_expired();
+
+    let name = container_name(group_folder);
+    let mut args = vec![
+        "run".to_string(),
+        "-d".to_string(),
+        "--rm".to_string(),
+        "--name".to_string(),
+        name.clone(),
+        "--label".to_string(),
+        format!("{}={}", POOL_LABEL, group_folder),
+    ];
+    args.extend(spec.iter().cloned());
+    docker(&args).await.map_err(|e| NuClawError::Container {
+        message: format!("Failed to start warm container for {}: {}", group_folder, e),
+    })?;
+    info!("Started warm container {} for {}", name, group_folder);
+
+    pool().lock().unwrap().insert(
+        name.clone(),
+        WarmContainer {
+            group_folder: group_folder.to_string(),
+            spec,
+            last_used: Instant::now(),
+            runs: 0,
+            in_use: 1,
+            retired: false,
+        },
+    );
+    Ok(Lease {
+        name,
+        released: false,
+    })
+}
+
+/// Unique container name for a group
+fn container_name(group_folder: &str) -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let folder: String = group_folder
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!(
+        "nuclaw-{}-{}-{}",
+        folder,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn finish_run(name: &str, succeeded: bool) {
+    if let Ok(mut pool) = pool().lock() {
+        if let Some(container) = pool.get_mut(name) {
+            container.in_use = container.in_use.saturating_sub(1);
+            container.runs += 1;
+            container.last_used = Instant::now();
+            if !succeeded {
+                container.retired = true;
+            }
+        }
+    }
+    remove_expired();
+}
+
+/// Remove retired and idle containers that no run is using
+fn remove_expired() {
+    let now = Instant::now();
+    let idle_timeout = idle_timeout();
+    let expired: Vec<String> = match pool().lock() {
+        Ok(mut pool) => {
+            let names: Vec<String> = pool
+                .iter()
+                .filter(|(_, c)| c.expired(now, idle_timeout))
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in &names {
+                pool.remove(name);
+            }
+            names
+        }
+        Err(_) => return,
+    };
+
+    for name in expired {
+        info!("Removing warm container {}", name);
+        // `docker rm` can take a while; never block the caller on it
+        std::thread::spawn(move || {
+            let _ = std::process::Command::new("docker")
+                .args(["rm", "-f", &name])
+                .output();
+        });
+    }
+}
+
+/// Start the background sweep for idle containers, once per process
+///
+/// The first sweep also removes warm containers left behind by earlier
+/// NuClaw processes.
+fn start_reaper() {
+    static STARTED: OnceLock<()> = OnceLock::new();
+    STARTED.get_or_init(|| {
+        tokio::spawn(async {
+            remove_orphans().await;
+            loop {
+                tokio::time::sleep(Duration::from_secs(REAP_INTERVAL_SECS)).await;
+                remove_expired();
+            }
+        });
+    });
+}
+
+/// Remove pool containers that another process started
+async fn remove_orphans() {
+    let args = [
+        "ps".to_string(),
+        "-a".to_string(),
+        "--filter".to_string(),
+        format!("label={}", POOL_LABEL),
+        "--format".to_string(),
+        "{{.Names}}".to_string(),
+    ];
+    let Ok(names) = docker(&args).await else {
+        return;
+    };
+    let own = format!("-{}-", std::process::id());
+    for name in names.lines().filter(|n| !n.contains(&own)) {
+        info!("Removing orphaned warm container {}", name);
+        let _ = docker(&["rm".to_string(), "-f".to_string(), name.to_string()]).await;
+    }
+}
+
+/// Run a docker command, returning its stdout
+async fn docker(args: &[String]) -> std::result::Result<String, String> {
+    let output = AsyncCommand::new("docker")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        warn!(
+            "docker {} failed: {}",
+            args.first().map_or("", String::as_str),
+            stderr
+        );
+        Err(stderr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(spec: &[&str]) -> WarmContainer {
+        WarmContainer {
+            group_folder: "family".to_string(),
+            spec: spec.iter().map(|s| s.to_string()).collect(),
+            last_used: Instant::now(),
+            runs: 0,
+            in_use: 0,
+            retired: false,
+        }
+    }
+
+    fn spec(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_reusable() {
+        let idle = Duration::from_secs(60);
+        let warm = container(&["image:1"]);
+        let now = warm.last_used;
+
+        assert!(warm.reusable(&spec(&["image:1"]), now, idle, 3));
+        // Settings changed
+        assert!(!warm.reusable(&spec(&["image:2"]), now, idle, 3));
+        // Idle too long
+        assert!(!warm.reusable(&spec(&["image:1"]), now + idle, idle, 3));
+        // Used up
+        let used = WarmContainer {
+            runs: 3,
+            ..warm.clone()
+        };
+        assert!(!used.reusable(&spec(&["image:1"]), now, idle, 3));
+        let retired = WarmContainer {
+            retired: true,
+            ..warm
+        };
+        assert!(!retired.reusable(&spec(&["image:1"]), now, idle, 3));
+    }
+
+    #[test]
+    fn test_expired_waits_for_runs_in_progress() {
+        let idle = Duration::from_secs(60);
+        let idle_container = container(&[]);
+        let now = idle_container.last_used;
+        let retired = WarmContainer {
+            retired: true,
+            in_use: 1,
+            ..idle_container.clone()
+        };
+
+        assert!(!retired.expired(now, idle));
+        assert!(WarmContainer {
+            in_use: 0,
+            ..retired
+        }
+        .expired(now, idle));
+        assert!(idle_container.expired(now + idle, idle));
+        assert!(!idle_container.expired(now, idle));
+    }
+
+    #[test]
+    fn test_container_name() {
+        let first = container_name("family chat");
+        assert!(first.starts_with("nuclaw-family-chat-"));
+        assert_ne!(first, container_name("family chat"));
+    }
+
+    #[test]
+    fn test_enabled_per_group() {
+        let on = ContainerConfig {
+            warm_pool: Some(true),
+            ..Default::default()
+        };
+        let off = ContainerConfig {
+            warm_pool: Some(false),
+            ..Default::default()
+        };
+        assert!(enabled(&on));
+        assert!(!enabled(&off));
+    }
+}
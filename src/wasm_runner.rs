@@ -0,0 +1,226 @@
+//! WASM Agent Runner for NuClaw
+//!
+//! Where Docker is unavailable (shared hosts, small VPSes), agents can run
+//! as a wasm32-wasi module under the `wasmtime` CLI instead
+//! (`CONTAINER_RUNNER=wasm`, or `"runner": "wasm"` in a group's
+//! `container_config`). The module receives the same JSON input on stdin
+//! and must print the same output markers as the container agent.
+//!
+//! WASI confines the module to its preopened directories: the group folder
+//! as `/workspace/group` and its IPC directory as `/workspace/ipc`. Of the
+//! host environment only the forwarded variables are passed, plus the
+//! group's `env`. Additional mounts are not supported.
+//!
+//! Preopened directories are always writable, so a group whose agent
+//! permissions make `/workspace/group` read-only is refused, as is network
+//! access in `WASMTIME_ARGS` for a group whose agent may not use the
+//! network.
+
+use crate::container_runner::{create_group_ipc_directory, forwarded_env, group_env};
+use crate::error::{NuClawError, Result};
+use crate::types::{ContainerConfig, ContainerInput};
+use std::path::{Path, PathBuf};
+use tokio::process::Command as AsyncCommand;
+
+/// wasmtime executable used when `WASMTIME_BIN` is unset
+const DEFAULT_WASMTIME_BIN: &str = "wasmtime";
+
+/// Agent runtime module (`WASM_AGENT_MODULE`)
+pub fn wasm_agent_module() -> Result<PathBuf> {
+    let module = std::env::var("WASM_AGENT_MODULE")
+        .ok()
+        .filter(|m| !m.trim().is_empty())
+        .ok_or_else(|| NuClawError::Config {
+            message: "WASM_AGENT_MODULE must be set to use the wasm runner".to_string(),
+        })?;
+    let module = PathBuf::from(module.trim());
+    if !module.is_file() {
+        return Err(NuClawError::Config {
+            message: format!("WASM agent module not found: {}", module.display()),
+        });
+    }
+    Ok(module)
+}
+
+fn wasmtime_bin() -> String {
+    std::env::var("WASMTIME_BIN")
+        .ok()
+        .filter(|b| !b.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_WASMTIME_BIN.to_string())
+}
+
+/// Extra `wasmtime run` flags (`WASMTIME_ARGS`), e.g. `-S inherit-network`
+fn extra_wasmtime_args() -> Vec<String> {
+    std::env::var("WASMTIME_ARGS")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+/// Refuse agent permissions the wasm runner cannot enforce
+fn check_permissions(config: &ContainerConfig, extra_args: &[String]) -> Result<()> {
+    let Some(permissions) = &config.permissions else {
+        return Ok(());
+    };
+    if !permissions.may_write("/workspace/group") {
+        return Err(NuClawError::Validation {
+            message: "A read-only group folder is not supported by the wasm runner".to_string(),
+        });
+    }
+    let networked = extra_args
+        .iter()
+        .any(|a| a.contains("inherit-network") || a.contains("allow-ip-name-lookup"));
+    if permissions.network_allowed == Some(false) && networked {
+        return Err(NuClawError::Validation {
+            message: "WASMTIME_ARGS gives network access to an agent that may not use the network"
+                .to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// `wasmtime run` arguments for one agent run
+pub fn wasm_run_args(
+    group_dir: &Path,
+    ipc_dir: &Path,
+    config: &ContainerConfig,
+    module: &Path,
+) -> Result<Vec<String>> {
+    if !config.additional_mounts.is_empty() {
+        return Err(NuClawError::Validation {
+            message: "Additional mounts are not supported by the wasm runner".to_string(),
+        });
+    }
+    let extra_args = extra_wasmtime_args();
+    check_permissions(config, &extra_args)?;
+
+    let mut args = vec![
+        "run".to_string(),
+        "--dir".to_string(),
+        format!("{}::/workspace/group", group_dir.display()),
+        "--dir".to_string(),
+        format!("{}::/workspace/ipc", ipc_dir.display()),
+    ];
+    // A bare name inherits the value from the host
+    for name in forwarded_env(config)? {
+        args.extend(["--env".to_string(), name]);
+    }
+    for var in group_env(config)? {
+        args.extend(["--env".to_string(), var]);
+    }
+    args.extend(extra_args);
+    args.push(module.display().to_string());
+    Ok(args)
+}
+
+/// Build the wasmtime command for a run; the input goes to its stdin
+pub fn build_wasm_command(
+    input: &ContainerInput,
+    group_dir: &Path,
+    config: &ContainerConfig,
+) -> Result<AsyncCommand> {
+    let module = wasm_agent_module()?;
+    let ipc_dir = create_group_ipc_directory(&input.group_folder)?;
+    let args = wasm_run_args(group_dir, &ipc_dir, config, &module)?;
+
+    let mut cmd = AsyncCommand::new(wasmtime_bin());
+    cmd.args(args)
+        .current_dir(group_dir)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AdditionalMount, AgentPermissions};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_wasm_run_args() {
+        let config = ContainerConfig {
+            env: BTreeMap::from([("LANG".to_string(), "C".to_string())]),
+            env_allowlist: Some(vec!["PATH".to_string()]),
+            ..Default::default()
+        };
+        let args = wasm_run_args(
+            Path::new("/data/groups/family"),
+            Path::new("/data/ipc/family"),
+            &config,
+            Path::new("/opt/agent.wasm"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            &args[..5],
+            &[
+                "run",
+                "--dir",
+                "/data/groups/family::/workspace/group",
+                "--dir",
+                "/data/ipc/family::/workspace/ipc",
+            ]
+        );
+        assert!(args.windows(2).any(|w| w == ["--env", "LANG=C"]));
+        assert!(args.windows(2).any(|w| w == ["--env", "PATH"]));
+        assert_eq!(args.last().unwrap(), "/opt/agent.wasm");
+    }
+
+    #[test]
+    fn test_wasm_run_args_reject_mounts() {
+        let config = ContainerConfig {
+            additional_mounts: vec![AdditionalMount {
+                host_path: "/srv/data".to_string(),
+                container_path: None,
+                readonly: true,
+            }],
+            ..Default::default()
+        };
+        let err = wasm_run_args(
+            Path::new("/g"),
+            Path::new("/i"),
+            &config,
+            Path::new("/a.wasm"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, NuClawError::Validation { .. }));
+    }
+
+    #[test]
+    fn test_wasm_permissions() {
+        let read_only = ContainerConfig {
+            permissions: Some(AgentPermissions {
+                writable_paths: Some(vec!["/workspace/group/notes".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let err = wasm_run_args(
+            Path::new("/g"),
+            Path::new("/i"),
+            &read_only,
+            Path::new("/a.wasm"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, NuClawError::Validation { .. }));
+
+        let offline = ContainerConfig {
+            permissions: Some(AgentPermissions {
+                network_allowed: Some(false),
+                writable_paths: Some(vec!["/workspace/group".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(check_permissions(&offline, &[]).is_ok());
+        let networked = ["-S".to_string(), "inherit-network".to_string()];
+        assert!(matches!(
+            check_permissions(&offline, &networked),
+            Err(NuClawError::Validation { .. })
+        ));
+        assert!(check_permissions(&ContainerConfig::default(), &networked).is_ok());
+    }
+}
@@ -0,0 +1,315 @@
+//! Webhook Signatures for NuClaw
+//!
+//! Inbound webhooks are checked against a shared secret before their body
+//! is parsed, so unsigned or tampered requests never reach the router.
+//! Three schemes are understood:
+//!
+//! - `github`: `X-Hub-Signature-256: sha256=<hex>`, the HMAC-SHA256 of the
+//!   body
+//! - `stripe`: `Stripe-Signature: t=<unix time>,v1=<hex>`, the
+//!   HMAC-SHA256 of `<time>.<body>`; the time must be within
+//!   `SIGNATURE_TOLERANCE` of now, so a captured request cannot be
+//!   replayed later
+//! - `telegram`: Telegram does not sign updates, but echoes the secret
+//!   given to `setWebhook` in `X-Telegram-Bot-Api-Secret-Token`
+//!
+//! Secrets and signatures are compared in constant time. A rejected
+//! request gets `401 Unauthorized` and is recorded in the audit log.
+
+use crate::admin_api::constant_time_eq;
+use crate::audit::{self, AuditEvent, AuditKind};
+use crate::db::Database;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Largest body read for verification: 2 MiB
+pub const MAX_WEBHOOK_BODY: usize = 2 * 1024 * 1024;
+/// How far the time of a `stripe` signature may be from now, in seconds
+pub const SIGNATURE_TOLERANCE: i64 = 300;
+
+const GITHUB_HEADER: &str = "x-hub-signature-256";
+const STRIPE_HEADER: &str = "stripe-signature";
+const TELEGRAM_HEADER: &str = "x-telegram-bot-api-secret-token";
+
+/// How a webhook sender proves it knows the secret
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureScheme {
+    Github,
+    Stripe,
+    Telegram,
+}
+
+impl SignatureScheme {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "github" => Some(SignatureScheme::Github),
+            "stripe" => Some(SignatureScheme::Stripe),
+            "telegram" => Some(SignatureScheme::Telegram),
+            _ => None,
+        }
+    }
+}
+
+/// Why a request was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    /// No signature header
+    Missing,
+    /// A signature header that cannot be parsed
+    Malformed,
+    /// A signature not made with the secret, or of another body
+    Mismatch,
+    /// A `stripe` signature too old, or too far in the future
+    Expired,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SignatureError::Missing => "missing signature",
+            SignatureError::Malformed => "malformed signature",
+            SignatureError::Mismatch => "invalid signature",
+            SignatureError::Expired => "expired signature",
+        })
+    }
+}
+
+/// Checks the requests of one webhook against its secret
+#[derive(Clone)]
+pub struct WebhookVerifier {
+    scheme: SignatureScheme,
+    secret: String,
+}
+
+impl WebhookVerifier {
+    pub fn new(scheme: SignatureScheme, secret: impl Into<String>) -> Self {
+        Self {
+            scheme,
+            secret: secret.into(),
+        }
+    }
+
+    /// Verify a request's headers and body, as of the unix time `now`
+    pub fn verify(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+        now: i64,
+    ) -> std::result::Result<(), SignatureError> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| value.to_str().map_err(|_| SignatureError::Malformed))
+                .transpose()?
+                .ok_or(SignatureError::Missing)
+        };
+        match self.scheme {
+            SignatureScheme::Github => {
+                let signature = header(GITHUB_HEADER)?
+                    .trim()
+                    .strip_prefix("sha256=")
+                    .ok_or(SignatureError::Malformed)?;
+                self.verify_hmac(&[body], signature)
+            }
+            SignatureScheme::Stripe => {
+                let mut time = None;
+                let mut signatures = Vec::new();
+                for part in header(STRIPE_HEADER)?.split(',') {
+                    match part.trim().split_once('=') {
+                        Some(("t", t)) => time = t.parse::<i64>().ok(),
+                        Some(("v1", signature)) => signatures.push(signature),
+                        _ => {}
+                    }
+                }
+                let time = time.ok_or(SignatureError::Malformed)?;
+                if signatures.is_empty() {
+                    return Err(SignatureError::Malformed);
+                }
+                if (now - time).abs() > SIGNATURE_TOLERANCE {
+                    return Err(SignatureError::Expired);
+                }
+                let prefix = format!("{}.", time);
+                // Several signatures are sent while the secret is rolled
+                signatures
+                    .iter()
+                    .map(|signature| self.verify_hmac(&[prefix.as_bytes(), body], signature))
+                    .find(|result| result.is_ok())
+                    .unwrap_or(Err(SignatureError::Mismatch))
+            }
+            SignatureScheme::Telegram => {
+                if constant_time_eq(header(TELEGRAM_HEADER)?.as_bytes(), self.secret.as_bytes()) {
+                    Ok(())
+                } else {
+                    Err(SignatureError::Mismatch)
+                }
+            }
+        }
+    }
+
+    /// Check a hex HMAC-SHA256 of the concatenated `parts`
+    fn verify_hmac(
+        &self,
+        parts: &[&[u8]],
+        signature: &str,
+    ) -> std::result::Result<(), SignatureError> {
+        let signature = hex::decode(signature.trim()).map_err(|_| SignatureError::Malformed)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC takes keys of any size");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.verify_slice(&signature)
+            .map_err(|_| SignatureError::Mismatch)
+    }
+}
+
+/// State of the verifying middleware
+#[derive(Clone)]
+struct SignedState {
+    verifier: Arc<WebhookVerifier>,
+    db: Database,
+}
+
+/// Require every request to the routes of `router` to pass `verifier`
+pub fn signed(router: Router, verifier: WebhookVerifier, db: Database) -> Router {
+    let state = SignedState {
+        verifier: Arc::new(verifier),
+        db,
+    };
+    router.route_layer(middleware::from_fn_with_state(state, require_signature))
+}
+
+async fn require_signature(
+    State(state): State<SignedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, MAX_WEBHOOK_BODY).await else {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+    let now = chrono::Utc::now().timestamp();
+    if let Err(e) = state.verifier.verify(&parts.headers, &body, now) {
+        let detail = format!("{} {}: {}", parts.method, parts.uri.path(), e);
+        warn!("Rejected webhook request {}", detail);
+        let event = AuditEvent::new(AuditKind::Denied, "webhook", detail);
+        if let Err(e) = state.db.call(move |db| audit::append(db, &event)).await {
+            warn!("{}", e);
+        }
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use axum::routing::post;
+    use tower::ServiceExt;
+
+    const SECRET: &str = "It's a Secret to Everybody";
+
+    fn hmac_hex(message: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(message);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_github_signature() {
+        let verifier = WebhookVerifier::new(SignatureScheme::Github, SECRET);
+        // Example from GitHub's documentation
+        let signed = headers(
+            GITHUB_HEADER,
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17",
+        );
+        assert_eq!(verifier.verify(&signed, b"Hello, World!", 0), Ok(()));
+        assert_eq!(
+            verifier.verify(&signed, b"Hello, World?", 0),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verifier.verify(&HeaderMap::new(), b"Hello, World!", 0),
+            Err(SignatureError::Missing)
+        );
+        assert_eq!(
+            verifier.verify(&headers(GITHUB_HEADER, "sha1=abc"), b"", 0),
+            Err(SignatureError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_stripe_signature() {
+        let verifier = WebhookVerifier::new(SignatureScheme::Stripe, SECRET);
+        let body = br#"{"type":"invoice.paid"}"#;
+        let signature = hmac_hex(&[b"1700000000.".as_slice(), body].concat());
+        let signed = headers(
+            STRIPE_HEADER,
+            &format!("t=1700000000,v1={},v1=00", signature),
+        );
+        assert_eq!(verifier.verify(&signed, body, 1_700_000_100), Ok(()));
+        assert_eq!(
+            verifier.verify(&signed, b"{}", 1_700_000_100),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verifier.verify(&signed, body, 1_700_000_000 + SIGNATURE_TOLERANCE + 1),
+            Err(SignatureError::Expired)
+        );
+        assert_eq!(
+            verifier.verify(&headers(STRIPE_HEADER, "v1=00"), body, 0),
+            Err(SignatureError::Malformed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_signed_router() {
+        let (db, _dir) = test_database();
+        let app = signed(
+            Router::new().route("/hook", post(|body: String| async move { body })),
+            WebhookVerifier::new(SignatureScheme::Telegram, SECRET),
+            db.clone(),
+        );
+        let request = |token: Option<&str>| {
+            let mut request = Request::post("/hook");
+            if let Some(token) = token {
+                request = request.header(TELEGRAM_HEADER, token);
+            }
+            request.body(Body::from("update")).unwrap()
+        };
+
+        let response = app.clone().oneshot(request(Some(SECRET))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 100)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"update");
+
+        for token in [None, Some("guess")] {
+            let response = app.clone().oneshot(request(token)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let filter = audit::AuditFilter {
+            kind: Some("denied".to_string()),
+            actor: Some("webhook".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(audit::query(&db, &filter, 10).unwrap().len(), 2);
+    }
+}
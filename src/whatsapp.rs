@@ -1,21 +1,103 @@
 //! WhatsApp Integration for NuClaw
 //!
 //! Provides WhatsApp connectivity via external WhatsApp MCP Server or HTTP API.
-
-use crate::config::{assistant_name, data_dir, store_dir};
-use crate::container_runner::run_container;
-use crate::db::Database;
+//! Each tenant with an MCP server URL gets its own client (see `tenants`).
+
+use crate::allowlist::{self, AllowlistKind};
+use crate::attachments::{
+    extension_for, mime_type_for, outgoing_files, prompt_reference, save_attachment,
+};
+use crate::audit::{self, chat_actor, AuditEvent, AuditKind};
+use crate::blocklist;
+use crate::broadcast::process_ipc_requests;
+use crate::chat_queue::ChatQueue;
+use crate::commands::{execute_command, parse_command, ChatCommand, CommandContext};
+use crate::container_runner::{
+    container_limiter, run_container_with_retry, RetryPolicy, CANCELLED_NOTICE, QUEUED_NOTICE,
+};
+use crate::context::conversation_context;
+use crate::correlation::{correlation_span, new_correlation_id, with_ref};
+use crate::credentials::auth_dir;
+use crate::db::{blocking, Database};
 use crate::error::{NuClawError, Result};
-use crate::types::{ContainerInput, NewMessage, RegisteredGroup, RouterState};
-use crate::utils::json::{load_json, save_json};
+use crate::group_config::{group_settings, with_group_file};
+pub use crate::groups::load_registered_groups;
+use crate::groups::{match_trigger, register_dm_chat};
+use crate::health;
+use crate::inbound;
+use crate::maintenance::{is_paused, PAUSED_NOTICE};
+use crate::memory::relevant_memories;
+use crate::outbox::Outbox;
+use crate::pairing::{check_pairing, unpaired_notice_due, PairingStatus, UNPAIRED_NOTICE};
+use crate::pending::PendingQueue;
+use crate::prompt_guard::{self, Guarded};
+use crate::roles;
+use crate::sessions::{record_run_session, resume_session_id};
+use crate::shutdown;
+use crate::tenants::{tenant, Tenant, DEFAULT_TENANT};
+use crate::transcription::{transcribe, TranscriptionConfig};
+use crate::types::{ContainerInput, DMPolicy, NewMessage, RegisteredGroup, RouterState};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use tokio::time::{timeout, Duration};
-use tracing::{debug, error, info};
+use std::path::Path;
+use tokio::time::Duration;
+use tracing::{debug, error, info, warn, Instrument};
+
+/// Channel name used for pairing and command context
+const CHANNEL: &str = "whatsapp";
 
 /// Default WhatsApp poll interval: 2 seconds
 const DEFAULT_WHATSAPP_POLL_INTERVAL_MS: u64 = 2000;
+/// How often "composing" presence is renewed; WhatsApp clears it after ~10s
+const PRESENCE_REFRESH_SECS: u64 = 8;
+/// Default upper bound for the delay between failed polls: 60 seconds
+const DEFAULT_WHATSAPP_MAX_BACKOFF_MS: u64 = 60_000;
+
+/// State of the connection to WhatsApp through the MCP server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Polling works
+    Connected,
+    /// Polling failed this many times in a row
+    Reconnecting { failures: u32 },
+    /// The WhatsApp session was logged out and needs a new QR scan
+    LoggedOut,
+}
+
+/// A message as returned by the MCP server, with optional media
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhatsAppMessage {
+    #[serde(flatten)]
+    pub message: NewMessage,
+    #[serde(default)]
+    pub media: Option<WhatsAppMedia>,
+}
+
+/// Media attached to a WhatsApp message
+///
+/// The bytes are fetched from the MCP server only for triggered messages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhatsAppMedia {
+    /// "image", "document", "audio", "video", ...
+    pub kind: String,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    #[serde(default)]
+    pub file_name: Option<String>,
+    /// Set by the bridge for push-to-talk audio (voice notes)
+    #[serde(default)]
+    pub ptt: bool,
+}
+
+impl WhatsAppMedia {
+    /// Whether this is a recorded voice note rather than an audio file
+    pub fn is_voice_note(&self) -> bool {
+        self.kind == "voice" || (self.kind == "audio" && self.ptt)
+    }
+}
 
 /// WhatsApp client state
+#[derive(Clone)]
 pub struct WhatsAppClient {
     /// Connection status
     pub connected: bool,
@@ -23,27 +105,88 @@ pub struct WhatsAppClient {
     pub last_qr: Option<String>,
     /// Reference to registered groups
     registered_groups: HashMap<String, RegisteredGroup>,
-    /// Router state for message deduplication
+    /// Router state of the last processed message
     router_state: RouterState,
-    /// Database connection
+    /// Database connection, scoped to the client's tenant
     db: Database,
-    /// Assistant name for trigger detection
-    assistant_name: String,
+    /// URL of the tenant's WhatsApp MCP server
+    mcp_url: Option<String>,
+    /// DM policy for private chats
+    dm_policy: DMPolicy,
+    /// Persistent outbound queue
+    outbox: Outbox,
+    /// Triggered messages whose agent run has not finished
+    pending: PendingQueue,
+    /// Agent runs waiting for their chat's turn
+    runs: ChatQueue,
+    /// Default for marking triggered messages as read
+    read_receipts: bool,
+    /// Default for publishing "composing" presence during a run
+    presence: bool,
+    /// Connection state as seen by the message listener
+    state: ConnectionState,
+    /// Delay between polls while connected
+    poll_interval: Duration,
+    /// Upper bound for the delay between failed polls
+    max_backoff: Duration,
+    /// Voice note transcription, if configured
+    transcription: Option<TranscriptionConfig>,
 }
 
 impl WhatsAppClient {
-    /// Create a new WhatsApp client
+    /// Create a new WhatsApp client for the default tenant
     pub fn new(db: Database) -> Self {
+        Self::for_tenant(db, &tenant(DEFAULT_TENANT))
+    }
+
+    /// Create a WhatsApp client for a tenant's MCP server
+    pub fn for_tenant(db: Database, tenant: &Tenant) -> Self {
+        let db = db.for_tenant(&tenant.id);
+        let router_state = inbound::load_router_state(&db, CHANNEL, &tenant.data_dir())
+            .unwrap_or_else(|e| {
+                warn!("Failed to load router state: {}", e);
+                RouterState::default()
+            });
         Self {
             connected: false,
             last_qr: None,
-            registered_groups: load_registered_groups(),
-            router_state: load_router_state(),
+            registered_groups: load_registered_groups(&tenant.id),
+            router_state,
+            outbox: Outbox::new(db.clone(), CHANNEL),
+            pending: PendingQueue::new(db.clone(), CHANNEL),
+            runs: ChatQueue::from_env(),
             db,
-            assistant_name: assistant_name(),
+            mcp_url: tenant.whatsapp_mcp_url(),
+            dm_policy: DMPolicy::parse(
+                &std::env::var("WHATSAPP_DM_POLICY").unwrap_or_else(|_| "open".to_string()),
+            ),
+            read_receipts: std::env::var("WHATSAPP_READ_RECEIPTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(true),
+            presence: std::env::var("WHATSAPP_PRESENCE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(true),
+            state: ConnectionState::Reconnecting { failures: 0 },
+            poll_interval: Duration::from_millis(DEFAULT_WHATSAPP_POLL_INTERVAL_MS),
+            max_backoff: Duration::from_millis(
+                std::env::var("WHATSAPP_MAX_BACKOFF")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_WHATSAPP_MAX_BACKOFF_MS),
+            ),
+            transcription: TranscriptionConfig::from_env(),
         }
     }
 
+    /// URL of the tenant's MCP server
+    fn mcp_url(&self) -> Result<String> {
+        self.mcp_url.clone().ok_or_else(|| NuClawError::Config {
+            message: format!("WHATSAPP_MCP_URL not set for tenant {}", self.db.tenant()),
+        })
+    }
+
     /// Connect to WhatsApp
     pub async fn connect(&mut self) -> Result<()> {
         info!("Connecting to WhatsApp...");
@@ -62,7 +205,7 @@ impl WhatsAppClient {
 
     /// Check if authentication is needed
     async fn needs_authentication(&self) -> Result<bool> {
-        let creds_path = store_dir().join("auth").join("creds.json");
+        let creds_path = auth_dir().join("creds.json");
 
         // If creds file exists and is valid, no auth needed
         if creds_path.exists() {
@@ -82,7 +225,7 @@ impl WhatsAppClient {
 
     /// Request QR code for authentication
     async fn request_qr_code(&mut self) -> Result<()> {
-        let mcp_url = get_mcp_url()?;
+        let mcp_url = self.mcp_url()?;
 
         let response = reqwest::Client::new()
             .post(format!("{}/auth/qr", mcp_url))
@@ -109,28 +252,146 @@ impl WhatsAppClient {
         Ok(())
     }
 
+    /// Current connection state
+    pub fn connection_state(&self) -> ConnectionState {
+        self.state
+    }
+
     /// Start listening for messages
+    ///
+    /// Polls at a fixed rate while connected and backs off exponentially
+    /// while the MCP server is unreachable or the session is logged out.
+    /// Returns once NuClaw is shutting down.
     pub async fn start_message_listener(&mut self) {
-        let mut interval =
-            tokio::time::interval(Duration::from_millis(DEFAULT_WHATSAPP_POLL_INTERVAL_MS));
-
         info!("Starting message listener...");
 
-        loop {
-            interval.tick().await;
+        let mcp_url = self.mcp_url.clone().unwrap_or_default();
+        tokio::spawn(self.outbox.clone().run(move |message| {
+            let mcp_url = mcp_url.clone();
+            async move { send_via_mcp(&mcp_url, &message.chat_jid, &message.content).await }
+        }));
+        self.replay_pending().await;
+
+        let component = health::component(CHANNEL, self.db.tenant());
+        while !shutdown::is_requested() {
+            match self.poll_messages().await {
+                Ok(()) => {
+                    health::reachable(&component);
+                    self.on_poll_success();
+                }
+                Err(e) => {
+                    health::unreachable(&component, &e);
+                    self.on_poll_failure(e).await;
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.next_poll_delay()) => {}
+                _ = shutdown::requested() => {}
+            }
+        }
+        info!("Stopped polling WhatsApp");
+    }
+
+    /// Record a successful poll
+    fn on_poll_success(&mut self) {
+        if self.state != ConnectionState::Connected {
+            info!("WhatsApp connection established");
+        }
+        self.state = ConnectionState::Connected;
+        self.connected = true;
+    }
+
+    /// Record a failed poll and react to a logged-out session
+    async fn on_poll_failure(&mut self, e: NuClawError) {
+        self.connected = false;
+        match (self.state, e) {
+            (ConnectionState::LoggedOut, _) => {
+                debug!("WhatsApp session still logged out");
+            }
+            (_, NuClawError::Auth { message }) => {
+                error!("WhatsApp session logged out: {}", message);
+                self.state = ConnectionState::LoggedOut;
+                self.handle_logged_out().await;
+            }
+            (state, e) => {
+                let failures = match state {
+                    ConnectionState::Reconnecting { failures } => failures + 1,
+                    _ => 1,
+                };
+                self.state = ConnectionState::Reconnecting { failures };
+                warn!(
+                    "Error polling messages (attempt {}, retrying in {:?}): {}",
+                    failures,
+                    self.next_poll_delay(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Timestamp to backfill from, while recovering from downtime
+    fn backfill_since(&self) -> Option<&str> {
+        let since = self.router_state.last_timestamp.as_str();
+        (self.state != ConnectionState::Connected && !since.is_empty()).then_some(since)
+    }
+
+    /// Delay before the next poll for the current state
+    fn next_poll_delay(&self) -> Duration {
+        match self.state {
+            ConnectionState::Connected => self.poll_interval,
+            ConnectionState::Reconnecting { failures } => {
+                reconnect_backoff(failures, self.poll_interval, self.max_backoff)
+            }
+            ConnectionState::LoggedOut => self.max_backoff,
+        }
+    }
+
+    /// Ask for a new QR code and tell the admins to re-authenticate
+    async fn handle_logged_out(&mut self) {
+        if let Err(e) = self.request_qr_code().await {
+            warn!("Failed to request a new QR code: {}", e);
+        }
+        self.notify_admins(
+            "NuClaw's WhatsApp session was logged out. Run `nuclaw auth whatsapp` and scan the new QR code to reconnect.",
+        );
+    }
 
-            if let Err(e) = self.poll_messages().await {
-                error!("Error polling messages: {}", e);
+    /// Queue a notice for the admins on Telegram
+    ///
+    /// WhatsApp itself is unusable while logged out, so numeric (Telegram)
+    /// admin IDs are notified through the Telegram outbox when it is set up.
+    fn notify_admins(&self, text: &str) {
+        let tenant = tenant(self.db.tenant());
+        if tenant.telegram_bot_token().is_none() {
+            return;
+        }
+        let telegram = Outbox::new(self.db.clone(), "telegram");
+        for admin in tenant
+            .admin_users()
+            .iter()
+            .filter(|a| a.parse::<i64>().is_ok())
+        {
+            if let Err(e) = telegram.enqueue(&format!("telegram:group:{}", admin), text) {
+                warn!("Failed to notify admin {}: {}", admin, e);
             }
         }
     }
 
     /// Poll for new messages
+    ///
+    /// Until the first successful poll (at startup or after an outage) the
+    /// MCP server is asked for everything since the last processed message,
+    /// so messages sent while NuClaw was offline are not lost.
     async fn poll_messages(&mut self) -> Result<()> {
-        let mcp_url = get_mcp_url()?;
+        let mcp_url = self.mcp_url()?;
 
-        let response = reqwest::Client::new()
-            .get(format!("{}/messages", mcp_url))
+        let mut request = reqwest::Client::new().get(format!("{}/messages", mcp_url));
+        if let Some(since) = self.backfill_since() {
+            debug!("Backfilling WhatsApp messages since {}", since);
+            request = request.query(&[("since", since)]);
+        }
+
+        let response = request
             .timeout(Duration::from_secs(30))
             .send()
             .await
@@ -138,43 +399,118 @@ impl WhatsAppClient {
                 message: format!("Failed to poll messages: {}", e),
             })?;
 
-        if response.status() == 200 {
-            let messages: Vec<NewMessage> =
-                response.json().await.map_err(|e| NuClawError::WhatsApp {
-                    message: format!("Failed to parse messages: {}", e),
-                })?;
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(NuClawError::Auth {
+                message: format!("MCP server rejected the session: status {}", status),
+            });
+        }
+        if !status.is_success() {
+            return Err(NuClawError::WhatsApp {
+                message: format!("Failed to poll messages: status {}", status),
+            });
+        }
+
+        let messages: Vec<WhatsAppMessage> =
+            response.json().await.map_err(|e| NuClawError::WhatsApp {
+                message: format!("Failed to parse messages: {}", e),
+            })?;
 
-            for msg in messages {
-                self.handle_message(&msg).await?;
+        // A failing message must not count as a connection failure
+        for msg in messages {
+            if let Err(e) = self
+                .handle_message_with_media(&msg.message, msg.media.as_ref())
+                .await
+            {
+                error!("Failed to handle message {}: {}", msg.message.id, e);
             }
         }
 
         Ok(())
     }
 
-    /// Handle a single message
+    /// Handle a single text message
     pub async fn handle_message(&mut self, msg: &NewMessage) -> Result<Option<String>> {
-        if self.is_duplicate_message(msg).await {
+        self.handle_message_with_media(msg, None).await
+    }
+
+    /// Handle a single message and its attached media, if any
+    pub async fn handle_message_with_media(
+        &mut self,
+        msg: &NewMessage,
+        media: Option<&WhatsAppMedia>,
+    ) -> Result<Option<String>> {
+        if self.is_blocked(&msg.sender).await? {
+            debug!("Ignoring message from blocked user: {}", msg.sender);
+            return Ok(None);
+        }
+        if !self.accept_message(msg).await? {
             debug!("Skipping duplicate message: {}", msg.id);
             return Ok(None);
         }
+        let id = new_correlation_id();
+        let msg = NewMessage {
+            correlation_id: Some(id.clone()),
+            ..msg.clone()
+        };
+        self.handle_accepted(&msg, media)
+            .instrument(correlation_span(&id))
+            .await
+    }
 
-        self.update_router_state(msg).await;
-        self.store_message(msg).await?;
+    /// Handle a message accepted for the first time
+    async fn handle_accepted(
+        &mut self,
+        msg: &NewMessage,
+        media: Option<&WhatsAppMedia>,
+    ) -> Result<Option<String>> {
+        if let Some(command) = parse_command(&msg.content) {
+            return self.handle_command(msg, command).await;
+        }
+
+        if is_private_chat(&msg.chat_jid) {
+            if !self.check_dm_policy(msg).await? {
+                debug!("Message from unauthorized user: {}", msg.sender);
+                return Ok(None);
+            }
+            self.register_dm_chat(msg).await?;
+        }
 
         if !self.is_registered_group(&msg.chat_jid).await {
             debug!("Message from unregistered group: {}", msg.chat_jid);
             return Ok(None);
         }
 
-        let (_, content) = match self.extract_trigger(&msg.content).await {
+        // Voice notes are matched and answered by their transcript
+        let mut media = media;
+        let mut transcript = None;
+        if let Some(voice) = media.filter(|m| m.is_voice_note()) {
+            if let Some(text) = self.transcribe_voice_note(msg, voice).await {
+                self.store_message(&NewMessage {
+                    content: text.clone(),
+                    ..msg.clone()
+                })
+                .await?;
+                transcript = Some(text);
+                media = None;
+            }
+        }
+        let text = transcript.as_deref().unwrap_or(&msg.content);
+
+        let (_, mut content) = match self.extract_trigger(&msg.chat_jid, text).await {
             Some((_, c)) => (String::new(), c),
             None => return Ok(None),
         };
 
+        let chat_jid = msg.chat_jid.clone();
+        let chat = self
+            .db
+            .call(move |db| Ok(db.chats().label(&chat_jid)))
+            .await?;
         info!(
-            "Received message from {}: {}",
+            "Received message from {} in {}: {}",
             msg.sender,
+            chat,
             truncate(&content, 50)
         );
 
@@ -185,122 +521,557 @@ impl WhatsAppClient {
                     message: format!("Group not found: {}", msg.chat_jid),
                 })?;
 
-        let session_id = format!("whatsapp_{}", msg.id);
+        self.react(msg, group_settings(&group_folder).ack_reaction())
+            .await;
+        let group = self
+            .registered_groups
+            .get(&msg.chat_jid)
+            .map(with_group_file);
+        let read_receipts = group_flag(group.as_ref(), |g| g.read_receipts, self.read_receipts);
+        if read_receipts {
+            if let Err(e) =
+                mark_read_via_mcp(&self.mcp_url()?, &msg.chat_jid, &msg.id, &msg.sender).await
+            {
+                warn!("Failed to mark {} as read: {}", msg.id, e);
+            }
+        }
+
+        if let Some(media) = media {
+            match self.download_media(msg, media, &group_folder).await {
+                Ok(reference) => content = format!("{}\n{}", content, reference),
+                Err(e) => warn!("Failed to download media of {}: {}", msg.id, e),
+            }
+        }
+
+        let content = match self.guard(msg, &group_folder, content).await? {
+            Guarded::Run(prompt) => prompt,
+            Guarded::Reply(reply) => {
+                self.reply(&msg.chat_jid, &reply).await?;
+                return Ok(Some(reply));
+            }
+        };
+        let pending = self.pending.clone();
+        let (message, prompt) = (msg.clone(), content.clone());
+        let pending_id = blocking(move || pending.enqueue(&message, &prompt)).await?;
+        self.queue_run(pending_id, msg.clone(), content, group_folder);
+        Ok(None)
+    }
+
+    /// Apply the content guard to a triggered message's prompt
+    async fn guard(&self, msg: &NewMessage, group_folder: &str, prompt: String) -> Result<Guarded> {
+        let (pending, message, group_folder) =
+            (self.pending.clone(), msg.clone(), group_folder.to_string());
+        self.db
+            .call(move |db| prompt_guard::apply(db, &pending, &message, &group_folder, &prompt))
+            .await
+    }
+
+    /// Start the agent run of a held message once an admin approved it
+    async fn run_approved(&self, id: i64) -> Result<()> {
+        let pending = self.pending.clone();
+        let Some(entry) = blocking(move || pending.claim_approved(id)).await? else {
+            return Ok(());
+        };
+        let Some(group_folder) = self.get_group_folder(&entry.message.chat_jid).await else {
+            let pending = self.pending.clone();
+            return blocking(move || pending.complete(entry.id)).await;
+        };
+        self.queue_run(entry.id, entry.message, entry.prompt, group_folder);
+        Ok(())
+    }
+
+    /// Re-run the agent for messages interrupted by a crash or restart
+    async fn replay_pending(&mut self) {
+        let pending = self.pending.clone();
+        let interrupted = match blocking(move || pending.claim_interrupted()).await {
+            Ok(interrupted) => interrupted,
+            Err(e) => {
+                error!("Failed to load pending messages: {}", e);
+                return;
+            }
+        };
+
+        for mut entry in interrupted {
+            // Pending messages of earlier versions have no correlation ID
+            entry
+                .message
+                .correlation_id
+                .get_or_insert_with(new_correlation_id);
+            info!(
+                "Replaying interrupted message {} from {} (attempt {})",
+                entry.message.id, entry.message.chat_jid, entry.attempts
+            );
+            let Some(group_folder) = self.get_group_folder(&entry.message.chat_jid).await else {
+                warn!(
+                    "Dropping pending message {}: {} is no longer registered",
+                    entry.message.id, entry.message.chat_jid
+                );
+                let pending = self.pending.clone();
+                if let Err(e) = blocking(move || pending.complete(entry.id)).await {
+                    error!("Failed to remove pending message {}: {}", entry.id, e);
+                }
+                continue;
+            };
+            self.queue_run(entry.id, entry.message, entry.prompt, group_folder);
+        }
+    }
+
+    /// Queue the agent run for an accepted message on the chat's turn
+    ///
+    /// The message leaves the pending queue once the run has finished.
+    fn queue_run(&self, pending_id: i64, msg: NewMessage, content: String, group_folder: String) {
+        let client = self.clone();
+        let chat_jid = msg.chat_jid.clone();
+        let span = correlation_span(msg.correlation_id.as_deref().unwrap_or_default());
+        self.runs.push(
+            &chat_jid,
+            async move {
+                if let Err(e) = client.run_agent(&msg, content, group_folder).await {
+                    error!("Failed to answer message {}: {}", msg.id, e);
+                }
+                let pending = client.pending.clone();
+                if let Err(e) = blocking(move || pending.complete(pending_id)).await {
+                    error!("Failed to remove pending message {}: {}", pending_id, e);
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Run the agent on a triggered message and deliver its answer
+    async fn run_agent(
+        &self,
+        msg: &NewMessage,
+        content: String,
+        group_folder: String,
+    ) -> Result<Option<String>> {
+        if self.db.call(|db| Ok(is_paused(db))).await.unwrap_or(false) {
+            self.reply(&msg.chat_jid, PAUSED_NOTICE).await?;
+            return Ok(None);
+        }
+        let settings = group_settings(&group_folder);
+        let group = self
+            .registered_groups
+            .get(&msg.chat_jid)
+            .map(|g| settings.apply(g));
+        let presence = group_flag(group.as_ref(), |g| g.presence, self.presence);
+
+        let context = {
+            let (msg, folder) = (msg.clone(), group_folder.clone());
+            self.db
+                .call(move |db| Ok(conversation_context(db, &msg, &folder)))
+                .await
+                .unwrap_or_default()
+        };
+        let resumed = {
+            let chat_jid = msg.chat_jid.clone();
+            self.db
+                .call(move |db| Ok(resume_session_id(db, &chat_jid)))
+                .await
+                .unwrap_or(None)
+        };
+        let memories = relevant_memories(&self.db, &group_folder, &content).await;
         let input = ContainerInput {
             prompt: content,
-            session_id: Some(session_id.clone()),
-            group_folder,
+            session_id: resumed.clone(),
+            resume_session: resumed.is_some(),
+            group_folder: group_folder.clone(),
             chat_jid: msg.chat_jid.clone(),
             is_main: msg.chat_jid.ends_with("@s.whatsapp.net"),
             is_scheduled_task: false,
+            context,
+            reply_to_id: msg.reply_to_id.clone(),
+            quoted_content: msg.quoted_content.clone(),
+            memories,
+            timeout: None,
+            sender: Some(chat_actor(CHANNEL, &msg.sender)),
+            correlation_id: msg.correlation_id.clone(),
+            permissions: None,
         };
 
-        let result = timeout(Duration::from_secs(300), run_container(input)).await;
+        if container_limiter().is_saturated() {
+            if let Err(e) = self.reply(&msg.chat_jid, QUEUED_NOTICE).await {
+                warn!("Failed to send queued notice to {}: {}", msg.chat_jid, e);
+            }
+        }
+
+        let typing = presence
+            .then(|| self.mcp_url().ok())
+            .flatten()
+            .map(|mcp_url| spawn_composing(mcp_url, msg.chat_jid.clone()));
+        // Each attempt is bounded by the run's own timeout
+        let result =
+            run_container_with_retry(&self.db, input, RetryPolicy::for_channel(CHANNEL), None)
+                .await;
+        if let Some(typing) = typing {
+            typing.abort();
+            let paused = match self.mcp_url() {
+                Ok(mcp_url) => send_presence_via_mcp(&mcp_url, &msg.chat_jid, "paused").await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = paused {
+                debug!("Failed to clear presence in {}: {}", msg.chat_jid, e);
+            }
+        }
+        self.send_outgoing_files(&msg.chat_jid, &group_folder).await;
+        let folder = group_folder.clone();
+        if let Err(e) = self
+            .db
+            .call(move |db| process_ipc_requests(db, &folder))
+            .await
+        {
+            warn!("Failed to process IPC requests for {}: {}", group_folder, e);
+        }
+        if let Ok(output) = &result {
+            let (chat_jid, output) = (msg.chat_jid.clone(), output.clone());
+            if let Err(e) = self
+                .db
+                .call(move |db| record_run_session(db, &chat_jid, resumed.as_deref(), &output))
+                .await
+            {
+                warn!("Failed to store the session of {}: {}", msg.chat_jid, e);
+            }
+        }
 
         match result {
-            Ok(Ok(output)) => {
+            Ok(output) => {
+                self.react(msg, settings.done_reaction()).await;
                 if let Some(response) = output.result {
-                    self.send_message(&msg.chat_jid, &response).await?;
+                    self.reply(&msg.chat_jid, &response).await?;
                     return Ok(Some(response));
                 }
             }
-            Ok(Err(e)) => {
-                error!("Container error: {}", e);
-                self.send_message(&msg.chat_jid, &format!("Error: {}", e))
-                    .await?;
+            Err(NuClawError::Cancelled { .. }) => {
+                info!("Run for {} was cancelled", msg.chat_jid);
+                self.react(msg, settings.error_reaction()).await;
+                self.reply(&msg.chat_jid, CANCELLED_NOTICE).await?;
             }
-            Err(_) => {
-                error!("Container timeout");
-                self.send_message(&msg.chat_jid, "Sorry, the request timed out.")
-                    .await?;
+            Err(e) => {
+                error!("Container error: {}", e);
+                self.react(msg, settings.error_reaction()).await;
+                let reply = with_ref(&format!("Error: {}", e), msg.correlation_id.as_deref());
+                self.reply(&msg.chat_jid, &reply).await?;
             }
         }
 
         Ok(None)
     }
 
-    /// Send a message
+    /// Fetch a message's media from the MCP server into the group folder
+    ///
+    /// Returns the prompt line referencing the saved file.
+    async fn download_media(
+        &self,
+        msg: &NewMessage,
+        media: &WhatsAppMedia,
+        group_folder: &str,
+    ) -> Result<String> {
+        let bytes = fetch_media_via_mcp(&self.mcp_url()?, &msg.id).await?;
+        let file_name = media_file_name(media);
+        let saved = save_attachment(group_folder, &msg.id, &file_name, &bytes)?;
+        info!("Saved {} from {} as {}", media.kind, msg.id, saved);
+
+        Ok(prompt_reference(&media.kind, &saved))
+    }
+
+    /// Download and transcribe a voice note
+    ///
+    /// Returns `None` if transcription is not configured or failed; the
+    /// voice note is then handled like any other audio attachment.
+    async fn transcribe_voice_note(
+        &self,
+        msg: &NewMessage,
+        media: &WhatsAppMedia,
+    ) -> Option<String> {
+        let config = self.transcription.as_ref()?;
+        let mime_type = media.mime_type.as_deref().unwrap_or("audio/ogg");
+        let fetched = match self.mcp_url() {
+            Ok(mcp_url) => fetch_media_via_mcp(&mcp_url, &msg.id).await,
+            Err(e) => Err(e),
+        };
+        let result = match fetched {
+            Ok(audio) => transcribe(config, audio, &media_file_name(media), mime_type).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(text) => {
+                debug!("Transcribed voice note {}: {}", msg.id, truncate(&text, 50));
+                Some(text)
+            }
+            Err(e) => {
+                warn!("Failed to transcribe voice note {}: {}", msg.id, e);
+                None
+            }
+        }
+    }
+
+    /// Upload files the agent left in the group's outgoing directory
+    ///
+    /// Delivered files are removed; failed ones stay for the next run.
+    async fn send_outgoing_files(&self, jid: &str, group_folder: &str) {
+        let files = match outgoing_files(group_folder) {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Failed to list outgoing files of {}: {}", group_folder, e);
+                return;
+            }
+        };
+
+        let Ok(mcp_url) = self.mcp_url() else {
+            return;
+        };
+        for path in files {
+            match send_media_via_mcp(&mcp_url, jid, &path).await {
+                Ok(()) => {
+                    let _ = std::fs::remove_file(&path);
+                }
+                Err(e) => warn!("Failed to send {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    /// Send a message right away, bypassing the outbox
     pub async fn send_message(&self, jid: &str, content: &str) -> Result<()> {
-        let mcp_url = get_mcp_url()?;
+        send_via_mcp(&self.mcp_url()?, jid, content).await
+    }
+
+    /// Queue a reply for the outbox worker
+    async fn reply(&self, jid: &str, content: &str) -> Result<()> {
+        let outbox = self.outbox.clone();
+        let (jid, content) = (jid.to_string(), content.to_string());
+        blocking(move || outbox.enqueue(&jid, &content)).await?;
+        Ok(())
+    }
+
+    /// React to a message, replacing any previous reaction from us
+    ///
+    /// Reactions are feedback only, so failures are logged and ignored.
+    async fn react(&self, msg: &NewMessage, emoji: Option<String>) {
+        let Some(emoji) = emoji else {
+            return;
+        };
+        if let Err(e) = self.send_reaction(&msg.chat_jid, &msg.id, &emoji).await {
+            warn!("Failed to set reaction on {}: {}", msg.id, e);
+        }
+    }
+
+    /// Send a reaction through the MCP server
+    async fn send_reaction(&self, jid: &str, message_id: &str, emoji: &str) -> Result<()> {
+        let mcp_url = self.mcp_url()?;
 
         let payload = serde_json::json!({
             "jid": jid,
-            "message": content,
+            "message_id": message_id,
+            "emoji": emoji,
         });
 
         let response = reqwest::Client::new()
-            .post(format!("{}/messages/send", mcp_url))
+            .post(format!("{}/messages/react", mcp_url))
             .json(&payload)
             .timeout(Duration::from_secs(30))
             .send()
             .await
             .map_err(|e| NuClawError::WhatsApp {
-                message: format!("Failed to send message: {}", e),
+                message: format!("Failed to send reaction: {}", e),
             })?;
 
         if !response.status().is_success() {
             return Err(NuClawError::WhatsApp {
-                message: format!("Failed to send message: status {}", response.status()),
+                message: format!("Failed to send reaction: status {}", response.status()),
             });
         }
 
         Ok(())
     }
 
-    /// Check if message is duplicate
-    async fn is_duplicate_message(&self, msg: &NewMessage) -> bool {
-        let last_timestamp = &self.router_state.last_timestamp;
-        let last_agent = self.router_state.last_agent_timestamp.get(&msg.chat_jid);
+    /// Whether a sender holds `admin` or `owner` for all groups; they
+    /// pass the DM policy
+    async fn is_admin(&self, sender: &str) -> Result<bool> {
+        let sender = sender.to_string();
+        self.db
+            .call(move |db| roles::is_admin(db, CHANNEL, &sender))
+            .await
+    }
 
-        if last_timestamp == &msg.timestamp {
-            return true;
-        }
+    /// Whether the sender is on the blocklist
+    async fn is_blocked(&self, sender: &str) -> Result<bool> {
+        let sender = sender.to_string();
+        self.db
+            .call(move |db| blocklist::is_blocked(db, CHANNEL, &sender))
+            .await
+    }
 
-        if let Some(agent_ts) = last_agent {
-            if agent_ts == &msg.timestamp {
-                return true;
+    /// Check DM policy
+    ///
+    /// Under the pairing policy, a DM carrying a valid pairing code pairs
+    /// the sender; the code itself is not forwarded to the agent.
+    async fn check_dm_policy(&self, msg: &NewMessage) -> Result<bool> {
+        match self.dm_policy {
+            DMPolicy::Disabled => Ok(false),
+            DMPolicy::Open => Ok(true),
+            DMPolicy::Allowlist => {
+                if self.is_admin(&msg.sender).await? {
+                    return Ok(true);
+                }
+                let (sender, chat_jid) = (msg.sender.clone(), msg.chat_jid.clone());
+                self.db
+                    .call(move |db| {
+                        let allowed =
+                            allowlist::is_allowed(db, CHANNEL, AllowlistKind::User, &sender)?;
+                        if !allowed {
+                            audit::record(
+                                db,
+                                AuditEvent::new(
+                                    AuditKind::Denied,
+                                    chat_actor(CHANNEL, &sender),
+                                    "DM refused: sender is not on the allowlist",
+                                )
+                                .in_chat(&chat_jid),
+                            );
+                        }
+                        Ok(allowed)
+                    })
+                    .await
+            }
+            DMPolicy::Pairing => {
+                if self.is_admin(&msg.sender).await? {
+                    return Ok(true);
+                }
+                let (sender, content) = (msg.sender.clone(), msg.content.clone());
+                let status = self
+                    .db
+                    .call(move |db| check_pairing(db, CHANNEL, &sender, &content))
+                    .await?;
+                match status {
+                    PairingStatus::Paired => Ok(true),
+                    PairingStatus::JustPaired => {
+                        info!("Paired WhatsApp user {}", msg.sender);
+                        self.reply(
+                            &msg.chat_jid,
+                            "Paired successfully. You can now talk to me here.",
+                        )
+                        .await?;
+                        Ok(false)
+                    }
+                    PairingStatus::InvalidCode => {
+                        self.reply(
+                            &msg.chat_jid,
+                            "That pairing code is invalid or has expired.",
+                        )
+                        .await?;
+                        Ok(false)
+                    }
+                    PairingStatus::Unpaired => {
+                        if unpaired_notice_due(CHANNEL, &msg.sender) {
+                            self.reply(&msg.chat_jid, UNPAIRED_NOTICE).await?;
+                        }
+                        Ok(false)
+                    }
+                }
             }
         }
-
-        false
     }
 
-    /// Update router state after processing
-    async fn update_router_state(&mut self, msg: &NewMessage) {
-        self.router_state.last_timestamp = msg.timestamp.clone();
-        self.router_state
-            .last_agent_timestamp
-            .insert(msg.chat_jid.clone(), msg.timestamp.clone());
+    /// Execute a chat command and reply with its result
+    async fn handle_command(
+        &mut self,
+        msg: &NewMessage,
+        command: ChatCommand,
+    ) -> Result<Option<String>> {
+        let registers = matches!(command, ChatCommand::Register(_));
+        let approved = match command {
+            ChatCommand::Approve(id) => Some(id),
+            _ => None,
+        };
+        let (sender, chat_jid, text) = (
+            msg.sender.clone(),
+            msg.chat_jid.clone(),
+            msg.content.clone(),
+        );
+        let reply = self
+            .db
+            .call(move |db| {
+                let ctx = CommandContext {
+                    channel: CHANNEL,
+                    sender: &sender,
+                    chat_jid: &chat_jid,
+                    chat_id: &chat_jid,
+                    is_private: is_private_chat(&chat_jid),
+                    text: &text,
+                };
+                execute_command(db, &ctx, command)
+            })
+            .await?;
+        if registers {
+            self.registered_groups = load_registered_groups(self.db.tenant());
+        }
+        if let Some(id) = approved.filter(|_| reply.is_some()) {
+            self.run_approved(id).await?;
+        }
 
-        let state_path = data_dir().join("router_state.json");
-        let _ = save_json(&state_path, &self.router_state);
+        match reply {
+            Some(reply) => {
+                self.reply(&msg.chat_jid, &reply).await?;
+                Ok(Some(reply))
+            }
+            None => Ok(None),
+        }
     }
 
-    /// Store message in database
-    async fn store_message(&self, msg: &NewMessage) -> Result<()> {
-        let conn = self
+    /// Store a message with its chat and the router state, unless it is a
+    /// duplicate; `false` for duplicates
+    async fn accept_message(&mut self, msg: &NewMessage) -> Result<bool> {
+        let stored = msg.clone();
+        let accepted = self
             .db
-            .get_connection()
+            .call(move |db| {
+                inbound::accept_message(db, CHANNEL, &stored, stored.id.starts_with("self"))
+            })
+            .await
             .map_err(|e| NuClawError::Database {
-                message: e.to_string(),
+                message: format!("Failed to store message: {}", e),
             })?;
+        if accepted {
+            self.router_state.last_timestamp = msg.timestamp.clone();
+            self.router_state
+                .last_agent_timestamp
+                .insert(msg.chat_jid.clone(), msg.timestamp.clone());
+        }
+        Ok(accepted)
+    }
 
-        conn.execute(
-            "INSERT OR REPLACE INTO messages (id, chat_jid, sender, sender_name, content, timestamp, is_from_me)
-             VALUES (?, ?, ?, ?, ?, ?, ?)",
-            rusqlite::params![
-                msg.id,
-                msg.chat_jid,
-                msg.sender,
-                msg.sender_name,
-                msg.content,
-                msg.timestamp,
-                if msg.id.starts_with("self") { 1 } else { 0 },
-            ],
-        ).map_err(|e| NuClawError::Database {
-            message: format!("Failed to store message: {}", e),
-        })?;
+    /// Store message in database
+    async fn store_message(&self, msg: &NewMessage) -> Result<()> {
+        let msg = msg.clone();
+        self.db
+            .call(move |db| {
+                db.messages().store(&msg, msg.id.starts_with("self"))?;
+                db.chats()
+                    .upsert(&msg.chat_jid, msg.chat_name.as_deref(), &msg.timestamp)
+            })
+            .await
+            .map_err(|e| NuClawError::Database {
+                message: format!("Failed to store message: {}", e),
+            })
+    }
 
+    /// Register an accepted private chat, so its user has a group folder
+    async fn register_dm_chat(&mut self, msg: &NewMessage) -> Result<()> {
+        if self.is_registered_group(&msg.chat_jid).await {
+            return Ok(());
+        }
+        let (tenant_id, chat_jid, sender) = (
+            self.db.tenant().to_string(),
+            msg.chat_jid.clone(),
+            msg.sender.clone(),
+        );
+        let group =
+            blocking(move || register_dm_chat(&tenant_id, CHANNEL, &chat_jid, &sender)).await?;
+        info!(
+            "Registered the private chat of {} as {}",
+            msg.sender, group.folder
+        );
+        self.registered_groups.insert(msg.chat_jid.clone(), group);
         Ok(())
     }
 
@@ -315,41 +1086,199 @@ impl WhatsAppClient {
     }
 
     /// Extract trigger and content from message
-    async fn extract_trigger(&self, content: &str) -> Option<(String, String)> {
-        extract_trigger_pure(content, &self.assistant_name)
+    ///
+    /// Uses the chat's triggers, from its registration and its group
+    /// folder's config file, or `@<assistant name>` if none are configured.
+    async fn extract_trigger(&self, chat_jid: &str, content: &str) -> Option<(String, String)> {
+        match self
+            .registered_groups
+            .get(chat_jid)
+            .map(|g| with_group_file(g).triggers())
+        {
+            Some(triggers) if !triggers.is_empty() => match_trigger(content, &triggers),
+            _ => extract_trigger_pure(content, &tenant(self.db.tenant()).assistant_name()),
+        }
     }
 }
 
 // Helper functions
 
-/// Get WhatsApp MCP URL from environment
-fn get_mcp_url() -> Result<String> {
-    std::env::var("WHATSAPP_MCP_URL").map_err(|_| NuClawError::Config {
-        message: "WHATSAPP_MCP_URL not set".to_string(),
+/// Send a message through the MCP server
+async fn send_via_mcp(mcp_url: &str, jid: &str, content: &str) -> Result<()> {
+    let payload = serde_json::json!({
+        "jid": jid,
+        "message": content,
+    });
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/messages/send", mcp_url))
+        .json(&payload)
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .map_err(|e| NuClawError::WhatsApp {
+            message: format!("Failed to send message: {}", e),
+        })?;
+
+    if !response.status().is_success() {
+        return Err(NuClawError::WhatsApp {
+            message: format!("Failed to send message: status {}", response.status()),
+        });
+    }
+
+    Ok(())
+}
+
+/// Delay before the next poll after `failures` consecutive failures
+///
+/// Doubles from `base` with every failure, capped at `max`.
+pub fn reconnect_backoff(failures: u32, base: Duration, max: Duration) -> Duration {
+    base.saturating_mul(2_u32.saturating_pow(failures.min(16)))
+        .min(max)
+}
+
+/// Keep "composing" presence up in a chat until the task is aborted
+fn spawn_composing(mcp_url: String, jid: String) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(PRESENCE_REFRESH_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = send_presence_via_mcp(&mcp_url, &jid, "composing").await {
+                debug!("Failed to send presence to {}: {}", jid, e);
+            }
+        }
     })
 }
 
-/// Load router state from file
-pub fn load_router_state() -> RouterState {
-    let state_path = data_dir().join("router_state.json");
-    load_json(
-        &state_path,
-        RouterState {
-            last_timestamp: String::new(),
-            last_agent_timestamp: HashMap::new(),
-        },
+/// Publish a chat presence ("composing", "paused") through the MCP server
+async fn send_presence_via_mcp(mcp_url: &str, jid: &str, state: &str) -> Result<()> {
+    post_to_mcp(
+        mcp_url,
+        "presence",
+        &serde_json::json!({ "jid": jid, "state": state }),
+        "send presence",
     )
+    .await
+}
+
+/// Send a read receipt for a message through the MCP server
+async fn mark_read_via_mcp(mcp_url: &str, jid: &str, message_id: &str, sender: &str) -> Result<()> {
+    post_to_mcp(
+        mcp_url,
+        "messages/read",
+        &serde_json::json!({ "jid": jid, "message_id": message_id, "sender": sender }),
+        "mark message as read",
+    )
+    .await
+}
+
+/// POST a JSON payload to an MCP server endpoint
+async fn post_to_mcp(
+    mcp_url: &str,
+    path: &str,
+    payload: &serde_json::Value,
+    action: &str,
+) -> Result<()> {
+    let response = reqwest::Client::new()
+        .post(format!("{}/{}", mcp_url, path))
+        .json(payload)
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .map_err(|e| NuClawError::WhatsApp {
+            message: format!("Failed to {}: {}", action, e),
+        })?;
+
+    if !response.status().is_success() {
+        return Err(NuClawError::WhatsApp {
+            message: format!("Failed to {}: status {}", action, response.status()),
+        });
+    }
+
+    Ok(())
+}
+
+/// Resolve a per-group setting, falling back to the channel default
+pub fn group_flag(
+    group: Option<&RegisteredGroup>,
+    pick: impl Fn(&RegisteredGroup) -> Option<bool>,
+    default: bool,
+) -> bool {
+    group.and_then(pick).unwrap_or(default)
+}
+
+/// Download the media of a message from the MCP server
+async fn fetch_media_via_mcp(mcp_url: &str, message_id: &str) -> Result<Vec<u8>> {
+    let response = reqwest::Client::new()
+        .get(format!("{}/messages/{}/media", mcp_url, message_id))
+        .timeout(Duration::from_secs(60))
+        .send()
+        .await
+        .map_err(|e| NuClawError::WhatsApp {
+            message: format!("Failed to download media: {}", e),
+        })?;
+
+    if !response.status().is_success() {
+        return Err(NuClawError::WhatsApp {
+            message: format!("Failed to download media: status {}", response.status()),
+        });
+    }
+
+    let bytes = response.bytes().await.map_err(|e| NuClawError::WhatsApp {
+        message: format!("Failed to read media: {}", e),
+    })?;
+    Ok(bytes.to_vec())
+}
+
+/// Upload a file to a chat through the MCP server
+async fn send_media_via_mcp(mcp_url: &str, jid: &str, path: &Path) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("file")
+        .to_string();
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|e| NuClawError::FileSystem {
+            message: format!("Failed to read {}: {}", path.display(), e),
+        })?;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/messages/send-media", mcp_url))
+        .query(&[("jid", jid), ("file_name", file_name.as_str())])
+        .header(reqwest::header::CONTENT_TYPE, mime_type_for(path))
+        .body(bytes)
+        .timeout(Duration::from_secs(120))
+        .send()
+        .await
+        .map_err(|e| NuClawError::WhatsApp {
+            message: format!("Failed to send media: {}", e),
+        })?;
+
+    if !response.status().is_success() {
+        return Err(NuClawError::WhatsApp {
+            message: format!("Failed to send media: status {}", response.status()),
+        });
+    }
+
+    Ok(())
 }
 
-/// Load registered groups from file
-pub fn load_registered_groups() -> HashMap<String, RegisteredGroup> {
-    let path = data_dir().join("registered_groups.json");
-    load_json(&path, HashMap::new())
+/// File name to save media under, derived from its MIME type if unnamed
+pub fn media_file_name(media: &WhatsAppMedia) -> String {
+    match &media.file_name {
+        Some(name) if !name.is_empty() => name.clone(),
+        _ => format!(
+            "{}.{}",
+            media.kind,
+            extension_for(media.mime_type.as_deref().unwrap_or(""))
+        ),
+    }
 }
 
 /// Start the authentication flow
 pub async fn start_auth_flow() {
-    let auth_path = store_dir().join("auth");
+    let auth_path = auth_dir();
     std::fs::create_dir_all(&auth_path).ok();
     info!("Use WHATSAPP_MCP_URL to configure WhatsApp connection");
 }
@@ -365,34 +1294,7 @@ fn truncate(s: &str, max_len: usize) -> String {
 
 /// Extract trigger and content from message (pure function)
 pub fn extract_trigger_pure(content: &str, assistant_name: &str) -> Option<(String, String)> {
-    let trigger_pattern = format!("@{}", assistant_name);
-
-    if let Some(idx) = content.find(&trigger_pattern) {
-        let after = &content[idx + trigger_pattern.len()..];
-        let c = after.trim().to_string();
-        return Some((trigger_pattern, c));
-    }
-
-    None
-}
-
-/// Check if message is duplicate (pure function)
-pub fn is_duplicate_message_pure(
-    msg: &NewMessage,
-    last_timestamp: &str,
-    last_agent_timestamps: &std::collections::HashMap<String, String>,
-) -> bool {
-    if last_timestamp == msg.timestamp {
-        return true;
-    }
-
-    if let Some(agent_ts) = last_agent_timestamps.get(&msg.chat_jid) {
-        if agent_ts == &msg.timestamp {
-            return true;
-        }
-    }
-
-    false
+    match_trigger(content, &[format!("@{}", assistant_name)])
 }
 
 /// Check if message is from a private chat
@@ -409,6 +1311,146 @@ pub fn get_group_name_from_jid(jid: &str) -> Option<String> {
 mod tests {
     use super::*;
 
+    fn test_client() -> WhatsAppClient {
+        let db = Database::new().unwrap();
+        WhatsAppClient {
+            connected: false,
+            last_qr: None,
+            registered_groups: HashMap::new(),
+            router_state: RouterState::default(),
+            outbox: Outbox::new(db.clone(), CHANNEL),
+            pending: PendingQueue::new(db.clone(), CHANNEL),
+            runs: ChatQueue::from_env(),
+            db,
+            mcp_url: None,
+            dm_policy: DMPolicy::Open,
+            read_receipts: false,
+            presence: false,
+            state: ConnectionState::Reconnecting { failures: 0 },
+            poll_interval: Duration::from_millis(DEFAULT_WHATSAPP_POLL_INTERVAL_MS),
+            max_backoff: Duration::from_millis(DEFAULT_WHATSAPP_MAX_BACKOFF_MS),
+            transcription: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_message_with_media() {
+        let msg: WhatsAppMessage = serde_json::from_str(
+            r#"{"id": "M1", "chat_jid": "123@g.us", "sender": "1@s.whatsapp.net",
+                "sender_name": "Ann", "content": "@Andy what is this?", "timestamp": "1",
+                "media": {"kind": "image", "mime_type": "image/jpeg"}}"#,
+        )
+        .unwrap();
+        assert_eq!(msg.message.content, "@Andy what is this?");
+        let media = msg.media.unwrap();
+        assert_eq!(media.kind, "image");
+        assert_eq!(media_file_name(&media), "image.jpg");
+
+        let msg: WhatsAppMessage = serde_json::from_str(
+            r#"{"id": "M2", "chat_jid": "123@g.us", "sender": "1@s.whatsapp.net",
+                "sender_name": "Ann", "content": "hi", "timestamp": "2"}"#,
+        )
+        .unwrap();
+        assert!(msg.media.is_none());
+        assert!(msg.message.reply_to_id.is_none());
+    }
+
+    #[test]
+    fn test_parse_quoted_message() {
+        let msg: WhatsAppMessage = serde_json::from_str(
+            r#"{"id": "M3", "chat_jid": "123@g.us", "sender": "1@s.whatsapp.net",
+                "sender_name": "Ann", "content": "@Andy do what I said above", "timestamp": "3",
+                "reply_to_id": "M1", "quoted_content": "Book a table for four"}"#,
+        )
+        .unwrap();
+        assert_eq!(msg.message.reply_to_id.as_deref(), Some("M1"));
+        assert_eq!(
+            msg.message.quoted_content.as_deref(),
+            Some("Book a table for four")
+        );
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let base = Duration::from_secs(2);
+        let max = Duration::from_secs(60);
+        assert_eq!(reconnect_backoff(0, base, max), Duration::from_secs(2));
+        assert_eq!(reconnect_backoff(1, base, max), Duration::from_secs(4));
+        assert_eq!(reconnect_backoff(3, base, max), Duration::from_secs(16));
+        assert_eq!(reconnect_backoff(5, base, max), max);
+        assert_eq!(reconnect_backoff(u32::MAX, base, max), max);
+    }
+
+    #[tokio::test]
+    async fn test_connection_state_transitions() {
+        let mut client = test_client();
+        let transient = || NuClawError::WhatsApp {
+            message: "connection refused".to_string(),
+        };
+
+        client.on_poll_failure(transient()).await;
+        client.on_poll_failure(transient()).await;
+        assert_eq!(
+            client.connection_state(),
+            ConnectionState::Reconnecting { failures: 2 }
+        );
+        assert_eq!(client.next_poll_delay(), Duration::from_secs(8));
+
+        client.on_poll_success();
+        assert_eq!(client.connection_state(), ConnectionState::Connected);
+        assert!(client.connected);
+        assert_eq!(
+            client.next_poll_delay(),
+            Duration::from_millis(DEFAULT_WHATSAPP_POLL_INTERVAL_MS)
+        );
+
+        client.router_state.last_timestamp = "1700000000".to_string();
+        assert_eq!(client.backfill_since(), None);
+
+        client.state = ConnectionState::LoggedOut;
+        assert_eq!(client.backfill_since(), Some("1700000000"));
+        client.on_poll_failure(transient()).await;
+        assert_eq!(client.connection_state(), ConnectionState::LoggedOut);
+        assert_eq!(client.next_poll_delay(), client.max_backoff);
+    }
+
+    #[test]
+    fn test_group_flag() {
+        let json = r#"{"name":"g","folder":"g","trigger":"@Andy","added_at":"","presence":false}"#;
+        let group: RegisteredGroup = serde_json::from_str(json).unwrap();
+
+        assert!(!group_flag(Some(&group), |g| g.presence, true));
+        assert!(group_flag(Some(&group), |g| g.read_receipts, true));
+        assert!(!group_flag(Some(&group), |g| g.read_receipts, false));
+        assert!(group_flag(None, |g| g.presence, true));
+    }
+
+    #[test]
+    fn test_media_file_name_keeps_original_name() {
+        let media = WhatsAppMedia {
+            kind: "document".to_string(),
+            mime_type: Some("application/pdf".to_string()),
+            file_name: Some("invoice.pdf".to_string()),
+            ptt: false,
+        };
+        assert_eq!(media_file_name(&media), "invoice.pdf");
+    }
+
+    #[test]
+    fn test_is_voice_note() {
+        let media = |kind: &str, ptt: bool| WhatsAppMedia {
+            kind: kind.to_string(),
+            mime_type: Some("audio/ogg; codecs=opus".to_string()),
+            file_name: None,
+            ptt,
+        };
+        assert!(media("voice", false).is_voice_note());
+        assert!(media("audio", true).is_voice_note());
+        assert!(!media("audio", false).is_voice_note());
+        assert!(!media("image", false).is_voice_note());
+        assert_eq!(media_file_name(&media("voice", false)), "voice.ogg");
+    }
+
     #[test]
     fn test_truncate_short() {
         assert_eq!(truncate("hello", 10), "hello");
@@ -421,18 +1463,11 @@ mod tests {
 
     #[test]
     fn test_extract_trigger_with_at() {
-        let client = WhatsAppClient {
-            connected: false,
-            last_qr: None,
-            registered_groups: HashMap::new(),
-            router_state: RouterState::default(),
-            db: Database::new().unwrap(),
-            assistant_name: "Andy".to_string(),
-        };
+        let client = test_client();
 
         let result = tokio::runtime::Runtime::new()
             .unwrap()
-            .block_on(client.extract_trigger("@Andy hello world"));
+            .block_on(client.extract_trigger("chat@g.us", "@Andy hello world"));
 
         assert!(result.is_some());
         let (trigger, content) = result.unwrap();
@@ -442,22 +1477,48 @@ mod tests {
 
     #[test]
     fn test_extract_trigger_without_at() {
-        let client = WhatsAppClient {
-            connected: false,
-            last_qr: None,
-            registered_groups: HashMap::new(),
-            router_state: RouterState::default(),
-            db: Database::new().unwrap(),
-            assistant_name: "Andy".to_string(),
-        };
+        let client = test_client();
 
         let result = tokio::runtime::Runtime::new()
             .unwrap()
-            .block_on(client.extract_trigger("hello world"));
+            .block_on(client.extract_trigger("chat@g.us", "hello world"));
 
         assert!(result.is_none());
     }
 
+    #[test]
+    fn test_extract_trigger_uses_group_triggers() {
+        let mut client = test_client();
+        client.registered_groups.insert(
+            "chat@g.us".to_string(),
+            RegisteredGroup {
+                name: "Family".to_string(),
+                folder: "family".to_string(),
+                trigger: "@Jarvis".to_string(),
+                added_at: String::new(),
+                aliases: vec!["hey jarvis".to_string()],
+                read_receipts: None,
+                presence: None,
+                container_config: None,
+            },
+        );
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let (trigger, content) = rt
+            .block_on(client.extract_trigger("chat@g.us", "Hey Jarvis lights off"))
+            .unwrap();
+        assert_eq!(trigger, "hey jarvis");
+        assert_eq!(content, "lights off");
+
+        // The global name only applies to chats without their own triggers
+        assert!(rt
+            .block_on(client.extract_trigger("chat@g.us", "@Andy hi"))
+            .is_none());
+        assert!(rt
+            .block_on(client.extract_trigger("other@g.us", "@andy hi"))
+            .is_some());
+    }
+
     #[test]
     fn test_extract_trigger_pure_basic() {
         let result = extract_trigger_pure("@Andy hello world", "Andy");
@@ -497,32 +1558,6 @@ mod tests {
         assert_eq!(content, "help me");
     }
 
-    #[test]
-    fn test_is_duplicate_message_pure_whatsapp() {
-        let msg = NewMessage {
-            id: "1".to_string(),
-            chat_jid: "123@s.whatsapp.net".to_string(),
-            sender: "user1".to_string(),
-            sender_name: "User".to_string(),
-            content: "hello".to_string(),
-            timestamp: "2025-01-01T00:00:00Z".to_string(),
-        };
-
-        let mut agent_ts = std::collections::HashMap::new();
-        agent_ts.insert(
-            "123@s.whatsapp.net".to_string(),
-            "2025-01-01T00:00:00Z".to_string(),
-        );
-
-        assert!(is_duplicate_message_pure(
-            &msg,
-            "2025-01-01T00:00:00Z",
-            &HashMap::new()
-        ));
-        assert!(is_duplicate_message_pure(&msg, "old", &agent_ts));
-        assert!(!is_duplicate_message_pure(&msg, "old", &HashMap::new()));
-    }
-
     #[test]
     fn test_is_private_chat() {
         assert!(is_private_chat("123@s.whatsapp.net"));
@@ -3,13 +3,18 @@
 //! These tests verify the interaction between components.
 
 use nuclaw::config;
-use nuclaw::error::Result;
 use std::fs;
 
+/// Keep the test files in the crate instead of the XDG directories
+fn use_crate_home() {
+    std::env::set_var("NUCLAW_HOME", env!("CARGO_MANIFEST_DIR"));
+}
+
 /// Test that required directories are created
 #[test]
 fn test_directory_creation() {
     // Ensure directories exist
+    use_crate_home();
     config::ensure_directories().expect("Failed to create directories");
 
     // Verify directories were created
@@ -27,6 +32,7 @@ fn test_database_initialization() {
     use nuclaw::db::Database;
 
     // Ensure directories exist first
+    use_crate_home();
     config::ensure_directories().expect("Failed to create directories");
 
     // Initialize database
@@ -36,14 +42,13 @@ fn test_database_initialization() {
     let conn = db.get_connection().expect("Failed to get connection");
 
     // Verify tables exist by running a simple query
-    let tables: rusqlite::Result<Vec<String>> = conn
-        .prepare("SELECT name FROM sqlite_master WHERE type='table'")
-        .expect("Failed to prepare statement")
-        .query_map([], |row| row.get::<_, String>(0))
-        .expect("Failed to query tables")
-        .collect();
-
-    let tables = tables.expect("Failed to collect results");
+    let tables: Vec<String> = conn
+        .query_map(
+            "SELECT name FROM sqlite_master WHERE type='table'",
+            (),
+            |row| row.get(0),
+        )
+        .expect("Failed to query tables");
 
     assert!(
         tables.contains(&"chats".to_string()),
@@ -125,16 +130,18 @@ fn test_scheduler_configuration() {
 #[test]
 fn test_database_operations() {
     use nuclaw::db::Database;
-    use rusqlite::params;
+    use nuclaw::params;
 
+    use_crate_home();
     config::ensure_directories().expect("Failed to create directories");
     let db = Database::new().expect("Failed to create database");
     let conn = db.get_connection().expect("Failed to get connection");
 
     // Insert a test message
     conn.execute(
-        "INSERT OR REPLACE INTO messages (id, chat_jid, sender, sender_name, content, timestamp, is_from_me)
-         VALUES (?, ?, ?, ?, ?, ?, ?)",
+        "INSERT INTO messages (id, chat_jid, sender, sender_name, content, timestamp, is_from_me)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (tenant, id, chat_jid) DO UPDATE SET content = excluded.content",
         params![
             "test_msg_1",
             "test@chat",
@@ -144,7 +151,8 @@ fn test_database_operations() {
             "2025-01-01T00:00:00Z",
             0,
         ],
-    ).expect("Failed to insert message");
+    )
+    .expect("Failed to insert message");
 
     // Query the message back
     let content: String = conn
@@ -167,6 +175,7 @@ fn test_database_operations() {
 fn test_group_context_isolation() {
     use nuclaw::container_runner::create_group_ipc_directory;
 
+    use_crate_home();
     config::ensure_directories().expect("Failed to create directories");
 
     // Create IPC directories for different groups